@@ -0,0 +1,8 @@
+-- Your SQL goes here
+CREATE TABLE related_posts (
+    id SERIAL PRIMARY KEY,
+    post_id INTEGER REFERENCES posts(id) ON DELETE CASCADE NOT NULL,
+    related_post_id INTEGER REFERENCES posts(id) ON DELETE CASCADE NOT NULL,
+    score REAL NOT NULL DEFAULT 0,
+    CONSTRAINT related_posts_unique UNIQUE (post_id, related_post_id)
+);
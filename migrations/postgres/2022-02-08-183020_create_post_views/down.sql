@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE post_view_visitors;
+DROP TABLE post_views;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE blogs DROP COLUMN comments_order;
+ALTER TABLE blogs DROP COLUMN comments_max_depth;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE blogs ADD COLUMN comments_order INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE blogs ADD COLUMN comments_max_depth INTEGER;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM notifications WHERE kind = 'COMMENT_LIKE';
+DROP TABLE comment_likes;
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE comment_likes (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    comment_id INTEGER REFERENCES comments(id) ON DELETE CASCADE NOT NULL,
+    creation_date TIMESTAMP NOT NULL DEFAULT now(),
+    ap_url VARCHAR NOT NULL DEFAULT '',
+    CONSTRAINT comment_likes_unique UNIQUE (user_id, comment_id)
+);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE guest_comments;
+ALTER TABLE blogs DROP COLUMN allow_guest_comments;
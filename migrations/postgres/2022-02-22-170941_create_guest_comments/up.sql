@@ -0,0 +1,12 @@
+-- Your SQL goes here
+ALTER TABLE blogs ADD COLUMN allow_guest_comments BOOLEAN NOT NULL DEFAULT 'f';
+CREATE TABLE guest_comments (
+    id SERIAL PRIMARY KEY,
+    post_id INTEGER REFERENCES posts(id) ON DELETE CASCADE NOT NULL,
+    author_name VARCHAR NOT NULL,
+    author_email VARCHAR,
+    content TEXT NOT NULL DEFAULT '',
+    creation_date TIMESTAMP NOT NULL DEFAULT now(),
+    approved BOOLEAN NOT NULL DEFAULT 'f',
+    visitor VARCHAR NOT NULL DEFAULT ''
+);
@@ -0,0 +1,26 @@
+-- Your SQL goes here
+CREATE TABLE connectors (
+    id SERIAL PRIMARY KEY,
+    blog_id INTEGER REFERENCES blogs(id) ON DELETE CASCADE NOT NULL,
+    kind VARCHAR NOT NULL,
+    url VARCHAR NOT NULL DEFAULT '',
+    api_key VARCHAR,
+    enabled BOOLEAN NOT NULL DEFAULT 't',
+    creation_date TIMESTAMP NOT NULL DEFAULT now()
+);
+CREATE TABLE crossposts (
+    id SERIAL PRIMARY KEY,
+    connector_id INTEGER REFERENCES connectors(id) ON DELETE CASCADE NOT NULL,
+    post_id INTEGER REFERENCES posts(id) ON DELETE CASCADE NOT NULL,
+    state INTEGER NOT NULL DEFAULT 0,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt TIMESTAMP NOT NULL DEFAULT now(),
+    remote_url VARCHAR,
+    last_error VARCHAR,
+    creation_date TIMESTAMP NOT NULL DEFAULT now(),
+    CONSTRAINT crossposts_unique UNIQUE (connector_id, post_id)
+);
+CREATE TABLE crosspost_opt_outs (
+    id SERIAL PRIMARY KEY,
+    post_id INTEGER REFERENCES posts(id) ON DELETE CASCADE NOT NULL UNIQUE
+);
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+CREATE TABLE quotes (
+    id SERIAL PRIMARY KEY,
+    post_id INTEGER REFERENCES posts(id) ON DELETE CASCADE,
+    comment_id INTEGER REFERENCES comments(id) ON DELETE CASCADE,
+    quoted_url VARCHAR NOT NULL,
+    creation_date TIMESTAMP NOT NULL DEFAULT now()
+);
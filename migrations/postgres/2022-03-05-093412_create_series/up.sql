@@ -0,0 +1,17 @@
+-- Your SQL goes here
+CREATE TABLE series (
+    id SERIAL PRIMARY KEY,
+    blog_id INTEGER REFERENCES blogs(id) ON DELETE CASCADE NOT NULL,
+    title VARCHAR NOT NULL,
+    slug VARCHAR NOT NULL,
+    summary TEXT NOT NULL DEFAULT '',
+    creation_date TIMESTAMP NOT NULL DEFAULT now(),
+    UNIQUE (blog_id, slug)
+);
+
+CREATE TABLE series_posts (
+    id SERIAL PRIMARY KEY,
+    series_id INTEGER REFERENCES series(id) ON DELETE CASCADE NOT NULL,
+    post_id INTEGER REFERENCES posts(id) ON DELETE CASCADE NOT NULL UNIQUE,
+    position INTEGER NOT NULL
+);
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+CREATE TABLE categories (
+    id SERIAL PRIMARY KEY,
+    blog_id INTEGER REFERENCES blogs(id) ON DELETE CASCADE NOT NULL,
+    parent_id INTEGER REFERENCES categories(id) ON DELETE SET NULL,
+    name VARCHAR NOT NULL,
+    slug VARCHAR NOT NULL,
+    creation_date TIMESTAMP NOT NULL DEFAULT now(),
+    UNIQUE (blog_id, slug)
+);
+
+CREATE TABLE post_categories (
+    id SERIAL PRIMARY KEY,
+    post_id INTEGER REFERENCES posts(id) ON DELETE CASCADE NOT NULL,
+    category_id INTEGER REFERENCES categories(id) ON DELETE CASCADE NOT NULL,
+    UNIQUE (post_id, category_id)
+);
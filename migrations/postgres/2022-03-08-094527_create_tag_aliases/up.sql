@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE tag_aliases (
+    id SERIAL PRIMARY KEY,
+    alias VARCHAR NOT NULL UNIQUE,
+    tag VARCHAR NOT NULL,
+    creation_date TIMESTAMP NOT NULL DEFAULT now()
+);
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+CREATE TABLE hashtag_follows (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    tag VARCHAR NOT NULL,
+    creation_date TIMESTAMP NOT NULL DEFAULT now(),
+    CONSTRAINT hashtag_follows_unique UNIQUE (user_id, tag)
+);
+
+CREATE TABLE relays (
+    id SERIAL PRIMARY KEY,
+    actor_id VARCHAR NOT NULL UNIQUE,
+    inbox_url VARCHAR NOT NULL,
+    public_key TEXT NOT NULL,
+    accepted BOOLEAN NOT NULL DEFAULT 'f',
+    creation_date TIMESTAMP NOT NULL DEFAULT now()
+);
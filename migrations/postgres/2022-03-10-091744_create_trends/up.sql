@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE trending_tags (
+    id SERIAL PRIMARY KEY,
+    tag VARCHAR NOT NULL UNIQUE,
+    score REAL NOT NULL DEFAULT 0,
+    accounts INTEGER NOT NULL DEFAULT 0,
+    computed_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE TABLE trending_posts (
+    id SERIAL PRIMARY KEY,
+    post_id INTEGER REFERENCES posts(id) ON DELETE CASCADE NOT NULL UNIQUE,
+    score REAL NOT NULL DEFAULT 0,
+    accounts INTEGER NOT NULL DEFAULT 0,
+    computed_at TIMESTAMP NOT NULL DEFAULT now()
+);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE profile_fields DROP COLUMN last_checked_at;
+ALTER TABLE profile_fields DROP COLUMN verified_at;
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE post_reviews;
+
+ALTER TABLE blog_authors ADD COLUMN is_owner BOOLEAN NOT NULL DEFAULT 'f';
+UPDATE blog_authors SET is_owner = 't' WHERE role = 0;
+ALTER TABLE blog_authors DROP COLUMN role;
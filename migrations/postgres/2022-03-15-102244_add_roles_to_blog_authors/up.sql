@@ -0,0 +1,11 @@
+-- Your SQL goes here
+ALTER TABLE blog_authors ADD COLUMN role INTEGER NOT NULL DEFAULT 1;
+UPDATE blog_authors SET role = 0 WHERE is_owner;
+ALTER TABLE blog_authors DROP COLUMN is_owner;
+
+CREATE TABLE post_reviews (
+    id SERIAL PRIMARY KEY,
+    post_id INTEGER REFERENCES posts(id) ON DELETE CASCADE NOT NULL UNIQUE,
+    submitted_by INTEGER REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    creation_date TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
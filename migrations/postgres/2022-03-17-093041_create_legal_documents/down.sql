@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE legal_acceptances;
+DROP TABLE legal_documents;
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE ip_records (
+    id SERIAL PRIMARY KEY,
+    kind INTEGER NOT NULL,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE,
+    comment_id INTEGER REFERENCES comments(id) ON DELETE CASCADE,
+    address TEXT NOT NULL,
+    ip_range TEXT,
+    creation_date TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX ip_records_address ON ip_records (address);
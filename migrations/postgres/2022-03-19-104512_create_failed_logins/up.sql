@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE failed_logins (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE,
+    address TEXT,
+    creation_date TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX failed_logins_user_id ON failed_logins (user_id);
+CREATE INDEX failed_logins_address ON failed_logins (address);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX medias_index_content_hash;
+ALTER TABLE medias DROP COLUMN content_hash;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE medias DROP COLUMN scan_result;
+ALTER TABLE medias DROP COLUMN scan_status;
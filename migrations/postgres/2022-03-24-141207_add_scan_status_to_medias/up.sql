@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE medias ADD COLUMN scan_status INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE medias ADD COLUMN scan_result TEXT;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE post_attachments;
+ALTER TABLE medias DROP COLUMN bitrate;
+ALTER TABLE medias DROP COLUMN duration;
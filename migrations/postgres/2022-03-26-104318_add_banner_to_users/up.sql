@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN banner_id INTEGER REFERENCES medias(id) ON DELETE SET NULL;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE maintenance (
+    id SERIAL PRIMARY KEY,
+    reason TEXT NOT NULL DEFAULT '',
+    start_date TIMESTAMP NOT NULL DEFAULT now()
+);
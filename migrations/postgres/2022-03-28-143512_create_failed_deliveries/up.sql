@@ -0,0 +1,15 @@
+-- Your SQL goes here
+CREATE TABLE failed_deliveries (
+    id SERIAL PRIMARY KEY,
+    activity_id TEXT NOT NULL,
+    activity TEXT NOT NULL,
+    key_id TEXT NOT NULL,
+    inbox TEXT NOT NULL,
+    host VARCHAR NOT NULL,
+    error TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 1,
+    creation_date TIMESTAMP NOT NULL DEFAULT now(),
+    last_attempt TIMESTAMP NOT NULL DEFAULT now()
+);
+CREATE INDEX failed_deliveries_host ON failed_deliveries (host);
+CREATE INDEX failed_deliveries_activity_id ON failed_deliveries (activity_id);
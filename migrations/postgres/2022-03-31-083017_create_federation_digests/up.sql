@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE federation_digests (
+    id SERIAL PRIMARY KEY,
+    report TEXT NOT NULL,
+    creation_date TIMESTAMP NOT NULL DEFAULT now()
+);
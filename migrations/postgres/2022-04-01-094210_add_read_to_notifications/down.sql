@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX notifications_user_id_read;
+ALTER TABLE notifications DROP COLUMN read;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX notifications_user_id_group_key;
+ALTER TABLE notifications DROP COLUMN count;
+ALTER TABLE notifications DROP COLUMN group_key;
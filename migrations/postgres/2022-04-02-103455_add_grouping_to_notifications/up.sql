@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE notifications ADD COLUMN group_key VARCHAR;
+ALTER TABLE notifications ADD COLUMN count INTEGER NOT NULL DEFAULT 1;
+CREATE INDEX notifications_user_id_group_key ON notifications (user_id, group_key);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE blogs DROP COLUMN directory_category;
+ALTER TABLE blogs DROP COLUMN discoverable;
+ALTER TABLE users DROP COLUMN discoverable;
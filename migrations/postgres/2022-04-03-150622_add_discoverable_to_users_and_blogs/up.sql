@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN discoverable BOOLEAN NOT NULL DEFAULT 'f';
+ALTER TABLE blogs ADD COLUMN discoverable BOOLEAN NOT NULL DEFAULT 'f';
+ALTER TABLE blogs ADD COLUMN directory_category VARCHAR;
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN indexable BOOLEAN NOT NULL DEFAULT 't';
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE media_policies (
+    id SERIAL PRIMARY KEY,
+    domain VARCHAR UNIQUE,
+    action INTEGER NOT NULL DEFAULT 0,
+    creation_date TIMESTAMP NOT NULL DEFAULT now()
+);
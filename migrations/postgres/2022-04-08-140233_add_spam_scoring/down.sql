@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE comments DROP COLUMN held;
+ALTER TABLE comments DROP COLUMN spam_score;
+ALTER TABLE users DROP COLUMN published;
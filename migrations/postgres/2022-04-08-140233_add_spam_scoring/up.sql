@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE comments ADD COLUMN held BOOLEAN NOT NULL DEFAULT 'f';
+ALTER TABLE comments ADD COLUMN spam_score INTEGER;
+ALTER TABLE users ADD COLUMN published TIMESTAMP;
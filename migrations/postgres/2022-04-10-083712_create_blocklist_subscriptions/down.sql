@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE instances DROP COLUMN blocked_by_subscription;
+DROP TABLE blocklist_exceptions;
+DROP TABLE blocklist_entries;
+DROP TABLE blocklist_sources;
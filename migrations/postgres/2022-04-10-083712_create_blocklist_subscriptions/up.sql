@@ -0,0 +1,21 @@
+-- Your SQL goes here
+CREATE TABLE blocklist_sources (
+    id SERIAL PRIMARY KEY,
+    url VARCHAR NOT NULL UNIQUE,
+    last_sync TIMESTAMP,
+    last_error TEXT,
+    creation_date TIMESTAMP NOT NULL DEFAULT now()
+);
+CREATE TABLE blocklist_entries (
+    id SERIAL PRIMARY KEY,
+    source_id INTEGER REFERENCES blocklist_sources(id) ON DELETE CASCADE NOT NULL,
+    domain VARCHAR NOT NULL,
+    reason TEXT NOT NULL DEFAULT '',
+    CONSTRAINT blocklist_entries_unique UNIQUE (source_id, domain)
+);
+CREATE TABLE blocklist_exceptions (
+    id SERIAL PRIMARY KEY,
+    domain VARCHAR NOT NULL UNIQUE,
+    creation_date TIMESTAMP NOT NULL DEFAULT now()
+);
+ALTER TABLE instances ADD COLUMN blocked_by_subscription BOOLEAN NOT NULL DEFAULT 'f';
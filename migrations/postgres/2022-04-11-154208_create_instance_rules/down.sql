@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE instances DROP COLUMN contact_user_id;
+DROP TABLE about_sections;
+DROP TABLE instance_rules;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE blogs DROP COLUMN moved_to;
+ALTER TABLE blogs DROP COLUMN moved_from;
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE post_translations (
+    id SERIAL PRIMARY KEY,
+    post_id INTEGER NOT NULL UNIQUE REFERENCES posts(id) ON DELETE CASCADE,
+    original_id INTEGER NOT NULL REFERENCES posts(id) ON DELETE CASCADE,
+    language VARCHAR NOT NULL,
+    translator_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    creation_date TIMESTAMP NOT NULL DEFAULT now()
+);
+CREATE INDEX post_translations_original_id ON post_translations (original_id);
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE blogs ADD COLUMN default_license TEXT;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX posts_canonical_url;
+ALTER TABLE posts DROP COLUMN canonical_url;
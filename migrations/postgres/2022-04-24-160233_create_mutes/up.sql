@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE mutes (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    kind INTEGER NOT NULL,
+    target_id INTEGER REFERENCES users(id) ON DELETE CASCADE,
+    value TEXT NOT NULL DEFAULT '',
+    expires_at TIMESTAMP,
+    creation_date TIMESTAMP NOT NULL DEFAULT now()
+);
+CREATE INDEX mutes_user_id ON mutes (user_id);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE notifications DROP COLUMN filtered;
+DROP TABLE notification_policies;
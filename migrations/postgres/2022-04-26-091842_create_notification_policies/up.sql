@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE notification_policies (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE UNIQUE,
+    not_following INTEGER NOT NULL DEFAULT 0,
+    new_accounts INTEGER NOT NULL DEFAULT 0,
+    no_avatar INTEGER NOT NULL DEFAULT 0
+);
+ALTER TABLE notifications ADD COLUMN filtered BOOLEAN NOT NULL DEFAULT 'f';
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE announcement_reactions;
+DROP TABLE announcement_dismissals;
+DROP TABLE announcements;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users DROP COLUMN last_active;
+ALTER TABLE users DROP COLUMN inactivity_reminded_at;
+ALTER TABLE users DROP COLUMN frozen_at;
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN last_active TIMESTAMP;
+ALTER TABLE users ADD COLUMN inactivity_reminded_at TIMESTAMP;
+ALTER TABLE users ADD COLUMN frozen_at TIMESTAMP;
+-- The existing local accounts are not treated as never used
+UPDATE users SET last_active = COALESCE(
+    (SELECT MAX(sessions.last_seen) FROM sessions WHERE sessions.user_id = users.id),
+    users.creation_date
+) WHERE private_key IS NOT NULL;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE email_changes;
+ALTER TABLE users DROP COLUMN pending_email;
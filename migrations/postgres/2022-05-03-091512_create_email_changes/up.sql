@@ -0,0 +1,11 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN pending_email TEXT;
+CREATE TABLE email_changes (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    email TEXT NOT NULL,
+    token VARCHAR NOT NULL UNIQUE,
+    confirmed BOOLEAN NOT NULL DEFAULT 'f',
+    expiration_date TIMESTAMP NOT NULL
+);
+CREATE INDEX email_changes_user_id ON email_changes (user_id);
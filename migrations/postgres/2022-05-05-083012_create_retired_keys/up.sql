@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE retired_keys (
+    id SERIAL PRIMARY KEY,
+    reference TEXT NOT NULL,
+    forget_after TIMESTAMP NOT NULL
+);
+CREATE INDEX retired_keys_forget_after ON retired_keys (forget_after);
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE failed_activities (
+    id SERIAL PRIMARY KEY,
+    activity TEXT NOT NULL,
+    error TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 1,
+    next_attempt TIMESTAMP NOT NULL DEFAULT now(),
+    creation_date TIMESTAMP NOT NULL DEFAULT now()
+);
+CREATE INDEX failed_activities_next_attempt ON failed_activities (next_attempt);
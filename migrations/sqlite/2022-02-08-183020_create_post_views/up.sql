@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE post_views (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    post_id INTEGER REFERENCES posts(id) ON DELETE CASCADE NOT NULL,
+    day DATE NOT NULL,
+    views INTEGER NOT NULL DEFAULT 0,
+    CONSTRAINT post_views_unique_post_day UNIQUE (post_id, day)
+);
+
+CREATE TABLE post_view_visitors (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    post_id INTEGER REFERENCES posts(id) ON DELETE CASCADE NOT NULL,
+    day DATE NOT NULL,
+    visitor VARCHAR NOT NULL,
+    CONSTRAINT post_view_visitors_unique UNIQUE (post_id, visitor)
+);
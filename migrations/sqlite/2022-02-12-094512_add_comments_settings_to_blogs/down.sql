@@ -0,0 +1,40 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE blogs_before_comments_settings (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    actor_id VARCHAR NOT NULL,
+    title VARCHAR NOT NULL,
+    summary TEXT NOT NULL DEFAULT '',
+    outbox_url VARCHAR NOT NULL UNIQUE,
+    inbox_url VARCHAR NOT NULL UNIQUE,
+    instance_id INTEGER REFERENCES instances(id) ON DELETE CASCADE NOT NULL,
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ap_url text not null default '' UNIQUE,
+    private_key TEXT,
+    public_key TEXT NOT NULL DEFAULT '',
+    fqn TEXT NOT NULL DEFAULT '',
+    summary_html TEXT NOT NULL DEFAULT '',
+    icon_id INTEGER REFERENCES medias(id) ON DELETE SET NULL DEFAULT NULL,
+    banner_id INTEGER REFERENCES medias(id) ON DELETE SET NULL DEFAULT NULL,
+    theme VARCHAR,
+    CONSTRAINT blog_unique UNIQUE (actor_id, instance_id)
+);
+INSERT INTO blogs_before_comments_settings SELECT
+    id,
+    actor_id,
+    title,
+    summary,
+    outbox_url,
+    inbox_url,
+    instance_id,
+    creation_date,
+    ap_url,
+    private_key,
+    public_key,
+    fqn,
+    summary_html,
+    icon_id,
+    banner_id,
+    theme
+FROM blogs;
+DROP TABLE blogs;
+ALTER TABLE blogs_before_comments_settings RENAME TO blogs;
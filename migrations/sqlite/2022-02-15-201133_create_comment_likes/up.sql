@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE comment_likes (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    comment_id INTEGER REFERENCES comments(id) ON DELETE CASCADE NOT NULL,
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ap_url VARCHAR NOT NULL DEFAULT '',
+    CONSTRAINT comment_likes_unique UNIQUE (user_id, comment_id)
+);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM notifications WHERE kind = 'THREAD_COMMENT';
+DROP TABLE thread_subscriptions;
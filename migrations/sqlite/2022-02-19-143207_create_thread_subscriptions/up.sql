@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE thread_subscriptions (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    post_id INTEGER REFERENCES posts(id) ON DELETE CASCADE NOT NULL,
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_emailed_comment_id INTEGER,
+    CONSTRAINT thread_subscriptions_unique UNIQUE (user_id, post_id)
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE crosspost_opt_outs;
+DROP TABLE crossposts;
+DROP TABLE connectors;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE tag_aliases (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    alias VARCHAR NOT NULL UNIQUE,
+    tag VARCHAR NOT NULL,
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+CREATE TABLE hashtag_follows (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    tag VARCHAR NOT NULL,
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT hashtag_follows_unique UNIQUE (user_id, tag)
+);
+
+CREATE TABLE relays (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    actor_id VARCHAR NOT NULL UNIQUE,
+    inbox_url VARCHAR NOT NULL,
+    public_key TEXT NOT NULL,
+    accepted BOOLEAN NOT NULL DEFAULT 'f',
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
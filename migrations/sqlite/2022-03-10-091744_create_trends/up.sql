@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE trending_tags (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    tag VARCHAR NOT NULL UNIQUE,
+    score REAL NOT NULL DEFAULT 0,
+    accounts INTEGER NOT NULL DEFAULT 0,
+    computed_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE trending_posts (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    post_id INTEGER REFERENCES posts(id) ON DELETE CASCADE NOT NULL UNIQUE,
+    score REAL NOT NULL DEFAULT 0,
+    accounts INTEGER NOT NULL DEFAULT 0,
+    computed_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE fundings (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    blog_id INTEGER REFERENCES blogs(id) ON DELETE CASCADE UNIQUE,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE UNIQUE,
+    payment_pointer VARCHAR NOT NULL DEFAULT '',
+    liberapay_url VARCHAR NOT NULL DEFAULT '',
+    kofi_url VARCHAR NOT NULL DEFAULT ''
+);
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE profile_fields (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    blog_id INTEGER REFERENCES blogs(id) ON DELETE CASCADE,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL DEFAULT 0,
+    name VARCHAR NOT NULL,
+    value TEXT NOT NULL,
+    value_html TEXT NOT NULL
+);
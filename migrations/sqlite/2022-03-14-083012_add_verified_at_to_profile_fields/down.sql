@@ -0,0 +1,21 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE profile_fields_before_verification (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    blog_id INTEGER REFERENCES blogs(id) ON DELETE CASCADE,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL DEFAULT 0,
+    name VARCHAR NOT NULL,
+    value TEXT NOT NULL,
+    value_html TEXT NOT NULL
+);
+INSERT INTO profile_fields_before_verification SELECT
+    id,
+    blog_id,
+    user_id,
+    position,
+    name,
+    value,
+    value_html
+FROM profile_fields;
+DROP TABLE profile_fields;
+ALTER TABLE profile_fields_before_verification RENAME TO profile_fields;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE profile_fields ADD COLUMN verified_at DATETIME;
+ALTER TABLE profile_fields ADD COLUMN last_checked_at DATETIME;
@@ -0,0 +1,23 @@
+-- Your SQL goes here
+CREATE TABLE blog_authors_with_role (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    blog_id INTEGER REFERENCES blogs(id) ON DELETE CASCADE NOT NULL,
+    author_id INTEGER REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    role INTEGER NOT NULL DEFAULT 1,
+    CONSTRAINT blog_authors_unique UNIQUE (blog_id, author_id)
+);
+INSERT INTO blog_authors_with_role SELECT
+    id,
+    blog_id,
+    author_id,
+    CASE WHEN is_owner THEN 0 ELSE 1 END
+FROM blog_authors;
+DROP TABLE blog_authors;
+ALTER TABLE blog_authors_with_role RENAME TO blog_authors;
+
+CREATE TABLE post_reviews (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    post_id INTEGER REFERENCES posts(id) ON DELETE CASCADE NOT NULL UNIQUE,
+    submitted_by INTEGER REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN silenced BOOLEAN NOT NULL DEFAULT 'f';
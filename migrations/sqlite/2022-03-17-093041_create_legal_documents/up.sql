@@ -0,0 +1,18 @@
+-- Your SQL goes here
+CREATE TABLE legal_documents (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    kind INTEGER NOT NULL,
+    version INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    content_html TEXT NOT NULL,
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT legal_documents_unique UNIQUE (kind, version)
+);
+
+CREATE TABLE legal_acceptances (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    document_id INTEGER REFERENCES legal_documents(id) ON DELETE CASCADE NOT NULL,
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT legal_acceptances_unique UNIQUE (user_id, document_id)
+);
@@ -0,0 +1,24 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE medias_before_variants (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    file_path TEXT NOT NULL DEFAULT '',
+    alt_text TEXT NOT NULL DEFAULT '',
+    is_remote BOOLEAN NOT NULL DEFAULT 'f',
+    remote_url TEXT,
+    sensitive BOOLEAN NOT NULL DEFAULT 'f',
+    content_warning TEXT,
+    owner_id INTEGER REFERENCES users(id) ON DELETE CASCADE NOT NULL
+);
+INSERT INTO medias_before_variants SELECT
+    id,
+    file_path,
+    alt_text,
+    is_remote,
+    remote_url,
+    sensitive,
+    content_warning,
+    owner_id
+FROM medias;
+DROP TABLE medias;
+ALTER TABLE medias_before_variants RENAME TO medias;
+CREATE INDEX medias_index_file_path ON medias (file_path);
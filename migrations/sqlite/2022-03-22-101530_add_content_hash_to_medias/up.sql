@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE medias ADD COLUMN content_hash TEXT;
+CREATE INDEX medias_index_content_hash ON medias (content_hash);
@@ -0,0 +1,31 @@
+-- Your SQL goes here
+CREATE TABLE medias_with_creation_date (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    file_path TEXT NOT NULL DEFAULT '',
+    alt_text TEXT NOT NULL DEFAULT '',
+    is_remote BOOLEAN NOT NULL DEFAULT 'f',
+    remote_url TEXT,
+    sensitive BOOLEAN NOT NULL DEFAULT 'f',
+    content_warning TEXT,
+    owner_id INTEGER REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    variants TEXT,
+    content_hash TEXT,
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+INSERT INTO medias_with_creation_date SELECT
+    id,
+    file_path,
+    alt_text,
+    is_remote,
+    remote_url,
+    sensitive,
+    content_warning,
+    owner_id,
+    variants,
+    content_hash,
+    CURRENT_TIMESTAMP
+FROM medias;
+DROP TABLE medias;
+ALTER TABLE medias_with_creation_date RENAME TO medias;
+CREATE INDEX medias_index_file_path ON medias (file_path);
+CREATE INDEX medias_index_content_hash ON medias (content_hash);
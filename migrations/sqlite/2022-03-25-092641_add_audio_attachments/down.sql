@@ -0,0 +1,37 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE post_attachments;
+
+CREATE TABLE medias_before_audio (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    file_path TEXT NOT NULL DEFAULT '',
+    alt_text TEXT NOT NULL DEFAULT '',
+    is_remote BOOLEAN NOT NULL DEFAULT 'f',
+    remote_url TEXT,
+    sensitive BOOLEAN NOT NULL DEFAULT 'f',
+    content_warning TEXT,
+    owner_id INTEGER REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    variants TEXT,
+    content_hash TEXT,
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    scan_status INTEGER NOT NULL DEFAULT 0,
+    scan_result TEXT
+);
+INSERT INTO medias_before_audio SELECT
+    id,
+    file_path,
+    alt_text,
+    is_remote,
+    remote_url,
+    sensitive,
+    content_warning,
+    owner_id,
+    variants,
+    content_hash,
+    creation_date,
+    scan_status,
+    scan_result
+FROM medias;
+DROP TABLE medias;
+ALTER TABLE medias_before_audio RENAME TO medias;
+CREATE INDEX medias_index_file_path ON medias (file_path);
+CREATE INDEX medias_index_content_hash ON medias (content_hash);
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+ALTER TABLE medias ADD COLUMN duration INTEGER;
+ALTER TABLE medias ADD COLUMN bitrate INTEGER;
+
+CREATE TABLE post_attachments (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    post_id INTEGER REFERENCES posts(id) ON DELETE CASCADE NOT NULL,
+    media_id INTEGER REFERENCES medias(id) ON DELETE CASCADE NOT NULL,
+    UNIQUE (post_id, media_id)
+);
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE maintenance (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    reason TEXT NOT NULL DEFAULT '',
+    start_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+CREATE TABLE failed_deliveries (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    activity_id TEXT NOT NULL,
+    activity TEXT NOT NULL,
+    key_id TEXT NOT NULL,
+    inbox TEXT NOT NULL,
+    host VARCHAR NOT NULL,
+    error TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 1,
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_attempt DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX failed_deliveries_host ON failed_deliveries (host);
+CREATE INDEX failed_deliveries_activity_id ON failed_deliveries (activity_id);
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+CREATE TABLE severances (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    domain VARCHAR NOT NULL,
+    reason VARCHAR NOT NULL,
+    notified BOOLEAN NOT NULL DEFAULT 'f',
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE severed_relationships (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    severance_id INTEGER NOT NULL REFERENCES severances(id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    remote_actor VARCHAR NOT NULL,
+    following BOOLEAN NOT NULL
+);
+CREATE INDEX severed_relationships_severance_id ON severed_relationships (severance_id);
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE federation_digests (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    report TEXT NOT NULL,
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,17 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE notifications_before_read (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    kind VARCHAR NOT NULL DEFAULT 'unknown',
+    object_id INTEGER NOT NULL DEFAULT 0
+);
+INSERT INTO notifications_before_read SELECT
+    id,
+    user_id,
+    creation_date,
+    kind,
+    object_id
+FROM notifications;
+DROP TABLE notifications;
+ALTER TABLE notifications_before_read RENAME TO notifications;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE notifications ADD COLUMN read BOOLEAN NOT NULL DEFAULT 'f';
+CREATE INDEX notifications_user_id_read ON notifications (user_id, read);
@@ -0,0 +1,20 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE notifications_before_grouping (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    kind VARCHAR NOT NULL DEFAULT 'unknown',
+    object_id INTEGER NOT NULL DEFAULT 0,
+    read BOOLEAN NOT NULL DEFAULT 'f'
+);
+INSERT INTO notifications_before_grouping SELECT
+    id,
+    user_id,
+    creation_date,
+    kind,
+    object_id,
+    read
+FROM notifications;
+DROP TABLE notifications;
+ALTER TABLE notifications_before_grouping RENAME TO notifications;
+CREATE INDEX notifications_user_id_read ON notifications (user_id, read);
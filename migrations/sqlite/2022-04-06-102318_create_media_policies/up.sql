@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE media_policies (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    domain VARCHAR UNIQUE,
+    action INTEGER NOT NULL DEFAULT 0,
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,21 @@
+-- Your SQL goes here
+CREATE TABLE blocklist_sources (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    url VARCHAR NOT NULL UNIQUE,
+    last_sync DATETIME,
+    last_error TEXT,
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE TABLE blocklist_entries (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    source_id INTEGER REFERENCES blocklist_sources(id) ON DELETE CASCADE NOT NULL,
+    domain VARCHAR NOT NULL,
+    reason TEXT NOT NULL DEFAULT '',
+    CONSTRAINT blocklist_entries_unique UNIQUE (source_id, domain)
+);
+CREATE TABLE blocklist_exceptions (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    domain VARCHAR NOT NULL UNIQUE,
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+ALTER TABLE instances ADD COLUMN blocked_by_subscription BOOLEAN NOT NULL DEFAULT 'f';
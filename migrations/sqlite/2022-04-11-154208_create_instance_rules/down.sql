@@ -0,0 +1,35 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE instances_before_contact (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    public_domain VARCHAR NOT NULL UNIQUE,
+    name VARCHAR NOT NULL,
+    local BOOLEAN NOT NULL DEFAULT 'f',
+    blocked BOOLEAN NOT NULL DEFAULT 'f',
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    open_registrations BOOLEAN NOT NULL DEFAULT 't',
+    short_description TEXT NOT NULL DEFAULT '',
+    long_description TEXT NOT NULL DEFAULT '',
+    default_license TEXT NOT NULL DEFAULT 'CC-BY-SA',
+    long_description_html VARCHAR NOT NULL DEFAULT '',
+    short_description_html VARCHAR NOT NULL DEFAULT '',
+    blocked_by_subscription BOOLEAN NOT NULL DEFAULT 'f'
+);
+INSERT INTO instances_before_contact SELECT
+    id,
+    public_domain,
+    name,
+    local,
+    blocked,
+    creation_date,
+    open_registrations,
+    short_description,
+    long_description,
+    default_license,
+    long_description_html,
+    short_description_html,
+    blocked_by_subscription
+FROM instances;
+DROP TABLE instances;
+ALTER TABLE instances_before_contact RENAME TO instances;
+DROP TABLE about_sections;
+DROP TABLE instance_rules;
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE instance_rules (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    position INTEGER NOT NULL DEFAULT 0,
+    short_text VARCHAR NOT NULL,
+    long_text TEXT NOT NULL DEFAULT '',
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE TABLE about_sections (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    position INTEGER NOT NULL DEFAULT 0,
+    title VARCHAR NOT NULL,
+    content TEXT NOT NULL DEFAULT '',
+    content_html TEXT NOT NULL DEFAULT ''
+);
+ALTER TABLE instances ADD COLUMN contact_user_id INTEGER REFERENCES users(id) ON DELETE SET NULL;
@@ -0,0 +1,50 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE blogs_before_moves (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    actor_id VARCHAR NOT NULL,
+    title VARCHAR NOT NULL,
+    summary TEXT NOT NULL DEFAULT '',
+    outbox_url VARCHAR NOT NULL UNIQUE,
+    inbox_url VARCHAR NOT NULL UNIQUE,
+    instance_id INTEGER REFERENCES instances(id) ON DELETE CASCADE NOT NULL,
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ap_url text not null default '' UNIQUE,
+    private_key TEXT,
+    public_key TEXT NOT NULL DEFAULT '',
+    fqn TEXT NOT NULL DEFAULT '',
+    summary_html TEXT NOT NULL DEFAULT '',
+    icon_id INTEGER REFERENCES medias(id) ON DELETE SET NULL DEFAULT NULL,
+    banner_id INTEGER REFERENCES medias(id) ON DELETE SET NULL DEFAULT NULL,
+    theme VARCHAR,
+    comments_order INTEGER NOT NULL DEFAULT 0,
+    comments_max_depth INTEGER,
+    allow_guest_comments BOOLEAN NOT NULL DEFAULT 'f',
+    discoverable BOOLEAN NOT NULL DEFAULT 'f',
+    directory_category VARCHAR,
+    CONSTRAINT blog_unique UNIQUE (actor_id, instance_id)
+);
+INSERT INTO blogs_before_moves SELECT
+    id,
+    actor_id,
+    title,
+    summary,
+    outbox_url,
+    inbox_url,
+    instance_id,
+    creation_date,
+    ap_url,
+    private_key,
+    public_key,
+    fqn,
+    summary_html,
+    icon_id,
+    banner_id,
+    theme,
+    comments_order,
+    comments_max_depth,
+    allow_guest_comments,
+    discoverable,
+    directory_category
+FROM blogs;
+DROP TABLE blogs;
+ALTER TABLE blogs_before_moves RENAME TO blogs;
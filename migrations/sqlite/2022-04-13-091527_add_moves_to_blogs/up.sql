@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE blogs ADD COLUMN moved_to TEXT;
+ALTER TABLE blogs ADD COLUMN moved_from TEXT;
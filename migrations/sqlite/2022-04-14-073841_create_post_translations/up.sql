@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE post_translations (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    post_id INTEGER NOT NULL UNIQUE REFERENCES posts(id) ON DELETE CASCADE,
+    original_id INTEGER NOT NULL REFERENCES posts(id) ON DELETE CASCADE,
+    language VARCHAR NOT NULL,
+    translator_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX post_translations_original_id ON post_translations (original_id);
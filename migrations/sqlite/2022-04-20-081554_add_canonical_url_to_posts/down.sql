@@ -0,0 +1,35 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX posts_canonical_url;
+CREATE TABLE posts_before_canonical_url (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    blog_id INTEGER REFERENCES blogs(id) ON DELETE CASCADE NOT NULL,
+    slug VARCHAR NOT NULL,
+    title VARCHAR NOT NULL,
+    content TEXT NOT NULL DEFAULT '',
+    published BOOLEAN NOT NULL DEFAULT 'f',
+    license VARCHAR NOT NULL DEFAULT 'CC-BY-SA',
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ap_url VARCHAR NOT NULL DEFAULT '' UNIQUE,
+    subtitle TEXT NOT NULL DEFAULT '',
+    source TEXT NOT NULL DEFAULT '',
+    cover_id INTEGER REFERENCES medias(id) ON DELETE SET NULL DEFAULT NULL,
+    excerpt TEXT NOT NULL DEFAULT '',
+    CONSTRAINT blog_authors_unique UNIQUE (blog_id, slug)
+);
+INSERT INTO posts_before_canonical_url SELECT
+    id,
+    blog_id,
+    slug,
+    title,
+    content,
+    published,
+    license,
+    creation_date,
+    ap_url,
+    subtitle,
+    source,
+    cover_id,
+    excerpt
+FROM posts;
+DROP TABLE posts;
+ALTER TABLE posts_before_canonical_url RENAME TO posts;
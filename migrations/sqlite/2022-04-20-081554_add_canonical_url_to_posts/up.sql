@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE posts ADD COLUMN canonical_url TEXT;
+CREATE INDEX posts_canonical_url ON posts (canonical_url);
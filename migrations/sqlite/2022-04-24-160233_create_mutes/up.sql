@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE mutes (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    kind INTEGER NOT NULL,
+    target_id INTEGER REFERENCES users(id) ON DELETE CASCADE,
+    value TEXT NOT NULL DEFAULT '',
+    expires_at DATETIME,
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX mutes_user_id ON mutes (user_id);
@@ -0,0 +1,26 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE notifications_before_filtering (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    kind VARCHAR NOT NULL DEFAULT 'unknown',
+    object_id INTEGER NOT NULL DEFAULT 0,
+    read BOOLEAN NOT NULL DEFAULT 'f',
+    group_key VARCHAR,
+    count INTEGER NOT NULL DEFAULT 1
+);
+INSERT INTO notifications_before_filtering SELECT
+    id,
+    user_id,
+    creation_date,
+    kind,
+    object_id,
+    read,
+    group_key,
+    count
+FROM notifications;
+DROP TABLE notifications;
+ALTER TABLE notifications_before_filtering RENAME TO notifications;
+CREATE INDEX notifications_user_id_read ON notifications (user_id, read);
+CREATE INDEX notifications_user_id_group_key ON notifications (user_id, group_key);
+DROP TABLE notification_policies;
@@ -0,0 +1,23 @@
+-- Your SQL goes here
+CREATE TABLE announcements (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    author_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    content TEXT NOT NULL,
+    content_html TEXT NOT NULL,
+    starts_at DATETIME,
+    ends_at DATETIME,
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE TABLE announcement_dismissals (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    announcement_id INTEGER NOT NULL REFERENCES announcements(id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    CONSTRAINT announcement_dismissals_unique UNIQUE (announcement_id, user_id)
+);
+CREATE TABLE announcement_reactions (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    announcement_id INTEGER NOT NULL REFERENCES announcements(id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    emoji VARCHAR NOT NULL,
+    CONSTRAINT announcement_reactions_unique UNIQUE (announcement_id, user_id, emoji)
+);
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE failed_activities (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    activity TEXT NOT NULL,
+    error TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 1,
+    next_attempt DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    creation_date DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX failed_activities_next_attempt ON failed_activities (next_attempt);
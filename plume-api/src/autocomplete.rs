@@ -0,0 +1,20 @@
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ActorSuggestionData {
+    pub id: i32,
+    pub fqn: String,
+    pub display_name: String,
+    pub avatar: String,
+    pub url: String,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct EmojiSuggestionData {
+    pub shortcode: String,
+    pub emoji: String,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct AutocompleteData {
+    pub actors: Vec<ActorSuggestionData>,
+    pub emojis: Vec<EmojiSuggestionData>,
+}
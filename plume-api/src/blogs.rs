@@ -0,0 +1,16 @@
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct BlogMemberData {
+    /// The fully qualified name of the user
+    pub user: String,
+    /// `owner`, `editor` or `contributor`
+    pub role: String,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ReviewData {
+    pub post_id: i32,
+    pub title: String,
+    /// The contributor who submitted the article
+    pub submitted_by: String,
+    pub creation_date: String,
+}
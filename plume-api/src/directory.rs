@@ -0,0 +1,16 @@
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct DirectoryEntryData {
+    pub id: i32,
+    /// `blog` or `author`
+    pub kind: String,
+    pub fqn: String,
+    pub name: String,
+    /// The description, as HTML
+    pub summary: String,
+    pub url: String,
+    /// The category of a blog
+    pub category: Option<String>,
+    pub creation_date: String,
+    /// When they last published an article
+    pub last_activity: Option<String>,
+}
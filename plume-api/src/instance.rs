@@ -0,0 +1,103 @@
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct MaintenanceData {
+    pub active: bool,
+    pub reason: String,
+    /// When it started, if it is active
+    pub since: Option<String>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct StartMaintenanceData {
+    /// Shown to visitors
+    pub reason: Option<String>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ConfigProblemData {
+    pub variable: String,
+    pub message: String,
+    pub hint: String,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ConfigReloadData {
+    /// False if there were problems, and the previous configuration is still used
+    pub reloaded: bool,
+    pub problems: Vec<ConfigProblemData>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct InstanceData {
+    pub domain: String,
+    pub title: String,
+    /// As HTML
+    pub short_description: String,
+    /// As HTML
+    pub description: String,
+    pub version: String,
+    pub registrations: bool,
+    pub default_license: String,
+    pub contact: Option<InstanceContactData>,
+    pub rules: Vec<InstanceRuleData>,
+    pub sections: Vec<AboutSectionData>,
+    pub stats: InstanceStatsData,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct InstanceContactData {
+    pub fqn: String,
+    pub name: String,
+    pub url: String,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct InstanceRuleData {
+    pub id: i32,
+    pub text: String,
+    /// More details about the rule, may be empty
+    pub hint: String,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct AboutSectionData {
+    pub title: String,
+    /// As HTML
+    pub content: String,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct InstanceStatsData {
+    pub users: i64,
+    pub articles: i64,
+    /// The number of other instances this one knows
+    pub instances: i64,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct AnnouncementData {
+    pub id: i32,
+    /// As HTML
+    pub content: String,
+    pub starts_at: Option<String>,
+    pub ends_at: Option<String>,
+    pub creation_date: String,
+    pub reactions: Vec<AnnouncementReactionData>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct AnnouncementReactionData {
+    pub emoji: String,
+    pub count: usize,
+    /// Whether the user reacted with it
+    pub me: bool,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct NewAnnouncementData {
+    /// As Markdown
+    pub content: String,
+    /// Formatted as `%Y-%m-%dT%H:%M:%SZ`, it is shown right away if it is missing
+    pub starts_at: Option<String>,
+    /// Formatted as `%Y-%m-%dT%H:%M:%SZ`, it is shown until it is deleted if it is missing
+    pub ends_at: Option<String>,
+}
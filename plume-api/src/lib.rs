@@ -2,4 +2,16 @@
 extern crate serde_derive;
 
 pub mod apps;
+pub mod autocomplete;
+pub mod blogs;
+pub mod directory;
+pub mod instance;
+pub mod medias;
+pub mod notifications;
 pub mod posts;
+pub mod profiles;
+pub mod recommendations;
+pub mod search;
+pub mod stats;
+pub mod trends;
+pub mod users;
@@ -0,0 +1,24 @@
+use std::collections::HashMap;
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct MediaVariantData {
+    /// `thumbnail`, `medium` or `full`
+    pub size: String,
+    pub mime_type: String,
+    pub width: u32,
+    pub height: u32,
+    pub url: String,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct MediaData {
+    pub id: i32,
+    pub url: String,
+    pub alt_text: String,
+    pub sensitive: bool,
+    pub content_warning: Option<String>,
+    /// Smaller and lighter copies of an image, once they are generated
+    pub variants: Vec<MediaVariantData>,
+    /// The variants in each type, ready to be used in a `srcset` attribute
+    pub srcset: HashMap<String, String>,
+}
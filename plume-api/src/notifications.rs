@@ -0,0 +1,51 @@
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct NotificationData {
+    pub id: i32,
+    /// `mention`, `comment`, `follow`, `like`, `boost`, `report` or `system`
+    #[serde(rename = "type")]
+    pub notification_type: String,
+    /// The precise kind of notification, like `THREAD_COMMENT`
+    pub kind: String,
+    pub read: bool,
+    pub creation_date: String,
+    /// The account that caused it, if any
+    pub account: Option<String>,
+    pub post_id: Option<i32>,
+    pub url: Option<String>,
+    /// How many times it happened, when the same thing happened several times in a row (like
+    /// several people liking the same article), `account` being the last one
+    pub count: i32,
+    /// Notifications with the same key are grouped while they are unread
+    pub group_key: Option<String>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct MarkReadData {
+    /// The notifications to mark as read, all of them if it is missing
+    pub ids: Option<Vec<i32>>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct MarkedReadData {
+    /// How many notifications were marked as read
+    pub marked: usize,
+    /// How many are still unread
+    pub unread: i64,
+}
+
+/// What to do with the notifications of some accounts: `accept`, `filter` or `drop` them.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct NotificationPolicyData {
+    /// For the accounts the user doesn't follow
+    pub not_following: String,
+    /// For the accounts created less than 30 days ago
+    pub new_accounts: String,
+    /// For the accounts without an avatar
+    pub no_avatar: String,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ReviewFilteredData {
+    /// The filtered notifications to accept or dismiss, all of them if it is missing
+    pub ids: Option<Vec<i32>>,
+}
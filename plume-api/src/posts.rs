@@ -2,6 +2,8 @@
 pub struct NewPostData {
     pub title: String,
     pub subtitle: Option<String>,
+    /// Presents the article in timelines and feeds, instead of its subtitle
+    pub excerpt: Option<String>,
     pub source: String,
     pub author: String,
     // If None, and that there is only one blog, it will be choosen automatically.
@@ -9,9 +11,46 @@ pub struct NewPostData {
     pub blog_id: Option<i32>,
     pub published: Option<bool>,
     pub creation_date: Option<String>,
+    /// If None, the default license of the blog is used.
     pub license: Option<String>,
     pub tags: Option<Vec<String>>,
     pub cover_id: Option<i32>,
+    /// Where the article was first published, if it is cross-posted
+    pub canonical_url: Option<String>,
+    /// Set to false to keep this post from being mirrored to the connectors of the blog
+    pub crosspost: Option<bool>,
+    /// The IDs of audio files of the author to attach to this post
+    pub attachments: Option<Vec<i32>>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct TocEntryData {
+    pub level: u32,
+    pub title: String,
+    /// The id of the heading in `content`
+    pub anchor: String,
+}
+
+/// A file attached to a post, that clients can show with a player.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct AttachmentData {
+    pub id: i32,
+    pub url: String,
+    pub mime_type: Option<String>,
+    pub alt_text: String,
+    /// In milliseconds
+    pub duration: Option<i32>,
+    /// In bits per second
+    pub bitrate: Option<i32>,
+}
+
+/// A mention of a new post that couldn't be linked to an account.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct MentionFailureData {
+    /// The mentioned account, without its `@`
+    pub mention: String,
+    /// `not_found`, `unreachable` or `invalid_actor`
+    pub reason: String,
 }
 
 #[derive(Clone, Default, Serialize, Deserialize)]
@@ -19,6 +58,7 @@ pub struct PostData {
     pub id: i32,
     pub title: String,
     pub subtitle: String,
+    pub excerpt: String,
     pub content: String,
     pub source: Option<String>,
     pub authors: Vec<String>,
@@ -28,4 +68,10 @@ pub struct PostData {
     pub license: String,
     pub tags: Vec<String>,
     pub cover_id: Option<i32>,
+    pub canonical_url: Option<String>,
+    pub toc: Vec<TocEntryData>,
+    pub attachments: Vec<AttachmentData>,
+    /// Only when the post was just created
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unresolved_mentions: Vec<MentionFailureData>,
 }
@@ -0,0 +1,15 @@
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ProfileFieldData {
+    pub name: String,
+    /// The value, as text
+    pub value: String,
+    pub value_html: String,
+    /// When the link of this field was found to link back to the profile, if it does
+    pub verified_at: Option<String>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct NewProfileFieldData {
+    pub name: String,
+    pub value: String,
+}
@@ -0,0 +1,29 @@
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct RecommendationsData {
+    pub blogs: Vec<RecommendedBlogData>,
+    pub authors: Vec<RecommendedAuthorData>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct RecommendedBlogData {
+    pub id: i32,
+    pub fqn: String,
+    pub title: String,
+    /// As HTML
+    pub summary: String,
+    pub url: String,
+    /// How many articles it published in the last 30 days
+    pub recent_articles: i64,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct RecommendedAuthorData {
+    pub id: i32,
+    pub fqn: String,
+    pub name: String,
+    /// As HTML
+    pub summary: String,
+    pub url: String,
+    /// How many users of this instance follow them
+    pub local_followers: i64,
+}
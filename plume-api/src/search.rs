@@ -0,0 +1,43 @@
+use crate::posts::PostData;
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct AccountData {
+    pub id: i32,
+    pub fqn: String,
+    pub display_name: String,
+    pub avatar: String,
+    pub url: String,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct BlogData {
+    pub id: i32,
+    pub fqn: String,
+    pub title: String,
+    pub summary: String,
+    pub icon: String,
+    pub url: String,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct CommentData {
+    pub id: i32,
+    pub post_id: i32,
+    pub in_response_to_id: Option<i32>,
+    /// The address of the author
+    pub author: String,
+    pub content: String,
+    pub sensitive: bool,
+    pub spoiler_text: String,
+    pub creation_date: String,
+    pub url: Option<String>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct SearchData {
+    pub articles: Vec<PostData>,
+    pub comments: Vec<CommentData>,
+    pub accounts: Vec<AccountData>,
+    pub blogs: Vec<BlogData>,
+    pub hashtags: Vec<String>,
+}
@@ -0,0 +1,17 @@
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct DailyStatsData {
+    pub date: String,
+    pub views: i64,
+    pub likes: i64,
+    pub reshares: i64,
+    pub comments: i64,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct StatsData {
+    pub views: i64,
+    pub likes: i64,
+    pub reshares: i64,
+    pub comments: i64,
+    pub days: Vec<DailyStatsData>,
+}
@@ -0,0 +1,25 @@
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct TrendingTagData {
+    pub tag: String,
+    pub score: f32,
+    /// How many different accounts used this tag recently
+    pub accounts: i32,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct TrendingPostData {
+    pub id: i32,
+    pub title: String,
+    pub subtitle: String,
+    pub url: String,
+    pub blog_id: i32,
+    pub score: f32,
+    /// How many different accounts interacted with this article recently
+    pub accounts: i32,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct TrendsData {
+    pub tags: Vec<TrendingTagData>,
+    pub posts: Vec<TrendingPostData>,
+}
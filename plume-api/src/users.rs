@@ -0,0 +1,71 @@
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ErasureData {
+    /// The password of the user, to confirm that they want their account to be deleted
+    pub password: String,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct PasswordChangeData {
+    /// The current password of the user
+    pub password: String,
+    /// It has to follow the password policy of the instance, or the problems are returned
+    pub new_password: String,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct LegalDocumentData {
+    /// `terms` or `privacy`
+    pub kind: String,
+    pub version: i32,
+    pub published_at: String,
+    /// If the user accepted this version
+    pub accepted: bool,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct SessionData {
+    pub id: i32,
+    pub user_agent: Option<String>,
+    /// The last address this session was used from, unless the instance only keeps a hash of it
+    pub address: Option<String>,
+    pub creation_date: String,
+    pub last_seen: String,
+}
+
+/// A private note about a followed account.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct AccountNoteData {
+    /// The fully qualified name of the account
+    pub account: String,
+    pub content: String,
+    pub updated_at: String,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct NewAccountNoteData {
+    /// An empty note deletes it
+    pub content: String,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct MuteData {
+    pub id: i32,
+    /// `account`, `domain` or `keyword`
+    pub kind: String,
+    /// The fully qualified name of the muted account
+    pub account: Option<String>,
+    /// The muted domain or keyword
+    pub value: String,
+    pub expires_at: Option<String>,
+    pub creation_date: String,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct NewMuteData {
+    /// `account`, `domain` or `keyword`
+    pub kind: String,
+    /// The fully qualified name of an account, a domain or a keyword
+    pub value: String,
+    /// Formatted as `%Y-%m-%dT%H:%M:%SZ`. The mute lasts until it is removed if it is not given.
+    pub expires_at: Option<String>,
+}
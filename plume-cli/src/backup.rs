@@ -0,0 +1,118 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use plume_models::{backup, Connection};
+use std::path::Path;
+
+pub fn command<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("backup")
+        .about("Save and restore the database, media and search index of the instance")
+        .subcommand(
+            SubCommand::with_name("create")
+                .arg(
+                    Arg::with_name("archive")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Where to write the archive"),
+                )
+                .about("Write an archive of the instance, while Plume is running"),
+        )
+        .subcommand(
+            SubCommand::with_name("restore")
+                .arg(
+                    Arg::with_name("archive")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The archive made with plm backup create"),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .short("f")
+                        .long("force")
+                        .help("Replace the current database, media and search index"),
+                )
+                .about("Restore an archive of the instance, while Plume is stopped"),
+        )
+        .subcommand(
+            SubCommand::with_name("info")
+                .arg(
+                    Arg::with_name("archive")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The archive made with plm backup create"),
+                )
+                .about("Show what an archive contains, and if it can be restored"),
+        )
+}
+
+pub fn run<'a>(args: &ArgMatches<'a>, conn: Option<Connection>) {
+    match args.subcommand() {
+        ("create", Some(x)) => create(x, &conn.expect("Couldn't connect to the database.")),
+        ("restore", Some(x)) => restore(x, conn),
+        ("info", Some(x)) => info(x),
+        ("", None) => command().print_help().unwrap(),
+        _ => println!("Unknown subcommand"),
+    }
+}
+
+fn create<'a>(args: &ArgMatches<'a>, conn: &Connection) {
+    let path = Path::new(args.value_of("archive").expect("No archive provided"));
+    let manifest = backup::create(conn, path).expect("Couldn't write the archive");
+    println!("The instance was saved to {}", path.display());
+    if !manifest.media {
+        println!("The media are stored in S3 and were not saved: back up the bucket too");
+    }
+}
+
+fn restore<'a>(args: &ArgMatches<'a>, conn: Option<Connection>) {
+    let path = Path::new(args.value_of("archive").expect("No archive provided"));
+    let manifest = backup::manifest(path).expect("Couldn't read the archive");
+    if let Some(problem) = manifest.incompatibility() {
+        eprintln!("The archive can't be restored: {}", problem);
+        return;
+    }
+    let existing = backup::existing_data(conn.as_ref());
+    if !existing.is_empty() && !args.is_present("force") {
+        eprintln!(
+            "This would replace {}, use --force to restore anyway",
+            existing.join(", ")
+        );
+        return;
+    }
+    // The database may be replaced: don't keep using it
+    drop(conn);
+
+    backup::restore(path).expect("Couldn't restore the archive");
+    println!(
+        "The instance was restored as of {}",
+        manifest.creation_date.format("%Y-%m-%d %H:%M")
+    );
+    if manifest.plume_version != env!("CARGO_PKG_VERSION") {
+        println!(
+            "It was saved by Plume {}: run plm migration run before starting Plume",
+            manifest.plume_version
+        );
+    }
+}
+
+fn info<'a>(args: &ArgMatches<'a>) {
+    let path = Path::new(args.value_of("archive").expect("No archive provided"));
+    let manifest = backup::manifest(path).expect("Couldn't read the archive");
+    println!(
+        "Saved on {} by Plume {}",
+        manifest.creation_date.format("%Y-%m-%d %H:%M"),
+        manifest.plume_version
+    );
+    println!("Database: {}", manifest.engine);
+    println!(
+        "Last migration: {}",
+        manifest.migration.as_deref().unwrap_or("none")
+    );
+    println!(
+        "Media included: {}",
+        if manifest.media { "yes" } else { "no" }
+    );
+    match manifest.incompatibility() {
+        Some(problem) => println!("It can't be restored: {}", problem),
+        None => println!("It can be restored by this version of Plume"),
+    }
+}
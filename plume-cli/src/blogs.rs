@@ -0,0 +1,51 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use plume_models::{blogs::Blog, static_export, Connection};
+use std::path::Path;
+
+pub fn command<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("blogs")
+        .about("Manage blogs")
+        .subcommand(
+            SubCommand::with_name("export-static")
+                .arg(
+                    Arg::with_name("blog")
+                        .required(true)
+                        .takes_value(true)
+                        .help("The name of the blog to export"),
+                )
+                .arg(
+                    Arg::with_name("out")
+                        .short("o")
+                        .long("out")
+                        .required(true)
+                        .takes_value(true)
+                        .help("The directory in which the HTML files are written"),
+                )
+                .about("Write a static HTML copy of a blog, with its media"),
+        )
+}
+
+pub fn run<'a>(args: &ArgMatches<'a>, conn: &Connection) {
+    let conn = conn;
+    match args.subcommand() {
+        ("export-static", Some(x)) => export_static(x, conn),
+        ("", None) => command().print_help().unwrap(),
+        _ => println!("Unknown subcommand"),
+    }
+}
+
+fn export_static(args: &ArgMatches<'_>, conn: &Connection) {
+    let name = args.value_of("blog").expect("No blog provided");
+    let out = args.value_of("out").expect("No output directory provided");
+    let blog = Blog::find_by_fqn(conn, name).expect("Blog not found");
+    let report =
+        static_export::export(conn, &blog, Path::new(out)).expect("Couldn't export the blog");
+    println!(
+        "{} article(s), {} tag(s) and {} media file(s) exported to {}",
+        report.articles, report.tags, report.medias, out
+    );
+    for error in report.errors {
+        eprintln!("Couldn't copy {}", error);
+    }
+}
@@ -0,0 +1,71 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use plume_models::{blogs::Blog, categories::Category, Connection};
+
+pub fn command<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("categories")
+        .about("Manage the categories of blogs")
+        .subcommand(
+            SubCommand::with_name("promote")
+                .arg(
+                    Arg::with_name("blog")
+                        .short("b")
+                        .long("blog")
+                        .takes_value(true)
+                        .help("The blog whose tags should be turned into categories"),
+                )
+                .arg(
+                    Arg::with_name("tag")
+                        .short("t")
+                        .long("tag")
+                        .takes_value(true)
+                        .help("The tag to turn into a category"),
+                )
+                .arg(
+                    Arg::with_name("parent")
+                        .short("p")
+                        .long("parent")
+                        .takes_value(true)
+                        .help("Slug of the category the new one should be put in"),
+                )
+                .arg(
+                    Arg::with_name("all")
+                        .short("a")
+                        .long("all")
+                        .help("Turn all the tags of the blog into top-level categories"),
+                )
+                .about("Turn tags into categories, with all the articles that have them"),
+        )
+}
+
+pub fn run<'a>(args: &ArgMatches<'a>, conn: &Connection) {
+    let conn = conn;
+    match args.subcommand() {
+        ("promote", Some(x)) => promote(x, conn),
+        ("", None) => command().print_help().unwrap(),
+        _ => println!("Unknown subcommand"),
+    }
+}
+
+fn promote(args: &ArgMatches<'_>, conn: &Connection) {
+    let blog = args.value_of("blog").expect("No blog provided");
+    let blog = Blog::find_by_fqn(conn, blog).expect("Couldn't find this blog");
+
+    let promoted = if args.is_present("all") {
+        Category::promote_all_tags(conn, &blog).expect("Couldn't promote the tags")
+    } else {
+        let tag = args.value_of("tag").expect("No tag provided");
+        let parent = args.value_of("parent").map(|slug| {
+            Category::find_by_slug(conn, blog.id, slug).expect("Couldn't find the parent category")
+        });
+        vec![Category::promote_tag(conn, &blog, tag, parent.as_ref())
+            .expect("Couldn't promote the tag")]
+    };
+
+    for (category, added) in promoted {
+        println!(
+            "{}: {} article(s) added to the category",
+            category.name, added
+        );
+    }
+}
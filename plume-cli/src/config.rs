@@ -0,0 +1,33 @@
+use clap::{App, ArgMatches, SubCommand};
+
+use plume_models::config;
+
+pub fn command<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("config")
+        .about("Inspect the configuration")
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Check the configuration, and list all the problems it has"),
+        )
+}
+
+pub fn run<'a>(args: &ArgMatches<'a>) {
+    match args.subcommand() {
+        ("check", Some(_)) => check(),
+        ("", None) => command().print_help().unwrap(),
+        _ => println!("Unknown subcommand"),
+    }
+}
+
+fn check() {
+    let problems = config::check();
+    if problems.is_empty() {
+        println!("The configuration is valid");
+        return;
+    }
+    for problem in &problems {
+        println!("{}\n", problem);
+    }
+    eprintln!("{} problems were found", problems.len());
+    std::process::exit(1);
+}
@@ -0,0 +1,38 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+pub fn command<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("db")
+        .about("Manage the database")
+        .subcommand(
+            SubCommand::with_name("backup")
+                .arg(
+                    Arg::with_name("path")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Where to write the copy of the database"),
+                )
+                .about("Copy the SQLite database, while Plume is running"),
+        )
+}
+
+pub fn run<'a>(args: &ArgMatches<'a>) {
+    match args.subcommand() {
+        ("backup", Some(x)) => backup(x),
+        ("", None) => command().print_help().unwrap(),
+        _ => println!("Unknown subcommand"),
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn backup<'a>(args: &ArgMatches<'a>) {
+    use std::path::Path;
+
+    let path = Path::new(args.value_of("path").unwrap());
+    plume_models::db_conn::backup(path).expect("Couldn't back up the database");
+    println!("The database was copied to {}", path.display());
+}
+
+#[cfg(feature = "postgres")]
+fn backup<'a>(_args: &ArgMatches<'a>) {
+    eprintln!("PostgreSQL databases can be copied while they are used with pg_dump");
+}
@@ -0,0 +1,145 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use plume_models::{failed_deliveries::FailedDelivery, lookup, probe, Connection};
+use std::collections::BTreeMap;
+
+pub fn command<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("federation")
+        .about("Inspect and debug the federation with other instances")
+        .subcommand(
+            SubCommand::with_name("fetch")
+                .arg(
+                    Arg::with_name("url")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The URL of the object"),
+                )
+                .arg(
+                    Arg::with_name("save")
+                        .short("s")
+                        .long("save")
+                        .help("Save the actor, article or comment, instead of printing it"),
+                )
+                .about("Fetch an object with a signed request, and print it"),
+        )
+        .subcommand(
+            SubCommand::with_name("resend")
+                .arg(
+                    Arg::with_name("activity")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The ID of the activity"),
+                )
+                .about("Send an activity again to the inboxes where its delivery failed"),
+        )
+        .subcommand(
+            SubCommand::with_name("deliveries")
+                .arg(
+                    Arg::with_name("host")
+                        .long("host")
+                        .takes_value(true)
+                        .help("Only list the deliveries to this domain"),
+                )
+                .about("List the deliveries that failed this week"),
+        )
+        .subcommand(
+            SubCommand::with_name("probe")
+                .arg(
+                    Arg::with_name("domain")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The domain of the instance"),
+                )
+                .arg(
+                    Arg::with_name("actor")
+                        .short("a")
+                        .long("actor")
+                        .takes_value(true)
+                        .help("An actor of the instance, to test signatures with"),
+                )
+                .about("Check that an instance can be reached and accepts signatures"),
+        )
+}
+
+pub fn run<'a>(args: &ArgMatches<'a>, conn: &Connection) {
+    match args.subcommand() {
+        ("fetch", Some(x)) => fetch(x, conn),
+        ("resend", Some(x)) => resend(x, conn),
+        ("deliveries", Some(x)) => deliveries(x, conn),
+        ("probe", Some(x)) => probe(x, conn),
+        ("", None) => command().print_help().unwrap(),
+        _ => println!("Unknown subcommand"),
+    }
+}
+
+fn fetch<'a>(args: &ArgMatches<'a>, conn: &Connection) {
+    let url = args.value_of("url").expect("No URL provided");
+    if !args.is_present("save") {
+        let json = lookup::fetch(conn, url).expect("Couldn't fetch the object");
+        println!("{:#}", json);
+        return;
+    }
+    match lookup::resolve(conn, url).expect("Couldn't save the object") {
+        lookup::Resolved::User(user) => println!("Saved the account {}", user.fqn),
+        lookup::Resolved::Blog(blog) => println!("Saved the blog {}", blog.fqn),
+        lookup::Resolved::Post(post) => println!("Saved the article {}", post.title),
+        lookup::Resolved::Comment(comment) => {
+            println!("Saved the comment {}", comment.ap_url.unwrap_or_default())
+        }
+    }
+}
+
+fn resend<'a>(args: &ArgMatches<'a>, conn: &Connection) {
+    let activity = args.value_of("activity").expect("No activity provided");
+    let deliveries =
+        FailedDelivery::for_activity(conn, activity).expect("Couldn't list the deliveries");
+    if deliveries.is_empty() {
+        println!("No delivery of this activity failed this week");
+    }
+    for delivery in deliveries {
+        match delivery.resend(conn) {
+            Ok(None) => println!("{}: delivered", delivery.inbox),
+            Ok(Some(error)) => println!("{}: failed again ({})", delivery.inbox, error),
+            Err(e) => println!("{}: couldn't be sent ({:?})", delivery.inbox, e),
+        }
+    }
+}
+
+fn deliveries<'a>(args: &ArgMatches<'a>, conn: &Connection) {
+    let host = args.value_of("host");
+    let deliveries = FailedDelivery::list(conn, host).expect("Couldn't list the deliveries");
+    if deliveries.is_empty() {
+        println!("No delivery failed this week");
+        return;
+    }
+    if host.is_none() {
+        let mut by_host = BTreeMap::new();
+        for delivery in &deliveries {
+            *by_host.entry(delivery.host.as_str()).or_insert(0) += 1;
+        }
+        for (host, count) in by_host {
+            println!("{}: {} failed deliveries", host, count);
+        }
+        println!();
+    }
+    for delivery in &deliveries {
+        println!(
+            "{} {} (to {}, {} attempts): {}",
+            delivery.last_attempt.format("%Y-%m-%d %H:%M"),
+            delivery.activity_id,
+            delivery.inbox,
+            delivery.attempts,
+            delivery.error
+        );
+    }
+}
+
+fn probe<'a>(args: &ArgMatches<'a>, conn: &Connection) {
+    let domain = args.value_of("domain").expect("No domain provided");
+    for check in probe::probe(conn, domain, args.value_of("actor")) {
+        match check.result {
+            Ok(found) => println!("[ok]     {}: {}", check.name, found),
+            Err(error) => println!("[failed] {}: {}", check.name, error),
+        }
+    }
+}
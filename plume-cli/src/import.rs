@@ -0,0 +1,132 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use plume_models::{
+    blogs::Blog,
+    import::{self, ghost, medium, ImportReport},
+    instance::Instance,
+    users::User,
+    Connection,
+};
+use std::fs::{self, File};
+
+pub fn command<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("import")
+        .about("Import articles from other platforms")
+        .subcommand(
+            SubCommand::with_name("medium")
+                .arg(
+                    Arg::with_name("archive")
+                        .short("a")
+                        .long("archive")
+                        .takes_value(true)
+                        .help("Path to the ZIP archive exported from Medium"),
+                )
+                .arg(
+                    Arg::with_name("blog")
+                        .short("b")
+                        .long("blog")
+                        .takes_value(true)
+                        .help("The blog the articles should be imported in"),
+                )
+                .arg(
+                    Arg::with_name("user")
+                        .short("u")
+                        .long("user")
+                        .takes_value(true)
+                        .help("Username of the author of the articles"),
+                )
+                .about("Import the articles of a Medium export"),
+        )
+        .subcommand(
+            SubCommand::with_name("ghost")
+                .arg(
+                    Arg::with_name("file")
+                        .short("f")
+                        .long("file")
+                        .takes_value(true)
+                        .help("Path to the JSON file exported from Ghost"),
+                )
+                .arg(
+                    Arg::with_name("blog")
+                        .short("b")
+                        .long("blog")
+                        .takes_value(true)
+                        .help("The blog the posts should be imported in"),
+                )
+                .arg(
+                    Arg::with_name("user")
+                        .short("u")
+                        .long("user")
+                        .takes_value(true)
+                        .help("Username of the author of the posts whose authors are not on this blog"),
+                )
+                .about("Import the posts of a Ghost export"),
+        )
+}
+
+pub fn run<'a>(args: &ArgMatches<'a>, conn: &Connection) {
+    let conn = conn;
+    match args.subcommand() {
+        ("medium", Some(x)) => import_medium(x, conn),
+        ("ghost", Some(x)) => import_ghost(x, conn),
+        ("", None) => command().print_help().unwrap(),
+        _ => println!("Unknown subcommand"),
+    }
+}
+
+fn get_destination(args: &ArgMatches<'_>, conn: &Connection) -> (Blog, User) {
+    let blog = args.value_of("blog").expect("No blog provided");
+    let username = args.value_of("user").expect("No user provided");
+
+    let instance = Instance::get_local_uncached(conn).expect("Failed to load local instance");
+    let user = User::find_by_name(conn, username, instance.id).expect("User not found");
+    let blog = Blog::find_by_fqn(conn, blog).expect("Blog not found");
+    if !user.is_author_in(conn, &blog).unwrap_or(false) {
+        panic!("{} is not an author of {}", username, blog.fqn);
+    }
+    (blog, user)
+}
+
+fn print_report(report: ImportReport) {
+    for post in &report.posts {
+        println!("Imported \"{}\"", post.title);
+    }
+    for (file, error) in &report.errors {
+        eprintln!("Couldn't import {}: {}", file, error);
+    }
+    println!(
+        "{} article(s) imported, {} error(s).",
+        report.posts.len(),
+        report.errors.len()
+    );
+    println!("Run `plm search refill` to make them searchable.");
+}
+
+fn import_medium(args: &ArgMatches<'_>, conn: &Connection) {
+    let path = args.value_of("archive").expect("No archive provided");
+    let (blog, user) = get_destination(args, conn);
+    let archive = File::open(path).expect("Couldn't open the archive");
+
+    let report =
+        medium::import_archive(conn, archive, &blog, &user).expect("Couldn't read the archive");
+    localize_images(conn, &report, &user);
+    print_report(report);
+}
+
+fn import_ghost(args: &ArgMatches<'_>, conn: &Connection) {
+    let path = args.value_of("file").expect("No file provided");
+    let (blog, user) = get_destination(args, conn);
+    let json = fs::read_to_string(path).expect("Couldn't read the file");
+
+    let report = ghost::import_export(conn, &json, &blog, &user).expect("Invalid Ghost export");
+    localize_images(conn, &report, &user);
+    print_report(report);
+}
+
+fn localize_images(conn: &Connection, report: &ImportReport, author: &User) {
+    for post in &report.posts {
+        if let Err(err) = import::localize_post_images(conn, post.id, author) {
+            eprintln!("Couldn't download the images of {}: {:?}", post.title, err);
+        }
+    }
+}
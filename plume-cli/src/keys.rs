@@ -0,0 +1,32 @@
+use clap::{App, ArgMatches, SubCommand};
+
+use plume_models::{keys::KEY_STORE, Connection};
+
+pub fn command<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("keys")
+        .about("Manage the private keys of the local users and blogs")
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about("Move the keys kept in other stores to the one set with KEY_STORE"),
+        )
+}
+
+pub fn run<'a>(args: &ArgMatches<'a>, conn: &Connection) {
+    let conn = conn;
+    match args.subcommand() {
+        ("migrate", Some(_)) => migrate(conn),
+        ("", None) => command().print_help().unwrap(),
+        _ => println!("Unknown subcommand"),
+    }
+}
+
+fn migrate(conn: &Connection) {
+    let moved = KEY_STORE
+        .migrate(conn)
+        .expect("Couldn't move the keys, the ones that were moved are still usable");
+    // The previous copies can go, now that the new ones are saved
+    KEY_STORE
+        .forget_retired(conn)
+        .expect("Couldn't delete the previous copies of the keys");
+    println!("{} keys moved", moved);
+}
@@ -0,0 +1,56 @@
+use lettre_email::Email;
+use plume_models::{
+    lettre::Transport,
+    smtp::{
+        authentication::{Credentials, Mechanism},
+        extension::ClientId,
+        ConnectionReuseParameters, SmtpClient, SmtpTransport,
+    },
+    SmtpNewWithAddr, CONFIG,
+};
+use std::env;
+
+/// Sends emails with the server configured for the instance.
+pub struct Mailer(SmtpTransport);
+
+impl Mailer {
+    /// `None` if no email server is configured.
+    pub fn init() -> Option<Self> {
+        let config = CONFIG.mail.as_ref()?;
+        let mail = SmtpClient::new_with_addr((&config.server, config.port))
+            .ok()?
+            .hello_name(ClientId::Domain(config.helo_name.clone()))
+            .credentials(Credentials::new(
+                config.username.clone(),
+                config.password.clone(),
+            ))
+            .smtp_utf8(true)
+            .authentication_mechanism(Mechanism::Plain)
+            .connection_reuse(ConnectionReuseParameters::ReuseUnlimited)
+            .transport();
+        Some(Mailer(mail))
+    }
+
+    pub fn send(&mut self, dest: &str, subject: &str, body: String) -> Result<(), String> {
+        let from = env::var("MAIL_ADDRESS")
+            .or_else(|_| {
+                Ok(format!(
+                    "{}@{}",
+                    env::var("MAIL_USER")?,
+                    env::var("MAIL_SERVER")?
+                )) as Result<_, env::VarError>
+            })
+            .map_err(|_| "the email server is not configured correctly".to_owned())?;
+        let email = Email::builder()
+            .from(from)
+            .to(dest)
+            .subject(subject)
+            .text(body)
+            .build()
+            .map_err(|e| e.to_string())?;
+        self.0
+            .send(email.into())
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
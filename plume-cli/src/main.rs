@@ -1,12 +1,25 @@
 use clap::App;
 use diesel::Connection;
-use plume_models::{instance::Instance, Connection as Conn, CONFIG};
+use plume_models::{db_conn::ConnectionSettings, instance::Instance, Connection as Conn, CONFIG};
 use std::io::{self, prelude::*};
 
+mod backup;
+mod blogs;
+mod categories;
+mod config;
+mod db;
+mod federation;
+mod import;
 mod instance;
+mod keys;
 mod list;
+mod mail;
+mod maintenance;
+mod medias;
 mod migration;
+mod relays;
 mod search;
+mod tags;
 mod timeline;
 mod users;
 
@@ -15,9 +28,21 @@ fn main() {
         .bin_name("plm")
         .version(env!("CARGO_PKG_VERSION"))
         .about("Collection of tools to manage your Plume instance.")
+        .subcommand(backup::command())
+        .subcommand(blogs::command())
+        .subcommand(categories::command())
+        .subcommand(config::command())
+        .subcommand(db::command())
+        .subcommand(federation::command())
+        .subcommand(import::command())
         .subcommand(instance::command())
+        .subcommand(keys::command())
+        .subcommand(maintenance::command())
+        .subcommand(medias::command())
         .subcommand(migration::command())
+        .subcommand(relays::command())
         .subcommand(search::command())
+        .subcommand(tags::command())
         .subcommand(timeline::command())
         .subcommand(list::command())
         .subcommand(users::command());
@@ -28,19 +53,54 @@ fn main() {
         Err(ref e) if e.not_found() => eprintln!("no .env was found"),
         e => e.map(|_| ()).unwrap(),
     }
+    plume_common::utils::set_math_rendering(CONFIG.math_rendering);
+    plume_common::utils::set_embed_allowlist(CONFIG.embed_allowlist.clone());
+    let federation = &plume_models::config::reloadable().federation;
+    plume_common::activity_pub::set_timeouts(federation.connect_timeout, federation.timeout);
     let conn = Conn::establish(CONFIG.database_url.as_str());
+    if let Ok(ref conn) = conn {
+        ConnectionSettings::from_config()
+            .apply(conn)
+            .expect("Couldn't configure the database connection.");
+    }
     let _ = conn.as_ref().map(Instance::cache_local);
 
     match matches.subcommand() {
+        ("backup", Some(args)) => backup::run(args, conn.ok()),
+        ("blogs", Some(args)) => {
+            blogs::run(args, &conn.expect("Couldn't connect to the database."))
+        }
+        ("categories", Some(args)) => {
+            categories::run(args, &conn.expect("Couldn't connect to the database."))
+        }
+        ("config", Some(args)) => config::run(args),
+        ("db", Some(args)) => db::run(args),
+        ("federation", Some(args)) => {
+            federation::run(args, &conn.expect("Couldn't connect to the database."))
+        }
+        ("import", Some(args)) => {
+            import::run(args, &conn.expect("Couldn't connect to the database."))
+        }
         ("instance", Some(args)) => {
             instance::run(args, &conn.expect("Couldn't connect to the database."))
         }
+        ("keys", Some(args)) => keys::run(args, &conn.expect("Couldn't connect to the database.")),
+        ("maintenance", Some(args)) => {
+            maintenance::run(args, &conn.expect("Couldn't connect to the database."))
+        }
+        ("media", Some(args)) => {
+            medias::run(args, &conn.expect("Couldn't connect to the database."))
+        }
         ("migration", Some(args)) => {
             migration::run(args, &conn.expect("Couldn't connect to the database."))
         }
+        ("relays", Some(args)) => {
+            relays::run(args, &conn.expect("Couldn't connect to the database."))
+        }
         ("search", Some(args)) => {
             search::run(args, &conn.expect("Couldn't connect to the database."))
         }
+        ("tags", Some(args)) => tags::run(args, &conn.expect("Couldn't connect to the database.")),
         ("timeline", Some(args)) => {
             timeline::run(args, &conn.expect("Couldn't connect to the database."))
         }
@@ -0,0 +1,59 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use plume_models::{maintenance::Maintenance, Connection};
+
+pub fn command<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("maintenance")
+        .about("Make the instance read-only, for backups and migrations")
+        .subcommand(
+            SubCommand::with_name("start")
+                .arg(
+                    Arg::with_name("reason")
+                        .short("r")
+                        .long("reason")
+                        .takes_value(true)
+                        .help("Why the instance is in maintenance, shown to visitors"),
+                )
+                .about("Start a maintenance"),
+        )
+        .subcommand(SubCommand::with_name("stop").about("Stop the current maintenance"))
+        .subcommand(
+            SubCommand::with_name("status").about("Tell whether the instance is in maintenance"),
+        )
+}
+
+pub fn run<'a>(args: &ArgMatches<'a>, conn: &Connection) {
+    match args.subcommand() {
+        ("start", Some(x)) => start(x, conn),
+        ("stop", Some(_)) => stop(conn),
+        ("status", Some(_)) => status(conn),
+        ("", None) => command().print_help().unwrap(),
+        _ => println!("Unknown subcommand"),
+    }
+}
+
+fn start<'a>(args: &ArgMatches<'a>, conn: &Connection) {
+    let reason = args.value_of("reason").unwrap_or_default();
+    Maintenance::start(conn, reason).expect("Couldn't start the maintenance");
+    println!("The instance is in maintenance, servers will notice it in a few seconds");
+}
+
+fn stop(conn: &Connection) {
+    Maintenance::stop(conn).expect("Couldn't stop the maintenance");
+    println!("The maintenance is over");
+}
+
+fn status(conn: &Connection) {
+    match Maintenance::current(conn).expect("Couldn't check for maintenance") {
+        Some(maintenance) => println!(
+            "In maintenance since {}{}",
+            maintenance.start_date,
+            if maintenance.reason.is_empty() {
+                String::new()
+            } else {
+                format!(": {}", maintenance.reason)
+            }
+        ),
+        None => println!("Not in maintenance"),
+    }
+}
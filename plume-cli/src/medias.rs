@@ -0,0 +1,123 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use chrono::Duration;
+use plume_models::{
+    media_dedup::{self, MediaDeduplication},
+    media_gc, media_scan, media_variants,
+    medias::Media,
+    Connection, CONFIG,
+};
+
+pub fn command<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("media")
+        .about("Manage uploaded media")
+        .subcommand(
+            SubCommand::with_name("dedupe")
+                .arg(
+                    Arg::with_name("instance")
+                        .long("instance")
+                        .help("Also merge files uploaded by different users"),
+                )
+                .about("Merge the media that have the same content, and remove the files that are not used anymore"),
+        )
+        .subcommand(
+            SubCommand::with_name("gc")
+                .arg(
+                    Arg::with_name("grace-days")
+                        .long("grace-days")
+                        .takes_value(true)
+                        .help("Keep the media uploaded in the last days (MEDIA_GC_GRACE_DAYS by default)"),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Only tell what would be deleted"),
+                )
+                .about("Delete the media used by no article, comment, avatar, icon or banner"),
+        )
+        .subcommand(
+            SubCommand::with_name("quarantine")
+                .about("List the media in which the antivirus found something"),
+        )
+        .subcommand(
+            SubCommand::with_name("release")
+                .arg(
+                    Arg::with_name("id")
+                        .long("id")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The id of the media"),
+                )
+                .about("Publish a quarantined media, if the antivirus was wrong"),
+        )
+}
+
+pub fn run<'a>(args: &ArgMatches<'a>, conn: &Connection) {
+    let conn = conn;
+    match args.subcommand() {
+        ("dedupe", Some(x)) => dedupe(x, conn),
+        ("gc", Some(x)) => gc(x, conn),
+        ("quarantine", Some(_)) => quarantine(conn),
+        ("release", Some(x)) => release(x, conn),
+        ("", None) => command().print_help().unwrap(),
+        _ => println!("Unknown subcommand"),
+    }
+}
+
+fn dedupe(args: &ArgMatches<'_>, conn: &Connection) {
+    let scope = if args.is_present("instance")
+        || CONFIG.media_deduplication == MediaDeduplication::Instance
+    {
+        MediaDeduplication::Instance
+    } else {
+        MediaDeduplication::Owner
+    };
+    let report = media_dedup::dedupe(conn, scope).expect("Couldn't merge the duplicated media");
+    println!(
+        "{} media merged, {} KiB reclaimed",
+        report.merged,
+        report.reclaimed / 1024
+    );
+}
+
+fn gc(args: &ArgMatches<'_>, conn: &Connection) {
+    let grace_days = args
+        .value_of("grace-days")
+        .map_or(CONFIG.media_gc.grace_days, |days| {
+            days.parse()
+                .expect("The grace period should be a number of days")
+        });
+    let dry_run = args.is_present("dry-run");
+    let report = media_gc::collect(conn, Duration::days(grace_days), dry_run)
+        .expect("Couldn't delete the unused media");
+    println!(
+        "{} media {}, {} KiB reclaimed",
+        report.removed,
+        if dry_run { "to delete" } else { "deleted" },
+        report.reclaimed / 1024
+    );
+}
+
+fn quarantine(conn: &Connection) {
+    for media in media_scan::list_quarantined(conn).expect("Couldn't list the quarantined media") {
+        println!(
+            "{}\t{}\t{}",
+            media.id,
+            media.file_path,
+            media.scan_result.unwrap_or_default()
+        );
+    }
+}
+
+fn release(args: &ArgMatches<'_>, conn: &Connection) {
+    let id = args
+        .value_of("id")
+        .and_then(|id| id.parse().ok())
+        .expect("No valid media id provided");
+    let media = Media::get(conn, id).expect("Unknown media");
+    let media = media_scan::release(conn, &media).expect("Couldn't publish the media");
+    if let Err(err) = media_variants::generate(conn, &media) {
+        eprintln!("Couldn't generate the variants of the media: {:?}", err);
+    }
+    println!("Media {} is now published", id);
+}
@@ -1,7 +1,13 @@
 use clap::{App, Arg, ArgMatches, SubCommand};
 
-use plume_models::{migrations::IMPORTED_MIGRATIONS, Connection};
-use std::path::Path;
+use plume_models::{
+    migrations::{long_running_transactions, Phase, IMPORTED_MIGRATIONS},
+    Connection,
+};
+use std::{path::Path, time::Duration};
+
+/// Transactions open for longer than this are reported before running migrations.
+const LONG_TRANSACTION: Duration = Duration::from_secs(30);
 
 pub fn command<'a, 'b>() -> App<'a, 'b> {
     SubCommand::with_name("migration")
@@ -16,8 +22,25 @@ pub fn command<'a, 'b>() -> App<'a, 'b> {
                         .required(false)
                         .help("Path to Plume's working directory"),
                 )
+                .arg(
+                    Arg::with_name("phase")
+                        .long("phase")
+                        .takes_value(true)
+                        .possible_values(&["expand", "contract"])
+                        .default_value("contract")
+                        .help("Run only the expand migrations, or all of them"),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .short("f")
+                        .long("force")
+                        .help("Run migrations even if long transactions could keep them waiting"),
+                )
                 .about("Run migrations"),
         )
+        .subcommand(
+            SubCommand::with_name("status").about("List migrations, and whether they were run"),
+        )
         .subcommand(
             SubCommand::with_name("redo")
                 .arg(
@@ -37,6 +60,7 @@ pub fn run<'a>(args: &ArgMatches<'a>, conn: &Connection) {
     match args.subcommand() {
         ("run", Some(x)) => run_(x, conn),
         ("redo", Some(x)) => redo(x, conn),
+        ("status", Some(_)) => status(conn),
         ("", None) => command().print_help().unwrap(),
         _ => println!("Unknown subcommand"),
     }
@@ -44,12 +68,52 @@ pub fn run<'a>(args: &ArgMatches<'a>, conn: &Connection) {
 
 fn run_<'a>(args: &ArgMatches<'a>, conn: &Connection) {
     let path = args.value_of("path").unwrap_or(".");
+    let phase = args
+        .value_of("phase")
+        .and_then(|phase| phase.parse().ok())
+        .unwrap_or(Phase::Contract);
+
+    let long_transactions = long_running_transactions(conn, LONG_TRANSACTION)
+        .expect("Failed to list running transactions");
+    if !long_transactions.is_empty() {
+        eprintln!(
+            "These transactions are open for a long time, and would make migrations wait for them:"
+        );
+        for transaction in long_transactions {
+            eprintln!(
+                "  process {}, for {}s: {}",
+                transaction.pid, transaction.seconds as u64, transaction.query
+            );
+        }
+        if !args.is_present("force") {
+            eprintln!("Wait for them to end, or use --force to run migrations anyway.");
+            std::process::exit(1);
+        }
+    }
 
     IMPORTED_MIGRATIONS
-        .run_pending_migrations(conn, Path::new(path))
+        .run_phase(conn, Path::new(path), phase)
         .expect("Failed to run migrations")
 }
 
+fn status(conn: &Connection) {
+    let migrations = IMPORTED_MIGRATIONS
+        .status(conn)
+        .expect("Failed to list migrations");
+    for migration in migrations {
+        println!(
+            "{} {:<8} {}",
+            migration.name,
+            if migration.phase == Phase::Expand {
+                "expand"
+            } else {
+                "contract"
+            },
+            if migration.applied { "run" } else { "pending" }
+        );
+    }
+}
+
 fn redo<'a>(args: &ArgMatches<'a>, conn: &Connection) {
     let path = args.value_of("path").unwrap_or(".");
 
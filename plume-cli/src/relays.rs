@@ -0,0 +1,73 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use plume_models::{relays::Relay, Connection};
+
+pub fn command<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("relays")
+        .about("Manage the relays this instance is subscribed to")
+        .subcommand(
+            SubCommand::with_name("add")
+                .arg(
+                    Arg::with_name("url")
+                        .short("u")
+                        .long("url")
+                        .takes_value(true)
+                        .help("The URL of the actor of the relay"),
+                )
+                .about("Subscribe to a relay, to receive the public articles it forwards"),
+        )
+        .subcommand(
+            SubCommand::with_name("remove")
+                .arg(
+                    Arg::with_name("url")
+                        .short("u")
+                        .long("url")
+                        .takes_value(true)
+                        .help("The URL of the actor of the relay"),
+                )
+                .about("Unsubscribe from a relay"),
+        )
+        .subcommand(SubCommand::with_name("list").about("List the relays"))
+}
+
+pub fn run<'a>(args: &ArgMatches<'a>, conn: &Connection) {
+    let conn = conn;
+    match args.subcommand() {
+        ("add", Some(x)) => add(x, conn),
+        ("remove", Some(x)) => remove(x, conn),
+        ("list", Some(_)) => list(conn),
+        ("", None) => command().print_help().unwrap(),
+        _ => println!("Unknown subcommand"),
+    }
+}
+
+fn add(args: &ArgMatches<'_>, conn: &Connection) {
+    let url = args.value_of("url").expect("No URL provided");
+    let relay = Relay::subscribe(conn, url).expect("Couldn't subscribe to the relay");
+    println!(
+        "Subscription sent to {}, waiting for it to be accepted",
+        relay.actor_id
+    );
+}
+
+fn remove(args: &ArgMatches<'_>, conn: &Connection) {
+    let url = args.value_of("url").expect("No URL provided");
+    Relay::find_by_actor_id(conn, url)
+        .expect("Unknown relay")
+        .unsubscribe(conn)
+        .expect("Couldn't unsubscribe from the relay");
+}
+
+fn list(conn: &Connection) {
+    for relay in Relay::list(conn).expect("Couldn't list the relays") {
+        println!(
+            "{} ({})",
+            relay.actor_id,
+            if relay.accepted {
+                "accepted"
+            } else {
+                "pending"
+            }
+        );
+    }
+}
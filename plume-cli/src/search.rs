@@ -1,9 +1,10 @@
-use clap::{App, Arg, ArgMatches, SubCommand};
+use chrono::NaiveDate;
+use clap::{App, Arg, ArgGroup, ArgMatches, SubCommand};
 
 use plume_models::{search::Searcher, Connection, CONFIG};
 use std::fs::{read_dir, remove_file};
 use std::io::ErrorKind;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 pub fn command<'a, 'b>() -> App<'a, 'b> {
     SubCommand::with_name("search")
@@ -38,6 +39,48 @@ pub fn command<'a, 'b>() -> App<'a, 'b> {
                 )
                 .about("Regenerate Plume's search index"),
         )
+        .subcommand(
+            SubCommand::with_name("rebuild")
+                .arg(
+                    Arg::with_name("path")
+                        .short("p")
+                        .long("path")
+                        .takes_value(true)
+                        .required(false)
+                        .help("Path to Plume's working directory"),
+                )
+                .arg(
+                    Arg::with_name("all")
+                        .long("all")
+                        .help("Index everything again, and forget what was deleted"),
+                )
+                .arg(
+                    Arg::with_name("since")
+                        .long("since")
+                        .takes_value(true)
+                        .help(
+                        "Only index the articles and comments written since this date (YYYY-MM-DD)",
+                    ),
+                )
+                .group(
+                    ArgGroup::with_name("range")
+                        .args(&["all", "since"])
+                        .required(true),
+                )
+                .about("Index articles and comments again"),
+        )
+        .subcommand(
+            SubCommand::with_name("status")
+                .arg(
+                    Arg::with_name("path")
+                        .short("p")
+                        .long("path")
+                        .takes_value(true)
+                        .required(false)
+                        .help("Path to Plume's working directory"),
+                )
+                .about("Compare the search index with the database"),
+        )
         .subcommand(
             SubCommand::with_name("unlock")
                 .arg(
@@ -57,6 +100,8 @@ pub fn run<'a>(args: &ArgMatches<'a>, conn: &Connection) {
     match args.subcommand() {
         ("init", Some(x)) => init(x, conn),
         ("refill", Some(x)) => refill(x, conn, None),
+        ("rebuild", Some(x)) => rebuild(x, conn),
+        ("status", Some(x)) => status(x, conn),
         ("unlock", Some(x)) => unlock(x),
         ("", None) => command().print_help().unwrap(),
         _ => println!("Unknown subcommand"),
@@ -106,6 +151,47 @@ fn refill<'a>(args: &ArgMatches<'a>, conn: &Connection, searcher: Option<Searche
     searcher.commit();
 }
 
+fn rebuild<'a>(args: &ArgMatches<'a>, conn: &Connection) {
+    let since = args.value_of("since").map(|since| {
+        NaiveDate::parse_from_str(since, "%Y-%m-%d")
+            .expect("The date should be formatted as YYYY-MM-DD")
+            .and_hms(0, 0, 0)
+    });
+    let searcher = Searcher::open(&index_path(args), &CONFIG.search_tokenizers).unwrap();
+
+    searcher.rebuild(conn, since).expect("Couldn't index posts");
+    println!("Commiting result");
+    searcher.commit();
+    print_health(&searcher, conn);
+}
+
+fn status<'a>(args: &ArgMatches<'a>, conn: &Connection) {
+    let searcher = Searcher::open(&index_path(args), &CONFIG.search_tokenizers).unwrap();
+    print_health(&searcher, conn);
+}
+
+fn print_health(searcher: &Searcher, conn: &Connection) {
+    let health = searcher.health(conn).expect("Couldn't count posts");
+    println!(
+        "{} of {} published articles are indexed",
+        health.indexed_articles, health.published_articles
+    );
+    println!(
+        "{} of {} comments are indexed",
+        health.indexed_comments, health.comments
+    );
+    if health.is_drifting() {
+        println!("The index is out of date, run `plm search rebuild --all` to fix it");
+    }
+}
+
+fn index_path(args: &ArgMatches) -> PathBuf {
+    match args.value_of("path") {
+        Some(path) => Path::new(path).join("search_index"),
+        None => Path::new(&CONFIG.search_index).to_path_buf(),
+    }
+}
+
 fn unlock(args: &ArgMatches) {
     let path = match args.value_of("path") {
         None => Path::new(&CONFIG.search_index),
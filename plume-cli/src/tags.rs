@@ -0,0 +1,76 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use plume_models::{tag_aliases::TagAlias, Connection};
+
+pub fn command<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("tags")
+        .about("Manage tags")
+        .subcommand(
+            SubCommand::with_name("alias")
+                .arg(
+                    Arg::with_name("alias")
+                        .short("a")
+                        .long("alias")
+                        .takes_value(true)
+                        .help("The tag that should be merged into the other one"),
+                )
+                .arg(
+                    Arg::with_name("tag")
+                        .short("t")
+                        .long("tag")
+                        .takes_value(true)
+                        .help("The tag it stands for"),
+                )
+                .about("Declare a tag as an alias of another one, and rename it in all articles"),
+        )
+        .subcommand(
+            SubCommand::with_name("unalias")
+                .arg(
+                    Arg::with_name("alias")
+                        .short("a")
+                        .long("alias")
+                        .takes_value(true)
+                        .help("The alias to remove"),
+                )
+                .about("Remove an alias. Tags that were already renamed keep their new name"),
+        )
+        .subcommand(
+            SubCommand::with_name("rewrite")
+                .about("Rename the tags of all articles that still use an alias"),
+        )
+}
+
+pub fn run<'a>(args: &ArgMatches<'a>, conn: &Connection) {
+    let conn = conn;
+    match args.subcommand() {
+        ("alias", Some(x)) => alias(x, conn),
+        ("unalias", Some(x)) => unalias(x, conn),
+        ("rewrite", Some(_)) => rewrite(conn),
+        ("", None) => command().print_help().unwrap(),
+        _ => println!("Unknown subcommand"),
+    }
+}
+
+fn alias(args: &ArgMatches<'_>, conn: &Connection) {
+    let alias = args.value_of("alias").expect("No alias provided");
+    let tag = args.value_of("tag").expect("No tag provided");
+    let alias = TagAlias::create(conn, alias, tag).expect("Couldn't create the alias");
+    let rewritten = alias.rewrite_tags(conn).expect("Couldn't rename the tags");
+    println!(
+        "{} is now an alias of {} ({} tag(s) renamed)",
+        alias.alias, alias.tag, rewritten
+    );
+}
+
+fn unalias(args: &ArgMatches<'_>, conn: &Connection) {
+    let alias = args.value_of("alias").expect("No alias provided");
+    TagAlias::find_by_alias(conn, alias)
+        .expect("Unknown alias")
+        .delete(conn)
+        .expect("Couldn't delete the alias");
+}
+
+fn rewrite(conn: &Connection) {
+    let rewritten = TagAlias::rewrite_all_tags(conn).expect("Couldn't rename the tags");
+    println!("{} tag(s) renamed", rewritten);
+}
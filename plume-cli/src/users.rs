@@ -1,8 +1,18 @@
 use clap::{App, Arg, ArgMatches, SubCommand};
 
-use plume_models::{instance::Instance, users::*, Connection};
+use crate::mail::Mailer;
+use chrono::Duration;
+use plume_common::utils::random_hex;
+use plume_models::{
+    inactivity, instance::Instance, password_reset_requests::PasswordResetRequest, users::*,
+    Connection, CONFIG,
+};
+use serde_derive::Deserialize;
 use std::io::{self, Write};
 
+/// How long the links sent by `plm users import` and `plm users send-reset` work, by default.
+const RESET_VALIDITY_DAYS: &str = "7";
+
 pub fn command<'a, 'b>() -> App<'a, 'b> {
     SubCommand::with_name("users")
         .about("Manage users")
@@ -78,6 +88,77 @@ pub fn command<'a, 'b>() -> App<'a, 'b> {
                 )
                 .about("Reset user password"),
         )
+        .subcommand(
+            SubCommand::with_name("import")
+                .arg(
+                    Arg::with_name("file")
+                        .takes_value(true)
+                        .required(true)
+                        .help("A CSV file with the columns username, email and optionally display_name, role and password"),
+                )
+                .arg(
+                    Arg::with_name("send-reset")
+                        .short("s")
+                        .long("send-reset")
+                        .help("Email a link to choose a password to the users without one in the file"),
+                )
+                .arg(valid_days())
+                .about("Create the users listed in a CSV file"),
+        )
+        .subcommand(
+            SubCommand::with_name("send-reset")
+                .arg(usernames().required_unless("all"))
+                .arg(
+                    Arg::with_name("all")
+                        .long("all")
+                        .help("Send a link to all the local users"),
+                )
+                .arg(valid_days())
+                .about("Email users a link to choose a new password"),
+        )
+        .subcommand(
+            SubCommand::with_name("deactivate")
+                .arg(usernames().required(true))
+                .about("Log users out and prevent them from logging in"),
+        )
+        .subcommand(
+            SubCommand::with_name("reactivate")
+                .arg(usernames().required(true))
+                .about("Allow deactivated users to log in again"),
+        )
+        .subcommand(
+            SubCommand::with_name("inactive")
+                .about("Show what the daily job would do to the accounts that are never or not used anymore, without doing it"),
+        )
+        .subcommand(
+            SubCommand::with_name("set-role")
+                .arg(
+                    Arg::with_name("role")
+                        .short("r")
+                        .long("role")
+                        .takes_value(true)
+                        .required(true)
+                        .possible_values(&["admin", "moderator", "normal"])
+                        .help("The new role of the users"),
+                )
+                .arg(usernames().required(true))
+                .about("Change the role of users"),
+        )
+}
+
+fn usernames<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("usernames")
+        .takes_value(true)
+        .multiple(true)
+        .help("The usernames of local users")
+}
+
+fn valid_days<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("valid-days")
+        .long("valid-days")
+        .takes_value(true)
+        .default_value(RESET_VALIDITY_DAYS)
+        .help("How many days the emailed links work")
 }
 
 pub fn run<'a>(args: &ArgMatches<'a>, conn: &Connection) {
@@ -85,6 +166,12 @@ pub fn run<'a>(args: &ArgMatches<'a>, conn: &Connection) {
     match args.subcommand() {
         ("new", Some(x)) => new(x, conn),
         ("reset-password", Some(x)) => reset_password(x, conn),
+        ("import", Some(x)) => import(x, conn),
+        ("send-reset", Some(x)) => send_reset(x, conn),
+        ("deactivate", Some(x)) => set_deactivated(x, conn, true),
+        ("reactivate", Some(x)) => set_deactivated(x, conn, false),
+        ("inactive", Some(_)) => inactive(conn),
+        ("set-role", Some(x)) => set_role(x, conn),
         ("", None) => command().print_help().unwrap(),
         _ => println!("Unknown subcommand"),
     }
@@ -160,3 +247,209 @@ fn reset_password<'a>(args: &ArgMatches<'a>, conn: &Connection) {
     user.reset_password(conn, &password)
         .expect("Failed to reset password");
 }
+
+/// A line of the file given to `plm users import`.
+#[derive(Debug, Deserialize)]
+struct ImportedUser {
+    username: String,
+    email: String,
+    display_name: Option<String>,
+    role: Option<String>,
+    password: Option<String>,
+}
+
+fn import<'a>(args: &ArgMatches<'a>, conn: &Connection) {
+    let path = args.value_of("file").expect("No file provided");
+    let validity = validity(args);
+    let send = args.is_present("send-reset");
+    let mut mailer = if send { Mailer::init() } else { None };
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)
+        .expect("Couldn't read the file");
+    let (mut created, mut failed) = (0, 0);
+    for (line, record) in reader.deserialize::<ImportedUser>().enumerate() {
+        // the first line has the names of the columns
+        let line = line + 2;
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                println!("Line {}: invalid user ({})", line, e);
+                failed += 1;
+                continue;
+            }
+        };
+        let role = match record.role.as_deref().filter(|r| !r.is_empty()) {
+            Some(role) => match role.parse() {
+                Ok(role) => role,
+                Err(_) => {
+                    println!("Line {}: unknown role {}", line, role);
+                    failed += 1;
+                    continue;
+                }
+            },
+            None => Role::Normal,
+        };
+        let password = record.password.filter(|p| !p.is_empty());
+        let has_password = password.is_some();
+        let hash =
+            User::hash_pass(&password.unwrap_or_else(random_hex)).expect("Couldn't hash password");
+        let display_name = match record.display_name {
+            Some(name) if !name.is_empty() => name,
+            _ => record.username.clone(),
+        };
+        let user = NewUser::new_local(
+            conn,
+            record.username.clone(),
+            display_name,
+            role,
+            "",
+            record.email,
+            Some(hash),
+        );
+        match user {
+            Ok(user) => {
+                created += 1;
+                println!("Created {}", user.username);
+                if send && !has_password {
+                    send_link(conn, &mut mailer, &user, validity);
+                }
+            }
+            Err(e) => {
+                println!(
+                    "Line {}: couldn't create {} ({:?})",
+                    line, record.username, e
+                );
+                failed += 1;
+            }
+        }
+    }
+    println!("{} users created, {} failed", created, failed);
+}
+
+fn send_reset<'a>(args: &ArgMatches<'a>, conn: &Connection) {
+    let validity = validity(args);
+    let users = if args.is_present("all") {
+        let count = User::count_local(conn).expect("Couldn't count the users");
+        User::get_local_page(conn, (0, count as i32)).expect("Couldn't list the users")
+    } else {
+        find_users(args, conn)
+    };
+    let mut mailer = Mailer::init();
+    for user in users {
+        send_link(conn, &mut mailer, &user, validity);
+    }
+}
+
+/// Emails a password reset link to `user`, or prints it if emails can't be sent.
+fn send_link(conn: &Connection, mailer: &mut Option<Mailer>, user: &User, validity: Duration) {
+    let email = match user.email.as_deref() {
+        Some(email) => email,
+        None => {
+            println!("{} has no email address", user.username);
+            return;
+        }
+    };
+    let token = PasswordResetRequest::insert_valid_for(conn, email, validity)
+        .expect("Couldn't create the password reset link");
+    let url = format!("https://{}/password-reset/{}", CONFIG.base_url, token);
+    let sent = mailer.as_mut().map(|mailer| {
+        mailer.send(
+            email,
+            "Choose your password",
+            format!(
+                "The administrators of {} invite you to choose a password for your account, {}, here: {}\n\n\
+                This link works for {} days.\n",
+                CONFIG.base_url,
+                user.username,
+                url,
+                validity.num_days()
+            ),
+        )
+    });
+    match sent {
+        Some(Ok(())) => println!("Sent a link to {}", user.username),
+        Some(Err(e)) => println!("Couldn't email {}: {} ({})", user.username, e, url),
+        None => println!("{}: {}", user.username, url),
+    }
+}
+
+fn set_deactivated<'a>(args: &ArgMatches<'a>, conn: &Connection, deactivated: bool) {
+    for user in find_users(args, conn) {
+        user.set_deactivated(conn, deactivated)
+            .expect("Couldn't update the user");
+        if deactivated {
+            println!("Deactivated {}", user.username);
+        } else {
+            println!("Reactivated {}", user.username);
+        }
+    }
+}
+
+fn inactive(conn: &Connection) {
+    let reminders = inactivity::pending_reminders(conn, &CONFIG.inactivity)
+        .expect("Couldn't list the accounts to remind");
+    for reminder in &reminders {
+        println!(
+            "{}\tto remind\t{} on {}",
+            reminder.user.username,
+            if reminder.deletion {
+                "deletion"
+            } else {
+                "freezing"
+            },
+            reminder.deadline.format("%Y-%m-%d")
+        );
+    }
+    let report = inactivity::clean_up(conn, &CONFIG.inactivity, true)
+        .expect("Couldn't list the inactive accounts");
+    for user in &report.frozen {
+        println!("{}\tto freeze", user.username);
+    }
+    for user in &report.deleted {
+        println!("{}\tto delete", user.username);
+    }
+    println!(
+        "{} accounts to remind, {} to freeze, {} to delete",
+        reminders.len(),
+        report.frozen.len(),
+        report.deleted.len()
+    );
+    if !CONFIG.inactivity.enabled {
+        println!("INACTIVITY_CLEANUP is not enabled: none of this will happen");
+    }
+}
+
+fn set_role<'a>(args: &ArgMatches<'a>, conn: &Connection) {
+    let role = args.value_of("role").expect("No role provided");
+    for user in find_users(args, conn) {
+        user.set_role(conn, role.parse().expect("Invalid role"))
+            .expect("Couldn't update the user");
+        println!("{} is now {}", user.username, role);
+    }
+}
+
+/// The local users named in the arguments. Unknown names are reported and skipped.
+fn find_users<'a>(args: &ArgMatches<'a>, conn: &Connection) -> Vec<User> {
+    let local = Instance::get_local().expect("Failed to get local instance");
+    args.values_of("usernames")
+        .into_iter()
+        .flatten()
+        .filter_map(|name| match User::find_by_name(conn, name, local.id) {
+            Ok(user) => Some(user),
+            Err(_) => {
+                println!("{} is not a local user", name);
+                None
+            }
+        })
+        .collect()
+}
+
+fn validity<'a>(args: &ArgMatches<'a>) -> Duration {
+    let days = args
+        .value_of("valid-days")
+        .unwrap_or(RESET_VALIDITY_DAYS)
+        .parse()
+        .expect("The number of days should be a number");
+    Duration::days(days)
+}
@@ -11,6 +11,7 @@ use activitystreams::{
 use activitystreams_ext::{Ext1, Ext2, UnparsedExtension};
 use array_tool::vec::Uniq;
 use futures::future::join_all;
+use once_cell::sync::Lazy;
 use reqwest::{header::HeaderValue, ClientBuilder, RequestBuilder, Url};
 use rocket::{
     http::Status,
@@ -18,13 +19,18 @@ use rocket::{
     response::{Responder, Response},
     Outcome,
 };
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    RwLock,
+};
 use tokio::{
     runtime,
     time::{sleep, Duration},
 };
-use tracing::{debug, warn};
+use tracing::{debug, info_span, warn, Instrument};
 
 use self::sign::Signable;
+use crate::utils::short_id;
 
 pub mod inbox;
 pub mod request;
@@ -36,6 +42,52 @@ pub const PUBLIC_VISIBILITY: &str = "https://www.w3.org/ns/activitystreams#Publi
 pub const AP_CONTENT_TYPE: &str =
     r#"application/ld+json; profile="https://www.w3.org/ns/activitystreams""#;
 
+static CONNECT_TIMEOUT_MS: AtomicU64 = AtomicU64::new(5_000);
+static TIMEOUT_MS: AtomicU64 = AtomicU64::new(30_000);
+
+/// Sets how long other instances have to accept a connection, and to answer a request.
+pub fn set_timeouts(connect: Duration, request: Duration) {
+    CONNECT_TIMEOUT_MS.store(connect.as_millis() as u64, Ordering::Relaxed);
+    TIMEOUT_MS.store(request.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// How long other instances have to accept a connection, and to answer a request.
+pub(crate) fn timeouts() -> (Duration, Duration) {
+    (
+        Duration::from_millis(CONNECT_TIMEOUT_MS.load(Ordering::Relaxed)),
+        Duration::from_millis(TIMEOUT_MS.load(Ordering::Relaxed)),
+    )
+}
+
+/// A delivery that didn't succeed.
+#[derive(Clone, Debug)]
+pub struct FailedDelivery {
+    /// The signed activity
+    pub activity: serde_json::Value,
+    /// The key of the sender
+    pub key_id: String,
+    pub inbox: String,
+    pub error: String,
+}
+
+type FailureHook = Box<dyn Fn(FailedDelivery) + Send + Sync>;
+
+static ON_FAILURE: Lazy<RwLock<Option<FailureHook>>> = Lazy::new(|| RwLock::new(None));
+
+/// Sets what is done with the deliveries that fail, like saving them to retry them later.
+pub fn on_delivery_failure(hook: impl Fn(FailedDelivery) + Send + Sync + 'static) {
+    *ON_FAILURE.write().unwrap() = Some(Box::new(hook));
+}
+
+type DeliveryHook = Box<dyn Fn() + Send + Sync>;
+
+static BEFORE_DELIVERY: Lazy<RwLock<Option<DeliveryHook>>> = Lazy::new(|| RwLock::new(None));
+
+/// Sets what is done before sending an activity, like waiting for the end of a maintenance.
+pub fn before_delivery(hook: impl Fn() + Send + Sync + 'static) {
+    *BEFORE_DELIVERY.write().unwrap() = Some(Box::new(hook));
+}
+
 pub fn ap_accept_header() -> Vec<&'static str> {
     vec![
         "application/ld+json; profile=\"https://www.w3.org/ns/activitystreams\"",
@@ -64,7 +116,9 @@ pub fn context() -> serde_json::Value {
                 "@container":"@list",
                 "@id":"toot:focalPoint"
             },
-            "featured":"toot:featured"
+            "featured":"toot:featured",
+            "discoverable":"toot:discoverable",
+            "indexable":"toot:indexable"
         }
     ])
 }
@@ -131,6 +185,9 @@ where
     A: Activity + serde::Serialize,
     T: inbox::AsActor<C>,
 {
+    if let Some(hook) = BEFORE_DELIVERY.read().unwrap().as_ref() {
+        hook();
+    }
     let boxes = to
         .into_iter()
         .filter(|u| !u.is_local())
@@ -143,49 +200,70 @@ where
 
     let mut act = serde_json::to_value(act).expect("activity_pub::broadcast: serialization error");
     act["@context"] = context();
+    let delivery_id = short_id();
+    // The runtime only uses this thread, so all the deliveries are in this span
+    let _delivery = info_span!(
+        "delivery",
+        id = %delivery_id,
+        activity = %act["id"].as_str().unwrap_or_default()
+    )
+    .entered();
     let signed = act
         .sign(sender)
         .expect("activity_pub::broadcast: signature error");
 
+    let (connect_timeout, timeout) = timeouts();
     let client = if let Some(proxy) = proxy {
         ClientBuilder::new().proxy(proxy)
     } else {
         ClientBuilder::new()
     }
-    .connect_timeout(std::time::Duration::from_secs(5))
+    .connect_timeout(connect_timeout)
+    .timeout(timeout)
     .build()
     .expect("Can't build client");
     let rt = runtime::Builder::new_current_thread()
         .enable_all()
         .build()
         .expect("Error while initializing tokio runtime for federation");
-    rt.block_on(async {
+    let failures = rt.block_on(async {
         // TODO: should be determined dependent on database connections because
         // after broadcasting, target instance sends request to this instance,
         // and Plume accesses database at that time.
         let capacity = 6;
-        let (tx, rx) = flume::bounded::<RequestBuilder>(capacity);
+        let (tx, rx) = flume::bounded::<(String, RequestBuilder)>(capacity);
         let mut handles = Vec::with_capacity(capacity);
         for _ in 0..capacity {
             let rx = rx.clone();
             let handle = rt.spawn(async move {
-                while let Ok(request_builder) = rx.recv_async().await {
+                let mut failures = vec![];
+                while let Ok((inbox, request_builder)) = rx.recv_async().await {
                     // After broadcasting, target instance sends request to this instance.
                     // Sleep here in order to reduce requests at once
                     sleep(Duration::from_millis(500)).await;
-                    let _ = request_builder
+                    match request_builder
                         .send()
+                        .instrument(info_span!("deliver", %inbox, otel.kind = "client"))
                         .await
-                        .map(move |r| {
-                            if r.status().is_success() {
-                                debug!("Successfully sent activity to inbox ({})", &r.url());
-                            } else {
-                                warn!("Error while sending to inbox ({:?})", &r)
-                            }
-                            debug!("Response: \"{:?}\"\n", r);
-                        })
-                        .map_err(|e| warn!("Error while sending to inbox ({:?})", e));
+                    {
+                        Ok(r) if r.status().is_success() => {
+                            debug!(inbox = %r.url(), "Activity delivered")
+                        }
+                        Ok(r) => {
+                            warn!(
+                                inbox = %r.url(),
+                                status = r.status().as_u16(),
+                                "Inbox refused the activity"
+                            );
+                            failures.push((inbox, format!("HTTP status {}", r.status())));
+                        }
+                        Err(e) => {
+                            warn!(error = %e, "Error while sending to inbox");
+                            failures.push((inbox, e.to_string()));
+                        }
+                    }
                 }
+                failures
             });
             handles.push(handle);
         }
@@ -194,20 +272,24 @@ where
             let mut headers = request::headers();
             let url = Url::parse(&inbox);
             if url.is_err() {
-                warn!("Inbox is invalid URL: {:?}", &inbox);
+                warn!(%inbox, "Inbox is invalid URL");
                 continue;
             }
             let url = url.unwrap();
             if !url.has_host() {
-                warn!("Inbox doesn't have host: {:?}", &inbox);
+                warn!(%inbox, "Inbox doesn't have host");
                 continue;
             };
             let host_header_value = HeaderValue::from_str(url.host_str().expect("Unreachable"));
             if host_header_value.is_err() {
-                warn!("Header value is invalid: {:?}", url.host_str());
+                warn!(%inbox, "Header value is invalid");
                 continue;
             }
             headers.insert("Host", host_header_value.unwrap());
+            // Lets the receiving instance log it with the same ID
+            if let Ok(id) = HeaderValue::from_str(&delivery_id) {
+                headers.insert("X-Request-Id", id);
+            }
             headers.insert("Digest", request::Digest::digest(&body));
             headers.insert(
                 "Signature",
@@ -215,11 +297,22 @@ where
                     .expect("activity_pub::broadcast: request signature error"),
             );
             let request_builder = client.post(&inbox).headers(headers.clone()).body(body);
-            let _ = tx.send_async(request_builder).await;
+            let _ = tx.send_async((inbox, request_builder)).await;
         }
         drop(tx);
-        join_all(handles).await;
+        join_all(handles).await
     });
+
+    if let Some(hook) = ON_FAILURE.read().unwrap().as_ref() {
+        for (inbox, error) in failures.into_iter().flatten().flatten() {
+            hook(FailedDelivery {
+                activity: signed.clone(),
+                key_id: sender.get_key_id(),
+                inbox,
+                error,
+            });
+        }
+    }
 }
 
 #[derive(Shrinkwrap, Clone, Serialize, Deserialize)]
@@ -300,6 +393,164 @@ where
 pub type CustomPerson = Ext1<ApActor<Person>, ApSignature>;
 pub type CustomGroup = Ext2<ApActor<Group>, ApSignature, SourceProperty>;
 
+/// Sets the `discoverable` property of an actor, telling other instances whether it can be
+/// listed in directories.
+pub fn set_discoverable<U>(actor: &mut U, discoverable: bool) -> Result<(), serde_json::Error>
+where
+    U: UnparsedMutExt,
+{
+    actor.insert("discoverable", discoverable)?;
+    Ok(())
+}
+
+/// Whether an actor can be listed in directories. Actors that don't tell are not.
+pub fn discoverable<U>(actor: &mut U) -> bool
+where
+    U: UnparsedMutExt,
+{
+    actor
+        .remove::<Option<bool>>("discoverable")
+        .ok()
+        .flatten()
+        .unwrap_or(false)
+}
+
+/// Sets the `indexable` property of an actor, telling other instances whether its public
+/// posts can be included in their search.
+pub fn set_indexable<U>(actor: &mut U, indexable: bool) -> Result<(), serde_json::Error>
+where
+    U: UnparsedMutExt,
+{
+    actor.insert("indexable", indexable)?;
+    Ok(())
+}
+
+/// Whether the posts of an actor can be included in search. Actors that don't tell are
+/// indexable, as their public posts always were.
+pub fn indexable<U>(actor: &mut U) -> bool
+where
+    U: UnparsedMutExt,
+{
+    actor
+        .remove::<Option<bool>>("indexable")
+        .ok()
+        .flatten()
+        .unwrap_or(true)
+}
+
+/// Sets the `alsoKnownAs` property of an actor, listing its previous identities, that it
+/// accepts to be moved from.
+pub fn set_also_known_as<U>(actor: &mut U, aliases: &[String]) -> Result<(), serde_json::Error>
+where
+    U: UnparsedMutExt,
+{
+    actor.insert("alsoKnownAs", aliases)?;
+    Ok(())
+}
+
+/// The previous identities of an actor.
+pub fn also_known_as<U>(actor: &mut U) -> Vec<String>
+where
+    U: UnparsedMutExt,
+{
+    match actor.remove::<Option<serde_json::Value>>("alsoKnownAs") {
+        Ok(Some(serde_json::Value::String(alias))) => vec![alias],
+        Ok(Some(serde_json::Value::Array(aliases))) => aliases
+            .into_iter()
+            .filter_map(|alias| alias.as_str().map(str::to_owned))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Sets the `movedTo` property of an actor that moved to another one.
+pub fn set_moved_to<U>(actor: &mut U, target: &str) -> Result<(), serde_json::Error>
+where
+    U: UnparsedMutExt,
+{
+    actor.insert("movedTo", target)?;
+    Ok(())
+}
+
+/// Marks an object as the translation of another one, in `language`.
+///
+/// The original is given by `translationOf`, and the language by the only key of
+/// `contentMap`, which other software also understands.
+pub fn set_translation_of<U>(
+    object: &mut U,
+    original: &str,
+    language: &str,
+    content: &str,
+) -> Result<(), serde_json::Error>
+where
+    U: UnparsedMutExt,
+{
+    object.insert("translationOf", original)?;
+    object.insert("contentMap", serde_json::json!({ language: content }))?;
+    Ok(())
+}
+
+/// The ID of the original of a translation, and the language it was translated to.
+pub fn translation_of<U>(object: &mut U) -> Option<(String, String)>
+where
+    U: UnparsedMutExt,
+{
+    let original = object.remove::<Option<String>>("translationOf").ok()??;
+    let language = object
+        .remove::<Option<serde_json::Map<String, serde_json::Value>>>("contentMap")
+        .ok()??
+        .keys()
+        .next()?
+        .clone();
+    Some((original, language))
+}
+
+/// Sets the `canonicalUrl` property of an object that was first published elsewhere.
+pub fn set_canonical_url<U>(object: &mut U, url: &str) -> Result<(), serde_json::Error>
+where
+    U: UnparsedMutExt,
+{
+    object.insert("canonicalUrl", url)?;
+    Ok(())
+}
+
+/// Where an object was first published, if it tells.
+pub fn canonical_url<U>(object: &mut U) -> Option<String>
+where
+    U: UnparsedMutExt,
+{
+    object
+        .remove::<Option<String>>("canonicalUrl")
+        .ok()?
+        .filter(|url| url.starts_with("https://") || url.starts_with("http://"))
+}
+
+/// Sets the default license of the articles of an actor, with the same `license` property
+/// as articles (see `Licensed`).
+pub fn set_default_license<U>(
+    actor: &mut U,
+    license: Option<String>,
+) -> Result<(), serde_json::Error>
+where
+    U: UnparsedMutExt,
+{
+    if license.is_some() {
+        Licensed { license }.try_into_unparsed(actor)?;
+    }
+    Ok(())
+}
+
+/// The default license of the articles of an actor, if it has one.
+pub fn default_license<U>(actor: &mut U) -> Option<String>
+where
+    U: UnparsedMutExt,
+{
+    Licensed::try_from_unparsed(actor)
+        .ok()?
+        .license
+        .filter(|license| !license.is_empty())
+}
+
 kind!(HashtagType, Hashtag);
 
 #[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
@@ -592,6 +843,60 @@ mod tests {
         assert_eq!(to_value(person).unwrap(), expected);
     }
 
+    #[test]
+    fn discoverable_actor() {
+        let mut actor = ApActor::new("https://example.com/inbox".parse().unwrap(), Person::new());
+        assert!(!discoverable(&mut actor.clone()));
+        set_discoverable(&mut actor, true).unwrap();
+        assert_eq!(to_value(&actor).unwrap()["discoverable"], json!(true));
+        assert!(discoverable(&mut actor));
+
+        let mut actor: ApActor<Person> = from_str(
+            r#"{ "type": "Person", "inbox": "https://example.com/inbox", "discoverable": false }"#,
+        )
+        .unwrap();
+        assert!(!discoverable(&mut actor));
+    }
+
+    #[test]
+    fn indexable_actor() {
+        let mut actor = ApActor::new("https://example.com/inbox".parse().unwrap(), Person::new());
+        assert!(indexable(&mut actor.clone()));
+        set_indexable(&mut actor, false).unwrap();
+        assert_eq!(to_value(&actor).unwrap()["indexable"], json!(false));
+        assert!(!indexable(&mut actor));
+    }
+
+    #[test]
+    fn canonical_url_of_object() {
+        let mut article = ApObject::new(Article::new());
+        assert_eq!(canonical_url(&mut article.clone()), None);
+        set_canonical_url(&mut article, "https://example.org/first").unwrap();
+        assert_eq!(
+            to_value(&article).unwrap()["canonicalUrl"],
+            json!("https://example.org/first")
+        );
+        assert_eq!(
+            canonical_url(&mut article),
+            Some("https://example.org/first".to_owned())
+        );
+
+        let mut article: ApObject<Article> =
+            from_str(r#"{ "type": "Article", "canonicalUrl": "javascript:alert()" }"#).unwrap();
+        assert_eq!(canonical_url(&mut article), None);
+    }
+
+    #[test]
+    fn default_license_of_actor() {
+        let mut actor = ApActor::new("https://example.com/inbox".parse().unwrap(), Group::new());
+        set_default_license(&mut actor, None).unwrap();
+        assert!(to_value(&actor).unwrap().get("license").is_none());
+        assert_eq!(default_license(&mut actor.clone()), None);
+        set_default_license(&mut actor, Some("CC-BY-4.0".to_owned())).unwrap();
+        assert_eq!(to_value(&actor).unwrap()["license"], json!("CC-BY-4.0"));
+        assert_eq!(default_license(&mut actor), Some("CC-BY-4.0".to_owned()));
+    }
+
     #[test]
     fn se_custom_group() {
         let group = CustomGroup::new(
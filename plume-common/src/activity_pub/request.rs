@@ -12,7 +12,7 @@ use std::time::SystemTime;
 use tracing::warn;
 
 use crate::activity_pub::sign::Signer;
-use crate::activity_pub::{ap_accept_header, AP_CONTENT_TYPE};
+use crate::activity_pub::{ap_accept_header, timeouts, AP_CONTENT_TYPE};
 
 const PLUME_USER_AGENT: &str = concat!("Plume/", env!("CARGO_PKG_VERSION"));
 
@@ -196,12 +196,14 @@ pub fn get(url_str: &str, sender: &dyn Signer, proxy: Option<Proxy>) -> Result<R
     }
     let host_header_value = HeaderValue::from_str(url.host_str().expect("Unreachable"))?;
     headers.insert(HOST, host_header_value);
+    let (connect_timeout, timeout) = timeouts();
     if let Some(proxy) = proxy {
         ClientBuilder::new().proxy(proxy)
     } else {
         ClientBuilder::new()
     }
-    .connect_timeout(Some(std::time::Duration::from_secs(5)))
+    .connect_timeout(Some(connect_timeout))
+    .timeout(Some(timeout))
     .build()?
     .get(url_str)
     .headers(headers.clone())
@@ -213,6 +215,42 @@ pub fn get(url_str: &str, sender: &dyn Signer, proxy: Option<Proxy>) -> Result<R
     .map_err(|_| Error())
 }
 
+/// Sends an activity, already signed by `sender`, to a single inbox and waits for the answer.
+pub fn post(
+    inbox: &str,
+    activity: &serde_json::Value,
+    sender: &dyn Signer,
+    proxy: Option<Proxy>,
+) -> Result<Response, Error> {
+    let body = activity.to_string();
+    let mut headers = headers();
+    let url = Url::parse(inbox)?;
+    if !url.has_host() {
+        return Err(Error());
+    }
+    let host_header_value = HeaderValue::from_str(url.host_str().expect("Unreachable"))?;
+    headers.insert(HOST, host_header_value);
+    headers.insert("Digest", Digest::digest(&body));
+    headers.insert(
+        "Signature",
+        signature(sender, &headers, ("post", url.path(), url.query()))?,
+    );
+    let (connect_timeout, timeout) = timeouts();
+    if let Some(proxy) = proxy {
+        ClientBuilder::new().proxy(proxy)
+    } else {
+        ClientBuilder::new()
+    }
+    .connect_timeout(Some(connect_timeout))
+    .timeout(Some(timeout))
+    .build()?
+    .post(inbox)
+    .headers(headers)
+    .body(body)
+    .send()
+    .map_err(|_| Error())
+}
+
 #[cfg(test)]
 mod tests {
     use super::signature;
@@ -2,6 +2,7 @@ use super::request;
 use chrono::{naive::NaiveDateTime, DateTime, Duration, Utc};
 use openssl::{pkey::PKey, rsa::Rsa, sha::sha256};
 use rocket::http::HeaderMap;
+use tracing::instrument;
 
 /// Returns (public key, private key)
 pub fn gen_keypair() -> (Vec<u8>, Vec<u8>) {
@@ -76,6 +77,7 @@ impl Signable for serde_json::Value {
         Ok(self)
     }
 
+    #[instrument(name = "verify_ld_signature", skip_all)]
     fn verify<T: Signer>(mut self, creator: &T) -> bool {
         let signature_obj =
             if let Some(sig) = self.as_object_mut().and_then(|o| o.remove("signature")) {
@@ -134,7 +136,8 @@ impl SignatureValidity {
     }
 }
 
-pub fn verify_http_headers<S: Signer + ::std::fmt::Debug>(
+#[instrument(skip_all)]
+pub fn verify_http_headers<S: Signer>(
     sender: &S,
     all_headers: &HeaderMap<'_>,
     data: &request::Digest,
@@ -8,4 +8,5 @@ extern crate serde_derive;
 extern crate serde_json;
 
 pub mod activity_pub;
+pub mod shortcodes;
 pub mod utils;
@@ -0,0 +1,238 @@
+//! Shortcodes, to insert content that Markdown can't describe in articles.
+//!
+//! A shortcode is written alone in its paragraph, like `{{gallery id="3,4"}}`. Some of
+//! them wrap the following paragraphs until they are closed, like
+//! `{{cw text="Spoilers"}}` … `{{/cw}}`.
+//!
+//! Other instances don't know about shortcodes: the Markdown source sent to them
+//! replaces each of them with its fallback.
+
+use crate::utils::{escape, MediaProcessor, TocEntry};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// Arguments of a shortcode, like `id` in `{{gallery id=3}}`.
+pub type ShortcodeArgs = HashMap<String, String>;
+
+/// Everything a shortcode can use to render itself.
+pub struct ShortcodeContext<'a, 'b> {
+    pub media_processor: &'a Option<MediaProcessor<'b>>,
+}
+
+type Render = dyn Fn(&ShortcodeArgs, &ShortcodeContext<'_, '_>) -> Option<String> + Send + Sync;
+type Fallback = dyn Fn(&ShortcodeArgs) -> String + Send + Sync;
+
+pub struct Shortcode {
+    /// Renders the shortcode as HTML. When it returns `None`, the shortcode is left
+    /// as it was written.
+    pub render: Box<Render>,
+    /// Markdown replacing the shortcode in the source sent to other instances.
+    pub fallback: Box<Fallback>,
+    /// For shortcodes wrapping the following paragraphs, the HTML that `{{/name}}`
+    /// is rendered as.
+    pub closing: Option<String>,
+}
+
+/// Marks the place of the table of contents, that is only known once the whole
+/// article has been rendered.
+pub(crate) const TOC_PLACEHOLDER: &str = "\u{E002}toc\u{E002}";
+
+static SHORTCODES: Lazy<RwLock<HashMap<String, Arc<Shortcode>>>> = Lazy::new(|| {
+    let mut shortcodes = HashMap::new();
+    shortcodes.insert(
+        "gallery".to_owned(),
+        Arc::new(Shortcode {
+            render: Box::new(gallery),
+            fallback: Box::new(|args| {
+                media_ids(args)
+                    .map(|id| format!("![]({})\n\n", id))
+                    .collect()
+            }),
+            closing: None,
+        }),
+    );
+    shortcodes.insert(
+        "toc".to_owned(),
+        Arc::new(Shortcode {
+            render: Box::new(|_, _| Some(TOC_PLACEHOLDER.to_owned())),
+            fallback: Box::new(|_| String::new()),
+            closing: None,
+        }),
+    );
+    shortcodes.insert(
+        "cw".to_owned(),
+        Arc::new(Shortcode {
+            render: Box::new(|args, _| {
+                Some(format!(
+                    r#"<details class="cw"><summary>{}</summary>"#,
+                    escape(args.get("text").map_or("", String::as_str))
+                ))
+            }),
+            fallback: Box::new(|args| {
+                format!("**{}**", args.get("text").map_or("", String::as_str))
+            }),
+            closing: Some("</details>".to_owned()),
+        }),
+    );
+    RwLock::new(shortcodes)
+});
+
+/// Adds a shortcode, or replaces an existing one.
+pub fn register_shortcode(name: &str, shortcode: Shortcode) {
+    SHORTCODES
+        .write()
+        .unwrap()
+        .insert(name.to_owned(), Arc::new(shortcode));
+}
+
+pub(crate) fn get(name: &str) -> Option<Arc<Shortcode>> {
+    SHORTCODES.read().unwrap().get(name).cloned()
+}
+
+/// Parses a shortcode. Returns its name, its arguments, and if it closes a shortcode.
+///
+/// Quotes may have been made typographic by the Markdown parser, so they are accepted too.
+pub(crate) fn parse(text: &str) -> Option<(String, ShortcodeArgs, bool)> {
+    let inner = text.trim().strip_prefix("{{")?.strip_suffix("}}")?.trim();
+    let (closing, inner) = match inner.strip_prefix('/') {
+        Some(inner) => (true, inner.trim_start()),
+        None => (false, inner),
+    };
+    let name_len = inner
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+        .unwrap_or_else(|| inner.len());
+    let (name, mut rest) = inner.split_at(name_len);
+    if name.is_empty() || (closing && !rest.trim().is_empty()) {
+        return None;
+    }
+
+    let mut args = ShortcodeArgs::new();
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        let eq = rest.find('=')?;
+        let key = rest[..eq].trim();
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            return None;
+        }
+        rest = rest[eq + 1..].trim_start();
+        let value = match rest.chars().next() {
+            Some(quote @ '"') | Some(quote @ '“') => {
+                let end_quote = if quote == '"' { '"' } else { '”' };
+                let start = quote.len_utf8();
+                let end = rest[start..].find(end_quote)? + start;
+                let value = rest[start..end].to_owned();
+                rest = &rest[end + end_quote.len_utf8()..];
+                value
+            }
+            _ => {
+                let end = rest.find(char::is_whitespace).unwrap_or_else(|| rest.len());
+                let value = rest[..end].to_owned();
+                rest = &rest[end..];
+                value
+            }
+        };
+        args.insert(key.to_owned(), value);
+    }
+    Some((name.to_owned(), args, closing))
+}
+
+/// Replaces the shortcodes of a Markdown document with their fallbacks, for software
+/// that doesn't know them. Code blocks are left as they are.
+pub fn with_fallbacks(md: &str) -> String {
+    let mut fence: Option<&str> = None;
+    let mut res = Vec::new();
+    for line in md.split('\n') {
+        let trimmed = line.trim();
+        for marker in &["```", "~~~"] {
+            if trimmed.starts_with(marker) {
+                match fence {
+                    None => fence = Some(*marker),
+                    Some(f) if f == *marker && trimmed == *marker => fence = None,
+                    _ => {}
+                }
+            }
+        }
+        let replaced = if fence.is_none() {
+            parse(trimmed).and_then(|(name, args, closing)| {
+                get(&name).map(|shortcode| {
+                    if closing {
+                        String::new()
+                    } else {
+                        (shortcode.fallback)(&args).trim_end().to_owned()
+                    }
+                })
+            })
+        } else {
+            None
+        };
+        res.push(replaced.unwrap_or_else(|| line.to_owned()));
+    }
+    res.join("\n")
+}
+
+/// Renders the table of contents that `{{toc}}` is replaced with.
+pub(crate) fn toc_html(toc: &[TocEntry]) -> String {
+    let entries = toc
+        .iter()
+        .map(|entry| {
+            format!(
+                r##"<li class="toc-level-{}"><a href="#{}">{}</a></li>"##,
+                entry.level,
+                escape(&entry.anchor),
+                escape(&entry.title)
+            )
+        })
+        .collect::<String>();
+    format!(r#"<nav class="toc"><ul>{}</ul></nav>"#, entries)
+}
+
+fn media_ids(args: &ShortcodeArgs) -> impl Iterator<Item = i32> + '_ {
+    args.get("id")
+        .into_iter()
+        .flat_map(|ids| ids.split(','))
+        .filter_map(|id| id.trim().parse::<i32>().ok())
+}
+
+fn gallery(args: &ShortcodeArgs, ctx: &ShortcodeContext<'_, '_>) -> Option<String> {
+    let processor = ctx.media_processor.as_ref()?;
+    let figures = media_ids(args)
+        .filter_map(|id| processor(id))
+        .map(|(url, _)| format!(r#"<figure><img src="{}" alt=""></figure>"#, escape(&url)))
+        .collect::<String>();
+    if figures.is_empty() {
+        None
+    } else {
+        Some(format!(r#"<div class="gallery">{}</div>"#, figures))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_shortcodes() {
+        let (name, args, closing) = parse(r#"{{cw text="Big spoilers" level=2}}"#).unwrap();
+        assert_eq!(name, "cw");
+        assert_eq!(args["text"], "Big spoilers");
+        assert_eq!(args["level"], "2");
+        assert!(!closing);
+        assert!(parse("{{ /cw }}").unwrap().2);
+        assert_eq!(
+            parse("{{cw text=“Typographic”}}").unwrap().1["text"],
+            "Typographic"
+        );
+        assert!(parse("{{/cw text=x}}").is_none());
+        assert!(parse("{{cw text}}").is_none());
+        assert!(parse("not {{toc}}").is_none());
+
+        assert_eq!(
+            with_fallbacks("{{toc}}\n# Title\n```\n{{toc}}\n```\n{{gallery id=\"1, 2\"}}"),
+            "\n# Title\n```\n{{toc}}\n```\n![](1)\n\n![](2)"
+        );
+    }
+}
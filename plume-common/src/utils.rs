@@ -1,10 +1,16 @@
+use crate::shortcodes::{self, ShortcodeContext, TOC_PLACEHOLDER};
+use latex2mathml::{latex_to_mathml, DisplayStyle};
+use once_cell::sync::Lazy;
 use openssl::rand::rand_bytes;
 use pulldown_cmark::{html, CodeBlockKind, CowStr, Event, LinkType, Options, Parser, Tag};
 use regex_syntax::is_word_character;
 use rocket::http::uri::Uri;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
 use syntect::html::{ClassStyle, ClassedHTMLGenerator};
 use syntect::parsing::SyntaxSet;
+use url::Url;
 
 /// Generates an hexadecimal representation of 32 bytes of random data
 pub fn random_hex() -> String {
@@ -15,6 +21,15 @@ pub fn random_hex() -> String {
         .fold(String::new(), |res, byte| format!("{}{:x}", res, byte))
 }
 
+/// A short random identifier, to follow a request, a job or a delivery in the logs
+pub fn short_id() -> String {
+    let mut bytes = [0; 8];
+    rand_bytes(&mut bytes).expect("Error while generating an identifier");
+    bytes
+        .iter()
+        .fold(String::new(), |res, byte| format!("{}{:02x}", res, byte))
+}
+
 /**
  * Percent-encode characters which are not allowed in IRI path segments.
  *
@@ -191,6 +206,663 @@ fn inline_tags<'a>(
     }
 }
 
+/// Prefix of the `id` of the elements of rendered content.
+///
+/// The HTML cleaner adds it to all the ids, so that they don't clash with the ones
+/// of the page. Anchors are generated with it, for links to them to keep working.
+pub const ID_PREFIX: &str = "postcontent-";
+
+/// A heading of a document, as listed in its table of contents.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TocEntry {
+    pub level: u32,
+    pub title: String,
+    /// The `id` of the heading, to link to it with `#anchor`
+    pub anchor: String,
+}
+
+#[derive(Default)]
+struct HeadingContext {
+    heading: Option<(u32, Vec<Event<'static>>)>,
+    used_anchors: HashSet<String>,
+    toc: Vec<TocEntry>,
+    formulas: Vec<Formula>,
+}
+
+/// Turns the text of a heading into an anchor, unique in the document.
+fn heading_anchor(title: &str, used: &mut HashSet<String>) -> String {
+    let mut slug = String::new();
+    for c in title.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if (c.is_whitespace() || c == '-' || c == '_') && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = match slug.trim_matches('-') {
+        "" => "section",
+        s => s,
+    };
+    let mut anchor = format!("{}{}", ID_PREFIX, slug);
+    let mut n = 1;
+    while used.contains(&anchor) {
+        anchor = format!("{}{}-{}", ID_PREFIX, slug, n);
+        n += 1;
+    }
+    used.insert(anchor.clone());
+    anchor
+}
+
+/// Gives an `id` to headings, and lists them in the table of contents.
+#[allow(clippy::unnecessary_wraps)]
+fn heading_anchors<'a>(ctx: &mut HeadingContext, evt: Event<'a>) -> Option<Vec<Event<'a>>> {
+    match evt {
+        Event::Start(Tag::Heading(level)) => {
+            ctx.heading = Some((level, vec![]));
+            Some(vec![])
+        }
+        Event::End(Tag::Heading(level)) => {
+            let events = ctx.heading.take().map(|(_, e)| e).unwrap_or_default();
+            let title = events
+                .iter()
+                .filter_map(|e| match e {
+                    Event::Text(t) | Event::Code(t) => Some(t.as_ref()),
+                    _ => None,
+                })
+                .collect::<String>();
+            let title = restore_math(&title, &ctx.formulas);
+            let anchor = heading_anchor(&title, &mut ctx.used_anchors);
+            let mut result = vec![Event::Html(
+                format!(r#"<h{} dir="auto" id="{}">"#, level, escape(&anchor)).into(),
+            )];
+            result.extend(events);
+            result.push(Event::Html(format!("</h{}>\n", level).into()));
+            ctx.toc.push(TocEntry {
+                level,
+                title: title.trim().to_owned(),
+                anchor,
+            });
+            Some(result)
+        }
+        e => match ctx.heading {
+            Some((_, ref mut events)) => {
+                events.push(into_static(e));
+                Some(vec![])
+            }
+            None => Some(vec![e]),
+        },
+    }
+}
+
+fn into_static(evt: Event<'_>) -> Event<'static> {
+    let own = |s: CowStr<'_>| CowStr::Boxed(s.into_string().into());
+    match evt {
+        Event::Start(t) => Event::Start(tag_into_static(t)),
+        Event::End(t) => Event::End(tag_into_static(t)),
+        Event::Text(t) => Event::Text(own(t)),
+        Event::Code(t) => Event::Code(own(t)),
+        Event::Html(t) => Event::Html(own(t)),
+        Event::FootnoteReference(t) => Event::FootnoteReference(own(t)),
+        Event::SoftBreak => Event::SoftBreak,
+        Event::HardBreak => Event::HardBreak,
+        Event::Rule => Event::Rule,
+        Event::TaskListMarker(checked) => Event::TaskListMarker(checked),
+    }
+}
+
+fn tag_into_static(tag: Tag<'_>) -> Tag<'static> {
+    let own = |s: CowStr<'_>| CowStr::Boxed(s.into_string().into());
+    match tag {
+        Tag::Link(typ, url, title) => Tag::Link(typ, own(url), own(title)),
+        Tag::Image(typ, url, title) => Tag::Image(typ, own(url), own(title)),
+        Tag::Paragraph => Tag::Paragraph,
+        Tag::Heading(level) => Tag::Heading(level),
+        Tag::BlockQuote => Tag::BlockQuote,
+        Tag::CodeBlock(CodeBlockKind::Indented) => Tag::CodeBlock(CodeBlockKind::Indented),
+        Tag::CodeBlock(CodeBlockKind::Fenced(lang)) => {
+            Tag::CodeBlock(CodeBlockKind::Fenced(own(lang)))
+        }
+        Tag::List(start) => Tag::List(start),
+        Tag::Item => Tag::Item,
+        Tag::FootnoteDefinition(name) => Tag::FootnoteDefinition(own(name)),
+        Tag::Table(align) => Tag::Table(align),
+        Tag::TableHead => Tag::TableHead,
+        Tag::TableRow => Tag::TableRow,
+        Tag::TableCell => Tag::TableCell,
+        Tag::Emphasis => Tag::Emphasis,
+        Tag::Strong => Tag::Strong,
+        Tag::Strikethrough => Tag::Strikethrough,
+    }
+}
+
+#[derive(Default)]
+struct FootnoteContext {
+    numbers: HashMap<String, usize>,
+    references: HashMap<usize, usize>,
+}
+
+impl FootnoteContext {
+    /// Footnotes are numbered in the order they first appear in.
+    fn number(&mut self, name: &str) -> usize {
+        let next = self.numbers.len() + 1;
+        *self.numbers.entry(name.to_owned()).or_insert(next)
+    }
+}
+
+/// Renders footnotes with ids that don't clash with the ones of headings, and links
+/// from the notes back to where they are referenced.
+#[allow(clippy::unnecessary_wraps)]
+fn footnotes<'a>(ctx: &mut FootnoteContext, evt: Event<'a>) -> Option<Event<'a>> {
+    Some(match evt {
+        Event::FootnoteReference(name) => {
+            let n = ctx.number(&name);
+            let count = ctx.references.entry(n).or_insert(0);
+            *count += 1;
+            let id = if *count == 1 {
+                format!("{}fnref-{}", ID_PREFIX, n)
+            } else {
+                format!("{}fnref-{}-{}", ID_PREFIX, n, count)
+            };
+            Event::Html(
+                format!(
+                    r##"<sup class="footnote-reference" id="{id}"><a href="#{prefix}fn-{n}">{n}</a></sup>"##,
+                    id = id,
+                    prefix = ID_PREFIX,
+                    n = n
+                )
+                .into(),
+            )
+        }
+        Event::Start(Tag::FootnoteDefinition(name)) => {
+            let n = ctx.number(&name);
+            Event::Html(
+                format!(
+                    r#"<div class="footnote-definition" id="{}fn-{}"><sup class="footnote-definition-label">{}</sup>"#,
+                    ID_PREFIX, n, n
+                )
+                .into(),
+            )
+        }
+        Event::End(Tag::FootnoteDefinition(name)) => {
+            let n = ctx.number(&name);
+            Event::Html(
+                format!("<a href=\"#{}fnref-{}\">\u{21a9}</a></div>\n", ID_PREFIX, n).into(),
+            )
+        }
+        // Checkboxes would be removed by most fediverse software
+        Event::TaskListMarker(true) => Event::Text("\u{2611} ".into()),
+        Event::TaskListMarker(false) => Event::Text("\u{2610} ".into()),
+        e => e,
+    })
+}
+
+static MATH_RENDERING: AtomicBool = AtomicBool::new(true);
+
+/// Enables or disables the rendering of `$...$` and `$$...$$` as math formulas.
+///
+/// It is enabled by default. When it is disabled, dollar signs are left as they are.
+pub fn set_math_rendering(enabled: bool) {
+    MATH_RENDERING.store(enabled, Ordering::Relaxed);
+}
+
+/// Marks the place of a formula while the rest of the Markdown is parsed.
+const MATH_START: char = '\u{E000}';
+const MATH_END: char = '\u{E001}';
+
+#[derive(Clone, Debug)]
+struct Formula {
+    latex: String,
+    display: bool,
+}
+
+impl Formula {
+    /// Renders the formula as MathML, that can be displayed without any script,
+    /// in federated content and feed readers too.
+    fn to_html(&self) -> String {
+        let style = if self.display {
+            DisplayStyle::Block
+        } else {
+            DisplayStyle::Inline
+        };
+        latex_to_mathml(&self.latex, style)
+            .unwrap_or_else(|_| format!("<code>{}</code>", escape(&self.to_source())))
+    }
+
+    fn to_source(&self) -> String {
+        if self.display {
+            format!("$${}$$", self.latex)
+        } else {
+            format!("${}$", self.latex)
+        }
+    }
+}
+
+/// Replaces the formulas of a Markdown document with placeholders, so that they are not
+/// parsed as Markdown. Code blocks, code spans and escaped dollar signs are kept as is.
+///
+/// Inline formulas can't start or end with a space, and the closing `$` can't be
+/// followed by a digit, so that prices are not mistaken for formulas.
+fn extract_math(md: &str) -> (String, Vec<Formula>) {
+    let chars = md.chars().collect::<Vec<_>>();
+    let mut res = String::with_capacity(md.len());
+    let mut formulas = vec![];
+    let mut fence: Option<String> = None;
+    let mut indented_code = false;
+    let mut after_blank = true;
+    // What is known not to be closed, so that it is not looked for again: each search
+    // would otherwise go to the end of the document, or of the paragraph
+    let mut unclosed_code_spans = HashSet::new();
+    let mut unclosed_display = false;
+    let mut unclosed_inline_until = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        let line_start = i == 0 || chars[i - 1] == '\n';
+        if line_start {
+            let line = chars[i..]
+                .iter()
+                .take_while(|c| **c != '\n')
+                .collect::<String>();
+            let trimmed = line.trim_start();
+            let indented = line.starts_with("    ") || line.starts_with('\t');
+            let marker = if indented && fence.is_none() {
+                None
+            } else if trimmed.starts_with("```") {
+                Some("```")
+            } else if trimmed.starts_with("~~~") {
+                Some("~~~")
+            } else {
+                None
+            };
+            match (&fence, marker) {
+                (None, Some(m)) => fence = Some(m.to_owned()),
+                (Some(f), Some(m)) if f == m && trimmed.trim_end() == m => fence = None,
+                _ => {}
+            }
+            // Indented code blocks can't interrupt a paragraph, and go on until a line
+            // that is not indented
+            let blank = trimmed.is_empty();
+            if !blank {
+                indented_code = indented && (indented_code || after_blank);
+            }
+            after_blank = blank;
+            if fence.is_some() || marker.is_some() || indented_code {
+                res.push_str(&line);
+                i += line.chars().count();
+                continue;
+            }
+        }
+
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                res.push(chars[i]);
+                res.push(chars[i + 1]);
+                i += 2;
+            }
+            '`' => {
+                let run = chars[i..].iter().take_while(|c| **c == '`').count();
+                let close = if unclosed_code_spans.contains(&run) {
+                    None
+                } else {
+                    (i + run..chars.len()).find(|j| {
+                        chars[*j - 1] != '`'
+                            && chars[*j..].iter().take_while(|c| **c == '`').count() == run
+                    })
+                };
+                if close.is_none() {
+                    unclosed_code_spans.insert(run);
+                }
+                let end = close.map(|j| j + run).unwrap_or(i + run);
+                res.extend(&chars[i..end]);
+                i = end;
+            }
+            '$' => {
+                let display = chars.get(i + 1) == Some(&'$');
+                let start = if display { i + 2 } else { i + 1 };
+                let close = if display && !unclosed_display {
+                    let close = (start..chars.len().saturating_sub(1))
+                        .find(|j| chars[*j] == '$' && chars[j + 1] == '$');
+                    unclosed_display = close.is_none();
+                    close
+                } else if display {
+                    None
+                } else if i >= unclosed_inline_until
+                    && chars.get(start).map_or(false, |c| !c.is_whitespace())
+                {
+                    let mut j = start;
+                    let mut close = None;
+                    while j < chars.len() && !(chars[j] == '\n' && chars.get(j + 1) == Some(&'\n'))
+                    {
+                        if chars[j] == '$'
+                            && chars[j - 1] != '\\'
+                            && !chars[j - 1].is_whitespace()
+                            && !chars.get(j + 1).map_or(false, char::is_ascii_digit)
+                        {
+                            close = Some(j);
+                            break;
+                        }
+                        j += 1;
+                    }
+                    if close.is_none() {
+                        // No other formula of this paragraph can be closed either
+                        unclosed_inline_until = j;
+                    }
+                    close
+                } else {
+                    None
+                };
+                match close {
+                    Some(end) if end > start => {
+                        res.push(MATH_START);
+                        res.push_str(&formulas.len().to_string());
+                        res.push(MATH_END);
+                        formulas.push(Formula {
+                            latex: chars[start..end]
+                                .iter()
+                                .collect::<String>()
+                                .trim()
+                                .to_owned(),
+                            display,
+                        });
+                        i = if display { end + 2 } else { end + 1 };
+                    }
+                    _ => {
+                        res.extend(&chars[i..start]);
+                        i = start;
+                    }
+                }
+            }
+            c => {
+                res.push(c);
+                i += 1;
+            }
+        }
+    }
+    (res, formulas)
+}
+
+/// Calls `f` with each formula of `text`, and the text around them.
+fn split_math<'f>(
+    text: &str,
+    formulas: &'f [Formula],
+    mut f: impl FnMut(&str, Option<&'f Formula>),
+) {
+    let mut rest = text;
+    while let Some(start) = rest.find(MATH_START) {
+        let end = match rest[start..].find(MATH_END) {
+            Some(end) => start + end,
+            None => break,
+        };
+        let formula = rest[start + MATH_START.len_utf8()..end]
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| formulas.get(n));
+        f(&rest[..start], formula);
+        rest = &rest[end + MATH_END.len_utf8()..];
+    }
+    f(rest, None);
+}
+
+/// Puts the source of the formulas back in `text`.
+fn restore_math(text: &str, formulas: &[Formula]) -> String {
+    let mut res = String::new();
+    split_math(text, formulas, |t, formula| {
+        res.push_str(t);
+        if let Some(formula) = formula {
+            res.push_str(&formula.to_source());
+        }
+    });
+    res
+}
+
+/// Renders the formulas in place of their placeholders.
+#[allow(clippy::unnecessary_wraps)]
+fn render_math<'a>(formulas: &mut &[Formula], evt: Event<'a>) -> Option<Vec<Event<'a>>> {
+    match evt {
+        Event::Text(txt) if txt.contains(MATH_START) => {
+            let mut events = vec![];
+            split_math(&txt, *formulas, |t, formula| {
+                if !t.is_empty() {
+                    events.push(Event::Text(t.to_owned().into()));
+                }
+                if let Some(formula) = formula {
+                    events.push(Event::Html(formula.to_html().into()));
+                }
+            });
+            Some(events)
+        }
+        e => Some(vec![e]),
+    }
+}
+
+/// Hosts whose content can be embedded in articles, unless the instance configures
+/// its own list.
+pub const DEFAULT_EMBED_ALLOWLIST: &[&str] = &[
+    "youtube.com",
+    "youtu.be",
+    "youtube-nocookie.com",
+    "openstreetmap.org",
+];
+
+static EMBED_ALLOWLIST: Lazy<RwLock<Vec<String>>> = Lazy::new(|| {
+    RwLock::new(
+        DEFAULT_EMBED_ALLOWLIST
+            .iter()
+            .map(|h| (*h).to_owned())
+            .collect(),
+    )
+});
+
+/// Sets the hosts whose content can be embedded. Their subdomains can be embedded too.
+pub fn set_embed_allowlist(hosts: Vec<String>) {
+    *EMBED_ALLOWLIST.write().unwrap() = hosts;
+}
+
+/// Tells if `url` can be loaded in an iframe: it has to use HTTPS, and its host
+/// (or one of its parents) has to be in the allowlist.
+pub fn is_embeddable(url: &str) -> bool {
+    is_in_allowlist(url, &EMBED_ALLOWLIST.read().unwrap())
+}
+
+fn is_in_allowlist(url: &str, allowlist: &[String]) -> bool {
+    let url = match Url::parse(url) {
+        Ok(url) if url.scheme() == "https" => url,
+        _ => return false,
+    };
+    let host = match url.host_str() {
+        Some(host) => host.to_lowercase(),
+        None => return false,
+    };
+    allowlist.iter().any(|allowed| {
+        let allowed = allowed.trim().to_lowercase();
+        !allowed.is_empty()
+            && (host == allowed
+                || host
+                    .strip_suffix(&allowed)
+                    .map_or(false, |sub| sub.ends_with('.')))
+    })
+}
+
+/// Finds the URL of the embeddable version of a video or map page: YouTube and
+/// PeerTube videos, and OpenStreetMap maps.
+fn embed_url(url: &str) -> Option<String> {
+    embed_url_in(url, &EMBED_ALLOWLIST.read().unwrap())
+}
+
+/// Like `embed_url`, with the hosts of `allowlist` instead of the configured ones.
+fn embed_url_in(url: &str, allowlist: &[String]) -> Option<String> {
+    if !is_in_allowlist(url, allowlist) {
+        return None;
+    }
+    let url = Url::parse(url).ok()?;
+    let host = url.host_str()?.to_lowercase();
+    let is_id = |id: &str| {
+        !id.is_empty()
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    };
+    let path = url.path_segments()?.collect::<Vec<_>>();
+
+    let embed = if host == "youtu.be" || host == "youtube.com" || host.ends_with(".youtube.com") {
+        let id = match path.as_slice() {
+            ["watch"] => url
+                .query_pairs()
+                .find(|(k, _)| k == "v")
+                .map(|(_, v)| v.into_owned())?,
+            [id] if host == "youtu.be" => (*id).to_owned(),
+            ["embed", id] | ["shorts", id] => (*id).to_owned(),
+            _ => return None,
+        };
+        if !is_id(&id) {
+            return None;
+        }
+        format!("https://www.youtube-nocookie.com/embed/{}", id)
+    } else if host == "openstreetmap.org" || host.ends_with(".openstreetmap.org") {
+        // The position is in the fragment: #map=zoom/latitude/longitude
+        let position = url.fragment()?.strip_prefix("map=")?;
+        let position = position
+            .split('/')
+            .map(|n| n.parse::<f64>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        let (zoom, lat, lon) = match position.as_slice() {
+            [zoom, lat, lon] => (*zoom, *lat, *lon),
+            _ => return None,
+        };
+        let width = 360.0 / 2f64.powf(zoom.max(0.0).min(20.0)) * 2.0;
+        format!(
+            "https://www.openstreetmap.org/export/embed.html?bbox={},{},{},{}&layer=mapnik&marker={},{}",
+            lon - width / 2.0,
+            lat - width / 4.0,
+            lon + width / 2.0,
+            lat + width / 4.0,
+            lat,
+            lon
+        )
+    } else {
+        // Any other allowed host is expected to be a PeerTube instance
+        let id = match path.as_slice() {
+            ["w", id] | ["videos", "watch", id] | ["videos", "embed", id] => *id,
+            _ => return None,
+        };
+        if !is_id(id) {
+            return None;
+        }
+        format!("https://{}/videos/embed/{}", host, id)
+    };
+    if is_in_allowlist(&embed, allowlist) {
+        Some(embed)
+    } else {
+        None
+    }
+}
+
+/// Replaces the paragraphs that only contain the URL of an embeddable video or map
+/// with a link to it, that plume-front turns into the actual embed when the reader
+/// asks for it. Until then, the other site doesn't know the article is being read.
+fn embeds<'a>(buffer: &mut Option<Vec<Event<'a>>>, evt: Event<'a>) -> Option<Vec<Event<'a>>> {
+    match evt {
+        Event::Start(Tag::Paragraph) => {
+            let previous = buffer.replace(vec![evt]);
+            Some(previous.unwrap_or_default())
+        }
+        Event::End(Tag::Paragraph) if buffer.is_some() => {
+            let mut events = buffer.take().unwrap_or_default();
+            let url = match &events[1..] {
+                [Event::Text(url)] => Some(url.trim().to_owned()),
+                [Event::Start(Tag::Link(_, dest, _)), Event::Text(text), Event::End(Tag::Link(_, _, _))]
+                    if dest.as_ref() == text.as_ref() =>
+                {
+                    Some(dest.to_string())
+                }
+                _ => None,
+            };
+            match url.and_then(|url| embed_url(&url).map(|embed| (url, embed))) {
+                Some((url, embed)) => Some(vec![Event::Html(
+                    format!(
+                        "<div class=\"embed\" data-embed=\"{embed}\"><a href=\"{url}\">{url}</a></div>\n",
+                        embed = escape(&embed),
+                        url = escape(&url)
+                    )
+                    .into(),
+                )]),
+                None => {
+                    events.push(evt);
+                    Some(events)
+                }
+            }
+        }
+        _ => match buffer {
+            Some(events) => {
+                events.push(evt);
+                Some(vec![])
+            }
+            None => Some(vec![evt]),
+        },
+    }
+}
+
+/// Renders the shortcodes that are alone in their paragraph. Unknown shortcodes, and
+/// the ones that can't be rendered, are left as they are.
+fn render_shortcodes<'a>(
+    buffer: &mut Option<Vec<Event<'a>>>,
+    evt: Event<'a>,
+    ctx: &ShortcodeContext<'_, '_>,
+) -> Option<Vec<Event<'a>>> {
+    match evt {
+        Event::Start(Tag::Paragraph) => {
+            let previous = buffer.replace(vec![evt]);
+            Some(previous.unwrap_or_default())
+        }
+        Event::End(Tag::Paragraph) if buffer.is_some() => {
+            let mut events = buffer.take().unwrap_or_default();
+            let html = match &events[1..] {
+                [Event::Text(text)] => shortcodes::parse(text).and_then(|(name, args, closing)| {
+                    let shortcode = shortcodes::get(&name)?;
+                    if closing {
+                        shortcode.closing.clone()
+                    } else {
+                        (shortcode.render)(&args, ctx)
+                    }
+                }),
+                _ => None,
+            };
+            match html {
+                Some(html) => Some(vec![Event::Html(format!("{}\n", html).into())]),
+                None => {
+                    events.push(evt);
+                    Some(events)
+                }
+            }
+        }
+        _ => match buffer {
+            Some(events) => {
+                events.push(evt);
+                Some(vec![])
+            }
+            None => Some(vec![evt]),
+        },
+    }
+}
+
+/// Lists the headings of a Markdown document, with the anchors `md_to_html` gives them.
+pub fn md_toc(md: &str) -> Vec<TocEntry> {
+    let (md, formulas) = if MATH_RENDERING.load(Ordering::Relaxed) {
+        extract_math(md)
+    } else {
+        (md.to_owned(), vec![])
+    };
+    let mut ctx = HeadingContext {
+        formulas,
+        ..HeadingContext::default()
+    };
+    Parser::new_ext(&md, Options::all())
+        .scan(None, flatten_text)
+        .flatten()
+        .scan(&mut ctx, heading_anchors)
+        .for_each(drop);
+    ctx.toc
+}
+
 pub type MediaProcessor<'a> = Box<dyn 'a + Fn(i32) -> Option<(String, Option<String>)>>;
 
 fn process_image<'a, 'b>(
@@ -261,17 +933,70 @@ pub fn md_to_html<'a>(
     inline: bool,
     media_processor: Option<MediaProcessor<'a>>,
 ) -> (String, HashSet<String>, HashSet<String>) {
+    let (html, mentions, hashtags, _) = md_to_html_with_toc(md, base_url, inline, media_processor);
+    (html, mentions, hashtags)
+}
+
+/// Returns (HTML, mentions, hashtags, table of contents)
+///
+/// Headings get an anchor, unless `inline` is true, in which case there are no
+/// headings and the table of contents is empty.
+pub fn md_to_html_with_toc<'a>(
+    md: &str,
+    base_url: Option<&str>,
+    inline: bool,
+    media_processor: Option<MediaProcessor<'a>>,
+) -> (String, HashSet<String>, HashSet<String>, Vec<TocEntry>) {
     let base_url = if let Some(base_url) = base_url {
         format!("https://{}/", base_url)
     } else {
         "/".to_owned()
     };
-    let parser = Parser::new_ext(md, Options::all());
+    let (md, formulas) = if MATH_RENDERING.load(Ordering::Relaxed) {
+        extract_math(md)
+    } else {
+        (md.to_owned(), vec![])
+    };
+    let parser = Parser::new_ext(&md, Options::all());
+    let shortcode_ctx = ShortcodeContext {
+        media_processor: &media_processor,
+    };
+    let mut headings = HeadingContext {
+        formulas: formulas.clone(),
+        ..HeadingContext::default()
+    };
 
     let (parser, mentions, hashtags): (Vec<Event<'_>>, Vec<String>, Vec<String>) = parser
         // Flatten text because pulldown_cmark break #hashtag in two individual text elements
         .scan(None, flatten_text)
         .flatten()
+        .scan(FootnoteContext::default(), footnotes)
+        .scan(None, |buffer, evt| {
+            if inline {
+                Some(vec![evt])
+            } else {
+                embeds(buffer, evt)
+            }
+        })
+        .flatten()
+        .scan(None, |buffer, evt| {
+            if inline {
+                Some(vec![evt])
+            } else {
+                render_shortcodes(buffer, evt, &shortcode_ctx)
+            }
+        })
+        .flatten()
+        .scan(&mut headings, |ctx, evt| {
+            if inline {
+                Some(vec![evt])
+            } else {
+                heading_anchors(ctx, evt)
+            }
+        })
+        .flatten()
+        .scan(&formulas[..], render_math)
+        .flatten()
         .scan(None, highlight_code)
         .flatten()
         .map(|evt| process_image(evt, inline, &media_processor))
@@ -438,7 +1163,14 @@ pub fn md_to_html<'a>(
 
     let mut buf = String::new();
     html::push_html(&mut buf, parser);
-    (buf, mentions.collect(), hashtags.collect())
+    // Formulas that were not in a text, like in the URL of a link, are put back as they were
+    let buf = restore_math(&buf, &formulas);
+    let buf = if buf.contains(TOC_PLACEHOLDER) {
+        buf.replace(TOC_PLACEHOLDER, &shortcodes::toc_html(&headings.toc))
+    } else {
+        buf
+    };
+    (buf, mentions.collect(), hashtags.collect(), headings.toc)
 }
 
 pub fn escape(string: &str) -> askama_escape::Escaped<askama_escape::Html> {
@@ -525,11 +1257,120 @@ mod tests {
     fn test_inline() {
         assert_eq!(
             md_to_html("# Hello", None, false, None).0,
-            String::from("<h1 dir=\"auto\">Hello</h1>\n")
+            String::from("<h1 dir=\"auto\" id=\"postcontent-hello\">Hello</h1>\n")
         );
         assert_eq!(
             md_to_html("# Hello", None, true, None).0,
             String::from("<p dir=\"auto\">Hello</p>\n")
         );
     }
+
+    #[test]
+    fn test_toc() {
+        let md = "# Intro\n\nText\n\n## Why `#[derive]`?\n\n## Intro\n\n# Ünïcode, wörks!";
+        let (html, _, hashtags, toc) = md_to_html_with_toc(md, None, false, None);
+        assert_eq!(
+            toc,
+            vec![
+                TocEntry {
+                    level: 1,
+                    title: "Intro".to_owned(),
+                    anchor: "postcontent-intro".to_owned(),
+                },
+                TocEntry {
+                    level: 2,
+                    title: "Why #[derive]?".to_owned(),
+                    anchor: "postcontent-why-derive".to_owned(),
+                },
+                TocEntry {
+                    level: 2,
+                    title: "Intro".to_owned(),
+                    anchor: "postcontent-intro-1".to_owned(),
+                },
+                TocEntry {
+                    level: 1,
+                    title: "Ünïcode, wörks!".to_owned(),
+                    anchor: "postcontent-ünïcode-wörks".to_owned(),
+                },
+            ]
+        );
+        assert!(html.contains("<h2 dir=\"auto\" id=\"postcontent-intro-1\">Intro</h2>"));
+        assert!(hashtags.is_empty());
+        assert_eq!(md_toc(md), toc);
+        assert!(md_to_html_with_toc(md, None, true, None).3.is_empty());
+    }
+
+    #[test]
+    fn test_math() {
+        let (md, formulas) =
+            extract_math("It costs $5 and $10.\n\nNot `$x$` but $x^2$.\n\n$$\n\\frac{1}{2}\n$$");
+        assert_eq!(formulas.len(), 2);
+        assert_eq!(formulas[0].latex, "x^2");
+        assert!(!formulas[0].display);
+        assert!(formulas[1].display);
+        assert!(md.contains("$5 and $10."));
+        assert!(md.contains("`$x$`"));
+
+        let (md, formulas) = extract_math("Code:\n\n    let a = $x$;\n\nbut $y$");
+        assert_eq!(formulas.len(), 1);
+        assert_eq!(formulas[0].latex, "y");
+        assert!(md.contains("    let a = $x$;"));
+        // A paragraph isn't a code block because it continues on an indented line
+        assert_eq!(extract_math("Some\n    $z$").1.len(), 1);
+
+        // Unclosed delimiters are only looked for once
+        let many = format!("$$ {}", "$a ".repeat(50_000));
+        let start = std::time::Instant::now();
+        assert!(extract_math(&many).1.is_empty());
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+
+        let html = md_to_html("Let $x^2$ be, but not \\$y$ or `$z$`.", None, false, None).0;
+        assert!(html.contains("<math"));
+        assert!(html.contains("<code>$z$</code>"));
+        assert!(!html.contains('\u{E000}'));
+    }
+
+    #[test]
+    fn test_embeds() {
+        assert!(is_embeddable("https://www.youtube.com/watch?v=abc"));
+        assert!(!is_embeddable("http://www.youtube.com/watch?v=abc"));
+        assert!(!is_embeddable("https://notyoutube.com/watch?v=abc"));
+
+        let html = md_to_html(
+            "Look:\n\nhttps://youtu.be/dQw4w9WgXcQ\n\n<https://www.openstreetmap.org/#map=12/48.85/2.35>\n\n\
+             Not https://youtu.be/dQw4w9WgXcQ, nor https://video.example/w/abc",
+            None,
+            false,
+            None,
+        )
+        .0;
+        assert!(html.contains(
+            "<div class=\"embed\" data-embed=\"https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ\">"
+        ));
+        assert!(html.contains("data-embed=\"https://www.openstreetmap.org/export/embed.html?bbox="));
+        assert_eq!(html.matches("class=\"embed\"").count(), 2);
+
+        let allowlist = vec!["video.example".to_owned()];
+        assert_eq!(
+            embed_url_in("https://video.example/w/abc", &allowlist).as_deref(),
+            Some("https://video.example/videos/embed/abc")
+        );
+        assert!(embed_url_in("https://youtu.be/dQw4w9WgXcQ", &allowlist).is_none());
+    }
+
+    #[test]
+    fn test_shortcodes() {
+        let md = "{{toc}}\n\n# Intro\n\n{{cw text=\"Spoilers\"}}\n\nThe end.\n\n{{/cw}}\n\n\
+                  {{gallery id=\"1,2\"}}\n\n`{{toc}}` and {{unknown}}";
+        let processor: MediaProcessor<'_> =
+            Box::new(|id| Some((format!("/static/media/{}.png", id), None)));
+        let html = md_to_html(md, None, false, Some(processor)).0;
+        assert!(html.starts_with(
+            "<nav class=\"toc\"><ul><li class=\"toc-level-1\"><a href=\"#postcontent-intro\">Intro</a></li></ul></nav>"
+        ));
+        assert!(html.contains("<details class=\"cw\"><summary>Spoilers</summary>"));
+        assert!(html.contains("The end.</p>\n</details>"));
+        assert!(html.contains("<img src=\"/static/media/2.png\" alt=\"\">"));
+        assert!(html.contains("<code>{{toc}}</code> and {{unknown}}"));
+    }
 }
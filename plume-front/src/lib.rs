@@ -85,6 +85,7 @@ pub fn main() -> Result<(), JsValue> {
 
     menu();
     search();
+    embeds();
     editor::init()
         .map_err(|e| console::error_1(&format!("Editor error: {:?}", e).into()))
         .ok();
@@ -170,6 +171,49 @@ fn search() {
     }
 }
 
+/// Load embedded videos and maps when the reader asks for it
+///
+/// Without JavaScript, a link to the original page is displayed instead.
+fn embeds() {
+    let embeds = match document().query_selector_all(".embed[data-embed]") {
+        Ok(embeds) => embeds,
+        Err(_) => return,
+    };
+    for i in 0..embeds.length() {
+        let embed = match embeds.get(i).and_then(|e| e.dyn_into::<Element>().ok()) {
+            Some(embed) => embed,
+            None => continue,
+        };
+        let src = embed.get_attribute("data-embed").unwrap_or_default();
+        let host = src
+            .split('/')
+            .nth(2)
+            .map(|h| h.to_owned())
+            .unwrap_or_default();
+        let button = document().create_element("button").unwrap();
+        button.set_text_content(Some(&i18n!(CATALOG, "Load content from {}"; host)));
+        let target = embed.clone();
+        let load = Closure::wrap(Box::new(move |_: Event| {
+            let iframe = document().create_element("iframe").unwrap();
+            iframe.set_attribute("src", &src).unwrap();
+            iframe.set_attribute("allowfullscreen", "").unwrap();
+            iframe
+                .set_attribute("sandbox", "allow-scripts allow-same-origin allow-popups")
+                .unwrap();
+            target.set_inner_html("");
+            target.append_child(&iframe).unwrap();
+            target.class_list().add_1("loaded").unwrap();
+        }) as Box<dyn FnMut(Event)>);
+        button
+            .add_event_listener_with_callback("click", load.as_ref().unchecked_ref())
+            .unwrap();
+        load.forget();
+        embed
+            .insert_before(&button, embed.first_child().as_ref())
+            .unwrap();
+    }
+}
+
 fn document() -> Document {
     window().unwrap().document().unwrap()
 }
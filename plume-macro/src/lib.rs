@@ -55,10 +55,21 @@ pub fn import_migrations(input: TokenStream) -> TokenStream {
                 .filter(char::is_ascii_digit)
                 .take(14)
                 .collect::<String>();
-            (name, up_sql, down_sql)
+            // Contract migrations remove what the previous version used, they are run after
+            // the new one is deployed everywhere
+            let phase = if up_sql
+                .lines()
+                .any(|line| line.trim() == "-- phase: contract")
+            {
+                quote!(Phase::Contract)
+            } else {
+                quote!(Phase::Expand)
+            };
+            (name, up_sql, down_sql, phase)
         })
         .collect::<Vec<_>>();
     let migrations_name = migrations.iter().map(|m| &m.0);
+    let migrations_phase = migrations.iter().map(|m| &m.3);
     let migrations_up = migrations
         .iter()
         .map(|m| m.1.as_str())
@@ -78,7 +89,7 @@ pub fn import_migrations(input: TokenStream) -> TokenStream {
 
     quote!(
         ImportedMigrations(
-            &[#(ComplexMigration{name: #migrations_name, up: #migrations_up, down: #migrations_down}),*]
+            &[#(ComplexMigration{name: #migrations_name, phase: #migrations_phase, up: #migrations_up, down: #migrations_down}),*]
             )
     ).into()
 }
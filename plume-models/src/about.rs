@@ -0,0 +1,271 @@
+//! What this instance tells about itself, besides its description: the rules everyone has
+//! to follow on it, and the sections of its about page.
+//!
+//! Both are ordered by the administrators, and are also given by NodeInfo and the API.
+
+use crate::{
+    instance::Instance,
+    medias::Media,
+    safe_string::SafeString,
+    schema::{about_sections, instance_rules},
+    Connection, Error, Result,
+};
+use chrono::NaiveDateTime;
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl, SaveChangesDsl};
+use plume_common::utils::md_to_html;
+
+/// Rules are meant to be read at a glance, their details go in their long text.
+const MAX_RULE_LENGTH: usize = 300;
+
+#[derive(Clone, Queryable, Identifiable, AsChangeset)]
+#[table_name = "instance_rules"]
+pub struct InstanceRule {
+    pub id: i32,
+    pub position: i32,
+    pub short_text: String,
+    /// Explains the rule, if needed
+    pub long_text: String,
+    pub creation_date: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "instance_rules"]
+pub struct NewInstanceRule {
+    pub position: i32,
+    pub short_text: String,
+    pub long_text: String,
+}
+
+#[derive(Clone, Queryable, Identifiable, AsChangeset)]
+#[table_name = "about_sections"]
+pub struct AboutSection {
+    pub id: i32,
+    pub position: i32,
+    pub title: String,
+    /// As Markdown
+    pub content: String,
+    pub content_html: SafeString,
+}
+
+#[derive(Insertable)]
+#[table_name = "about_sections"]
+pub struct NewAboutSection {
+    pub position: i32,
+    pub title: String,
+    pub content: String,
+    pub content_html: SafeString,
+}
+
+impl InstanceRule {
+    insert!(instance_rules, NewInstanceRule);
+    get!(instance_rules);
+
+    pub fn list(conn: &Connection) -> Result<Vec<Self>> {
+        instance_rules::table
+            .order((instance_rules::position.asc(), instance_rules::id.asc()))
+            .load::<Self>(conn)
+            .map_err(Error::from)
+    }
+
+    /// Adds a rule after the other ones.
+    pub fn add(conn: &Connection, short_text: &str, long_text: &str) -> Result<Self> {
+        let (short_text, long_text) = check_rule(short_text, long_text)?;
+        let position = instance_rules::table
+            .select(diesel::dsl::max(instance_rules::position))
+            .first::<Option<i32>>(conn)?
+            .map_or(0, |last| last + 1);
+        Self::insert(
+            conn,
+            NewInstanceRule {
+                position,
+                short_text,
+                long_text,
+            },
+        )
+    }
+
+    pub fn update(&mut self, conn: &Connection, short_text: &str, long_text: &str) -> Result<()> {
+        let (short_text, long_text) = check_rule(short_text, long_text)?;
+        diesel::update(&*self)
+            .set((
+                instance_rules::short_text.eq(&short_text),
+                instance_rules::long_text.eq(&long_text),
+            ))
+            .execute(conn)?;
+        self.short_text = short_text;
+        self.long_text = long_text;
+        Ok(())
+    }
+
+    /// Swaps this rule with the previous one.
+    pub fn move_up(&self, conn: &Connection) -> Result<()> {
+        let rules = Self::list(conn)?;
+        if let Some(index) = rules
+            .iter()
+            .position(|r| r.id == self.id)
+            .filter(|i| *i > 0)
+        {
+            let mut rules = rules;
+            rules.swap(index - 1, index);
+            for (position, mut rule) in rules.into_iter().enumerate() {
+                rule.position = position as i32;
+                rule.save_changes::<Self>(conn)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn delete(&self, conn: &Connection) -> Result<()> {
+        diesel::delete(self)
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+}
+
+impl AboutSection {
+    insert!(about_sections, NewAboutSection);
+    get!(about_sections);
+
+    pub fn list(conn: &Connection) -> Result<Vec<Self>> {
+        about_sections::table
+            .order((about_sections::position.asc(), about_sections::id.asc()))
+            .load::<Self>(conn)
+            .map_err(Error::from)
+    }
+
+    /// Adds a section at the end of the about page.
+    pub fn add(conn: &Connection, title: &str, content: &str) -> Result<Self> {
+        let title = check_title(title)?;
+        let position = about_sections::table
+            .select(diesel::dsl::max(about_sections::position))
+            .first::<Option<i32>>(conn)?
+            .map_or(0, |last| last + 1);
+        Self::insert(
+            conn,
+            NewAboutSection {
+                position,
+                title,
+                content: content.to_owned(),
+                content_html: render(conn, content)?,
+            },
+        )
+    }
+
+    pub fn update(&mut self, conn: &Connection, title: &str, content: &str) -> Result<()> {
+        // Only the text is saved, the position may have changed since it was loaded
+        self.title = check_title(title)?;
+        self.content = content.to_owned();
+        self.content_html = render(conn, content)?;
+        diesel::update(&*self)
+            .set((
+                about_sections::title.eq(&self.title),
+                about_sections::content.eq(&self.content),
+                about_sections::content_html.eq(&self.content_html),
+            ))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    /// Swaps this section with the previous one.
+    pub fn move_up(&self, conn: &Connection) -> Result<()> {
+        let sections = Self::list(conn)?;
+        if let Some(index) = sections
+            .iter()
+            .position(|s| s.id == self.id)
+            .filter(|i| *i > 0)
+        {
+            let mut sections = sections;
+            sections.swap(index - 1, index);
+            for (position, mut section) in sections.into_iter().enumerate() {
+                section.position = position as i32;
+                section.save_changes::<Self>(conn)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn delete(&self, conn: &Connection) -> Result<()> {
+        diesel::delete(self)
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+}
+
+fn check_rule(short_text: &str, long_text: &str) -> Result<(String, String)> {
+    let short_text = short_text.trim();
+    if short_text.is_empty() || short_text.chars().count() > MAX_RULE_LENGTH {
+        return Err(Error::InvalidValue);
+    }
+    Ok((short_text.to_owned(), long_text.trim().to_owned()))
+}
+
+fn check_title(title: &str) -> Result<String> {
+    Some(title.trim())
+        .filter(|t| !t.is_empty())
+        .map(str::to_owned)
+        .ok_or(Error::InvalidValue)
+}
+
+fn render(conn: &Connection, content: &str) -> Result<SafeString> {
+    let (html, _, _) = md_to_html(
+        content,
+        Some(&Instance::get_local()?.public_domain),
+        false,
+        Some(Media::get_media_processor(conn, vec![])),
+    );
+    Ok(SafeString::new(&html))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{instance::tests as instance_tests, tests::db};
+    use diesel::Connection;
+
+    #[test]
+    fn rules() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            instance_tests::fill_database(conn);
+            let first = InstanceRule::add(conn, "Be nice", "")?;
+            let mut second = InstanceRule::add(conn, " No spam ", "Including advertising")?;
+            assert_eq!(second.short_text, "No spam");
+            assert!(InstanceRule::add(conn, "  ", "Empty").is_err());
+            assert!(InstanceRule::add(conn, &"a".repeat(MAX_RULE_LENGTH + 1), "").is_err());
+
+            second.move_up(conn)?;
+            let ids = InstanceRule::list(conn)?
+                .into_iter()
+                .map(|r| r.id)
+                .collect::<Vec<_>>();
+            assert_eq!(ids, vec![second.id, first.id]);
+
+            second.update(conn, "No spam or ads", "")?;
+            assert_eq!(
+                InstanceRule::get(conn, second.id)?.short_text,
+                "No spam or ads"
+            );
+            first.delete(conn)?;
+            assert_eq!(InstanceRule::list(conn)?.len(), 1);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn sections() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            instance_tests::fill_database(conn);
+            let mut section = AboutSection::add(conn, "Funding", "Thanks to *our* donors")?;
+            assert!(section.content_html.contains("<em>our</em>"));
+            assert!(AboutSection::add(conn, "", "No title").is_err());
+            section.update(conn, "Money", "Nobody pays")?;
+            let sections = AboutSection::list(conn)?;
+            assert_eq!(sections.len(), 1);
+            assert_eq!(sections[0].title, "Money");
+            Ok(())
+        });
+    }
+}
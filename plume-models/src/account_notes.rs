@@ -0,0 +1,175 @@
+//! Private notes that users write about the accounts they follow, to remember why they
+//! followed them. Only their authors can read them.
+
+use crate::{schema::account_notes, users::User, Connection, Cursor, Error, Result};
+use chrono::{NaiveDateTime, Utc};
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+
+/// Notes are reminders, not articles.
+pub const MAX_NOTE_LENGTH: usize = 2000;
+
+#[derive(Clone, Queryable, Identifiable)]
+pub struct AccountNote {
+    pub id: i32,
+    /// The user who wrote it
+    pub owner_id: i32,
+    /// The account it is about
+    pub target_id: i32,
+    pub content: String,
+    pub creation_date: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "account_notes"]
+pub struct NewAccountNote {
+    pub owner_id: i32,
+    pub target_id: i32,
+    pub content: String,
+}
+
+impl AccountNote {
+    insert!(account_notes, NewAccountNote);
+    get!(account_notes);
+    find_by!(account_notes, find_for, owner_id as i32, target_id as i32);
+
+    /// The notes of a user, with the accounts they are about, the most recently edited first.
+    pub fn list_for_owner(conn: &Connection, owner_id: i32) -> Result<Vec<(Self, User)>> {
+        let notes = account_notes::table
+            .filter(account_notes::owner_id.eq(owner_id))
+            .order(account_notes::updated_at.desc())
+            .load::<Self>(conn)?;
+        with_targets(conn, notes)
+    }
+
+    /// A page of the notes of a user, as API clients ask for it.
+    pub fn list_for_owner_page(
+        conn: &Connection,
+        owner_id: i32,
+        cursor: Cursor,
+    ) -> Result<Vec<(Self, User)>> {
+        let notes = paginate!(
+            account_notes::table
+                .filter(account_notes::owner_id.eq(owner_id))
+                .into_boxed(),
+            account_notes,
+            cursor
+        )
+        .load::<Self>(conn)?;
+        with_targets(conn, cursor.finish(notes))
+    }
+
+    /// Writes the note of `owner` about `target`, replacing the previous one. An empty note
+    /// deletes it.
+    ///
+    /// Notes can only be written about the accounts `owner` follows.
+    pub fn set(
+        conn: &Connection,
+        owner: &User,
+        target: &User,
+        content: &str,
+    ) -> Result<Option<Self>> {
+        let content = content.trim();
+        if content.chars().count() > MAX_NOTE_LENGTH {
+            return Err(Error::InvalidValue);
+        }
+        let existing = Self::find_for(conn, owner.id, target.id).ok();
+        if content.is_empty() {
+            if let Some(note) = existing {
+                note.delete(conn)?;
+            }
+            return Ok(None);
+        }
+        if !target.is_followed_by(conn, owner.id)? {
+            return Err(Error::Unauthorized);
+        }
+
+        match existing {
+            Some(note) => {
+                diesel::update(&note)
+                    .set((
+                        account_notes::content.eq(content),
+                        account_notes::updated_at.eq(Utc::now().naive_utc()),
+                    ))
+                    .execute(conn)?;
+                Self::get(conn, note.id).map(Some)
+            }
+            None => Self::insert(
+                conn,
+                NewAccountNote {
+                    owner_id: owner.id,
+                    target_id: target.id,
+                    content: content.to_owned(),
+                },
+            )
+            .map(Some),
+        }
+    }
+
+    pub fn delete(&self, conn: &Connection) -> Result<()> {
+        diesel::delete(self)
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+}
+
+/// The notes, with the accounts they are about.
+fn with_targets(conn: &Connection, notes: Vec<AccountNote>) -> Result<Vec<(AccountNote, User)>> {
+    notes
+        .into_iter()
+        .map(|note| {
+            let target = User::get(conn, note.target_id)?;
+            Ok((note, target))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        follows::{Follow, NewFollow},
+        tests::db,
+        users::tests as user_tests,
+    };
+    use diesel::Connection;
+
+    #[test]
+    fn set_and_list() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let users = user_tests::fill_database(conn);
+            let (owner, target) = (&users[0], &users[1]);
+            // Only followed accounts can have notes
+            assert!(AccountNote::set(conn, owner, target, "Met at a conference").is_err());
+
+            Follow::insert(
+                conn,
+                NewFollow {
+                    follower_id: owner.id,
+                    following_id: target.id,
+                    ap_url: "https://plu.me/follows/notes".to_owned(),
+                },
+            )?;
+            let note = AccountNote::set(conn, owner, target, " Met at a conference ")?.unwrap();
+            assert_eq!(note.content, "Met at a conference");
+            let updated = AccountNote::set(conn, owner, target, "Writes about birds")?.unwrap();
+            assert_eq!(updated.id, note.id);
+            assert_eq!(updated.content, "Writes about birds");
+            assert!(
+                AccountNote::set(conn, owner, target, &"a".repeat(MAX_NOTE_LENGTH + 1)).is_err()
+            );
+
+            let notes = AccountNote::list_for_owner(conn, owner.id)?;
+            assert_eq!(notes.len(), 1);
+            assert_eq!(notes[0].1.id, target.id);
+            // Notes are private
+            assert!(AccountNote::list_for_owner(conn, target.id)?.is_empty());
+
+            assert!(AccountNote::set(conn, owner, target, "")?.is_none());
+            assert!(AccountNote::list_for_owner(conn, owner.id)?.is_empty());
+            Ok(())
+        });
+    }
+}
@@ -1,9 +1,10 @@
-use crate::users::User;
+use crate::users::{Role, User};
 use rocket::{
     http::Status,
     request::{self, FromRequest, Request},
     Outcome,
 };
+use std::marker::PhantomData;
 
 /// Wrapper around User to use as a request guard on pages exclusively reserved to admins.
 pub struct Admin(pub User);
@@ -53,3 +54,90 @@ impl<'a, 'r> FromRequest<'a, 'r> for Moderator {
         }
     }
 }
+
+/// The moderation and administration actions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Permission {
+    BlockInstances,
+    ManageEmailBlocklist,
+    ManageTags,
+    /// Hiding the posts of an account from the timelines of the instance
+    SilenceAccounts,
+    /// Granting or revoking the moderator and admin roles
+    ManageRoles,
+    /// Banning accounts, which deletes them
+    DeleteUsers,
+    ManageSettings,
+}
+
+impl Permission {
+    /// The permission matrix: moderators take care of the content and the accounts of
+    /// the instance, while only admins can delete users, change roles and settings.
+    pub fn granted_to(self, role: i32) -> bool {
+        if role == Role::Admin as i32 {
+            true
+        } else if role == Role::Moderator as i32 {
+            match self {
+                Permission::BlockInstances
+                | Permission::ManageEmailBlocklist
+                | Permission::ManageTags
+                | Permission::SilenceAccounts => true,
+                Permission::ManageRoles | Permission::DeleteUsers | Permission::ManageSettings => {
+                    false
+                }
+            }
+        } else {
+            false
+        }
+    }
+}
+
+/// A permission that can be required by a route, with the `Can` guard.
+pub trait PermissionScope {
+    fn permission() -> Permission;
+}
+
+/// Types to use with `Can`, one for each `Permission`.
+pub mod permissions {
+    use super::{Permission, PermissionScope};
+
+    macro_rules! scopes {
+        ($($name:ident),*) => {
+            $(
+                pub enum $name {}
+
+                impl PermissionScope for $name {
+                    fn permission() -> Permission {
+                        Permission::$name
+                    }
+                }
+            )*
+        };
+    }
+
+    scopes!(
+        BlockInstances,
+        ManageEmailBlocklist,
+        ManageTags,
+        SilenceAccounts,
+        ManageRoles,
+        DeleteUsers,
+        ManageSettings
+    );
+}
+
+/// Request guard for the users who have a given permission, like `Can<permissions::ManageTags>`.
+pub struct Can<P: PermissionScope>(pub User, PhantomData<P>);
+
+impl<'a, 'r, P: PermissionScope> FromRequest<'a, 'r> for Can<P> {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Can<P>, ()> {
+        let user = request.guard::<User>()?;
+        if user.has_permission(P::permission()) {
+            Outcome::Success(Can(user, PhantomData))
+        } else {
+            Outcome::Failure((Status::Unauthorized, ()))
+        }
+    }
+}
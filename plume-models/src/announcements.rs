@@ -0,0 +1,535 @@
+//! Announcements of the administrators to everyone on this instance, like a maintenance
+//! window, shown at the top of every page while they are running.
+//!
+//! They are not articles: they are not federated, and users only see them until they
+//! dismiss them.
+
+use crate::{
+    instance::Instance,
+    medias::Media,
+    safe_string::SafeString,
+    schema::{announcement_dismissals, announcement_reactions, announcements},
+    users::User,
+    Connection, Cursor, Error, Result,
+};
+use chrono::{NaiveDateTime, Utc};
+use diesel::{self, BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl};
+use plume_common::utils::md_to_html;
+
+type Backend = <Connection as diesel::Connection>::Backend;
+
+/// Emojis made of several code points, like flags or families, are still short.
+const MAX_EMOJI_LENGTH: usize = 10;
+
+/// How many different emojis a user can react with to an announcement.
+const MAX_REACTIONS_PER_USER: i64 = 3;
+
+#[derive(Clone, Queryable, Identifiable)]
+pub struct Announcement {
+    pub id: i32,
+    /// The administrator who wrote it, if they still have an account
+    pub author_id: Option<i32>,
+    /// As Markdown
+    pub content: String,
+    pub content_html: SafeString,
+    /// Shown right away if there is no start date
+    pub starts_at: Option<NaiveDateTime>,
+    /// Shown until it is deleted if there is no end date
+    pub ends_at: Option<NaiveDateTime>,
+    pub creation_date: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "announcements"]
+pub struct NewAnnouncement {
+    pub author_id: Option<i32>,
+    pub content: String,
+    pub content_html: SafeString,
+    pub starts_at: Option<NaiveDateTime>,
+    pub ends_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "announcement_dismissals"]
+struct NewAnnouncementDismissal {
+    announcement_id: i32,
+    user_id: i32,
+}
+
+#[derive(Insertable)]
+#[table_name = "announcement_reactions"]
+struct NewAnnouncementReaction {
+    announcement_id: i32,
+    user_id: i32,
+    emoji: String,
+}
+
+/// How many people reacted to an announcement with an emoji.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reaction {
+    pub emoji: String,
+    pub count: usize,
+    /// Whether the user it was loaded for is one of them
+    pub me: bool,
+}
+
+impl Announcement {
+    insert!(announcements, NewAnnouncement);
+    get!(announcements);
+
+    /// All the announcements, the most recent first, for the administrators.
+    pub fn list(conn: &Connection) -> Result<Vec<Self>> {
+        announcements::table
+            .order(announcements::creation_date.desc())
+            .load::<Self>(conn)
+            .map_err(Error::from)
+    }
+
+    /// The running announcements, that `user` did not dismiss, with their reactions.
+    pub fn active_with_reactions(
+        conn: &Connection,
+        user: Option<&User>,
+    ) -> Result<Vec<(Self, Vec<Reaction>)>> {
+        let active = Self::active(conn, user)?;
+        with_reactions(conn, active, user)
+    }
+
+    /// A page of the running announcements, that `user` did not dismiss, with their
+    /// reactions, as API clients ask for it.
+    pub fn active_with_reactions_page(
+        conn: &Connection,
+        user: Option<&User>,
+        cursor: Cursor,
+    ) -> Result<Vec<(Self, Vec<Reaction>)>> {
+        let active =
+            paginate!(Self::active_query(user), announcements, cursor).load::<Self>(conn)?;
+        with_reactions(conn, cursor.finish(active), user)
+    }
+
+    /// The running announcements, that `user` did not dismiss.
+    pub fn active(conn: &Connection, user: Option<&User>) -> Result<Vec<Self>> {
+        Self::active_query(user)
+            .order(announcements::creation_date.desc())
+            .load::<Self>(conn)
+            .map_err(Error::from)
+    }
+
+    fn active_query(user: Option<&User>) -> announcements::BoxedQuery<'static, Backend> {
+        let now = Utc::now().naive_utc();
+        let mut query = announcements::table
+            .filter(
+                announcements::starts_at
+                    .is_null()
+                    .or(announcements::starts_at.le(now)),
+            )
+            .filter(
+                announcements::ends_at
+                    .is_null()
+                    .or(announcements::ends_at.gt(now)),
+            )
+            .into_boxed();
+        if let Some(user) = user {
+            query = query.filter(
+                announcements::id.ne_all(
+                    announcement_dismissals::table
+                        .filter(announcement_dismissals::user_id.eq(user.id))
+                        .select(announcement_dismissals::announcement_id),
+                ),
+            );
+        }
+        query
+    }
+
+    pub fn create(
+        conn: &Connection,
+        author: &User,
+        content: &str,
+        starts_at: Option<NaiveDateTime>,
+        ends_at: Option<NaiveDateTime>,
+    ) -> Result<Self> {
+        let content = check(content, starts_at, ends_at)?;
+        Self::insert(
+            conn,
+            NewAnnouncement {
+                author_id: Some(author.id),
+                content_html: render(conn, &content)?,
+                content,
+                starts_at,
+                ends_at,
+            },
+        )
+    }
+
+    pub fn update(
+        &mut self,
+        conn: &Connection,
+        content: &str,
+        starts_at: Option<NaiveDateTime>,
+        ends_at: Option<NaiveDateTime>,
+    ) -> Result<()> {
+        self.content = check(content, starts_at, ends_at)?;
+        self.content_html = render(conn, &self.content)?;
+        self.starts_at = starts_at;
+        self.ends_at = ends_at;
+        diesel::update(&*self)
+            .set((
+                announcements::content.eq(&self.content),
+                announcements::content_html.eq(&self.content_html),
+                announcements::starts_at.eq(self.starts_at),
+                announcements::ends_at.eq(self.ends_at),
+            ))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    /// Whether it is shown now.
+    pub fn is_running(&self) -> bool {
+        let now = Utc::now().naive_utc();
+        self.starts_at.map_or(true, |start| start <= now)
+            && self.ends_at.map_or(true, |end| end > now)
+    }
+
+    /// Stops showing this announcement to `user`.
+    pub fn dismiss(&self, conn: &Connection, user: &User) -> Result<()> {
+        if !self.is_running() {
+            return Err(Error::NotFound);
+        }
+        if self.is_dismissed_by(conn, user)? {
+            return Ok(());
+        }
+        diesel::insert_into(announcement_dismissals::table)
+            .values(NewAnnouncementDismissal {
+                announcement_id: self.id,
+                user_id: user.id,
+            })
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+
+    pub fn is_dismissed_by(&self, conn: &Connection, user: &User) -> Result<bool> {
+        announcement_dismissals::table
+            .filter(announcement_dismissals::announcement_id.eq(self.id))
+            .filter(announcement_dismissals::user_id.eq(user.id))
+            .count()
+            .get_result::<i64>(conn)
+            .map(|count| count > 0)
+            .map_err(Error::from)
+    }
+
+    /// Reacts with `emoji`, which has to be a single emoji.
+    ///
+    /// Only running announcements can be reacted to, with a few different emojis per user.
+    pub fn react(&self, conn: &Connection, user: &User, emoji: &str) -> Result<()> {
+        if !self.is_running() {
+            return Err(Error::NotFound);
+        }
+        let emoji = emoji.trim();
+        if !is_emoji(emoji) {
+            return Err(Error::InvalidValue);
+        }
+        let own = announcement_reactions::table
+            .filter(announcement_reactions::announcement_id.eq(self.id))
+            .filter(announcement_reactions::user_id.eq(user.id))
+            .select(announcement_reactions::emoji)
+            .load::<String>(conn)?;
+        if own.iter().any(|e| e == emoji) {
+            return Ok(());
+        }
+        if own.len() as i64 >= MAX_REACTIONS_PER_USER {
+            return Err(Error::InvalidValue);
+        }
+        diesel::insert_into(announcement_reactions::table)
+            .values(NewAnnouncementReaction {
+                announcement_id: self.id,
+                user_id: user.id,
+                emoji: emoji.to_owned(),
+            })
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+
+    /// Takes a reaction back, returning whether there was one.
+    pub fn unreact(&self, conn: &Connection, user: &User, emoji: &str) -> Result<bool> {
+        if !self.is_running() {
+            return Err(Error::NotFound);
+        }
+        let reaction = announcement_reactions::table
+            .filter(announcement_reactions::announcement_id.eq(self.id))
+            .filter(announcement_reactions::user_id.eq(user.id))
+            .filter(announcement_reactions::emoji.eq(emoji.trim()));
+        diesel::delete(reaction)
+            .execute(conn)
+            .map(|removed| removed > 0)
+            .map_err(Error::from)
+    }
+
+    /// Reacts with `emoji`, or takes the reaction back if `user` already reacted with it.
+    pub fn toggle_reaction(&self, conn: &Connection, user: &User, emoji: &str) -> Result<()> {
+        if self.unreact(conn, user, emoji)? {
+            Ok(())
+        } else {
+            self.react(conn, user, emoji)
+        }
+    }
+
+    /// The reactions to this announcement, in the order they were first used.
+    pub fn reactions(&self, conn: &Connection, user: Option<&User>) -> Result<Vec<Reaction>> {
+        let reactions = announcement_reactions::table
+            .filter(announcement_reactions::announcement_id.eq(self.id))
+            .order(announcement_reactions::id.asc())
+            .select((
+                announcement_reactions::emoji,
+                announcement_reactions::user_id,
+            ))
+            .load::<(String, i32)>(conn)?;
+        Ok(count_reactions(reactions, user))
+    }
+
+    pub fn delete(&self, conn: &Connection) -> Result<()> {
+        diesel::delete(self)
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+}
+
+/// The reactions to each announcement.
+fn with_reactions(
+    conn: &Connection,
+    active: Vec<Announcement>,
+    user: Option<&User>,
+) -> Result<Vec<(Announcement, Vec<Reaction>)>> {
+    let mut reactions = announcement_reactions::table
+        .filter(
+            announcement_reactions::announcement_id
+                .eq_any(active.iter().map(|a| a.id).collect::<Vec<_>>()),
+        )
+        .order(announcement_reactions::id.asc())
+        .select((
+            announcement_reactions::announcement_id,
+            announcement_reactions::emoji,
+            announcement_reactions::user_id,
+        ))
+        .load::<(i32, String, i32)>(conn)?;
+    Ok(active
+        .into_iter()
+        .map(|announcement| {
+            let (own, others): (Vec<_>, Vec<_>) = reactions
+                .drain(..)
+                .partition(|(announcement_id, _, _)| *announcement_id == announcement.id);
+            reactions = others;
+            let counted = count_reactions(
+                own.into_iter().map(|(_, emoji, user_id)| (emoji, user_id)),
+                user,
+            );
+            (announcement, counted)
+        })
+        .collect())
+}
+
+/// Counts the `(emoji, user_id)` reactions, in the order they were loaded.
+fn count_reactions(
+    reactions: impl IntoIterator<Item = (String, i32)>,
+    user: Option<&User>,
+) -> Vec<Reaction> {
+    let mut counted: Vec<Reaction> = vec![];
+    for (emoji, user_id) in reactions {
+        let me = user.map_or(false, |u| u.id == user_id);
+        match counted.iter_mut().find(|r| r.emoji == emoji) {
+            Some(reaction) => {
+                reaction.count += 1;
+                reaction.me |= me;
+            }
+            None => counted.push(Reaction {
+                emoji,
+                count: 1,
+                me,
+            }),
+        }
+    }
+    counted
+}
+
+fn check(
+    content: &str,
+    starts_at: Option<NaiveDateTime>,
+    ends_at: Option<NaiveDateTime>,
+) -> Result<String> {
+    let content = content.trim();
+    if content.is_empty() {
+        return Err(Error::InvalidValue);
+    }
+    if let (Some(start), Some(end)) = (starts_at, ends_at) {
+        if end <= start {
+            return Err(Error::InvalidValue);
+        }
+    }
+    Ok(content.to_owned())
+}
+
+/// Whether it looks like a single emoji: a few pictographs, maybe joined or modified, and
+/// nothing else, so that invisible or direction changing characters are refused.
+fn is_emoji(emoji: &str) -> bool {
+    let chars = emoji.chars().collect::<Vec<_>>();
+    if chars.is_empty() || chars.len() > MAX_EMOJI_LENGTH || !is_pictograph(chars[0]) {
+        return false;
+    }
+    chars.iter().enumerate().all(|(i, &c)| match c {
+        // Zero width joiners, between two pictographs
+        '\u{200D}' => chars.get(i + 1).map_or(false, |&next| is_pictograph(next)),
+        // Variation selectors, and the tags of subdivision flags
+        '\u{FE0E}' | '\u{FE0F}' | '\u{E0020}'..='\u{E007F}' => true,
+        c => is_pictograph(c),
+    })
+}
+
+/// The blocks of Unicode where emojis are, including skin tones and regional indicators.
+fn is_pictograph(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00A9}'
+            | '\u{00AE}'
+            | '\u{203C}'
+            | '\u{2049}'
+            | '\u{2122}'
+            | '\u{2139}'
+            | '\u{2194}'..='\u{21AA}'
+            | '\u{2300}'..='\u{23FF}'
+            | '\u{24C2}'
+            | '\u{25A0}'..='\u{25FF}'
+            | '\u{2600}'..='\u{27BF}'
+            | '\u{2934}'..='\u{2935}'
+            | '\u{2B00}'..='\u{2BFF}'
+            | '\u{3030}'
+            | '\u{303D}'
+            | '\u{3297}'
+            | '\u{3299}'
+            | '\u{1F000}'..='\u{1FAFF}'
+    )
+}
+
+fn render(conn: &Connection, content: &str) -> Result<SafeString> {
+    let (html, _, _) = md_to_html(
+        content,
+        Some(&Instance::get_local()?.public_domain),
+        false,
+        Some(Media::get_media_processor(conn, vec![])),
+    );
+    Ok(SafeString::new(&html))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tests::db, users::tests as user_tests};
+    use chrono::Duration;
+    use diesel::Connection;
+
+    #[test]
+    fn emojis() {
+        assert!(is_emoji("🎉"));
+        assert!(is_emoji("👍🏽"));
+        assert!(!is_emoji(""));
+        assert!(!is_emoji(":tada:"));
+        assert!(!is_emoji("é"));
+        assert!(is_emoji("❤️"));
+        assert!(is_emoji("👩\u{200D}💻"));
+        assert!(is_emoji("🇫🇷"));
+        // Invisible or direction changing characters
+        assert!(!is_emoji("\u{202E}🎉"));
+        assert!(!is_emoji("🎉\u{200B}"));
+        assert!(!is_emoji("🎉\u{200D}"));
+        assert!(!is_emoji("\u{FE0F}"));
+    }
+
+    #[test]
+    fn schedule_and_dismiss() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let users = user_tests::fill_database(conn);
+            let (admin, user) = (&users[0], &users[1]);
+            let now = Utc::now().naive_utc();
+            assert!(Announcement::create(conn, admin, "  ", None, None).is_err());
+            assert!(Announcement::create(
+                conn,
+                admin,
+                "Backwards",
+                Some(now),
+                Some(now - Duration::hours(1))
+            )
+            .is_err());
+
+            let running = Announcement::create(
+                conn,
+                admin,
+                "Maintenance **tonight**",
+                None,
+                Some(now + Duration::hours(2)),
+            )?;
+            assert!(running.content_html.get().contains("<strong>"));
+            let mut later = Announcement::create(
+                conn,
+                admin,
+                "Next week",
+                Some(now + Duration::days(7)),
+                None,
+            )?;
+            let active = Announcement::active(conn, Some(user))?;
+            assert_eq!(active.len(), 1);
+            assert_eq!(active[0].id, running.id);
+
+            // Announcements that are not running yet can't be dismissed or reacted to
+            assert!(later.dismiss(conn, user).is_err());
+            assert!(later.react(conn, user, "🎉").is_err());
+
+            later.update(conn, "Right now", None, None)?;
+            assert_eq!(Announcement::active(conn, None)?.len(), 2);
+
+            running.dismiss(conn, user)?;
+            running.dismiss(conn, user)?;
+            assert!(running.is_dismissed_by(conn, user)?);
+            assert_eq!(Announcement::active(conn, Some(user))?.len(), 1);
+            assert_eq!(Announcement::active(conn, Some(admin))?.len(), 2);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn reactions() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let users = user_tests::fill_database(conn);
+            let announcement = Announcement::create(conn, &users[0], "Hello", None, None)?;
+            assert!(announcement
+                .toggle_reaction(conn, &users[1], "hello")
+                .is_err());
+            announcement.toggle_reaction(conn, &users[1], "🎉")?;
+            announcement.toggle_reaction(conn, &users[2], "🎉")?;
+            announcement.toggle_reaction(conn, &users[2], "❤️")?;
+            // Reacting twice through the API counts once
+            announcement.react(conn, &users[1], "🎉")?;
+            let reactions = announcement.reactions(conn, Some(&users[1]))?;
+            assert_eq!(reactions.len(), 2);
+            assert_eq!(reactions[0].emoji, "🎉");
+            assert_eq!(reactions[0].count, 2);
+            assert!(reactions[0].me);
+            assert!(!reactions[1].me);
+
+            // Reacting again takes it back
+            announcement.toggle_reaction(conn, &users[2], "❤️")?;
+            assert_eq!(announcement.reactions(conn, None)?.len(), 1);
+
+            // A user can only use a few different emojis
+            for emoji in &["👍", "👀"] {
+                announcement.react(conn, &users[1], emoji)?;
+            }
+            assert!(announcement.react(conn, &users[1], "🚀").is_err());
+            let active = Announcement::active_with_reactions(conn, Some(&users[1]))?;
+            assert_eq!(active.len(), 1);
+            assert_eq!(active[0].1, announcement.reactions(conn, Some(&users[1]))?);
+            Ok(())
+        });
+    }
+}
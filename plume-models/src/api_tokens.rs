@@ -1,4 +1,4 @@
-use crate::{db_conn::DbConn, schema::api_tokens, Error, Result};
+use crate::{db_conn::DbConn, schema::api_tokens, users::User, Error, Result};
 use chrono::NaiveDateTime;
 use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
 use rocket::{
@@ -104,7 +104,13 @@ impl<'a, 'r> FromRequest<'a, 'r> for ApiToken {
                 .guard::<DbConn>()
                 .map_failure(|_| (Status::InternalServerError, TokenError::DbError))?;
             if let Ok(token) = ApiToken::find_by_value(&conn, val) {
-                return Outcome::Success(token);
+                // the tokens of deactivated users stay, but can't be used
+                if let Ok(user) = User::get(&conn, token.user_id) {
+                    if !user.deactivated {
+                        user.mark_active(&conn).ok();
+                        return Outcome::Success(token);
+                    }
+                }
             }
         }
 
@@ -0,0 +1,317 @@
+//! The duration and bitrate of audio files.
+//!
+//! They are read from the headers of MP3, WAV and FLAC files when they are uploaded, and
+//! sent with the attachments of articles, so that players can show them before the file
+//! is downloaded.
+
+use crate::{medias::Media, schema::medias, Connection, Result};
+use diesel::{self, ExpressionMethods, RunQueryDsl};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AudioMetadata {
+    /// In milliseconds
+    pub duration: u32,
+    /// In bits per second
+    pub bitrate: u32,
+}
+
+impl AudioMetadata {
+    fn new(duration_secs: f64, bitrate: u32) -> Option<Self> {
+        if !duration_secs.is_finite() || duration_secs <= 0.0 {
+            return None;
+        }
+        Some(AudioMetadata {
+            duration: (duration_secs * 1000.0).round() as u32,
+            bitrate,
+        })
+    }
+
+    fn from_size(duration_secs: f64, audio_bytes: usize) -> Option<Self> {
+        Self::new(
+            duration_secs,
+            (audio_bytes as f64 * 8.0 / duration_secs).round() as u32,
+        )
+    }
+}
+
+/// The metadata of an audio file, if it can be read.
+pub fn metadata(bytes: &[u8], ext: &str) -> Option<AudioMetadata> {
+    match &*ext.to_lowercase() {
+        "mp3" => mp3(bytes),
+        "wav" => wav(bytes),
+        "flac" => flac(bytes),
+        _ => None,
+    }
+}
+
+/// The media type of an audio file, from its extension.
+pub fn media_type(ext: &str) -> Option<&'static str> {
+    match &*ext.to_lowercase() {
+        "mp3" => Some("audio/mpeg"),
+        "wav" => Some("audio/wav"),
+        "flac" => Some("audio/flac"),
+        _ => None,
+    }
+}
+
+/// Saves the metadata of a media, if it is an audio file.
+pub fn save_metadata(conn: &Connection, media: &Media, bytes: &[u8]) -> Result<Media> {
+    let ext = media
+        .file_path
+        .rsplit_once('.')
+        .map(|(_, ext)| ext)
+        .unwrap_or_default();
+    match metadata(bytes, ext) {
+        Some(meta) => {
+            set_metadata(conn, media, meta)?;
+            Media::get(conn, media.id)
+        }
+        None => Ok(media.clone()),
+    }
+}
+
+pub fn set_metadata(conn: &Connection, media: &Media, meta: AudioMetadata) -> Result<()> {
+    diesel::update(media)
+        .set((
+            medias::duration.eq(meta.duration as i32),
+            medias::bitrate.eq(meta.bitrate as i32),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Formats a duration in milliseconds as an ISO 8601 duration, like `PT192.5S`.
+pub fn iso_duration(duration: u32) -> String {
+    let secs = f64::from(duration) / 1000.0;
+    format!("PT{}S", secs)
+}
+
+/// Formats a duration in milliseconds for humans, like `3:12` or `1:03:12`.
+pub fn format_duration(duration: u32) -> String {
+    let secs = duration / 1000;
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+/// Reads an ISO 8601 duration like `PT3M12S`, in milliseconds.
+pub fn parse_iso_duration(duration: &str) -> Option<u32> {
+    let time = duration.strip_prefix("PT")?;
+    let mut total = 0.0;
+    let mut number = String::new();
+    for c in time.chars() {
+        match c {
+            '0'..='9' | '.' => number.push(c),
+            'H' | 'M' | 'S' => {
+                let value = number.parse::<f64>().ok()?;
+                number.clear();
+                total += value
+                    * match c {
+                        'H' => 3600.0,
+                        'M' => 60.0,
+                        _ => 1.0,
+                    };
+            }
+            _ => return None,
+        }
+    }
+    if !number.is_empty() {
+        return None;
+    }
+    Some((total * 1000.0).round() as u32)
+}
+
+fn wav(bytes: &[u8]) -> Option<AudioMetadata> {
+    if bytes.get(0..4)? != b"RIFF" || bytes.get(8..12)? != b"WAVE" {
+        return None;
+    }
+    let mut pos = 12;
+    let mut byte_rate = None;
+    while let Some(header) = bytes.get(pos..pos + 8) {
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let data = pos + 8;
+        match &header[0..4] {
+            b"fmt " => {
+                let rate = bytes.get(data + 8..data + 12)?;
+                byte_rate = Some(u32::from_le_bytes([rate[0], rate[1], rate[2], rate[3]]));
+            }
+            b"data" => {
+                let byte_rate = byte_rate.filter(|r| *r > 0)?;
+                // The size of streamed files may be unknown
+                let size = size.min(bytes.len() - data);
+                return AudioMetadata::new(size as f64 / f64::from(byte_rate), byte_rate * 8);
+            }
+            _ => {}
+        }
+        // Chunks are aligned on two bytes
+        pos = data + size + size % 2;
+    }
+    None
+}
+
+fn flac(bytes: &[u8]) -> Option<AudioMetadata> {
+    // The first metadata block is always STREAMINFO
+    if bytes.get(0..4)? != b"fLaC" || bytes.get(4)? & 0x7F != 0 {
+        return None;
+    }
+    let info = bytes.get(18..26)?;
+    let sample_rate =
+        (u32::from(info[0]) << 12) | (u32::from(info[1]) << 4) | (u32::from(info[2]) >> 4);
+    let samples = (u64::from(info[3] & 0x0F) << 32)
+        | u64::from(u32::from_be_bytes([info[4], info[5], info[6], info[7]]));
+    if sample_rate == 0 {
+        return None;
+    }
+    AudioMetadata::from_size(samples as f64 / f64::from(sample_rate), bytes.len())
+}
+
+/// The kilobits per second of MPEG 1 and 2 layer III frames.
+const MP3_BITRATES: [[u32; 15]; 2] = [
+    [
+        0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ],
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+];
+
+/// The sample rates of MPEG 1, 2 and 2.5.
+const MP3_SAMPLE_RATES: [[u32; 3]; 3] = [
+    [44100, 48000, 32000],
+    [22050, 24000, 16000],
+    [11025, 12000, 8000],
+];
+
+fn mp3(bytes: &[u8]) -> Option<AudioMetadata> {
+    let mut start = 0;
+    // Skips the ID3v2 tag
+    if bytes.get(0..3)? == b"ID3" {
+        let size = bytes
+            .get(6..10)?
+            .iter()
+            .fold(0usize, |size, b| (size << 7) | usize::from(b & 0x7F));
+        let footer = if bytes.get(5)? & 0x10 != 0 { 10 } else { 0 };
+        start = 10 + size + footer;
+    }
+    let frame = (start..bytes.len().saturating_sub(4))
+        .find(|&i| bytes[i] == 0xFF && bytes[i + 1] & 0xE0 == 0xE0)?;
+    let header = bytes.get(frame..frame + 4)?;
+
+    // MPEG 1 = 0, 2 = 1, 2.5 = 2
+    let version = match (header[1] >> 3) & 0b11 {
+        0b11 => 0,
+        0b10 => 1,
+        0b00 => 2,
+        _ => return None,
+    };
+    // Only layer III
+    if (header[1] >> 1) & 0b11 != 0b01 {
+        return None;
+    }
+    let bitrate = *MP3_BITRATES[version.min(1)].get(usize::from(header[2] >> 4))?;
+    let sample_rate = *MP3_SAMPLE_RATES[version].get(usize::from((header[2] >> 2) & 0b11))?;
+    if bitrate == 0 {
+        return None;
+    }
+    let samples_per_frame = if version == 0 { 1152 } else { 576 };
+    let mono = header[3] >> 6 == 0b11;
+    let audio_bytes = bytes.len() - frame;
+
+    // Files with a variable bitrate tell how many frames they have in their first frame
+    let side_info = match (version == 0, mono) {
+        (true, false) => 32,
+        (true, true) | (false, false) => 17,
+        (false, true) => 9,
+    };
+    let xing = frame + 4 + side_info;
+    if let Some(b"Xing") | Some(b"Info") = bytes.get(xing..xing + 4) {
+        let flags = bytes.get(xing + 4..xing + 8)?;
+        if flags[3] & 1 != 0 {
+            let count = bytes.get(xing + 8..xing + 12)?;
+            let frames = u32::from_be_bytes([count[0], count[1], count[2], count[3]]);
+            let duration =
+                f64::from(frames) * f64::from(samples_per_frame) / f64::from(sample_rate);
+            return AudioMetadata::from_size(duration, audio_bytes);
+        }
+    }
+
+    let duration = audio_bytes as f64 * 8.0 / f64::from(bitrate * 1000);
+    AudioMetadata::new(duration, bitrate * 1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wav_metadata() {
+        // 2 seconds of 8 kHz, 16 bits, mono
+        let mut wav = b"RIFF\0\0\0\0WAVEfmt \x10\0\0\0\x01\0\x01\0".to_vec();
+        wav.extend_from_slice(&8000u32.to_le_bytes());
+        wav.extend_from_slice(&16000u32.to_le_bytes());
+        wav.extend_from_slice(&[2, 0, 16, 0]);
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&32000u32.to_le_bytes());
+        wav.extend(vec![0; 32000]);
+        assert_eq!(
+            metadata(&wav, "WAV"),
+            Some(AudioMetadata {
+                duration: 2000,
+                bitrate: 128_000
+            })
+        );
+        assert_eq!(metadata(b"RIFF", "wav"), None);
+    }
+
+    #[test]
+    fn flac_metadata() {
+        let mut flac = b"fLaC\x80\0\0\x22".to_vec();
+        flac.extend_from_slice(&[0; 10]);
+        // 44.1 kHz, stereo, 16 bits, 441000 samples
+        let info = (44100u64 << 44) | (1 << 41) | (15 << 36) | 441_000;
+        flac.extend_from_slice(&info.to_be_bytes());
+        flac.extend(vec![0; 14]);
+        assert_eq!(
+            metadata(&flac, "flac"),
+            Some(AudioMetadata {
+                duration: 10_000,
+                bitrate: 32
+            })
+        );
+    }
+
+    #[test]
+    fn mp3_metadata() {
+        // A tag, then 128 kbps frames at 44.1 kHz
+        let mut mp3 = b"ID3\x04\0\0\0\0\0\x0A".to_vec();
+        mp3.extend_from_slice(&[0; 10]);
+        for _ in 0..100 {
+            mp3.extend_from_slice(&[0xFF, 0xFB, 0x90, 0x40]);
+            mp3.extend(vec![0; 413]);
+        }
+        let meta = metadata(&mp3, "mp3").unwrap();
+        assert_eq!(meta.bitrate, 128_000);
+        assert_eq!(meta.duration, 2606);
+
+        // A variable bitrate file with 1000 frames
+        let mut vbr = vec![0xFF, 0xFB, 0x90, 0x40];
+        vbr.extend(vec![0; 32]);
+        vbr.extend_from_slice(b"Xing\0\0\0\x01");
+        vbr.extend_from_slice(&1000u32.to_be_bytes());
+        vbr.extend(vec![0; 4000]);
+        assert_eq!(metadata(&vbr, "mp3").unwrap().duration, 26122);
+
+        assert_eq!(metadata(b"not an mp3", "mp3"), None);
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(iso_duration(192_500), "PT192.5S");
+        assert_eq!(parse_iso_duration("PT192.5S"), Some(192_500));
+        assert_eq!(parse_iso_duration("PT1H3M12S"), Some(3_792_000));
+        assert_eq!(parse_iso_duration("P1D"), None);
+        assert_eq!(parse_iso_duration("PT12"), None);
+        assert_eq!(format_duration(192_500), "3:12");
+        assert_eq!(format_duration(3_792_000), "1:03:12");
+    }
+}
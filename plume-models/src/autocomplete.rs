@@ -0,0 +1,250 @@
+use crate::{
+    schema::{comments, follows, mentions, post_authors, users},
+    users::{Role, User},
+    Connection, Result,
+};
+use diesel::{
+    sql_types::{Integer, Text},
+    BoolExpressionMethods, EscapeExpressionMethods, ExpressionMethods, JoinOnDsl,
+    NullableExpressionMethods, QueryDsl, RunQueryDsl, TextExpressionMethods,
+};
+use std::{cmp::Ordering, collections::HashMap};
+
+type Backend = <Connection as diesel::Connection>::Backend;
+
+/// How many of the matching actors the user never interacted with are ranked.
+const CANDIDATES: i64 = 50;
+
+/// Score given to an actor the user follows.
+const FOLLOWING_WEIGHT: i64 = 3;
+/// Score given to an actor following the user.
+const FOLLOWER_WEIGHT: i64 = 1;
+/// Score given each time the user mentioned an actor.
+const MENTION_WEIGHT: i64 = 2;
+
+/// Emoji that can be inserted with a `:shortcode:`.
+///
+/// Plume has no custom emoji, so these are plain Unicode characters.
+pub const EMOJIS: &[(&str, &str)] = &[
+    ("+1", "👍"),
+    ("-1", "👎"),
+    ("angry", "😠"),
+    ("blush", "😊"),
+    ("books", "📚"),
+    ("bulb", "💡"),
+    ("clap", "👏"),
+    ("confused", "😕"),
+    ("cry", "😢"),
+    ("eyes", "👀"),
+    ("fire", "🔥"),
+    ("grin", "😁"),
+    ("heart", "❤️"),
+    ("joy", "😂"),
+    ("laughing", "😆"),
+    ("memo", "📝"),
+    ("ok_hand", "👌"),
+    ("open_mouth", "😮"),
+    ("party", "🎉"),
+    ("pencil", "✏️"),
+    ("pray", "🙏"),
+    ("rocket", "🚀"),
+    ("sad", "😞"),
+    ("scream", "😱"),
+    ("smile", "😄"),
+    ("smiley", "😃"),
+    ("sparkles", "✨"),
+    ("star", "⭐"),
+    ("sunglasses", "😎"),
+    ("sweat_smile", "😅"),
+    ("tada", "🎉"),
+    ("thinking", "🤔"),
+    ("thumbsdown", "👎"),
+    ("thumbsup", "👍"),
+    ("warning", "⚠️"),
+    ("wave", "👋"),
+    ("wink", "😉"),
+    ("wrench", "🔧"),
+];
+
+/// Escapes the wildcards of a `LIKE` pattern.
+pub(crate) fn escape_like(prefix: &str) -> String {
+    prefix
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Local and known remote actors whose name starts with `prefix`, ignoring case,
+/// the ones `user` interacted the most with first.
+pub fn actors(conn: &Connection, user: &User, prefix: &str, limit: i64) -> Result<Vec<User>> {
+    let prefix = prefix.trim_start_matches('@');
+    if prefix.is_empty() {
+        return Ok(vec![]);
+    }
+    let pattern = format!("{}%", escape_like(&prefix.to_lowercase()));
+
+    // All the matching actors the user interacted with are ranked, not only the first ones
+    let mut scores: HashMap<i32, i64> = HashMap::new();
+    for following in follows::table
+        .filter(follows::follower_id.eq(user.id))
+        .filter(follows::following_id.eq_any(matching(user, &pattern)))
+        .select(follows::following_id)
+        .load::<i32>(conn)?
+    {
+        *scores.entry(following).or_insert(0) += FOLLOWING_WEIGHT;
+    }
+    for follower in follows::table
+        .filter(follows::following_id.eq(user.id))
+        .filter(follows::follower_id.eq_any(matching(user, &pattern)))
+        .select(follows::follower_id)
+        .load::<i32>(conn)?
+    {
+        *scores.entry(follower).or_insert(0) += FOLLOWER_WEIGHT;
+    }
+    let mentioned_in_comments = mentions::table
+        .inner_join(comments::table)
+        .filter(comments::author_id.eq(user.id))
+        .filter(mentions::mentioned_id.eq_any(matching(user, &pattern)))
+        .select(mentions::mentioned_id)
+        .load::<i32>(conn)?;
+    let mentioned_in_posts = mentions::table
+        .inner_join(post_authors::table.on(mentions::post_id.eq(post_authors::post_id.nullable())))
+        .filter(post_authors::author_id.eq(user.id))
+        .filter(mentions::mentioned_id.eq_any(matching(user, &pattern)))
+        .select(mentions::mentioned_id)
+        .load::<i32>(conn)?;
+    for mentioned in mentioned_in_comments
+        .into_iter()
+        .chain(mentioned_in_posts.into_iter())
+    {
+        *scores.entry(mentioned).or_insert(0) += MENTION_WEIGHT;
+    }
+    let interacted = scores.keys().cloned().collect::<Vec<_>>();
+
+    let mut ranked = users::table
+        .filter(users::id.eq_any(&interacted))
+        .load::<User>(conn)?;
+    ranked.sort_by(|a, b| {
+        let score = |u: &User| scores.get(&u.id).cloned().unwrap_or(0);
+        score(b).cmp(&score(a)).then_with(|| by_name(a, b))
+    });
+    ranked.truncate(limit as usize);
+
+    // Then the other ones
+    let missing = limit as usize - ranked.len();
+    if missing > 0 {
+        let mut others = users::table
+            .filter(users::id.eq_any(matching(user, &pattern)))
+            .filter(users::id.ne_all(&interacted))
+            .order(users::fqn.asc())
+            .limit(CANDIDATES)
+            .load::<User>(conn)?;
+        others.sort_by(by_name);
+        ranked.extend(others.into_iter().take(missing));
+    }
+    Ok(ranked)
+}
+
+sql_function!(fn lower(text: Text) -> Text);
+
+/// The actors other than `user` whose name matches `pattern`, ignoring case.
+fn matching(user: &User, pattern: &str) -> users::BoxedQuery<'static, Backend, Integer> {
+    users::table
+        .select(users::id)
+        .filter(users::role.ne(Role::Instance as i32))
+        .filter(users::id.ne(user.id))
+        .filter(
+            lower(users::username)
+                .like(pattern.to_owned())
+                .escape('\\')
+                .or(lower(users::display_name)
+                    .like(pattern.to_owned())
+                    .escape('\\'))
+                .or(lower(users::fqn).like(pattern.to_owned()).escape('\\')),
+        )
+        .into_boxed()
+}
+
+/// Local actors first, then the ones with the shortest address.
+fn by_name(a: &User, b: &User) -> Ordering {
+    b.is_local()
+        .cmp(&a.is_local())
+        .then(a.fqn.len().cmp(&b.fqn.len()))
+        .then(a.fqn.cmp(&b.fqn))
+}
+
+/// Emoji whose shortcode starts with `prefix`.
+pub fn emojis(prefix: &str, limit: usize) -> Vec<(&'static str, &'static str)> {
+    let prefix = prefix.trim_start_matches(':').to_lowercase();
+    EMOJIS
+        .iter()
+        .filter(|(shortcode, _)| shortcode.starts_with(&prefix))
+        .take(limit)
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        follows::{Follow, NewFollow},
+        inbox::tests::fill_database,
+        tests::db,
+        users::NewUser,
+        Error,
+    };
+    use diesel::Connection;
+
+    #[test]
+    fn rank_actors() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (_, users, _) = fill_database(conn);
+            let other_user = NewUser::new_local(
+                conn,
+                "user2".to_owned(),
+                "Another user".to_owned(),
+                Role::Normal,
+                "",
+                "user2@example.com".to_owned(),
+                None,
+            )?;
+            assert!(actors(conn, &users[0], "@", 10)?.is_empty());
+            assert!(actors(conn, &users[0], "%", 10)?.is_empty());
+
+            let found = actors(conn, &users[0], "@us", 10)?;
+            assert_eq!(
+                found.iter().map(|u| u.id).collect::<Vec<_>>(),
+                vec![users[1].id, other_user.id]
+            );
+
+            Follow::insert(
+                conn,
+                NewFollow {
+                    follower_id: users[0].id,
+                    following_id: other_user.id,
+                    ap_url: String::new(),
+                },
+            )?;
+            let found = actors(conn, &users[0], "@us", 10)?;
+            assert_eq!(
+                found.iter().map(|u| u.id).collect::<Vec<_>>(),
+                vec![other_user.id, users[1].id]
+            );
+            assert_eq!(actors(conn, &users[0], "us", 1)?.len(), 1);
+            assert_eq!(actors(conn, &users[0], "US", 1)?[0].id, other_user.id);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn find_emojis() {
+        assert_eq!(
+            emojis(":thu", 5),
+            vec![("thumbsdown", "👎"), ("thumbsup", "👍")]
+        );
+        assert_eq!(emojis("smile", 5), vec![("smile", "😄")]);
+        assert!(emojis(":nothing", 5).is_empty());
+    }
+}
@@ -0,0 +1,535 @@
+//! Archives of a whole instance, for `plm backup`.
+//!
+//! An archive is a ZIP file containing a `manifest.json` that describes it, the database (a
+//! copy of the SQLite file, or a `pg_dump` of the PostgreSQL database), the media and the
+//! search index. Media stored in S3 are not included: the bucket has to be backed up
+//! separately.
+//!
+//! An archive can only be restored with the same database engine, and by a version of Plume
+//! that knows all the migrations that were run on its database. Everything is extracted next to
+//! where it goes before the database is replaced, and the media and search index are swapped
+//! with the extracted copies last: a restore that fails midway leaves the instance as it was.
+//!
+//! Archives and database dumps are only readable by the user running Plume.
+
+use crate::{migrations::IMPORTED_MIGRATIONS, Connection, Error, Result, CONFIG};
+use chrono::{NaiveDateTime, Utc};
+use migrations_internals::MigrationConnection;
+use plume_common::utils::random_hex;
+#[cfg(feature = "postgres")]
+use std::process::Command;
+use std::{
+    env,
+    fs::{self, DirBuilder, File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+};
+#[cfg(feature = "postgres")]
+use url::Url;
+use walkdir::WalkDir;
+use zip::{result::ZipError, write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+/// Changes when archives are made differently.
+const FORMAT: u32 = 1;
+
+const MANIFEST: &str = "manifest.json";
+const MEDIA: &str = "media";
+const SEARCH_INDEX: &str = "search_index";
+
+#[cfg(feature = "postgres")]
+const ENGINE: &str = "postgres";
+#[cfg(feature = "postgres")]
+const DATABASE: &str = "database.sql";
+
+#[cfg(feature = "sqlite")]
+const ENGINE: &str = "sqlite";
+#[cfg(feature = "sqlite")]
+const DATABASE: &str = "database.sqlite";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format: u32,
+    /// The version of Plume that made the archive
+    pub plume_version: String,
+    /// `postgres` or `sqlite`
+    pub engine: String,
+    /// The last migration that was run on the database
+    pub migration: Option<String>,
+    pub creation_date: NaiveDateTime,
+    /// Whether the media are in the archive, which they are not when they are stored in S3
+    pub media: bool,
+}
+
+impl Manifest {
+    fn current(conn: &Connection) -> Result<Self> {
+        Ok(Manifest {
+            format: FORMAT,
+            plume_version: env!("CARGO_PKG_VERSION").to_owned(),
+            engine: ENGINE.to_owned(),
+            migration: conn.latest_run_migration_version()?,
+            creation_date: Utc::now().naive_utc(),
+            media: CONFIG.s3.is_none(),
+        })
+    }
+
+    /// Why this archive can't be restored by this version of Plume, if it can't.
+    pub fn incompatibility(&self) -> Option<String> {
+        if self.format != FORMAT {
+            return Some(format!(
+                "this archive was made by Plume {}, which archives differently",
+                self.plume_version
+            ));
+        }
+        if self.engine != ENGINE {
+            return Some(format!(
+                "this archive has a {} database, but this version of Plume uses {}",
+                self.engine, ENGINE
+            ));
+        }
+        match self.migration {
+            Some(ref migration) if !IMPORTED_MIGRATIONS.contains(migration) => Some(format!(
+                "the database was migrated by a newer version of Plume ({}), upgrade this one first",
+                self.plume_version
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Writes an archive of the instance to `destination`.
+///
+/// The database is copied consistently, but media or articles written in the meantime may be
+/// missing from it: putting the instance in maintenance first avoids that.
+pub fn create(conn: &Connection, destination: &Path) -> Result<Manifest> {
+    let manifest = Manifest::current(conn)?;
+    let options = FileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .large_file(true);
+    let mut zip = ZipWriter::new(create_private(destination)?);
+    zip.start_file(MANIFEST, options).map_err(zip_error)?;
+    serde_json::to_writer_pretty(&mut zip, &manifest)?;
+
+    let dir = PrivateDir::new()?;
+    let dump = dir.join(DATABASE);
+    dump_database(&dump)?;
+    add_file(&mut zip, &dump, DATABASE, options)?;
+    drop(dir);
+
+    if manifest.media {
+        add_dir(&mut zip, Path::new(&CONFIG.media_directory), MEDIA, options)?;
+    }
+    add_dir(
+        &mut zip,
+        Path::new(&CONFIG.search_index),
+        SEARCH_INDEX,
+        options,
+    )?;
+    zip.finish().map_err(zip_error)?;
+    Ok(manifest)
+}
+
+/// The manifest of an archive.
+pub fn manifest(archive: &Path) -> Result<Manifest> {
+    let mut archive = open(archive)?;
+    let manifest = archive.by_name(MANIFEST).map_err(zip_error)?;
+    serde_json::from_reader(manifest).map_err(Error::from)
+}
+
+/// What restoring an archive would replace: the database if it was migrated already, and the
+/// media and search index if they are not empty.
+pub fn existing_data(conn: Option<&Connection>) -> Vec<&'static str> {
+    let mut existing = vec![];
+    let migrated = conn
+        .and_then(|conn| conn.latest_run_migration_version().ok())
+        .flatten()
+        .is_some();
+    if migrated {
+        existing.push("the database");
+    }
+    if !is_empty(Path::new(&CONFIG.media_directory)) {
+        existing.push("the media");
+    }
+    if !is_empty(Path::new(&CONFIG.search_index)) {
+        existing.push("the search index");
+    }
+    existing
+}
+
+/// Replaces the database, the media and the search index with the ones of an archive.
+///
+/// Plume must be stopped, and the archive compatible with this version.
+pub fn restore(archive: &Path) -> Result<Manifest> {
+    let manifest = manifest(archive)?;
+    if manifest.incompatibility().is_some() {
+        return Err(Error::InvalidValue);
+    }
+    let mut archive = open(archive)?;
+
+    let search_index = Path::new(&CONFIG.search_index);
+    let media = Path::new(&CONFIG.media_directory);
+    let staged_index = sibling(search_index, "restoring");
+    let staged_media = sibling(media, "restoring");
+    let staged = extract_dir(&mut archive, SEARCH_INDEX, &staged_index).and_then(|_| {
+        if manifest.media {
+            extract_dir(&mut archive, MEDIA, &staged_media)?;
+        }
+        let dir = PrivateDir::new()?;
+        let dump = dir.join(DATABASE);
+        extract_file(&mut archive, DATABASE, &dump)?;
+        load_database(&dump)
+    });
+    if let Err(err) = staged {
+        fs::remove_dir_all(&staged_index).ok();
+        fs::remove_dir_all(&staged_media).ok();
+        return Err(err);
+    }
+
+    replace_dir(&staged_index, search_index)?;
+    if manifest.media {
+        replace_dir(&staged_media, media)?;
+    }
+    Ok(manifest)
+}
+
+/// Puts `staged` in place of `dir`, and removes the previous one.
+fn replace_dir(staged: &Path, dir: &Path) -> Result<()> {
+    let previous = sibling(dir, "previous");
+    if dir.exists() {
+        fs::rename(dir, &previous)?;
+    }
+    if let Err(err) = fs::rename(staged, dir) {
+        fs::rename(&previous, dir).ok();
+        return Err(err.into());
+    }
+    if previous.exists() {
+        fs::remove_dir_all(&previous)?;
+    }
+    Ok(())
+}
+
+/// A new path next to `path`, on the same file system so that it can be renamed to it.
+fn sibling(path: &Path, purpose: &str) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!("{}.{}-{}", name, purpose, random_hex()))
+}
+
+#[cfg(feature = "postgres")]
+fn dump_database(destination: &Path) -> Result<()> {
+    run(database_tool("pg_dump")?
+        .args(&["--no-owner", "--no-privileges", "--clean", "--if-exists"])
+        .arg("--file")
+        .arg(destination))
+}
+
+#[cfg(feature = "sqlite")]
+fn dump_database(destination: &Path) -> Result<()> {
+    crate::db_conn::backup(destination)
+}
+
+#[cfg(feature = "postgres")]
+fn load_database(dump: &Path) -> Result<()> {
+    run(database_tool("psql")?
+        .args(&[
+            "--quiet",
+            "--single-transaction",
+            "--set",
+            "ON_ERROR_STOP=1",
+        ])
+        .arg("--file")
+        .arg(dump))
+}
+
+#[cfg(feature = "sqlite")]
+fn load_database(dump: &Path) -> Result<()> {
+    let database = Path::new(&CONFIG.database_url);
+    let staged = sibling(database, "restoring");
+    fs::copy(dump, &staged)?;
+    // The journal of the previous database doesn't match the restored one
+    for journal in &["-wal", "-shm"] {
+        fs::remove_file(format!("{}{}", CONFIG.database_url, journal)).ok();
+    }
+    fs::rename(&staged, database)?;
+    Ok(())
+}
+
+/// The parameters of libpq that can be in the query of the database URL, and the environment
+/// variables they can be given with.
+#[cfg(feature = "postgres")]
+const LIBPQ_PARAMETERS: &[(&str, &str)] = &[
+    ("host", "PGHOST"),
+    ("port", "PGPORT"),
+    ("dbname", "PGDATABASE"),
+    ("user", "PGUSER"),
+    ("password", "PGPASSWORD"),
+    ("sslmode", "PGSSLMODE"),
+    ("sslcert", "PGSSLCERT"),
+    ("sslkey", "PGSSLKEY"),
+    ("sslrootcert", "PGSSLROOTCERT"),
+    ("connect_timeout", "PGCONNECT_TIMEOUT"),
+    ("application_name", "PGAPPNAME"),
+    ("options", "PGOPTIONS"),
+];
+
+/// A PostgreSQL tool, connecting to the database of the instance. The URL is given in the
+/// environment, not on the command line where other users could see its password.
+#[cfg(feature = "postgres")]
+fn database_tool(program: &str) -> Result<Command> {
+    let url = Url::parse(&CONFIG.database_url)?;
+    let mut command = Command::new(program);
+    if let Some(host) = url.host_str() {
+        command.env("PGHOST", host.trim_start_matches('[').trim_end_matches(']'));
+    }
+    if let Some(port) = url.port() {
+        command.env("PGPORT", port.to_string());
+    }
+    if !url.username().is_empty() {
+        command.env("PGUSER", percent_decode(url.username()));
+    }
+    if let Some(password) = url.password() {
+        command.env("PGPASSWORD", percent_decode(password));
+    }
+    let database = url.path().trim_start_matches('/');
+    if !database.is_empty() {
+        command.env("PGDATABASE", percent_decode(database));
+    }
+    for (key, value) in url.query_pairs() {
+        if let Some((_, var)) = LIBPQ_PARAMETERS.iter().find(|(param, _)| *param == key) {
+            command.env(var, value.as_ref());
+        }
+    }
+    Ok(command)
+}
+
+#[cfg(feature = "postgres")]
+fn percent_decode(encoded: &str) -> String {
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut rest = encoded.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let decoded = tail
+            .get(..2)
+            .filter(|_| byte == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match decoded {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Runs a database tool.
+#[cfg(feature = "postgres")]
+fn run(command: &mut Command) -> Result<()> {
+    let status = command.status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::Io(io::Error::new(
+            io::ErrorKind::Other,
+            format!("{:?} failed ({})", command.get_program(), status),
+        )))
+    }
+}
+
+fn add_file(
+    zip: &mut ZipWriter<File>,
+    path: &Path,
+    name: &str,
+    options: FileOptions,
+) -> Result<()> {
+    zip.start_file(name, options).map_err(zip_error)?;
+    io::copy(&mut File::open(path)?, zip)?;
+    Ok(())
+}
+
+fn add_dir(
+    zip: &mut ZipWriter<File>,
+    dir: &Path,
+    prefix: &str,
+    options: FileOptions,
+) -> Result<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+    for entry in WalkDir::new(dir) {
+        let entry = entry.map_err(io::Error::from)?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry
+            .path()
+            .strip_prefix(dir)
+            .map_err(|_| Error::InvalidValue)?;
+        add_file(zip, entry.path(), &entry_name(prefix, relative), options)?;
+    }
+    Ok(())
+}
+
+/// The name of a file in the archive, with `/` as separator on all platforms.
+fn entry_name(prefix: &str, relative: &Path) -> String {
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .fold(prefix.to_owned(), |name, part| name + "/" + &part)
+}
+
+fn extract_file(archive: &mut ZipArchive<File>, name: &str, destination: &Path) -> Result<()> {
+    let mut file = archive.by_name(name).map_err(zip_error)?;
+    io::copy(&mut file, &mut create_private(destination)?)?;
+    Ok(())
+}
+
+/// Extracts the files under `prefix`, ignoring the ones whose path would leave `destination`.
+fn extract_dir(archive: &mut ZipArchive<File>, prefix: &str, destination: &Path) -> Result<()> {
+    fs::create_dir_all(destination)?;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(zip_error)?;
+        let path = match file
+            .enclosed_name()
+            .and_then(|path| path.strip_prefix(prefix).ok())
+        {
+            Some(path) if file.is_file() => destination.join(path),
+            _ => continue,
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        io::copy(&mut file, &mut File::create(path)?)?;
+    }
+    Ok(())
+}
+
+fn open(archive: &Path) -> Result<ZipArchive<File>> {
+    ZipArchive::new(File::open(archive)?).map_err(zip_error)
+}
+
+fn is_empty(dir: &Path) -> bool {
+    fs::read_dir(dir).map_or(true, |mut entries| entries.next().is_none())
+}
+
+/// Creates a file only the user running Plume can read.
+fn create_private(path: &Path) -> Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    Ok(options.open(path)?)
+}
+
+/// A temporary directory only the user running Plume can open, removed when dropped.
+struct PrivateDir(PathBuf);
+
+impl PrivateDir {
+    fn new() -> Result<Self> {
+        let path = env::temp_dir().join(format!("plume-backup-{}", random_hex()));
+        let mut builder = DirBuilder::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        builder.create(&path)?;
+        Ok(PrivateDir(path))
+    }
+
+    fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for PrivateDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+fn zip_error(e: ZipError) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::Other, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::db;
+    use diesel::Connection;
+
+    #[test]
+    fn compatibility() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let manifest = Manifest::current(conn)?;
+            assert!(manifest.migration.is_some());
+            assert_eq!(manifest.incompatibility(), None);
+
+            let other_format = Manifest {
+                format: FORMAT + 1,
+                ..manifest.clone()
+            };
+            assert!(other_format.incompatibility().is_some());
+            let other_engine = Manifest {
+                engine: "mysql".to_owned(),
+                ..manifest.clone()
+            };
+            assert!(other_engine.incompatibility().is_some());
+            let newer = Manifest {
+                migration: Some("2999-01-01-000000_from_the_future".to_owned()),
+                ..manifest
+            };
+            assert!(newer.incompatibility().is_some());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn entry_names() {
+        assert_eq!(
+            entry_name(
+                MEDIA,
+                Path::new("static/media/a.png")
+                    .strip_prefix("static/media")
+                    .unwrap()
+            ),
+            "media/a.png"
+        );
+        assert_eq!(
+            entry_name(SEARCH_INDEX, Path::new("x/meta.json")),
+            "search_index/x/meta.json"
+        );
+    }
+
+    #[test]
+    fn replace_dirs() {
+        let root = env::temp_dir().join(format!("plume-restore-{}", random_hex()));
+        let dir = root.join("media");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("old.png"), b"old").unwrap();
+        let staged = sibling(&dir, "restoring");
+        fs::create_dir_all(&staged).unwrap();
+        fs::write(staged.join("new.png"), b"new").unwrap();
+
+        replace_dir(&staged, &dir).unwrap();
+        assert!(!dir.join("old.png").exists());
+        assert!(dir.join("new.png").exists());
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 1);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(feature = "postgres")]
+    #[test]
+    fn encoded_passwords() {
+        assert_eq!(percent_decode("p%40ss%2Fw0rd+%"), "p@ss/w0rd+%");
+    }
+}
@@ -0,0 +1,598 @@
+//! Subscriptions to the blocklists that other instances or communities publish.
+//!
+//! A source is the URL of a CSV file, like the ones Mastodon exports, or of a JSON list of
+//! domain blocks. Sources are synced regularly: the domains they list get blocked, and the
+//! ones they stop listing are unblocked again, unless a moderator blocked them by hand.
+//! Like on Mastodon, blocking a domain also blocks its subdomains. Moderators can also make
+//! exceptions for some domains, that subscriptions never block.
+
+use crate::{
+    db_conn::write_transaction,
+    instance::{Instance, NewInstance},
+    outgoing::{self, Limits},
+    safe_string::SafeString,
+    schema::{blocklist_entries, blocklist_exceptions, blocklist_sources, instances},
+    severed_relationships::Severance,
+    Connection, Error, Result,
+};
+use chrono::{NaiveDateTime, Utc};
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use tracing::warn;
+use url::Url;
+
+/// Blocklists bigger than that are refused, rather than truncated and missing some domains.
+const MAX_BLOCKLIST_SIZE: u64 = 5 * 1024 * 1024;
+
+/// In seconds
+const FETCH_TIMEOUT: u64 = 30;
+
+#[derive(Clone, Queryable, Identifiable)]
+#[table_name = "blocklist_sources"]
+pub struct BlocklistSource {
+    pub id: i32,
+    pub url: String,
+    pub last_sync: Option<NaiveDateTime>,
+    /// Why the last sync failed, if it did
+    pub last_error: Option<String>,
+    pub creation_date: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "blocklist_sources"]
+pub struct NewBlocklistSource {
+    pub url: String,
+}
+
+/// A domain listed by a source, with the reason it gives.
+#[derive(Clone, Queryable, Identifiable)]
+#[table_name = "blocklist_entries"]
+pub struct BlocklistEntry {
+    pub id: i32,
+    pub source_id: i32,
+    pub domain: String,
+    pub reason: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "blocklist_entries"]
+pub struct NewBlocklistEntry {
+    pub source_id: i32,
+    pub domain: String,
+    pub reason: String,
+}
+
+/// A domain that is not blocked, even if a source lists it.
+#[derive(Clone, Queryable, Identifiable)]
+#[table_name = "blocklist_exceptions"]
+pub struct BlocklistException {
+    pub id: i32,
+    pub domain: String,
+    pub creation_date: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "blocklist_exceptions"]
+pub struct NewBlocklistException {
+    pub domain: String,
+}
+
+impl BlocklistSource {
+    insert!(blocklist_sources, NewBlocklistSource);
+    get!(blocklist_sources);
+
+    pub fn list(conn: &Connection) -> Result<Vec<Self>> {
+        blocklist_sources::table
+            .order(blocklist_sources::url.asc())
+            .load::<Self>(conn)
+            .map_err(Error::from)
+    }
+
+    /// Subscribes to the blocklist at `url`. It is only applied once it has been synced.
+    pub fn add(conn: &Connection, url: &str) -> Result<Self> {
+        let url = Url::parse(url.trim())?;
+        if url.scheme() != "https" && url.scheme() != "http" {
+            return Err(Error::InvalidValue);
+        }
+        Self::insert(
+            conn,
+            NewBlocklistSource {
+                url: url.to_string(),
+            },
+        )
+    }
+
+    pub fn entries(&self, conn: &Connection) -> Result<Vec<BlocklistEntry>> {
+        blocklist_entries::table
+            .filter(blocklist_entries::source_id.eq(self.id))
+            .order(blocklist_entries::domain.asc())
+            .load::<BlocklistEntry>(conn)
+            .map_err(Error::from)
+    }
+
+    pub fn count_entries(&self, conn: &Connection) -> Result<i64> {
+        blocklist_entries::table
+            .filter(blocklist_entries::source_id.eq(self.id))
+            .count()
+            .get_result(conn)
+            .map_err(Error::from)
+    }
+
+    /// Unsubscribes, unblocking the domains that only this source listed.
+    pub fn delete(&self, conn: &Connection) -> Result<()> {
+        diesel::delete(blocklist_entries::table.filter(blocklist_entries::source_id.eq(self.id)))
+            .execute(conn)?;
+        diesel::delete(self).execute(conn)?;
+        apply(conn)
+    }
+
+    /// Downloads the blocklist again, replaces the entries of this source, and applies them.
+    ///
+    /// The result is remembered to be shown to the administrators. A list that is empty or
+    /// that can't be read is refused, and the previous entries are kept, as it is more
+    /// likely to be a broken file than a source that doesn't block anything anymore.
+    pub fn sync(&self, conn: &Connection) -> Result<usize> {
+        let entries = fetch(&self.url).and_then(|body| match parse(&body) {
+            entries if entries.is_empty() => {
+                Err("The blocklist is empty, or its format is not supported".to_owned())
+            }
+            entries => Ok(entries),
+        });
+        match entries {
+            Ok(entries) => {
+                let count = entries.len();
+                write_transaction::<_, Error, _>(conn, || {
+                    self.replace_entries(conn, entries)?;
+                    diesel::update(self)
+                        .set((
+                            blocklist_sources::last_sync.eq(Some(Utc::now().naive_utc())),
+                            blocklist_sources::last_error.eq(None::<String>),
+                        ))
+                        .execute(conn)?;
+                    apply(conn)
+                })?;
+                Ok(count)
+            }
+            Err(e) => {
+                diesel::update(self)
+                    .set(blocklist_sources::last_error.eq(Some(e)))
+                    .execute(conn)?;
+                Err(Error::Request)
+            }
+        }
+    }
+
+    fn replace_entries(&self, conn: &Connection, entries: Vec<(String, String)>) -> Result<()> {
+        diesel::delete(blocklist_entries::table.filter(blocklist_entries::source_id.eq(self.id)))
+            .execute(conn)?;
+        for (domain, reason) in entries {
+            diesel::insert_into(blocklist_entries::table)
+                .values(NewBlocklistEntry {
+                    source_id: self.id,
+                    domain,
+                    reason,
+                })
+                .execute(conn)?;
+        }
+        Ok(())
+    }
+}
+
+impl BlocklistEntry {
+    /// The sources that list `domain`, with their reasons.
+    pub fn find_by_domain(conn: &Connection, domain: &str) -> Result<Vec<(Self, String)>> {
+        blocklist_entries::table
+            .inner_join(blocklist_sources::table)
+            .filter(blocklist_entries::domain.eq(domain))
+            .select((blocklist_entries::all_columns, blocklist_sources::url))
+            .load::<(Self, String)>(conn)
+            .map_err(Error::from)
+    }
+
+    pub fn is_excepted(&self, exceptions: &[BlocklistException]) -> bool {
+        exceptions.iter().any(|e| e.domain == self.domain)
+    }
+
+    /// Tells if a subscription blocks `host` because it lists one of its parent domains.
+    ///
+    /// The subdomains that are not known yet are not blocked in the database until the next
+    /// sync, so they have to be checked when their activities come in.
+    pub fn blocks_subdomain(conn: &Connection, host: &str) -> Result<bool> {
+        let parents = parent_domains(host);
+        if parents.is_empty() {
+            return Ok(false);
+        }
+        let excepted = blocklist_exceptions::table
+            .filter(blocklist_exceptions::domain.eq(host))
+            .count()
+            .get_result::<i64>(conn)?
+            > 0;
+        if excepted {
+            return Ok(false);
+        }
+        blocklist_entries::table
+            .filter(blocklist_entries::domain.eq_any(parents))
+            .count()
+            .get_result::<i64>(conn)
+            .map(|count| count > 0)
+            .map_err(Error::from)
+    }
+}
+
+impl BlocklistException {
+    get!(blocklist_exceptions);
+
+    pub fn list(conn: &Connection) -> Result<Vec<Self>> {
+        blocklist_exceptions::table
+            .order(blocklist_exceptions::domain.asc())
+            .load::<Self>(conn)
+            .map_err(Error::from)
+    }
+
+    /// Makes sure subscriptions never block `domain`, unblocking it if needed.
+    pub fn add(conn: &Connection, domain: &str) -> Result<()> {
+        let domain = normalize_domain(domain).ok_or(Error::InvalidValue)?;
+        let exists = blocklist_exceptions::table
+            .filter(blocklist_exceptions::domain.eq(&domain))
+            .count()
+            .get_result::<i64>(conn)?
+            > 0;
+        if !exists {
+            diesel::insert_into(blocklist_exceptions::table)
+                .values(NewBlocklistException { domain })
+                .execute(conn)?;
+        }
+        apply(conn)
+    }
+
+    /// Lets subscriptions block this domain again.
+    pub fn delete(&self, conn: &Connection) -> Result<()> {
+        diesel::delete(self).execute(conn)?;
+        apply(conn)
+    }
+}
+
+/// Syncs all the sources, and applies their entries.
+pub fn sync_all(conn: &Connection) -> Result<()> {
+    for source in BlocklistSource::list(conn)? {
+        if let Err(e) = source.sync(conn) {
+            warn!("Failed to sync the blocklist at {}: {:?}", source.url, e);
+        }
+    }
+    Ok(())
+}
+
+/// Blocks the domains listed by the sources and the known instances on their subdomains,
+/// and unblocks the ones that were blocked because of them and that are not listed anymore.
+///
+/// Instances blocked by hand are never unblocked here.
+pub fn apply(conn: &Connection) -> Result<()> {
+    let exceptions = BlocklistException::list(conn)?
+        .into_iter()
+        .map(|e| e.domain)
+        .collect::<HashSet<_>>();
+    let local = Instance::get_local()?.public_domain;
+    let listed = blocklist_entries::table
+        .select(blocklist_entries::domain)
+        .distinct()
+        .load::<String>(conn)?
+        .into_iter()
+        .filter(|domain| !exceptions.contains(domain) && *domain != local)
+        .collect::<HashSet<_>>();
+    let is_listed = |domain: &str| {
+        !exceptions.contains(domain)
+            && domain != local
+            && (listed.contains(domain)
+                || parent_domains(domain)
+                    .iter()
+                    .any(|parent| listed.contains(parent)))
+    };
+
+    for instance in instances::table
+        .filter(instances::blocked_by_subscription.eq(true))
+        .load::<Instance>(conn)?
+    {
+        if !is_listed(&instance.public_domain) {
+            diesel::update(&instance)
+                .set((
+                    instances::blocked.eq(false),
+                    instances::blocked_by_subscription.eq(false),
+                ))
+                .execute(conn)?;
+        }
+    }
+
+    let subdomains = instances::table
+        .filter(instances::blocked.eq(false))
+        .filter(instances::local.eq(false))
+        .load::<Instance>(conn)?
+        .into_iter()
+        .filter(|instance| {
+            !listed.contains(&instance.public_domain) && is_listed(&instance.public_domain)
+        })
+        .map(|instance| instance.public_domain);
+    let to_block = listed.iter().cloned().chain(subdomains).collect::<Vec<_>>();
+
+    for domain in to_block {
+        let instance = Instance::find_by_domain(conn, &domain).or_else(|_| {
+            Instance::insert(
+                conn,
+                NewInstance {
+                    name: domain.clone(),
+                    public_domain: domain.clone(),
+                    local: false,
+                    long_description: SafeString::new(""),
+                    short_description: SafeString::new(""),
+                    default_license: String::new(),
+                    open_registrations: true,
+                    short_description_html: String::new(),
+                    long_description_html: String::new(),
+                },
+            )
+        })?;
+        if !instance.blocked {
+            diesel::update(&instance)
+                .set((
+                    instances::blocked.eq(true),
+                    instances::blocked_by_subscription.eq(true),
+                ))
+                .execute(conn)?;
+            Severance::sever_instance(conn, &instance)?;
+        }
+    }
+    Ok(())
+}
+
+/// Downloads a blocklist, from a public address only.
+fn fetch(url: &str) -> std::result::Result<String, String> {
+    let download = outgoing::get(url, &Limits::new(FETCH_TIMEOUT, MAX_BLOCKLIST_SIZE)).map_err(
+        |e| match e {
+            Error::Url => "This address is not public".to_owned(),
+            Error::InvalidValue => "The blocklist is too large".to_owned(),
+            e => format!("The blocklist couldn't be downloaded: {:?}", e),
+        },
+    )?;
+    String::from_utf8(download.body).map_err(|_| "The blocklist is not valid text".to_owned())
+}
+
+/// The domains of which `domain` is a subdomain, without the top-level one.
+fn parent_domains(domain: &str) -> Vec<String> {
+    let labels = domain.split('.').collect::<Vec<_>>();
+    (1..labels.len().saturating_sub(1))
+        .map(|i| labels[i..].join("."))
+        .collect()
+}
+
+/// The domains listed in a blocklist, with the reasons given for each of them.
+///
+/// JSON blocklists are lists of domains, or of objects with a `domain`, like the ones of the
+/// Mastodon API. Anything else is read as CSV, with an optional header: the domain is in the
+/// first column, unless a `domain` column is named. Blocks with a severity other than
+/// `suspend` only limit instances, and are ignored, as are obfuscated domains.
+fn parse(body: &str) -> Vec<(String, String)> {
+    let body = body.trim_start_matches('\u{feff}').trim();
+    let entries = if body.starts_with('[') {
+        parse_json(body)
+    } else {
+        parse_csv(body)
+    };
+    // Deduplicated, the first reason is kept
+    let mut domains = BTreeMap::new();
+    for (domain, reason) in entries {
+        if let Some(domain) = normalize_domain(&domain) {
+            domains.entry(domain).or_insert(reason);
+        }
+    }
+    domains.into_iter().collect()
+}
+
+fn parse_json(body: &str) -> Vec<(String, String)> {
+    let blocks = match serde_json::from_str::<Value>(body) {
+        Ok(Value::Array(blocks)) => blocks,
+        _ => return vec![],
+    };
+    blocks
+        .iter()
+        .filter_map(|block| match block {
+            Value::String(domain) => Some((domain.clone(), String::new())),
+            Value::Object(block) => {
+                let severity = block.get("severity").and_then(Value::as_str);
+                if severity.map_or(false, |s| s != "suspend") {
+                    return None;
+                }
+                let reason = ["comment", "public_comment", "reason"]
+                    .iter()
+                    .find_map(|key| block.get(*key).and_then(Value::as_str))
+                    .unwrap_or_default();
+                Some((block.get("domain")?.as_str()?.to_owned(), reason.to_owned()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn parse_csv(body: &str) -> Vec<(String, String)> {
+    let mut lines = body
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .peekable();
+    let header = lines
+        .peek()
+        .map(|line| csv_cells(line))
+        .filter(|cells| {
+            cells.first().map_or(false, |c| {
+                c.starts_with('#') || c.eq_ignore_ascii_case("domain")
+            })
+        })
+        .map(|cells| {
+            cells
+                .into_iter()
+                .map(|c| c.trim_start_matches('#').to_lowercase())
+                .collect::<Vec<_>>()
+        });
+    if header.is_some() {
+        lines.next();
+    }
+    let column = |names: &[&str]| {
+        header
+            .as_ref()
+            .and_then(|header| header.iter().position(|c| names.contains(&c.as_str())))
+    };
+    let domain_column = column(&["domain"]).unwrap_or(0);
+    let severity_column = column(&["severity"]);
+    let reason_column = column(&["public_comment", "comment", "reason"]);
+
+    lines
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let cells = csv_cells(line);
+            let severity = severity_column.and_then(|i| cells.get(i));
+            if severity.map_or(false, |s| !s.is_empty() && s != "suspend") {
+                return None;
+            }
+            let reason = reason_column
+                .and_then(|i| cells.get(i))
+                .cloned()
+                .unwrap_or_default();
+            Some((cells.get(domain_column)?.clone(), reason))
+        })
+        .collect()
+}
+
+/// The cells of a CSV line, that may be quoted.
+fn csv_cells(line: &str) -> Vec<String> {
+    let mut cells = vec![];
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                cell.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => cells.push(std::mem::take(&mut cell).trim().to_owned()),
+            c => cell.push(c),
+        }
+    }
+    cells.push(cell.trim().to_owned());
+    cells
+}
+
+fn normalize_domain(domain: &str) -> Option<String> {
+    let domain = domain.trim().trim_end_matches('.').to_lowercase();
+    if domain.is_empty()
+        || domain.contains(|c: char| c.is_whitespace() || ['/', '*', '@', ':'].contains(&c))
+    {
+        None
+    } else {
+        Some(domain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{instance::tests as instance_tests, tests::db};
+    use diesel::Connection;
+
+    #[test]
+    fn csv_blocklists() {
+        let mastodon = "#domain,#severity,#reject_media,#reject_reports,#public_comment,#obfuscate
+spam.example,suspend,true,true,\"Spam, and harassment\",false
+Loud.Example,silence,false,false,Too loud,false
+hidden.*.example,suspend,false,false,,true
+spam.example,suspend,false,false,Again,false
+";
+        assert_eq!(
+            parse(mastodon),
+            vec![("spam.example".to_owned(), "Spam, and harassment".to_owned())]
+        );
+        assert_eq!(
+            parse("bad.example\n\nworse.example.\n"),
+            vec![
+                ("bad.example".to_owned(), String::new()),
+                ("worse.example".to_owned(), String::new())
+            ]
+        );
+    }
+
+    #[test]
+    fn json_blocklists() {
+        let api = r#"[
+            {"domain": "spam.example", "digest": "…", "severity": "suspend", "comment": "Spam"},
+            {"domain": "loud.example", "severity": "silence", "comment": "Too loud"}
+        ]"#;
+        assert_eq!(
+            parse(api),
+            vec![("spam.example".to_owned(), "Spam".to_owned())]
+        );
+        assert_eq!(
+            parse(r#"["bad.example"]"#),
+            vec![("bad.example".to_owned(), String::new())]
+        );
+        assert!(parse("{}").is_empty());
+    }
+
+    #[test]
+    fn parents() {
+        assert_eq!(
+            parent_domains("a.b.example.org"),
+            vec!["b.example.org".to_owned(), "example.org".to_owned()]
+        );
+        assert!(parent_domains("example.org").is_empty());
+    }
+
+    #[test]
+    fn sync_blocks() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let remotes = instance_tests::fill_database(conn)
+                .into_iter()
+                .map(|(_, inst)| inst)
+                .filter(|inst| !inst.local && !inst.blocked)
+                .collect::<Vec<_>>();
+            let (listed, blocked_by_hand) = (&remotes[0], &remotes[1]);
+            blocked_by_hand.toggle_block(conn)?;
+
+            let source = BlocklistSource::add(conn, "https://lists.example/blocklist.csv")?;
+            source.replace_entries(
+                conn,
+                vec![
+                    (listed.public_domain.clone(), "Spam".to_owned()),
+                    (blocked_by_hand.public_domain.clone(), String::new()),
+                    ("unknown.example".to_owned(), String::new()),
+                ],
+            )?;
+            apply(conn)?;
+            let listed = Instance::get(conn, listed.id)?;
+            assert!(listed.blocked && listed.blocked_by_subscription);
+            let unknown = Instance::find_by_domain(conn, "unknown.example")?;
+            assert!(unknown.blocked && unknown.blocked_by_subscription);
+            assert!(!Instance::get(conn, blocked_by_hand.id)?.blocked_by_subscription);
+            assert_eq!(
+                BlocklistEntry::find_by_domain(conn, "unknown.example")?.len(),
+                1
+            );
+
+            // Subdomains are blocked too
+            assert!(BlocklistEntry::blocks_subdomain(
+                conn,
+                "social.unknown.example"
+            )?);
+            assert!(!BlocklistEntry::blocks_subdomain(conn, "example")?);
+
+            // Exceptions are never blocked
+            BlocklistException::add(conn, "Unknown.Example")?;
+            assert!(!Instance::find_by_domain(conn, "unknown.example")?.blocked);
+
+            // Only the blocks of the subscriptions are removed with them
+            source.delete(conn)?;
+            assert!(!Instance::get(conn, listed.id)?.blocked);
+            assert!(Instance::get(conn, blocked_by_hand.id)?.blocked);
+            Ok(())
+        });
+    }
+}
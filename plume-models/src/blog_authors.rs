@@ -1,12 +1,61 @@
-use crate::{schema::blog_authors, Error, Result};
-use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+use crate::{schema::blog_authors, Connection, Error, Result};
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl, SaveChangesDsl};
 
-#[derive(Clone, Queryable, Identifiable)]
+/// What the members of a blog can do.
+///
+/// Owners manage the blog and its members, editors publish articles and review the
+/// ones of contributors, and contributors write drafts that an editor has to approve.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BlogRole {
+    Owner = 0,
+    Editor = 1,
+    Contributor = 2,
+}
+
+impl BlogRole {
+    pub fn from_i32(role: i32) -> Option<Self> {
+        match role {
+            0 => Some(BlogRole::Owner),
+            1 => Some(BlogRole::Editor),
+            2 => Some(BlogRole::Contributor),
+            _ => None,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "owner" => Some(BlogRole::Owner),
+            "editor" => Some(BlogRole::Editor),
+            "contributor" => Some(BlogRole::Contributor),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BlogRole::Owner => "owner",
+            BlogRole::Editor => "editor",
+            BlogRole::Contributor => "contributor",
+        }
+    }
+
+    /// Publishing and approving drafts.
+    pub fn can_publish(self) -> bool {
+        self <= BlogRole::Editor
+    }
+
+    /// Editing the blog and managing its members.
+    pub fn can_manage(self) -> bool {
+        self == BlogRole::Owner
+    }
+}
+
+#[derive(Clone, Queryable, Identifiable, AsChangeset)]
 pub struct BlogAuthor {
     pub id: i32,
     pub blog_id: i32,
     pub author_id: i32,
-    pub is_owner: bool,
+    pub role: i32,
 }
 
 #[derive(Insertable)]
@@ -14,10 +63,119 @@ pub struct BlogAuthor {
 pub struct NewBlogAuthor {
     pub blog_id: i32,
     pub author_id: i32,
-    pub is_owner: bool,
+    pub role: i32,
 }
 
 impl BlogAuthor {
     insert!(blog_authors, NewBlogAuthor);
     get!(blog_authors);
+
+    pub fn find(conn: &Connection, blog_id: i32, author_id: i32) -> Result<Self> {
+        blog_authors::table
+            .filter(blog_authors::blog_id.eq(blog_id))
+            .filter(blog_authors::author_id.eq(author_id))
+            .get_result(conn)
+            .map_err(Error::from)
+    }
+
+    pub fn list_for_blog(conn: &Connection, blog_id: i32) -> Result<Vec<Self>> {
+        blog_authors::table
+            .filter(blog_authors::blog_id.eq(blog_id))
+            .order((blog_authors::role.asc(), blog_authors::id.asc()))
+            .load::<Self>(conn)
+            .map_err(Error::from)
+    }
+
+    pub fn role(&self) -> BlogRole {
+        BlogRole::from_i32(self.role).unwrap_or(BlogRole::Contributor)
+    }
+
+    /// Adds a member to a blog, or changes their role.
+    ///
+    /// A blog always keeps at least one owner.
+    pub fn set_role(
+        conn: &Connection,
+        blog_id: i32,
+        author_id: i32,
+        role: BlogRole,
+    ) -> Result<Self> {
+        match Self::find(conn, blog_id, author_id) {
+            Ok(mut member) => {
+                if member.role() == BlogRole::Owner
+                    && role != BlogRole::Owner
+                    && Self::count_owners(conn, blog_id)? <= 1
+                {
+                    return Err(Error::InvalidValue);
+                }
+                member.role = role as i32;
+                member.save_changes(conn).map_err(Error::from)
+            }
+            Err(_) => Self::insert(
+                conn,
+                NewBlogAuthor {
+                    blog_id,
+                    author_id,
+                    role: role as i32,
+                },
+            ),
+        }
+    }
+
+    /// Removes a member from a blog, unless they are its last owner.
+    pub fn remove(&self, conn: &Connection) -> Result<()> {
+        if self.role() == BlogRole::Owner && Self::count_owners(conn, self.blog_id)? <= 1 {
+            return Err(Error::InvalidValue);
+        }
+        diesel::delete(self)
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+
+    fn count_owners(conn: &Connection, blog_id: i32) -> Result<i64> {
+        blog_authors::table
+            .filter(blog_authors::blog_id.eq(blog_id))
+            .filter(blog_authors::role.eq(BlogRole::Owner as i32))
+            .count()
+            .get_result(conn)
+            .map_err(Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{blogs::tests::fill_database, tests::db};
+    use diesel::Connection;
+
+    #[test]
+    fn roles() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (users, blogs) = fill_database(conn);
+            let owner = BlogAuthor::find(conn, blogs[0].id, users[0].id)?;
+            assert_eq!(owner.role(), BlogRole::Owner);
+            assert!(
+                BlogAuthor::set_role(conn, blogs[0].id, users[0].id, BlogRole::Editor).is_err()
+            );
+            assert!(owner.remove(conn).is_err());
+
+            let contributor =
+                BlogAuthor::set_role(conn, blogs[0].id, users[2].id, BlogRole::Contributor)?;
+            assert!(!contributor.role().can_publish());
+            assert_eq!(
+                BlogAuthor::list_for_blog(conn, blogs[0].id)?
+                    .last()
+                    .map(|m| m.author_id),
+                Some(users[2].id)
+            );
+            let editor = BlogAuthor::set_role(conn, blogs[0].id, users[2].id, BlogRole::Editor)?;
+            assert_eq!(editor.id, contributor.id);
+            assert!(editor.role().can_publish());
+            assert!(!editor.role().can_manage());
+            editor.remove(conn)?;
+            assert!(BlogAuthor::find(conn, blogs[0].id, users[2].id).is_err());
+            Ok(())
+        });
+    }
 }
@@ -0,0 +1,496 @@
+//! Transfers of a blog from an instance to another.
+//!
+//! A blog is exported as a ZIP archive, with its articles and the media they use, and
+//! imported in a blog of another instance. The new blog then names the old one as an alias,
+//! which allows the old one to move: it publishes a `Move`, and points to its new location
+//! from then on. A blog that moved can't get new articles, nor move again.
+//!
+//! Plume blogs can't be followed by themselves: their followers are the followers of their
+//! authors, who are told about the move, but keep following the authors. Nobody is made to
+//! follow the new blog.
+
+use crate::{
+    blogs::Blog,
+    db_conn::write_transaction,
+    import::{save_local_post, ImportReport, ImportedPost},
+    inbox::InboxResult,
+    instance::Instance,
+    medias::Media,
+    outgoing,
+    posts::Post,
+    tags::Tag,
+    users::User,
+    Connection, Error, Result, CONFIG,
+};
+use activitystreams::{activity::Move, base::AnyBase, iri_string::types::IriString, prelude::*};
+use chrono::NaiveDateTime;
+use diesel::SaveChangesDsl;
+use plume_common::activity_pub::{
+    also_known_as, inbox::FromId, request, CustomGroup, PUBLIC_VISIBILITY,
+};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::io::{self, Cursor, Read, Seek, Write};
+use zip::{result::ZipError, write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+const FORMAT: u32 = 1;
+const MANIFEST: &str = "blog.json";
+const MEDIA: &str = "media/";
+
+/// The largest `blog.json` that can be imported, once decompressed.
+const MAX_MANIFEST_SIZE: u64 = 64 * 1024 * 1024;
+/// How much an archive can contain once decompressed, in total.
+const MAX_EXTRACTED_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+
+/// The content of `blog.json`, at the root of an archive.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format: u32,
+    /// The ActivityPub ID of the exported blog
+    pub url: String,
+    pub title: String,
+    /// As Markdown
+    pub summary: String,
+    pub articles: Vec<ArchivedArticle>,
+    pub media: Vec<ArchivedMedia>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ArchivedArticle {
+    pub url: String,
+    pub title: String,
+    pub subtitle: String,
+    /// Missing from the archives of older versions
+    #[serde(default)]
+    pub excerpt: String,
+    /// As Markdown, with the media referenced by their ID on the old instance
+    pub source: String,
+    pub license: String,
+    pub published: bool,
+    pub creation_date: NaiveDateTime,
+    pub tags: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ArchivedMedia {
+    pub id: i32,
+    /// Its path in the archive
+    pub file: String,
+    pub url: String,
+    pub alt_text: String,
+    pub sensitive: bool,
+    pub content_warning: Option<String>,
+}
+
+/// The outcome of an import.
+pub struct TransferReport {
+    pub articles: ImportReport,
+    pub media: usize,
+    /// The blog that can now be moved here
+    pub moved_from: String,
+}
+
+/// An archive of `blog`, to import it on another instance.
+pub fn export(conn: &Connection, blog: &Blog) -> Result<Vec<u8>> {
+    let authors = blog.list_authors(conn)?;
+    let posts = Post::get_for_blog(conn, blog)?;
+
+    // The local media of the authors that the articles use, by ID or by URL
+    let mut used = Vec::new();
+    for author in &authors {
+        for media in Media::for_user(conn, author.id)? {
+            if media.is_remote {
+                continue;
+            }
+            let url = media.url()?;
+            let reference = format!("]({})", media.id);
+            if posts
+                .iter()
+                .any(|p| p.source.contains(&reference) || p.source.contains(&url))
+            {
+                used.push((media, url));
+            }
+        }
+    }
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let mut media = Vec::with_capacity(used.len());
+    for (m, url) in used {
+        let file = format!(
+            "{}{}.{}",
+            MEDIA,
+            m.id,
+            m.file_path.rsplit_once('.').map_or("bin", |(_, ext)| ext)
+        );
+        let bytes = match m.read() {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                tracing::warn!("Couldn't export media {}: {:?}", m.id, e);
+                continue;
+            }
+        };
+        zip.start_file(file.as_str(), options).map_err(zip_error)?;
+        zip.write_all(&bytes)?;
+        media.push(ArchivedMedia {
+            id: m.id,
+            file,
+            url,
+            alt_text: m.alt_text,
+            sensitive: m.sensitive,
+            content_warning: m.content_warning,
+        });
+    }
+
+    let mut articles = Vec::with_capacity(posts.len());
+    for post in posts {
+        articles.push(ArchivedArticle {
+            tags: Tag::for_post(conn, post.id)?
+                .into_iter()
+                .filter(|t| !t.is_hashtag)
+                .map(|t| t.tag)
+                .collect(),
+            url: post.ap_url,
+            title: post.title,
+            subtitle: post.subtitle,
+            excerpt: post.excerpt,
+            source: post.source,
+            license: post.license,
+            published: post.published,
+            creation_date: post.creation_date,
+        });
+    }
+
+    let manifest = Manifest {
+        format: FORMAT,
+        url: blog.ap_url.clone(),
+        title: blog.title.clone(),
+        summary: blog.summary.clone(),
+        articles,
+        media,
+    };
+    zip.start_file(MANIFEST, options).map_err(zip_error)?;
+    serde_json::to_writer_pretty(&mut zip, &manifest)?;
+    Ok(zip.finish().map_err(zip_error)?.into_inner())
+}
+
+/// Imports an archive made by `export` in `blog`, with `author` as the author of its articles
+/// and the owner of its media.
+///
+/// The articles are not federated: they are old news for the followers of the blog. Nothing
+/// is saved if the import fails, and archives that would be too large once decompressed are
+/// refused.
+pub fn import<R: Read + Seek>(
+    conn: &Connection,
+    archive: R,
+    blog: &Blog,
+    author: &User,
+) -> Result<TransferReport> {
+    if blog.moved_to.is_some() {
+        return Err(Error::Unauthorized);
+    }
+    let mut archive = ZipArchive::new(archive).map_err(|_| Error::InvalidValue)?;
+    let manifest = read_entry(&mut archive, MANIFEST, MAX_MANIFEST_SIZE)?;
+    let remaining = MAX_EXTRACTED_SIZE - manifest.len() as u64;
+    let manifest: Manifest = serde_json::from_slice(&manifest)?;
+    if manifest.format != FORMAT || manifest.url == blog.ap_url {
+        return Err(Error::InvalidValue);
+    }
+
+    write_transaction(conn, || {
+        import_manifest(conn, &mut archive, manifest, blog, author, remaining)
+    })
+}
+
+fn import_manifest<R: Read + Seek>(
+    conn: &Connection,
+    archive: &mut ZipArchive<R>,
+    manifest: Manifest,
+    blog: &Blog,
+    author: &User,
+    mut remaining: u64,
+) -> Result<TransferReport> {
+    let mut replacements = Vec::with_capacity(manifest.media.len());
+    for archived in &manifest.media {
+        let max_size = remaining.min(CONFIG.body_limits.media.size);
+        let read = read_entry(archive, &archived.file, max_size);
+        if let Err(Error::InvalidValue) = read {
+            if max_size == remaining {
+                // It is the whole archive that is too large
+                return Err(Error::InvalidValue);
+            }
+        }
+        let ext = archived.file.rsplit_once('.').map_or("", |(_, ext)| ext);
+        let saved = read.and_then(|bytes| {
+            remaining -= bytes.len() as u64;
+            Media::save_bytes(conn, &bytes, ext, archived.alt_text.clone(), author)
+        });
+        match saved {
+            Ok(mut media) => {
+                if archived.sensitive {
+                    media.sensitive = true;
+                    media.content_warning = archived.content_warning.clone();
+                    media = media.save_changes(conn)?;
+                }
+                replacements.push((format!("]({})", archived.id), format!("]({})", media.id)));
+                replacements.push((archived.url.clone(), media.url()?));
+            }
+            Err(e) => tracing::warn!("Couldn't import media {}: {:?}", archived.file, e),
+        }
+    }
+
+    let mut articles = ImportReport::default();
+    for archived in manifest.articles {
+        let source = replace_references(archived.source, &replacements);
+        let imported = ImportedPost {
+            title: archived.title,
+            subtitle: archived.subtitle,
+            source,
+            creation_date: Some(archived.creation_date),
+            published: archived.published,
+            tags: archived.tags,
+        };
+        match save_local_post(conn, blog, author, imported) {
+            Ok(mut post) => {
+                if !archived.license.is_empty() {
+                    post.license = archived.license;
+                }
+                post.excerpt = archived.excerpt;
+                post = post.save_changes(conn)?;
+                articles.posts.push(post);
+            }
+            Err(e) => articles.errors.push((archived.url, format!("{:?}", e))),
+        }
+    }
+
+    let mut blog = blog.clone();
+    blog.moved_from = Some(manifest.url.clone());
+    if blog.summary.is_empty() {
+        blog.summary = manifest.summary;
+    }
+    let blog: Blog = blog.save_changes(conn)?;
+    blog.forget_cached();
+
+    Ok(TransferReport {
+        articles,
+        media: replacements.len() / 2,
+        moved_from: manifest.url,
+    })
+}
+
+/// Reads a file of an archive, failing with `InvalidValue` if it is larger than `max_size`
+/// once decompressed.
+///
+/// The size written in the archive is not trusted.
+fn read_entry<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    name: &str,
+    max_size: u64,
+) -> Result<Vec<u8>> {
+    let file = archive.by_name(name).map_err(|_| Error::NotFound)?;
+    let mut bytes = Vec::new();
+    file.take(max_size + 1).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > max_size {
+        return Err(Error::InvalidValue);
+    }
+    Ok(bytes)
+}
+
+/// The people to tell about a move: the followers of the authors of `blog`.
+pub fn followers(conn: &Connection, blog: &Blog) -> Result<Vec<User>> {
+    let mut seen = HashSet::new();
+    let mut followers = Vec::new();
+    for author in blog.list_authors(conn)? {
+        for follower in author.get_followers(conn)? {
+            if seen.insert(follower.id) {
+                followers.push(follower);
+            }
+        }
+    }
+    Ok(followers)
+}
+
+/// Moves `blog` to the blog at `target`, that has to name it as an alias.
+///
+/// A blog can only move once. The returned activity has to be sent to the `followers` of the
+/// blog.
+pub fn move_to(conn: &Connection, blog: &Blog, target: &str) -> Result<Move> {
+    if blog.moved_to.is_some() {
+        return Err(Error::Unauthorized);
+    }
+    let (new_blog, aliases) = fetch_blog(conn, target)?;
+    if !aliases.contains(&blog.ap_url) || new_blog.ap_url == blog.ap_url {
+        return Err(Error::Unauthorized);
+    }
+
+    let mut moved = blog.clone();
+    moved.moved_to = Some(new_blog.ap_url.clone());
+    let moved: Blog = moved.save_changes(conn)?;
+    moved.forget_cached();
+
+    let mut act = Move::new(
+        blog.ap_url.parse::<IriString>()?,
+        blog.ap_url.parse::<IriString>()?,
+    );
+    act.set_id(format!("{}#move/{}", blog.ap_url, new_blog.id).parse()?);
+    act.set_target(new_blog.ap_url.parse::<IriString>()?);
+    act.set_many_tos(vec![PUBLIC_VISIBILITY.parse::<IriString>()?]);
+    act.set_many_ccs(
+        followers(conn, blog)?
+            .into_iter()
+            .filter_map(|f| f.ap_url.parse::<IriString>().ok())
+            .collect::<Vec<_>>(),
+    );
+    Ok(act)
+}
+
+/// Tells if `act` is a blog moving to another one.
+pub fn is_blog_move(conn: &Connection, act: &Value) -> bool {
+    act["type"].as_str() == Some("Move")
+        && act["actor"]
+            .as_str()
+            .map_or(false, |actor| Blog::find_by_ap_url(conn, actor).is_ok())
+}
+
+/// Saves where a remote blog moved.
+///
+/// The new blog is fetched again, to check that it really is the new location of the old one.
+pub fn handle_move(conn: &Connection, act: &Value) -> Result<InboxResult> {
+    let actor = act["actor"].as_str().ok_or(Error::MissingApProperty)?;
+    let object = act["object"]
+        .as_str()
+        .or_else(|| act["object"]["id"].as_str());
+    let target = act["target"]
+        .as_str()
+        .or_else(|| act["target"]["id"].as_str())
+        .ok_or(Error::MissingApProperty)?;
+    if object != Some(actor) {
+        return Err(Error::Unauthorized);
+    }
+
+    let blog = Blog::find_by_ap_url(conn, actor)?;
+    if blog.instance_id == Instance::get_local()?.id {
+        return Err(Error::Unauthorized);
+    }
+    let (new_blog, aliases) = fetch_blog(conn, target)?;
+    if !aliases.contains(&blog.ap_url) {
+        return Err(Error::Unauthorized);
+    }
+    let mut blog = blog;
+    blog.moved_to = Some(new_blog.ap_url);
+    let blog: Blog = blog.save_changes(conn)?;
+    blog.forget_cached();
+    Ok(InboxResult::Other)
+}
+
+/// Fetches the current version of a blog, with its aliases, from a public address only.
+fn fetch_blog(conn: &Connection, url: &str) -> Result<(Blog, Vec<String>)> {
+    outgoing::check_url(url)?;
+    let json: Value = request::get(
+        url,
+        Instance::get_local_instance_user().ok_or(Error::NotFound)?,
+        CONFIG.proxy().cloned(),
+    )?
+    .json()?;
+    let mut group: CustomGroup = serde_json::from_value(json)?;
+    let aliases = also_known_as(&mut group.inner);
+    let id = AnyBase::from_extended(group.clone())?
+        .id()
+        .map(|id| id.to_string())
+        .ok_or(Error::MissingApProperty)?;
+    let blog = match Blog::find_by_ap_url(conn, &id) {
+        Ok(blog) => blog,
+        Err(_) => Blog::from_activity(conn, group)?,
+    };
+    Ok((blog, aliases))
+}
+
+/// Replaces the references to the old media with the new ones, in a single pass, as the new
+/// IDs may be the old IDs of other media.
+fn replace_references(source: String, replacements: &[(String, String)]) -> String {
+    let marked = replacements
+        .iter()
+        .enumerate()
+        .fold(source, |source, (i, (old, _))| {
+            source.replace(old, &format!("\u{0}{}\u{0}", i))
+        });
+    replacements
+        .iter()
+        .enumerate()
+        .fold(marked, |source, (i, (_, new))| {
+            source.replace(&format!("\u{0}{}\u{0}", i), new)
+        })
+}
+
+fn zip_error(e: ZipError) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::Other, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{inbox::tests::fill_database, tests::db};
+    use diesel::Connection;
+
+    #[test]
+    fn export_and_import() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, users, blogs) = fill_database(conn);
+            let archive = export(conn, &blogs[0])?;
+
+            // The same archive can't be imported in the blog it comes from
+            assert!(import(conn, Cursor::new(archive.clone()), &blogs[0], &users[0]).is_err());
+
+            let report = import(conn, Cursor::new(archive), &blogs[1], &users[1])?;
+            assert_eq!(report.moved_from, blogs[0].ap_url);
+            assert_eq!(report.articles.posts.len(), posts.len());
+            assert!(report.articles.errors.is_empty());
+            let imported = &report.articles.posts[0];
+            assert_eq!(imported.blog_id, blogs[1].id);
+            assert_eq!(imported.title, posts[0].title);
+            assert_eq!(imported.license, posts[0].license);
+            assert!(imported.is_author(conn, users[1].id)?);
+            assert_eq!(
+                Blog::get(conn, blogs[1].id)?.moved_from,
+                Some(blogs[0].ap_url.clone())
+            );
+
+            // Nothing can be imported in a blog that moved
+            let mut moved = blogs[0].clone();
+            moved.moved_to = Some(blogs[1].ap_url.clone());
+            let moved: Blog = moved.save_changes(conn)?;
+            let archive = export(conn, &blogs[1])?;
+            assert!(import(conn, Cursor::new(archive), &moved, &users[0]).is_err());
+            assert!(move_to(conn, &moved, &blogs[1].ap_url).is_err());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn references() {
+        let replacements = vec![
+            ("](1)".to_owned(), "](2)".to_owned()),
+            ("](2)".to_owned(), "](3)".to_owned()),
+        ];
+        assert_eq!(
+            replace_references("![a](1) ![b](2) ![c](12)".to_owned(), &replacements),
+            "![a](2) ![b](3) ![c](12)"
+        );
+    }
+
+    #[test]
+    fn moved_blogs_in_activities() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (_, _, blogs) = fill_database(conn);
+            let mut blog = blogs[1].clone();
+            blog.moved_from = Some(blogs[0].ap_url.clone());
+            let blog: Blog = blog.save_changes(conn)?;
+            let act = serde_json::to_value(blog.to_activity(conn)?)?;
+            assert_eq!(act["alsoKnownAs"][0], blogs[0].ap_url.as_str());
+            assert!(act["movedTo"].is_null());
+            Ok(())
+        });
+    }
+}
@@ -1,5 +1,14 @@
 use crate::{
-    instance::*, medias::Media, posts::Post, safe_string::SafeString, schema::blogs, users::User,
+    cache::{self, Entry},
+    fundings::Funding,
+    instance::*,
+    keys::KEY_STORE,
+    medias::Media,
+    posts::Post,
+    profile_fields::{ProfileField, ProfileOwner},
+    safe_string::SafeString,
+    schema::blogs,
+    users::User,
     Connection, Error, PlumeRocket, Result, CONFIG, ITEMS_PER_PAGE,
 };
 use activitystreams::{
@@ -9,6 +18,7 @@ use activitystreams::{
     iri_string::types::IriString,
     object::{kind::ImageType, ApObject, Image, ObjectExt},
     prelude::*,
+    primitives::OneOrMany,
 };
 use chrono::NaiveDateTime;
 use diesel::{self, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl, SaveChangesDsl};
@@ -20,9 +30,11 @@ use openssl::{
 };
 use plume_common::{
     activity_pub::{
+        default_license, discoverable,
         inbox::{AsActor, FromId},
-        sign, ActivityStream, ApSignature, CustomGroup, Id, IntoId, PublicKey, Source,
-        SourceProperty, ToAsString, ToAsUri,
+        set_also_known_as, set_default_license, set_discoverable, set_moved_to, sign,
+        ActivityStream, ApSignature, CustomGroup, Id, IntoId, PublicKey, Source, SourceProperty,
+        ToAsString, ToAsUri,
     },
     utils::iri_percent_encode_seg,
 };
@@ -47,6 +59,19 @@ pub struct Blog {
     pub icon_id: Option<i32>,
     pub banner_id: Option<i32>,
     pub theme: Option<String>,
+    pub comments_order: i32,
+    pub comments_max_depth: Option<i32>,
+    pub allow_guest_comments: bool,
+    /// Whether it is listed in the directory, here and on other instances
+    pub discoverable: bool,
+    /// One of `directory::CATEGORIES`
+    pub directory_category: Option<String>,
+    /// The blog this one was moved to (see `blog_transfer`)
+    pub moved_to: Option<String>,
+    /// The blog this one was imported from, that can be moved here
+    pub moved_from: Option<String>,
+    /// The license of its new articles, if not the one of the instance
+    pub default_license: Option<String>,
 }
 
 #[derive(Default, Insertable)]
@@ -65,6 +90,12 @@ pub struct NewBlog {
     pub icon_id: Option<i32>,
     pub banner_id: Option<i32>,
     pub theme: Option<String>,
+    pub comments_order: i32,
+    pub comments_max_depth: Option<i32>,
+    pub allow_guest_comments: bool,
+    pub discoverable: bool,
+    pub directory_category: Option<String>,
+    pub default_license: Option<String>,
 }
 
 const BLOG_PREFIX: &str = "~";
@@ -184,42 +215,37 @@ impl Blog {
             },
         };
 
-        let mut icon = Image::new();
-        let _ = self.icon_id.map(|id| {
-            Media::get(conn, id).and_then(|m| {
-                let _ = m
-                    .url()
-                    .and_then(|url| url.parse::<IriString>().map_err(|_| Error::Url))
-                    .map(|url| icon.set_url(url));
-                icon.set_attributed_to(
-                    User::get(conn, m.owner_id)?
-                        .into_id()
-                        .parse::<IriString>()?,
-                );
-                Ok(())
-            })
-        });
+        let icon = self
+            .icon_id
+            .and_then(|id| Media::get(conn, id).and_then(|m| m.to_image(conn)).ok())
+            .unwrap_or_else(Image::new);
         blog.set_icon(icon.into_any_base()?);
 
-        let mut banner = Image::new();
-        let _ = self.banner_id.map(|id| {
-            Media::get(conn, id).and_then(|m| {
-                let _ = m
-                    .url()
-                    .and_then(|url| url.parse::<IriString>().map_err(|_| Error::Url))
-                    .map(|url| banner.set_url(url));
-                banner.set_attributed_to(
-                    User::get(conn, m.owner_id)?
-                        .into_id()
-                        .parse::<IriString>()?,
-                );
-                Ok(())
-            })
-        });
+        let banner = self
+            .banner_id
+            .and_then(|id| Media::get(conn, id).and_then(|m| m.to_image(conn)).ok())
+            .unwrap_or_else(Image::new);
         blog.set_image(banner.into_any_base()?);
 
         blog.set_id(self.ap_url.parse()?);
 
+        let mut attachments =
+            ProfileField::to_attachments(&ProfileField::list(conn, ProfileOwner::Blog(self.id))?)?;
+        if let Ok(funding) = Funding::for_blog(conn, self.id) {
+            attachments.extend(funding.to_attachments()?);
+        }
+        if !attachments.is_empty() {
+            blog.set_many_attachments(attachments);
+        }
+        set_discoverable(&mut blog, self.discoverable)?;
+        if let Some(ref moved_from) = self.moved_from {
+            set_also_known_as(&mut blog, &[moved_from.clone()])?;
+        }
+        if let Some(ref moved_to) = self.moved_to {
+            set_moved_to(&mut blog, moved_to)?;
+        }
+        set_default_license(&mut blog, self.default_license.clone())?;
+
         let pub_key = PublicKey {
             id: format!("{}#main-key", self.ap_url).parse()?,
             owner: self.ap_url.parse()?,
@@ -299,13 +325,11 @@ impl Blog {
     }
 
     pub fn get_keypair(&self) -> Result<PKey<Private>> {
-        PKey::from_rsa(Rsa::private_key_from_pem(
+        KEY_STORE.load(
             self.private_key
-                .clone()
-                .ok_or(Error::MissingApProperty)?
-                .as_ref(),
-        )?)
-        .map_err(Error::from)
+                .as_deref()
+                .ok_or(Error::MissingApProperty)?,
+        )
     }
 
     pub fn webfinger(&self, conn: &Connection) -> Result<Webfinger> {
@@ -345,24 +369,42 @@ impl Blog {
 
     pub fn icon_url(&self, conn: &Connection) -> String {
         self.icon_id
-            .and_then(|id| Media::get(conn, id).and_then(|m| m.url()).ok())
+            .and_then(|id| Media::get(conn, id).ok())
+            .and_then(|m| m.inline_url(conn))
             .unwrap_or_else(|| "/static/images/default-avatar.png".to_string())
     }
 
     pub fn banner_url(&self, conn: &Connection) -> Option<String> {
         self.banner_id
             .and_then(|i| Media::get(conn, i).ok())
-            .and_then(|c| c.url().ok())
+            .and_then(|c| c.inline_url(conn))
+    }
+
+    /// The license new articles of this blog get, unless their authors choose another one.
+    pub fn license(&self, conn: &Connection) -> Result<String> {
+        match self.default_license {
+            Some(ref license) => Ok(license.clone()),
+            None => Ok(self.get_instance(conn)?.default_license),
+        }
     }
 
     pub fn delete(&self, conn: &Connection) -> Result<()> {
         for post in Post::get_for_blog(conn, self)? {
             post.delete(conn)?;
         }
-        diesel::delete(self)
-            .execute(conn)
-            .map(|_| ())
-            .map_err(Error::from)
+        diesel::delete(self).execute(conn)?;
+        if let Some(ref private_key) = self.private_key {
+            KEY_STORE.retire(conn, private_key)?;
+        }
+        self.forget_cached();
+        Ok(())
+    }
+
+    /// Forgets the cached actor document and WebFinger answer of this blog, after they
+    /// changed.
+    pub fn forget_cached(&self) {
+        cache::forget(Entry::BlogActor(self.id));
+        cache::forget(Entry::BlogWebFinger(&self.fqn));
     }
 }
 
@@ -380,7 +422,9 @@ impl FromId<Connection> for Blog {
         Self::find_by_ap_url(conn, id)
     }
 
-    fn from_activity(conn: &Connection, acct: CustomGroup) -> Result<Self> {
+    fn from_activity(conn: &Connection, mut acct: CustomGroup) -> Result<Self> {
+        let discoverable = discoverable(&mut acct.inner);
+        let default_license = default_license(&mut acct.inner);
         let (name, outbox_url, inbox_url) = {
             let actor = acct.ap_actor_ref();
             let name = actor
@@ -405,54 +449,35 @@ impl FromId<Connection> for Blog {
             public_key: acct.ext_one.public_key.public_key_pem.to_string(),
             private_key: None,
             theme: None,
+            discoverable,
+            default_license,
             ..NewBlog::default()
         };
 
         let object = ApObject::new(acct.inner);
+        let attachments = object
+            .attachment()
+            .map(|attachments| attachments.iter().cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
         new_blog.title = object
             .name()
             .and_then(|name| name.to_as_string())
             .unwrap_or(name);
-        new_blog.summary_html = SafeString::new(
+        new_blog.summary_html = SafeString::new_remote(
             &object
                 .summary()
                 .and_then(|summary| summary.to_as_string())
                 .unwrap_or_default(),
         );
 
-        let icon_id = object
+        new_blog.icon_id = object
             .icon()
-            .and_then(|icons| {
-                icons.iter().next().and_then(|icon| {
-                    let icon = icon.to_owned().extend::<Image, ImageType>().ok()??;
-                    let owner = icon.attributed_to()?.to_as_uri()?;
-                    Media::save_remote(
-                        conn,
-                        icon.url()?.to_as_uri()?,
-                        &User::from_id(conn, &owner, None, CONFIG.proxy()).ok()?,
-                    )
-                    .ok()
-                })
-            })
+            .and_then(|icons| save_remote_image(conn, icons))
             .map(|m| m.id);
-        new_blog.icon_id = icon_id;
-
-        let banner_id = object
+        new_blog.banner_id = object
             .image()
-            .and_then(|banners| {
-                banners.iter().next().and_then(|banner| {
-                    let banner = banner.to_owned().extend::<Image, ImageType>().ok()??;
-                    let owner = banner.attributed_to()?.to_as_uri()?;
-                    Media::save_remote(
-                        conn,
-                        banner.url()?.to_as_uri()?,
-                        &User::from_id(conn, &owner, None, CONFIG.proxy()).ok()?,
-                    )
-                    .ok()
-                })
-            })
+            .and_then(|banners| save_remote_image(conn, banners))
             .map(|m| m.id);
-        new_blog.banner_id = banner_id;
 
         new_blog.summary = acct.ext_two.source.content;
 
@@ -484,7 +509,13 @@ impl FromId<Connection> for Blog {
         })?;
         new_blog.instance_id = instance.id;
 
-        Blog::insert(conn, new_blog)
+        let blog = Blog::insert(conn, new_blog)?;
+        if let Err(e) =
+            ProfileField::replace_remote(conn, ProfileOwner::Blog(blog.id), &attachments)
+        {
+            tracing::error!("{:?}", e);
+        }
+        Ok(blog)
     }
 
     fn get_sender() -> &'static dyn sign::Signer {
@@ -492,6 +523,24 @@ impl FromId<Connection> for Blog {
     }
 }
 
+/// Saves the icon or the banner of a remote blog.
+///
+/// The media belongs to the user it is attributed to, or to the instance if it is a simple URL
+/// or if that user can't be found.
+fn save_remote_image(conn: &Connection, images: &OneOrMany<AnyBase>) -> Option<Media> {
+    let owner = images
+        .iter()
+        .next()
+        .and_then(|image| image.clone().extend::<Image, ImageType>().ok()?)
+        .and_then(|image| image.attributed_to()?.to_as_uri())
+        .and_then(|owner| User::from_id(conn, &owner, None, CONFIG.proxy()).ok());
+    let owner = match owner {
+        Some(owner) => owner,
+        None => Instance::get_local_instance_user_uncached(conn).ok()?,
+    };
+    Media::save_remote_image(conn, images, &owner).ok()
+}
+
 impl AsActor<&PlumeRocket> for Blog {
     fn get_inbox_url(&self) -> String {
         self.inbox_url.clone()
@@ -535,14 +584,14 @@ impl NewBlog {
         summary: String,
         instance_id: i32,
     ) -> Result<NewBlog> {
-        let (pub_key, priv_key) = sign::gen_keypair();
+        let (public_key, private_key) = KEY_STORE.generate()?;
         Ok(NewBlog {
             actor_id,
             title,
             summary,
             instance_id,
-            public_key: String::from_utf8(pub_key).or(Err(Error::Signature))?,
-            private_key: Some(String::from_utf8(priv_key).or(Err(Error::Signature))?),
+            public_key,
+            private_key: Some(private_key),
             ..NewBlog::default()
         })
     }
@@ -601,7 +650,7 @@ pub(crate) mod tests {
             NewBlogAuthor {
                 blog_id: blog1.id,
                 author_id: users[0].id,
-                is_owner: true,
+                role: BlogRole::Owner as i32,
             },
         )
         .unwrap();
@@ -611,7 +660,7 @@ pub(crate) mod tests {
             NewBlogAuthor {
                 blog_id: blog1.id,
                 author_id: users[1].id,
-                is_owner: false,
+                role: BlogRole::Editor as i32,
             },
         )
         .unwrap();
@@ -621,7 +670,7 @@ pub(crate) mod tests {
             NewBlogAuthor {
                 blog_id: blog2.id,
                 author_id: users[1].id,
-                is_owner: true,
+                role: BlogRole::Owner as i32,
             },
         )
         .unwrap();
@@ -631,7 +680,7 @@ pub(crate) mod tests {
             NewBlogAuthor {
                 blog_id: blog3.id,
                 author_id: users[2].id,
-                is_owner: true,
+                role: BlogRole::Owner as i32,
             },
         )
         .unwrap();
@@ -735,7 +784,7 @@ pub(crate) mod tests {
                 NewBlogAuthor {
                     blog_id: blog[0].id,
                     author_id: user[0].id,
-                    is_owner: true,
+                    role: BlogRole::Owner as i32,
                 },
             )
             .unwrap();
@@ -745,7 +794,7 @@ pub(crate) mod tests {
                 NewBlogAuthor {
                     blog_id: blog[0].id,
                     author_id: user[1].id,
-                    is_owner: false,
+                    role: BlogRole::Editor as i32,
                 },
             )
             .unwrap();
@@ -755,7 +804,7 @@ pub(crate) mod tests {
                 NewBlogAuthor {
                     blog_id: blog[1].id,
                     author_id: user[0].id,
-                    is_owner: true,
+                    role: BlogRole::Owner as i32,
                 },
             )
             .unwrap();
@@ -894,7 +943,7 @@ pub(crate) mod tests {
                 NewBlogAuthor {
                     blog_id: blog[0].id,
                     author_id: user[0].id,
-                    is_owner: true,
+                    role: BlogRole::Owner as i32,
                 },
             )
             .unwrap();
@@ -904,7 +953,7 @@ pub(crate) mod tests {
                 NewBlogAuthor {
                     blog_id: blog[0].id,
                     author_id: user[1].id,
-                    is_owner: false,
+                    role: BlogRole::Editor as i32,
                 },
             )
             .unwrap();
@@ -914,7 +963,7 @@ pub(crate) mod tests {
                 NewBlogAuthor {
                     blog_id: blog[1].id,
                     author_id: user[0].id,
-                    is_owner: true,
+                    role: BlogRole::Owner as i32,
                 },
             )
             .unwrap();
@@ -996,14 +1045,17 @@ pub(crate) mod tests {
             let act = blog.to_activity(conn)?;
 
             let expected = json!({
+                "discoverable": false,
                 "icon": {
                     "attributedTo": "https://plu.me/@/admin/",
+                    "mediaType": "image/png",
                     "type": "Image",
                     "url": "https://plu.me/aaa.png"
                 },
                 "id": "https://plu.me/~/BlogName/",
                 "image": {
                     "attributedTo": "https://plu.me/@/admin/",
+                    "mediaType": "image/png",
                     "type": "Image",
                     "url": "https://plu.me/bbb.png"
                 },
@@ -1030,6 +1082,30 @@ pub(crate) mod tests {
         });
     }
 
+    #[test]
+    fn blog_license() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (_users, mut blogs) = fill_database(conn);
+            assert_eq!(
+                blogs[0].license(conn)?,
+                Instance::get_local()?.default_license
+            );
+            assert!(to_value(blogs[0].to_activity(conn)?)?
+                .get("license")
+                .is_none());
+
+            blogs[0].default_license = Some("CC-BY-NC-4.0".to_owned());
+            let blog: Blog = blogs[0].save_changes(conn)?;
+            assert_eq!(blog.license(conn)?, "CC-BY-NC-4.0");
+            assert_eq!(
+                to_value(blog.to_activity(conn)?)?["license"],
+                "CC-BY-NC-4.0"
+            );
+            Ok(())
+        });
+    }
+
     #[test]
     fn outbox_collection() {
         let conn = &db();
@@ -0,0 +1,376 @@
+//! A cache for what is often requested and costly to build: articles as rendered for
+//! anonymous readers, the ActivityPub documents of local actors, timeline pages, WebFinger
+//! answers, what is rendered from Markdown when showing or federating posts and comments,
+//! the mentioned accounts that couldn't be found, and the media policies.
+//!
+//! Entries are kept in Redis when `REDIS_URL` is set, so that they are shared by all the
+//! processes of an instance, and in memory otherwise. They expire after `CACHE_TTL`, and
+//! are removed as soon as what they were built from changes, except for what articles show
+//! of their blog and authors, or their likes and reshares, that can be late by up to
+//! `CACHE_TTL`.
+
+use crate::CONFIG;
+use once_cell::sync::Lazy;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+pub static CACHE: Lazy<Box<dyn Cache>> = Lazy::new(|| {
+    // Tests reuse the same ids in different transactions, they can't share a cache
+    if cfg!(test) || CONFIG.cache.ttl == Duration::default() {
+        return Box::new(NoCache);
+    }
+    match CONFIG.cache.redis_url {
+        #[cfg(feature = "redis")]
+        Some(ref url) => Box::new(RedisCache::new(url)),
+        #[cfg(not(feature = "redis"))]
+        Some(_) => {
+            warn!("REDIS_URL is set, but Plume was built without Redis support: the cache will be kept in memory");
+            Box::new(MemoryCache::new(CONFIG.cache.max_entries))
+        }
+        None => Box::new(MemoryCache::new(CONFIG.cache.max_entries)),
+    }
+});
+
+/// Where cached entries are kept.
+///
+/// Failures are logged and treated as cache misses: the cache is never required to serve a
+/// request.
+pub trait Cache: Send + Sync {
+    fn get(&self, key: &str) -> Option<String>;
+    fn set(&self, key: &str, value: &str, ttl: Duration);
+    fn remove(&self, key: &str);
+    /// Removes all the entries whose key starts with `prefix`.
+    fn remove_prefix(&self, prefix: &str);
+}
+
+/// Something that can be cached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Entry<'a> {
+    /// The page of an article, for anonymous readers, in a given language
+    Article(i32, &'a str),
+    /// The actor document of a local user
+    UserActor(i32),
+    /// The actor document of a local blog
+    BlogActor(i32),
+    /// The posts of a timeline, between two indices
+    TimelinePage(i32, (i32, i32)),
+    /// The number of posts in a timeline
+    TimelineCount(i32),
+    /// The WebFinger answer for a local user, from their username
+    UserWebFinger(&'a str),
+    /// The WebFinger answer for a local blog, from its name
+    BlogWebFinger(&'a str),
+    /// The table of contents of a post, for a `revision` of its source
+    TableOfContents(i32, &'a str),
+    /// The source of a post with the fallbacks of its shortcodes, for a `revision`
+    SourceWithFallbacks(i32, &'a str),
+    /// The HTML and the mentions of a comment as it is federated, for a `revision`
+    CommentNote(i32, &'a str),
+    /// Why a mentioned account couldn't be found, from its WebFinger address
+    MentionFailure(&'a str),
+    /// The media policies of the instance
+    MediaPolicies,
+}
+
+impl<'a> Entry<'a> {
+    pub fn key(&self) -> String {
+        match self {
+            Entry::Article(post, lang) => format!("article:{}:{}", post, lang),
+            Entry::UserActor(user) => format!("actor:user:{}", user),
+            Entry::BlogActor(blog) => format!("actor:blog:{}", blog),
+            Entry::TimelinePage(timeline, (min, max)) => {
+                format!("timeline:{}:page:{}-{}", timeline, min, max)
+            }
+            Entry::TimelineCount(timeline) => format!("timeline:{}:count", timeline),
+            Entry::UserWebFinger(acct) => format!("webfinger:acct:{}", acct),
+            Entry::BlogWebFinger(acct) => format!("webfinger:group:{}", acct),
+            Entry::TableOfContents(post, revision) => {
+                format!("markdown:post:{}:toc:{}", post, revision)
+            }
+            Entry::SourceWithFallbacks(post, revision) => {
+                format!("markdown:post:{}:source:{}", post, revision)
+            }
+            Entry::CommentNote(comment, revision) => {
+                format!("markdown:comment:{}:{}", comment, revision)
+            }
+            Entry::MentionFailure(acct) => format!("mention:failure:{}", acct),
+            Entry::MediaPolicies => "media-policies".to_owned(),
+        }
+    }
+}
+
+/// Returns the cached value of `entry`, or builds it and caches it.
+///
+/// Errors are not cached.
+pub fn get_or_insert<T, E, F>(entry: Entry<'_>, build: F) -> Result<T, E>
+where
+    T: Serialize + DeserializeOwned,
+    F: FnOnce() -> Result<T, E>,
+{
+    if let Some(value) = get(entry) {
+        return Ok(value);
+    }
+    let value = build()?;
+    set(entry, &value);
+    Ok(value)
+}
+
+/// Returns the cached value of `entry`, if there is one.
+pub fn get<T: DeserializeOwned>(entry: Entry<'_>) -> Option<T> {
+    CACHE
+        .get(&entry.key())
+        .and_then(|cached| serde_json::from_str(&cached).ok())
+}
+
+pub fn set<T: Serialize>(entry: Entry<'_>, value: &T) {
+    set_for(entry, value, CONFIG.cache.ttl)
+}
+
+/// Caches `value` for less time than the other entries, at most `ttl`.
+pub fn set_for<T: Serialize>(entry: Entry<'_>, value: &T, ttl: Duration) {
+    if let Ok(json) = serde_json::to_string(value) {
+        CACHE.set(&entry.key(), &json, ttl.min(CONFIG.cache.ttl));
+    }
+}
+
+pub fn forget(entry: Entry<'_>) {
+    CACHE.remove(&entry.key());
+}
+
+/// Forgets the pages of an article, in all languages.
+pub fn forget_article(post: i32) {
+    CACHE.remove_prefix(&format!("article:{}:", post));
+}
+
+/// Forgets the pages of all articles.
+pub fn forget_articles() {
+    CACHE.remove_prefix("article:");
+}
+
+/// Identifies a version of a Markdown document, as rendered with the current configuration.
+///
+/// It changes with the document, but also with the policy of the sanitizer, the hosts that
+/// can be embedded, math rendering or the version of Plume: what was rendered before is
+/// then not used anymore.
+pub fn revision(source: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    CONFIG.sanitizer.hash(&mut hasher);
+    CONFIG.embed_allowlist.hash(&mut hasher);
+    CONFIG.math_rendering.hash(&mut hasher);
+    source.hash(&mut hasher);
+    format!("{:x}", hasher.finish())
+}
+
+/// Forgets what was rendered from the Markdown of a post, for all its revisions.
+pub fn forget_post_markdown(post: i32) {
+    CACHE.remove_prefix(&format!("markdown:post:{}:", post));
+}
+
+/// Forgets what was rendered from the Markdown of a comment, for all its revisions.
+pub fn forget_comment_markdown(comment: i32) {
+    CACHE.remove_prefix(&format!("markdown:comment:{}:", comment));
+}
+
+/// Forgets the pages and the length of a timeline.
+pub fn forget_timeline(timeline: i32) {
+    CACHE.remove_prefix(&format!("timeline:{}:", timeline));
+}
+
+/// Forgets the pages of all timelines, when a post they may contain is deleted.
+pub fn forget_timelines() {
+    CACHE.remove_prefix("timeline:");
+}
+
+/// Used when caching is disabled.
+pub struct NoCache;
+
+impl Cache for NoCache {
+    fn get(&self, _key: &str) -> Option<String> {
+        None
+    }
+
+    fn set(&self, _key: &str, _value: &str, _ttl: Duration) {}
+
+    fn remove(&self, _key: &str) {}
+
+    fn remove_prefix(&self, _prefix: &str) {}
+}
+
+/// A cache for a single process.
+pub struct MemoryCache {
+    max_entries: usize,
+    /// The entries, with the date they expire
+    entries: Mutex<HashMap<String, (Instant, String)>>,
+}
+
+impl MemoryCache {
+    pub fn new(max_entries: usize) -> Self {
+        MemoryCache {
+            max_entries,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Cache for MemoryCache {
+    fn get(&self, key: &str) -> Option<String> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(key)
+            .filter(|(expires, _)| *expires > Instant::now())
+            .map(|(_, value)| value.clone())
+    }
+
+    fn set(&self, key: &str, value: &str, ttl: Duration) {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.max_entries && !entries.contains_key(key) {
+            entries.retain(|_, (expires, _)| *expires > now);
+            // Still full: make room for a tenth of the entries, in no particular order
+            if entries.len() >= self.max_entries {
+                let removed = entries
+                    .keys()
+                    .take(self.max_entries / 10 + 1)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for key in removed {
+                    entries.remove(&key);
+                }
+            }
+        }
+        entries.insert(key.to_owned(), (now + ttl, value.to_owned()));
+    }
+
+    fn remove(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    fn remove_prefix(&self, prefix: &str) {
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|key, _| !key.starts_with(prefix));
+    }
+}
+
+/// A cache kept in Redis, that can be shared by several processes.
+#[cfg(feature = "redis")]
+pub struct RedisCache {
+    pool: diesel::r2d2::Pool<redis::Client>,
+}
+
+#[cfg(feature = "redis")]
+impl RedisCache {
+    /// Keys are prefixed with the domain of the instance, in case the Redis server is
+    /// shared.
+    fn namespaced(key: &str) -> String {
+        format!("plume:{}:{}", CONFIG.base_url, key)
+    }
+
+    pub fn new(url: &str) -> Self {
+        let client = redis::Client::open(url)
+            .expect("RedisCache::new: REDIS_URL was checked with the configuration");
+        RedisCache {
+            // Redis may start after Plume: connections are only opened when needed
+            pool: diesel::r2d2::Pool::builder().build_unchecked(client),
+        }
+    }
+
+    fn run<T, F>(&self, command: F) -> Option<T>
+    where
+        F: FnOnce(&mut redis::Connection) -> redis::RedisResult<T>,
+    {
+        let res = match self.pool.get() {
+            Ok(mut conn) => command(&mut conn).map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        res.map_err(|e| warn!("Cache error: {}", e)).ok()
+    }
+}
+
+#[cfg(feature = "redis")]
+impl Cache for RedisCache {
+    fn get(&self, key: &str) -> Option<String> {
+        use redis::Commands;
+        self.run(|conn| conn.get::<_, Option<String>>(Self::namespaced(key)))
+            .flatten()
+    }
+
+    fn set(&self, key: &str, value: &str, ttl: Duration) {
+        use redis::Commands;
+        self.run(|conn| {
+            conn.set_ex::<_, _, ()>(Self::namespaced(key), value, ttl.as_secs() as usize)
+        });
+    }
+
+    fn remove(&self, key: &str) {
+        use redis::Commands;
+        self.run(|conn| conn.del::<_, ()>(Self::namespaced(key)));
+    }
+
+    fn remove_prefix(&self, prefix: &str) {
+        use redis::Commands;
+        let pattern = Self::namespaced(prefix)
+            .chars()
+            .fold(String::new(), |mut pattern, c| {
+                if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+                    pattern.push('\\');
+                }
+                pattern.push(c);
+                pattern
+            });
+        self.run(|conn| {
+            let keys = conn
+                .scan_match::<_, String>(format!("{}*", pattern))?
+                .collect::<Vec<_>>();
+            if keys.is_empty() {
+                Ok(())
+            } else {
+                conn.del::<_, ()>(keys)
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_cache() {
+        let cache = MemoryCache::new(3);
+        let ttl = Duration::from_secs(60);
+        cache.set("article:1:en", "one", ttl);
+        cache.set("article:1:fr", "un", ttl);
+        cache.set("article:12:en", "twelve", ttl);
+        assert_eq!(cache.get("article:1:en"), Some("one".to_owned()));
+
+        cache.remove_prefix(&Entry::Article(1, "").key());
+        assert_eq!(cache.get("article:1:en"), None);
+        assert_eq!(cache.get("article:1:fr"), None);
+        assert_eq!(cache.get("article:12:en"), Some("twelve".to_owned()));
+
+        cache.set("expired", "value", Duration::default());
+        assert_eq!(cache.get("expired"), None);
+
+        // Never more than `max_entries`
+        for i in 0..10 {
+            cache.set(&i.to_string(), "value", ttl);
+        }
+        assert!(cache.entries.lock().unwrap().len() <= 3);
+        assert_eq!(cache.get("9"), Some("value".to_owned()));
+    }
+
+    #[test]
+    fn markdown_revisions() {
+        assert_eq!(revision("# Title"), revision("# Title"));
+        assert_ne!(revision("# Title"), revision("# Other title"));
+    }
+}
@@ -0,0 +1,372 @@
+//! Categories of a blog, that can be nested in each other.
+//!
+//! Unlike tags, which are shared by the whole fediverse, categories belong to a blog,
+//! and an article in a category is also listed in all of its parent categories.
+
+use crate::{
+    blogs::Blog,
+    posts::Post,
+    schema::{categories, post_categories, posts, tags},
+    Connection, Error, Result,
+};
+use chrono::NaiveDateTime;
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+
+#[derive(Clone, Queryable, Identifiable)]
+#[table_name = "categories"]
+pub struct Category {
+    pub id: i32,
+    pub blog_id: i32,
+    pub parent_id: Option<i32>,
+    pub name: String,
+    pub slug: String,
+    pub creation_date: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "categories"]
+pub struct NewCategory {
+    pub blog_id: i32,
+    pub parent_id: Option<i32>,
+    pub name: String,
+    pub slug: String,
+}
+
+#[derive(Clone, Queryable, Identifiable)]
+pub struct PostCategory {
+    pub id: i32,
+    pub post_id: i32,
+    pub category_id: i32,
+}
+
+#[derive(Insertable)]
+#[table_name = "post_categories"]
+pub struct NewPostCategory {
+    pub post_id: i32,
+    pub category_id: i32,
+}
+
+impl Category {
+    insert!(categories, NewCategory);
+    get!(categories);
+    find_by!(categories, find_by_slug, blog_id as i32, slug as &str);
+    list_by!(categories, list_for_blog, blog_id as i32);
+
+    /// Finds the category of `blog` with this name, creating it under `parent` if needed.
+    pub fn find_or_create(
+        conn: &Connection,
+        blog: &Blog,
+        name: &str,
+        parent: Option<&Category>,
+    ) -> Result<Self> {
+        let name = name.trim();
+        if name.is_empty() || parent.map(|p| p.blog_id != blog.id).unwrap_or(false) {
+            return Err(Error::InvalidValue);
+        }
+        Self::find_by_slug(conn, blog.id, Post::slug(name)).or_else(|_| {
+            Self::insert(
+                conn,
+                NewCategory {
+                    blog_id: blog.id,
+                    parent_id: parent.map(|p| p.id),
+                    name: name.to_owned(),
+                    slug: Post::slug(name).to_owned(),
+                },
+            )
+        })
+    }
+
+    /// The categories of a blog, each one followed by its sub-categories, with their depth.
+    pub fn tree_for_blog(conn: &Connection, blog_id: i32) -> Result<Vec<(Self, usize)>> {
+        fn walk(
+            all: &[Category],
+            parent: Option<i32>,
+            depth: usize,
+            res: &mut Vec<(Category, usize)>,
+        ) {
+            for cat in all.iter().filter(|c| c.parent_id == parent) {
+                res.push((cat.clone(), depth));
+                walk(all, Some(cat.id), depth + 1, res);
+            }
+        }
+
+        let mut all = Self::list_for_blog(conn, blog_id)?;
+        all.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+        let mut res = vec![];
+        walk(&all, None, 0, &mut res);
+        Ok(res)
+    }
+
+    /// The parents of this category, starting from the top-level one.
+    pub fn ancestors(&self, conn: &Connection) -> Result<Vec<Self>> {
+        let mut res: Vec<Self> = vec![];
+        let mut parent = self.parent_id;
+        while let Some(id) = parent {
+            // Parents are checked when they are set, but better safe than looping forever
+            if id == self.id || res.iter().any(|c| c.id == id) {
+                break;
+            }
+            let cat = Self::get(conn, id)?;
+            parent = cat.parent_id;
+            res.insert(0, cat);
+        }
+        Ok(res)
+    }
+
+    /// The ids of this category and of all the categories nested in it.
+    pub fn descendant_ids(&self, conn: &Connection) -> Result<Vec<i32>> {
+        let all = Self::list_for_blog(conn, self.blog_id)?;
+        let mut res = vec![self.id];
+        let mut i = 0;
+        while i < res.len() {
+            let parent = res[i];
+            for cat in all.iter().filter(|c| c.parent_id == Some(parent)) {
+                if !res.contains(&cat.id) {
+                    res.push(cat.id);
+                }
+            }
+            i += 1;
+        }
+        Ok(res)
+    }
+
+    /// Moves this category under another one, or to the top level.
+    pub fn set_parent(&mut self, conn: &Connection, parent: Option<&Category>) -> Result<()> {
+        if let Some(parent) = parent {
+            if parent.blog_id != self.blog_id || self.descendant_ids(conn)?.contains(&parent.id) {
+                return Err(Error::InvalidValue);
+            }
+        }
+        self.parent_id = parent.map(|p| p.id);
+        diesel::update(&*self)
+            .set(categories::parent_id.eq(self.parent_id))
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+
+    /// The categories an article was directly put in.
+    pub fn for_post(conn: &Connection, post_id: i32) -> Result<Vec<Self>> {
+        let ids = post_categories::table
+            .filter(post_categories::post_id.eq(post_id))
+            .select(post_categories::category_id);
+        categories::table
+            .filter(categories::id.eq_any(ids))
+            .order(categories::name.asc())
+            .load::<Self>(conn)
+            .map_err(Error::from)
+    }
+
+    /// Replaces the categories of an article.
+    pub fn set_for_post(conn: &Connection, post_id: i32, categories: &[Category]) -> Result<()> {
+        diesel::delete(post_categories::table.filter(post_categories::post_id.eq(post_id)))
+            .execute(conn)?;
+        for cat in categories {
+            diesel::insert_into(post_categories::table)
+                .values(NewPostCategory {
+                    post_id,
+                    category_id: cat.id,
+                })
+                .execute(conn)?;
+        }
+        Ok(())
+    }
+
+    /// Tells if an article is in one of these categories, or in one of their sub-categories.
+    ///
+    /// Categories are designated by their name or their slug, in any blog.
+    pub fn post_is_in(conn: &Connection, post_id: i32, names: &[&str]) -> Result<bool> {
+        for cat in Self::for_post(conn, post_id)? {
+            let ancestors = cat.ancestors(conn)?;
+            if ancestors
+                .iter()
+                .chain(std::iter::once(&cat))
+                .any(|c| names.iter().any(|n| n == &c.name || n == &c.slug))
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// The published articles of this category and of its sub-categories, most recent first.
+    pub fn posts_page(&self, conn: &Connection, (min, max): (i32, i32)) -> Result<Vec<Post>> {
+        let ids = post_categories::table
+            .filter(post_categories::category_id.eq_any(self.descendant_ids(conn)?))
+            .select(post_categories::post_id);
+        posts::table
+            .filter(posts::id.eq_any(ids))
+            .filter(posts::published.eq(true))
+            .order(posts::creation_date.desc())
+            .offset(min.into())
+            .limit((max - min).into())
+            .load::<Post>(conn)
+            .map_err(Error::from)
+    }
+
+    pub fn count_posts(&self, conn: &Connection) -> Result<i64> {
+        let ids = post_categories::table
+            .filter(post_categories::category_id.eq_any(self.descendant_ids(conn)?))
+            .select(post_categories::post_id);
+        posts::table
+            .filter(posts::id.eq_any(ids))
+            .filter(posts::published.eq(true))
+            .count()
+            .get_result(conn)
+            .map_err(Error::from)
+    }
+
+    /// Turns a tag into a category: all the articles of `blog` with this tag are added
+    /// to the category, which is created if needed.
+    ///
+    /// Returns the category and the number of articles that were added to it.
+    pub fn promote_tag(
+        conn: &Connection,
+        blog: &Blog,
+        tag: &str,
+        parent: Option<&Category>,
+    ) -> Result<(Self, usize)> {
+        let category = Self::find_or_create(conn, blog, tag, parent)?;
+        let tagged = tags::table
+            .filter(tags::tag.eq(tag.trim()))
+            .select(tags::post_id);
+        let already_in = post_categories::table
+            .filter(post_categories::category_id.eq(category.id))
+            .select(post_categories::post_id);
+        let post_ids = posts::table
+            .filter(posts::blog_id.eq(blog.id))
+            .filter(posts::id.eq_any(tagged))
+            .filter(posts::id.ne_all(already_in))
+            .select(posts::id)
+            .load::<i32>(conn)?;
+        for post_id in &post_ids {
+            diesel::insert_into(post_categories::table)
+                .values(NewPostCategory {
+                    post_id: *post_id,
+                    category_id: category.id,
+                })
+                .execute(conn)?;
+        }
+        Ok((category, post_ids.len()))
+    }
+
+    /// Turns all the tags used on `blog` into top-level categories.
+    ///
+    /// Hashtags, that were written in the content of the articles, are left out.
+    pub fn promote_all_tags(conn: &Connection, blog: &Blog) -> Result<Vec<(Self, usize)>> {
+        tags::table
+            .inner_join(posts::table)
+            .filter(posts::blog_id.eq(blog.id))
+            .filter(tags::is_hashtag.eq(false))
+            .select(tags::tag)
+            .distinct()
+            .load::<String>(conn)?
+            .into_iter()
+            .map(|tag| Self::promote_tag(conn, blog, &tag, None))
+            .collect()
+    }
+
+    pub fn get_blog(&self, conn: &Connection) -> Result<Blog> {
+        Blog::get(conn, self.blog_id)
+    }
+
+    /// Deletes this category. Its sub-categories are moved to its parent.
+    pub fn delete(&self, conn: &Connection) -> Result<()> {
+        diesel::update(categories::table.filter(categories::parent_id.eq(self.id)))
+            .set(categories::parent_id.eq(self.parent_id))
+            .execute(conn)?;
+        diesel::delete(self)
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        inbox::tests::fill_database,
+        tags::{NewTag, Tag},
+        tests::db,
+    };
+    use diesel::Connection;
+
+    #[test]
+    fn nested_categories() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, _, blogs) = fill_database(conn);
+            let languages = Category::find_or_create(conn, &blogs[0], "Languages", None)?;
+            let mut rust = Category::find_or_create(conn, &blogs[0], "Rust", Some(&languages))?;
+            let async_rust = Category::find_or_create(conn, &blogs[0], "Async", Some(&rust))?;
+            assert!(Category::find_or_create(conn, &blogs[0], "  ", None).is_err());
+            assert!(Category::find_or_create(conn, &blogs[1], "Misc", Some(&rust)).is_err());
+
+            assert_eq!(
+                async_rust
+                    .ancestors(conn)?
+                    .iter()
+                    .map(|c| c.id)
+                    .collect::<Vec<_>>(),
+                vec![languages.id, rust.id]
+            );
+            assert_eq!(
+                languages.descendant_ids(conn)?,
+                vec![languages.id, rust.id, async_rust.id]
+            );
+            // A category can't be moved inside of itself
+            assert!(rust.set_parent(conn, Some(&async_rust)).is_err());
+            assert_eq!(
+                Category::tree_for_blog(conn, blogs[0].id)?
+                    .iter()
+                    .map(|(c, depth)| (c.id, *depth))
+                    .collect::<Vec<_>>(),
+                vec![(languages.id, 0), (rust.id, 1), (async_rust.id, 2)]
+            );
+
+            Category::set_for_post(conn, posts[0].id, &[async_rust.clone()])?;
+            assert_eq!(languages.count_posts(conn)?, 1);
+            assert_eq!(languages.posts_page(conn, (0, 10))?[0].id, posts[0].id);
+            assert!(Category::post_is_in(conn, posts[0].id, &["Languages"])?);
+            assert!(!Category::post_is_in(conn, posts[0].id, &["Misc"])?);
+
+            rust.delete(conn)?;
+            assert_eq!(
+                Category::get(conn, async_rust.id)?.parent_id,
+                Some(languages.id)
+            );
+            assert!(Category::post_is_in(conn, posts[0].id, &["Languages"])?);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn promote_tag() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, _, blogs) = fill_database(conn);
+            Tag::insert(
+                conn,
+                NewTag {
+                    tag: "Plume".to_owned(),
+                    is_hashtag: false,
+                    post_id: posts[0].id,
+                },
+            )?;
+            let (category, added) = Category::promote_tag(conn, &blogs[0], "Plume", None)?;
+            assert_eq!(added, 1);
+            assert_eq!(Category::for_post(conn, posts[0].id)?[0].id, category.id);
+            // Promoting it again doesn't add the articles twice
+            assert_eq!(Category::promote_tag(conn, &blogs[0], "Plume", None)?.1, 0);
+            assert_eq!(
+                Category::promote_all_tags(conn, &blogs[0])?
+                    .into_iter()
+                    .map(|(c, added)| (c.id, added))
+                    .collect::<Vec<_>>(),
+                vec![(category.id, 0)]
+            );
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,246 @@
+use crate::{
+    comments::Comment, instance::Instance, notifications::*, schema::comment_likes, users::User,
+    Connection, Error, Result, CONFIG,
+};
+use activitystreams::{
+    activity::{ActorAndObjectRef, Like as LikeAct, Undo},
+    base::AnyBase,
+    iri_string::types::IriString,
+    prelude::*,
+};
+use chrono::NaiveDateTime;
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+use plume_common::activity_pub::{
+    inbox::{AsActor, AsObject, FromId},
+    sign::Signer,
+    PUBLIC_VISIBILITY,
+};
+
+#[derive(Clone, Queryable, Identifiable)]
+pub struct CommentLike {
+    pub id: i32,
+    pub user_id: i32,
+    pub comment_id: i32,
+    pub creation_date: NaiveDateTime,
+    pub ap_url: String,
+}
+
+#[derive(Default, Insertable)]
+#[table_name = "comment_likes"]
+pub struct NewCommentLike {
+    pub user_id: i32,
+    pub comment_id: i32,
+    pub ap_url: String,
+}
+
+impl CommentLike {
+    insert!(comment_likes, NewCommentLike);
+    get!(comment_likes);
+    find_by!(comment_likes, find_by_ap_url, ap_url as &str);
+    find_by!(
+        comment_likes,
+        find_by_user_on_comment,
+        user_id as i32,
+        comment_id as i32
+    );
+
+    pub fn count_for_comment(conn: &Connection, comment_id: i32) -> Result<i64> {
+        comment_likes::table
+            .filter(comment_likes::comment_id.eq(comment_id))
+            .count()
+            .get_result(conn)
+            .map_err(Error::from)
+    }
+
+    pub fn to_activity(&self, conn: &Connection) -> Result<LikeAct> {
+        let user = User::get(conn, self.user_id)?;
+        let mut act = LikeAct::new(
+            user.ap_url.parse::<IriString>()?,
+            Comment::get(conn, self.comment_id)?
+                .ap_url
+                .unwrap_or_default()
+                .parse::<IriString>()?,
+        );
+        act.set_many_tos(vec![PUBLIC_VISIBILITY.parse::<IriString>()?]);
+        act.set_many_ccs(vec![user.followers_endpoint.parse::<IriString>()?]);
+        act.set_id(self.ap_url.parse::<IriString>()?);
+
+        Ok(act)
+    }
+
+    pub fn notify(&self, conn: &Connection) -> Result<()> {
+        let author = Comment::get(conn, self.comment_id)?.get_author(conn)?;
+        if author.is_local() && author.id != self.user_id {
+            Notification::notify_from(
+                conn,
+                &User::get(conn, self.user_id)?,
+                "",
+                NewNotification {
+                    kind: notification_kind::COMMENT_LIKE.to_string(),
+                    object_id: self.id,
+                    user_id: author.id,
+                    group_key: None,
+                    filtered: false,
+                },
+                Some(self.comment_id),
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn build_undo(&self, conn: &Connection) -> Result<Undo> {
+        let user = User::get(conn, self.user_id)?;
+        let mut act = Undo::new(
+            user.ap_url.parse::<IriString>()?,
+            AnyBase::from_extended(self.to_activity(conn)?)?,
+        );
+        act.set_id(format!("{}#delete", self.ap_url).parse::<IriString>()?);
+        act.set_many_tos(vec![PUBLIC_VISIBILITY.parse::<IriString>()?]);
+        act.set_many_ccs(vec![user.followers_endpoint.parse::<IriString>()?]);
+
+        Ok(act)
+    }
+}
+
+impl AsObject<User, LikeAct, &Connection> for Comment {
+    type Error = Error;
+    type Output = CommentLike;
+
+    fn activity(self, conn: &Connection, actor: User, id: &str) -> Result<CommentLike> {
+        let res = CommentLike::insert(
+            conn,
+            NewCommentLike {
+                comment_id: self.id,
+                user_id: actor.id,
+                ap_url: id.to_string(),
+            },
+        )?;
+        res.notify(conn)?;
+        Ok(res)
+    }
+}
+
+impl FromId<Connection> for CommentLike {
+    type Error = Error;
+    type Object = LikeAct;
+
+    fn from_db(conn: &Connection, id: &str) -> Result<Self> {
+        CommentLike::find_by_ap_url(conn, id)
+    }
+
+    fn from_activity(conn: &Connection, act: LikeAct) -> Result<Self> {
+        let res = CommentLike::insert(
+            conn,
+            NewCommentLike {
+                comment_id: Comment::from_id(
+                    conn,
+                    act.object_field_ref()
+                        .as_single_id()
+                        .ok_or(Error::MissingApProperty)?
+                        .as_str(),
+                    None,
+                    CONFIG.proxy(),
+                )
+                .map_err(|(_, e)| e)?
+                .id,
+                user_id: User::from_id(
+                    conn,
+                    act.actor_field_ref()
+                        .as_single_id()
+                        .ok_or(Error::MissingApProperty)?
+                        .as_str(),
+                    None,
+                    CONFIG.proxy(),
+                )
+                .map_err(|(_, e)| e)?
+                .id,
+                ap_url: act
+                    .id_unchecked()
+                    .ok_or(Error::MissingApProperty)?
+                    .to_string(),
+            },
+        )?;
+        res.notify(conn)?;
+        Ok(res)
+    }
+
+    fn get_sender() -> &'static dyn Signer {
+        Instance::get_local_instance_user().expect("Failed to local instance user")
+    }
+}
+
+impl AsObject<User, Undo, &Connection> for CommentLike {
+    type Error = Error;
+    type Output = ();
+
+    fn activity(self, conn: &Connection, actor: User, _id: &str) -> Result<()> {
+        if actor.id == self.user_id {
+            diesel::delete(&self).execute(conn)?;
+
+            Notification::retract(
+                conn,
+                notification_kind::COMMENT_LIKE,
+                self.id,
+                self.comment_id,
+            )?;
+            Ok(())
+        } else {
+            Err(Error::Unauthorized)
+        }
+    }
+}
+
+impl NewCommentLike {
+    pub fn new(c: &Comment, u: &User) -> Self {
+        let ap_url = format!("{}like/{}", u.ap_url, c.ap_url.clone().unwrap_or_default());
+        NewCommentLike {
+            comment_id: c.id,
+            user_id: u.id,
+            ap_url,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diesel::Connection;
+    use crate::{
+        comments::NewComment, inbox::tests::fill_database, safe_string::SafeString, tests::db,
+    };
+    use assert_json_diff::assert_json_eq;
+    use serde_json::{json, to_value};
+
+    #[test]
+    fn to_activity() {
+        let conn = db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, users, _blogs) = fill_database(&conn);
+            let comment = Comment::insert(
+                &conn,
+                NewComment {
+                    content: SafeString::new("A comment"),
+                    post_id: posts[0].id,
+                    author_id: users[1].id,
+                    public_visibility: true,
+                    ..NewComment::default()
+                },
+            )?;
+            let like = CommentLike::insert(&conn, NewCommentLike::new(&comment, &users[0]))?;
+            assert_eq!(CommentLike::count_for_comment(&conn, comment.id)?, 1);
+            let act = like.to_activity(&conn)?;
+
+            let expected = json!({
+                "actor": "https://plu.me/@/admin/",
+                "cc": ["https://plu.me/@/admin/followers"],
+                "id": format!("https://plu.me/@/admin/like/https://plu.me/~/BlogName/testing/comment/{}", comment.id),
+                "object": format!("https://plu.me/~/BlogName/testing/comment/{}", comment.id),
+                "to": ["https://www.w3.org/ns/activitystreams#Public"],
+                "type": "Like",
+            });
+            assert_json_eq!(to_value(act)?, expected);
+
+            Ok(())
+        });
+    }
+}
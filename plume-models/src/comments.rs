@@ -1,14 +1,23 @@
 use crate::{
+    blogs::Blog,
+    cache::{self, Entry},
+    comment_likes::CommentLike,
     comment_seers::{CommentSeers, NewCommentSeers},
     instance::Instance,
+    media_policies::MediaPolicy,
     medias::Media,
     mentions::Mention,
     notifications::*,
     posts::Post,
+    quotes::Quote,
+    rate_limits::{self, Bucket, Client},
     safe_string::SafeString,
     schema::comments,
+    spam,
+    thread_subscriptions::ThreadSubscription,
     users::User,
-    Connection, Error, Result, CONFIG,
+    CommentEvent::*,
+    Connection, Error, Result, COMMENT_CHAN, CONFIG,
 };
 use activitystreams::{
     activity::{Create, Delete},
@@ -30,7 +39,50 @@ use plume_common::{
     },
     utils,
 };
+use riker::actors::{Publish, Tell};
+use std::cmp::Reverse;
 use std::collections::HashSet;
+use std::sync::Arc;
+
+/// How long the same comment can't be posted again on an article.
+const DUPLICATE_WINDOW_MINUTES: i64 = 10;
+
+/// How comments are ordered under a post, or under another comment.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CommentOrder {
+    Oldest = 0,
+    Newest = 1,
+    MostLiked = 2,
+}
+
+impl From<i32> for CommentOrder {
+    fn from(order: i32) -> Self {
+        match order {
+            1 => CommentOrder::Newest,
+            2 => CommentOrder::MostLiked,
+            _ => CommentOrder::Oldest,
+        }
+    }
+}
+
+impl CommentOrder {
+    fn sort(self, conn: &Connection, comments: &mut Vec<Comment>) {
+        match self {
+            CommentOrder::Oldest => comments.sort_by_key(|c| (c.creation_date, c.id)),
+            CommentOrder::Newest => {
+                comments.sort_by_key(|c| (c.creation_date, c.id));
+                comments.reverse();
+            }
+            CommentOrder::MostLiked => comments.sort_by_cached_key(|c| {
+                (
+                    Reverse(CommentLike::count_for_comment(conn, c.id).unwrap_or(0)),
+                    c.creation_date,
+                    c.id,
+                )
+            }),
+        }
+    }
+}
 
 #[derive(Queryable, Identifiable, Clone, AsChangeset)]
 pub struct Comment {
@@ -44,6 +96,11 @@ pub struct Comment {
     pub sensitive: bool,
     pub spoiler_text: String,
     pub public_visibility: bool,
+    /// Held comments look like spam, and are only shown once an author of the blog approved
+    /// them (see `spam`)
+    pub held: bool,
+    /// The spam score of comments received from other instances
+    pub spam_score: Option<i32>,
 }
 
 #[derive(Insertable, Default)]
@@ -57,6 +114,8 @@ pub struct NewComment {
     pub sensitive: bool,
     pub spoiler_text: String,
     pub public_visibility: bool,
+    pub held: bool,
+    pub spam_score: Option<i32>,
 }
 
 impl Comment {
@@ -69,6 +128,8 @@ impl Comment {
             ));
             let _: Comment = inserted.save_changes(conn)?;
         }
+        cache::forget_article(inserted.post_id);
+        inserted.publish_published();
         Ok(inserted)
     });
     get!(comments);
@@ -80,6 +141,69 @@ impl Comment {
         User::get(conn, self.author_id)
     }
 
+    /// Whether the author already posted a comment with this content on the article, less
+    /// than `DUPLICATE_WINDOW_MINUTES` ago.
+    pub fn is_recent_duplicate(
+        conn: &Connection,
+        author_id: i32,
+        post_id: i32,
+        content: &SafeString,
+    ) -> Result<bool> {
+        let since =
+            chrono::Utc::now().naive_utc() - chrono::Duration::minutes(DUPLICATE_WINDOW_MINUTES);
+        comments::table
+            .filter(comments::author_id.eq(author_id))
+            .filter(comments::post_id.eq(post_id))
+            .filter(comments::creation_date.gt(since))
+            .filter(comments::content.eq(content))
+            .count()
+            .get_result::<i64>(conn)
+            .map(|count| count > 0)
+            .map_err(Error::from)
+    }
+
+    /// Checks a comment delivered to an inbox, before it is saved: a single remote actor
+    /// can't flood the articles with comments, or post the same one again.
+    ///
+    /// Comments that are fetched, to show a thread for instance, are not checked.
+    pub(crate) fn check_delivery(conn: &Connection, act: &serde_json::Value) -> Result<()> {
+        let note = &act["object"];
+        if act["type"] != "Create" || note["type"] != "Note" {
+            return Ok(());
+        }
+        let id = note["id"].as_str().ok_or(Error::MissingApProperty)?;
+        if Comment::find_by_ap_url(conn, id).is_ok() {
+            return Ok(());
+        }
+        let actor = act["actor"]
+            .as_str()
+            .or_else(|| act["actor"]["id"].as_str())
+            .ok_or(Error::MissingApProperty)?;
+        rate_limits::take(Bucket::Comment, &[Client::Actor(actor.to_owned())])
+            .map_err(|_| Error::Unauthorized)?;
+
+        let author = match User::find_by_ap_url(conn, actor) {
+            Ok(author) => author,
+            Err(_) => return Ok(()),
+        };
+        let previous_url = note["inReplyTo"]
+            .as_str()
+            .or_else(|| note["inReplyTo"]["id"].as_str())
+            .unwrap_or_default();
+        let post_id = match Comment::find_by_ap_url(conn, previous_url) {
+            Ok(previous) => previous.post_id,
+            Err(_) => match Post::find_by_ap_url(conn, previous_url) {
+                Ok(post) => post.id,
+                Err(_) => return Ok(()),
+            },
+        };
+        let content = SafeString::new_remote(note["content"].as_str().unwrap_or_default());
+        if Comment::is_recent_duplicate(conn, author.id, post_id, &content)? {
+            return Err(Error::InvalidValue);
+        }
+        Ok(())
+    }
+
     pub fn get_post(&self, conn: &Connection) -> Result<Post> {
         Post::get(conn, self.post_id)
     }
@@ -103,22 +227,129 @@ impl Comment {
             .map_err(Error::from)
     }
 
+    /// Number of comments between this one and the post, 1 for a direct
+    /// reply to the post.
+    pub fn depth(&self, conn: &Connection) -> Result<i32> {
+        let mut depth = 1;
+        let mut parent = self.in_response_to_id;
+        while let Some(id) = parent {
+            depth += 1;
+            parent = Comment::get(conn, id)?.in_response_to_id;
+        }
+        Ok(depth)
+    }
+
+    /// The comment this one is displayed as an answer to.
+    ///
+    /// It is the one it replies to, unless the blog limits the
+    /// nesting of comments: in that case, replies that are too deep
+    /// are attached to their ancestor at the maximum depth.
+    pub fn displayed_parent(
+        &self,
+        conn: &Connection,
+        max_depth: Option<i32>,
+    ) -> Result<Option<i32>> {
+        let max_depth = match (self.in_response_to_id, max_depth) {
+            (Some(_), Some(max_depth)) => max_depth.max(1),
+            _ => return Ok(self.in_response_to_id),
+        };
+
+        let mut ancestors = vec![];
+        let mut parent = self.in_response_to_id;
+        while let Some(id) = parent {
+            ancestors.push(id);
+            parent = Comment::get(conn, id)?.in_response_to_id;
+        }
+        // ancestors are ordered from the parent to the top-level comment,
+        // and the top-level comment is at depth 1
+        let parent_depth = ancestors.len() as i32;
+        if parent_depth <= max_depth {
+            Ok(self.in_response_to_id)
+        } else {
+            Ok(ancestors.get((parent_depth - max_depth) as usize).cloned())
+        }
+    }
+
+    /// The content of the comment, with the images that the media policies don't allow
+    /// removed, if it comes from another instance.
+    pub fn displayed_content(&self, conn: &Connection) -> String {
+        MediaPolicy::apply_to_html(conn, self.content.get(), self.ap_url.as_deref())
+    }
+
     pub fn can_see(&self, conn: &Connection, user: Option<&User>) -> bool {
-        self.public_visibility
-            || user
-                .as_ref()
-                .map(|u| CommentSeers::can_see(conn, self, u).unwrap_or(false))
-                .unwrap_or(false)
+        !self.held
+            && (self.public_visibility
+                || user
+                    .as_ref()
+                    .map(|u| CommentSeers::can_see(conn, self, u).unwrap_or(false))
+                    .unwrap_or(false))
+    }
+
+    /// The held comments on the articles of a blog, the most recent ones first.
+    pub fn list_held_for_blog(conn: &Connection, blog: &Blog) -> Result<Vec<Self>> {
+        use crate::schema::posts;
+        comments::table
+            .filter(comments::held.eq(true))
+            .filter(
+                comments::post_id.eq_any(
+                    posts::table
+                        .filter(posts::blog_id.eq(blog.id))
+                        .select(posts::id),
+                ),
+            )
+            .order(comments::creation_date.desc())
+            .load::<Self>(conn)
+            .map_err(Error::from)
+    }
+
+    /// Publishes a held comment, notifying the authors of the article.
+    pub fn approve(&self, conn: &Connection) -> Result<()> {
+        diesel::update(self)
+            .set(comments::held.eq(false))
+            .execute(conn)?;
+        let approved = Comment::get(conn, self.id)?;
+        cache::forget_article(self.post_id);
+        approved.publish_published();
+        approved.notify(conn)
+    }
+
+    /// Deletes this comment locally, without federating it.
+    pub fn delete(&self, conn: &Connection) -> Result<()> {
+        for m in Mention::list_for_comment(conn, self.id)? {
+            for n in Notification::find_for_mention(conn, &m)? {
+                n.delete(conn)?;
+            }
+            m.delete(conn)?;
+        }
+
+        for n in Notification::find_for_comment(conn, self)? {
+            n.delete(conn)?;
+        }
+
+        diesel::update(comments::table)
+            .filter(comments::in_response_to_id.eq(self.id))
+            .set(comments::in_response_to_id.eq(self.in_response_to_id))
+            .execute(conn)?;
+        diesel::delete(self).execute(conn)?;
+        cache::forget_article(self.post_id);
+        cache::forget_comment_markdown(self.id);
+        self.publish_deleted();
+        Ok(())
     }
 
     pub fn to_activity(&self, conn: &Connection) -> Result<Note> {
         let author = User::get(conn, self.author_id)?;
-        let (html, mentions, _hashtags) = utils::md_to_html(
-            self.content.get().as_ref(),
-            Some(&Instance::get_local()?.public_domain),
-            true,
-            Some(Media::get_media_processor(conn, vec![&author])),
-        );
+        let revision = cache::revision(self.content.get());
+        let (html, mentions) =
+            cache::get_or_insert(Entry::CommentNote(self.id, &revision), || {
+                let (html, mentions, _hashtags) = utils::md_to_html(
+                    self.content.get().as_ref(),
+                    Some(&Instance::get_local()?.public_domain),
+                    true,
+                    Some(Media::get_media_processor(conn, vec![&author])),
+                );
+                Ok::<_, Error>((html, mentions))
+            })?;
 
         let mut note = Note::new();
         let to = vec![PUBLIC_VISIBILITY.parse::<IriString>()?];
@@ -131,7 +362,8 @@ impl Comment {
         );
         note.set_summary(self.spoiler_text.clone());
         note.set_content(html);
-        note.set_in_reply_to(self.in_response_to_id.map_or_else(
+        let max_depth = self.get_post(conn)?.get_blog(conn)?.comments_max_depth;
+        note.set_in_reply_to(self.displayed_parent(conn, max_depth)?.map_or_else(
             || Post::get(conn, self.post_id).map(|post| post.ap_url),
             |id| Comment::get(conn, id).map(|comment| comment.ap_url.unwrap_or_default()),
         )?);
@@ -141,11 +373,12 @@ impl Comment {
         );
         note.set_attributed_to(author.into_id().parse::<IriString>()?);
         note.set_many_tos(to);
-        note.set_many_tags(mentions.into_iter().filter_map(|m| {
-            Mention::build_activity(conn, &m)
-                .map(|mention| mention.into_any_base().expect("Can convert"))
-                .ok()
-        }));
+        note.set_many_tags(
+            Mention::resolve_all(conn, mentions)
+                .mentions
+                .into_iter()
+                .map(|mention| mention.into_any_base().expect("Can convert")),
+        );
         Ok(note)
     }
 
@@ -177,23 +410,31 @@ impl Comment {
     }
 
     pub fn notify(&self, conn: &Connection) -> Result<()> {
+        let mut notified = Mention::list_for_comment(conn, self.id)?
+            .iter()
+            .filter_map(|m| m.get_mentioned(conn).ok())
+            .map(|u| u.id)
+            .collect::<Vec<_>>();
+        let commenter = self.get_author(conn)?;
         for author in self.get_post(conn)?.get_authors(conn)? {
-            if Mention::list_for_comment(conn, self.id)?
-                .iter()
-                .all(|m| m.get_mentioned(conn).map(|u| u != author).unwrap_or(true))
-                && author.is_local()
-            {
-                Notification::insert(
+            if !notified.contains(&author.id) && author.is_local() {
+                Notification::notify_from(
                     conn,
+                    &commenter,
+                    self.content.get(),
                     NewNotification {
                         kind: notification_kind::COMMENT.to_string(),
                         object_id: self.id,
                         user_id: author.id,
+                        group_key: None,
+                        filtered: false,
                     },
+                    None,
                 )?;
             }
+            notified.push(author.id);
         }
-        Ok(())
+        ThreadSubscription::notify(conn, self, &notified)
     }
 
     pub fn build_delete(&self, conn: &Connection) -> Result<Delete> {
@@ -215,6 +456,36 @@ impl Comment {
 
         Ok(act)
     }
+
+    /// Adds it to the search index or removes it, after its author changed whether it can be
+    /// included.
+    pub(crate) fn reindex(&self, indexable: bool) {
+        if indexable {
+            self.publish_published();
+        } else {
+            self.publish_deleted();
+        }
+    }
+
+    fn publish_published(&self) {
+        COMMENT_CHAN.tell(
+            Publish {
+                msg: CommentPublished(Arc::new(self.clone())),
+                topic: "comment.published".into(),
+            },
+            None,
+        )
+    }
+
+    fn publish_deleted(&self) {
+        COMMENT_CHAN.tell(
+            Publish {
+                msg: CommentDeleted(Arc::new(self.clone())),
+                topic: "comment.deleted".into(),
+            },
+            None,
+        )
+    }
 }
 
 impl FromId<Connection> for Comment {
@@ -252,16 +523,38 @@ impl FromId<Connection> for Comment {
 
             let summary = note.summary().and_then(|summary| summary.to_as_string());
             let sensitive = summary.is_some();
+            let content = SafeString::new_remote(
+                &note
+                    .content()
+                    .ok_or(Error::MissingApProperty)?
+                    .to_as_string()
+                    .ok_or(Error::InvalidValue)?,
+            );
+            let post_id = match previous_comment {
+                Ok(ref previous) => previous.post_id,
+                Err(_) => Post::find_by_ap_url(conn, previous_url.as_str())?.id,
+            };
+            let author = User::from_id(
+                conn,
+                &note
+                    .attributed_to()
+                    .ok_or(Error::MissingApProperty)?
+                    .to_as_uri()
+                    .ok_or(Error::MissingApProperty)?,
+                None,
+                CONFIG.proxy(),
+            )
+            .map_err(|(_, e)| e)?;
+            let spam_score = if author.is_local() {
+                None
+            } else {
+                Some(spam::score(conn, &author, content.get())?)
+            };
+            let held = spam_score.map_or(false, spam::is_spam);
             let comm = Comment::insert(
                 conn,
                 NewComment {
-                    content: SafeString::new(
-                        &note
-                            .content()
-                            .ok_or(Error::MissingApProperty)?
-                            .to_as_string()
-                            .ok_or(Error::InvalidValue)?,
-                    ),
+                    content,
                     spoiler_text: summary.unwrap_or_default(),
                     ap_url: Some(
                         note.id_unchecked()
@@ -269,23 +562,12 @@ impl FromId<Connection> for Comment {
                             .to_string(),
                     ),
                     in_response_to_id: previous_comment.iter().map(|c| c.id).next(),
-                    post_id: previous_comment.map(|c| c.post_id).or_else(|_| {
-                        Ok(Post::find_by_ap_url(conn, previous_url.as_str())?.id) as Result<i32>
-                    })?,
-                    author_id: User::from_id(
-                        conn,
-                        &note
-                            .attributed_to()
-                            .ok_or(Error::MissingApProperty)?
-                            .to_as_uri()
-                            .ok_or(Error::MissingApProperty)?,
-                        None,
-                        CONFIG.proxy(),
-                    )
-                    .map_err(|(_, e)| e)?
-                    .id,
+                    post_id,
+                    author_id: author.id,
                     sensitive,
                     public_visibility,
+                    held,
+                    spam_score,
                 },
             )?;
 
@@ -299,8 +581,14 @@ impl FromId<Connection> for Comment {
                     }
                     let m = m.unwrap();
                     let not_author = m.href().ok_or(Error::MissingApProperty)? != author_url;
-                    let _ = Mention::from_activity(conn, &m, comm.id, false, not_author);
+                    let _ =
+                        Mention::from_activity(conn, &m, comm.id, false, not_author && !comm.held);
                 }
+                Quote::set_for_comment(
+                    conn,
+                    comm.id,
+                    Quote::urls_from_tags(&serde_json::to_value(tags)?),
+                )?;
             }
             comm
         };
@@ -345,7 +633,9 @@ impl FromId<Connection> for Comment {
             }
         }
 
-        comm.notify(conn)?;
+        if !comm.held {
+            comm.notify(conn)?;
+        }
         Ok(comm)
     }
 
@@ -372,27 +662,16 @@ impl AsObject<User, Delete, &Connection> for Comment {
         if self.author_id != actor.id {
             return Err(Error::Unauthorized);
         }
-
-        for m in Mention::list_for_comment(conn, self.id)? {
-            for n in Notification::find_for_mention(conn, &m)? {
-                n.delete(conn)?;
-            }
-            m.delete(conn)?;
-        }
-
-        for n in Notification::find_for_comment(conn, &self)? {
-            n.delete(conn)?;
-        }
-
-        diesel::update(comments::table)
-            .filter(comments::in_response_to_id.eq(self.id))
-            .set(comments::in_response_to_id.eq(self.in_response_to_id))
-            .execute(conn)?;
-        diesel::delete(&self).execute(conn)?;
-        Ok(())
+        self.delete(conn)
     }
 }
 
+#[derive(Clone, Debug)]
+pub enum CommentEvent {
+    CommentPublished(Arc<Comment>),
+    CommentDeleted(Arc<Comment>),
+}
+
 pub struct CommentTree {
     pub comment: Comment,
     pub responses: Vec<CommentTree>,
@@ -400,20 +679,74 @@ pub struct CommentTree {
 
 impl CommentTree {
     pub fn from_post(conn: &Connection, p: &Post, user: Option<&User>) -> Result<Vec<Self>> {
-        Ok(Comment::list_by_post(conn, p.id)?
+        let blog = Blog::get(conn, p.blog_id)?;
+        let order = CommentOrder::from(blog.comments_order);
+        let mut comments = Comment::list_by_post(conn, p.id)?
             .into_iter()
             .filter(|c| c.in_response_to_id.is_none())
             .filter(|c| c.can_see(conn, user))
-            .filter_map(|c| Self::from_comment(conn, c, user).ok())
+            .collect::<Vec<_>>();
+        order.sort(conn, &mut comments);
+        Ok(comments
+            .into_iter()
+            .filter_map(|c| Self::build(conn, c, user, order, 1, blog.comments_max_depth).ok())
             .collect())
     }
 
     pub fn from_comment(conn: &Connection, comment: Comment, user: Option<&User>) -> Result<Self> {
-        let responses = comment
+        let blog = comment.get_post(conn)?.get_blog(conn)?;
+        let depth = comment.depth(conn)?;
+        Self::build(
+            conn,
+            comment,
+            user,
+            CommentOrder::from(blog.comments_order),
+            depth,
+            blog.comments_max_depth,
+        )
+    }
+
+    fn build(
+        conn: &Connection,
+        comment: Comment,
+        user: Option<&User>,
+        order: CommentOrder,
+        depth: i32,
+        max_depth: Option<i32>,
+    ) -> Result<Self> {
+        if max_depth.map_or(false, |max| depth >= max) {
+            // Too deep: all the replies are flattened at the next level
+            let mut responses = vec![];
+            let mut to_visit = comment.get_responses(conn)?;
+            while let Some(response) = to_visit.pop() {
+                if !response.can_see(conn, user) {
+                    continue;
+                }
+                to_visit.extend(response.get_responses(conn)?);
+                responses.push(response);
+            }
+            order.sort(conn, &mut responses);
+            return Ok(CommentTree {
+                comment,
+                responses: responses
+                    .into_iter()
+                    .map(|c| CommentTree {
+                        comment: c,
+                        responses: vec![],
+                    })
+                    .collect(),
+            });
+        }
+
+        let mut responses = comment
             .get_responses(conn)?
             .into_iter()
             .filter(|c| c.can_see(conn, user))
-            .filter_map(|c| Self::from_comment(conn, c, user).ok())
+            .collect::<Vec<_>>();
+        order.sort(conn, &mut responses);
+        let responses = responses
+            .into_iter()
+            .filter_map(|c| Self::build(conn, c, user, order, depth + 1, max_depth).ok())
             .collect();
         Ok(CommentTree { comment, responses })
     }
@@ -445,6 +778,8 @@ mod tests {
                 sensitive: true,
                 spoiler_text: "My CW".into(),
                 public_visibility: true,
+                held: false,
+                spam_score: None,
             },
         )
         .unwrap();
@@ -498,6 +833,8 @@ mod tests {
                     sensitive: false,
                     spoiler_text: "".into(),
                     public_visibility: true,
+                    held: false,
+                    spam_score: None,
                 },
             )
             .unwrap();
@@ -576,6 +913,84 @@ mod tests {
         });
     }
 
+    #[test]
+    fn recent_duplicates() {
+        let conn = db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (comment, posts, users, _blogs) = prepare_activity(&conn);
+            assert!(Comment::is_recent_duplicate(
+                &conn,
+                users[0].id,
+                posts[0].id,
+                &comment.content
+            )?);
+            assert!(!Comment::is_recent_duplicate(
+                &conn,
+                users[1].id,
+                posts[0].id,
+                &comment.content
+            )?);
+            assert!(!Comment::is_recent_duplicate(
+                &conn,
+                users[0].id,
+                posts[1].id,
+                &comment.content
+            )?);
+            assert!(!Comment::is_recent_duplicate(
+                &conn,
+                users[0].id,
+                posts[0].id,
+                &SafeString::new("Something else")
+            )?);
+
+            // Delivered again, with another id
+            let mut act = json!({
+                "type": "Create",
+                "actor": users[0].ap_url,
+                "object": {
+                    "type": "Note",
+                    "id": "https://plu.me/comment/copy",
+                    "inReplyTo": posts[0].ap_url,
+                    "content": comment.content.get(),
+                },
+            });
+            assert!(Comment::check_delivery(&conn, &act).is_err());
+            act["object"]["content"] = json!("Something else");
+            assert!(Comment::check_delivery(&conn, &act).is_ok());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn held_comments() {
+        let conn = db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (_comment, posts, users, _blogs) = prepare_activity(&conn);
+            let blog = Blog::get(&conn, posts[0].blog_id)?;
+            let held = Comment::insert(
+                &conn,
+                NewComment {
+                    content: SafeString::new("Cheap pills"),
+                    post_id: posts[0].id,
+                    author_id: users[1].id,
+                    public_visibility: true,
+                    held: true,
+                    spam_score: Some(6),
+                    ..NewComment::default()
+                },
+            )?;
+            assert!(!held.can_see(&conn, Some(&users[0])));
+            let queue = Comment::list_held_for_blog(&conn, &blog)?;
+            assert_eq!(queue.len(), 1);
+            assert_eq!(queue[0].id, held.id);
+
+            held.approve(&conn)?;
+            assert!(Comment::get(&conn, held.id)?.can_see(&conn, None));
+            assert!(Comment::list_held_for_blog(&conn, &blog)?.is_empty());
+            Ok(())
+        });
+    }
+
     #[test]
     fn build_delete() {
         let conn = db();
@@ -599,4 +1014,59 @@ mod tests {
             Ok(())
         });
     }
+
+    #[test]
+    fn tree_max_depth() {
+        let conn = db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (comment, posts, users, mut blogs) = prepare_activity(&conn);
+            blogs[0].comments_max_depth = Some(1);
+            let _: Blog = blogs[0].save_changes(&*conn)?;
+
+            let reply = |parent: &Comment| {
+                Comment::insert(
+                    &conn,
+                    NewComment {
+                        content: SafeString::new("A reply"),
+                        in_response_to_id: Some(parent.id),
+                        post_id: posts[0].id,
+                        author_id: users[1].id,
+                        public_visibility: true,
+                        ..NewComment::default()
+                    },
+                )
+            };
+            let answer = reply(&comment)?;
+            let deep_answer = reply(&answer)?;
+
+            let tree = CommentTree::from_post(&conn, &posts[0], None)?;
+            assert_eq!(tree.len(), 1);
+            let mut responses = tree[0]
+                .responses
+                .iter()
+                .map(|r| {
+                    assert!(r.responses.is_empty());
+                    r.comment.id
+                })
+                .collect::<Vec<_>>();
+            responses.sort_unstable();
+            assert_eq!(responses, vec![answer.id, deep_answer.id]);
+
+            assert_eq!(deep_answer.depth(&conn)?, 3);
+            assert_eq!(
+                deep_answer.displayed_parent(&conn, Some(1))?,
+                Some(comment.id)
+            );
+            assert_eq!(deep_answer.displayed_parent(&conn, None)?, Some(answer.id));
+            let note = deep_answer.to_activity(&conn)?;
+            assert_eq!(
+                note.in_reply_to()
+                    .and_then(|r| r.as_single_id())
+                    .map(|id| id.to_string()),
+                comment.ap_url
+            );
+
+            Ok(())
+        });
+    }
 }
@@ -0,0 +1,476 @@
+use crate::{
+    blogs::Blog,
+    import::html_to_markdown,
+    outgoing::{self, Limits},
+    posts::Post,
+    schema::connectors,
+    tags::Tag,
+    Connection, Error, Result,
+};
+use chrono::NaiveDateTime;
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+use openssl::{
+    hash::MessageDigest,
+    pkey::PKey,
+    rand::rand_bytes,
+    sha::sha256,
+    sign::Signer,
+    symm::{self, Cipher},
+};
+use reqwest::blocking::RequestBuilder;
+use serde_json::Value;
+use std::{env::var, str::FromStr};
+
+/// The endpoint used to publish articles on dev.to.
+pub const DEVTO_API: &str = "https://dev.to/api/articles";
+
+/// dev.to doesn't accept more tags than that.
+const DEVTO_MAX_TAGS: usize = 4;
+
+/// How long sending an article can take, in seconds.
+const SEND_TIMEOUT: u64 = 30;
+
+/// Only the URL of the copy is read from the response, that should be much smaller.
+const MAX_RESPONSE_SIZE: u64 = 1024 * 1024;
+
+/// Lengths of the nonce and of the tag of encrypted secrets.
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// An external service articles can be mirrored to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConnectorKind {
+    /// Publishes a copy of the article on dev.to, linking back to the original
+    DevTo,
+    /// Sends the article as JSON to any URL
+    Webhook,
+}
+
+impl ConnectorKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ConnectorKind::DevTo => "devto",
+            ConnectorKind::Webhook => "webhook",
+        }
+    }
+}
+
+impl FromStr for ConnectorKind {
+    type Err = Error;
+
+    fn from_str(kind: &str) -> Result<Self> {
+        match kind {
+            "devto" => Ok(ConnectorKind::DevTo),
+            "webhook" => Ok(ConnectorKind::Webhook),
+            _ => Err(Error::InvalidValue),
+        }
+    }
+}
+
+/// A service the new articles of a blog are mirrored to.
+#[derive(Clone, Queryable, Identifiable)]
+pub struct Connector {
+    pub id: i32,
+    pub blog_id: i32,
+    pub kind: String,
+    pub url: String,
+    /// The dev.to API key, or the secret used to sign webhooks, encrypted with a key
+    /// derived from `ROCKET_SECRET_KEY`. Use `secret` to read it.
+    pub api_key: Option<String>,
+    pub enabled: bool,
+    pub creation_date: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "connectors"]
+pub struct NewConnector {
+    pub blog_id: i32,
+    pub kind: String,
+    pub url: String,
+    pub api_key: Option<String>,
+}
+
+impl Connector {
+    insert!(connectors, NewConnector);
+    get!(connectors);
+    list_by!(connectors, list_for_blog, blog_id as i32);
+
+    /// Adds a connector to `blog`, checking its settings.
+    pub fn create(
+        conn: &Connection,
+        blog: &Blog,
+        kind: ConnectorKind,
+        url: &str,
+        api_key: Option<&str>,
+    ) -> Result<Self> {
+        let api_key = api_key.map(str::trim).filter(|k| !k.is_empty());
+        let url = match kind {
+            ConnectorKind::DevTo => {
+                if api_key.is_none() {
+                    return Err(Error::InvalidValue);
+                }
+                DEVTO_API.to_owned()
+            }
+            ConnectorKind::Webhook => {
+                let url = url.trim();
+                let parsed = url::Url::parse(url)?;
+                if parsed.scheme() != "https" && parsed.scheme() != "http" {
+                    return Err(Error::InvalidValue);
+                }
+                // The address is checked again before each request
+                if !outgoing::may_be_public(&parsed) {
+                    return Err(Error::Url);
+                }
+                url.to_owned()
+            }
+        };
+        let api_key = api_key.map(encrypt_secret).transpose()?;
+        Self::insert(
+            conn,
+            NewConnector {
+                blog_id: blog.id,
+                kind: kind.as_str().to_owned(),
+                url,
+                api_key,
+            },
+        )
+    }
+
+    pub fn list_enabled_for_blog(conn: &Connection, blog_id: i32) -> Result<Vec<Self>> {
+        connectors::table
+            .filter(connectors::blog_id.eq(blog_id))
+            .filter(connectors::enabled.eq(true))
+            .load::<Self>(conn)
+            .map_err(Error::from)
+    }
+
+    pub fn kind(&self) -> Result<ConnectorKind> {
+        self.kind.parse()
+    }
+
+    /// The API key or the secret, decrypted.
+    ///
+    /// It can't be read anymore if `ROCKET_SECRET_KEY` changed.
+    pub fn secret(&self) -> Result<Option<String>> {
+        self.api_key.as_deref().map(decrypt_secret).transpose()
+    }
+
+    pub fn set_enabled(&self, conn: &Connection, enabled: bool) -> Result<()> {
+        diesel::update(self)
+            .set(connectors::enabled.eq(enabled))
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+
+    pub fn delete(&self, conn: &Connection) -> Result<()> {
+        diesel::delete(self)
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+
+    /// Sends `post` to the service.
+    ///
+    /// Returns the URL of the copy of the article, if the service gave one.
+    /// Errors are described in a way that can be shown to the authors of the blog.
+    pub fn send(
+        &self,
+        conn: &Connection,
+        post: &Post,
+    ) -> std::result::Result<Option<String>, String> {
+        let kind = self
+            .kind()
+            .map_err(|_| format!("Unknown connector: {}", self.kind))?;
+        let secret = self.secret().map_err(|_| {
+            "The API key or secret can't be read anymore, add this connector again".to_owned()
+        })?;
+        let (url, address) = outgoing::check_url(&self.url)
+            .map_err(|_| format!("{} can't be reached from this instance", self.url))?;
+        let limits = Limits::new(SEND_TIMEOUT, MAX_RESPONSE_SIZE);
+        let request = outgoing::client_for(&url, address, &limits)
+            .map_err(|e| format!("{:?}", e))?
+            .post(url);
+
+        let tags = Tag::for_post(conn, post.id)
+            .map_err(|e| format!("{:?}", e))?
+            .into_iter()
+            .filter(|t| !t.is_hashtag)
+            .map(|t| t.tag)
+            .collect::<Vec<_>>();
+        let request = match kind {
+            ConnectorKind::DevTo => devto_request(request, secret.as_deref(), post, tags),
+            ConnectorKind::Webhook => {
+                webhook_request(conn, request, secret.as_deref(), post, tags)?
+            }
+        };
+
+        let response = request.send().map_err(|e| e.to_string())?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(format!("The server answered with an error ({})", status));
+        }
+        Ok(outgoing::read_body(response, MAX_RESPONSE_SIZE)
+            .ok()
+            .and_then(|body| serde_json::from_slice::<Value>(&body).ok())
+            .and_then(|res| res["url"].as_str().map(str::to_owned)))
+    }
+}
+
+fn devto_request(
+    request: RequestBuilder,
+    api_key: Option<&str>,
+    post: &Post,
+    tags: Vec<String>,
+) -> RequestBuilder {
+    // dev.to tags can only contain lowercase letters and numbers
+    let tags = tags
+        .iter()
+        .map(|t| {
+            t.to_lowercase()
+                .chars()
+                .filter(|c| c.is_alphanumeric())
+                .collect::<String>()
+        })
+        .filter(|t| !t.is_empty())
+        .take(DEVTO_MAX_TAGS)
+        .collect::<Vec<_>>();
+    request
+        .header("api-key", api_key.unwrap_or_default())
+        .json(&json!({
+            "article": {
+                "title": post.title,
+                "description": post.summary(),
+                // The HTML is used, as it contains the full URL of the medias
+                "body_markdown": html_to_markdown(post.content.get()),
+                "canonical_url": post.ap_url,
+                "published": true,
+                "tags": tags,
+            }
+        }))
+}
+
+fn webhook_request(
+    conn: &Connection,
+    request: RequestBuilder,
+    secret: Option<&str>,
+    post: &Post,
+    tags: Vec<String>,
+) -> std::result::Result<RequestBuilder, String> {
+    let blog = post.get_blog(conn).map_err(|e| format!("{:?}", e))?;
+    let authors = post
+        .get_authors(conn)
+        .map_err(|e| format!("{:?}", e))?
+        .into_iter()
+        .map(|a| a.fqn)
+        .collect::<Vec<_>>();
+    let body = json!({
+        "event": "post.published",
+        "post": {
+            "id": post.id,
+            "title": post.title,
+            "subtitle": post.subtitle,
+            "url": post.ap_url,
+            "content": post.content.get(),
+            "source": post.source,
+            "license": post.license,
+            "tags": tags,
+            "authors": authors,
+            "blog": blog.fqn,
+            "creation_date": post.creation_date.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        }
+    })
+    .to_string();
+
+    let request = request.header("Content-Type", "application/json");
+    let request = match secret {
+        Some(secret) => request.header(
+            "X-Plume-Signature",
+            format!("sha256={}", sign(secret, &body)?),
+        ),
+        None => request,
+    };
+    Ok(request.body(body))
+}
+
+/// The key secrets are encrypted with.
+fn secret_key() -> [u8; 32] {
+    sha256(var("ROCKET_SECRET_KEY").unwrap_or_default().as_bytes())
+}
+
+/// Encrypts `secret` with AES-256-GCM: the nonce, the encrypted secret and the tag are saved
+/// together, as hexadecimal.
+fn encrypt_secret(secret: &str) -> Result<String> {
+    let mut nonce = [0; NONCE_LEN];
+    rand_bytes(&mut nonce)?;
+    let mut tag = [0; TAG_LEN];
+    let encrypted = symm::encrypt_aead(
+        Cipher::aes_256_gcm(),
+        &secret_key(),
+        Some(&nonce),
+        &[],
+        secret.as_bytes(),
+        &mut tag,
+    )?;
+    Ok([&nonce[..], &encrypted, &tag]
+        .concat()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+fn decrypt_secret(saved: &str) -> Result<String> {
+    if saved.len() % 2 != 0 || !saved.is_ascii() {
+        return Err(Error::InvalidValue);
+    }
+    let bytes = (0..saved.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&saved[i..i + 2], 16))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|_| Error::InvalidValue)?;
+    if bytes.len() < NONCE_LEN + TAG_LEN {
+        return Err(Error::InvalidValue);
+    }
+    let (nonce, rest) = bytes.split_at(NONCE_LEN);
+    let (encrypted, tag) = rest.split_at(rest.len() - TAG_LEN);
+    let secret = symm::decrypt_aead(
+        Cipher::aes_256_gcm(),
+        &secret_key(),
+        Some(nonce),
+        &[],
+        encrypted,
+        tag,
+    )?;
+    String::from_utf8(secret).map_err(|_| Error::InvalidValue)
+}
+
+/// Signs a webhook body with HMAC-SHA256, so that the receiver can check where it comes from.
+fn sign(secret: &str, body: &str) -> std::result::Result<String, String> {
+    let key = PKey::hmac(secret.as_bytes()).map_err(|e| e.to_string())?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).map_err(|e| e.to_string())?;
+    let signature = signer
+        .sign_oneshot_to_vec(body.as_bytes())
+        .map_err(|e| e.to_string())?;
+    Ok(signature.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{inbox::tests::fill_database, tests::db};
+    use diesel::Connection;
+
+    #[test]
+    fn create_connectors() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (_, _, blogs) = fill_database(conn);
+            assert!(Connector::create(conn, &blogs[0], ConnectorKind::DevTo, "", None).is_err());
+            assert!(Connector::create(
+                conn,
+                &blogs[0],
+                ConnectorKind::Webhook,
+                "ftp://example.com",
+                None
+            )
+            .is_err());
+
+            let devto = Connector::create(
+                conn,
+                &blogs[0],
+                ConnectorKind::DevTo,
+                "https://example.com",
+                Some(" key "),
+            )?;
+            assert_eq!(devto.url, DEVTO_API);
+            assert_ne!(devto.api_key, Some("key".to_owned()));
+            assert_eq!(devto.secret()?, Some("key".to_owned()));
+            let webhook = Connector::create(
+                conn,
+                &blogs[0],
+                ConnectorKind::Webhook,
+                "https://example.com/hook",
+                Some(""),
+            )?;
+            assert_eq!(webhook.kind()?, ConnectorKind::Webhook);
+            assert_eq!(webhook.api_key, None);
+            for url in &["http://127.0.0.1:9/hook", "http://localhost/hook"] {
+                assert!(
+                    Connector::create(conn, &blogs[0], ConnectorKind::Webhook, url, None).is_err()
+                );
+            }
+
+            webhook.set_enabled(conn, false)?;
+            assert_eq!(Connector::list_for_blog(conn, blogs[0].id)?.len(), 2);
+            assert_eq!(
+                Connector::list_enabled_for_blog(conn, blogs[0].id)?
+                    .iter()
+                    .map(|c| c.id)
+                    .collect::<Vec<_>>(),
+                vec![devto.id]
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn requests() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, _, _) = fill_database(conn);
+            let client = reqwest::blocking::Client::new();
+            let tags = vec!["Rust Lang".to_owned(), "!!".to_owned()];
+
+            let request =
+                devto_request(client.post(DEVTO_API), Some("key"), &posts[0], tags.clone())
+                    .build()?;
+            assert_eq!(request.headers()["api-key"], "key");
+            let body: Value =
+                serde_json::from_slice(request.body().and_then(|b| b.as_bytes()).unwrap())?;
+            assert_eq!(body["article"]["canonical_url"], posts[0].ap_url.as_str());
+            assert_eq!(body["article"]["tags"], json!(["rustlang"]));
+
+            let request = webhook_request(
+                conn,
+                client.post("https://example.com/hook"),
+                Some("secret"),
+                &posts[0],
+                tags,
+            )
+            .unwrap()
+            .build()?;
+            let body = request.body().and_then(|b| b.as_bytes()).unwrap();
+            let signature = sign("secret", std::str::from_utf8(body).unwrap()).unwrap();
+            assert_eq!(
+                request.headers()["X-Plume-Signature"],
+                format!("sha256={}", signature).as_str()
+            );
+            let body: Value = serde_json::from_slice(body)?;
+            assert_eq!(body["event"], "post.published");
+            assert_eq!(body["post"]["url"], posts[0].ap_url.as_str());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn secrets() {
+        let encrypted = encrypt_secret("secret").unwrap();
+        assert!(!encrypted.contains("secret"));
+        // Each secret has its own nonce
+        assert_ne!(encrypt_secret("secret").unwrap(), encrypted);
+        assert_eq!(decrypt_secret(&encrypted).unwrap(), "secret");
+        assert!(decrypt_secret("secret").is_err());
+        let last = if encrypted.ends_with('0') { "1" } else { "0" };
+        let tampered = format!("{}{}", &encrypted[..encrypted.len() - 1], last);
+        assert!(decrypt_secret(&tampered).is_err());
+    }
+
+    #[test]
+    fn sign_webhook() {
+        // Known HMAC-SHA256 test vector
+        assert_eq!(
+            sign("key", "The quick brown fox jumps over the lazy dog").unwrap(),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+}
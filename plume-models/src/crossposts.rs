@@ -0,0 +1,302 @@
+use crate::{
+    connectors::Connector,
+    posts::Post,
+    schema::{crosspost_opt_outs, crossposts},
+    Connection, Error, Result,
+};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+use tracing::warn;
+
+/// How many times sending an article is tried before giving up.
+pub const MAX_ATTEMPTS: i32 = 6;
+
+/// How many crossposts are sent in a single run of the worker.
+const BATCH_SIZE: i64 = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CrosspostState {
+    Pending = 0,
+    Done = 1,
+    Failed = 2,
+}
+
+impl From<i32> for CrosspostState {
+    fn from(state: i32) -> Self {
+        match state {
+            1 => CrosspostState::Done,
+            2 => CrosspostState::Failed,
+            _ => CrosspostState::Pending,
+        }
+    }
+}
+
+/// The copy of an article sent to a connector.
+#[derive(Clone, Queryable, Identifiable)]
+pub struct Crosspost {
+    pub id: i32,
+    pub connector_id: i32,
+    pub post_id: i32,
+    pub state: i32,
+    pub attempts: i32,
+    pub next_attempt: NaiveDateTime,
+    /// Where the copy of the article is, if the service told us
+    pub remote_url: Option<String>,
+    pub last_error: Option<String>,
+    pub creation_date: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "crossposts"]
+pub struct NewCrosspost {
+    pub connector_id: i32,
+    pub post_id: i32,
+}
+
+impl Crosspost {
+    insert!(crossposts, NewCrosspost);
+    get!(crossposts);
+    find_by!(
+        crossposts,
+        find_for_connector_and_post,
+        connector_id as i32,
+        post_id as i32
+    );
+
+    /// Queues a newly published article for all the connectors of its blog,
+    /// unless its authors opted out.
+    pub fn schedule(conn: &Connection, post: &Post) -> Result<Vec<Self>> {
+        if !post.published || CrosspostOptOut::exists(conn, post.id)? {
+            return Ok(vec![]);
+        }
+        let mut scheduled = vec![];
+        for connector in Connector::list_enabled_for_blog(conn, post.blog_id)? {
+            if Self::find_for_connector_and_post(conn, connector.id, post.id).is_err() {
+                scheduled.push(Self::insert(
+                    conn,
+                    NewCrosspost {
+                        connector_id: connector.id,
+                        post_id: post.id,
+                    },
+                )?);
+            }
+        }
+        Ok(scheduled)
+    }
+
+    /// The latest crossposts of a connector.
+    pub fn list_for_connector(
+        conn: &Connection,
+        connector_id: i32,
+        limit: i64,
+    ) -> Result<Vec<Self>> {
+        crossposts::table
+            .filter(crossposts::connector_id.eq(connector_id))
+            .order(crossposts::creation_date.desc())
+            .limit(limit)
+            .load::<Self>(conn)
+            .map_err(Error::from)
+    }
+
+    /// Crossposts waiting to be (re)sent.
+    pub fn list_due(conn: &Connection) -> Result<Vec<Self>> {
+        crossposts::table
+            .filter(crossposts::state.eq(CrosspostState::Pending as i32))
+            .filter(crossposts::next_attempt.le(Utc::now().naive_utc()))
+            .order(crossposts::next_attempt.asc())
+            .limit(BATCH_SIZE)
+            .load::<Self>(conn)
+            .map_err(Error::from)
+    }
+
+    pub fn state(&self) -> CrosspostState {
+        CrosspostState::from(self.state)
+    }
+
+    pub fn get_post(&self, conn: &Connection) -> Result<Post> {
+        Post::get(conn, self.post_id)
+    }
+
+    /// Tries to send the article to the connector.
+    ///
+    /// Failed attempts are retried later, waiting longer each time.
+    pub fn deliver(&self, conn: &Connection) -> Result<Self> {
+        let connector = Connector::get(conn, self.connector_id)?;
+        let post = self.get_post(conn)?;
+        let result = if connector.enabled {
+            connector.send(conn, &post)
+        } else {
+            Err("The connector is disabled".to_owned())
+        };
+
+        match result {
+            Ok(remote_url) => diesel::update(self)
+                .set((
+                    crossposts::state.eq(CrosspostState::Done as i32),
+                    crossposts::attempts.eq(self.attempts + 1),
+                    crossposts::remote_url.eq(remote_url),
+                    crossposts::last_error.eq(None::<String>),
+                ))
+                .execute(conn)?,
+            Err(error) => {
+                let attempts = self.attempts + 1;
+                let state = if attempts >= MAX_ATTEMPTS {
+                    CrosspostState::Failed
+                } else {
+                    CrosspostState::Pending
+                };
+                diesel::update(self)
+                    .set((
+                        crossposts::state.eq(state as i32),
+                        crossposts::attempts.eq(attempts),
+                        crossposts::next_attempt.eq(Utc::now().naive_utc() + retry_delay(attempts)),
+                        crossposts::last_error.eq(Some(error)),
+                    ))
+                    .execute(conn)?
+            }
+        };
+        Self::get(conn, self.id)
+    }
+
+    /// Sends all the crossposts that are due.
+    pub fn deliver_due(conn: &Connection) -> Result<()> {
+        for crosspost in Self::list_due(conn)? {
+            match crosspost.deliver(conn) {
+                Ok(c) if c.state() == CrosspostState::Failed => warn!(
+                    "Giving up cross-posting article {} to connector {}: {}",
+                    c.post_id,
+                    c.connector_id,
+                    c.last_error.unwrap_or_default()
+                ),
+                Ok(_) => {}
+                Err(e) => warn!(
+                    "Failed to cross-post article {}: {:?}",
+                    crosspost.post_id, e
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    /// Tries to send a failed crosspost again, as if it was just scheduled.
+    pub fn retry(&self, conn: &Connection) -> Result<()> {
+        diesel::update(self)
+            .set((
+                crossposts::state.eq(CrosspostState::Pending as i32),
+                crossposts::attempts.eq(0),
+                crossposts::next_attempt.eq(Utc::now().naive_utc()),
+            ))
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+}
+
+/// Waits 2, 4, 8, … minutes between attempts.
+fn retry_delay(attempts: i32) -> Duration {
+    Duration::minutes(1 << attempts.min(10))
+}
+
+/// An article that should not be cross-posted.
+#[derive(Clone, Queryable, Identifiable)]
+pub struct CrosspostOptOut {
+    pub id: i32,
+    pub post_id: i32,
+}
+
+#[derive(Insertable)]
+#[table_name = "crosspost_opt_outs"]
+pub struct NewCrosspostOptOut {
+    pub post_id: i32,
+}
+
+impl CrosspostOptOut {
+    insert!(crosspost_opt_outs, NewCrosspostOptOut);
+    find_by!(crosspost_opt_outs, find_by_post, post_id as i32);
+
+    pub fn exists(conn: &Connection, post_id: i32) -> Result<bool> {
+        Ok(Self::find_by_post(conn, post_id).is_ok())
+    }
+
+    /// Sets whether an article should be cross-posted or not.
+    pub fn set(conn: &Connection, post_id: i32, opted_out: bool) -> Result<()> {
+        match (Self::find_by_post(conn, post_id), opted_out) {
+            (Err(_), true) => Self::insert(conn, NewCrosspostOptOut { post_id }).map(|_| ()),
+            (Ok(opt_out), false) => diesel::delete(&opt_out)
+                .execute(conn)
+                .map(|_| ())
+                .map_err(Error::from),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        connectors::{ConnectorKind, NewConnector},
+        inbox::tests::fill_database,
+        tests::db,
+    };
+    use diesel::Connection;
+
+    #[test]
+    fn schedule_and_retry() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, _, blogs) = fill_database(conn);
+            // Saved without the checks of `Connector::create`: delivery fails without
+            // connecting, as the address is private
+            let connector = Connector::insert(
+                conn,
+                NewConnector {
+                    blog_id: blogs[0].id,
+                    kind: ConnectorKind::Webhook.as_str().to_owned(),
+                    url: "http://127.0.0.1:9/hook".to_owned(),
+                    api_key: None,
+                },
+            )?;
+            let disabled = Connector::create(
+                conn,
+                &blogs[0],
+                ConnectorKind::Webhook,
+                "https://example.com/other",
+                None,
+            )?;
+            disabled.set_enabled(conn, false)?;
+
+            CrosspostOptOut::set(conn, posts[0].id, true)?;
+            assert!(Crosspost::schedule(conn, &posts[0])?.is_empty());
+            CrosspostOptOut::set(conn, posts[0].id, false)?;
+            assert!(!CrosspostOptOut::exists(conn, posts[0].id)?);
+
+            let scheduled = Crosspost::schedule(conn, &posts[0])?;
+            assert_eq!(scheduled.len(), 1);
+            assert_eq!(scheduled[0].connector_id, connector.id);
+            // Articles are only sent once to each connector
+            assert!(Crosspost::schedule(conn, &posts[0])?.is_empty());
+            assert_eq!(Crosspost::list_due(conn)?.len(), 1);
+
+            let crosspost = scheduled[0].deliver(conn)?;
+            assert_eq!(crosspost.state(), CrosspostState::Pending);
+            assert_eq!(crosspost.attempts, 1);
+            assert!(crosspost.last_error.is_some());
+            assert!(crosspost.next_attempt > Utc::now().naive_utc());
+            assert!(Crosspost::list_due(conn)?.is_empty());
+
+            let mut crosspost = crosspost;
+            for _ in 1..MAX_ATTEMPTS {
+                crosspost = crosspost.deliver(conn)?;
+            }
+            assert_eq!(crosspost.state(), CrosspostState::Failed);
+
+            crosspost.retry(conn)?;
+            let crosspost = Crosspost::get(conn, crosspost.id)?;
+            assert_eq!(crosspost.state(), CrosspostState::Pending);
+            assert_eq!(crosspost.attempts, 0);
+            assert_eq!(Crosspost::list_due(conn)?.len(), 1);
+            Ok(())
+        });
+    }
+}
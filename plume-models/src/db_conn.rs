@@ -1,15 +1,30 @@
-use crate::Connection;
+use crate::{Connection, CONFIG};
 use diesel::r2d2::{
     ConnectionManager, CustomizeConnection, Error as ConnError, Pool, PooledConnection,
 };
+use diesel::{connection::SimpleConnection, Connection as _, ConnectionError, QueryResult};
 #[cfg(feature = "sqlite")]
-use diesel::{dsl::sql_query, ConnectionError, RunQueryDsl};
+use diesel::{dsl::sql_query, RunQueryDsl};
+#[cfg(feature = "sqlite")]
+use once_cell::sync::Lazy;
 use rocket::{
     http::Status,
     request::{self, FromRequest},
     Outcome, Request, State,
 };
-use std::ops::Deref;
+#[cfg(feature = "sqlite")]
+use std::{
+    cell::Cell,
+    io,
+    path::Path,
+    sync::{Mutex, PoisonError},
+};
+use std::{
+    ops::Deref,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+use tracing::{info_span, warn};
 
 pub type DbPool = Pool<ConnectionManager<Connection>>;
 
@@ -26,9 +41,19 @@ impl<'a, 'r> FromRequest<'a, 'r> for DbConn {
 
     fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
         let pool = request.guard::<State<'_, DbPool>>()?;
-        match pool.get() {
+        let _span = info_span!("db_checkout").entered();
+        let start = Instant::now();
+        let conn = pool.get();
+        POOL_METRICS.record(start.elapsed(), conn.is_ok());
+        match conn {
             Ok(conn) => Outcome::Success(DbConn(conn)),
-            Err(_) => Outcome::Failure((Status::ServiceUnavailable, ())),
+            Err(_) => {
+                warn!(
+                    max_size = pool.max_size(),
+                    "No database connection was available in time, DB_MAX_SIZE may be too low"
+                );
+                Outcome::Failure((Status::ServiceUnavailable, ()))
+            }
         }
     }
 }
@@ -59,6 +84,193 @@ impl CustomizeConnection<Connection, ConnError> for PragmaForeignKey {
     }
 }
 
+/// Applies the settings of the configuration to every new connection.
+#[derive(Debug, Default)]
+pub struct ConnectionSettings {
+    /// How long a query can run before being cancelled, with PostgreSQL
+    pub statement_timeout: Option<Duration>,
+    /// How long to wait for another connection to finish writing, with SQLite
+    pub busy_timeout: Duration,
+}
+
+impl ConnectionSettings {
+    pub fn from_config() -> Self {
+        ConnectionSettings {
+            statement_timeout: CONFIG.db_statement_timeout,
+            busy_timeout: CONFIG.db_busy_timeout,
+        }
+    }
+
+    /// Also used for the connections that are not in a pool, like the one of `plm`.
+    pub fn apply(&self, conn: &Connection) -> QueryResult<()> {
+        if cfg!(feature = "sqlite") {
+            // With the write-ahead log, reading doesn't prevent writing, and the other way
+            // around
+            conn.batch_execute(&format!(
+                "PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL; PRAGMA busy_timeout = {};",
+                self.busy_timeout.as_millis()
+            ))?;
+        }
+        match self.statement_timeout {
+            Some(timeout) if cfg!(feature = "postgres") => {
+                conn.batch_execute(&format!("SET statement_timeout = {};", timeout.as_millis()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl CustomizeConnection<Connection, ConnError> for ConnectionSettings {
+    fn on_acquire(&self, conn: &mut Connection) -> Result<(), ConnError> {
+        PragmaForeignKey.on_acquire(conn)?;
+        self.apply(conn)
+            .map_err(|e| ConnError::ConnectionError(ConnectionError::BadConnection(e.to_string())))
+    }
+}
+
+#[cfg(feature = "sqlite")]
+static WRITER: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+#[cfg(feature = "sqlite")]
+thread_local! {
+    /// Whether this thread is already in `write_transaction`.
+    static WRITING: Cell<bool> = Cell::new(false);
+}
+
+/// Runs `f` in a transaction meant to write to the database.
+///
+/// SQLite only allows one writer at a time. A query outside of a transaction waits for the
+/// others for `DB_BUSY_TIMEOUT`, but a transaction that starts by reading and then writes
+/// can fail with `database is locked` when another one wrote in the meantime, without
+/// waiting: these transactions are made one after the other, and take the lock of the
+/// database as they start. Every transaction that writes has to use this function instead
+/// of `Connection::transaction`.
+#[cfg(feature = "sqlite")]
+pub fn write_transaction<T, E, F>(conn: &Connection, f: F) -> Result<T, E>
+where
+    F: FnOnce() -> Result<T, E>,
+    E: From<diesel::result::Error>,
+{
+    use diesel::connection::TransactionManager;
+
+    if WRITING.with(Cell::get) {
+        return conn.transaction(f);
+    }
+    let _writer = WRITER.lock().unwrap_or_else(PoisonError::into_inner);
+    WRITING.with(|writing| writing.set(true));
+    // Tests run in a transaction that was already started
+    let res = if conn.transaction_manager().get_transaction_depth() > 0 {
+        conn.transaction(f)
+    } else {
+        conn.immediate_transaction(f)
+    };
+    WRITING.with(|writing| writing.set(false));
+    res
+}
+
+/// Runs `f` in a transaction meant to write to the database.
+#[cfg(feature = "postgres")]
+pub fn write_transaction<T, E, F>(conn: &Connection, f: F) -> Result<T, E>
+where
+    F: FnOnce() -> Result<T, E>,
+    E: From<diesel::result::Error>,
+{
+    conn.transaction(f)
+}
+
+/// Copies the database to `destination`, while it is used.
+///
+/// The copy is made a few pages at a time, and starts again if the database is written to
+/// in between, so that it is consistent.
+#[cfg(feature = "sqlite")]
+pub fn backup(destination: &Path) -> crate::Result<()> {
+    use rusqlite::{backup::Backup, OpenFlags};
+
+    let to_io = |e: rusqlite::Error| crate::Error::Io(io::Error::new(io::ErrorKind::Other, e));
+    let source = rusqlite::Connection::open_with_flags(
+        &CONFIG.database_url,
+        OpenFlags::SQLITE_OPEN_READ_ONLY,
+    )
+    .map_err(to_io)?;
+    let mut copy = rusqlite::Connection::open(destination).map_err(to_io)?;
+    Backup::new(&source, &mut copy)
+        .and_then(|backup| backup.run_to_completion(100, Duration::from_millis(10), None))
+        .map_err(to_io)
+}
+
+/// Checkouts waiting longer than this are logged.
+const SLOW_CHECKOUT: Duration = Duration::from_secs(1);
+
+/// How long requests waited for a connection, since the start.
+pub static POOL_METRICS: PoolMetrics = PoolMetrics {
+    checkouts: AtomicU64::new(0),
+    timeouts: AtomicU64::new(0),
+    slow: AtomicU64::new(0),
+    total_wait_us: AtomicU64::new(0),
+    max_wait_us: AtomicU64::new(0),
+};
+
+pub struct PoolMetrics {
+    checkouts: AtomicU64,
+    timeouts: AtomicU64,
+    slow: AtomicU64,
+    total_wait_us: AtomicU64,
+    max_wait_us: AtomicU64,
+}
+
+impl PoolMetrics {
+    fn record(&self, wait: Duration, success: bool) {
+        let wait_us = wait.as_micros() as u64;
+        self.checkouts.fetch_add(1, Ordering::Relaxed);
+        self.total_wait_us.fetch_add(wait_us, Ordering::Relaxed);
+        self.max_wait_us.fetch_max(wait_us, Ordering::Relaxed);
+        if !success {
+            self.timeouts.fetch_add(1, Ordering::Relaxed);
+        } else if wait > SLOW_CHECKOUT {
+            self.slow.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                wait_ms = wait.as_millis() as u64,
+                "Waited for a database connection, DB_MAX_SIZE may be too low"
+            );
+        }
+    }
+
+    /// The state of `pool`, with what was measured so far.
+    pub fn stats(&self, pool: &DbPool) -> PoolStats {
+        let state = pool.state();
+        let checkouts = self.checkouts.load(Ordering::Relaxed);
+        let total_wait_us = self.total_wait_us.load(Ordering::Relaxed);
+        PoolStats {
+            max_size: pool.max_size(),
+            connections: state.connections,
+            idle: state.idle_connections,
+            checkouts,
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+            slow: self.slow.load(Ordering::Relaxed),
+            mean_wait: Duration::from_micros(total_wait_us.checked_div(checkouts).unwrap_or(0)),
+            max_wait: Duration::from_micros(self.max_wait_us.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// How saturated the pool is, as shown to administrators.
+#[derive(Clone, Debug)]
+pub struct PoolStats {
+    pub max_size: u32,
+    /// Open connections
+    pub connections: u32,
+    /// Open connections that are not used
+    pub idle: u32,
+    /// Connections requested by requests
+    pub checkouts: u64,
+    /// Requests that didn't get one before `DB_ACQUIRE_TIMEOUT`
+    pub timeouts: u64,
+    /// Requests that waited more than a second
+    pub slow: u64,
+    pub mean_wait: Duration,
+    pub max_wait: Duration,
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
@@ -73,4 +285,13 @@ pub(crate) mod tests {
             Ok(())
         }
     }
+
+    #[test]
+    fn nested_write_transactions() {
+        let conn = &crate::tests::db();
+        let res = write_transaction(conn, || {
+            write_transaction(conn, || Ok::<_, crate::Error>(1)).map(|x| x + 1)
+        });
+        assert_eq!(res.unwrap(), 2);
+    }
 }
@@ -0,0 +1,318 @@
+//! The directory of the local blogs and authors, to help readers find them.
+//!
+//! Only the ones that opted in (`discoverable`) are listed. The flag is federated as the
+//! `discoverable` property of their actors, so that other instances can respect it too.
+
+use crate::{
+    blogs::Blog,
+    instance::Instance,
+    schema::{blog_authors, blogs, post_authors, posts, users},
+    users::{Role, User},
+    Connection, Cursor, Error, Result,
+};
+use chrono::NaiveDateTime;
+use diesel::{
+    dsl::max, sql_types::Integer, BoolExpressionMethods, ExpressionMethods, JoinOnDsl,
+    NullableExpressionMethods, QueryDsl, RunQueryDsl,
+};
+
+type Backend = <Connection as diesel::Connection>::Backend;
+
+/// The categories a blog can be listed in.
+pub const CATEGORIES: &[&str] = &[
+    "art",
+    "culture",
+    "education",
+    "food",
+    "games",
+    "music",
+    "news",
+    "personal",
+    "politics",
+    "science",
+    "sports",
+    "technology",
+    "travel",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    /// The ones that published an article most recently first
+    Active,
+    /// The ones that were created most recently first
+    New,
+}
+
+impl Order {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "active" => Some(Order::Active),
+            "new" => Some(Order::New),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Order::Active => "active",
+            Order::New => "new",
+        }
+    }
+}
+
+impl Default for Order {
+    fn default() -> Self {
+        Order::Active
+    }
+}
+
+/// A blog or an author listed in the directory.
+#[derive(Clone, Debug)]
+pub struct Entry<T> {
+    pub item: T,
+    /// When they last published an article
+    pub last_activity: Option<NaiveDateTime>,
+}
+
+/// The part of the directory to list.
+#[derive(Clone, Copy, Debug)]
+pub enum Window {
+    /// Sorted by `Order`, from the first index to the last one, excluded, as the pages of
+    /// the site
+    Range(Order, (i32, i32)),
+    /// Sorted by ID, as API clients ask for it
+    Cursor(Cursor),
+}
+
+/// The discoverable local blogs, in `category` if it is given.
+///
+/// The blogs of silenced or deactivated authors are not listed.
+pub fn blogs(
+    conn: &Connection,
+    category: Option<&str>,
+    window: Window,
+) -> Result<Vec<Entry<Blog>>> {
+    let last_activity = || max(posts::creation_date.nullable());
+    let mut query = blogs::table
+        .left_join(posts::table.on(posts::blog_id.eq(blogs::id).and(posts::published.eq(true))))
+        .filter(blogs::id.eq_any(listed_blogs(category)?))
+        .group_by(blogs::id)
+        .select((blogs::all_columns, last_activity()))
+        .into_boxed();
+    query = match window {
+        Window::Range(order, (min, max)) => {
+            query = match order {
+                // The ones that never published anything last
+                Order::Active => query.order((
+                    last_activity().is_null(),
+                    last_activity().desc(),
+                    blogs::creation_date.desc(),
+                )),
+                Order::New => query.order(blogs::creation_date.desc()),
+            };
+            query.offset(min.into()).limit((max - min).into())
+        }
+        Window::Cursor(cursor) => paginate!(query, blogs, cursor),
+    };
+    let page = query
+        .load::<(Blog, Option<NaiveDateTime>)>(conn)?
+        .into_iter()
+        .map(|(item, last_activity)| Entry {
+            item,
+            last_activity,
+        })
+        .collect();
+    Ok(match window {
+        Window::Cursor(cursor) => cursor.finish(page),
+        Window::Range(..) => page,
+    })
+}
+
+pub fn count_blogs(conn: &Connection, category: Option<&str>) -> Result<i64> {
+    blogs::table
+        .filter(blogs::id.eq_any(listed_blogs(category)?))
+        .count()
+        .get_result(conn)
+        .map_err(Error::from)
+}
+
+fn listed_blogs(category: Option<&str>) -> Result<blogs::BoxedQuery<'static, Backend, Integer>> {
+    let mut query = blogs::table
+        .filter(blogs::instance_id.eq(Instance::get_local()?.id))
+        .filter(blogs::discoverable.eq(true))
+        .filter(
+            blogs::id.ne_all(
+                blog_authors::table
+                    .inner_join(users::table)
+                    .filter(users::silenced.eq(true).or(users::deactivated.eq(true)))
+                    .select(blog_authors::blog_id),
+            ),
+        )
+        .select(blogs::id)
+        .into_boxed();
+    if let Some(category) = category {
+        query = query.filter(blogs::directory_category.eq(category.to_owned()));
+    }
+    Ok(query)
+}
+
+/// The discoverable local authors, and only the authors of a blog in `category` if it is
+/// given.
+pub fn authors(
+    conn: &Connection,
+    category: Option<&str>,
+    window: Window,
+) -> Result<Vec<Entry<User>>> {
+    let last_activity = || max(posts::creation_date.nullable());
+    let mut query = users::table
+        .left_join(post_authors::table)
+        .left_join(
+            posts::table.on(posts::id
+                .eq(post_authors::post_id)
+                .and(posts::published.eq(true))),
+        )
+        .filter(users::id.eq_any(listed_authors(category)?))
+        .group_by(users::id)
+        .select((users::all_columns, last_activity()))
+        .into_boxed();
+    query = match window {
+        Window::Range(order, (min, max)) => {
+            query = match order {
+                Order::Active => query.order((
+                    last_activity().is_null(),
+                    last_activity().desc(),
+                    users::creation_date.desc(),
+                )),
+                Order::New => query.order(users::creation_date.desc()),
+            };
+            query.offset(min.into()).limit((max - min).into())
+        }
+        Window::Cursor(cursor) => paginate!(query, users, cursor),
+    };
+    let page = query
+        .load::<(User, Option<NaiveDateTime>)>(conn)?
+        .into_iter()
+        .map(|(item, last_activity)| Entry {
+            item,
+            last_activity,
+        })
+        .collect();
+    Ok(match window {
+        Window::Cursor(cursor) => cursor.finish(page),
+        Window::Range(..) => page,
+    })
+}
+
+pub fn count_authors(conn: &Connection, category: Option<&str>) -> Result<i64> {
+    users::table
+        .filter(users::id.eq_any(listed_authors(category)?))
+        .count()
+        .get_result(conn)
+        .map_err(Error::from)
+}
+
+fn listed_authors(category: Option<&str>) -> Result<users::BoxedQuery<'static, Backend, Integer>> {
+    let mut query = users::table
+        .filter(users::instance_id.eq(Instance::get_local()?.id))
+        .filter(users::discoverable.eq(true))
+        .filter(users::role.ne(Role::Instance as i32))
+        .filter(users::deactivated.eq(false))
+        .filter(users::silenced.eq(false))
+        .select(users::id)
+        .into_boxed();
+    if let Some(category) = category {
+        query = query.filter(
+            users::id.eq_any(
+                blog_authors::table
+                    .inner_join(blogs::table)
+                    .filter(blogs::directory_category.eq(category.to_owned()))
+                    .select(blog_authors::author_id),
+            ),
+        );
+    }
+    Ok(query)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        blogs::tests::fill_database,
+        posts::{NewPost, Post},
+        safe_string::SafeString,
+        tests::db,
+        Error,
+    };
+    use diesel::{Connection, SaveChangesDsl};
+
+    #[test]
+    fn directory() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (users, mut blogs) = fill_database(conn);
+            assert!(super::blogs(conn, None, first_page(Order::Active))?.is_empty());
+
+            for blog in blogs.iter_mut() {
+                blog.discoverable = true;
+                let _: Blog = blog.save_changes(conn)?;
+            }
+            blogs[1].directory_category = Some("science".to_owned());
+            let _: Blog = blogs[1].save_changes(conn)?;
+            let post = Post::insert(
+                conn,
+                NewPost {
+                    blog_id: blogs[0].id,
+                    slug: "recent".to_owned(),
+                    title: "Recent".to_owned(),
+                    content: SafeString::new(""),
+                    published: true,
+                    license: String::new(),
+                    creation_date: None,
+                    ap_url: String::new(),
+                    subtitle: String::new(),
+                    source: String::new(),
+                    cover_id: None,
+                    excerpt: String::new(),
+                    canonical_url: None,
+                },
+            )?;
+            let listed = super::blogs(conn, None, first_page(Order::Active))?;
+            assert_eq!(listed.len(), blogs.len());
+            assert_eq!(listed[0].item.id, blogs[0].id);
+            assert_eq!(listed[0].last_activity, Some(post.creation_date));
+
+            assert_eq!(count_blogs(conn, None)?, blogs.len() as i64);
+            let cursor = Cursor {
+                max_id: None,
+                min_id: None,
+                limit: 1,
+            };
+            let newest = super::blogs(conn, None, Window::Cursor(cursor))?;
+            assert_eq!(newest.len(), 1);
+            assert_eq!(newest[0].item.id, blogs.iter().map(|b| b.id).max().unwrap());
+
+            let science = super::blogs(conn, Some("science"), first_page(Order::New))?;
+            assert_eq!(science.len(), 1);
+            assert_eq!(science[0].item.id, blogs[1].id);
+            assert_eq!(count_blogs(conn, Some("science"))?, 1);
+
+            let mut author = users[0].clone();
+            author.discoverable = true;
+            let _: User = author.save_changes(conn)?;
+            let authors = super::authors(conn, None, first_page(Order::Active))?;
+            assert_eq!(authors.len(), 1);
+            assert_eq!(authors[0].item.id, author.id);
+
+            // Silenced authors are not listed, nor their blogs
+            author.silenced = true;
+            let _: User = author.save_changes(conn)?;
+            assert!(super::authors(conn, None, first_page(Order::Active))?.is_empty());
+            assert!(count_blogs(conn, None)? < blogs.len() as i64);
+            Ok(())
+        });
+    }
+
+    fn first_page(order: Order) -> Window {
+        Window::Range(order, (0, 20))
+    }
+}
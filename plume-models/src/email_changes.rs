@@ -0,0 +1,210 @@
+//! Changing the email address of an account.
+//!
+//! The change has to be confirmed with links sent to both the current and the new address:
+//! the new one to be sure it works, the current one so that someone who got hold of a session
+//! can't take the account over. Until then, the new address waits in `User::pending_email`.
+
+use crate::{
+    blocklisted_emails::BlocklistedEmail,
+    password_reset_requests::PasswordResetRequest,
+    schema::{email_changes, users},
+    users::User,
+    Connection, Error, Result,
+};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+use plume_common::utils::random_hex;
+
+/// How long the links work.
+const TOKEN_VALIDITY_HOURS: i64 = 24;
+
+#[derive(Clone, Queryable, Identifiable)]
+pub struct EmailChange {
+    pub id: i32,
+    pub user_id: i32,
+    /// The address the link was sent to
+    pub email: String,
+    pub token: String,
+    pub confirmed: bool,
+    pub expiration_date: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "email_changes"]
+pub struct NewEmailChange {
+    pub user_id: i32,
+    pub email: String,
+    pub token: String,
+    pub expiration_date: NaiveDateTime,
+}
+
+/// The links to send for a new change.
+pub struct EmailChangeTokens {
+    /// For the current address, if the account has one
+    pub current: Option<String>,
+    pub new: String,
+}
+
+/// Where a change is, after a link was followed.
+pub enum EmailChangeStatus {
+    /// The link of the other address has to be followed too
+    Waiting,
+    /// The account uses its new address
+    Done {
+        user: User,
+        previous: Option<String>,
+    },
+}
+
+impl EmailChange {
+    insert!(email_changes, NewEmailChange);
+    get!(email_changes);
+    find_by!(email_changes, find_by_token, token as &str);
+    list_by!(email_changes, list_for_user, user_id as i32);
+
+    /// Starts changing the address of `user` to `email`, replacing the previous change if
+    /// there was one.
+    pub fn request(conn: &Connection, user: &User, email: &str) -> Result<EmailChangeTokens> {
+        let email = email.trim();
+        if !email.contains('@') || user.email.as_deref() == Some(email) {
+            return Err(Error::InvalidValue);
+        }
+        if User::find_by_email(conn, email).is_ok() {
+            return Err(Error::UserAlreadyExists);
+        }
+        if let Some(entry) = BlocklistedEmail::matches_blocklist(conn, email)? {
+            return Err(Error::Blocklisted(
+                entry.notify_user,
+                entry.notification_text,
+            ));
+        }
+
+        Self::cancel(conn, user)?;
+        diesel::update(user)
+            .set(users::pending_email.eq(email))
+            .execute(conn)?;
+        let expiration_date = Utc::now().naive_utc() + Duration::hours(TOKEN_VALIDITY_HOURS);
+        let mut link = |email: &str| {
+            Self::insert(
+                conn,
+                NewEmailChange {
+                    user_id: user.id,
+                    email: email.to_owned(),
+                    token: random_hex(),
+                    expiration_date,
+                },
+            )
+            .map(|change| change.token)
+        };
+        Ok(EmailChangeTokens {
+            current: user.email.as_deref().map(&mut link).transpose()?,
+            new: link(email)?,
+        })
+    }
+
+    /// Confirms the change from one of the addresses, and makes it once both did.
+    pub fn confirm(conn: &Connection, token: &str) -> Result<EmailChangeStatus> {
+        let change = Self::find_by_token(conn, token)?;
+        if change.expiration_date < Utc::now().naive_utc() {
+            return Err(Error::Expired);
+        }
+        diesel::update(&change)
+            .set(email_changes::confirmed.eq(true))
+            .execute(conn)?;
+        if Self::list_for_user(conn, change.user_id)?
+            .iter()
+            .any(|c| !c.confirmed)
+        {
+            return Ok(EmailChangeStatus::Waiting);
+        }
+
+        let user = User::get(conn, change.user_id)?;
+        let email = user.pending_email.clone().ok_or(Error::NotFound)?;
+        // It may have been taken while the links were waiting
+        if User::find_by_email(conn, &email).is_ok() {
+            Self::cancel(conn, &user)?;
+            return Err(Error::UserAlreadyExists);
+        }
+        diesel::update(&user)
+            .set((
+                users::email.eq(&email),
+                users::pending_email.eq(None::<String>),
+            ))
+            .execute(conn)?;
+        Self::delete_for_user(conn, user.id)?;
+        // The links sent to the previous address stop working
+        if let Some(ref previous) = user.email {
+            PasswordResetRequest::delete_for_email(conn, previous)?;
+        }
+        Ok(EmailChangeStatus::Done {
+            previous: user.email,
+            user: User::get(conn, change.user_id)?,
+        })
+    }
+
+    /// Gives up the pending change of `user`, if there is one.
+    pub fn cancel(conn: &Connection, user: &User) -> Result<()> {
+        Self::delete_for_user(conn, user.id)?;
+        diesel::update(user)
+            .set(users::pending_email.eq(None::<String>))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    fn delete_for_user(conn: &Connection, user_id: i32) -> Result<()> {
+        diesel::delete(email_changes::table.filter(email_changes::user_id.eq(user_id)))
+            .execute(conn)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tests::db, users::tests as user_tests};
+    use diesel::Connection;
+
+    #[test]
+    fn confirm_from_both_addresses() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let users = user_tests::fill_database(conn);
+            let user = &users[1];
+            assert!(EmailChange::request(conn, user, "user@example.com").is_err());
+            // Already used by another account
+            assert!(EmailChange::request(conn, user, "admin@example.com").is_err());
+
+            let tokens = EmailChange::request(conn, user, " new@example.com ")?;
+            assert_eq!(
+                User::get(conn, user.id)?.pending_email.as_deref(),
+                Some("new@example.com")
+            );
+            match EmailChange::confirm(conn, &tokens.new)? {
+                EmailChangeStatus::Waiting => {}
+                EmailChangeStatus::Done { .. } => panic!("The current address didn't confirm"),
+            }
+            assert_eq!(
+                User::get(conn, user.id)?.email.as_deref(),
+                Some("user@example.com")
+            );
+            match EmailChange::confirm(conn, &tokens.current.unwrap())? {
+                EmailChangeStatus::Done { user, previous } => {
+                    assert_eq!(user.email.as_deref(), Some("new@example.com"));
+                    assert!(user.pending_email.is_none());
+                    assert_eq!(previous.as_deref(), Some("user@example.com"));
+                }
+                EmailChangeStatus::Waiting => panic!("Both addresses confirmed"),
+            }
+            // The links only work once
+            assert!(EmailChange::confirm(conn, &tokens.new).is_err());
+
+            // A new change replaces the previous one, and can be cancelled
+            let first = EmailChange::request(conn, user, "first@example.com")?;
+            EmailChange::request(conn, user, "second@example.com")?;
+            assert!(EmailChange::confirm(conn, &first.new).is_err());
+            EmailChange::cancel(conn, user)?;
+            assert!(User::get(conn, user.id)?.pending_email.is_none());
+            Ok(())
+        });
+    }
+}
@@ -1,14 +1,12 @@
 use crate::{
     blocklisted_emails::BlocklistedEmail,
-    db_conn::DbConn,
+    db_conn::{write_transaction, DbConn},
     schema::email_signups,
     users::{NewUser, Role, User},
-    Error, Result,
+    Connection, Error, Result,
 };
 use chrono::{offset::Utc, Duration, NaiveDateTime};
-use diesel::{
-    Connection as _, ExpressionMethods, Identifiable, Insertable, QueryDsl, Queryable, RunQueryDsl,
-};
+use diesel::{ExpressionMethods, Identifiable, Insertable, QueryDsl, Queryable, RunQueryDsl};
 use plume_common::utils::random_hex;
 use std::ops::Deref;
 
@@ -63,7 +61,7 @@ impl EmailSignup {
     pub fn start(conn: &DbConn, email: &str) -> Result<Token> {
         Self::ensure_email_not_blocked(conn, email)?;
 
-        conn.transaction(|| {
+        write_transaction(conn, || {
             Self::ensure_user_not_exist_by_email(conn, email)?;
             let _rows = Self::delete_existings_by_email(conn, email)?;
             let token = Token::generate();
@@ -95,7 +93,7 @@ impl EmailSignup {
     pub fn confirm(&self, conn: &DbConn) -> Result<()> {
         Self::ensure_email_not_blocked(conn, &self.email)?;
 
-        conn.transaction(|| {
+        write_transaction(conn, || {
             Self::ensure_user_not_exist_by_email(conn, &self.email)?;
             if self.expired() {
                 Self::delete_existings_by_email(conn, &self.email)?;
@@ -108,7 +106,7 @@ impl EmailSignup {
     pub fn complete(&self, conn: &DbConn, username: String, password: String) -> Result<User> {
         Self::ensure_email_not_blocked(conn, &self.email)?;
 
-        conn.transaction(|| {
+        write_transaction(conn, || {
             Self::ensure_user_not_exist_by_email(conn, &self.email)?;
             let user = NewUser::new_local(
                 conn,
@@ -145,10 +143,10 @@ impl EmailSignup {
         Ok(())
     }
 
-    fn delete_existings_by_email(conn: &DbConn, email: &str) -> Result<usize> {
+    pub(crate) fn delete_existings_by_email(conn: &Connection, email: &str) -> Result<usize> {
         let existing_signups = email_signups::table.filter(email_signups::email.eq(email));
         diesel::delete(existing_signups)
-            .execute(&**conn)
+            .execute(conn)
             .map_err(Error::from)
     }
 
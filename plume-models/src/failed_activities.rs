@@ -0,0 +1,166 @@
+//! Activities received in an inbox that could not be handled.
+//!
+//! They often fail because another instance or the database was not available for a moment,
+//! so they are tried again later, waiting longer each time, and kept for a week.
+
+use crate::{inbox::inbox, schema::failed_activities, Connection, Error, Result};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+use tracing::warn;
+
+/// How many times an activity is handled before giving up.
+pub const MAX_ATTEMPTS: i32 = 6;
+
+/// How many activities are tried again in a single run of the worker.
+const BATCH_SIZE: i64 = 20;
+
+/// How long failed activities are kept, in days.
+const RETENTION_DAYS: i64 = 7;
+
+#[derive(Clone, Debug, Queryable, Identifiable)]
+#[table_name = "failed_activities"]
+pub struct FailedActivity {
+    pub id: i32,
+    /// The activity, as JSON
+    pub activity: String,
+    /// The last error
+    pub error: String,
+    pub attempts: i32,
+    pub next_attempt: NaiveDateTime,
+    pub creation_date: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "failed_activities"]
+struct NewFailedActivity {
+    activity: String,
+    error: String,
+    next_attempt: NaiveDateTime,
+}
+
+impl FailedActivity {
+    get!(failed_activities);
+
+    /// Saves an activity that could not be handled, to try it again later.
+    pub fn record(conn: &Connection, activity: &serde_json::Value, error: &str) -> Result<Self> {
+        diesel::insert_into(failed_activities::table)
+            .values(NewFailedActivity {
+                activity: activity.to_string(),
+                error: error.to_owned(),
+                next_attempt: Utc::now().naive_utc() + retry_delay(1),
+            })
+            .execute(conn)?;
+        failed_activities::table
+            .order(failed_activities::id.desc())
+            .first(conn)
+            .map_err(Error::from)
+    }
+
+    /// Activities waiting to be handled again.
+    pub fn list_due(conn: &Connection) -> Result<Vec<Self>> {
+        failed_activities::table
+            .filter(failed_activities::attempts.lt(MAX_ATTEMPTS))
+            .filter(failed_activities::next_attempt.le(Utc::now().naive_utc()))
+            .order(failed_activities::next_attempt.asc())
+            .limit(BATCH_SIZE)
+            .load::<Self>(conn)
+            .map_err(Error::from)
+    }
+
+    /// Handles the activity again. It is forgotten if it works this time.
+    pub fn retry(&self, conn: &Connection) -> Result<Option<Self>> {
+        let activity = serde_json::from_str(&self.activity)?;
+        match inbox(conn, activity) {
+            Ok(_) => {
+                diesel::delete(self).execute(conn)?;
+                Ok(None)
+            }
+            Err(e) => {
+                let attempts = self.attempts + 1;
+                diesel::update(self)
+                    .set((
+                        failed_activities::error.eq(format!("{:?}", e)),
+                        failed_activities::attempts.eq(attempts),
+                        failed_activities::next_attempt
+                            .eq(Utc::now().naive_utc() + retry_delay(attempts)),
+                    ))
+                    .execute(conn)?;
+                Self::get(conn, self.id).map(Some)
+            }
+        }
+    }
+
+    /// Handles all the activities that are due again.
+    pub fn retry_due(conn: &Connection) -> Result<()> {
+        for failed in Self::list_due(conn)? {
+            match failed.retry(conn) {
+                Ok(Some(f)) if f.attempts >= MAX_ATTEMPTS => {
+                    warn!("Giving up handling activity {}: {}", f.id, f.error)
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Failed to handle activity {} again: {:?}", failed.id, e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Forgets the activities that failed more than a week ago.
+    pub fn purge_expired(conn: &Connection) -> Result<usize> {
+        let limit = Utc::now().naive_utc() - Duration::days(RETENTION_DAYS);
+        diesel::delete(failed_activities::table.filter(failed_activities::creation_date.lt(limit)))
+            .execute(conn)
+            .map_err(Error::from)
+    }
+}
+
+/// Waits 2, 4, 8, … minutes between attempts.
+fn retry_delay(attempts: i32) -> Duration {
+    Duration::minutes(1 << attempts.min(10))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::db;
+    use diesel::Connection;
+    use serde_json::json;
+
+    #[test]
+    fn retry_and_purge() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            // No handler matches this activity: it fails every time
+            let activity = json!({ "id": "https://remote.example/activity/1", "type": "Nothing" });
+            let failed = FailedActivity::record(conn, &activity, "NotFound")?;
+            assert_eq!(failed.attempts, 1);
+            assert!(FailedActivity::list_due(conn)?.is_empty());
+
+            diesel::update(&failed)
+                .set(failed_activities::next_attempt.eq(Utc::now().naive_utc()))
+                .execute(&**conn)?;
+            assert_eq!(FailedActivity::list_due(conn)?.len(), 1);
+            FailedActivity::retry_due(conn)?;
+            let again = FailedActivity::get(conn, failed.id)?;
+            assert_eq!(again.attempts, 2);
+            assert!(again.next_attempt > Utc::now().naive_utc());
+
+            diesel::update(&failed)
+                .set((
+                    failed_activities::attempts.eq(MAX_ATTEMPTS),
+                    failed_activities::next_attempt.eq(Utc::now().naive_utc()),
+                ))
+                .execute(&**conn)?;
+            assert!(FailedActivity::list_due(conn)?.is_empty());
+
+            assert_eq!(FailedActivity::purge_expired(conn)?, 0);
+            diesel::update(failed_activities::table)
+                .set(
+                    failed_activities::creation_date
+                        .eq(Utc::now().naive_utc() - Duration::days(RETENTION_DAYS + 1)),
+                )
+                .execute(&**conn)?;
+            assert_eq!(FailedActivity::purge_expired(conn)?, 1);
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,190 @@
+//! Activities that could not be delivered to another instance.
+//!
+//! They are kept for a week, so that administrators can see which instances have problems
+//! (`plm federation deliveries`) and send the activities again (`plm federation resend`).
+
+use crate::{
+    blogs::Blog, schema::failed_deliveries, users::User, Connection, Error, Result, CONFIG,
+};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+use plume_common::activity_pub::{request, sign::Signer, FailedDelivery as Failure};
+use url::Url;
+
+/// How long failed deliveries are kept, in days.
+const RETENTION_DAYS: i64 = 7;
+
+#[derive(Clone, Debug, Queryable, Identifiable)]
+#[table_name = "failed_deliveries"]
+pub struct FailedDelivery {
+    pub id: i32,
+    pub activity_id: String,
+    /// The signed activity, as JSON
+    pub activity: String,
+    /// The key of the sender
+    pub key_id: String,
+    pub inbox: String,
+    pub host: String,
+    /// The last error
+    pub error: String,
+    pub attempts: i32,
+    pub creation_date: NaiveDateTime,
+    pub last_attempt: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "failed_deliveries"]
+struct NewFailedDelivery {
+    activity_id: String,
+    activity: String,
+    key_id: String,
+    inbox: String,
+    host: String,
+    error: String,
+}
+
+impl FailedDelivery {
+    get!(failed_deliveries);
+
+    /// Saves a failed delivery, or counts another attempt if it already failed.
+    pub fn record(conn: &Connection, failure: Failure) -> Result<Self> {
+        let activity_id = failure.activity["id"]
+            .as_str()
+            .unwrap_or_default()
+            .to_owned();
+        let previous = failed_deliveries::table
+            .filter(failed_deliveries::activity_id.eq(&activity_id))
+            .filter(failed_deliveries::inbox.eq(&failure.inbox))
+            .first::<Self>(conn)
+            .ok();
+        if let Some(previous) = previous {
+            return previous.failed_again(conn, &failure.error);
+        }
+        let host = Url::parse(&failure.inbox)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_owned))
+            .unwrap_or_default();
+        diesel::insert_into(failed_deliveries::table)
+            .values(NewFailedDelivery {
+                activity_id,
+                activity: failure.activity.to_string(),
+                key_id: failure.key_id,
+                inbox: failure.inbox,
+                host,
+                error: failure.error,
+            })
+            .execute(conn)?;
+        failed_deliveries::table
+            .order(failed_deliveries::id.desc())
+            .first(conn)
+            .map_err(Error::from)
+    }
+
+    /// The failed deliveries, the most recent first, optionally only to `host`.
+    pub fn list(conn: &Connection, host: Option<&str>) -> Result<Vec<Self>> {
+        let mut query = failed_deliveries::table
+            .order(failed_deliveries::last_attempt.desc())
+            .into_boxed();
+        if let Some(host) = host {
+            query = query.filter(failed_deliveries::host.eq(host));
+        }
+        query.load(conn).map_err(Error::from)
+    }
+
+    /// The inboxes to which an activity could not be delivered.
+    pub fn for_activity(conn: &Connection, activity_id: &str) -> Result<Vec<Self>> {
+        failed_deliveries::table
+            .filter(failed_deliveries::activity_id.eq(activity_id))
+            .load(conn)
+            .map_err(Error::from)
+    }
+
+    /// Sends the activity again. It is forgotten if it is delivered this time.
+    ///
+    /// Returns the new error, if it failed again.
+    pub fn resend(&self, conn: &Connection) -> Result<Option<String>> {
+        let sender = signer(conn, &self.key_id)?;
+        let activity: serde_json::Value = serde_json::from_str(&self.activity)?;
+        let error = match request::post(&self.inbox, &activity, &*sender, CONFIG.proxy().cloned()) {
+            Ok(res) if res.status().is_success() => {
+                diesel::delete(self).execute(conn)?;
+                return Ok(None);
+            }
+            Ok(res) => format!("HTTP status {}", res.status()),
+            Err(_) => "the inbox could not be reached".to_owned(),
+        };
+        self.failed_again(conn, &error)?;
+        Ok(Some(error))
+    }
+
+    fn failed_again(&self, conn: &Connection, error: &str) -> Result<Self> {
+        diesel::update(self)
+            .set((
+                failed_deliveries::error.eq(error),
+                failed_deliveries::attempts.eq(self.attempts + 1),
+                failed_deliveries::last_attempt.eq(Utc::now().naive_utc()),
+            ))
+            .execute(conn)?;
+        Self::get(conn, self.id)
+    }
+
+    /// Forgets the deliveries that failed more than a week ago.
+    pub fn purge_expired(conn: &Connection) -> Result<usize> {
+        let limit = Utc::now().naive_utc() - Duration::days(RETENTION_DAYS);
+        diesel::delete(failed_deliveries::table.filter(failed_deliveries::creation_date.lt(limit)))
+            .execute(conn)
+            .map_err(Error::from)
+    }
+}
+
+/// The local user or blog that has the key `key_id`.
+fn signer(conn: &Connection, key_id: &str) -> Result<Box<dyn Signer>> {
+    let actor = key_id.trim_end_matches("#main-key");
+    match User::find_by_ap_url(conn, actor) {
+        Ok(user) => Ok(Box::new(user)),
+        Err(_) => Ok(Box::new(Blog::find_by_ap_url(conn, actor)?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tests::db, users::tests::fill_database};
+    use diesel::Connection;
+    use serde_json::json;
+
+    #[test]
+    fn record_and_purge() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let users = fill_database(conn);
+            let failure = Failure {
+                activity: json!({ "id": "https://plu.me/activity/1", "type": "Create" }),
+                key_id: users[0].get_key_id(),
+                inbox: "https://remote.example/inbox".to_owned(),
+                error: "HTTP status 502 Bad Gateway".to_owned(),
+            };
+            FailedDelivery::record(conn, failure.clone())?;
+            let again = FailedDelivery::record(conn, failure)?;
+            assert_eq!(again.attempts, 2);
+            assert_eq!(again.host, "remote.example");
+            assert_eq!(
+                FailedDelivery::for_activity(conn, "https://plu.me/activity/1")?.len(),
+                1
+            );
+            assert_eq!(FailedDelivery::list(conn, Some("remote.example"))?.len(), 1);
+            assert!(FailedDelivery::list(conn, Some("other.example"))?.is_empty());
+            assert!(signer(conn, &again.key_id).is_ok());
+
+            assert_eq!(FailedDelivery::purge_expired(conn)?, 0);
+            diesel::update(failed_deliveries::table)
+                .set(
+                    failed_deliveries::creation_date
+                        .eq(Utc::now().naive_utc() - Duration::days(RETENTION_DAYS + 1)),
+                )
+                .execute(&**conn)?;
+            assert_eq!(FailedDelivery::purge_expired(conn)?, 1);
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,230 @@
+//! Protection against password guessing.
+//!
+//! Failed logins are counted for each account and each IP address. Past a few failures,
+//! logging in is refused for a delay that doubles with each new failure, and the owner
+//! of the account is warned the first time it gets locked.
+
+use crate::{
+    instance::Instance,
+    ip_records::{stored_address, ClientIp},
+    schema::failed_logins,
+    users::User,
+    Connection, Error, Result,
+};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+use rocket::{
+    request::{self, FromRequest, Request},
+    Outcome,
+};
+use std::net::IpAddr;
+
+/// Failures allowed for an account before it gets locked.
+const ACCOUNT_THRESHOLD: usize = 5;
+/// Failures allowed for an IP address, that may be shared by many people.
+const ADDRESS_THRESHOLD: usize = 20;
+/// How long failures are remembered, in hours.
+const WINDOW: i64 = 24;
+/// The first lockout, in seconds. It doubles with each new failure, up to a day.
+const BASE_DELAY: i64 = 60;
+
+#[derive(Insertable)]
+#[table_name = "failed_logins"]
+struct NewFailedLogin {
+    user_id: Option<i32>,
+    address: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum LoginError {
+    /// Wrong credentials. If this failure locked the account, its owner is given, so
+    /// that they can be warned.
+    Invalid(Option<User>),
+    /// Too many failures: logging in is refused until this date.
+    Locked(NaiveDateTime),
+    Other(Error),
+}
+
+impl From<Error> for LoginError {
+    fn from(err: Error) -> Self {
+        LoginError::Other(err)
+    }
+}
+
+/// Checks credentials, while keeping track of the failures.
+pub struct LoginGuard {
+    ip: Option<IpAddr>,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for LoginGuard {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        let ip = request.guard::<ClientIp>()?;
+        Outcome::Success(LoginGuard { ip: ip.0 })
+    }
+}
+
+impl LoginGuard {
+    pub fn new(ip: Option<IpAddr>) -> Self {
+        LoginGuard { ip }
+    }
+
+    /// Logs in, like `User::login`, unless the account or the address is locked.
+    pub fn login(
+        &self,
+        conn: &Connection,
+        ident: &str,
+        password: &str,
+    ) -> std::result::Result<User, LoginError> {
+        let account = find_local(conn, ident);
+        let address = self.ip.map(stored_address);
+
+        let account_lock = match account {
+            Some(ref account) => lock_end(&failures_for_user(conn, account.id)?, ACCOUNT_THRESHOLD),
+            None => None,
+        };
+        let address_lock = match address {
+            Some(ref address) => lock_end(&failures_for_address(conn, address)?, ADDRESS_THRESHOLD),
+            None => None,
+        };
+        if let Some(until) = account_lock.max(address_lock) {
+            return Err(LoginError::Locked(until));
+        }
+
+        match User::login(conn, ident, password) {
+            Ok(user) => {
+                diesel::delete(failed_logins::table.filter(failed_logins::user_id.eq(user.id)))
+                    .execute(conn)
+                    .map_err(Error::from)?;
+                Ok(user)
+            }
+            Err(_) => {
+                diesel::insert_into(failed_logins::table)
+                    .values(NewFailedLogin {
+                        user_id: account.as_ref().map(|a| a.id),
+                        address,
+                    })
+                    .execute(conn)
+                    .map_err(Error::from)?;
+                let just_locked = match account {
+                    Some(ref account) => {
+                        failures_for_user(conn, account.id)?.len() == ACCOUNT_THRESHOLD
+                    }
+                    None => false,
+                };
+                Err(LoginError::Invalid(account.filter(|_| just_locked)))
+            }
+        }
+    }
+}
+
+/// Forgets the failures that are too old to matter.
+pub fn purge_expired(conn: &Connection) -> Result<usize> {
+    diesel::delete(failed_logins::table.filter(failed_logins::creation_date.lt(window_start())))
+        .execute(conn)
+        .map_err(Error::from)
+}
+
+fn window_start() -> NaiveDateTime {
+    Utc::now().naive_utc() - Duration::hours(WINDOW)
+}
+
+fn find_local(conn: &Connection, ident: &str) -> Option<User> {
+    let local_id = Instance::get_local().ok()?.id;
+    User::find_by_email(conn, ident)
+        .or_else(|_| User::find_by_name(conn, ident, local_id))
+        .ok()
+        .filter(|u| u.instance_id == local_id)
+}
+
+fn failures_for_user(conn: &Connection, user_id: i32) -> Result<Vec<NaiveDateTime>> {
+    failed_logins::table
+        .filter(failed_logins::user_id.eq(user_id))
+        .filter(failed_logins::creation_date.gt(window_start()))
+        .select(failed_logins::creation_date)
+        .order(failed_logins::creation_date.desc())
+        .load(conn)
+        .map_err(Error::from)
+}
+
+fn failures_for_address(conn: &Connection, address: &str) -> Result<Vec<NaiveDateTime>> {
+    failed_logins::table
+        .filter(failed_logins::address.eq(address))
+        .filter(failed_logins::creation_date.gt(window_start()))
+        .select(failed_logins::creation_date)
+        .order(failed_logins::creation_date.desc())
+        .load(conn)
+        .map_err(Error::from)
+}
+
+/// When the lockout caused by these failures (the most recent first) ends, if it didn't yet.
+fn lock_end(failures: &[NaiveDateTime], threshold: usize) -> Option<NaiveDateTime> {
+    if failures.len() < threshold {
+        return None;
+    }
+    let doublings = (failures.len() - threshold).min(16) as u32;
+    let delay = (BASE_DELAY << doublings).min(Duration::days(1).num_seconds());
+    let end = failures[0] + Duration::seconds(delay);
+    Some(end).filter(|end| *end > Utc::now().naive_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        tests::db,
+        users::{tests::fill_database, NewUser, Role},
+    };
+    use diesel::Connection;
+
+    #[test]
+    fn lockout() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            fill_database(conn);
+            let user = NewUser::new_local(
+                conn,
+                "test".to_owned(),
+                "test user".to_owned(),
+                Role::Normal,
+                "Hello I'm a test",
+                "test@example.com".to_owned(),
+                Some(User::hash_pass("test_password").unwrap()),
+            )?;
+            let guard = LoginGuard::new("198.51.100.7".parse().ok());
+            guard.login(conn, "test", "wrong").unwrap_err();
+            assert_eq!(
+                guard.login(conn, "test", "test_password").unwrap().id,
+                user.id
+            );
+            for i in 1..ACCOUNT_THRESHOLD {
+                match guard.login(conn, "test", "wrong") {
+                    Err(LoginError::Invalid(None)) => {}
+                    _ => panic!("Failure {} should not lock the account", i),
+                }
+            }
+            match guard.login(conn, "test@example.com", "wrong") {
+                Err(LoginError::Invalid(Some(locked))) => assert_eq!(locked.id, user.id),
+                _ => panic!("The account should be locked"),
+            }
+            match LoginGuard::new(None).login(conn, "test", "test_password") {
+                Err(LoginError::Locked(until)) => assert!(until > Utc::now().naive_utc()),
+                _ => panic!("The account should be locked"),
+            }
+            assert_eq!(purge_expired(conn)?, 0);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn delays() {
+        let now = Utc::now().naive_utc();
+        assert_eq!(lock_end(&[now; 4], 5), None);
+        let end = lock_end(&[now; 7], 5).unwrap();
+        assert_eq!((end - now).num_seconds(), BASE_DELAY * 4);
+        let end = lock_end(&[now; 60], 5).unwrap();
+        assert_eq!((end - now).num_days(), 1);
+        assert_eq!(lock_end(&[now - Duration::hours(2); 6], 5), None);
+    }
+}
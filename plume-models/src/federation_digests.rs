@@ -0,0 +1,254 @@
+//! Summaries of the instances that keep failing to receive activities, sent to the admins
+//! every day or every week (`FEDERATION_DIGEST`).
+//!
+//! They are compiled from the failed deliveries, which are otherwise only visible in the logs
+//! or with `plm federation deliveries`.
+
+use crate::{
+    failed_deliveries::FailedDelivery,
+    instance::Instance,
+    notifications::{notification_kind, NewNotification, Notification},
+    schema::{failed_deliveries, federation_digests},
+    Connection, Error, Result, CONFIG,
+};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+use std::collections::HashMap;
+
+/// How many attempts have to fail during the period for a host to be in the digest.
+const MIN_ATTEMPTS: i32 = 3;
+
+/// Why deliveries to an instance fail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Problem {
+    /// The instance refuses our signatures (401 or 403)
+    Signature,
+    /// The inbox doesn't exist anymore (404 or 410)
+    Gone,
+    /// The instance answers with 5xx errors
+    ServerError,
+    /// The TLS connection can't be established, usually because of the certificate
+    Tls,
+    /// The instance can't be resolved or reached, or doesn't answer in time
+    Unreachable,
+    Other,
+}
+
+impl Problem {
+    /// The problem behind the error of a failed delivery.
+    pub fn of(error: &str) -> Self {
+        let status = error
+            .strip_prefix("HTTP status ")
+            .and_then(|status| status.get(..3))
+            .and_then(|code| code.parse::<u16>().ok());
+        match status {
+            Some(401) | Some(403) => Problem::Signature,
+            Some(404) | Some(410) => Problem::Gone,
+            Some(500..=599) => Problem::ServerError,
+            Some(_) => Problem::Other,
+            None => {
+                let error = error.to_lowercase();
+                if ["certificate", "tls", "ssl", "handshake"]
+                    .iter()
+                    .any(|word| error.contains(word))
+                {
+                    Problem::Tls
+                } else if ["connect", "dns", "timed out", "could not be reached"]
+                    .iter()
+                    .any(|word| error.contains(word))
+                {
+                    Problem::Unreachable
+                } else {
+                    Problem::Other
+                }
+            }
+        }
+    }
+}
+
+/// The failures of an instance during the period of a digest.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostReport {
+    pub host: String,
+    /// The problem behind the last failure
+    pub problem: Problem,
+    /// How many activities could not be delivered
+    pub deliveries: usize,
+    pub attempts: i32,
+    pub last_error: String,
+    pub last_attempt: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Queryable, Identifiable)]
+pub struct FederationDigest {
+    pub id: i32,
+    /// The hosts, as JSON
+    pub report: String,
+    pub creation_date: NaiveDateTime,
+}
+
+impl FederationDigest {
+    get!(federation_digests);
+
+    /// The hosts in this digest, the ones with the most failed attempts first.
+    pub fn hosts(&self) -> Result<Vec<HostReport>> {
+        serde_json::from_str(&self.report).map_err(Error::from)
+    }
+
+    fn latest(conn: &Connection) -> Result<Option<Self>> {
+        federation_digests::table
+            .order(federation_digests::creation_date.desc())
+            .first(conn)
+            .map(Some)
+            .or_else(|e| match e {
+                diesel::result::Error::NotFound => Ok(None),
+                e => Err(Error::from(e)),
+            })
+    }
+
+    /// Compiles a digest if the previous one is older than the configured frequency, and
+    /// notifies the admins if some hosts had recurring failures.
+    pub fn compile(conn: &Connection) -> Result<Option<Self>> {
+        let frequency = match CONFIG.federation_digest {
+            Some(frequency) => frequency,
+            None => return Ok(None),
+        };
+        let since = Utc::now().naive_utc() - Duration::days(frequency.days());
+        if Self::latest(conn)?.map_or(false, |digest| digest.creation_date > since) {
+            return Ok(None);
+        }
+
+        // An empty digest is kept too, so that the next one covers the next period
+        let hosts = recurring_failures(conn, since)?;
+        diesel::insert_into(federation_digests::table)
+            .values(federation_digests::report.eq(serde_json::to_string(&hosts)?))
+            .execute(conn)?;
+        let digest = federation_digests::table
+            .order(federation_digests::id.desc())
+            .first::<Self>(conn)?;
+        if !hosts.is_empty() {
+            for admin in Instance::get_local()?.admins(conn)? {
+                Notification::insert(
+                    conn,
+                    NewNotification {
+                        user_id: admin.id,
+                        kind: notification_kind::FEDERATION_DIGEST.to_string(),
+                        object_id: digest.id,
+                        group_key: None,
+                        filtered: false,
+                    },
+                )?;
+            }
+        }
+        Ok(Some(digest))
+    }
+}
+
+/// The hosts that failed at least `MIN_ATTEMPTS` times since `since`.
+fn recurring_failures(conn: &Connection, since: NaiveDateTime) -> Result<Vec<HostReport>> {
+    let failures = failed_deliveries::table
+        .filter(failed_deliveries::last_attempt.gt(since))
+        .order(failed_deliveries::last_attempt.asc())
+        .then_order_by(failed_deliveries::id.asc())
+        .load::<FailedDelivery>(conn)?;
+    let mut hosts = HashMap::<String, HostReport>::new();
+    for failure in failures {
+        let report = hosts
+            .entry(failure.host.clone())
+            .or_insert_with(|| HostReport {
+                host: failure.host.clone(),
+                problem: Problem::Other,
+                deliveries: 0,
+                attempts: 0,
+                last_error: String::new(),
+                last_attempt: failure.last_attempt,
+            });
+        report.problem = Problem::of(&failure.error);
+        report.deliveries += 1;
+        report.attempts += failure.attempts;
+        report.last_error = failure.error;
+        report.last_attempt = failure.last_attempt;
+    }
+    let mut hosts = hosts
+        .into_values()
+        .filter(|report| report.attempts >= MIN_ATTEMPTS)
+        .collect::<Vec<_>>();
+    hosts.sort_by(|a, b| b.attempts.cmp(&a.attempts).then(a.host.cmp(&b.host)));
+    Ok(hosts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tests::db, users::tests::fill_database};
+    use diesel::Connection;
+    use plume_common::activity_pub::FailedDelivery as Failure;
+    use serde_json::json;
+
+    #[test]
+    fn problems() {
+        assert_eq!(
+            Problem::of("HTTP status 401 Unauthorized"),
+            Problem::Signature
+        );
+        assert_eq!(Problem::of("HTTP status 410 Gone"), Problem::Gone);
+        assert_eq!(
+            Problem::of("HTTP status 502 Bad Gateway"),
+            Problem::ServerError
+        );
+        assert_eq!(
+            Problem::of("error trying to connect: invalid peer certificate: Expired"),
+            Problem::Tls
+        );
+        assert_eq!(
+            Problem::of("error trying to connect: dns error: failed to lookup address"),
+            Problem::Unreachable
+        );
+        assert_eq!(
+            Problem::of("HTTP status 422 Unprocessable Entity"),
+            Problem::Other
+        );
+    }
+
+    #[test]
+    fn compile() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let users = fill_database(conn);
+            let fail = |id: u32, inbox: &str, error: &str| {
+                FailedDelivery::record(
+                    conn,
+                    Failure {
+                        activity: json!({ "id": format!("https://plu.me/activity/{}", id) }),
+                        key_id: users[0].get_key_id(),
+                        inbox: inbox.to_owned(),
+                        error: error.to_owned(),
+                    },
+                )
+            };
+            fail(1, "https://rejecting.example/inbox", "HTTP status 503")?;
+            fail(1, "https://rejecting.example/inbox", "HTTP status 503")?;
+            fail(2, "https://rejecting.example/inbox", "HTTP status 401")?;
+            fail(1, "https://flaky.example/inbox", "HTTP status 502")?;
+
+            let digest = FederationDigest::compile(conn)?.unwrap();
+            let hosts = digest.hosts()?;
+            assert_eq!(hosts.len(), 1);
+            assert_eq!(hosts[0].host, "rejecting.example");
+            assert_eq!(hosts[0].problem, Problem::Signature);
+            assert_eq!(hosts[0].deliveries, 2);
+            assert_eq!(hosts[0].attempts, 3);
+            let admin = Instance::get_local()?.admins(conn)?.remove(0);
+            assert!(
+                Notification::find(conn, notification_kind::FEDERATION_DIGEST, digest.id)
+                    .map(|notif| notif.user_id == admin.id)
+                    .unwrap_or(false)
+            );
+
+            // Not again before the next period
+            assert!(FederationDigest::compile(conn)?.is_none());
+            Ok(())
+        });
+    }
+}
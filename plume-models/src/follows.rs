@@ -1,6 +1,6 @@
 use crate::{
-    ap_url, instance::Instance, notifications::*, schema::follows, users::User, Connection, Error,
-    Result, CONFIG,
+    ap_url, instance::Instance, notifications::*, schema::follows,
+    severed_relationships::Severance, users::User, Connection, Error, Result, CONFIG,
 };
 use activitystreams::{
     activity::{Accept, ActorAndObjectRef, Follow as FollowAct, Undo},
@@ -70,13 +70,18 @@ impl Follow {
 
     pub fn notify(&self, conn: &Connection) -> Result<()> {
         if User::get(conn, self.following_id)?.is_local() {
-            Notification::insert(
+            Notification::notify_from(
                 conn,
+                &User::get(conn, self.follower_id)?,
+                "",
                 NewNotification {
                     kind: notification_kind::FOLLOW.to_string(),
                     object_id: self.id,
                     user_id: self.following_id,
+                    group_key: None,
+                    filtered: false,
                 },
+                Some(self.following_id),
             )?;
         }
         Ok(())
@@ -210,12 +215,13 @@ impl AsObject<User, Undo, &Connection> for Follow {
         let conn = conn;
         if self.follower_id == actor.id {
             diesel::delete(&self).execute(conn)?;
-
-            // delete associated notification if any
-            if let Ok(notif) = Notification::find(conn, notification_kind::FOLLOW, self.id) {
-                diesel::delete(&notif).execute(conn)?;
+            let followed = User::get(conn, self.following_id)?;
+            if !actor.is_local() && followed.is_local() {
+                Severance::record_unfollow(conn, &actor, &followed)?;
             }
 
+            Notification::retract(conn, notification_kind::FOLLOW, self.id, self.following_id)?;
+
             Ok(())
         } else {
             Err(Error::Unauthorized)
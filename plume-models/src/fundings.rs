@@ -0,0 +1,223 @@
+//! Ways to support blogs and authors financially.
+//!
+//! A Web Monetization payment pointer is added to their pages as a meta tag, and
+//! Liberapay and Ko-fi pages are linked from their profile. They all federate as
+//! `PropertyValue` attachments of the actor, like the profile fields of Mastodon.
+
+use crate::{
+    cache::{self, Entry},
+    schema::fundings,
+    Connection, Error, Result,
+};
+use activitystreams::base::AnyBase;
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl, SaveChangesDsl};
+use plume_common::utils::escape;
+use serde_json::json;
+
+#[derive(Clone, Queryable, Identifiable, AsChangeset)]
+pub struct Funding {
+    pub id: i32,
+    pub blog_id: Option<i32>,
+    pub user_id: Option<i32>,
+    pub payment_pointer: String,
+    pub liberapay_url: String,
+    pub kofi_url: String,
+}
+
+#[derive(Default, Insertable)]
+#[table_name = "fundings"]
+pub struct NewFunding {
+    pub blog_id: Option<i32>,
+    pub user_id: Option<i32>,
+    pub payment_pointer: String,
+    pub liberapay_url: String,
+    pub kofi_url: String,
+}
+
+impl Funding {
+    insert!(fundings, NewFunding);
+    get!(fundings);
+
+    pub fn for_blog(conn: &Connection, blog_id: i32) -> Result<Self> {
+        fundings::table
+            .filter(fundings::blog_id.eq(blog_id))
+            .get_result(conn)
+            .map_err(Error::from)
+    }
+
+    pub fn for_user(conn: &Connection, user_id: i32) -> Result<Self> {
+        fundings::table
+            .filter(fundings::user_id.eq(user_id))
+            .get_result(conn)
+            .map_err(Error::from)
+    }
+
+    /// Saves the funding links of a blog or a user, replacing the previous ones.
+    ///
+    /// Liberapay and Ko-fi accounts can be given as a username or as a URL. When all
+    /// the fields are empty, the previous links are removed and `None` is returned.
+    pub fn save(conn: &Connection, new: NewFunding) -> Result<Option<Self>> {
+        let new = NewFunding {
+            payment_pointer: normalize_payment_pointer(&new.payment_pointer)?,
+            liberapay_url: normalize_url(&new.liberapay_url, "https://liberapay.com/")?,
+            kofi_url: normalize_url(&new.kofi_url, "https://ko-fi.com/")?,
+            ..new
+        };
+        let (previous, actor) = match (new.blog_id, new.user_id) {
+            (Some(blog_id), None) => (
+                Self::for_blog(conn, blog_id).ok(),
+                Entry::BlogActor(blog_id),
+            ),
+            (None, Some(user_id)) => (
+                Self::for_user(conn, user_id).ok(),
+                Entry::UserActor(user_id),
+            ),
+            _ => return Err(Error::InvalidValue),
+        };
+        let empty = new.payment_pointer.is_empty()
+            && new.liberapay_url.is_empty()
+            && new.kofi_url.is_empty();
+        let saved = match previous {
+            Some(previous) if empty => {
+                diesel::delete(&previous).execute(conn)?;
+                None
+            }
+            Some(mut previous) => {
+                previous.payment_pointer = new.payment_pointer;
+                previous.liberapay_url = new.liberapay_url;
+                previous.kofi_url = new.kofi_url;
+                Some(previous.save_changes(conn)?)
+            }
+            None if empty => None,
+            None => Some(Self::insert(conn, new)?),
+        };
+        // The links are attachments of the actor
+        cache::forget(actor);
+        Ok(saved)
+    }
+
+    /// The pages where one can give money, with the name of the platform.
+    pub fn links(&self) -> Vec<(&'static str, &str)> {
+        let mut links = vec![];
+        if !self.liberapay_url.is_empty() {
+            links.push(("Liberapay", self.liberapay_url.as_str()));
+        }
+        if !self.kofi_url.is_empty() {
+            links.push(("Ko-fi", self.kofi_url.as_str()));
+        }
+        links
+    }
+
+    /// The funding links as `PropertyValue`, to be attached to an actor.
+    pub fn to_attachments(&self) -> Result<Vec<AnyBase>> {
+        let mut fields = self
+            .links()
+            .into_iter()
+            .map(|(name, url)| {
+                (
+                    name,
+                    format!(
+                        r#"<a href="{url}" rel="me nofollow noopener noreferrer" target="_blank">{url}</a>"#,
+                        url = escape(url)
+                    ),
+                )
+            })
+            .collect::<Vec<_>>();
+        if !self.payment_pointer.is_empty() {
+            fields.push((
+                "Web Monetization",
+                escape(&self.payment_pointer).to_string(),
+            ));
+        }
+        fields
+            .into_iter()
+            .map(|(name, value)| {
+                AnyBase::from_arbitrary_json(json!({
+                    "type": "PropertyValue",
+                    "name": name,
+                    "value": value,
+                }))
+                .map_err(Error::from)
+            })
+            .collect()
+    }
+}
+
+/// Payment pointers look like `$wallet.example/alice`, or are an HTTPS URL.
+fn normalize_payment_pointer(pointer: &str) -> Result<String> {
+    let pointer = pointer.trim();
+    let valid = pointer.is_empty()
+        || ((pointer.starts_with('$') || pointer.starts_with("https://"))
+            && pointer.len() > 1
+            && !pointer.contains(char::is_whitespace));
+    if valid {
+        Ok(pointer.to_owned())
+    } else {
+        Err(Error::InvalidValue)
+    }
+}
+
+fn normalize_url(account: &str, base: &str) -> Result<String> {
+    let account = account.trim();
+    if account.is_empty() {
+        Ok(String::new())
+    } else if let Some(username) = account.strip_prefix(base) {
+        normalize_url(username.trim_end_matches('/'), base)
+    } else if account
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == '.')
+    {
+        Ok(format!("{}{}", base, account))
+    } else {
+        Err(Error::InvalidValue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{blogs::tests::fill_database, tests::db};
+    use diesel::Connection;
+
+    #[test]
+    fn save_funding() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (_, blogs) = fill_database(conn);
+            let funding = Funding::save(
+                conn,
+                NewFunding {
+                    blog_id: Some(blogs[0].id),
+                    payment_pointer: "$wallet.example/blog".to_owned(),
+                    liberapay_url: "https://liberapay.com/Plume/".to_owned(),
+                    kofi_url: "plume".to_owned(),
+                    ..NewFunding::default()
+                },
+            )?
+            .unwrap();
+            assert_eq!(funding.liberapay_url, "https://liberapay.com/Plume");
+            assert_eq!(funding.kofi_url, "https://ko-fi.com/plume");
+            assert_eq!(funding.to_attachments()?.len(), 3);
+            assert!(Funding::save(
+                conn,
+                NewFunding {
+                    blog_id: Some(blogs[0].id),
+                    kofi_url: "https://evil.example/".to_owned(),
+                    ..NewFunding::default()
+                },
+            )
+            .is_err());
+
+            assert!(Funding::save(
+                conn,
+                NewFunding {
+                    blog_id: Some(blogs[0].id),
+                    ..NewFunding::default()
+                },
+            )?
+            .is_none());
+            assert!(Funding::for_blog(conn, blogs[0].id).is_err());
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,412 @@
+//! Image posts, like the ones published with Pixelfed.
+//!
+//! They are notes with a few images attached, that would only show their caption if
+//! they were read as comments. Instead, they are saved as articles showing all the
+//! images, in a blog standing for the account that posted them.
+
+use crate::{
+    blog_authors::{BlogAuthor, BlogRole, NewBlogAuthor},
+    blogs::{Blog, NewBlog},
+    inbox::InboxResult,
+    medias::{Media, NewMedia},
+    post_authors::{NewPostAuthor, PostAuthor},
+    posts::{NewPost, Post},
+    safe_string::SafeString,
+    tags::{NewTag, Tag},
+    timeline::{Kind, Timeline},
+    users::User,
+    Connection, Error, Result, CONFIG,
+};
+use chrono::DateTime;
+use diesel::SaveChangesDsl;
+use plume_common::{
+    activity_pub::{inbox::FromId, PUBLIC_VISIBILITY},
+    utils::{escape, random_hex},
+};
+use serde_json::Value;
+
+/// Titles made from the caption of a post are cut after this many characters.
+const MAX_TITLE_LENGTH: usize = 80;
+
+/// An image attached to a note.
+#[derive(Debug, PartialEq)]
+pub struct GalleryImage {
+    pub url: String,
+    pub alt_text: String,
+}
+
+/// Tells if `act` is the public creation of a note with images, that is not a reply.
+///
+/// Notes for followers or for a few accounts are left to the comment path, so that they
+/// don't end up in public galleries.
+pub fn is_image_post(act: &Value) -> bool {
+    act["type"].as_str() == Some("Create")
+        && act["object"]["type"].as_str() == Some("Note")
+        && act["object"]["inReplyTo"].is_null()
+        && is_public(&act["object"])
+        && !images(&act["object"]).is_empty()
+}
+
+/// Tells if the public collection is one of the recipients of `object`.
+fn is_public(object: &Value) -> bool {
+    ["to", "cc"].iter().any(|field| match &object[field] {
+        Value::String(recipient) => is_public_collection(recipient),
+        Value::Array(recipients) => recipients
+            .iter()
+            .filter_map(Value::as_str)
+            .any(is_public_collection),
+        _ => false,
+    })
+}
+
+fn is_public_collection(recipient: &str) -> bool {
+    recipient == PUBLIC_VISIBILITY || recipient == "as:Public" || recipient == "Public"
+}
+
+/// The images attached to a note, with their alternative texts.
+pub fn images(note: &Value) -> Vec<GalleryImage> {
+    let attachments = match &note["attachment"] {
+        Value::Array(attachments) => attachments.iter().collect(),
+        attachment @ Value::Object(_) => vec![attachment],
+        _ => vec![],
+    };
+    attachments
+        .into_iter()
+        .filter(|a| {
+            a["type"].as_str() == Some("Image")
+                || (a["type"].as_str() == Some("Document")
+                    && a["mediaType"]
+                        .as_str()
+                        .map(|t| t.starts_with("image/"))
+                        .unwrap_or(false))
+        })
+        .filter_map(|a| {
+            let url = match &a["url"] {
+                Value::String(url) => Some(url.as_str()),
+                Value::Array(urls) => urls.iter().find_map(|u| u["href"].as_str()),
+                url => url["href"].as_str(),
+            }?;
+            Some(GalleryImage {
+                url: url.to_owned(),
+                alt_text: a["name"].as_str().unwrap_or_default().to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// Saves an image post as an article.
+pub fn handle_create(conn: &Connection, act: &Value) -> Result<InboxResult> {
+    let note = &act["object"];
+    let actor = act["actor"]
+        .as_str()
+        .or_else(|| act["actor"]["id"].as_str())
+        .ok_or(Error::MissingApProperty)?;
+    let attributed_to = note["attributedTo"]
+        .as_str()
+        .or_else(|| note["attributedTo"]["id"].as_str());
+    if attributed_to != Some(actor) {
+        return Err(Error::Unauthorized);
+    }
+    let id = note["id"].as_str().ok_or(Error::MissingApProperty)?;
+    if let Ok(post) = Post::find_by_ap_url(conn, id) {
+        return Ok(InboxResult::Post(post));
+    }
+
+    let author = User::from_id(conn, actor, None, CONFIG.proxy()).map_err(|(_, e)| e)?;
+    let blog = gallery_blog(conn, &author)?;
+    let images = images(note);
+    let caption = note["content"].as_str().unwrap_or_default();
+    let content_warning = note["summary"]
+        .as_str()
+        .filter(|cw| !cw.is_empty())
+        .or_else(|| {
+            note["sensitive"]
+                .as_bool()
+                .filter(|sensitive| *sensitive)
+                .map(|_| "⚠")
+        });
+
+    let cover = images.first().and_then(|image| {
+        Media::insert(
+            conn,
+            NewMedia {
+                file_path: String::new(),
+                alt_text: image.alt_text.clone(),
+                is_remote: true,
+                remote_url: Some(image.url.clone()),
+                sensitive: content_warning.is_some(),
+                content_warning: content_warning.map(str::to_owned),
+                owner_id: author.id,
+            },
+        )
+        .ok()
+    });
+
+    let post = Post::insert(
+        conn,
+        NewPost {
+            blog_id: blog.id,
+            // Captions are often empty, so they can't be used to make unique slugs
+            slug: id
+                .trim_end_matches('/')
+                .rsplit('/')
+                .next()
+                .unwrap_or(id)
+                .to_owned(),
+            title: title(caption).unwrap_or_else(|| author.display_name.clone()),
+            content: SafeString::new_remote(&format!(
+                "{}{}",
+                gallery_html(&images, content_warning),
+                caption
+            )),
+            published: true,
+            license: String::new(),
+            creation_date: note["published"]
+                .as_str()
+                .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+                .map(|date| date.naive_utc()),
+            ap_url: id.to_owned(),
+            subtitle: String::new(),
+            source: String::new(),
+            cover_id: cover.map(|c| c.id),
+            excerpt: String::new(),
+            canonical_url: None,
+        },
+    )?;
+    PostAuthor::insert(
+        conn,
+        NewPostAuthor {
+            post_id: post.id,
+            author_id: author.id,
+        },
+    )?;
+
+    if let Value::Array(tags) = &note["tag"] {
+        for tag in tags
+            .iter()
+            .filter(|t| t["type"].as_str() == Some("Hashtag"))
+        {
+            if let Some(name) = tag["name"].as_str().map(|n| n.trim_start_matches('#')) {
+                Tag::insert(
+                    conn,
+                    NewTag {
+                        tag: name.to_owned(),
+                        is_hashtag: true,
+                        post_id: post.id,
+                    },
+                )
+                .ok();
+            }
+        }
+    }
+
+    Timeline::add_to_all_timelines(conn, &post, Kind::Original)?;
+    Ok(InboxResult::Post(post))
+}
+
+/// The blog in which the image posts of a remote account are saved.
+///
+/// Its id is the one of the account with a `#gallery` fragment, so that looking the account
+/// up by its id never finds the blog instead.
+pub fn gallery_blog(conn: &Connection, author: &User) -> Result<Blog> {
+    let ap_url = gallery_id(author);
+    if let Ok(blog) = Blog::find_by_ap_url(conn, &ap_url) {
+        return Ok(blog);
+    }
+    // Galleries used to share the id of their account
+    if let Ok(mut blog) = Blog::find_by_ap_url(conn, &author.ap_url) {
+        if BlogAuthor::find(conn, blog.id, author.id).is_ok() {
+            blog.ap_url = ap_url;
+            return blog.save_changes::<Blog>(conn).map_err(Error::from);
+        }
+    }
+    let blog = Blog::insert(
+        conn,
+        NewBlog {
+            actor_id: author.username.clone(),
+            title: author.display_name.clone(),
+            summary: author.summary.clone(),
+            summary_html: author.summary_html.clone(),
+            outbox_url: author.outbox_url.clone(),
+            inbox_url: author.inbox_url.clone(),
+            instance_id: author.instance_id,
+            ap_url,
+            public_key: author.public_key.clone(),
+            icon_id: author.avatar_id,
+            ..NewBlog::default()
+        },
+    )?;
+    BlogAuthor::insert(
+        conn,
+        NewBlogAuthor {
+            blog_id: blog.id,
+            author_id: author.id,
+            role: BlogRole::Owner as i32,
+        },
+    )?;
+    Ok(blog)
+}
+
+fn gallery_id(author: &User) -> String {
+    format!("{}#gallery", author.ap_url)
+}
+
+/// All the images of a post, with their alternative texts as captions.
+pub fn gallery_html(images: &[GalleryImage], content_warning: Option<&str>) -> String {
+    let figures = images
+        .iter()
+        .map(|image| {
+            let img = format!(
+                r#"<img src="{url}" alt="{alt}" title="{alt}">"#,
+                url = escape(&image.url),
+                alt = escape(&image.alt_text)
+            );
+            let img = match content_warning {
+                Some(cw) => format!(
+                    r#"<label for="postcontent-cw-{id}"><input type="checkbox" id="postcontent-cw-{id}" checked="checked" class="cw-checkbox"><span class="cw-container"><span class="cw-text">{cw}</span>{img}</span></label>"#,
+                    id = random_hex(),
+                    cw = escape(cw),
+                    img = img
+                ),
+                None => img,
+            };
+            if image.alt_text.is_empty() {
+                format!("<figure>{}</figure>", img)
+            } else {
+                format!(
+                    "<figure>{}<figcaption>{}</figcaption></figure>",
+                    img,
+                    escape(&image.alt_text)
+                )
+            }
+        })
+        .collect::<String>();
+    format!(r#"<div class="gallery">{}</div>"#, figures)
+}
+
+/// The first line of the caption of a post, to be used as its title.
+fn title(caption: &str) -> Option<String> {
+    let text = ammonia::Builder::empty()
+        .clean(&caption.replace("<br", "\n<br").replace("</p>", "</p>\n"))
+        .to_string()
+        .replace("&amp;", "&")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'");
+    let line = text.lines().map(str::trim).find(|l| !l.is_empty())?;
+    if line.chars().count() > MAX_TITLE_LENGTH {
+        Some(format!(
+            "{}…",
+            line.chars()
+                .take(MAX_TITLE_LENGTH)
+                .collect::<String>()
+                .trim_end()
+        ))
+    } else {
+        Some(line.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{inbox::tests::fill_database, tests::db};
+    use diesel::Connection;
+
+    #[test]
+    fn pixelfed_images() {
+        let note = json!({
+            "type": "Note",
+            "attachment": [
+                {
+                    "type": "Image",
+                    "mediaType": "image/jpeg",
+                    "url": "https://pixelfed.example/storage/1.jpg",
+                    "name": "A cat"
+                },
+                {
+                    "type": "Document",
+                    "mediaType": "image/png",
+                    "url": [{ "type": "Link", "href": "https://pixelfed.example/storage/2.png" }]
+                },
+                {
+                    "type": "Document",
+                    "mediaType": "video/mp4",
+                    "url": "https://pixelfed.example/storage/3.mp4"
+                }
+            ]
+        });
+        assert_eq!(
+            images(&note),
+            vec![
+                GalleryImage {
+                    url: "https://pixelfed.example/storage/1.jpg".to_owned(),
+                    alt_text: "A cat".to_owned(),
+                },
+                GalleryImage {
+                    url: "https://pixelfed.example/storage/2.png".to_owned(),
+                    alt_text: String::new(),
+                }
+            ]
+        );
+        assert_eq!(
+            title("<p>My cats &amp; me<br>at home</p>"),
+            Some("My cats & me".to_owned())
+        );
+        assert_eq!(title("<p></p>"), None);
+    }
+
+    #[test]
+    fn save_image_post() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (_, users, _) = fill_database(conn);
+            let id = format!("{}p/42", users[1].ap_url);
+            let act = json!({
+                "type": "Create",
+                "actor": users[1].ap_url,
+                "object": {
+                    "type": "Note",
+                    "id": id,
+                    "attributedTo": users[1].ap_url,
+                    "content": "<p>Holidays <a href=\"https://plu.me/tag/sea\">#sea</a></p>",
+                    "attachment": [
+                        { "type": "Image", "url": "https://plu.me/1.jpg", "name": "The sea" },
+                        { "type": "Image", "url": "https://plu.me/2.jpg", "name": "The beach" }
+                    ],
+                    "tag": [{ "type": "Hashtag", "name": "#sea" }]
+                }
+            });
+            assert!(is_image_post(&act));
+
+            let post = match handle_create(conn, &act)? {
+                InboxResult::Post(post) => post,
+                _ => panic!("Unexpected result"),
+            };
+            assert_eq!(post.title, "Holidays #sea");
+            assert_eq!(post.slug, "42");
+            assert!(post.content.contains("The beach"));
+            assert_eq!(
+                Media::get(conn, post.cover_id.unwrap())?.alt_text,
+                "The sea"
+            );
+            assert_eq!(Tag::for_post(conn, post.id)?[0].tag, "sea");
+            let blog = post.get_blog(conn)?;
+            assert_eq!(blog.ap_url, format!("{}#gallery", users[1].ap_url));
+            assert!(users[1].is_author_in(conn, &blog)?);
+            assert_eq!(gallery_blog(conn, &users[1])?.id, blog.id);
+
+            // Notes for followers stay comments
+            let mut private = act.clone();
+            private["object"]["to"] = json!([users[1].followers_endpoint]);
+            assert!(!is_image_post(&private));
+
+            // Only the author can publish it
+            let mut act = act;
+            act["actor"] = json!(users[0].ap_url);
+            assert!(handle_create(conn, &act).is_err());
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,297 @@
+//! Interoperability with groups, as described in FEP-1b12.
+//!
+//! Groups like Lemmy communities don't publish anything themselves: they relay the
+//! activities of their members, wrapped in an `Announce`. The submissions they relay
+//! are saved as articles of the blog representing the group.
+
+use crate::{
+    blogs::Blog,
+    inbox::InboxResult,
+    instance::Instance,
+    posts::{Post, PostUpdate},
+    schema::blogs,
+    users::User,
+    Connection, Error, Result, CONFIG,
+};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use plume_common::{
+    activity_pub::{
+        inbox::{AsObject, FromId},
+        request, CustomGroup, LicensedArticle,
+    },
+    utils::escape,
+};
+use serde_json::Value;
+
+/// The kinds of objects groups relay that can be read as articles.
+const ARTICLE_TYPES: &[&str] = &["Article", "Page"];
+
+/// Tells if `act` is an activity relayed by a group, rather than a reshare.
+///
+/// Reshares only contain the URL of the reshared article, while groups embed the
+/// whole activity they relay.
+pub fn is_group_announce(act: &Value) -> bool {
+    act["type"].as_str() == Some("Announce")
+        && ["Create", "Update"].contains(&act["object"]["type"].as_str().unwrap_or_default())
+}
+
+/// Saves the submission relayed by a group.
+pub fn handle_announce(conn: &Connection, act: &Value) -> Result<InboxResult> {
+    let group_url = act["actor"]
+        .as_str()
+        .or_else(|| act["actor"]["id"].as_str())
+        .ok_or(Error::MissingApProperty)?;
+    let group = fetch_group(conn, group_url)?;
+    let relayed = &act["object"];
+    let member = relayed["actor"]
+        .as_str()
+        .or_else(|| relayed["actor"]["id"].as_str())
+        .ok_or(Error::MissingApProperty)?;
+
+    // The group signed the announce, but not necessarily the activity it relays:
+    // the object is only trusted if it comes from the same server.
+    let object = match &relayed["object"] {
+        Value::String(id) => fetch(id)?,
+        object if same_origin(object["id"].as_str(), group_url) => object.clone(),
+        object => fetch(object["id"].as_str().ok_or(Error::MissingApProperty)?)?,
+    };
+    if !ARTICLE_TYPES.contains(&object["type"].as_str().unwrap_or_default()) {
+        return Ok(InboxResult::Other);
+    }
+    let id = object["id"].as_str().ok_or(Error::MissingApProperty)?;
+
+    // Groups don't list their members: the least is that the member who submitted
+    // the article wrote it, on their own server
+    if !is_attributed_to(&object, member) || !same_origin(Some(id), member) {
+        return Err(Error::Unauthorized);
+    }
+
+    let existing = Post::find_by_ap_url(conn, id).ok();
+    let article: LicensedArticle = serde_json::from_value(page_to_article(object, &group.ap_url))?;
+    match (relayed["type"].as_str(), existing) {
+        (Some("Update"), Some(_)) => {
+            let author = User::from_id(conn, member, None, CONFIG.proxy()).map_err(|(_, e)| e)?;
+            PostUpdate::from_activity(conn, article)?.activity(
+                conn,
+                author,
+                act["id"].as_str().unwrap_or_default(),
+            )?;
+            Ok(InboxResult::Other)
+        }
+        // The same article can be relayed by many groups, or sent directly
+        (_, Some(post)) => Ok(InboxResult::Post(post)),
+        (_, None) => Post::from_activity(conn, article).map(InboxResult::Post),
+    }
+}
+
+/// Splits the instances that a new article is delivered to between the ones that get its
+/// `Create`, and the ones that get the `Announce` of its blog.
+///
+/// The latter are the instances on which groups are known, and they would process the same
+/// article twice if they got both.
+pub fn split_destinations(conn: &Connection, dest: Vec<User>) -> Result<(Vec<User>, Vec<User>)> {
+    let with_groups = blogs::table
+        .filter(blogs::instance_id.ne(Instance::get_local()?.id))
+        .select(blogs::instance_id)
+        .distinct()
+        .load::<i32>(conn)?;
+    Ok(dest
+        .into_iter()
+        .partition(|user| !with_groups.contains(&user.instance_id)))
+}
+
+/// Finds a group, fetching it if it is not known yet.
+///
+/// Lemmy communities don't always have a `source`, that Plume blogs require.
+pub fn fetch_group(conn: &Connection, url: &str) -> Result<Blog> {
+    if let Ok(blog) = Blog::from_db(conn, url) {
+        return Ok(blog);
+    }
+    let mut group = fetch(url)?;
+    if group["source"].is_null() {
+        group["source"] = json!({
+            "content": group["summary"].as_str().unwrap_or_default(),
+            "mediaType": "text/html",
+        });
+    }
+    let group: CustomGroup = serde_json::from_value(group)?;
+    Blog::from_activity(conn, group)
+}
+
+/// Turns a submission to a group into an article of the blog representing this group.
+///
+/// The URL of link submissions is added at the beginning of their content, as
+/// articles use `url` for their own address.
+pub fn page_to_article(mut page: Value, group: &str) -> Value {
+    let id = page["id"].as_str().unwrap_or_default().to_owned();
+    let link = match &page["url"] {
+        Value::String(url) => Some(url.to_owned()),
+        url => url["href"].as_str().map(str::to_owned),
+    }
+    .filter(|url| url != &id);
+
+    let mut attributed_to = match page["attributedTo"].take() {
+        Value::Array(actors) => actors,
+        Value::Null => vec![],
+        actor => vec![actor],
+    };
+    if !attributed_to.iter().any(|a| a.as_str() == Some(group)) {
+        attributed_to.push(json!(group));
+    }
+
+    let mut content = page["content"].as_str().unwrap_or_default().to_owned();
+    let mut source = page["source"]["content"]
+        .as_str()
+        .unwrap_or_default()
+        .to_owned();
+    if let Some(ref link) = link {
+        content = format!("<p><a href=\"{0}\">{0}</a></p>{1}", escape(link), content);
+        source = format!("<{}>\n\n{}", link, source);
+    }
+
+    page["type"] = json!("Article");
+    page["url"] = json!(id);
+    page["attributedTo"] = json!(attributed_to);
+    page["content"] = json!(content);
+    page["source"] = json!({
+        "content": source,
+        "mediaType": "text/markdown",
+    });
+    if page["summary"].is_null() {
+        page["summary"] = json!("");
+    }
+    page
+}
+
+fn is_attributed_to(object: &Value, actor: &str) -> bool {
+    match &object["attributedTo"] {
+        Value::Array(actors) => actors
+            .iter()
+            .any(|a| a.as_str().or_else(|| a["id"].as_str()) == Some(actor)),
+        a => a.as_str().or_else(|| a["id"].as_str()) == Some(actor),
+    }
+}
+
+fn fetch(url: &str) -> Result<Value> {
+    request::get(
+        url,
+        Instance::get_local_instance_user().ok_or(Error::NotFound)?,
+        CONFIG.proxy().cloned(),
+    )?
+    .json()
+    .map_err(Error::from)
+}
+
+fn same_origin(url: Option<&str>, other: &str) -> bool {
+    match (
+        url.and_then(|u| url::Url::parse(u).ok()),
+        url::Url::parse(other),
+    ) {
+        (Some(url), Ok(other)) => url.origin() == other.origin(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{inbox::tests::fill_database, tests::db};
+    use diesel::Connection;
+
+    #[test]
+    fn link_page_to_article() {
+        let article = page_to_article(
+            json!({
+                "type": "Page",
+                "id": "https://lemmy.example/post/1",
+                "attributedTo": "https://lemmy.example/u/alice",
+                "name": "A link",
+                "url": "https://example.com/?a=1&b=2",
+                "content": "<p>Look</p>",
+                "source": { "content": "Look", "mediaType": "text/markdown" }
+            }),
+            "https://lemmy.example/c/plume",
+        );
+        assert_eq!(article["type"], "Article");
+        assert_eq!(article["url"], "https://lemmy.example/post/1");
+        assert_eq!(
+            article["attributedTo"],
+            json!([
+                "https://lemmy.example/u/alice",
+                "https://lemmy.example/c/plume"
+            ])
+        );
+        assert_eq!(
+            article["content"],
+            "<p><a href=\"https://example.com/?a=1&amp;b=2\">https://example.com/?a=1&amp;b=2</a></p><p>Look</p>"
+        );
+        assert_eq!(
+            article["source"]["content"],
+            "<https://example.com/?a=1&b=2>\n\nLook"
+        );
+        assert_eq!(article["summary"], "");
+        assert!(serde_json::from_value::<LicensedArticle>(article).is_ok());
+    }
+
+    #[test]
+    fn announced_page() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (_, users, blogs) = fill_database(conn);
+            // A reshare is not a relayed activity
+            assert!(!is_group_announce(&json!({
+                "type": "Announce",
+                "actor": users[0].ap_url,
+                "object": "https://plu.me/~/Blog/article"
+            })));
+
+            let page_id = format!("{}lemmy-page", blogs[0].ap_url);
+            let act = json!({
+                "type": "Announce",
+                "actor": blogs[0].ap_url,
+                "object": {
+                    "type": "Create",
+                    "actor": users[1].ap_url,
+                    "object": {
+                        "type": "Page",
+                        "id": page_id,
+                        "attributedTo": users[1].ap_url,
+                        "name": "Submitted to the group",
+                        "content": "<p>Hello group</p>"
+                    }
+                }
+            });
+            assert!(is_group_announce(&act));
+            match handle_announce(conn, &act)? {
+                InboxResult::Post(post) => {
+                    assert_eq!(post.ap_url, page_id);
+                    assert_eq!(post.blog_id, blogs[0].id);
+                    assert_eq!(post.title, "Submitted to the group");
+                    assert!(post.get_authors(conn)?.iter().any(|a| a.id == users[1].id));
+                }
+                _ => panic!("Unexpected result"),
+            }
+
+            // Relayed again, by the same group or another one
+            let count = Post::count(conn)?;
+            match handle_announce(conn, &act)? {
+                InboxResult::Post(post) => assert_eq!(post.ap_url, page_id),
+                _ => panic!("Unexpected result"),
+            }
+            assert_eq!(Post::count(conn)?, count);
+
+            let mut update = act.clone();
+            update["object"]["type"] = json!("Update");
+            update["object"]["object"]["name"] = json!("Edited");
+            handle_announce(conn, &update)?;
+            assert_eq!(Post::find_by_ap_url(conn, &page_id)?.title, "Edited");
+
+            // Only the author can submit their article
+            let mut stolen = act.clone();
+            stolen["object"]["actor"] = json!(users[0].ap_url);
+            stolen["object"]["object"]["id"] = json!(format!("{}stolen", blogs[0].ap_url));
+            assert!(handle_announce(conn, &stolen).is_err());
+            Ok(())
+        });
+    }
+}
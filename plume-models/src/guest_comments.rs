@@ -0,0 +1,180 @@
+use crate::{
+    blogs::Blog,
+    post_views::Visitor,
+    posts::Post,
+    safe_string::SafeString,
+    schema::{guest_comments, posts},
+    Connection, Error, Result,
+};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+use plume_common::utils;
+
+/// How many comments a guest can post in an hour.
+pub const GUEST_COMMENTS_PER_HOUR: i64 = 3;
+
+/// A comment left by a visitor who isn't logged in.
+///
+/// Guest comments are only displayed once approved by an author of the blog,
+/// and are never federated.
+#[derive(Clone, Queryable, Identifiable)]
+pub struct GuestComment {
+    pub id: i32,
+    pub post_id: i32,
+    pub author_name: String,
+    pub author_email: Option<String>,
+    pub content: SafeString,
+    pub creation_date: NaiveDateTime,
+    pub approved: bool,
+    /// Anonymous identifier of the visitor, used for rate-limiting
+    pub visitor: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "guest_comments"]
+pub struct NewGuestComment {
+    pub post_id: i32,
+    pub author_name: String,
+    pub author_email: Option<String>,
+    pub content: SafeString,
+    pub visitor: String,
+}
+
+impl GuestComment {
+    insert!(guest_comments, NewGuestComment);
+    get!(guest_comments);
+
+    /// Saves a comment from a guest, to be reviewed by the authors of the blog.
+    pub fn create(
+        conn: &Connection,
+        post: &Post,
+        author_name: &str,
+        author_email: Option<&str>,
+        source: &str,
+        visitor: &Visitor,
+    ) -> Result<Self> {
+        if !post.published || !post.get_blog(conn)?.allow_guest_comments {
+            return Err(Error::Unauthorized);
+        }
+        let (html, _mentions, _hashtags) = utils::md_to_html(source, None, true, None);
+        Self::insert(
+            conn,
+            NewGuestComment {
+                post_id: post.id,
+                author_name: author_name.trim().to_owned(),
+                author_email: author_email
+                    .map(str::trim)
+                    .filter(|e| !e.is_empty())
+                    .map(str::to_owned),
+                content: SafeString::new(&html),
+                visitor: visitor.anonymous_id(),
+            },
+        )
+    }
+
+    /// Whether this visitor posted too many comments recently.
+    pub fn is_rate_limited(conn: &Connection, visitor: &Visitor) -> Result<bool> {
+        let since = Utc::now().naive_utc() - Duration::hours(1);
+        let count = guest_comments::table
+            .filter(guest_comments::visitor.eq(visitor.anonymous_id()))
+            .filter(guest_comments::creation_date.gt(since))
+            .count()
+            .get_result::<i64>(conn)?;
+        Ok(count >= GUEST_COMMENTS_PER_HOUR)
+    }
+
+    pub fn list_approved_for_post(conn: &Connection, post_id: i32) -> Result<Vec<Self>> {
+        guest_comments::table
+            .filter(guest_comments::post_id.eq(post_id))
+            .filter(guest_comments::approved.eq(true))
+            .order(guest_comments::creation_date.asc())
+            .load::<Self>(conn)
+            .map_err(Error::from)
+    }
+
+    /// The moderation queue of a blog.
+    pub fn list_pending_for_blog(conn: &Connection, blog: &Blog) -> Result<Vec<Self>> {
+        guest_comments::table
+            .filter(
+                guest_comments::post_id.eq_any(
+                    posts::table
+                        .filter(posts::blog_id.eq(blog.id))
+                        .select(posts::id),
+                ),
+            )
+            .filter(guest_comments::approved.eq(false))
+            .order(guest_comments::creation_date.asc())
+            .load::<Self>(conn)
+            .map_err(Error::from)
+    }
+
+    pub fn get_post(&self, conn: &Connection) -> Result<Post> {
+        Post::get(conn, self.post_id)
+    }
+
+    pub fn approve(&self, conn: &Connection) -> Result<()> {
+        diesel::update(self)
+            .set(guest_comments::approved.eq(true))
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+
+    pub fn delete(&self, conn: &Connection) -> Result<()> {
+        diesel::delete(self)
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{inbox::tests::fill_database, tests::db};
+    use diesel::{Connection, SaveChangesDsl};
+
+    #[test]
+    fn moderation_and_rate_limit() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, _, blogs) = fill_database(conn);
+            let visitor = Visitor {
+                ip: Some("127.0.0.1".to_owned()),
+                user_agent: "Plume tests".to_owned(),
+            };
+            assert!(GuestComment::create(conn, &posts[0], "Guest", None, "Hi", &visitor).is_err());
+
+            let mut blog = blogs[0].clone();
+            blog.allow_guest_comments = true;
+            let _: Blog = blog.save_changes(conn)?;
+
+            let comment =
+                GuestComment::create(conn, &posts[0], " Guest ", Some(""), "*Hi*", &visitor)?;
+            assert_eq!(comment.author_name, "Guest");
+            assert_eq!(comment.author_email, None);
+            assert!(GuestComment::list_approved_for_post(conn, posts[0].id)?.is_empty());
+            assert_eq!(
+                GuestComment::list_pending_for_blog(conn, &blog)?
+                    .iter()
+                    .map(|c| c.id)
+                    .collect::<Vec<_>>(),
+                vec![comment.id]
+            );
+
+            comment.approve(conn)?;
+            assert_eq!(
+                GuestComment::list_approved_for_post(conn, posts[0].id)?.len(),
+                1
+            );
+            assert!(GuestComment::list_pending_for_blog(conn, &blog)?.is_empty());
+
+            assert!(!GuestComment::is_rate_limited(conn, &visitor)?);
+            for _ in 1..GUEST_COMMENTS_PER_HOUR {
+                GuestComment::create(conn, &posts[0], "Guest", None, "Hi", &visitor)?;
+            }
+            assert!(GuestComment::is_rate_limited(conn, &visitor)?);
+            Ok(())
+        });
+    }
+}
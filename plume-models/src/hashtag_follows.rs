@@ -0,0 +1,152 @@
+//! Hashtags followed by users.
+//!
+//! Articles with a followed tag are added to the "My feed" timeline of the follower,
+//! like the ones of the authors they follow, wherever they come from.
+
+use crate::{
+    posts::Post,
+    schema::{hashtag_follows, tags},
+    tag_aliases::TagAlias,
+    users::User,
+    Connection, Error, Result,
+};
+use chrono::NaiveDateTime;
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+
+#[derive(Clone, Queryable, Identifiable)]
+pub struct HashtagFollow {
+    pub id: i32,
+    pub user_id: i32,
+    pub tag: String,
+    pub creation_date: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "hashtag_follows"]
+pub struct NewHashtagFollow {
+    pub user_id: i32,
+    pub tag: String,
+}
+
+impl HashtagFollow {
+    insert!(hashtag_follows, NewHashtagFollow);
+    get!(hashtag_follows);
+
+    pub fn list_for_user(conn: &Connection, user_id: i32) -> Result<Vec<Self>> {
+        hashtag_follows::table
+            .filter(hashtag_follows::user_id.eq(user_id))
+            .order(hashtag_follows::tag.asc())
+            .load::<Self>(conn)
+            .map_err(Error::from)
+    }
+
+    pub fn find(conn: &Connection, user_id: i32, tag: &str) -> Result<Self> {
+        hashtag_follows::table
+            .filter(hashtag_follows::user_id.eq(user_id))
+            .filter(hashtag_follows::tag.eq(Self::normalize(conn, tag)))
+            .get_result(conn)
+            .map_err(Error::from)
+    }
+
+    pub fn follow(conn: &Connection, user: &User, tag: &str) -> Result<Self> {
+        let tag = Self::normalize(conn, tag);
+        if tag.is_empty() {
+            return Err(Error::InvalidValue);
+        }
+        Self::find(conn, user.id, &tag).or_else(|_| {
+            Self::insert(
+                conn,
+                NewHashtagFollow {
+                    user_id: user.id,
+                    tag,
+                },
+            )
+        })
+    }
+
+    pub fn is_following(conn: &Connection, user_id: i32, tag: &str) -> Result<bool> {
+        diesel::dsl::select(diesel::dsl::exists(
+            hashtag_follows::table
+                .filter(hashtag_follows::user_id.eq(user_id))
+                .filter(hashtag_follows::tag.eq(Self::normalize(conn, tag))),
+        ))
+        .get_result(conn)
+        .map_err(Error::from)
+    }
+
+    /// Tells if `post` has one of the tags followed by a user.
+    pub fn follows_tag_of(conn: &Connection, user_id: i32, post: &Post) -> Result<bool> {
+        let followed = hashtag_follows::table
+            .filter(hashtag_follows::user_id.eq(user_id))
+            .select(hashtag_follows::tag);
+        diesel::dsl::select(diesel::dsl::exists(
+            tags::table
+                .filter(tags::post_id.eq(post.id))
+                .filter(tags::tag.eq_any(followed)),
+        ))
+        .get_result(conn)
+        .map_err(Error::from)
+    }
+
+    pub fn delete(&self, conn: &Connection) -> Result<()> {
+        diesel::delete(self)
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+
+    /// Tags are saved with their canonical name, so aliases are followed as this name.
+    fn normalize(conn: &Connection, tag: &str) -> String {
+        TagAlias::canonical(conn, tag.trim().trim_start_matches('#'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        inbox::tests::fill_database,
+        tags::{NewTag, Tag},
+        tests::db,
+        timeline::{Kind, Timeline},
+    };
+    use diesel::Connection;
+
+    #[test]
+    fn follow_hashtag() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, users, _) = fill_database(conn);
+            let timeline = Timeline::new_for_user(
+                conn,
+                users[2].id,
+                "My feed".to_owned(),
+                "followed".to_owned(),
+            )?;
+            Tag::insert(
+                conn,
+                NewTag {
+                    tag: "Plume".to_owned(),
+                    is_hashtag: true,
+                    post_id: posts[0].id,
+                },
+            )?;
+            assert!(!timeline.matches(conn, &posts[0], Kind::Original)?);
+
+            let follow = HashtagFollow::follow(conn, &users[2], "#Plume")?;
+            assert_eq!(follow.tag, "Plume");
+            assert_eq!(
+                HashtagFollow::follow(conn, &users[2], "Plume")?.id,
+                follow.id
+            );
+            assert!(HashtagFollow::is_following(conn, users[2].id, "Plume")?);
+            assert!(!HashtagFollow::is_following(conn, users[1].id, "Plume")?);
+            assert!(timeline.matches(conn, &posts[0], Kind::Original)?);
+
+            follow.delete(conn)?;
+            assert!(HashtagFollow::list_for_user(conn, users[2].id)?.is_empty());
+            assert!(!timeline.matches(conn, &posts[0], Kind::Original)?);
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,315 @@
+//! Removing the metadata of uploaded images.
+//!
+//! Photos often tell where they were taken, and with which device. JPEG, PNG and WebP
+//! images are decoded and encoded again before being stored, which only keeps their
+//! pixels, and the largest ones are made smaller on the way (see `MEDIA_MAX_DIMENSION`).
+//! The EXIF orientation of JPEG photos is applied first, as it is removed too.
+//!
+//! SVG images are sanitized instead (see `svg`). Animated PNG and WebP images are kept as
+//! they are, as only their first frame would be left otherwise.
+
+use crate::{svg, Result, CONFIG};
+use image::{
+    codecs::webp::{WebPEncoder, WebPQuality},
+    imageops::FilterType,
+    ColorType, DynamicImage, ImageFormat, ImageOutputFormat,
+};
+use std::{borrow::Cow, io::Cursor};
+
+const QUALITY: u8 = 90;
+
+/// The image in `bytes` without its metadata, or `bytes` if it isn't an image that can
+/// be cleaned (GIFs, animated images and other files).
+pub fn clean<'a>(bytes: &'a [u8], ext: &str) -> Result<Cow<'a, [u8]>> {
+    clean_with_limit(bytes, ext, CONFIG.media_max_dimension)
+}
+
+fn clean_with_limit<'a>(bytes: &'a [u8], ext: &str, max_dimension: u32) -> Result<Cow<'a, [u8]>> {
+    let format = match &*ext.to_lowercase() {
+        "jpg" | "jpeg" => ImageFormat::Jpeg,
+        "png" => ImageFormat::Png,
+        "webp" => ImageFormat::WebP,
+        "svg" => return svg::sanitize(bytes).map(Cow::Owned),
+        _ => return Ok(Cow::Borrowed(bytes)),
+    };
+    if is_animated(bytes, ext) {
+        return Ok(Cow::Borrowed(bytes));
+    }
+    let mut image = image::load_from_memory_with_format(bytes, format)?;
+    if format == ImageFormat::Jpeg {
+        image = apply_orientation(image, jpeg_orientation(bytes).unwrap_or(1));
+    }
+    if max_dimension > 0 && (image.width() > max_dimension || image.height() > max_dimension) {
+        image = image.resize(max_dimension, max_dimension, FilterType::Lanczos3);
+    }
+
+    let mut cleaned = Vec::new();
+    match format {
+        ImageFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8()).write_to(
+            &mut Cursor::new(&mut cleaned),
+            ImageOutputFormat::Jpeg(QUALITY),
+        )?,
+        ImageFormat::WebP => {
+            let rgba = image.to_rgba8();
+            WebPEncoder::new_with_quality(&mut cleaned, WebPQuality::lossy(QUALITY)).encode(
+                &rgba,
+                rgba.width(),
+                rgba.height(),
+                ColorType::Rgba8,
+            )?;
+        }
+        _ => image.write_to(&mut Cursor::new(&mut cleaned), ImageOutputFormat::Png)?,
+    }
+    Ok(Cow::Owned(cleaned))
+}
+
+/// Whether an image has more than one frame.
+pub fn is_animated(bytes: &[u8], ext: &str) -> bool {
+    match &*ext.to_lowercase() {
+        "gif" => gif_frames(bytes) > 1,
+        "png" | "apng" => png_is_animated(bytes),
+        "webp" => webp_is_animated(bytes),
+        _ => false,
+    }
+}
+
+/// APNG files have an `acTL` chunk before their image data.
+fn png_is_animated(bytes: &[u8]) -> bool {
+    let mut pos = 8;
+    while let Some(header) = bytes.get(pos..pos + 8) {
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        match &header[4..8] {
+            b"acTL" => return true,
+            b"IDAT" | b"IEND" => return false,
+            _ => {}
+        }
+        // The chunk is followed by its CRC
+        pos += 12 + len;
+    }
+    false
+}
+
+/// Animated WebP files start with a `VP8X` chunk, with the animation flag.
+fn webp_is_animated(bytes: &[u8]) -> bool {
+    bytes.get(0..4) == Some(b"RIFF")
+        && bytes.get(8..16) == Some(b"WEBPVP8X")
+        && bytes.get(20).map_or(false, |flags| flags & 0x02 != 0)
+}
+
+/// Counts the image descriptors of a GIF file.
+fn gif_frames(bytes: &[u8]) -> usize {
+    let flags = match bytes.get(10) {
+        Some(flags) if bytes.starts_with(b"GIF") => *flags,
+        _ => return 0,
+    };
+    let color_table = |flags: u8| {
+        if flags & 0x80 != 0 {
+            3 << ((flags & 0x07) + 1)
+        } else {
+            0
+        }
+    };
+    // Skips a list of sub-blocks, ending with an empty one
+    let skip_blocks = |mut pos: usize| {
+        while let Some(&len) = bytes.get(pos) {
+            pos += 1 + len as usize;
+            if len == 0 {
+                return Some(pos);
+            }
+        }
+        None
+    };
+
+    let mut frames = 0;
+    let mut pos = 13 + color_table(flags);
+    loop {
+        pos = match bytes.get(pos) {
+            // An image
+            Some(0x2C) => {
+                frames += 1;
+                let flags = match bytes.get(pos + 9) {
+                    Some(flags) => *flags,
+                    None => return frames,
+                };
+                // The LZW code size comes before the data
+                match skip_blocks(pos + 10 + color_table(flags) + 1) {
+                    Some(pos) => pos,
+                    None => return frames,
+                }
+            }
+            // An extension
+            Some(0x21) => match skip_blocks(pos + 2) {
+                Some(pos) => pos,
+                None => return frames,
+            },
+            _ => return frames,
+        };
+    }
+}
+
+/// Turns an image the way its EXIF orientation tag says.
+fn apply_orientation(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+/// Finds the orientation tag in the EXIF segment of a JPEG file.
+fn jpeg_orientation(bytes: &[u8]) -> Option<u16> {
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut pos = 2;
+    while bytes.get(pos) == Some(&0xFF) {
+        let marker = *bytes.get(pos + 1)?;
+        // The image data starts: there is no more metadata
+        if marker == 0xDA {
+            return None;
+        }
+        let len = u16::from_be_bytes([*bytes.get(pos + 2)?, *bytes.get(pos + 3)?]) as usize;
+        let segment = bytes.get(pos + 4..pos + 2 + len)?;
+        if marker == 0xE1 && segment.starts_with(b"Exif\0\0") {
+            return tiff_orientation(&segment[6..]);
+        }
+        pos += 2 + len;
+    }
+    None
+}
+
+fn tiff_orientation(tiff: &[u8]) -> Option<u16> {
+    let big_endian = match tiff.get(0..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let read_u16 = |pos: usize| {
+        let b = tiff.get(pos..pos + 2)?;
+        Some(if big_endian {
+            u16::from_be_bytes([b[0], b[1]])
+        } else {
+            u16::from_le_bytes([b[0], b[1]])
+        })
+    };
+    let read_u32 = |pos: usize| {
+        let b = tiff.get(pos..pos + 4)?;
+        Some(if big_endian {
+            u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+        } else {
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        })
+    };
+
+    let ifd = read_u32(4)? as usize;
+    for i in 0..read_u16(ifd)? as usize {
+        let entry = ifd + 2 + i * 12;
+        if read_u16(entry)? == 0x0112 {
+            return read_u16(entry + 8);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, ImageBuffer, Rgb};
+
+    /// A JPEG file of `width`×`height` pixels, with an EXIF segment.
+    fn photo(width: u32, height: u32, orientation: u16) -> Vec<u8> {
+        let image =
+            DynamicImage::ImageRgb8(ImageBuffer::from_pixel(width, height, Rgb([0, 90, 200])));
+        let mut jpeg = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut jpeg), ImageOutputFormat::Jpeg(90))
+            .unwrap();
+
+        let mut tiff = b"MM\0\x2A\0\0\0\x08\0\x02".to_vec();
+        // Orientation
+        tiff.extend_from_slice(&[0x01, 0x12, 0, 3, 0, 0, 0, 1]);
+        tiff.extend_from_slice(&orientation.to_be_bytes());
+        tiff.extend_from_slice(&[0, 0]);
+        // GPS info
+        tiff.extend_from_slice(&[0x88, 0x25, 0, 4, 0, 0, 0, 1, 0, 0, 0, 0]);
+        tiff.extend_from_slice(&[0, 0, 0, 0]);
+        let mut segment = b"Exif\0\0".to_vec();
+        segment.extend(tiff);
+
+        let mut photo = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        photo.extend_from_slice(&(segment.len() as u16 + 2).to_be_bytes());
+        photo.extend(segment);
+        photo.extend_from_slice(&jpeg[2..]);
+        photo
+    }
+
+    #[test]
+    fn orientation() {
+        assert_eq!(jpeg_orientation(&photo(4, 2, 6)), Some(6));
+        assert_eq!(jpeg_orientation(&photo(4, 2, 1)), Some(1));
+        assert_eq!(jpeg_orientation(b"not a jpeg"), None);
+    }
+
+    #[test]
+    fn strip_metadata() {
+        let original = photo(40, 20, 6);
+        let cleaned = clean_with_limit(&original, "JPG", 0).unwrap();
+        assert!(!cleaned.windows(4).any(|w| w == b"Exif"));
+        assert_eq!(jpeg_orientation(&cleaned), None);
+        let image = image::load_from_memory(&cleaned).unwrap();
+        assert_eq!(image.dimensions(), (20, 40));
+
+        let resized = clean_with_limit(&original, "jpeg", 10).unwrap();
+        let image = image::load_from_memory(&resized).unwrap();
+        assert_eq!(image.dimensions(), (5, 10));
+
+        let gif = b"GIF89a";
+        assert!(matches!(
+            clean_with_limit(gif, "gif", 10).unwrap(),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn animations() {
+        let png = |chunks: &[&[u8; 4]]| {
+            let mut png = b"\x89PNG\r\n\x1A\n".to_vec();
+            for chunk in chunks {
+                png.extend_from_slice(&[0, 0, 0, 1]);
+                png.extend_from_slice(*chunk);
+                png.extend_from_slice(&[0; 5]);
+            }
+            png
+        };
+        assert!(is_animated(&png(&[b"IHDR", b"acTL", b"IDAT"]), "png"));
+        assert!(!is_animated(&png(&[b"IHDR", b"IDAT", b"acTL"]), "png"));
+        assert!(matches!(
+            clean_with_limit(&png(&[b"IHDR", b"acTL", b"IDAT"]), "png", 0).unwrap(),
+            Cow::Borrowed(_)
+        ));
+
+        let webp = |flags: u8| {
+            let mut webp = b"RIFF\0\0\0\0WEBPVP8X\x0A\0\0\0".to_vec();
+            webp.extend_from_slice(&[flags, 0, 0, 0]);
+            webp
+        };
+        assert!(is_animated(&webp(0x12), "WEBP"));
+        assert!(!is_animated(&webp(0x10), "webp"));
+
+        // Two frames of 1×1 pixel, without color tables
+        let mut gif = b"GIF89a\x01\0\x01\0\0\0\0".to_vec();
+        let frame = [0x2C, 0, 0, 0, 0, 1, 0, 1, 0, 0, 2, 2, 0x4C, 0x01, 0];
+        gif.extend_from_slice(&[0x21, 0xF9, 4, 0, 10, 0, 0, 0]);
+        gif.extend_from_slice(&frame);
+        assert!(!is_animated(&gif, "gif"));
+        gif.extend_from_slice(&[0x21, 0xF9, 4, 0, 10, 0, 0, 0]);
+        gif.extend_from_slice(&frame);
+        gif.push(0x3B);
+        assert!(is_animated(&gif, "gif"));
+    }
+}
@@ -0,0 +1,500 @@
+//! Import of the JSON files exported from Ghost (Settings > Labs > Export your content).
+//!
+//! The content of the posts is stored as [Mobiledoc](https://github.com/bustle/mobiledoc-kit/blob/master/MOBILEDOC.md),
+//! which is converted to Markdown. Exports of old versions of Ghost, that only contain
+//! Markdown or HTML, are supported too.
+
+use super::{html_to_markdown, save_post, ImportReport, ImportedPost};
+use crate::{
+    blogs::Blog,
+    post_authors::{NewPostAuthor, PostAuthor},
+    users::User,
+    Connection, Error, Result,
+};
+use chrono::{DateTime, NaiveDateTime};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Imports all the posts of a Ghost export in `blog`.
+///
+/// Ghost authors are matched with the account of `default_author`, who imports the posts,
+/// by their email address. If they manage the blog, the other members that can publish in
+/// it are matched too. Posts whose authors couldn't be found are attributed to
+/// `default_author`.
+pub fn import_export(
+    conn: &Connection,
+    json: &str,
+    blog: &Blog,
+    default_author: &User,
+) -> Result<ImportReport> {
+    let export: Value = serde_json::from_str(json)?;
+    let data = export
+        .pointer("/db/0/data")
+        .or_else(|| export.get("data"))
+        .ok_or(Error::InvalidValue)?;
+
+    let tags = list(data, "tags")
+        .filter_map(|tag| Some((id(&tag["id"])?, tag["name"].as_str()?)))
+        // Internal tags are only used to customize the theme
+        .filter(|(_, name)| !name.starts_with('#'))
+        .collect::<HashMap<_, _>>();
+    let mut post_tags: HashMap<String, Vec<String>> = HashMap::new();
+    for post_tag in list(data, "posts_tags") {
+        if let (Some(post), Some(tag)) = (
+            id(&post_tag["post_id"]),
+            id(&post_tag["tag_id"]).and_then(|tag| tags.get(&tag)),
+        ) {
+            post_tags.entry(post).or_default().push((*tag).to_owned());
+        }
+    }
+
+    // Other members can't be made authors of posts by anyone but the managers of the blog
+    let can_attribute = default_author.can_manage(conn, blog)?;
+    let mut authors = HashMap::new();
+    for user in list(data, "users") {
+        if let (Some(id), Some(email)) = (id(&user["id"]), user["email"].as_str()) {
+            if let Ok(local) = User::find_by_email(conn, email) {
+                if local.id == default_author.id
+                    || (can_attribute && local.can_publish_in(conn, blog)?)
+                {
+                    authors.insert(id, local);
+                }
+            }
+        }
+    }
+    let mut post_authors: HashMap<String, Vec<String>> = HashMap::new();
+    for post_author in list(data, "posts_authors") {
+        if let (Some(post), Some(author)) =
+            (id(&post_author["post_id"]), id(&post_author["author_id"]))
+        {
+            post_authors.entry(post).or_default().push(author);
+        }
+    }
+
+    let mut report = ImportReport::default();
+    for post in list(data, "posts") {
+        let name = post["title"]
+            .as_str()
+            .or_else(|| post["slug"].as_str())
+            .unwrap_or_default()
+            .to_owned();
+        if post["type"].as_str() == Some("page") || post["page"].as_bool() == Some(true) {
+            report
+                .errors
+                .push((name, "Pages can't be imported".to_owned()));
+            continue;
+        }
+        let ghost_id = id(&post["id"]).unwrap_or_default();
+        let mut imported = match parse_post(post) {
+            Some(imported) => imported,
+            None => {
+                report.errors.push((name, "Not a Ghost post".to_owned()));
+                continue;
+            }
+        };
+        imported.tags = post_tags.remove(&ghost_id).unwrap_or_default();
+
+        // Exports of Ghost 0.x and 1.x only have a single author per post
+        let ghost_authors = post_authors
+            .remove(&ghost_id)
+            .or_else(|| id(&post["author_id"]).map(|author| vec![author]))
+            .unwrap_or_default();
+        let mut local_authors = ghost_authors
+            .iter()
+            .filter_map(|author| authors.get(author))
+            .collect::<Vec<_>>();
+        local_authors.dedup_by_key(|author| author.id);
+        let author = local_authors.first().cloned().unwrap_or(default_author);
+
+        match save_post(conn, blog, author, imported).and_then(|post| {
+            for coauthor in local_authors.iter().skip(1) {
+                PostAuthor::insert(
+                    conn,
+                    NewPostAuthor {
+                        post_id: post.id,
+                        author_id: coauthor.id,
+                    },
+                )?;
+            }
+            Ok(post)
+        }) {
+            Ok(post) => report.posts.push(post),
+            Err(e) => report.errors.push((name, format!("{:?}", e))),
+        }
+    }
+    Ok(report)
+}
+
+/// Reads a post of a Ghost export, without its tags.
+pub fn parse_post(post: &Value) -> Option<ImportedPost> {
+    let title = post["title"].as_str()?.trim().to_owned();
+    let source = post["mobiledoc"]
+        .as_str()
+        .and_then(mobiledoc_to_markdown)
+        .or_else(|| post["markdown"].as_str().map(str::to_owned))
+        .or_else(|| post["html"].as_str().map(html_to_markdown))
+        .unwrap_or_default();
+    let creation_date = date(&post["published_at"]).or_else(|| date(&post["created_at"]));
+
+    Some(ImportedPost {
+        title,
+        subtitle: post["custom_excerpt"]
+            .as_str()
+            .unwrap_or_default()
+            .to_owned(),
+        source,
+        creation_date,
+        published: post["status"].as_str() == Some("published"),
+        tags: vec![],
+    })
+}
+
+/// Converts a Mobiledoc document to Markdown.
+pub fn mobiledoc_to_markdown(mobiledoc: &str) -> Option<String> {
+    let doc: Value = serde_json::from_str(mobiledoc).ok()?;
+    let empty = vec![];
+    let markups = doc["markups"].as_array().unwrap_or(&empty);
+    let atoms = doc["atoms"].as_array().unwrap_or(&empty);
+    let cards = doc["cards"].as_array().unwrap_or(&empty);
+
+    let mut blocks = vec![];
+    for section in doc["sections"].as_array()? {
+        let block = match section[0].as_u64() {
+            // Markup section
+            Some(1) => {
+                let text = markers_to_markdown(&section[2], markups, atoms);
+                match section[1].as_str().unwrap_or("p").to_lowercase().as_str() {
+                    "h1" => Some(format!("# {}", text)),
+                    "h2" => Some(format!("## {}", text)),
+                    "h3" => Some(format!("### {}", text)),
+                    "h4" => Some(format!("#### {}", text)),
+                    "h5" => Some(format!("##### {}", text)),
+                    "h6" => Some(format!("###### {}", text)),
+                    "blockquote" | "aside" => Some(quote(&text)),
+                    _ => Some(text),
+                }
+            }
+            // Image section
+            Some(2) => section[1].as_str().map(|src| format!("![]({})", src)),
+            // List section
+            Some(3) => {
+                let ordered = section[1].as_str() == Some("ol");
+                section[2].as_array().map(|items| {
+                    items
+                        .iter()
+                        .enumerate()
+                        .map(|(i, item)| {
+                            let text = markers_to_markdown(item, markups, atoms);
+                            if ordered {
+                                format!("{}. {}", i + 1, text)
+                            } else {
+                                format!("- {}", text)
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                })
+            }
+            // Card section
+            Some(10) => section[1]
+                .as_u64()
+                .and_then(|i| cards.get(i as usize))
+                .and_then(card_to_markdown),
+            _ => None,
+        };
+        if let Some(block) = block.filter(|b| !b.trim().is_empty()) {
+            blocks.push(block);
+        }
+    }
+    Some(blocks.join("\n\n"))
+}
+
+/// Converts the markers of a Mobiledoc section to inline Markdown.
+fn markers_to_markdown(markers: &Value, markups: &[Value], atoms: &[Value]) -> String {
+    let mut text = String::new();
+    let mut open = vec![];
+    for marker in markers.as_array().into_iter().flatten() {
+        for markup in marker[1].as_array().into_iter().flatten() {
+            if let Some(markup) = markup.as_u64().and_then(|i| markups.get(i as usize)) {
+                let (start, end) = markup_delimiters(markup);
+                text.push_str(&start);
+                open.push(end);
+            }
+        }
+        match marker[0].as_u64() {
+            Some(1) => {
+                if let Some(atom) = marker[3].as_u64().and_then(|i| atoms.get(i as usize)) {
+                    match atom[0].as_str() {
+                        Some("soft-return") => text.push_str("  \n"),
+                        _ => text.push_str(atom[1].as_str().unwrap_or_default()),
+                    }
+                }
+            }
+            _ => text.push_str(marker[3].as_str().unwrap_or_default()),
+        }
+        for _ in 0..marker[2].as_u64().unwrap_or(0) {
+            if let Some(end) = open.pop() {
+                text.push_str(&end);
+            }
+        }
+    }
+    // Markups should all be closed, but don't break the rest of the document if they aren't
+    while let Some(end) = open.pop() {
+        text.push_str(&end);
+    }
+    text
+}
+
+/// The Markdown to put around a text having this Mobiledoc markup.
+fn markup_delimiters(markup: &Value) -> (String, String) {
+    match markup[0]
+        .as_str()
+        .unwrap_or_default()
+        .to_lowercase()
+        .as_str()
+    {
+        "strong" | "b" => ("**".to_owned(), "**".to_owned()),
+        "em" | "i" => ("*".to_owned(), "*".to_owned()),
+        "code" => ("`".to_owned(), "`".to_owned()),
+        "s" | "del" => ("~~".to_owned(), "~~".to_owned()),
+        "a" => {
+            // Attributes are stored as a flat list of names and values
+            let href = markup[1]
+                .as_array()
+                .and_then(|attrs| {
+                    attrs
+                        .chunks(2)
+                        .find(|attr| attr[0].as_str() == Some("href"))
+                        .and_then(|attr| attr.get(1)?.as_str())
+                })
+                .unwrap_or_default();
+            ("[".to_owned(), format!("]({})", href))
+        }
+        _ => (String::new(), String::new()),
+    }
+}
+
+/// Converts a Mobiledoc card to Markdown.
+fn card_to_markdown(card: &Value) -> Option<String> {
+    let payload = &card[1];
+    match card[0].as_str()? {
+        "markdown" | "card-markdown" => payload["markdown"].as_str().map(str::to_owned),
+        "html" => payload["html"].as_str().map(html_to_markdown),
+        "image" => {
+            let image = format!(
+                "![{}]({})",
+                payload["alt"].as_str().unwrap_or_default(),
+                payload["src"].as_str()?
+            );
+            match payload["caption"].as_str().map(html_to_markdown) {
+                Some(caption) if !caption.is_empty() => Some(format!("{}\n\n{}", image, caption)),
+                _ => Some(image),
+            }
+        }
+        "gallery" => payload["images"].as_array().map(|images| {
+            images
+                .iter()
+                .filter_map(|image| {
+                    Some(format!(
+                        "![{}]({})",
+                        image["alt"].as_str().unwrap_or_default(),
+                        image["src"].as_str()?
+                    ))
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }),
+        "code" => Some(format!(
+            "```{}\n{}\n```",
+            payload["language"].as_str().unwrap_or_default(),
+            payload["code"].as_str()?
+        )),
+        "hr" => Some("---".to_owned()),
+        "embed" | "bookmark" => {
+            let url = payload["url"].as_str()?;
+            let title = payload
+                .pointer("/metadata/title")
+                .and_then(Value::as_str)
+                .unwrap_or(url);
+            Some(format!("[{}]({})", title, url))
+        }
+        "callout" => payload["calloutText"]
+            .as_str()
+            .map(|text| quote(&html_to_markdown(text))),
+        _ => None,
+    }
+}
+
+/// Turns a block of Markdown into a quote.
+fn quote(text: &str) -> String {
+    text.lines()
+        .map(|line| format!("> {}", line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The elements of the `table` array of an export.
+fn list<'a>(data: &'a Value, table: &str) -> impl Iterator<Item = &'a Value> {
+    data[table].as_array().into_iter().flatten()
+}
+
+/// Ghost identifiers are strings, but they used to be numbers.
+fn id(value: &Value) -> Option<String> {
+    match value {
+        Value::String(id) => Some(id.clone()),
+        Value::Number(id) => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+/// Dates are in RFC 3339 format, or timestamps in milliseconds in old exports.
+fn date(value: &Value) -> Option<NaiveDateTime> {
+    match value {
+        Value::String(date) => DateTime::parse_from_rfc3339(date)
+            .ok()
+            .map(|date| date.naive_utc()),
+        Value::Number(timestamp) => {
+            let timestamp = timestamp.as_i64()?;
+            NaiveDateTime::from_timestamp_opt(timestamp / 1000, 0)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{inbox::tests::fill_database, posts::Post, tags::Tag, tests::db};
+    use chrono::NaiveDate;
+    use diesel::Connection;
+
+    const MOBILEDOC: &str = r#"{
+        "version": "0.3.1",
+        "atoms": [["soft-return", "", {}]],
+        "cards": [
+            ["markdown", {"markdown": "Some *Markdown*"}],
+            ["image", {"src": "https://example.com/cat.png", "alt": "A cat", "caption": "My <b>cat</b>"}],
+            ["code", {"code": "fn main() {}", "language": "rust"}]
+        ],
+        "markups": [["strong"], ["a", ["href", "https://joinplu.me", "rel", "noopener"]]],
+        "sections": [
+            [1, "h2", [[0, [], 0, "Title"]]],
+            [1, "p", [[0, [], 0, "Hello "], [0, [0], 0, "bold "], [0, [1], 2, "link"], [1, [], 0, 0], [0, [], 0, "!"]]],
+            [3, "ol", [[[0, [], 0, "one"]], [[0, [], 0, "two"]]]],
+            [10, 0],
+            [10, 1],
+            [10, 2],
+            [1, "blockquote", [[0, [], 0, "Quote"]]]
+        ]
+    }"#;
+
+    #[test]
+    fn convert_mobiledoc() {
+        assert_eq!(
+            mobiledoc_to_markdown(MOBILEDOC).unwrap(),
+            "## Title\n\n\
+             Hello **bold [link](https://joinplu.me)**  \n!\n\n\
+             1. one\n2. two\n\n\
+             Some *Markdown*\n\n\
+             ![A cat](https://example.com/cat.png)\n\nMy **cat**\n\n\
+             ```rust\nfn main() {}\n```\n\n\
+             > Quote"
+        );
+        assert!(mobiledoc_to_markdown("not JSON").is_none());
+    }
+
+    #[test]
+    fn import_posts() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (_, users, blogs) = fill_database(conn);
+            let export = json!({
+                "db": [{
+                    "meta": {"version": "4.0.0"},
+                    "data": {
+                        "posts": [
+                            {
+                                "id": "p1",
+                                "title": "From Ghost",
+                                "custom_excerpt": "Imported",
+                                "mobiledoc": "{\"version\":\"0.3.1\",\"sections\":[[1,\"p\",[[0,[],0,\"Hi\"]]]]}",
+                                "status": "published",
+                                "published_at": "2020-03-04T05:06:07.000Z",
+                                "type": "post"
+                            },
+                            {
+                                "id": "p2",
+                                "title": "Draft",
+                                "html": "<p>Not <em>yet</em></p>",
+                                "status": "draft",
+                                "type": "post"
+                            },
+                            {"id": "p3", "title": "About", "type": "page"}
+                        ],
+                        "tags": [
+                            {"id": "t1", "name": "Rust"},
+                            {"id": "t2", "name": "#internal"}
+                        ],
+                        "posts_tags": [
+                            {"post_id": "p1", "tag_id": "t1"},
+                            {"post_id": "p1", "tag_id": "t2"}
+                        ],
+                        "users": [
+                            {"id": "u1", "email": users[1].email},
+                            {"id": "u2", "email": "unknown@example.com"}
+                        ],
+                        "posts_authors": [
+                            {"post_id": "p1", "author_id": "u1"},
+                            {"post_id": "p2", "author_id": "u2"}
+                        ]
+                    }
+                }]
+            });
+            let report = import_export(conn, &export.to_string(), &blogs[0], &users[0])?;
+            assert_eq!(report.posts.len(), 2);
+            assert_eq!(report.errors.len(), 1);
+
+            let post = Post::find_by_slug(conn, "From Ghost", blogs[0].id)?;
+            assert_eq!(post.source, "Hi");
+            assert_eq!(post.subtitle, "Imported");
+            assert!(post.published);
+            assert_eq!(
+                post.creation_date,
+                NaiveDate::from_ymd(2020, 3, 4).and_hms(5, 6, 7)
+            );
+            assert!(post.is_author(conn, users[1].id)?);
+            assert!(!post.is_author(conn, users[0].id)?);
+            assert_eq!(
+                Tag::for_post(conn, post.id)?
+                    .into_iter()
+                    .map(|t| t.tag)
+                    .collect::<Vec<_>>(),
+                vec!["Rust".to_owned()]
+            );
+
+            let draft = Post::find_by_slug(conn, "Draft", blogs[0].id)?;
+            assert_eq!(draft.source, "Not *yet*");
+            assert!(!draft.published);
+            assert!(draft.is_author(conn, users[0].id)?);
+
+            // An editor can't attribute posts to the other members
+            let export = json!({
+                "data": {
+                    "posts": [{
+                        "id": "p4",
+                        "title": "Not by the owner",
+                        "html": "<p>Hi</p>",
+                        "status": "published"
+                    }],
+                    "users": [{"id": "u1", "email": users[0].email}],
+                    "posts_authors": [{"post_id": "p4", "author_id": "u1"}]
+                }
+            });
+            import_export(conn, &export.to_string(), &blogs[0], &users[1])?;
+            let post = Post::find_by_slug(conn, "Not by the owner", blogs[0].id)?;
+            assert!(post.is_author(conn, users[1].id)?);
+            assert!(!post.is_author(conn, users[0].id)?);
+            Ok(())
+        });
+    }
+}
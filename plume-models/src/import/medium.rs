@@ -0,0 +1,168 @@
+//! Import of the archives exported from Medium (Settings > Download your information).
+//!
+//! These archives are ZIP files, with an HTML file for each article in their `posts` directory.
+
+use super::{html_to_markdown, save_post, ImportReport, ImportedPost};
+use crate::{blogs::Blog, users::User, Connection, Error, Result};
+use chrono::DateTime;
+use std::io::{Read, Seek};
+
+/// Imports all the articles of a Medium archive in `blog`.
+pub fn import_archive<R: Read + Seek>(
+    conn: &Connection,
+    archive: R,
+    blog: &Blog,
+    author: &User,
+) -> Result<ImportReport> {
+    let mut archive = zip::ZipArchive::new(archive).map_err(|_| Error::InvalidValue)?;
+    let mut report = ImportReport::default();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(|_| Error::InvalidValue)?;
+        let name = file.name().to_owned();
+        if !name.starts_with("posts/") || !name.ends_with(".html") {
+            continue;
+        }
+        let mut html = String::new();
+        if file.read_to_string(&mut html).is_err() {
+            report.errors.push((name, "Invalid file".to_owned()));
+            continue;
+        }
+        let is_draft = name.trim_start_matches("posts/").starts_with("draft_");
+        match parse_post(&html, is_draft) {
+            Some(post) => match save_post(conn, blog, author, post) {
+                Ok(post) => report.posts.push(post),
+                Err(e) => report.errors.push((name, format!("{:?}", e))),
+            },
+            None => report
+                .errors
+                .push((name, "Not a Medium article".to_owned())),
+        }
+    }
+    Ok(report)
+}
+
+/// Reads an article from the HTML file exported by Medium.
+pub fn parse_post(html: &str, is_draft: bool) -> Option<ImportedPost> {
+    let title = text(inner_html(html, "class=\"p-name\"", "</h1>")?);
+    let subtitle = inner_html(html, "data-field=\"subtitle\"", "</section>")
+        .map(text)
+        .unwrap_or_default();
+    let creation_date = html
+        .find("class=\"dt-published\"")
+        .and_then(|start| attribute(&html[start..], "datetime"))
+        .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+        .map(|date| date.naive_utc());
+
+    let body_start = html.find("data-field=\"body\"")?;
+    let body_start = body_start + html[body_start..].find('>')? + 1;
+    let body_end = html[body_start..]
+        .find("<footer")
+        .map(|end| body_start + end)
+        .unwrap_or_else(|| html.len());
+    let body = &html[body_start..body_end];
+    let body = &body[..body.rfind("</section>").unwrap_or_else(|| body.len())];
+    // Medium repeats the title and the subtitle at the beginning of the article
+    let body = remove_element(
+        &remove_element(body, "graf--title", "h3"),
+        "graf--subtitle",
+        "h4",
+    );
+
+    Some(ImportedPost {
+        title,
+        subtitle,
+        source: html_to_markdown(&body),
+        creation_date,
+        published: !is_draft,
+        tags: vec![],
+    })
+}
+
+/// The HTML between the end of the tag containing `marker`, and `end`.
+fn inner_html<'a>(html: &'a str, marker: &str, end: &str) -> Option<&'a str> {
+    let start = html.find(marker)?;
+    let start = start + html[start..].find('>')? + 1;
+    let len = html[start..].find(end)?;
+    Some(&html[start..start + len])
+}
+
+/// The value of the first `name` attribute in `html`.
+fn attribute<'a>(html: &'a str, name: &str) -> Option<&'a str> {
+    let marker = format!("{}=\"", name);
+    let start = html.find(&marker)? + marker.len();
+    let len = html[start..].find('"')?;
+    Some(&html[start..start + len])
+}
+
+/// Removes the first `tag` element having `class` from `html`.
+fn remove_element(html: &str, class: &str, tag: &str) -> String {
+    let found = html.find(class).and_then(|pos| {
+        let start = html[..pos].rfind(&format!("<{}", tag))?;
+        let close = format!("</{}>", tag);
+        let end = pos + html[pos..].find(&close)? + close.len();
+        Some((start, end))
+    });
+    match found {
+        Some((start, end)) => format!("{}{}", &html[..start], &html[end..]),
+        None => html.to_owned(),
+    }
+}
+
+/// The text of an HTML fragment, without its tags.
+fn text(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    const ARTICLE: &str = r#"<!DOCTYPE html><html><head><title>Hello &amp; welcome</title></head><body><article class="h-entry">
+<header><h1 class="p-name">Hello &amp; welcome</h1></header>
+<section data-field="subtitle" class="p-summary">
+A first article
+</section>
+<section data-field="body" class="e-content">
+<section name="a1b2" class="section section--body section--first"><div class="section-content"><div class="section-inner"><h3 name="c3d4" class="graf graf--h3 graf--leading graf--title">Hello &amp; welcome</h3><p name="e5f6" class="graf graf--p">Some <strong>bold</strong> text.</p><figure name="g7h8" class="graf graf--figure"><img class="graf-image" src="https://cdn-images-1.medium.com/max/800/1*abc.png"></figure></div></div></section>
+</section>
+<footer><p>By <a href="https://medium.com/@someone" class="p-author h-card">Someone</a> on <a href="https://medium.com/p/123"><time class="dt-published" datetime="2019-05-01T10:20:30.000Z">May 1, 2019</time></a>.</p></footer></article></body></html>"#;
+
+    #[test]
+    fn parse_article() {
+        let post = parse_post(ARTICLE, false).unwrap();
+        assert_eq!(post.title, "Hello & welcome");
+        assert_eq!(post.subtitle, "A first article");
+        assert_eq!(
+            post.creation_date,
+            Some(NaiveDate::from_ymd(2019, 5, 1).and_hms(10, 20, 30))
+        );
+        assert!(post.published);
+        assert!(!post.source.contains("welcome"));
+        assert!(post.source.contains("**bold**"));
+        assert!(post
+            .source
+            .contains("![](https://cdn-images-1.medium.com/max/800/"));
+
+        let draft = parse_post(ARTICLE, true).unwrap();
+        assert!(!draft.published);
+        assert!(parse_post("<html></html>", false).is_none());
+    }
+}
@@ -0,0 +1,244 @@
+//! Importers for articles exported from other blogging platforms.
+//!
+//! Imported articles are saved as if they were written on Plume, but
+//! they are not federated: they are old news for the followers of the blog.
+
+use crate::{
+    blogs::Blog,
+    instance::Instance,
+    medias::Media,
+    outgoing::{self, Limits},
+    post_authors::{NewPostAuthor, PostAuthor},
+    posts::{NewPost, Post},
+    safe_string::SafeString,
+    tags::{NewTag, Tag},
+    timeline::{Kind, Timeline},
+    users::User,
+    Connection, Error, Result, CONFIG,
+};
+use chrono::NaiveDateTime;
+use plume_common::utils::md_to_html;
+use std::collections::HashSet;
+use tracing::warn;
+
+pub mod ghost;
+pub mod medium;
+
+/// An article read from an export, before being saved.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportedPost {
+    pub title: String,
+    pub subtitle: String,
+    /// Markdown source of the article
+    pub source: String,
+    pub creation_date: Option<NaiveDateTime>,
+    pub published: bool,
+    pub tags: Vec<String>,
+}
+
+/// The outcome of an import.
+#[derive(Default)]
+pub struct ImportReport {
+    pub posts: Vec<Post>,
+    /// The articles that couldn't be imported, with the reason why
+    pub errors: Vec<(String, String)>,
+}
+
+/// Converts a fragment of HTML to Markdown.
+pub fn html_to_markdown(html: &str) -> String {
+    html2md::parse_html(html).trim().to_owned()
+}
+
+/// How long downloading an image can take, in seconds.
+const IMAGE_TIMEOUT: u64 = 30;
+
+/// Downloads an image, and saves it as a media of `owner`.
+fn download_image(conn: &Connection, url: &str, alt_text: &str, owner: &User) -> Result<Media> {
+    let image = outgoing::get(
+        url,
+        &Limits::new(IMAGE_TIMEOUT, CONFIG.body_limits.media.size),
+    )?;
+    let ext = image
+        .url
+        .path()
+        .rsplit('/')
+        .next()
+        .and_then(|file| file.rsplit_once('.'))
+        .map(|(_, ext)| ext)
+        .unwrap_or("png");
+    Media::save_bytes(conn, &image.body, ext, alt_text.to_owned(), owner)
+}
+
+/// Replaces the remote images of a Markdown document with local medias.
+///
+/// Images that can't be downloaded are kept as links to their original location.
+pub fn localize_images(conn: &Connection, source: &str, owner: &User) -> String {
+    let mut result = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some(start) = rest.find("![") {
+        result.push_str(&rest[..start]);
+        let image = &rest[start..];
+        let parsed = image.find("](").and_then(|alt_end| {
+            let url_end = image[alt_end..].find(')')? + alt_end;
+            Some((&image[2..alt_end], &image[alt_end + 2..url_end], url_end))
+        });
+        match parsed {
+            Some((alt, url, end)) if url.starts_with("http://") || url.starts_with("https://") => {
+                // Markdown images can have a title after the URL
+                let url = url.split_whitespace().next().unwrap_or_default();
+                match download_image(conn, url, alt, owner).and_then(|m| m.markdown()) {
+                    Ok(markdown) => result.push_str(markdown.get()),
+                    Err(e) => {
+                        warn!("Couldn't import image {}: {:?}", url, e);
+                        result.push_str(&image[..=end]);
+                    }
+                }
+                rest = &image[end + 1..];
+            }
+            _ => {
+                result.push_str("![");
+                rest = &image[2..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Saves an imported article in `blog`, written by `author`.
+///
+/// If another article of the blog already has the same title, a number is added to its slug.
+/// Its images are still the remote ones: `localize_post_images` downloads them later, in the
+/// background.
+pub fn save_post(
+    conn: &Connection,
+    blog: &Blog,
+    author: &User,
+    imported: ImportedPost,
+) -> Result<Post> {
+    save_local_post(conn, blog, author, imported)
+}
+
+/// Replaces the remote images of an imported article with local medias of `author`.
+pub fn localize_post_images(conn: &Connection, post_id: i32, author: &User) -> Result<()> {
+    let mut post = Post::get(conn, post_id)?;
+    let source = localize_images(conn, &post.source, author);
+    if source == post.source {
+        return Ok(());
+    }
+    post.content = SafeString::new(&render(conn, &source, author)?.0);
+    post.source = source;
+    post.update(conn)?;
+    Ok(())
+}
+
+fn render(conn: &Connection, source: &str, author: &User) -> Result<(String, HashSet<String>)> {
+    let (content, _mentions, hashtags) = md_to_html(
+        source,
+        Some(&Instance::get_local()?.public_domain),
+        false,
+        Some(Media::get_media_processor(conn, vec![author])),
+    );
+    Ok((content, hashtags))
+}
+
+/// Saves an imported article whose images are already medias of this instance.
+pub fn save_local_post(
+    conn: &Connection,
+    blog: &Blog,
+    author: &User,
+    imported: ImportedPost,
+) -> Result<Post> {
+    if imported.title.trim().is_empty() {
+        return Err(Error::InvalidValue);
+    }
+    let source = imported.source;
+    let (content, hashtags) = render(conn, &source, author)?;
+
+    let base_slug = Post::slug(&imported.title).to_owned();
+    let mut slug = base_slug.clone();
+    let mut n = 1;
+    while Post::find_by_slug(conn, &slug, blog.id).is_ok() {
+        n += 1;
+        slug = format!("{}-{}", base_slug, n);
+    }
+
+    let post = Post::insert(
+        conn,
+        NewPost {
+            blog_id: blog.id,
+            slug,
+            title: imported.title,
+            content: SafeString::new(&content),
+            published: imported.published,
+            license: blog.license(conn)?,
+            creation_date: imported.creation_date,
+            ap_url: String::new(),
+            subtitle: imported.subtitle,
+            source,
+            cover_id: None,
+            excerpt: String::new(),
+            canonical_url: None,
+        },
+    )?;
+    PostAuthor::insert(
+        conn,
+        NewPostAuthor {
+            post_id: post.id,
+            author_id: author.id,
+        },
+    )?;
+    for tag in imported.tags {
+        Tag::insert(
+            conn,
+            NewTag {
+                tag,
+                is_hashtag: false,
+                post_id: post.id,
+            },
+        )?;
+    }
+    for hashtag in hashtags {
+        Tag::insert(
+            conn,
+            NewTag {
+                tag: hashtag,
+                is_hashtag: true,
+                post_id: post.id,
+            },
+        )?;
+    }
+    if post.published {
+        Timeline::add_to_all_timelines(conn, &post, Kind::Original)?;
+    }
+    Ok(post)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{inbox::tests::fill_database, tests::db};
+    use diesel::Connection;
+
+    #[test]
+    fn save_with_unique_slug() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (_, users, blogs) = fill_database(conn);
+            let imported = ImportedPost {
+                title: "testing".to_owned(),
+                subtitle: "Imported".to_owned(),
+                source: "Hello ![not an image](relative.png)".to_owned(),
+                creation_date: None,
+                published: true,
+                tags: vec!["Import".to_owned()],
+            };
+            let post = save_post(conn, &blogs[0], &users[0], imported)?;
+            assert_eq!(post.slug, "testing-2");
+            assert_eq!(post.source, "Hello ![not an image](relative.png)");
+            assert!(post.is_author(conn, users[0].id)?);
+            assert_eq!(Tag::for_post(conn, post.id)?[0].tag, "Import");
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,335 @@
+//! What happens to the local accounts that are never used, or not anymore.
+//!
+//! The accounts whose owner never logged in after signing up are deleted after a while, and
+//! the ones nobody used for a long time are first frozen (deactivated), then deleted. Their
+//! owner is emailed before each step: if they can't be, nothing happens, and when they are
+//! reminded late the step is delayed to give them as much time as usual. Logging in unfreezes
+//! an account. Administrators and moderators, and the accounts deactivated by them, are left
+//! alone.
+
+use crate::{
+    config::InactivityConfig,
+    instance::Instance,
+    personal_data,
+    schema::users,
+    users::{Role, User},
+    Connection, Error, Result,
+};
+use activitystreams::activity::Delete;
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{self, BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl};
+use tracing::warn;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Workflow {
+    /// Their owner never logged in after signing up
+    Unconfirmed,
+    /// Nobody used them for a while
+    Inactive,
+}
+
+/// An account whose owner has to be told what will happen to it.
+pub struct Reminder {
+    pub user: User,
+    pub workflow: Workflow,
+    /// Whether it will be deleted, and not only frozen
+    pub deletion: bool,
+    /// When it will be frozen or deleted, if it isn't used before
+    pub deadline: NaiveDateTime,
+}
+
+/// A deleted account, and how to tell the other instances to delete their copy of it.
+pub struct Deletion {
+    pub user: User,
+    pub activity: Delete,
+    /// The accounts to send the activity to, found before the deletion
+    pub targets: Vec<User>,
+}
+
+/// The result of `clean_up`.
+#[derive(Default)]
+pub struct CleanupReport {
+    pub frozen: Vec<User>,
+    pub deleted: Vec<User>,
+    /// The activities to send, empty for a dry run
+    pub deletions: Vec<Deletion>,
+}
+
+/// The days after which each step happens, 0 if it never does.
+struct Steps {
+    reminder: i64,
+    freeze: i64,
+    deletion: i64,
+}
+
+impl Steps {
+    fn for_workflow(config: &InactivityConfig, workflow: Workflow) -> Self {
+        match workflow {
+            Workflow::Unconfirmed => Steps {
+                reminder: config.unconfirmed_reminder_days,
+                freeze: 0,
+                deletion: config.unconfirmed_deletion_days,
+            },
+            Workflow::Inactive => Steps {
+                reminder: config.inactive_reminder_days,
+                freeze: config.inactive_freeze_days,
+                deletion: config.inactive_deletion_days,
+            },
+        }
+    }
+
+    /// The days after which the first step happens, and whether it is the deletion.
+    fn first(&self) -> (i64, bool) {
+        if self.freeze > 0 {
+            (self.freeze, false)
+        } else {
+            (self.deletion, true)
+        }
+    }
+
+    /// How many days the owner of an account has between a reminder and the step it is about.
+    fn notice(&self) -> i64 {
+        self.first().0 - self.reminder
+    }
+
+    /// When the step happening after `days` is due, if it is enabled and the owner of the
+    /// account was reminded (at `reminded_at`) when they have to be.
+    fn due(
+        &self,
+        days: i64,
+        since: NaiveDateTime,
+        reminded_at: Option<NaiveDateTime>,
+    ) -> Option<NaiveDateTime> {
+        if days <= 0 {
+            return None;
+        }
+        let date = since + Duration::days(days);
+        if self.reminder <= 0 {
+            return Some(date);
+        }
+        reminded_at.map(|reminded| date.max(reminded + Duration::days(self.notice())))
+    }
+}
+
+/// What happens to an account, and since when it isn't used.
+pub fn workflow(user: &User) -> (Workflow, NaiveDateTime) {
+    match user.last_active {
+        Some(date) => (Workflow::Inactive, date),
+        None => (Workflow::Unconfirmed, user.creation_date),
+    }
+}
+
+/// The local accounts that can be frozen or deleted.
+fn candidates(conn: &Connection) -> Result<Vec<User>> {
+    users::table
+        .filter(users::instance_id.eq(Instance::get_local()?.id))
+        .filter(users::role.eq(Role::Normal as i32))
+        .filter(
+            users::deactivated
+                .eq(false)
+                .or(users::frozen_at.is_not_null()),
+        )
+        .order(users::id.asc())
+        .load::<User>(conn)
+        .map_err(Error::from)
+}
+
+/// The owners of the accounts to remind now.
+///
+/// The owners of frozen accounts are reminded again before their account is deleted.
+pub fn pending_reminders(conn: &Connection, config: &InactivityConfig) -> Result<Vec<Reminder>> {
+    let now = Utc::now().naive_utc();
+    let mut reminders = vec![];
+    for user in candidates(conn)? {
+        if user.inactivity_reminded_at.is_some() {
+            continue;
+        }
+        let (workflow, since) = workflow(&user);
+        let steps = Steps::for_workflow(config, workflow);
+        let (days, deletion) = if user.frozen_at.is_some() {
+            (steps.deletion, true)
+        } else {
+            steps.first()
+        };
+        if steps.reminder <= 0 || days <= 0 || since + Duration::days(days - steps.notice()) > now {
+            continue;
+        }
+        if let Some(deadline) = steps.due(days, since, Some(now)) {
+            reminders.push(Reminder {
+                user,
+                workflow,
+                deletion,
+                deadline,
+            });
+        }
+    }
+    Ok(reminders)
+}
+
+/// Remembers that the owner of an account was reminded, once they are.
+pub fn mark_reminded(conn: &Connection, user: &User) -> Result<()> {
+    diesel::update(user)
+        .set(users::inactivity_reminded_at.eq(Utc::now().naive_utc()))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Freezes and deletes the accounts that are due.
+///
+/// With `dry_run`, nothing changes, but the report tells what would.
+pub fn clean_up(
+    conn: &Connection,
+    config: &InactivityConfig,
+    dry_run: bool,
+) -> Result<CleanupReport> {
+    let now = Utc::now().naive_utc();
+    let mut report = CleanupReport::default();
+    // The instances to tell about the deletions, loaded once
+    let mut targets = None;
+    for user in candidates(conn)? {
+        let (workflow, since) = workflow(&user);
+        let steps = Steps::for_workflow(config, workflow);
+        let reminded_at = user.inactivity_reminded_at;
+        let frozen = user.frozen_at.is_some() || steps.freeze <= 0;
+        if steps
+            .due(steps.deletion, since, reminded_at)
+            .map_or(false, |date| date <= now && frozen)
+        {
+            if !dry_run {
+                if targets.is_none() {
+                    targets = Some(User::one_by_instance(conn)?);
+                }
+                match personal_data::erase(conn, &user) {
+                    Ok(activity) => report.deletions.push(Deletion {
+                        user: user.clone(),
+                        activity,
+                        targets: targets.clone().unwrap_or_default(),
+                    }),
+                    Err(err) => {
+                        warn!("Couldn't delete inactive user {}: {:?}", user.id, err);
+                        continue;
+                    }
+                }
+            }
+            report.deleted.push(user);
+        } else if !frozen
+            && steps
+                .due(steps.freeze, since, reminded_at)
+                .map_or(false, |date| date <= now)
+        {
+            if !dry_run {
+                // Their owner will be reminded again before the deletion
+                diesel::update(&user)
+                    .set((
+                        users::frozen_at.eq(now),
+                        users::inactivity_reminded_at.eq(None::<NaiveDateTime>),
+                    ))
+                    .execute(conn)?;
+                user.set_deactivated(conn, true)?;
+            }
+            report.frozen.push(user);
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tests::db, users::tests as user_tests};
+    use diesel::Connection;
+
+    fn config() -> InactivityConfig {
+        InactivityConfig {
+            enabled: true,
+            unconfirmed_reminder_days: 7,
+            unconfirmed_deletion_days: 30,
+            inactive_reminder_days: 335,
+            inactive_freeze_days: 365,
+            inactive_deletion_days: 730,
+        }
+    }
+
+    fn set_dates(
+        conn: &crate::Connection,
+        user: &User,
+        creation_days: i64,
+        last_active_days: Option<i64>,
+    ) -> Result<User> {
+        let now = Utc::now().naive_utc();
+        diesel::update(user)
+            .set((
+                users::creation_date.eq(now - Duration::days(creation_days)),
+                users::last_active.eq(last_active_days.map(|days| now - Duration::days(days))),
+            ))
+            .execute(conn)?;
+        User::get(conn, user.id)
+    }
+
+    #[test]
+    fn remind_freeze_and_delete() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let users = user_tests::fill_database(conn);
+            let config = config();
+            // users[0] is an admin, and is left alone
+            set_dates(conn, &users[0], 1000, None)?;
+            let unconfirmed = set_dates(conn, &users[1], 10, None)?;
+            let inactive = set_dates(conn, &users[2], 1000, Some(400))?;
+
+            // Nothing happens before the owners are reminded
+            let report = clean_up(conn, &config, false)?;
+            assert!(report.frozen.is_empty() && report.deleted.is_empty());
+            let reminders = pending_reminders(conn, &config)?;
+            assert_eq!(reminders.len(), 2);
+            assert_eq!(reminders[0].user.id, unconfirmed.id);
+            assert_eq!(reminders[0].workflow, Workflow::Unconfirmed);
+            assert!(reminders[0].deletion);
+            assert_eq!(reminders[1].workflow, Workflow::Inactive);
+            assert!(!reminders[1].deletion);
+            // Reminded late, the owner still has 30 days before their account is frozen
+            assert!(reminders[1].deadline > Utc::now().naive_utc() + Duration::days(29));
+            for reminder in &reminders {
+                mark_reminded(conn, &reminder.user)?;
+            }
+            assert!(pending_reminders(conn, &config)?.is_empty());
+
+            // Logging in cancels the reminder
+            let unconfirmed = User::get(conn, unconfirmed.id)?;
+            unconfirmed.mark_active(conn)?;
+            assert!(User::get(conn, unconfirmed.id)?
+                .inactivity_reminded_at
+                .is_none());
+
+            // Once the delay after the reminder is over, the account is frozen, then deleted
+            let long_ago = Utc::now().naive_utc() - Duration::days(400);
+            diesel::update(&inactive)
+                .set(users::inactivity_reminded_at.eq(long_ago))
+                .execute(conn)?;
+            let dry_run = clean_up(conn, &config, true)?;
+            assert_eq!(dry_run.frozen.len(), 1);
+            assert!(!User::get(conn, inactive.id)?.deactivated);
+            let report = clean_up(conn, &config, false)?;
+            assert_eq!(report.frozen.len(), 1);
+            let frozen = User::get(conn, inactive.id)?;
+            assert!(frozen.deactivated && frozen.frozen_at.is_some());
+
+            set_dates(conn, &frozen, 1000, Some(800))?;
+
+            // Its owner is reminded again before it is deleted
+            assert!(clean_up(conn, &config, false)?.deleted.is_empty());
+            let reminders = pending_reminders(conn, &config)?;
+            assert_eq!(reminders.len(), 1);
+            assert!(reminders[0].deletion);
+            assert!(reminders[0].deadline > Utc::now().naive_utc() + Duration::days(29));
+            diesel::update(&frozen)
+                .set(users::inactivity_reminded_at.eq(long_ago))
+                .execute(conn)?;
+            let report = clean_up(conn, &config, false)?;
+            assert_eq!(report.deleted.len(), 1);
+            assert_eq!(report.deletions.len(), 1);
+            assert!(User::get(conn, inactive.id).is_err());
+            Ok(())
+        });
+    }
+}
@@ -1,15 +1,24 @@
 use activitystreams::activity::{Announce, Create, Delete, Follow, Like, Undo, Update};
 
 use crate::{
+    blog_transfer,
+    comment_likes::CommentLike,
     comments::Comment,
-    follows, likes,
+    follows, galleries, groups, likes,
     posts::{Post, PostUpdate},
+    relays,
     reshares::Reshare,
     users::User,
+    worker::JobQueue,
     Connection, Error, CONFIG,
 };
+use once_cell::sync::Lazy;
 use plume_common::activity_pub::inbox::Inbox;
 
+/// The activities waiting to be handled by the worker. When it is full, the next ones are
+/// refused, and delivered again later by their instance.
+pub static QUEUE: Lazy<JobQueue> = Lazy::new(|| JobQueue::new(CONFIG.inbox_queue));
+
 macro_rules! impl_into_inbox_result {
     ( $( $t:ty => $variant:ident ),+ ) => {
         $(
@@ -24,6 +33,7 @@ macro_rules! impl_into_inbox_result {
 
 pub enum InboxResult {
     Commented(Comment),
+    CommentLiked(CommentLike),
     Followed(follows::Follow),
     Liked(likes::Like),
     Other,
@@ -39,6 +49,7 @@ impl From<()> for InboxResult {
 
 impl_into_inbox_result! {
     Comment => Commented,
+    CommentLike => CommentLiked,
     follows::Follow => Followed,
     likes::Like => Liked,
     Post => Post,
@@ -46,6 +57,19 @@ impl_into_inbox_result! {
 }
 
 pub fn inbox(conn: &Connection, act: serde_json::Value) -> Result<InboxResult, Error> {
+    if let Some(relay) = relays::sender(conn, &act) {
+        return relays::handle(conn, relay, &act);
+    }
+    if blog_transfer::is_blog_move(conn, &act) {
+        return blog_transfer::handle_move(conn, &act);
+    }
+    if groups::is_group_announce(&act) {
+        return groups::handle_announce(conn, &act);
+    }
+    if galleries::is_image_post(&act) {
+        return galleries::handle_create(conn, &act);
+    }
+    Comment::check_delivery(conn, &act)?;
     Inbox::handle(conn, act)
         .with::<User, Announce, Post>(CONFIG.proxy())
         .with::<User, Create, Comment>(CONFIG.proxy())
@@ -55,9 +79,11 @@ pub fn inbox(conn: &Connection, act: serde_json::Value) -> Result<InboxResult, E
         .with::<User, Delete, User>(CONFIG.proxy())
         .with::<User, Follow, User>(CONFIG.proxy())
         .with::<User, Like, Post>(CONFIG.proxy())
+        .with::<User, Like, Comment>(CONFIG.proxy())
         .with::<User, Undo, Reshare>(CONFIG.proxy())
         .with::<User, Undo, follows::Follow>(CONFIG.proxy())
         .with::<User, Undo, likes::Like>(CONFIG.proxy())
+        .with::<User, Undo, CommentLike>(CONFIG.proxy())
         .with::<User, Update, PostUpdate>(CONFIG.proxy())
         .done()
 }
@@ -96,6 +122,8 @@ pub(crate) mod tests {
                 subtitle: "Bye".to_string(),
                 source: "Hello".to_string(),
                 cover_id: None,
+                excerpt: String::new(),
+                canonical_url: None,
             },
         )
         .unwrap();
@@ -423,6 +451,8 @@ pub(crate) mod tests {
                     sensitive: false,
                     spoiler_text: "spoiler".to_owned(),
                     public_visibility: true,
+                    held: false,
+                    spam_score: None,
                 },
             )
             .unwrap();
@@ -546,6 +576,55 @@ pub(crate) mod tests {
         });
     }
 
+    #[test]
+    fn like_comment() {
+        use crate::comments::*;
+
+        let conn = db();
+        conn.test_transaction::<_, (), _>(|| {
+            let (posts, users, _) = fill_database(&conn);
+            let comment = Comment::insert(
+                &conn,
+                NewComment {
+                    content: SafeString::new("My comment"),
+                    post_id: posts[0].id,
+                    author_id: users[0].id,
+                    public_visibility: true,
+                    ..NewComment::default()
+                },
+            )
+            .unwrap();
+
+            let act = json!({
+                "id": "https://plu.me/like/2",
+                "actor": users[1].ap_url,
+                "object": comment.ap_url,
+                "type": "Like",
+            });
+            match super::inbox(&conn, act).unwrap() {
+                InboxResult::CommentLiked(l) => {
+                    assert_eq!(l.user_id, users[1].id);
+                    assert_eq!(l.comment_id, comment.id);
+                    assert_eq!(l.ap_url, "https://plu.me/like/2".to_owned());
+                }
+                _ => panic!("Unexpected result"),
+            }
+
+            let undo = json!({
+                "id": "https://plu.me/undo/2",
+                "actor": users[1].ap_url,
+                "object": "https://plu.me/like/2",
+                "type": "Undo",
+            });
+            assert!(super::inbox(&conn, undo).is_ok());
+            assert_eq!(
+                crate::comment_likes::CommentLike::count_for_comment(&conn, comment.id).unwrap(),
+                0
+            );
+            Ok(())
+        });
+    }
+
     #[test]
     fn undo_reshare() {
         use crate::reshares::*;
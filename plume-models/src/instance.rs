@@ -1,5 +1,6 @@
 use crate::{
     ap_url,
+    blocklists::BlocklistEntry,
     medias::Media,
     safe_string::SafeString,
     schema::{instances, users},
@@ -26,6 +27,11 @@ pub struct Instance {
     pub default_license: String,
     pub long_description_html: SafeString,
     pub short_description_html: SafeString,
+    /// Whether it was blocked because a subscribed blocklist lists it, rather than by a
+    /// moderator of this instance (see `blocklists`)
+    pub blocked_by_subscription: bool,
+    /// The local user to contact about this instance, if it is not its main administrator
+    pub contact_user_id: Option<i32>,
 }
 
 #[derive(Clone, Insertable)]
@@ -46,7 +52,8 @@ lazy_static! {
     static ref LOCAL_INSTANCE: RwLock<Option<Instance>> = RwLock::new(None);
 }
 
-const LOCAL_INSTANCE_USERNAME: &str = "__instance__";
+/// The username of the actor of Plume instances, here and on other instances
+pub(crate) const LOCAL_INSTANCE_USERNAME: &str = "__instance__";
 static LOCAL_INSTANCE_USER: OnceCell<User> = OnceCell::new();
 
 impl Instance {
@@ -129,9 +136,14 @@ impl Instance {
     get!(instances);
     find_by!(instances, find_by_domain, public_domain as &str);
 
+    /// Blocks or unblocks this instance by hand, taking over any block from a subscribed
+    /// blocklist.
     pub fn toggle_block(&self, conn: &Connection) -> Result<()> {
         diesel::update(self)
-            .set(instances::blocked.eq(!self.blocked))
+            .set((
+                instances::blocked.eq(!self.blocked),
+                instances::blocked_by_subscription.eq(false),
+            ))
             .execute(conn)
             .map(|_| ())
             .map_err(Error::from)
@@ -148,7 +160,15 @@ impl Instance {
             }
         }
 
-        Ok(false)
+        // Subdomains of the domains listed by blocklists, that were not known when they synced
+        match url::Url::parse(id)
+            .ok()
+            .as_ref()
+            .and_then(url::Url::host_str)
+        {
+            Some(host) => BlocklistEntry::blocks_subdomain(conn, host),
+            None => Ok(false),
+        }
     }
 
     pub fn has_admin(&self, conn: &Connection) -> Result<bool> {
@@ -168,6 +188,36 @@ impl Instance {
             .map_err(Error::from)
     }
 
+    /// Who to contact about this instance: the chosen account, or the main administrator.
+    pub fn contact(&self, conn: &Connection) -> Result<User> {
+        match self.contact_user_id {
+            Some(id) => User::get(conn, id),
+            None => self.main_admin(conn),
+        }
+    }
+
+    /// Chooses the account to contact about this instance, which has to be a local one.
+    pub fn set_contact(&self, conn: &Connection, contact: Option<&User>) -> Result<()> {
+        if contact.map_or(false, |user| user.instance_id != self.id) {
+            return Err(Error::InvalidValue);
+        }
+        diesel::update(self)
+            .set(instances::contact_user_id.eq(contact.map(|user| user.id)))
+            .execute(conn)?;
+        if self.local {
+            Instance::cache_local(conn);
+        }
+        Ok(())
+    }
+
+    pub fn admins(&self, conn: &Connection) -> Result<Vec<User>> {
+        users::table
+            .filter(users::instance_id.eq(self.id))
+            .filter(users::role.eq(Role::Admin as i32))
+            .load::<User>(conn)
+            .map_err(Error::from)
+    }
+
     pub fn compute_box(&self, prefix: &str, name: &str, box_name: &str) -> String {
         ap_url(&format!(
             "{instance}/{prefix}/{name}/{box_name}",
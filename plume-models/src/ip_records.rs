@@ -0,0 +1,286 @@
+//! The IP addresses that created accounts and comments.
+//!
+//! They help moderators to find accounts created by the same person, and to limit the
+//! registrations from some networks. Unless `IP_HASH` is disabled, only a keyed hash of
+//! the addresses is stored, and all of them are forgotten after `IP_RETENTION_DAYS`.
+
+use crate::{config::reloadable, schema::ip_records, Connection, Error, Result, CONFIG};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+use openssl::sha::sha256;
+use rocket::{
+    request::{self, FromRequest, Request},
+    Outcome,
+};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// The address of the client, as seen by Rocket (which reads `X-Real-IP` when behind a proxy).
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for ClientIp {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        Outcome::Success(ClientIp(request.client_ip()))
+    }
+}
+
+/// A range of addresses, like `192.0.2.0/24` or `2001:db8::/32`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IpRange {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, ip) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                prefix_matches(&range.octets(), &ip.octets(), self.prefix)
+            }
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                prefix_matches(&range.octets(), &ip.octets(), self.prefix)
+            }
+            (IpAddr::V4(range), IpAddr::V6(ip)) => ip
+                .to_ipv4()
+                .map(|ip| prefix_matches(&range.octets(), &ip.octets(), self.prefix))
+                .unwrap_or(false),
+            (IpAddr::V6(_), IpAddr::V4(_)) => false,
+        }
+    }
+}
+
+fn prefix_matches(range: &[u8], ip: &[u8], prefix: u8) -> bool {
+    let full_bytes = (prefix / 8) as usize;
+    let rest = prefix % 8;
+    if range[..full_bytes] != ip[..full_bytes] {
+        return false;
+    }
+    if rest == 0 {
+        return true;
+    }
+    let mask = 0xffu8 << (8 - rest);
+    range[full_bytes] & mask == ip[full_bytes] & mask
+}
+
+impl FromStr for IpRange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+        let address = address
+            .trim()
+            .parse::<IpAddr>()
+            .map_err(|_| Error::InvalidValue)?;
+        let max = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse::<u8>()
+                .map_err(|_| Error::InvalidValue)?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(Error::InvalidValue);
+        }
+        Ok(IpRange { address, prefix })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpRecordKind {
+    Registration = 0,
+    Comment = 1,
+}
+
+#[derive(Clone, Queryable, Identifiable)]
+pub struct IpRecord {
+    pub id: i32,
+    pub kind: i32,
+    pub user_id: Option<i32>,
+    pub comment_id: Option<i32>,
+    /// The address, or its hash
+    pub address: String,
+    /// The throttled range the address was in, if any
+    pub ip_range: Option<String>,
+    pub creation_date: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "ip_records"]
+pub struct NewIpRecord {
+    pub kind: i32,
+    pub user_id: Option<i32>,
+    pub comment_id: Option<i32>,
+    pub address: String,
+    pub ip_range: Option<String>,
+}
+
+impl IpRecord {
+    insert!(ip_records, NewIpRecord);
+    get!(ip_records);
+
+    /// Remembers that an account or a comment was created from `ip`.
+    pub fn record(
+        conn: &Connection,
+        kind: IpRecordKind,
+        ip: Option<IpAddr>,
+        user_id: Option<i32>,
+        comment_id: Option<i32>,
+    ) -> Result<Option<Self>> {
+        let ip = match ip {
+            Some(ip) => ip,
+            None => return Ok(None),
+        };
+        Self::insert(
+            conn,
+            NewIpRecord {
+                kind: kind as i32,
+                user_id,
+                comment_id,
+                address: stored_address(ip),
+                ip_range: throttled_range(ip),
+            },
+        )
+        .map(Some)
+    }
+
+    /// Checks that a new account can be created from `ip`.
+    pub fn check_registration(conn: &Connection, ip: Option<IpAddr>) -> Result<()> {
+        let ip = match ip {
+            Some(ip) => ip,
+            None => return Ok(()),
+        };
+        let config = reloadable();
+        if config.ip.blocked_ranges.iter().any(|r| r.contains(ip)) {
+            return Err(Error::Blocklisted(
+                true,
+                "Registrations from your network are not allowed.".to_owned(),
+            ));
+        }
+        if let Some(range) = throttled_range(ip) {
+            let today = ip_records::table
+                .filter(ip_records::kind.eq(IpRecordKind::Registration as i32))
+                .filter(ip_records::ip_range.eq(range))
+                .filter(ip_records::creation_date.gt(Utc::now().naive_utc() - Duration::days(1)))
+                .count()
+                .get_result::<i64>(conn)?;
+            if today >= config.ip.throttle_limit {
+                return Err(Error::Blocklisted(
+                    true,
+                    "Too many accounts were created from your network today, please try again later."
+                        .to_owned(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The other users who created their account from the same address as `user_id`.
+    pub fn same_address_as(conn: &Connection, user_id: i32) -> Result<Vec<i32>> {
+        let addresses = ip_records::table
+            .filter(ip_records::user_id.eq(user_id))
+            .select(ip_records::address)
+            .load::<String>(conn)?;
+        ip_records::table
+            .filter(ip_records::address.eq_any(addresses))
+            .filter(ip_records::user_id.ne(user_id))
+            .select(ip_records::user_id)
+            .distinct()
+            .load::<Option<i32>>(conn)
+            .map(|ids| ids.into_iter().flatten().collect())
+            .map_err(Error::from)
+    }
+
+    pub fn for_user(conn: &Connection, user_id: i32) -> Result<Vec<Self>> {
+        ip_records::table
+            .filter(ip_records::user_id.eq(user_id))
+            .load::<Self>(conn)
+            .map_err(Error::from)
+    }
+
+    /// Forgets the addresses older than the retention period.
+    pub fn purge_expired(conn: &Connection) -> Result<usize> {
+        let limit = Utc::now().naive_utc() - Duration::days(reloadable().ip.retention_days);
+        diesel::delete(ip_records::table.filter(ip_records::creation_date.lt(limit)))
+            .execute(conn)
+            .map_err(Error::from)
+    }
+}
+
+pub(crate) fn stored_address(ip: IpAddr) -> String {
+    match CONFIG.ip_hash {
+        Some(ref salt) => sha256(format!("{}|{}", salt, ip).as_bytes())
+            .iter()
+            .fold(String::new(), |res, byte| format!("{}{:02x}", res, byte)),
+        None => ip.to_string(),
+    }
+}
+
+/// The throttled range containing `ip`, as written in the configuration.
+fn throttled_range(ip: IpAddr) -> Option<String> {
+    reloadable()
+        .ip
+        .throttled_ranges
+        .iter()
+        .find(|(_, range)| range.contains(ip))
+        .map(|(name, _)| name.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tests::db, users::tests::fill_database};
+    use diesel::Connection;
+
+    #[test]
+    fn ranges() {
+        let range = "192.0.2.0/24".parse::<IpRange>().unwrap();
+        assert!(range.contains("192.0.2.42".parse().unwrap()));
+        assert!(!range.contains("192.0.3.1".parse().unwrap()));
+        assert!(range.contains("::ffff:192.0.2.1".parse().unwrap()));
+        let range = "2001:db8::/33".parse::<IpRange>().unwrap();
+        assert!(range.contains("2001:db8:7fff::1".parse().unwrap()));
+        assert!(!range.contains("2001:db8:8000::1".parse().unwrap()));
+        assert!("192.0.2.0/33".parse::<IpRange>().is_err());
+        assert_eq!(
+            "198.51.100.7".parse::<IpRange>().unwrap(),
+            "198.51.100.7/32".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn record_and_purge() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let users = fill_database(conn);
+            let ip = "198.51.100.7".parse().ok();
+            IpRecord::check_registration(conn, ip)?;
+            IpRecord::record(
+                conn,
+                IpRecordKind::Registration,
+                ip,
+                Some(users[1].id),
+                None,
+            )?;
+            IpRecord::record(
+                conn,
+                IpRecordKind::Registration,
+                ip,
+                Some(users[2].id),
+                None,
+            )?;
+            assert_eq!(
+                IpRecord::same_address_as(conn, users[1].id)?,
+                vec![users[2].id]
+            );
+            assert_eq!(IpRecord::purge_expired(conn)?, 0);
+            assert_eq!(IpRecord::for_user(conn, users[1].id)?.len(), 1);
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,282 @@
+//! Versioned terms of service and privacy policy of the instance.
+//!
+//! Documents are never edited: publishing a new text creates a new version, that
+//! every user has to accept again. Until they do, `TermsAcceptance` sends their requests,
+//! from the site or with an API token, to `ROUTE` or `API_ROUTE`.
+
+use crate::{
+    api_tokens::ApiToken,
+    db_conn::DbPool,
+    safe_string::SafeString,
+    schema::{legal_acceptances, legal_documents},
+    sessions::Session,
+    users::User,
+    Connection, Error, Result,
+};
+use chrono::NaiveDateTime;
+use diesel::{self, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+use plume_common::utils::md_to_html;
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::{uri::Origin, Method},
+    request::{self, FromRequest},
+    Data, Outcome, Request, State,
+};
+
+/// Where the requests of users who have new terms to accept are sent.
+pub const ROUTE: &str = "/terms/pending";
+
+/// Where the API requests of users who have new terms to accept are sent.
+pub const API_ROUTE: &str = "/api/v1/me/terms/pending";
+
+/// Requests that are still accepted, to read and accept the terms, or to leave.
+const EXEMPTED: &[&str] = &[
+    "/terms",
+    "/terms/accept",
+    "/privacy",
+    "/logout",
+    "/api/v1/oauth2",
+    "/api/v1/me/terms",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DocumentKind {
+    Terms = 0,
+    Privacy = 1,
+}
+
+impl DocumentKind {
+    pub const ALL: [DocumentKind; 2] = [DocumentKind::Terms, DocumentKind::Privacy];
+
+    pub fn from_i32(kind: i32) -> Option<Self> {
+        match kind {
+            0 => Some(DocumentKind::Terms),
+            1 => Some(DocumentKind::Privacy),
+            _ => None,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "terms" => Some(DocumentKind::Terms),
+            "privacy" => Some(DocumentKind::Privacy),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DocumentKind::Terms => "terms",
+            DocumentKind::Privacy => "privacy",
+        }
+    }
+}
+
+#[derive(Clone, Queryable, Identifiable)]
+pub struct LegalDocument {
+    pub id: i32,
+    pub kind: i32,
+    pub version: i32,
+    pub content: String,
+    pub content_html: SafeString,
+    pub creation_date: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "legal_documents"]
+pub struct NewLegalDocument {
+    pub kind: i32,
+    pub version: i32,
+    pub content: String,
+    pub content_html: SafeString,
+}
+
+#[derive(Insertable)]
+#[table_name = "legal_acceptances"]
+struct NewLegalAcceptance {
+    user_id: i32,
+    document_id: i32,
+}
+
+impl LegalDocument {
+    insert!(legal_documents, NewLegalDocument);
+    get!(legal_documents);
+
+    pub fn kind(&self) -> DocumentKind {
+        DocumentKind::from_i32(self.kind).unwrap_or(DocumentKind::Terms)
+    }
+
+    /// The current version of a document, if one was published.
+    pub fn latest(conn: &Connection, kind: DocumentKind) -> Result<Option<Self>> {
+        legal_documents::table
+            .filter(legal_documents::kind.eq(kind as i32))
+            .order(legal_documents::version.desc())
+            .first(conn)
+            .optional()
+            .map_err(Error::from)
+    }
+
+    /// Publishes a new version of a document, unless its content didn't change.
+    pub fn publish(conn: &Connection, kind: DocumentKind, content: &str) -> Result<Self> {
+        let latest = Self::latest(conn, kind)?;
+        match latest {
+            Some(latest) if latest.content == content => Ok(latest),
+            _ => Self::insert(
+                conn,
+                NewLegalDocument {
+                    kind: kind as i32,
+                    version: latest.map(|l| l.version + 1).unwrap_or(1),
+                    content: content.to_owned(),
+                    content_html: SafeString::new(&md_to_html(content, None, false, None).0),
+                },
+            ),
+        }
+    }
+
+    /// Whether there are current documents that a user didn't accept yet.
+    pub fn has_pending(conn: &Connection, user_id: i32) -> Result<bool> {
+        let mut latest = vec![];
+        for kind in DocumentKind::ALL.iter() {
+            latest.extend(Self::latest(conn, *kind)?.map(|doc| doc.id));
+        }
+        if latest.is_empty() {
+            return Ok(false);
+        }
+        let accepted = legal_acceptances::table
+            .filter(legal_acceptances::user_id.eq(user_id))
+            .filter(legal_acceptances::document_id.eq_any(&latest))
+            .count()
+            .get_result::<i64>(conn)?;
+        Ok(accepted < latest.len() as i64)
+    }
+
+    /// The current documents that a user didn't accept yet.
+    pub fn pending_for(conn: &Connection, user: &User) -> Result<Vec<Self>> {
+        let mut pending = vec![];
+        for kind in DocumentKind::ALL.iter() {
+            if let Some(doc) = Self::latest(conn, *kind)? {
+                if !doc.is_accepted_by(conn, user)? {
+                    pending.push(doc);
+                }
+            }
+        }
+        Ok(pending)
+    }
+
+    pub fn is_accepted_by(&self, conn: &Connection, user: &User) -> Result<bool> {
+        diesel::dsl::select(diesel::dsl::exists(
+            legal_acceptances::table
+                .filter(legal_acceptances::document_id.eq(self.id))
+                .filter(legal_acceptances::user_id.eq(user.id)),
+        ))
+        .get_result(conn)
+        .map_err(Error::from)
+    }
+
+    /// Records that a user accepted this version of the document.
+    pub fn accept(&self, conn: &Connection, user: &User) -> Result<()> {
+        if self.is_accepted_by(conn, user)? {
+            return Ok(());
+        }
+        diesel::insert_into(legal_acceptances::table)
+            .values(NewLegalAcceptance {
+                user_id: user.id,
+                document_id: self.id,
+            })
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+}
+
+/// The address of a request sent to `ROUTE`, to go back to it once the terms are accepted.
+pub struct PendingFrom(pub String);
+
+impl<'a, 'r> FromRequest<'a, 'r> for PendingFrom {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        let from = request.local_cache(|| PendingFrom("/".to_owned()));
+        Outcome::Success(PendingFrom(from.0.clone()))
+    }
+}
+
+/// Sends the requests of users who have new terms to accept to `ROUTE`, or to `API_ROUTE`
+/// if they come with an API token.
+pub struct TermsAcceptance;
+
+impl Fairing for TermsAcceptance {
+    fn info(&self) -> Info {
+        Info {
+            name: "Terms acceptance",
+            kind: Kind::Request,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request<'_>, _: &Data) {
+        let path = request.uri().path();
+        if EXEMPTED.contains(&path) || path.starts_with("/static/") {
+            return;
+        }
+        let (user_id, api) = match request.guard::<ApiToken>().succeeded() {
+            Some(token) => (token.user_id, true),
+            None => match request.guard::<Session>().succeeded() {
+                Some(session) => (session.user_id, false),
+                None => return,
+            },
+        };
+        let pending = match request.guard::<State<'_, DbPool>>() {
+            Outcome::Success(pool) => match pool.get() {
+                Ok(conn) => LegalDocument::has_pending(&conn, user_id).unwrap_or(false),
+                Err(_) => false,
+            },
+            _ => false,
+        };
+        if pending {
+            // Only pages can be gone back to
+            let from = if request.method() == Method::Get {
+                request.uri().to_string()
+            } else {
+                "/".to_owned()
+            };
+            request.local_cache(|| PendingFrom(from));
+            request.set_method(Method::Get);
+            let route = if api { API_ROUTE } else { ROUTE };
+            request.set_uri(Origin::parse(route).expect("legal_documents: invalid route"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tests::db, users::tests::fill_database};
+    use diesel::Connection;
+
+    #[test]
+    fn versions_and_acceptance() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let users = fill_database(conn);
+            assert!(LegalDocument::pending_for(conn, &users[0])?.is_empty());
+
+            let terms = LegalDocument::publish(conn, DocumentKind::Terms, "Be *nice*")?;
+            assert_eq!(terms.version, 1);
+            assert!(terms.content_html.get().contains("<em>nice</em>"));
+            assert_eq!(
+                LegalDocument::publish(conn, DocumentKind::Terms, "Be *nice*")?.id,
+                terms.id
+            );
+            assert_eq!(LegalDocument::pending_for(conn, &users[0])?.len(), 1);
+            terms.accept(conn, &users[0])?;
+            assert!(LegalDocument::pending_for(conn, &users[0])?.is_empty());
+            assert!(!LegalDocument::has_pending(conn, users[0].id)?);
+
+            let terms = LegalDocument::publish(conn, DocumentKind::Terms, "Be very nice")?;
+            assert_eq!(terms.version, 2);
+            assert_eq!(LegalDocument::pending_for(conn, &users[0])?[0].id, terms.id);
+            assert!(LegalDocument::has_pending(conn, users[0].id)?);
+            Ok(())
+        });
+    }
+}
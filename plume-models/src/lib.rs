@@ -17,6 +17,7 @@ extern crate serde_json;
 extern crate tantivy;
 
 use activitystreams::iri_string;
+use comments::CommentEvent;
 pub use lettre;
 pub use lettre::smtp;
 use once_cell::sync::Lazy;
@@ -49,6 +50,9 @@ pub(crate) static USER_CHAN: Lazy<ChannelRef<UserEvent>> =
 pub(crate) static POST_CHAN: Lazy<ChannelRef<PostEvent>> =
     Lazy::new(|| channel("post_events", &*ACTOR_SYS).expect("Failed to create post channel"));
 
+pub(crate) static COMMENT_CHAN: Lazy<ChannelRef<CommentEvent>> =
+    Lazy::new(|| channel("comment_events", &*ACTOR_SYS).expect("Failed to create comment channel"));
+
 /// All the possible errors that can be encoutered in this crate
 #[derive(Debug)]
 pub enum Error {
@@ -69,6 +73,8 @@ pub enum Error {
     Webfinger,
     Expired,
     UserAlreadyExists,
+    /// The password doesn't follow the password policy
+    WeakPassword(Vec<password_policy::PasswordProblem>),
     #[cfg(feature = "s3")]
     S3(s3::error::S3Error),
 }
@@ -166,6 +172,12 @@ impl From<InboxError<Error>> for Error {
     }
 }
 
+impl From<image::ImageError> for Error {
+    fn from(_: image::ImageError) -> Self {
+        Error::InvalidValue
+    }
+}
+
 impl From<request::Error> for Error {
     fn from(_err: request::Error) -> Error {
         Error::Request
@@ -181,6 +193,13 @@ impl From<s3::error::S3Error> for Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Opens a span for a database query, that is closed when it is dropped.
+macro_rules! db_span {
+    ($table:ident, $operation:expr) => {
+        tracing::info_span!("db", table = stringify!($table), operation = $operation).entered()
+    };
+}
+
 /// Adds a function to a model, that returns the first
 /// matching row for a given list of fields.
 ///
@@ -198,6 +217,7 @@ macro_rules! find_by {
     ($table:ident, $fn:ident, $($col:ident as $type:ty),+) => {
         /// Try to find a $table with a given $col
         pub fn $fn(conn: &crate::Connection, $($col: $type),+) -> Result<Self> {
+            let _span = db_span!($table, stringify!($fn));
             $table::table
                 $(.filter($table::$col.eq($col)))+
                 .first(conn)
@@ -222,6 +242,7 @@ macro_rules! list_by {
     ($table:ident, $fn:ident, $($col:ident as $type:ty),+) => {
         /// Try to find a $table with a given $col
         pub fn $fn(conn: &crate::Connection, $($col: $type),+) -> Result<Vec<Self>> {
+            let _span = db_span!($table, stringify!($fn));
             $table::table
                 $(.filter($table::$col.eq($col)))+
                 .load::<Self>(conn)
@@ -245,6 +266,7 @@ macro_rules! list_by {
 macro_rules! get {
     ($table:ident) => {
         pub fn get(conn: &crate::Connection, id: i32) -> Result<Self> {
+            let _span = db_span!($table, "get");
             $table::table
                 .filter($table::id.eq(id))
                 .first(conn)
@@ -274,6 +296,7 @@ macro_rules! insert {
 
         #[allow(dead_code)]
         pub fn insert(conn: &crate::Connection, new: $from) -> Result<Self> {
+            let _span = db_span!($table, "insert");
             diesel::insert_into($table::table)
                 .values(new)
                 .execute(conn)?;
@@ -301,6 +324,7 @@ macro_rules! last {
     ($table:ident) => {
         #[allow(dead_code)]
         pub fn last(conn: &crate::Connection) -> Result<Self> {
+            let _span = db_span!($table, "last");
             $table::table
                 .order_by($table::id.desc())
                 .first(conn)
@@ -309,6 +333,52 @@ macro_rules! last {
     };
 }
 
+/// Restricts a boxed query to the page given by a `Cursor`.
+///
+/// The rows are sorted by ID, but in ascending order when `min_id` is set: pass them
+/// to `Cursor::finish` to always get the most recent first.
+///
+/// # Usage
+///
+/// ```ignore
+/// let query = paginate!(model_table::table.into_boxed(), model_table, cursor);
+/// let page = cursor.finish(query.load::<Model>(conn)?);
+/// ```
+macro_rules! paginate {
+    ($query:expr, $table:ident, $cursor:expr) => {{
+        let cursor: crate::Cursor = $cursor;
+        let mut query = $query;
+        if let Some(max_id) = cursor.max_id {
+            query = query.filter($table::id.lt(max_id));
+        }
+        if let Some(min_id) = cursor.min_id {
+            query = query.filter($table::id.gt(min_id)).order($table::id.asc());
+        } else {
+            query = query.order($table::id.desc());
+        }
+        query.limit(cursor.limit)
+    }};
+}
+
+/// A page of a list sorted by ID, as API clients ask for it: the `limit` rows just
+/// before `max_id`, or just after `min_id`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cursor {
+    pub max_id: Option<i32>,
+    pub min_id: Option<i32>,
+    pub limit: i64,
+}
+
+impl Cursor {
+    /// Sorts a page loaded with `paginate!`, the most recent first.
+    pub fn finish<T>(self, mut page: Vec<T>) -> Vec<T> {
+        if self.min_id.is_some() {
+            page.reverse();
+        }
+        page
+    }
+}
+
 mod config;
 pub use config::CONFIG;
 
@@ -409,38 +479,107 @@ mod tests {
     }
 }
 
+pub mod about;
+pub mod account_notes;
 pub mod admin;
+pub mod announcements;
 pub mod api_tokens;
 pub mod apps;
+pub mod audio;
+pub mod autocomplete;
+pub mod backup;
 pub mod blocklisted_emails;
+pub mod blocklists;
 pub mod blog_authors;
+pub mod blog_transfer;
 pub mod blogs;
+pub mod cache;
+pub mod categories;
+pub mod comment_likes;
 pub mod comment_seers;
 pub mod comments;
+pub mod connectors;
+pub mod crossposts;
 pub mod db_conn;
+pub mod directory;
+pub mod email_changes;
 pub mod email_signups;
+pub mod failed_activities;
+pub mod failed_deliveries;
+pub mod failed_logins;
+pub mod federation_digests;
 pub mod follows;
+pub mod fundings;
+pub mod galleries;
+pub mod groups;
+pub mod guest_comments;
+pub mod hashtag_follows;
 pub mod headers;
+pub mod image_cleanup;
+pub mod import;
+pub mod inactivity;
 pub mod inbox;
 pub mod instance;
+pub mod ip_records;
+pub mod keys;
+pub mod legal_documents;
 pub mod likes;
 pub mod lists;
+pub mod lookup;
+pub mod maintenance;
+pub mod media_dedup;
+pub mod media_gc;
+pub mod media_policies;
+pub mod media_proxy;
+pub mod media_scan;
+pub mod media_variants;
 pub mod medias;
 pub mod mentions;
 pub mod migrations;
+pub mod mutes;
+pub mod neighborhood;
+pub mod notification_policies;
 pub mod notifications;
+pub mod opml;
+pub mod outgoing;
+pub mod password_policy;
 pub mod password_reset_requests;
+pub mod personal_data;
 pub mod plume_rocket;
+pub mod post_attachments;
 pub mod post_authors;
+pub mod post_reviews;
+pub mod post_translations;
+pub mod post_views;
 pub mod posts;
+pub mod probe;
+pub mod profile_fields;
+pub mod quotes;
+pub mod rate_limits;
+pub mod recommendations;
+pub mod related_posts;
+pub mod relays;
 pub mod remote_fetch_actor;
+pub mod request_ids;
+pub mod request_limits;
 pub mod reshares;
 pub mod safe_string;
 #[allow(unused_imports)]
 pub mod schema;
 pub mod search;
+pub mod series;
+pub mod sessions;
+pub mod severed_relationships;
+pub mod signature_pool;
 pub mod signups;
+pub mod spam;
+pub mod static_export;
+pub mod svg;
+pub mod tag_aliases;
 pub mod tags;
+pub mod thread_subscriptions;
 pub mod timeline;
+pub mod trends;
 pub mod users;
+pub mod worker;
 pub use plume_rocket::PlumeRocket;
@@ -55,15 +55,21 @@ impl Like {
 
     pub fn notify(&self, conn: &Connection) -> Result<()> {
         let post = Post::get(conn, self.post_id)?;
+        let actor = User::get(conn, self.user_id)?;
         for author in post.get_authors(conn)? {
             if author.is_local() {
-                Notification::insert(
+                Notification::notify_from(
                     conn,
+                    &actor,
+                    "",
                     NewNotification {
                         kind: notification_kind::LIKE.to_string(),
                         object_id: self.id,
                         user_id: author.id,
+                        group_key: None,
+                        filtered: false,
                     },
+                    Some(self.post_id),
                 )?;
             }
         }
@@ -162,10 +168,7 @@ impl AsObject<User, Undo, &Connection> for Like {
         if actor.id == self.user_id {
             diesel::delete(&self).execute(conn)?;
 
-            // delete associated notification if any
-            if let Ok(notif) = Notification::find(conn, notification_kind::LIKE, self.id) {
-                diesel::delete(&notif).execute(conn)?;
-            }
+            Notification::retract(conn, notification_kind::LIKE, self.id, self.post_id)?;
             Ok(())
         } else {
             Err(Error::Unauthorized)
@@ -0,0 +1,256 @@
+//! Finding accounts, blogs and hashtags by name, and remote content by its address.
+//!
+//! Articles are searched with the full-text index (see `search`): this module covers
+//! everything else the search box can find.
+
+use crate::{
+    autocomplete::escape_like,
+    blogs::Blog,
+    comments::Comment,
+    instance::Instance,
+    posts::Post,
+    schema::{blogs, tags, users},
+    users::{Role, User},
+    Connection, Error, Result, CONFIG,
+};
+use diesel::{
+    BoolExpressionMethods, EscapeExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl,
+    TextExpressionMethods,
+};
+use plume_common::activity_pub::{inbox::FromId, request::get};
+use serde_json::Value;
+
+/// Something found from its URL, or its `name@instance` address.
+pub enum Resolved {
+    User(User),
+    Blog(Blog),
+    Post(Post),
+    Comment(Comment),
+}
+
+/// Local and known remote accounts whose name or address contains `q`.
+///
+/// Remote accounts that are not `discoverable` are only found with their full address.
+pub fn accounts(conn: &Connection, q: &str, limit: i64) -> Result<Vec<User>> {
+    let address = q.trim_start_matches('@');
+    let pattern = format!("%{}%", escape_like(address));
+    // TODO: use `ilike` instead of `like` for PostgreSQL
+    users::table
+        .filter(users::role.ne(Role::Instance as i32))
+        .filter(
+            users::instance_id
+                .eq(Instance::get_local()?.id)
+                .or(users::discoverable.eq(true))
+                .or(users::fqn.eq(address)),
+        )
+        .filter(
+            users::username
+                .like(&pattern)
+                .escape('\\')
+                .or(users::display_name.like(&pattern).escape('\\'))
+                .or(users::fqn.like(&pattern).escape('\\')),
+        )
+        .order(users::fqn.asc())
+        .limit(limit)
+        .load::<User>(conn)
+        .map_err(Error::from)
+}
+
+/// Local and known remote blogs whose title or address contains `q`.
+///
+/// Remote blogs that are not `discoverable` are only found with their full address.
+pub fn blogs(conn: &Connection, q: &str, limit: i64) -> Result<Vec<Blog>> {
+    let address = q.trim_start_matches('~');
+    let pattern = format!("%{}%", escape_like(address));
+    blogs::table
+        .filter(
+            blogs::instance_id
+                .eq(Instance::get_local()?.id)
+                .or(blogs::discoverable.eq(true))
+                .or(blogs::fqn.eq(address)),
+        )
+        .filter(
+            blogs::title
+                .like(&pattern)
+                .escape('\\')
+                .or(blogs::fqn.like(&pattern).escape('\\')),
+        )
+        .order(blogs::fqn.asc())
+        .limit(limit)
+        .load::<Blog>(conn)
+        .map_err(Error::from)
+}
+
+/// The hashtags starting with `q`, the most used first.
+pub fn hashtags(conn: &Connection, q: &str, limit: i64) -> Result<Vec<String>> {
+    let pattern = format!("{}%", escape_like(q.trim_start_matches('#')));
+    let mut found = tags::table
+        .filter(tags::is_hashtag.eq(true))
+        .filter(tags::tag.like(&pattern).escape('\\'))
+        .select(tags::tag)
+        .load::<String>(conn)?;
+    found.sort();
+    let mut counted: Vec<(String, usize)> = vec![];
+    for tag in found {
+        match counted.last_mut() {
+            Some((last, count)) if *last == tag => *count += 1,
+            _ => counted.push((tag, 1)),
+        }
+    }
+    counted.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+    Ok(counted
+        .into_iter()
+        .take(limit as usize)
+        .map(|(tag, _)| tag)
+        .collect())
+}
+
+/// If `q` looks like something `resolve` can find, rather than words to search for.
+pub fn is_address(q: &str) -> bool {
+    let q = q.trim();
+    if q.contains(char::is_whitespace) {
+        return false;
+    }
+    if q.starts_with("https://") || q.starts_with("http://") {
+        return true;
+    }
+    let address = q.trim_start_matches(|c| c == '@' || c == '~');
+    match address.split_once('@') {
+        Some((name, host)) => !name.is_empty() && host.contains('.') && !host.contains('@'),
+        None => false,
+    }
+}
+
+/// Finds what `target` points to, fetching it from its instance and saving it if it
+/// isn't known yet.
+///
+/// `target` can be the URL of an actor, an article or a comment, or the address of an
+/// account or a blog (`name@instance`).
+pub fn resolve(conn: &Connection, target: &str) -> Result<Resolved> {
+    let target = target.trim();
+    if !target.starts_with("https://") && !target.starts_with("http://") {
+        let address = target.trim_start_matches(|c| c == '@' || c == '~');
+        if !address.contains('@') {
+            return Err(Error::InvalidValue);
+        }
+        return User::find_by_fqn(conn, address)
+            .map(Resolved::User)
+            .or_else(|_| Blog::find_by_fqn(conn, address).map(Resolved::Blog));
+    }
+
+    // Known objects don't need to be fetched
+    if let Ok(user) = User::from_db(conn, target) {
+        return Ok(Resolved::User(user));
+    }
+    if let Ok(blog) = Blog::from_db(conn, target) {
+        return Ok(Resolved::Blog(blog));
+    }
+    if let Ok(post) = Post::from_db(conn, target) {
+        return Ok(Resolved::Post(post));
+    }
+    if let Ok(comment) = Comment::from_db(conn, target) {
+        return Ok(Resolved::Comment(comment));
+    }
+
+    // The first attempt fetches the object, the other ones try to read it as another type
+    let json = match fetch_as::<User>(conn, target, None) {
+        Ok(user) => return Ok(Resolved::User(user)),
+        Err(json) => json.ok_or(Error::NotFound)?,
+    };
+    if let Ok(blog) = fetch_as::<Blog>(conn, target, Some(&json)) {
+        return Ok(Resolved::Blog(blog));
+    }
+    if let Ok(post) = fetch_as::<Post>(conn, target, Some(&json)) {
+        return Ok(Resolved::Post(post));
+    }
+    fetch_as::<Comment>(conn, target, Some(&json))
+        .map(Resolved::Comment)
+        .map_err(|_| Error::NotFound)
+}
+
+/// The JSON document at `url`, fetched with a request signed by the instance, without
+/// saving anything.
+pub fn fetch(conn: &Connection, url: &str) -> Result<Value> {
+    let sender = Instance::get_local_instance_user_uncached(conn)?;
+    let res = get(url, &sender, CONFIG.proxy().cloned())?;
+    if !res.status().is_success() {
+        return Err(Error::NotFound);
+    }
+    res.json().map_err(Error::from)
+}
+
+/// Builds a `T` from `url`, or from its already fetched JSON.
+///
+/// On failure, the JSON of the object is returned if it could be fetched.
+fn fetch_as<T: FromId<Connection, Error = Error>>(
+    conn: &Connection,
+    url: &str,
+    json: Option<&Value>,
+) -> std::result::Result<T, Option<Value>> {
+    let object = match json {
+        Some(json) => Some(serde_json::from_value(json.clone()).map_err(|_| Some(json.clone()))?),
+        None => None,
+    };
+    T::from_id(conn, url, object, CONFIG.proxy())
+        .map_err(|(fetched, _)| fetched.or_else(|| json.cloned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{inbox::tests::fill_database, safe_string::SafeString, tests::db, users::NewUser};
+    use diesel::{Connection, SaveChangesDsl};
+
+    #[test]
+    fn find_by_name() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, users, blog_list) = fill_database(conn);
+            assert!(accounts(conn, "@admi", 10)?
+                .iter()
+                .any(|u| u.id == users[0].id));
+            assert!(blogs(conn, &blog_list[0].title, 10)?
+                .iter()
+                .any(|b| b.id == blog_list[0].id));
+            assert!(accounts(conn, "%", 10)?.is_empty());
+
+            let remote_instance = Instance::find_by_domain(conn, "1plu.me")?;
+            let mut remote = User::insert(
+                conn,
+                NewUser {
+                    username: "hidden".to_owned(),
+                    display_name: "Hidden".to_owned(),
+                    outbox_url: "https://1plu.me/@/hidden/outbox".to_owned(),
+                    inbox_url: "https://1plu.me/@/hidden/inbox".to_owned(),
+                    instance_id: remote_instance.id,
+                    ap_url: "https://1plu.me/@/hidden/".to_owned(),
+                    followers_endpoint: "https://1plu.me/@/hidden/followers".to_owned(),
+                    summary_html: SafeString::new(""),
+                    role: Role::Normal as i32,
+                    fqn: "hidden@1plu.me".to_owned(),
+                    ..NewUser::default()
+                },
+            )?;
+            assert!(accounts(conn, "hidde", 10)?.is_empty());
+            assert_eq!(accounts(conn, "@hidden@1plu.me", 10)?.len(), 1);
+            remote.discoverable = true;
+            let _: User = remote.save_changes(conn)?;
+            assert_eq!(accounts(conn, "hidde", 10)?.len(), 1);
+
+            match resolve(conn, &posts[0].ap_url)? {
+                Resolved::Post(post) => assert_eq!(post.id, posts[0].id),
+                _ => panic!("The article should be found"),
+            }
+            match resolve(conn, &users[1].ap_url)? {
+                Resolved::User(user) => assert_eq!(user.id, users[1].id),
+                _ => panic!("The account should be found"),
+            }
+            assert!(resolve(conn, "not an address").is_err());
+            assert!(is_address("@user@plu.me"));
+            assert!(is_address("https://plu.me/~/Blog/article"));
+            assert!(!is_address("user@"));
+            assert!(!is_address("some words"));
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,188 @@
+//! A read-only mode, for backups and migrations.
+//!
+//! While the instance is in maintenance, pages can still be read, but requests that could
+//! change something (anything but `GET`, `HEAD` and `OPTIONS`) are answered with a
+//! `503 Service Unavailable` status and a `Retry-After` header, so that other instances
+//! deliver their activities again later. Scheduled jobs, the activities that were already
+//! received, deliveries and actors are paused too, and reading a page doesn't count a view or
+//! the activity of a session.
+//!
+//! Maintenance is started and stopped with `plm maintenance` or with the API, and is saved in
+//! the database so that all the processes of the instance see it.
+
+use crate::{db_conn::DbPool, schema::maintenance, Connection, Error, Result};
+use chrono::NaiveDateTime;
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+use once_cell::sync::Lazy;
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::{uri::Origin, Method},
+    Data, Request, State,
+};
+use std::{
+    sync::Mutex,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+/// Where the requests refused during maintenance are sent.
+pub const ROUTE: &str = "/maintenance";
+
+/// How long other instances and clients are asked to wait, in seconds.
+pub const RETRY_AFTER: u64 = 120;
+
+/// Requests that are still accepted, to stop the maintenance.
+const EXEMPTED: &[&str] = &["/api/v1/instance/maintenance"];
+
+/// How long other processes can take to notice a change.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Whether the instance was in maintenance, and when it was checked.
+static ACTIVE: Lazy<Mutex<Option<(Instant, bool)>>> = Lazy::new(|| Mutex::new(None));
+
+#[derive(Clone, Debug, Queryable, Identifiable)]
+#[table_name = "maintenance"]
+pub struct Maintenance {
+    pub id: i32,
+    /// Shown to visitors
+    pub reason: String,
+    pub start_date: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "maintenance"]
+struct NewMaintenance<'a> {
+    reason: &'a str,
+}
+
+impl Maintenance {
+    /// The current maintenance, if any.
+    pub fn current(conn: &Connection) -> Result<Option<Self>> {
+        maintenance::table
+            .order(maintenance::id.desc())
+            .first(conn)
+            .map(Some)
+            .or_else(|e| match e {
+                diesel::result::Error::NotFound => Ok(None),
+                e => Err(Error::from(e)),
+            })
+    }
+
+    /// Starts a maintenance, or changes the reason of the current one.
+    pub fn start(conn: &Connection, reason: &str) -> Result<Self> {
+        diesel::delete(maintenance::table).execute(conn)?;
+        diesel::insert_into(maintenance::table)
+            .values(NewMaintenance { reason })
+            .execute(conn)?;
+        Self::forget();
+        Self::current(conn)?.ok_or(Error::NotFound)
+    }
+
+    pub fn stop(conn: &Connection) -> Result<()> {
+        diesel::delete(maintenance::table).execute(conn)?;
+        Self::forget();
+        Ok(())
+    }
+
+    /// Whether the instance is in maintenance, as checked at most `CHECK_INTERVAL` ago.
+    ///
+    /// If the database can't be reached, the instance is not considered in maintenance.
+    pub fn is_active(conn: &Connection) -> bool {
+        let mut active = ACTIVE.lock().unwrap();
+        match *active {
+            Some((checked, value)) if checked.elapsed() < CHECK_INTERVAL => value,
+            _ => {
+                let value = Self::current(conn)
+                    .map_err(|e| warn!("Couldn't check for maintenance: {:?}", e))
+                    .ok()
+                    .flatten()
+                    .is_some();
+                *active = Some((Instant::now(), value));
+                value
+            }
+        }
+    }
+
+    /// Blocks until the maintenance ends, for background work that can't be refused and
+    /// delivered again later like requests.
+    pub fn wait_for_end(pool: &DbPool) {
+        while pool.get().map_or(false, |conn| Self::is_active(&conn)) {
+            sleep(CHECK_INTERVAL);
+        }
+    }
+
+    /// Makes the next check read the database again, after a change in this process.
+    fn forget() {
+        *ACTIVE.lock().unwrap() = None;
+    }
+}
+
+/// The path of a request refused during maintenance, before it was sent to `ROUTE`.
+pub struct RefusedPath(pub String);
+
+/// Whether a request could change something.
+fn writes(method: Method) -> bool {
+    !matches!(method, Method::Get | Method::Head | Method::Options)
+}
+
+/// Sends the requests that could change something to `ROUTE` during maintenance.
+pub struct MaintenanceMode;
+
+impl Fairing for MaintenanceMode {
+    fn info(&self) -> Info {
+        Info {
+            name: "Maintenance mode",
+            kind: Kind::Request,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request<'_>, _: &Data) {
+        if !writes(request.method()) || EXEMPTED.contains(&request.uri().path()) {
+            return;
+        }
+        let active = match request.guard::<State<'_, DbPool>>() {
+            rocket::Outcome::Success(pool) => match pool.get() {
+                Ok(conn) => Maintenance::is_active(&conn),
+                Err(_) => false,
+            },
+            _ => false,
+        };
+        if active {
+            let path = request.uri().path().to_owned();
+            request.local_cache(|| RefusedPath(path));
+            request.set_method(Method::Get);
+            request.set_uri(Origin::parse(ROUTE).expect("maintenance: invalid route"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::db;
+    use diesel::Connection;
+
+    #[test]
+    fn start_and_stop() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            assert!(Maintenance::current(conn)?.is_none());
+            Maintenance::start(conn, "Backup")?;
+            let current = Maintenance::start(conn, "Upgrade")?;
+            assert_eq!(current.reason, "Upgrade");
+            assert_eq!(maintenance::table.count().get_result::<i64>(&**conn)?, 1);
+            Maintenance::stop(conn)?;
+            assert!(Maintenance::current(conn)?.is_none());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn read_only_methods() {
+        assert!(!writes(Method::Get));
+        assert!(!writes(Method::Head));
+        assert!(writes(Method::Post));
+        assert!(writes(Method::Delete));
+    }
+}
@@ -0,0 +1,396 @@
+//! Storing each uploaded file only once.
+//!
+//! The SHA-256 hash of the files is saved with the media. When a user uploads a file they
+//! already have, their previous media is used instead, unless they describe it differently:
+//! the new media then uses the same file. With `MEDIA_DEDUPLICATION=instance`, files uploaded
+//! by other users are also reused: each user gets their own media, but they share the same
+//! file, that is only removed with the last of them.
+//!
+//! Media uploaded before that, or with deduplication disabled, can be merged with `dedupe`
+//! (`plm media dedupe`).
+
+pub use crate::config::MediaDeduplication;
+use crate::{
+    media_scan::ScanStatus,
+    media_variants,
+    medias::{self, Media},
+    schema::{comments, medias as medias_table, posts},
+    Connection, Error, Result, CONFIG,
+};
+use diesel::{
+    self, BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl,
+};
+use openssl::sha::sha256;
+use std::{collections::BTreeMap, fs};
+use tracing::warn;
+
+/// The result of `dedupe`.
+#[derive(Debug, Default, PartialEq)]
+pub struct DedupeReport {
+    /// How many media now use the file of another one
+    pub merged: usize,
+    /// How many bytes were freed, not counting files stored on S3
+    pub reclaimed: u64,
+}
+
+/// The hash of the content of a file, as an hexadecimal string.
+pub fn content_hash(bytes: &[u8]) -> String {
+    sha256(bytes)
+        .iter()
+        .fold(String::new(), |res, byte| format!("{}{:02x}", res, byte))
+}
+
+/// A media with the same content that can be reused for an upload of `owner_id`, following
+/// `MEDIA_DEDUPLICATION`.
+///
+/// Media of the same owner come first.
+pub fn find_duplicate(conn: &Connection, hash: &str, owner_id: i32) -> Result<Option<Media>> {
+    find_duplicate_in(conn, hash, owner_id, CONFIG.media_deduplication)
+}
+
+fn find_duplicate_in(
+    conn: &Connection,
+    hash: &str,
+    owner_id: i32,
+    scope: MediaDeduplication,
+) -> Result<Option<Media>> {
+    let query = medias_table::table
+        .filter(medias_table::content_hash.eq(hash))
+        .filter(medias_table::is_remote.eq(false))
+        .filter(medias_table::scan_status.eq(ScanStatus::Clean as i32))
+        .order(medias_table::id.asc());
+    let own = query
+        .filter(medias_table::owner_id.eq(owner_id))
+        .first::<Media>(conn)
+        .optional()?;
+    Ok(match scope {
+        MediaDeduplication::Off => None,
+        MediaDeduplication::Owner => own,
+        MediaDeduplication::Instance => match own {
+            Some(media) => Some(media),
+            None => query.first::<Media>(conn).optional()?,
+        },
+    })
+}
+
+pub fn set_content_hash(conn: &Connection, media: &Media, hash: &str) -> Result<Media> {
+    diesel::update(media)
+        .set(medias_table::content_hash.eq(hash))
+        .execute(conn)?;
+    Media::get(conn, media.id)
+}
+
+/// Makes `media` use the file (and variants) of `original`, that have the same content.
+pub fn share(conn: &Connection, original: &Media, media: &Media) -> Result<Media> {
+    diesel::update(media)
+        .set((
+            medias_table::file_path.eq(&original.file_path),
+            medias_table::variants.eq(&original.variants),
+            medias_table::content_hash.eq(&original.content_hash),
+        ))
+        .execute(conn)?;
+    Media::get(conn, media.id)
+}
+
+/// Whether another media uses the same file as this one.
+pub fn is_shared(conn: &Connection, media: &Media) -> Result<bool> {
+    if media.is_remote || media.file_path.is_empty() {
+        return Ok(false);
+    }
+    let others: i64 = medias_table::table
+        .filter(medias_table::file_path.eq(&media.file_path))
+        .filter(medias_table::id.ne(media.id))
+        .count()
+        .get_result(conn)?;
+    Ok(others > 0)
+}
+
+/// Merges the local media that have the same content, and removes the files that are not
+/// used anymore.
+///
+/// The files of media that were never hashed are read first. The oldest media of each group
+/// keeps its file, and the others are updated to use it: the media themselves are not
+/// removed, and the drafts showing them are updated with their new URL. Media shown in
+/// published articles or in comments are left as they are, as the copies of these on other
+/// instances would lose them.
+pub fn dedupe(conn: &Connection, scope: MediaDeduplication) -> Result<DedupeReport> {
+    let mut report = DedupeReport::default();
+    if scope == MediaDeduplication::Off {
+        return Ok(report);
+    }
+
+    let unhashed = medias_table::table
+        .filter(medias_table::is_remote.eq(false))
+        .filter(medias_table::content_hash.is_null())
+        .filter(medias_table::file_path.ne(""))
+        .load::<Media>(conn)?;
+    for media in unhashed {
+        match media.read() {
+            Ok(bytes) => {
+                set_content_hash(conn, &media, &content_hash(&bytes))?;
+            }
+            Err(err) => warn!("Couldn't read media {}: {:?}", media.id, err),
+        }
+    }
+
+    let hashed = medias_table::table
+        .filter(medias_table::is_remote.eq(false))
+        .filter(medias_table::content_hash.is_not_null())
+        .filter(medias_table::scan_status.eq(ScanStatus::Clean as i32))
+        .order(medias_table::id.asc())
+        .load::<Media>(conn)?;
+    let mut groups = BTreeMap::<_, Vec<Media>>::new();
+    for media in hashed {
+        let owner = match scope {
+            MediaDeduplication::Owner => Some(media.owner_id),
+            _ => None,
+        };
+        groups
+            .entry((media.content_hash.clone(), owner))
+            .or_default()
+            .push(media);
+    }
+
+    for mut group in groups.into_values() {
+        let kept = group.remove(0);
+        for duplicate in group {
+            if duplicate.file_path == kept.file_path {
+                continue;
+            }
+            let old_urls = urls(&duplicate);
+            if is_federated(conn, &old_urls)? {
+                continue;
+            }
+            rewrite_urls(conn, &old_urls, &duplicate, &kept)?;
+            share(conn, &kept, &duplicate)?;
+            report.merged += 1;
+
+            if !is_shared(conn, &duplicate)? {
+                report.reclaimed += files_size(&duplicate);
+                media_variants::remove_files(&duplicate);
+                if let Err(err) = medias::remove_file(&duplicate.file_path) {
+                    warn!(
+                        "Couldn't remove the file of media {}: {:?}",
+                        duplicate.id, err
+                    );
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// The size of the files of a media and of its variants, if they are stored locally.
+pub(crate) fn files_size(media: &Media) -> u64 {
+    if CONFIG.s3.is_some() {
+        return 0;
+    }
+    let size = |file_path: &str| fs::metadata(medias::local_path(file_path)).map_or(0, |m| m.len());
+    size(&media.file_path)
+        + media_variants::list(media)
+            .iter()
+            .filter(|v| v.file_path != media.file_path)
+            .map(|v| size(&v.file_path))
+            .sum::<u64>()
+}
+
+/// The URLs of the files of a media, and of its variants.
+fn urls(media: &Media) -> Vec<String> {
+    let mut urls = vec![medias::relative_url(&media.file_path)];
+    urls.extend(
+        media_variants::list(media)
+            .iter()
+            .map(|variant| medias::relative_url(&variant.file_path)),
+    );
+    urls
+}
+
+/// Whether one of `urls` is shown in a published article or in a comment, that other
+/// instances have a copy of.
+fn is_federated(conn: &Connection, urls: &[String]) -> Result<bool> {
+    for url in urls {
+        let pattern = format!("%{}%", url);
+        let posts: i64 = posts::table
+            .filter(posts::published.eq(true))
+            .filter(posts::content.like(&pattern))
+            .count()
+            .get_result(conn)?;
+        let comments: i64 = comments::table
+            .filter(comments::content.like(&pattern))
+            .count()
+            .get_result(conn)?;
+        if posts + comments > 0 {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Replaces the URLs of the files of `old` with the ones of `new` in the drafts that show
+/// them, in their HTML and in their Markdown source.
+fn rewrite_urls(conn: &Connection, old_urls: &[String], old: &Media, new: &Media) -> Result<()> {
+    let new_variants = media_variants::list(new);
+    let mut new_urls = vec![medias::relative_url(&new.file_path)];
+    for variant in media_variants::list(old) {
+        let replacement = new_variants
+            .iter()
+            .find(|v| v.size == variant.size && v.format == variant.format)
+            .map_or(&new.file_path, |v| &v.file_path);
+        new_urls.push(medias::relative_url(replacement));
+    }
+
+    for (old_url, new_url) in old_urls.iter().zip(new_urls) {
+        if *old_url == new_url {
+            continue;
+        }
+        let pattern = format!("%{}%", old_url);
+        let drafts = posts::table
+            .filter(posts::published.eq(false))
+            .filter(
+                posts::content
+                    .like(&pattern)
+                    .or(posts::source.like(&pattern)),
+            )
+            .select((posts::id, posts::content, posts::source))
+            .load::<(i32, String, String)>(conn)?;
+        for (id, content, source) in drafts {
+            diesel::update(posts::table.find(id))
+                .set((
+                    posts::content.eq(content.replace(old_url, &new_url)),
+                    posts::source.eq(source.replace(old_url, &new_url)),
+                ))
+                .execute(conn)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{medias::tests::fill_database, tests::db};
+    use diesel::Connection;
+
+    #[test]
+    fn hash() {
+        assert_eq!(
+            content_hash(b"Plume"),
+            "324b7ca1312182bf601625ff0bbf5b05c7303116a05065e8bd0a039e8ff79c2b"
+        );
+        assert_ne!(content_hash(b"Plume"), content_hash(b"plume"));
+    }
+
+    #[test]
+    fn find_duplicates() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (users, _) = fill_database(conn);
+            let media = Media::save_bytes(conn, b"same", "txt", String::new(), &users[0])?;
+            let hash = media.content_hash.clone().unwrap();
+
+            assert_eq!(
+                find_duplicate_in(conn, &hash, users[0].id, MediaDeduplication::Owner)?
+                    .map(|m| m.id),
+                Some(media.id)
+            );
+            assert!(
+                find_duplicate_in(conn, &hash, users[1].id, MediaDeduplication::Owner)?.is_none()
+            );
+            assert_eq!(
+                find_duplicate_in(conn, &hash, users[1].id, MediaDeduplication::Instance)?
+                    .map(|m| m.id),
+                Some(media.id)
+            );
+            assert!(
+                find_duplicate_in(conn, &hash, users[0].id, MediaDeduplication::Off)?.is_none()
+            );
+
+            medias::tests::clean(conn);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn merge_duplicates() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (users, _) = fill_database(conn);
+            // The empty files of the other media would be merged too
+            medias::tests::clean(conn);
+            let first = Media::save_bytes(conn, b"twice", "txt", String::new(), &users[0])?;
+            let second = Media::save_bytes(conn, b"twice", "txt", String::new(), &users[0])?;
+            let other = Media::save_bytes(conn, b"twice", "txt", String::new(), &users[1])?;
+            assert_ne!(first.file_path, second.file_path);
+
+            let report = dedupe(conn, MediaDeduplication::Owner)?;
+            assert_eq!(
+                report,
+                DedupeReport {
+                    merged: 1,
+                    reclaimed: 5
+                }
+            );
+            let second_merged = Media::get(conn, second.id)?;
+            assert_eq!(second_merged.file_path, first.file_path);
+            assert!(!medias::local_path(&second.file_path).exists());
+            assert_eq!(Media::get(conn, other.id)?.file_path, other.file_path);
+
+            let report = dedupe(conn, MediaDeduplication::Instance)?;
+            assert_eq!(report.merged, 1);
+            let other = Media::get(conn, other.id)?;
+            assert_eq!(other.file_path, first.file_path);
+
+            // The file is only removed with the last media using it
+            assert!(is_shared(conn, &first)?);
+            first.delete(conn)?;
+            second_merged.delete(conn)?;
+            assert!(medias::local_path(&other.file_path).exists());
+            other.delete(conn)?;
+            assert!(!medias::local_path(&other.file_path).exists());
+
+            medias::tests::clean(conn);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn keep_federated_media() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (articles, users, _) = crate::inbox::tests::fill_database(conn);
+            let first = Media::save_bytes(conn, b"shown", "txt", String::new(), &users[0])?;
+            let shown = Media::save_bytes(conn, b"shown", "txt", String::new(), &users[0])?;
+            let unused = Media::save_bytes(conn, b"shown", "txt", String::new(), &users[0])?;
+            let url = medias::relative_url(&shown.file_path);
+            diesel::update(posts::table.find(articles[0].id))
+                .set((
+                    posts::content.eq(format!("<img src=\"/{}\">", url)),
+                    posts::source.eq(format!("![](/{})", url)),
+                ))
+                .execute(conn)?;
+
+            // Other instances have a copy of the article
+            assert_eq!(dedupe(conn, MediaDeduplication::Owner)?.merged, 1);
+            assert_eq!(Media::get(conn, unused.id)?.file_path, first.file_path);
+            assert_eq!(Media::get(conn, shown.id)?.file_path, shown.file_path);
+            assert!(medias::local_path(&shown.file_path).exists());
+
+            // Drafts are rewritten, with their source
+            diesel::update(posts::table.find(articles[0].id))
+                .set(posts::published.eq(false))
+                .execute(conn)?;
+            assert_eq!(dedupe(conn, MediaDeduplication::Owner)?.merged, 1);
+            assert_eq!(Media::get(conn, shown.id)?.file_path, first.file_path);
+            let (content, source) = posts::table
+                .find(articles[0].id)
+                .select((posts::content, posts::source))
+                .first::<(String, String)>(conn)?;
+            let new_url = medias::relative_url(&first.file_path);
+            assert!(content.contains(&new_url) && !content.contains(&url));
+            assert!(source.contains(&new_url) && !source.contains(&url));
+
+            medias::tests::clean(conn);
+            Ok(())
+        });
+    }
+}
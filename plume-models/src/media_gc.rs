@@ -0,0 +1,196 @@
+//! Deleting the media that are not used anymore.
+//!
+//! A media is used when it is the avatar or the banner of an account, the icon or the banner
+//! of a blog, the cover of an article, or when it is shown in or attached to an article (even
+//! a draft), in a comment, in the description of an account, a blog or the instance. Recent
+//! media are always kept: they are often uploaded a while before the article showing them is
+//! written.
+
+use crate::{
+    media_dedup,
+    medias::{self, Media},
+    schema::{blogs, comments, instances, medias as medias_table, post_attachments, posts, users},
+    Connection, Result,
+};
+use chrono::{Duration, Utc};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use std::collections::HashSet;
+use tracing::warn;
+
+/// How many texts are read at once, looking for the media they show.
+const PAGE_SIZE: i64 = 500;
+
+/// The result of `collect`.
+#[derive(Debug, Default, PartialEq)]
+pub struct GcReport {
+    /// How many media were deleted
+    pub removed: usize,
+    /// How many bytes were freed, not counting files stored on S3
+    pub reclaimed: u64,
+}
+
+/// The media older than `grace` that are not used anywhere.
+pub fn orphans(conn: &Connection, grace: Duration) -> Result<Vec<Media>> {
+    let limit = Utc::now().naive_utc() - grace;
+    let used = referenced_ids(conn)?;
+    let mut orphans = medias_table::table
+        .filter(medias_table::creation_date.lt(limit))
+        .order(medias_table::id.asc())
+        .load::<Media>(conn)?
+        .into_iter()
+        .filter(|media| !used.contains(&media.id))
+        .map(|media| {
+            let mut urls = media.remote_url.iter().cloned().collect::<Vec<_>>();
+            if !media.file_path.is_empty() {
+                urls.push(medias::relative_url(&media.file_path));
+            }
+            // Images are inserted in Markdown as `![alt](id)`
+            urls.push(format!("]({})", media.id));
+            (media, urls)
+        })
+        .collect::<Vec<_>>();
+
+    // Each text that can show a media is read once, whatever the number of media
+    macro_rules! remove_shown {
+        ($table:ident, $column:ident) => {
+            let mut last = 0;
+            while !orphans.is_empty() {
+                let page = $table::table
+                    .filter($table::id.gt(last))
+                    .order($table::id.asc())
+                    .select(($table::id, $table::$column))
+                    .limit(PAGE_SIZE)
+                    .load::<(i32, String)>(conn)?;
+                for (_, text) in &page {
+                    orphans.retain(|(_, urls)| !urls.iter().any(|url| text.contains(url.as_str())));
+                }
+                match page.last() {
+                    Some((id, _)) => last = *id,
+                    None => break,
+                }
+            }
+        };
+    }
+    remove_shown!(posts, source);
+    remove_shown!(posts, content);
+    remove_shown!(comments, content);
+    remove_shown!(users, summary);
+    remove_shown!(users, summary_html);
+    remove_shown!(blogs, summary);
+    remove_shown!(blogs, summary_html);
+    remove_shown!(instances, short_description_html);
+    remove_shown!(instances, long_description_html);
+
+    Ok(orphans.into_iter().map(|(media, _)| media).collect())
+}
+
+/// Deletes the media older than `grace` that are not used anywhere, with their files.
+///
+/// With `dry_run`, nothing is deleted, but the report tells what would be.
+pub fn collect(conn: &Connection, grace: Duration, dry_run: bool) -> Result<GcReport> {
+    let mut report = GcReport::default();
+    for media in orphans(conn, grace)? {
+        let reclaimed = if media.is_remote || media_dedup::is_shared(conn, &media)? {
+            0
+        } else {
+            media_dedup::files_size(&media)
+        };
+        if !dry_run {
+            if let Err(err) = media.delete(conn) {
+                warn!("Couldn't delete media {}: {:?}", media.id, err);
+                continue;
+            }
+        }
+        report.removed += 1;
+        report.reclaimed += reclaimed;
+    }
+    Ok(report)
+}
+
+/// The media used as avatars, banners, icons, covers and attachments.
+fn referenced_ids(conn: &Connection) -> Result<HashSet<i32>> {
+    let mut ids = HashSet::new();
+    ids.extend(
+        users::table
+            .select(users::avatar_id)
+            .load::<Option<i32>>(conn)?
+            .into_iter()
+            .chain(
+                users::table
+                    .select(users::banner_id)
+                    .load::<Option<i32>>(conn)?,
+            )
+            .chain(
+                blogs::table
+                    .select(blogs::icon_id)
+                    .load::<Option<i32>>(conn)?,
+            )
+            .chain(
+                blogs::table
+                    .select(blogs::banner_id)
+                    .load::<Option<i32>>(conn)?,
+            )
+            .chain(
+                posts::table
+                    .select(posts::cover_id)
+                    .load::<Option<i32>>(conn)?,
+            )
+            .flatten(),
+    );
+    ids.extend(
+        post_attachments::table
+            .select(post_attachments::media_id)
+            .load::<i32>(conn)?,
+    );
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{medias::tests::fill_database, tests::db, Error};
+    use diesel::Connection;
+
+    #[test]
+    fn collect_orphans() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (users, medias) = fill_database(conn);
+            diesel::update(medias_table::table)
+                .set(medias_table::creation_date.eq(Utc::now().naive_utc() - Duration::days(30)))
+                .execute(conn)?;
+            users[0].set_avatar(conn, medias[0].id)?;
+            let recent = Media::save_bytes(conn, b"new", "txt", String::new(), &users[0])?;
+
+            let report = collect(conn, Duration::days(7), true)?;
+            assert_eq!(report.removed, 2);
+            assert!(Media::get(conn, medias[1].id).is_ok());
+
+            let orphans = orphans(conn, Duration::days(7))?
+                .into_iter()
+                .map(|m| m.id)
+                .collect::<Vec<_>>();
+            assert_eq!(orphans, vec![medias[1].id, medias[2].id]);
+
+            // Shown in a bio
+            diesel::update(&users[1])
+                .set(users::summary_html.eq("<p><img src=\"https://example.com/\"></p>"))
+                .execute(conn)?;
+            let orphans = super::orphans(conn, Duration::days(7))?
+                .into_iter()
+                .map(|m| m.id)
+                .collect::<Vec<_>>();
+            assert_eq!(orphans, vec![medias[1].id]);
+
+            assert_eq!(collect(conn, Duration::days(7), false)?.removed, 1);
+            assert!(Media::get(conn, medias[0].id).is_ok());
+            assert!(Media::get(conn, medias[1].id).is_err());
+            assert!(Media::get(conn, medias[2].id).is_ok());
+            assert!(Media::get(conn, recent.id).is_ok());
+            assert!(!medias::local_path(&medias[1].file_path).exists());
+
+            medias::tests::clean(conn);
+            Ok(())
+        });
+    }
+}
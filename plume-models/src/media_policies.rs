@@ -0,0 +1,501 @@
+//! How media from other instances are treated.
+//!
+//! A policy applies either to all the media of a domain (and of its subdomains), or, when it
+//! has no domain, to the remote media marked as sensitive. Sensitive media without a policy
+//! are blurred. Media that are rejected are not saved when they are received, and the ones
+//! that were already saved are not shown anymore. The images that are only linked from the
+//! content of remote articles and comments follow the policies of their domain too.
+//!
+//! Policies are checked for most media that are shown, so they are kept in the cache.
+
+use crate::{
+    cache::{self, Entry},
+    instance::Instance,
+    medias::Media,
+    schema::media_policies,
+    users::User,
+    Connection, Error, Result,
+};
+use chrono::NaiveDateTime;
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+use plume_common::utils::escape;
+use url::Url;
+
+/// What is done with a media, from the most lenient to the strictest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MediaAction {
+    /// Shown blurred, until the reader hovers or focuses it
+    Blur = 0,
+    /// Only loaded when the reader clicks a link to it
+    ClickThrough = 1,
+    /// Neither saved nor shown
+    Reject = 2,
+}
+
+impl MediaAction {
+    pub fn from_i32(action: i32) -> Option<Self> {
+        match action {
+            0 => Some(MediaAction::Blur),
+            1 => Some(MediaAction::ClickThrough),
+            2 => Some(MediaAction::Reject),
+            _ => None,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "blur" => Some(MediaAction::Blur),
+            "click_through" => Some(MediaAction::ClickThrough),
+            "reject" => Some(MediaAction::Reject),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MediaAction::Blur => "blur",
+            MediaAction::ClickThrough => "click_through",
+            MediaAction::Reject => "reject",
+        }
+    }
+}
+
+#[derive(Clone, Queryable, Identifiable)]
+#[table_name = "media_policies"]
+pub struct MediaPolicy {
+    pub id: i32,
+    /// The domain this policy applies to, or `None` for the media marked as sensitive
+    pub domain: Option<String>,
+    /// A `MediaAction`
+    pub action: i32,
+    pub creation_date: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "media_policies"]
+pub struct NewMediaPolicy {
+    pub domain: Option<String>,
+    pub action: i32,
+}
+
+impl MediaPolicy {
+    insert!(media_policies, NewMediaPolicy);
+    get!(media_policies);
+
+    /// The policy for sensitive media first, then the ones of each domain.
+    pub fn list(conn: &Connection) -> Result<Vec<Self>> {
+        media_policies::table
+            .order(media_policies::domain.asc())
+            .load::<Self>(conn)
+            .map(|mut policies: Vec<Self>| {
+                policies.sort_by_key(|p| p.domain.is_some());
+                policies
+            })
+            .map_err(Error::from)
+    }
+
+    pub fn action(&self) -> MediaAction {
+        MediaAction::from_i32(self.action).unwrap_or(MediaAction::Blur)
+    }
+
+    /// Sets the policy of `domain`, or of the sensitive media if it is `None`, replacing the
+    /// previous one.
+    ///
+    /// `domain` may also be given as a URL.
+    pub fn set(conn: &Connection, domain: Option<&str>, action: MediaAction) -> Result<Self> {
+        let domain = match domain.map(normalize_domain) {
+            Some(None) => return Err(Error::InvalidValue),
+            Some(Some(domain)) => Some(domain),
+            None => None,
+        };
+        let existing = match domain {
+            Some(ref domain) => media_policies::table
+                .filter(media_policies::domain.eq(domain))
+                .first::<Self>(conn),
+            None => media_policies::table
+                .filter(media_policies::domain.is_null())
+                .first::<Self>(conn),
+        };
+        let policy = match existing {
+            Ok(policy) => {
+                diesel::update(&policy)
+                    .set(media_policies::action.eq(action as i32))
+                    .execute(conn)?;
+                Self::get(conn, policy.id)
+            }
+            Err(_) => Self::insert(
+                conn,
+                NewMediaPolicy {
+                    domain,
+                    action: action as i32,
+                },
+            ),
+        };
+        forget_policies();
+        policy
+    }
+
+    pub fn delete(&self, conn: &Connection) -> Result<()> {
+        diesel::delete(self).execute(conn)?;
+        forget_policies();
+        Ok(())
+    }
+
+    /// The domains and the actions of all the policies, from the cache if possible.
+    fn cached(conn: &Connection) -> Result<Vec<(Option<String>, MediaAction)>> {
+        let policies = cache::get_or_insert(Entry::MediaPolicies, || {
+            media_policies::table
+                .select((media_policies::domain, media_policies::action))
+                .load::<(Option<String>, i32)>(conn)
+                .map_err(Error::from)
+        })?;
+        Ok(policies
+            .into_iter()
+            .map(|(domain, action)| {
+                (
+                    domain,
+                    MediaAction::from_i32(action).unwrap_or(MediaAction::Blur),
+                )
+            })
+            .collect())
+    }
+
+    /// What has to be done with media from any of `domains`, or `None` if they are shown
+    /// normally.
+    ///
+    /// The strictest policy of the domains wins. If none of them has one, the policy for
+    /// sensitive media applies to the ones that are marked as such.
+    pub fn resolve(
+        conn: &Connection,
+        domains: &[String],
+        sensitive: bool,
+    ) -> Result<Option<MediaAction>> {
+        Ok(resolve_in(&Self::cached(conn)?, domains, sensitive))
+    }
+
+    /// What has to be done with `media` when it is shown, or `None` if it is shown normally.
+    ///
+    /// Policies only apply to media from other instances.
+    pub fn for_media(conn: &Connection, media: &Media) -> Result<Option<MediaAction>> {
+        // Avatars are shown on most pages, so the common case should not need any query
+        let policies = Self::cached(conn)?;
+        if !media.sensitive && policies.iter().all(|(domain, _)| domain.is_none()) {
+            return Ok(None);
+        }
+        let owner = User::get(conn, media.owner_id)?;
+        let instance = owner.get_instance(conn)?;
+        if instance.id == Instance::get_local()?.id {
+            return Ok(None);
+        }
+        let mut domains = vec![instance.public_domain];
+        domains.extend(media.remote_url.as_deref().and_then(host_of));
+        Ok(resolve_in(&policies, &domains, media.sensitive))
+    }
+
+    /// Fails if a remote media at `url`, sent by `sender` (the URL of an actor), has to be
+    /// rejected.
+    pub fn check_remote(conn: &Connection, url: &str, sender: &str, sensitive: bool) -> Result<()> {
+        let domains = host_of(url)
+            .into_iter()
+            .chain(host_of(sender))
+            .collect::<Vec<_>>();
+        match Self::resolve(conn, &domains, sensitive)? {
+            Some(MediaAction::Reject) => Err(Error::Unauthorized),
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies the policies to the images of `html`, the content of the article or the
+    /// comment at `ap_url`, if it comes from another instance.
+    ///
+    /// Rejected images are removed, the ones that are only shown on click are replaced by a
+    /// link, and the others can be blurred.
+    pub fn apply_to_html(conn: &Connection, html: &str, ap_url: Option<&str>) -> String {
+        let sender = match ap_url.and_then(host_of) {
+            Some(sender) => sender,
+            None => return html.to_owned(),
+        };
+        let policies = match Self::cached(conn) {
+            Ok(policies) if policies.iter().any(|(domain, _)| domain.is_some()) => policies,
+            _ => return html.to_owned(),
+        };
+        if Instance::get_local().map_or(true, |local| local.public_domain == sender) {
+            return html.to_owned();
+        }
+        rewrite_images(html, |src| {
+            let domains = host_of(src)
+                .into_iter()
+                .chain(Some(sender.clone()))
+                .collect::<Vec<_>>();
+            resolve_in(&policies, &domains, false)
+        })
+    }
+}
+
+fn forget_policies() {
+    cache::forget(Entry::MediaPolicies);
+    // The pages of articles kept for anonymous readers show their images
+    cache::forget_articles();
+}
+
+fn resolve_in(
+    policies: &[(Option<String>, MediaAction)],
+    domains: &[String],
+    sensitive: bool,
+) -> Option<MediaAction> {
+    let by_domain = policies
+        .iter()
+        .filter_map(|(rule, action)| Some((rule.as_deref()?, *action)))
+        .filter(|(rule, _)| {
+            domains
+                .iter()
+                .any(|domain| domain == rule || domain.ends_with(&format!(".{}", rule)))
+        })
+        .map(|(_, action)| action)
+        .max();
+    if by_domain.is_some() || !sensitive {
+        return by_domain;
+    }
+    Some(
+        policies
+            .iter()
+            .find(|(rule, _)| rule.is_none())
+            .map_or(MediaAction::Blur, |(_, action)| *action),
+    )
+}
+
+/// Calls `action_for` with the address of each image of `html`, and applies what it returns.
+///
+/// `html` has to be sanitized: attributes are then always quoted, with `"`.
+fn rewrite_images(html: &str, action_for: impl Fn(&str) -> Option<MediaAction>) -> String {
+    let mut result = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find("<img") {
+        result.push_str(&rest[..start]);
+        let tag = &rest[start..];
+        if !tag[4..].starts_with(&[' ', '>', '/'][..]) {
+            result.push_str("<img");
+            rest = &tag[4..];
+            continue;
+        }
+        // Quoted values can contain a '>'
+        let mut quoted = false;
+        let end = tag
+            .char_indices()
+            .find(|&(_, c)| {
+                if c == '"' {
+                    quoted = !quoted;
+                }
+                c == '>' && !quoted
+            })
+            .map_or(tag.len(), |(i, _)| i + 1);
+        let (tag, after) = tag.split_at(end);
+        rest = after;
+
+        let src = attribute(tag, "src");
+        match src.as_deref().and_then(&action_for) {
+            None => result.push_str(tag),
+            Some(MediaAction::Blur) => {
+                result.push_str(&tag.replacen("<img", "<img class=\"blurred\"", 1))
+            }
+            Some(MediaAction::ClickThrough) => {
+                let src = src.unwrap_or_default();
+                let text = attribute(tag, "alt")
+                    .filter(|alt| !alt.trim().is_empty())
+                    .unwrap_or_else(|| src.clone());
+                result.push_str(&format!(
+                    "<a href=\"{}\">{}</a>",
+                    escape(&src),
+                    escape(&text)
+                ));
+            }
+            Some(MediaAction::Reject) => {}
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// The unescaped value of the attribute `name` of a sanitized `tag`.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let start = tag.find(&format!(" {}=\"", name))? + name.len() + 3;
+    let end = start + tag[start..].find('"')?;
+    Some(
+        tag[start..end]
+            .replace("&quot;", "\"")
+            .replace("&nbsp;", "\u{a0}")
+            .replace("&amp;", "&"),
+    )
+}
+
+fn host_of(url: &str) -> Option<String> {
+    Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_lowercase))
+}
+
+fn normalize_domain(domain: &str) -> Option<String> {
+    let domain = domain.trim();
+    let domain = if domain.contains("://") {
+        host_of(domain)?
+    } else {
+        domain.trim_end_matches('/').to_lowercase()
+    };
+    if domain.is_empty() || domain.contains(&['/', ' ', '@'][..]) {
+        None
+    } else {
+        Some(domain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        safe_string::SafeString,
+        tests::db,
+        users::{tests::fill_database, NewUser, Role},
+    };
+    use diesel::Connection;
+
+    #[test]
+    fn resolve() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let domains = |d: &str| vec![d.to_owned()];
+            assert_eq!(
+                MediaPolicy::resolve(conn, &domains("media.example"), false)?,
+                None
+            );
+            assert_eq!(
+                MediaPolicy::resolve(conn, &domains("media.example"), true)?,
+                Some(MediaAction::Blur)
+            );
+
+            MediaPolicy::set(conn, None, MediaAction::ClickThrough)?;
+            MediaPolicy::set(conn, Some("https://Example.org/"), MediaAction::Blur)?;
+            MediaPolicy::set(conn, Some("example.org"), MediaAction::Reject)?;
+            assert_eq!(MediaPolicy::list(conn)?.len(), 2);
+            assert!(MediaPolicy::set(conn, Some(" "), MediaAction::Reject).is_err());
+
+            assert_eq!(
+                MediaPolicy::resolve(conn, &domains("media.example"), true)?,
+                Some(MediaAction::ClickThrough)
+            );
+            assert_eq!(
+                MediaPolicy::resolve(conn, &domains("cdn.example.org"), false)?,
+                Some(MediaAction::Reject)
+            );
+            assert_eq!(
+                MediaPolicy::resolve(conn, &domains("notexample.org"), false)?,
+                None
+            );
+            let sender = "https://media.example/@/cat/";
+            assert!(
+                MediaPolicy::check_remote(conn, "https://example.org/cat.png", sender, false)
+                    .is_err()
+            );
+            assert!(
+                MediaPolicy::check_remote(conn, "https://media.example/cat.png", sender, true)
+                    .is_ok()
+            );
+            // The instance sending the media counts too
+            assert!(MediaPolicy::check_remote(
+                conn,
+                "https://media.example/cat.png",
+                "https://cdn.example.org/@/cat/",
+                false
+            )
+            .is_err());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn for_media() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let users = fill_database(conn);
+            let remote = User::insert(
+                conn,
+                NewUser {
+                    username: "painter".to_owned(),
+                    display_name: "Painter".to_owned(),
+                    outbox_url: "https://1plu.me/@/painter/outbox".to_owned(),
+                    inbox_url: "https://1plu.me/@/painter/inbox".to_owned(),
+                    instance_id: Instance::find_by_domain(conn, "1plu.me")?.id,
+                    ap_url: "https://1plu.me/@/painter/".to_owned(),
+                    followers_endpoint: "https://1plu.me/@/painter/followers".to_owned(),
+                    summary_html: SafeString::new(""),
+                    role: Role::Normal as i32,
+                    fqn: "painter@1plu.me".to_owned(),
+                    ..NewUser::default()
+                },
+            )?;
+            let local = Media::save_remote(conn, "https://1plu.me/a.png".to_owned(), &users[0])?;
+            let mut painting =
+                Media::save_remote(conn, "https://1plu.me/b.png".to_owned(), &remote)?;
+            assert_eq!(MediaPolicy::for_media(conn, &painting)?, None);
+            painting.sensitive = true;
+            assert_eq!(
+                MediaPolicy::for_media(conn, &painting)?,
+                Some(MediaAction::Blur)
+            );
+
+            MediaPolicy::set(conn, Some("1plu.me"), MediaAction::Reject)?;
+            assert_eq!(
+                MediaPolicy::for_media(conn, &painting)?,
+                Some(MediaAction::Reject)
+            );
+            // Media of local users are always shown
+            assert_eq!(MediaPolicy::for_media(conn, &local)?, None);
+            assert!(Media::save_remote(conn, "https://1plu.me/c.png".to_owned(), &remote).is_err());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn images_in_html() {
+        let html = r#"<p>a</p><img src="https://bad.example/a.png" alt="x > y"><img src="https://cats.example/b.png?a=1&amp;b=2"><img alt="" src="https://ok.example/c.png"><p>b</p>"#;
+        let rewritten = rewrite_images(html, |src| {
+            if src.contains("bad.example") {
+                Some(MediaAction::Reject)
+            } else if src.contains("cats.example") {
+                Some(MediaAction::ClickThrough)
+            } else {
+                None
+            }
+        });
+        assert_eq!(
+            rewritten,
+            r#"<p>a</p><a href="https://cats.example/b.png?a=1&amp;b=2">https://cats.example/b.png?a=1&amp;b=2</a><img alt="" src="https://ok.example/c.png"><p>b</p>"#
+        );
+        assert_eq!(
+            rewrite_images(r#"<img src="https://a.example/">"#, |_| Some(
+                MediaAction::Blur
+            )),
+            r#"<img class="blurred" src="https://a.example/">"#
+        );
+
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            MediaPolicy::set(conn, Some("bad.example"), MediaAction::Reject)?;
+            let html = r#"<img src="https://bad.example/a.png">"#;
+            assert_eq!(
+                MediaPolicy::apply_to_html(conn, html, Some("https://remote.example/~/b/a")),
+                ""
+            );
+            // Local content is not changed, and the instance sending the content counts too
+            assert_eq!(MediaPolicy::apply_to_html(conn, html, None), html);
+            assert_eq!(
+                MediaPolicy::apply_to_html(
+                    conn,
+                    r#"<img src="https://ok.example/a.png">"#,
+                    Some("https://bad.example/~/b/a")
+                ),
+                ""
+            );
+            Ok(())
+        });
+    }
+}
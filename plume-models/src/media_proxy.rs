@@ -0,0 +1,267 @@
+//! Remote avatars, icons, banners and covers, served from this instance.
+//!
+//! Browsers don't load them from other instances, that can't see who reads what, and
+//! they keep working when the instance they come from goes away. They are fetched in the
+//! background on their first request, only once when they are asked for many times at once,
+//! and kept in a cache of limited size: when it gets too large, the files that were fetched
+//! the longest time ago are removed first. Until the first copy is there, browsers are sent
+//! to the original.
+
+use crate::{
+    ap_url,
+    instance::Instance,
+    medias::Media,
+    outgoing::{self, Limits},
+    schema::{blogs, posts, users},
+    worker::Worker,
+    Connection, Error, Result, CONFIG,
+};
+use chrono::Duration;
+use diesel::{dsl, BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl};
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashSet,
+    fs::{self, DirBuilder},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+use tracing::warn;
+
+const CACHE_DIRECTORY: &str = "proxy";
+
+/// How long fetching a file can take, in seconds.
+const FETCH_TIMEOUT: u64 = 30;
+
+/// The types of files that can be proxied, and the extension they are stored with.
+///
+/// SVG is not allowed, as it could run scripts from the domain of the instance.
+const ALLOWED_TYPES: &[(&str, &str)] = &[
+    ("image/png", "png"),
+    ("image/jpeg", "jpg"),
+    ("image/gif", "gif"),
+    ("image/webp", "webp"),
+    ("image/avif", "avif"),
+];
+
+/// The medias being fetched.
+static FETCHING: Lazy<Mutex<HashSet<i32>>> = Lazy::new(Mutex::default);
+
+/// What the proxy has for a media.
+pub enum Proxied {
+    /// A local copy, that may be a bit old if a new one is being fetched
+    File(PathBuf),
+    /// The address of the original, while the first copy is being fetched
+    Fetching(String),
+}
+
+/// The URL at which a remote media is served by this instance.
+pub fn proxied_url(media: &Media) -> Result<String> {
+    Ok(ap_url(&format!(
+        "{}/medias/{}/proxy",
+        Instance::get_local()?.public_domain,
+        media.id
+    )))
+}
+
+/// The local copy of a remote media, that is fetched with `worker` if it isn't cached or is
+/// too old.
+///
+/// Only the medias of known remote accounts, blogs and articles are proxied.
+pub fn get(conn: &Connection, media: &Media, worker: &Worker) -> Result<Proxied> {
+    let url = media
+        .remote_url
+        .clone()
+        .filter(|_| media.is_remote && CONFIG.media_proxy.enabled)
+        .ok_or(Error::NotFound)?;
+    if !is_shown(conn, media.id)? {
+        return Err(Error::NotFound);
+    }
+    let dir = cache_directory();
+    let cached = find_cached(&dir, media.id);
+    let fresh = cached.as_deref().and_then(age).map_or(false, |age| {
+        age < Duration::days(CONFIG.media_proxy.max_age_days)
+    });
+    if !fresh {
+        if let Some(fetching) = Fetching::start(media.id) {
+            let remote = url.clone();
+            worker.execute(move || {
+                if let Err(err) = refresh(&dir, fetching.0, &remote) {
+                    warn!("Couldn't fetch proxied media {}: {:?}", fetching.0, err);
+                }
+            });
+        }
+    }
+    Ok(match cached {
+        Some(path) => Proxied::File(path),
+        None => Proxied::Fetching(url),
+    })
+}
+
+/// Whether a media is the avatar or banner of an account, the icon or banner of a blog, or
+/// the cover of an article.
+fn is_shown(conn: &Connection, media_id: i32) -> Result<bool> {
+    let user: bool = dsl::select(dsl::exists(
+        users::table.filter(
+            users::avatar_id
+                .eq(media_id)
+                .or(users::banner_id.eq(media_id)),
+        ),
+    ))
+    .get_result(conn)?;
+    let blog: bool = dsl::select(dsl::exists(
+        blogs::table.filter(
+            blogs::icon_id
+                .eq(media_id)
+                .or(blogs::banner_id.eq(media_id)),
+        ),
+    ))
+    .get_result(conn)?;
+    let post: bool = dsl::select(dsl::exists(
+        posts::table.filter(posts::cover_id.eq(media_id)),
+    ))
+    .get_result(conn)?;
+    Ok(user || blog || post)
+}
+
+/// Marks a media as being fetched, as long as it lives.
+struct Fetching(i32);
+
+impl Fetching {
+    /// `None` if the media is already being fetched.
+    fn start(media_id: i32) -> Option<Self> {
+        if FETCHING.lock().unwrap().insert(media_id) {
+            Some(Fetching(media_id))
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for Fetching {
+    fn drop(&mut self) {
+        FETCHING.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// Fetches a new copy of a media, replacing the previous one.
+fn refresh(dir: &Path, media_id: i32, url: &str) -> Result<PathBuf> {
+    let (bytes, ext) = download(url)?;
+    if !dir.is_dir() {
+        DirBuilder::new().recursive(true).create(&dir)?;
+    }
+    let path = dir.join(format!("{}.{}", media_id, ext));
+    // Requests being answered keep reading the old copy
+    let partial = dir.join(format!("{}.{}.part", media_id, ext));
+    fs::write(&partial, bytes)?;
+    if let Some(old) = find_cached(dir, media_id).filter(|old| *old != path) {
+        fs::remove_file(old)?;
+    }
+    fs::rename(&partial, &path)?;
+    if let Err(err) = evict(dir, CONFIG.media_proxy.cache_size) {
+        warn!("Couldn't clean the media proxy cache: {:?}", err);
+    }
+    Ok(path)
+}
+
+/// Removes the oldest files of the cache until it is smaller than `limit` bytes.
+///
+/// Returns how many files were removed.
+pub fn evict(dir: &Path, limit: u64) -> Result<usize> {
+    let mut files = fs::read_dir(dir)?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let metadata = entry.metadata().ok().filter(|m| m.is_file())?;
+            Some((entry.path(), metadata.modified().ok()?, metadata.len()))
+        })
+        .collect::<Vec<_>>();
+    files.sort_by_key(|(_, modified, _)| *modified);
+
+    let mut size = files.iter().map(|(_, _, len)| len).sum::<u64>();
+    let mut removed = 0;
+    for (path, _, len) in files {
+        if size <= limit {
+            break;
+        }
+        fs::remove_file(path)?;
+        size -= len;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+fn cache_directory() -> PathBuf {
+    Path::new(&CONFIG.media_directory).join(CACHE_DIRECTORY)
+}
+
+fn find_cached(dir: &Path, media_id: i32) -> Option<PathBuf> {
+    ALLOWED_TYPES
+        .iter()
+        .map(|(_, ext)| dir.join(format!("{}.{}", media_id, ext)))
+        .find(|path| path.is_file())
+}
+
+fn age(path: &Path) -> Option<Duration> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    let age = SystemTime::now().duration_since(modified).ok()?;
+    Duration::from_std(age).ok()
+}
+
+/// The extension to store a file with, if its type is allowed.
+fn extension_for(content_type: &str) -> Option<&'static str> {
+    let mime = content_type.split(';').next()?.trim().to_lowercase();
+    ALLOWED_TYPES
+        .iter()
+        .find(|(allowed, _)| *allowed == mime)
+        .map(|(_, ext)| *ext)
+}
+
+fn download(url: &str) -> Result<(Vec<u8>, &'static str)> {
+    let file = outgoing::get(
+        url,
+        &Limits::new(FETCH_TIMEOUT, CONFIG.media_proxy.max_size),
+    )?;
+    let ext = file
+        .content_type
+        .as_deref()
+        .and_then(extension_for)
+        .ok_or(Error::InvalidValue)?;
+    Ok((file.body, ext))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowed_types() {
+        assert_eq!(extension_for("image/png"), Some("png"));
+        assert_eq!(extension_for("IMAGE/JPEG; charset=binary"), Some("jpg"));
+        assert_eq!(extension_for("image/svg+xml"), None);
+        assert_eq!(extension_for("text/html"), None);
+    }
+
+    #[test]
+    fn eviction() {
+        let dir = std::env::temp_dir().join(format!("plume-proxy-{}", std::process::id()));
+        DirBuilder::new().recursive(true).create(&dir).unwrap();
+        for id in 1..=4 {
+            fs::write(dir.join(format!("{}.png", id)), [0u8; 100]).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        assert_eq!(evict(&dir, 500).unwrap(), 0);
+        assert_eq!(evict(&dir, 250).unwrap(), 2);
+        assert!(find_cached(&dir, 1).is_none());
+        assert!(find_cached(&dir, 4).is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fetched_once() {
+        let first = Fetching::start(-1);
+        assert!(first.is_some());
+        assert!(Fetching::start(-1).is_none());
+        drop(first);
+        assert!(Fetching::start(-1).is_some());
+    }
+}
@@ -0,0 +1,392 @@
+//! Checking uploaded files with an antivirus.
+//!
+//! When `MEDIA_SCAN_CLAMD` or `MEDIA_SCAN_COMMAND` is set, new uploads are first stored in
+//! the `pending` directory, that is not served, and only moved to their path in the media
+//! directory once the antivirus found nothing. This directory is always on the disk of the
+//! instance, even when media are stored on S3, so that the bucket never has files that were
+//! not scanned. Infected files stay there, the media is quarantined, and the admins of the
+//! instance are notified. Files that couldn't be scanned, because the antivirus was not
+//! available, are scanned again later (see `scan_pending`).
+
+use crate::{
+    config::MediaScanner,
+    instance::Instance,
+    medias::{self, Media},
+    notifications::{notification_kind, NewNotification, Notification},
+    schema::medias as medias_table,
+    Connection, Error, Result, CONFIG,
+};
+use chrono::{Duration, Utc};
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+use std::{
+    fs::{self, DirBuilder},
+    io::{Read, Write},
+    path::{self, Path, PathBuf},
+    process::{Command, Stdio},
+    thread, time,
+};
+use tracing::warn;
+
+/// Where files are kept until they are scanned, in the media directory.
+pub const PENDING_DIRECTORY: &str = "pending";
+
+/// How much of a file is sent to clamd at once.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// How long clamd can take to read or answer, before the file is scanned again later.
+const CLAMD_TIMEOUT: time::Duration = time::Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScanStatus {
+    /// Scanned and clean, or uploaded without an antivirus
+    Clean = 0,
+    /// Waiting to be scanned
+    Pending = 1,
+    /// The antivirus found something
+    Quarantined = 2,
+}
+
+impl ScanStatus {
+    pub fn from_i32(status: i32) -> Option<Self> {
+        match status {
+            0 => Some(ScanStatus::Clean),
+            1 => Some(ScanStatus::Pending),
+            2 => Some(ScanStatus::Quarantined),
+            _ => None,
+        }
+    }
+}
+
+impl Media {
+    pub fn scan_status(&self) -> ScanStatus {
+        ScanStatus::from_i32(self.scan_status).unwrap_or(ScanStatus::Pending)
+    }
+}
+
+/// What an antivirus thinks of a file.
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Clean,
+    /// With the name of what was found
+    Infected(String),
+}
+
+/// Whether new uploads have to be scanned.
+pub fn enabled() -> bool {
+    CONFIG.media_scanner.is_some()
+}
+
+/// Stores a new upload where it is not served, until it is scanned.
+///
+/// Returns the path where it will be published, that is saved with the media from the start.
+pub fn store_pending(bytes: &[u8], ext: &str) -> Result<String> {
+    let file_path = medias::new_file_path(ext);
+    let pending = pending_path(&file_path);
+    if let Some(dir) = pending.parent().filter(|dir| !dir.is_dir()) {
+        DirBuilder::new().recursive(true).create(dir)?;
+    }
+    fs::write(pending, bytes)?;
+    Ok(file_path)
+}
+
+/// Where the file that will be published at `file_path` is kept until then.
+fn pending_path(file_path: &str) -> PathBuf {
+    let name = file_path
+        .rsplit(|c| c == '/' || c == path::MAIN_SEPARATOR)
+        .next()
+        .unwrap_or_default();
+    Path::new(&CONFIG.media_directory)
+        .join(PENDING_DIRECTORY)
+        .join(name)
+}
+
+/// The content of a media that was not published yet.
+fn read_pending(media: &Media) -> Result<Vec<u8>> {
+    Ok(fs::read(pending_path(&media.file_path))?)
+}
+
+/// Removes the file of a media that was never published, when it is deleted.
+pub(crate) fn remove_pending(media: &Media) -> Result<()> {
+    Ok(fs::remove_file(pending_path(&media.file_path))?)
+}
+
+/// Marks a media stored with `store_pending` as waiting to be scanned.
+pub fn set_pending(conn: &Connection, media: &Media) -> Result<Media> {
+    diesel::update(media)
+        .set(medias_table::scan_status.eq(ScanStatus::Pending as i32))
+        .execute(conn)?;
+    Media::get(conn, media.id)
+}
+
+/// Scans a pending media: it is published if it is clean, and quarantined otherwise.
+///
+/// If the antivirus can't be used, the media stays pending.
+pub fn check(conn: &Connection, media: &Media) -> Result<Media> {
+    if media.scan_status() != ScanStatus::Pending {
+        return Ok(media.clone());
+    }
+    let bytes = read_pending(media)?;
+    let verdict = match CONFIG.media_scanner {
+        Some(ref scanner) => scan(scanner, &bytes)?,
+        None => Verdict::Clean,
+    };
+    match verdict {
+        Verdict::Clean => release_bytes(conn, media, &bytes),
+        Verdict::Infected(signature) => quarantine(conn, media, &signature),
+    }
+}
+
+/// Scans the media that are still pending, because the antivirus was not available when
+/// they were uploaded.
+///
+/// Returns the media that were published.
+pub fn scan_pending(conn: &Connection) -> Result<Vec<Media>> {
+    // The most recent ones are still being scanned after their upload
+    let before = Utc::now().naive_utc() - Duration::minutes(5);
+    let mut released = vec![];
+    for media in medias_table::table
+        .filter(medias_table::scan_status.eq(ScanStatus::Pending as i32))
+        .filter(medias_table::creation_date.lt(before))
+        .load::<Media>(conn)?
+    {
+        match check(conn, &media) {
+            Ok(media) if media.scan_status() == ScanStatus::Clean => released.push(media),
+            Ok(_) => {}
+            Err(err) => warn!("Couldn't scan media {}: {:?}", media.id, err),
+        }
+    }
+    Ok(released)
+}
+
+pub fn list_quarantined(conn: &Connection) -> Result<Vec<Media>> {
+    medias_table::table
+        .filter(medias_table::scan_status.eq(ScanStatus::Quarantined as i32))
+        .order(medias_table::id.asc())
+        .load::<Media>(conn)
+        .map_err(Error::from)
+}
+
+/// Publishes a media without scanning it again, for instance after a false positive.
+pub fn release(conn: &Connection, media: &Media) -> Result<Media> {
+    if media.scan_status() == ScanStatus::Clean {
+        return Ok(media.clone());
+    }
+    release_bytes(conn, media, &read_pending(media)?)
+}
+
+/// Moves the file to the path the media was given when it was uploaded, so that the links
+/// to it that were already written work once it is published.
+fn release_bytes(conn: &Connection, media: &Media, bytes: &[u8]) -> Result<Media> {
+    medias::store_at(&media.file_path, bytes)?;
+    diesel::update(media)
+        .set((
+            medias_table::scan_status.eq(ScanStatus::Clean as i32),
+            medias_table::scan_result.eq(None::<String>),
+        ))
+        .execute(conn)?;
+    if let Err(err) = remove_pending(media) {
+        warn!(
+            "Couldn't remove the pending file of media {}: {:?}",
+            media.id, err
+        );
+    }
+    Media::get(conn, media.id)
+}
+
+fn quarantine(conn: &Connection, media: &Media, signature: &str) -> Result<Media> {
+    warn!("Media {} is infected: {}", media.id, signature);
+    diesel::update(media)
+        .set((
+            medias_table::scan_status.eq(ScanStatus::Quarantined as i32),
+            medias_table::scan_result.eq(signature),
+        ))
+        .execute(conn)?;
+    for admin in Instance::get_local()?.admins(conn)? {
+        Notification::insert(
+            conn,
+            NewNotification {
+                user_id: admin.id,
+                kind: notification_kind::MEDIA_QUARANTINED.to_string(),
+                object_id: media.id,
+                group_key: None,
+                filtered: false,
+            },
+        )?;
+    }
+    Media::get(conn, media.id)
+}
+
+/// Sends `bytes` to the antivirus.
+pub fn scan(scanner: &MediaScanner, bytes: &[u8]) -> Result<Verdict> {
+    match scanner {
+        MediaScanner::Clamd(socket) => scan_with_clamd(socket, bytes),
+        MediaScanner::Command(command) => scan_with_command(command, bytes),
+    }
+}
+
+/// Uses the `INSTREAM` command of clamd.
+#[cfg(unix)]
+fn scan_with_clamd(socket: &std::path::Path, bytes: &[u8]) -> Result<Verdict> {
+    let mut stream = std::os::unix::net::UnixStream::connect(socket)?;
+    stream.set_read_timeout(Some(CLAMD_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLAMD_TIMEOUT))?;
+    stream.write_all(b"zINSTREAM\0")?;
+    for chunk in bytes.chunks(CHUNK_SIZE) {
+        stream.write_all(&(chunk.len() as u32).to_be_bytes())?;
+        stream.write_all(chunk)?;
+    }
+    stream.write_all(&0u32.to_be_bytes())?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    parse_clamd_response(&response)
+}
+
+#[cfg(not(unix))]
+fn scan_with_clamd(_socket: &std::path::Path, _bytes: &[u8]) -> Result<Verdict> {
+    Err(Error::InvalidValue)
+}
+
+/// Reads responses like `stream: OK` or `stream: Eicar-Signature FOUND`.
+fn parse_clamd_response(response: &str) -> Result<Verdict> {
+    let result = response
+        .trim_end_matches(|c: char| c == '\0' || c.is_whitespace())
+        .trim_start_matches("stream:")
+        .trim();
+    if result == "OK" {
+        Ok(Verdict::Clean)
+    } else if let Some(signature) = result.strip_suffix(" FOUND") {
+        Ok(Verdict::Infected(signature.to_owned()))
+    } else {
+        warn!("Unexpected response from clamd: {}", result);
+        Err(Error::InvalidValue)
+    }
+}
+
+fn scan_with_command(command: &[String], bytes: &[u8]) -> Result<Verdict> {
+    let (program, args) = command.split_first().ok_or(Error::InvalidValue)?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // Writing from another thread, as the scanner may fill its output before reading
+    // all of the file
+    let mut stdin = child.stdin.take().ok_or(Error::InvalidValue)?;
+    let input = bytes.to_vec();
+    let writer = thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output()?;
+    // The scanner may also exit without reading everything
+    if let Ok(Err(err)) = writer.join() {
+        warn!("The antivirus didn't read all of the file: {}", err);
+    }
+    match output.status.code() {
+        Some(0) => Ok(Verdict::Clean),
+        Some(1) => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let signature = stdout.lines().next().unwrap_or_default().trim();
+            Ok(Verdict::Infected(if signature.is_empty() {
+                "unknown".to_owned()
+            } else {
+                signature.to_owned()
+            }))
+        }
+        _ => Err(Error::InvalidValue),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{medias::tests::fill_database, tests::db};
+    use diesel::Connection;
+
+    #[test]
+    fn clamd_responses() {
+        assert_eq!(
+            parse_clamd_response("stream: OK\0").unwrap(),
+            Verdict::Clean
+        );
+        assert_eq!(
+            parse_clamd_response("stream: Eicar-Signature FOUND\0").unwrap(),
+            Verdict::Infected("Eicar-Signature".to_owned())
+        );
+        assert!(parse_clamd_response("INSTREAM size limit exceeded. ERROR\0").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn command() {
+        let grep = |pattern: &str| {
+            MediaScanner::Command(vec![
+                "grep".to_owned(),
+                "-vq".to_owned(),
+                pattern.to_owned(),
+            ])
+        };
+        // grep -v exits with 1 when every line matches
+        assert_eq!(scan(&grep("virus"), b"clean").unwrap(), Verdict::Clean);
+        assert!(matches!(
+            scan(&grep("virus"), b"virus").unwrap(),
+            Verdict::Infected(_)
+        ));
+        let missing = MediaScanner::Command(vec!["/nonexistent/scanner".to_owned()]);
+        assert!(scan(&missing, b"clean").is_err());
+    }
+
+    #[test]
+    fn release_and_quarantine() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (users, _) = fill_database(conn);
+            let pending = |bytes: &[u8]| -> Result<Media> {
+                let media = Media::insert(
+                    conn,
+                    medias::NewMedia {
+                        file_path: store_pending(bytes, "txt")?,
+                        alt_text: String::new(),
+                        is_remote: false,
+                        remote_url: None,
+                        sensitive: false,
+                        content_warning: None,
+                        owner_id: users[0].id,
+                    },
+                )?;
+                set_pending(conn, &media)
+            };
+
+            let media = pending(b"clean")?;
+            assert_eq!(media.scan_status(), ScanStatus::Pending);
+            assert!(pending_path(&media.file_path).exists());
+            assert!(!medias::local_path(&media.file_path).exists());
+            let released = release(conn, &media)?;
+            assert_eq!(released.scan_status(), ScanStatus::Clean);
+            // The path given on upload is kept
+            assert_eq!(released.file_path, media.file_path);
+            assert_eq!(released.read()?, b"clean");
+            assert!(!pending_path(&media.file_path).exists());
+
+            let media = pending(b"infected")?;
+            let media = quarantine(conn, &media, "Eicar-Signature")?;
+            assert_eq!(media.scan_status(), ScanStatus::Quarantined);
+            assert_eq!(media.scan_result.as_deref(), Some("Eicar-Signature"));
+            assert!(
+                Notification::find(conn, notification_kind::MEDIA_QUARANTINED, media.id)
+                    .map(|n| n.user_id == users[0].id)
+                    .unwrap_or(false)
+            );
+            assert_eq!(
+                list_quarantined(conn)?
+                    .into_iter()
+                    .map(|m| m.id)
+                    .collect::<Vec<_>>(),
+                vec![media.id]
+            );
+            // Already scanned
+            assert_eq!(check(conn, &media)?.scan_status(), ScanStatus::Quarantined);
+
+            medias::tests::clean(conn);
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,298 @@
+//! Smaller and lighter copies of uploaded images.
+//!
+//! Once an image is uploaded, it is resized to a few widths and encoded in WebP (and in
+//! AVIF, with the `avif` feature) in addition to its original format, so that browsers
+//! can pick the copy they need with `srcset`. The variants are described as JSON in the
+//! `variants` column of the media.
+
+use crate::{
+    image_cleanup,
+    medias::{self, Media},
+    schema::medias as medias_table,
+    Connection, Error, Result,
+};
+use diesel::{self, ExpressionMethods, RunQueryDsl};
+use image::{
+    codecs::webp::{WebPEncoder, WebPQuality},
+    imageops::FilterType,
+    ColorType, DynamicImage, ImageFormat, ImageOutputFormat,
+};
+use plume_common::utils::escape;
+use std::io::Cursor;
+use tracing::warn;
+
+/// The names and the maximum widths of the variants, in pixels.
+pub const SIZES: &[(&str, u32)] = &[("thumbnail", 320), ("medium", 960), ("full", 1920)];
+
+const QUALITY: u8 = 80;
+
+/// How wide images are displayed, at most: the width of an article.
+const SIZES_ATTRIBUTE: &str = "(max-width: 960px) 100vw, 960px";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Variant {
+    /// One of `SIZES`
+    pub size: String,
+    /// The extension of the file: `jpg`, `png`, `webp` or `avif`
+    pub format: String,
+    pub width: u32,
+    pub height: u32,
+    pub file_path: String,
+}
+
+impl Variant {
+    pub fn url(&self) -> Result<String> {
+        medias::public_url(&medias::relative_url(&self.file_path))
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        mime_type(&self.format)
+    }
+}
+
+fn mime_type(format: &str) -> &'static str {
+    match format {
+        "png" => "image/png",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        _ => "image/jpeg",
+    }
+}
+
+/// The format of the original file, if variants can be generated from it.
+fn original_format(media: &Media) -> Option<(ImageFormat, &'static str)> {
+    let ext = media.file_path.rsplit_once('.')?.1.to_lowercase();
+    match &*ext {
+        "png" => Some((ImageFormat::Png, "png")),
+        "jpg" | "jpeg" => Some((ImageFormat::Jpeg, "jpg")),
+        _ => None,
+    }
+}
+
+/// The variants of a media, if they were generated.
+pub fn list(media: &Media) -> Vec<Variant> {
+    media
+        .variants
+        .as_deref()
+        .and_then(|json| serde_json::from_str(json).ok())
+        .unwrap_or_default()
+}
+
+/// A `srcset` attribute listing the variants of a media in one format.
+pub fn srcset(media: &Media, format: &str) -> Option<String> {
+    let sources = list(media)
+        .into_iter()
+        .filter(|v| v.format == format)
+        .map(|v| Some(format!("{} {}w", v.url().ok()?, v.width)))
+        .collect::<Option<Vec<_>>>()?;
+    if sources.is_empty() {
+        None
+    } else {
+        Some(sources.join(", "))
+    }
+}
+
+/// A `<picture>` showing the best variant of a media for the browser, or `None` if it
+/// has no variants.
+pub fn picture(media: &Media) -> Option<String> {
+    let (_, original) = original_format(media)?;
+    let url = media.url().ok()?;
+    let alt = escape(&media.alt_text);
+    let mut html = String::from("<picture>");
+    for format in &["avif", "webp"] {
+        if let Some(srcset) = srcset(media, format) {
+            html += &format!(
+                r#"<source type="{}" srcset="{}" sizes="{}">"#,
+                mime_type(format),
+                srcset,
+                SIZES_ATTRIBUTE
+            );
+        }
+    }
+    html += &format!(
+        r#"<img src="{}" srcset="{}" sizes="{}" alt="{}" title="{}"></picture>"#,
+        url,
+        srcset(media, original)?,
+        SIZES_ATTRIBUTE,
+        alt,
+        alt
+    );
+    Some(html)
+}
+
+/// The formats in which variants are encoded, the lightest first.
+pub fn formats(original: &str) -> Vec<&str> {
+    let mut formats = vec![];
+    if cfg!(feature = "avif") {
+        formats.push("avif");
+    }
+    formats.push("webp");
+    formats.push(original);
+    formats
+}
+
+/// Resizes and encodes a local image, and saves the description of its variants.
+///
+/// GIFs and other animated images, and SVGs, that don't need it, have no variants. Previous
+/// variants of the media are removed.
+pub fn generate(conn: &Connection, media: &Media) -> Result<Vec<Variant>> {
+    if media.is_remote {
+        return Ok(vec![]);
+    }
+    let (image_format, original) = match original_format(media) {
+        Some(format) => format,
+        None => return Ok(vec![]),
+    };
+    let bytes = media.read()?;
+    // Only the first frame would be kept
+    if image_cleanup::is_animated(&bytes, original) {
+        return Ok(vec![]);
+    }
+    let image = image::load_from_memory_with_format(&bytes, image_format)?;
+
+    let mut variants = vec![];
+    for (size, max_width) in SIZES {
+        let resized = if image.width() > *max_width {
+            image.resize(*max_width, u32::MAX, FilterType::Lanczos3)
+        } else if *size == "full" {
+            image.clone()
+        } else {
+            continue;
+        };
+        for format in formats(original) {
+            // The original file is used as is when it doesn't need to be resized
+            let file_path = if format == original && resized.width() == image.width() {
+                media.file_path.clone()
+            } else {
+                medias::store(&encode(&resized, format)?, format)?
+            };
+            variants.push(Variant {
+                size: (*size).to_owned(),
+                format: format.to_owned(),
+                width: resized.width(),
+                height: resized.height(),
+                file_path,
+            });
+        }
+    }
+
+    remove_files(media);
+    diesel::update(media)
+        .set(medias_table::variants.eq(serde_json::to_string(&variants)?))
+        .execute(conn)?;
+    Ok(variants)
+}
+
+/// Removes the files of the variants of a media, but not the original one.
+pub(crate) fn remove_files(media: &Media) {
+    for variant in list(media) {
+        if variant.file_path == media.file_path {
+            continue;
+        }
+        if let Err(err) = medias::remove_file(&variant.file_path) {
+            warn!("Couldn't remove a variant of media {}: {:?}", media.id, err);
+        }
+    }
+}
+
+fn encode(image: &DynamicImage, format: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    match format {
+        "webp" => {
+            let rgba = image.to_rgba8();
+            WebPEncoder::new_with_quality(&mut bytes, WebPQuality::lossy(QUALITY)).encode(
+                &rgba,
+                rgba.width(),
+                rgba.height(),
+                ColorType::Rgba8,
+            )?;
+        }
+        #[cfg(feature = "avif")]
+        "avif" => {
+            use image::{codecs::avif::AvifEncoder, ImageEncoder};
+
+            let rgba = image.to_rgba8();
+            AvifEncoder::new_with_speed_quality(&mut bytes, 8, QUALITY).write_image(
+                &rgba,
+                rgba.width(),
+                rgba.height(),
+                ColorType::Rgba8,
+            )?;
+        }
+        "png" => image.write_to(&mut Cursor::new(&mut bytes), ImageOutputFormat::Png)?,
+        "jpg" => DynamicImage::ImageRgb8(image.to_rgb8()).write_to(
+            &mut Cursor::new(&mut bytes),
+            ImageOutputFormat::Jpeg(QUALITY),
+        )?,
+        _ => return Err(Error::InvalidValue),
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        medias::{tests::fill_database, NewMedia},
+        tests::db,
+    };
+    use diesel::Connection;
+    use image::{ImageBuffer, Rgb};
+
+    #[test]
+    fn generate_variants() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (users, _) = fill_database(conn);
+            let image =
+                DynamicImage::ImageRgb8(ImageBuffer::from_pixel(1000, 500, Rgb([200, 20, 20])));
+            let mut bytes = Vec::new();
+            image.write_to(&mut Cursor::new(&mut bytes), ImageOutputFormat::Png)?;
+            let media = Media::save_bytes(conn, &bytes, "png", "red".to_owned(), &users[0])?;
+
+            let variants = generate(conn, &media)?;
+            let media = Media::get(conn, media.id)?;
+            assert_eq!(list(&media), variants);
+            assert!(variants
+                .iter()
+                .any(|v| v.size == "thumbnail" && v.width == 320 && v.height == 160));
+            assert!(variants
+                .iter()
+                .any(|v| v.size == "medium" && v.format == "png"));
+            assert!(variants
+                .iter()
+                .any(|v| v.size == "full" && v.format == "webp"));
+            // The original is not copied
+            assert!(variants
+                .iter()
+                .any(|v| v.size == "full" && v.format == "png" && v.file_path == media.file_path));
+            assert_eq!(srcset(&media, "webp").unwrap().split(", ").count(), 3);
+
+            let gif = Media::insert(
+                conn,
+                NewMedia {
+                    file_path: "static/media/animated.gif".to_owned(),
+                    alt_text: String::new(),
+                    is_remote: false,
+                    remote_url: None,
+                    sensitive: false,
+                    content_warning: None,
+                    owner_id: users[0].id,
+                },
+            )?;
+            assert!(generate(conn, &gif)?.is_empty());
+            assert!(srcset(&gif, "webp").is_none());
+            assert!(picture(&gif).is_none());
+            assert!(picture(&media)
+                .unwrap()
+                .contains(r#"<source type="image/webp""#));
+
+            media.delete(conn)?;
+            for variant in &variants {
+                assert!(!medias::local_path(&variant.file_path).exists());
+            }
+            medias::tests::clean(conn);
+            Ok(())
+        });
+    }
+}
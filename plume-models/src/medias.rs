@@ -1,8 +1,25 @@
 use crate::{
-    ap_url, instance::Instance, safe_string::SafeString, schema::medias, users::User, Connection,
-    Error, Result, CONFIG,
+    ap_url, audio, image_cleanup,
+    instance::Instance,
+    media_dedup,
+    media_policies::{MediaAction, MediaPolicy},
+    media_proxy,
+    media_scan::{self, ScanStatus},
+    media_variants,
+    safe_string::SafeString,
+    schema::medias,
+    users::User,
+    Connection, Error, Result, CONFIG,
 };
-use activitystreams::{object::Image, prelude::*};
+use activitystreams::{
+    base::AnyBase,
+    iri_string::types::IriString,
+    mime::Mime,
+    object::{kind::ImageType, Image},
+    prelude::*,
+    primitives::OneOrMany,
+};
+use chrono::NaiveDateTime;
 use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
 use guid_create::GUID;
 use plume_common::{
@@ -31,6 +48,19 @@ pub struct Media {
     pub sensitive: bool,
     pub content_warning: Option<String>,
     pub owner_id: i32,
+    /// The smaller copies of this image, as JSON (see `media_variants`)
+    pub variants: Option<String>,
+    /// The SHA-256 hash of the file, to find duplicates (see `media_dedup`)
+    pub content_hash: Option<String>,
+    pub creation_date: NaiveDateTime,
+    /// A `media_scan::ScanStatus`
+    pub scan_status: i32,
+    /// What the antivirus found in the file
+    pub scan_result: Option<String>,
+    /// The length of audio files, in milliseconds (see `audio`)
+    pub duration: Option<i32>,
+    /// The bitrate of audio files, in bits per second
+    pub bitrate: Option<i32>,
 }
 
 #[derive(Insertable)]
@@ -111,22 +141,48 @@ impl Media {
             .unwrap_or("")
             .to_lowercase()
         {
-            "png" | "jpg" | "jpeg" | "gif" | "svg" => MediaCategory::Image,
+            "png" | "apng" | "jpg" | "jpeg" | "gif" | "svg" | "webp" | "avif" => {
+                MediaCategory::Image
+            }
             "mp3" | "wav" | "flac" => MediaCategory::Audio,
             "mp4" | "avi" | "webm" | "mov" => MediaCategory::Video,
             _ => MediaCategory::Unknown,
         }
     }
 
+    /// The media type of the file, guessed from its extension (or the one of its URL, for
+    /// remote media).
+    pub fn media_type(&self) -> Option<&'static str> {
+        let path = if self.is_remote {
+            self.remote_url.as_deref()?
+        } else {
+            &self.file_path
+        };
+        let ext = path.rsplit_once('.')?.1.to_lowercase();
+        match &*ext {
+            "png" => Some("image/png"),
+            "apng" => Some("image/apng"),
+            "jpg" | "jpeg" => Some("image/jpeg"),
+            "gif" => Some("image/gif"),
+            "svg" => Some("image/svg+xml"),
+            "webp" => Some("image/webp"),
+            "avif" => Some("image/avif"),
+            ext => audio::media_type(ext),
+        }
+    }
+
     pub fn html(&self) -> Result<SafeString> {
         let url = self.url()?;
         Ok(match self.category() {
-            MediaCategory::Image => SafeString::trusted(&format!(
-                r#"<img src="{}" alt="{}" title="{}">"#,
-                url,
-                escape(&self.alt_text),
-                escape(&self.alt_text)
-            )),
+            MediaCategory::Image => match media_variants::picture(self) {
+                Some(picture) => SafeString::trusted(&picture),
+                None => SafeString::trusted(&format!(
+                    r#"<img src="{}" alt="{}" title="{}">"#,
+                    url,
+                    escape(&self.alt_text),
+                    escape(&self.alt_text)
+                )),
+            },
             MediaCategory::Audio => SafeString::trusted(&format!(
                 r#"<div class="media-preview audio"></div><audio src="{}" title="{}" controls></audio>"#,
                 url,
@@ -159,21 +215,7 @@ impl Media {
         if self.file_path.is_empty() {
             return None;
         }
-
-        if CONFIG.s3.is_some() {
-            #[cfg(feature="s3")]
-            unreachable!("Called Media::local_path() but media are stored on S3");
-            #[cfg(not(feature="s3"))]
-            unreachable!();
-        }
-
-        let relative_path = self
-            .file_path
-            .trim_start_matches(&CONFIG.media_directory)
-            .trim_start_matches(path::MAIN_SEPARATOR)
-            .trim_start_matches("static/media/");
-
-        Some(Path::new(&CONFIG.media_directory).join(relative_path))
+        Some(local_path(&self.file_path))
     }
 
     /// Returns the relative URL to access this file, which is also the key at which
@@ -183,69 +225,47 @@ impl Media {
         if self.file_path.is_empty() {
             return None;
         }
-
-        let relative_path = self
-            .file_path
-            .trim_start_matches(&CONFIG.media_directory)
-            .replace(path::MAIN_SEPARATOR, "/");
-
-        let relative_path = relative_path
-            .trim_start_matches('/')
-            .trim_start_matches("static/media/");
-
-        Some(format!("static/media/{}", relative_path))
+        Some(relative_url(&self.file_path))
     }
 
     /// Returns a public URL through which this media file can be accessed
+    ///
+    /// Remote media are served through the media proxy, if it is enabled.
     pub fn url(&self) -> Result<String> {
         if self.is_remote {
+            if CONFIG.media_proxy.enabled && self.remote_url.is_some() {
+                return media_proxy::proxied_url(self);
+            }
             Ok(self.remote_url.clone().unwrap_or_default())
         } else {
-            let relative_url = self.relative_url().unwrap_or_default();
-
-            #[cfg(feature="s3")]
-            if CONFIG.s3.as_ref().map(|x| x.direct_download).unwrap_or(false) {
-                let s3_url = match CONFIG.s3.as_ref().unwrap() {
-                    S3Config { alias: Some(alias), .. } => {
-                        format!("https://{}/{}", alias, relative_url)
-                    }
-                    S3Config { path_style: true, hostname, bucket, .. } => {
-                        format!("https://{}/{}/{}",
-                            hostname,
-                            bucket,
-                            relative_url
-                        )
-                    }
-                    S3Config { path_style: false, hostname, bucket, .. } => {
-                        format!("https://{}.{}/{}",
-                            bucket,
-                            hostname,
-                            relative_url
-                        )
-                    }
-                };
-                return Ok(s3_url);
-            }
+            public_url(&self.relative_url().unwrap_or_default())
+        }
+    }
 
-            Ok(ap_url(&format!(
-                "{}/{}",
-                Instance::get_local()?.public_domain,
-                relative_url
-            )))
+    /// What has to be done with this media when it is shown, if it comes from another
+    /// instance (see `media_policies`).
+    pub fn policy(&self, conn: &Connection) -> Option<MediaAction> {
+        MediaPolicy::for_media(conn, self).ok().flatten()
+    }
+
+    /// The URL of this media if it can be shown inline, like avatars, icons and banners are.
+    ///
+    /// Media that are rejected or only shown on click are left out.
+    pub fn inline_url(&self, conn: &Connection) -> Option<String> {
+        match self.policy(conn) {
+            Some(MediaAction::Reject) | Some(MediaAction::ClickThrough) => None,
+            _ => self.url().ok(),
         }
     }
 
+    /// Deletes this media, and its files if no other media uses them (see `media_dedup`).
     pub fn delete(&self, conn: &Connection) -> Result<()> {
-        if !self.is_remote {
-            if CONFIG.s3.is_some() {
-                #[cfg(not(feature="s3"))]
-                unreachable!();
-
-                #[cfg(feature = "s3")]
-                CONFIG.s3.as_ref().unwrap().get_bucket()
-                    .delete_object_blocking(&self.relative_url().ok_or(Error::NotFound)?)?;
+        if !self.is_remote && !media_dedup::is_shared(conn, self)? {
+            if self.scan_status() == ScanStatus::Clean {
+                media_variants::remove_files(self);
+                remove_file(&self.file_path)?;
             } else {
-                fs::remove_file(self.local_path().ok_or(Error::NotFound)?)?;
+                media_scan::remove_pending(self)?;
             }
         }
         diesel::delete(self)
@@ -254,10 +274,13 @@ impl Media {
             .map_err(Error::from)
     }
 
+    /// Saves a remote media by its URL, unless the instance rejects the media of its domain
+    /// (see `media_policies`).
     pub fn save_remote(conn: &Connection, url: String, user: &User) -> Result<Media> {
         if url.contains(&['<', '>', '"'][..]) {
             Err(Error::Url)
         } else {
+            MediaPolicy::check_remote(conn, &url, &user.ap_url, false)?;
             Media::insert(
                 conn,
                 NewMedia {
@@ -273,6 +296,70 @@ impl Media {
         }
     }
 
+    /// Stores `bytes` in the media directory (or bucket), as a new media owned by `user`.
+    ///
+    /// The metadata of images are removed first, and the hash of the file is saved, with the
+    /// duration of audio files. If an antivirus is configured, the file is scanned before
+    /// being published.
+    pub fn save_bytes(
+        conn: &Connection,
+        bytes: &[u8],
+        ext: &str,
+        alt_text: String,
+        user: &User,
+    ) -> Result<Media> {
+        let bytes = image_cleanup::clean(bytes, ext)?;
+        let file_path = if media_scan::enabled() {
+            media_scan::store_pending(&bytes, ext)?
+        } else {
+            store(&bytes, ext)?
+        };
+        let media = Media::insert(
+            conn,
+            NewMedia {
+                file_path,
+                alt_text,
+                is_remote: false,
+                remote_url: None,
+                sensitive: false,
+                content_warning: None,
+                owner_id: user.id,
+            },
+        )?;
+        let media =
+            media_dedup::set_content_hash(conn, &media, &media_dedup::content_hash(&bytes))?;
+        let media = audio::save_metadata(conn, &media, &bytes)?;
+        if !media_scan::enabled() {
+            return Ok(media);
+        }
+        let media = media_scan::set_pending(conn, &media)?;
+        media_scan::check(conn, &media).or_else(|err| {
+            warn!("Couldn't scan media {}: {:?}", media.id, err);
+            Ok(media)
+        })
+    }
+
+    /// The content of a local media.
+    pub fn read(&self) -> Result<Vec<u8>> {
+        if self.is_remote || self.file_path.is_empty() {
+            return Err(Error::NotFound);
+        }
+        if CONFIG.s3.is_some() {
+            #[cfg(not(feature = "s3"))]
+            unreachable!();
+
+            #[cfg(feature = "s3")]
+            return Ok(CONFIG
+                .s3
+                .as_ref()
+                .unwrap()
+                .get_bucket()
+                .get_object_blocking(&relative_url(&self.file_path))?
+                .to_vec());
+        }
+        Ok(fs::read(local_path(&self.file_path))?)
+    }
+
     pub fn set_owner(&self, conn: &Connection, user: &User) -> Result<()> {
         diesel::update(self)
             .set(medias::owner_id.eq(user.id))
@@ -281,15 +368,66 @@ impl Media {
             .map_err(Error::from)
     }
 
+    /// The `Image` representing this media in the `icon` or `image` of an actor.
+    pub fn to_image(&self, conn: &Connection) -> Result<Image> {
+        let mut image = Image::new();
+        image.set_url(self.url()?.parse::<IriString>()?);
+        if let Some(media_type) = self.media_type().and_then(|t| t.parse::<Mime>().ok()) {
+            image.set_media_type(media_type);
+        }
+        image.set_attributed_to(
+            User::get(conn, self.owner_id)?
+                .ap_url
+                .parse::<IriString>()?,
+        );
+        Ok(image)
+    }
+
+    /// Saves the first image of the `icon` or `image` of a remote actor, as a media owned by
+    /// `owner`.
+    ///
+    /// They may be `Image` objects or just URLs. The media is reused if `owner` already has
+    /// one with the same URL.
+    pub fn save_remote_image(
+        conn: &Connection,
+        images: &OneOrMany<AnyBase>,
+        owner: &User,
+    ) -> Result<Media> {
+        let image = images.iter().next().ok_or(Error::MissingApProperty)?;
+        let url = match image.as_xsd_any_uri() {
+            Some(url) => url.to_string(),
+            None => image
+                .clone()
+                .extend::<Image, ImageType>()?
+                .and_then(|image| image.url().and_then(|url| url.to_as_uri()))
+                .ok_or(Error::MissingApProperty)?,
+        };
+        medias::table
+            .filter(medias::is_remote.eq(true))
+            .filter(medias::remote_url.eq(&url))
+            .filter(medias::owner_id.eq(owner.id))
+            .first::<Media>(conn)
+            .or_else(|_| Media::save_remote(conn, url, owner))
+    }
+
     // TODO: merge with save_remote?
     pub fn from_activity(conn: &Connection, image: &Image) -> Result<Media> {
         let remote_url = image
             .url()
             .and_then(|url| url.to_as_uri())
             .ok_or(Error::MissingApProperty)?;
+        let owner_url = image
+            .attributed_to()
+            .and_then(|attributed_to| attributed_to.to_as_uri());
+        MediaPolicy::check_remote(
+            conn,
+            &remote_url,
+            owner_url.as_deref().unwrap_or_default(),
+            image.summary().is_some(),
+        )?;
 
         let file_path = if CONFIG.s3.is_some() {
-            #[cfg(not(feature="s3"))]
+            #[cfg(not(feature = "s3"))]
             unreachable!();
 
             #[cfg(feature = "s3")]
@@ -317,7 +455,7 @@ impl Media {
                 bucket.put_object_with_content_type_blocking(
                     &dest,
                     &bytes,
-                    &content_type.to_string()
+                    &content_type.to_string(),
                 )?;
 
                 dest
@@ -392,10 +530,7 @@ impl Media {
                         content_warning: summary,
                         owner_id: User::from_id(
                             conn,
-                            &image
-                                .attributed_to()
-                                .and_then(|attributed_to| attributed_to.to_as_uri())
-                                .ok_or(Error::MissingApProperty)?,
+                            owner_url.as_deref().ok_or(Error::MissingApProperty)?,
                             None,
                             CONFIG.proxy(),
                         )
@@ -420,6 +555,177 @@ impl Media {
     }
 }
 
+/// The full path of a file stored in the local media directory.
+pub(crate) fn local_path(file_path: &str) -> PathBuf {
+    if CONFIG.s3.is_some() {
+        #[cfg(feature = "s3")]
+        unreachable!("Called Media::local_path() but media are stored on S3");
+        #[cfg(not(feature = "s3"))]
+        unreachable!();
+    }
+
+    let relative_path = file_path
+        .trim_start_matches(&CONFIG.media_directory)
+        .trim_start_matches(path::MAIN_SEPARATOR)
+        .trim_start_matches("static/media/");
+
+    Path::new(&CONFIG.media_directory).join(relative_path)
+}
+
+/// The URL of a stored file, relative to the instance (see `Media::relative_url`).
+pub(crate) fn relative_url(file_path: &str) -> String {
+    let relative_path = file_path
+        .trim_start_matches(&CONFIG.media_directory)
+        .replace(path::MAIN_SEPARATOR, "/");
+
+    let relative_path = relative_path
+        .trim_start_matches('/')
+        .trim_start_matches("static/media/");
+
+    format!("static/media/{}", relative_path)
+}
+
+/// The public URL of a stored file, from its relative URL.
+pub(crate) fn public_url(relative_url: &str) -> Result<String> {
+    // The bucket can't send the restrictive CSP of media files: those that could run
+    // scripts when opened are always served by Plume
+    #[cfg(feature = "s3")]
+    if CONFIG
+        .s3
+        .as_ref()
+        .map(|x| x.direct_download)
+        .unwrap_or(false)
+        && is_passive(relative_url)
+    {
+        let s3_url = match CONFIG.s3.as_ref().unwrap() {
+            S3Config {
+                alias: Some(alias), ..
+            } => {
+                format!("https://{}/{}", alias, relative_url)
+            }
+            S3Config {
+                path_style: true,
+                hostname,
+                bucket,
+                ..
+            } => {
+                format!("https://{}/{}/{}", hostname, bucket, relative_url)
+            }
+            S3Config {
+                path_style: false,
+                hostname,
+                bucket,
+                ..
+            } => {
+                format!("https://{}.{}/{}", bucket, hostname, relative_url)
+            }
+        };
+        return Ok(s3_url);
+    }
+
+    Ok(ap_url(&format!(
+        "{}/{}",
+        Instance::get_local()?.public_domain,
+        relative_url
+    )))
+}
+
+/// Whether a stored file is a raster image, an audio or a video file, from its extension.
+#[cfg(feature = "s3")]
+fn is_passive(relative_url: &str) -> bool {
+    use rocket::http::ContentType;
+
+    Path::new(relative_url)
+        .extension()
+        .and_then(|ext| ContentType::from_extension(&ext.to_string_lossy()))
+        .map(|ct| match ct.top().as_str() {
+            "image" => ct.sub() != "svg+xml",
+            "audio" | "video" => true,
+            _ => false,
+        })
+        .unwrap_or(false)
+}
+
+/// Stores `bytes` in the media directory (or bucket), and returns its path.
+pub(crate) fn store(bytes: &[u8], ext: &str) -> Result<String> {
+    let file_path = new_file_path(ext);
+    store_at(&file_path, bytes)?;
+    Ok(file_path)
+}
+
+/// A new path in the media directory (or bucket), for a file with the extension `ext`.
+pub(crate) fn new_file_path(ext: &str) -> String {
+    let ext = if ext.chars().all(|c| c.is_ascii_alphanumeric()) {
+        ext.to_lowercase()
+    } else {
+        String::new()
+    };
+    let name = format!("{}.{}", GUID::rand(), ext);
+    if CONFIG.s3.is_some() {
+        format!("static/media/{}", name)
+    } else {
+        format!("{}/{}", CONFIG.media_directory, name)
+    }
+}
+
+/// Stores `bytes` at a path given by `new_file_path`.
+pub(crate) fn store_at(file_path: &str, bytes: &[u8]) -> Result<()> {
+    if CONFIG.s3.is_some() {
+        #[cfg(not(feature = "s3"))]
+        unreachable!();
+
+        #[cfg(feature = "s3")]
+        {
+            use rocket::http::ContentType;
+
+            let content_type = Path::new(file_path)
+                .extension()
+                .and_then(|ext| ContentType::from_extension(&ext.to_string_lossy()))
+                .unwrap_or(ContentType::Binary)
+                .to_string();
+            CONFIG
+                .s3
+                .as_ref()
+                .unwrap()
+                .get_bucket()
+                .put_object_with_content_type_blocking(
+                    &relative_url(file_path),
+                    bytes,
+                    &content_type,
+                )?;
+        }
+    } else {
+        let dest = local_path(file_path);
+        if let Some(dir) = dest.parent().filter(|dir| !dir.is_dir()) {
+            DirBuilder::new().recursive(true).create(dir)?;
+        }
+        fs::write(&dest, bytes)?;
+    }
+    Ok(())
+}
+
+/// Removes a stored file.
+pub(crate) fn remove_file(file_path: &str) -> Result<()> {
+    if file_path.is_empty() {
+        return Err(Error::NotFound);
+    }
+    if CONFIG.s3.is_some() {
+        #[cfg(not(feature = "s3"))]
+        unreachable!();
+
+        #[cfg(feature = "s3")]
+        CONFIG
+            .s3
+            .as_ref()
+            .unwrap()
+            .get_bucket()
+            .delete_object_blocking(&relative_url(file_path))?;
+    } else {
+        fs::remove_file(local_path(file_path))?;
+    }
+    Ok(())
+}
+
 fn determine_mirror_file_path(url: &str) -> PathBuf {
     let mut file_path = Path::new(&CONFIG.media_directory).join(REMOTE_MEDIA_DIRECTORY);
 
@@ -450,11 +756,12 @@ fn determine_mirror_file_path(url: &str) -> PathBuf {
     file_path
 }
 
-#[cfg(feature="s3")]
+#[cfg(feature = "s3")]
 fn determine_mirror_s3_path(url: &str) -> String {
     match Url::parse(url) {
         Ok(url) if url.has_host() => {
-            format!("static/media/{}/{}/{}",
+            format!(
+                "static/media/{}/{}/{}",
                 REMOTE_MEDIA_DIRECTORY,
                 url.host_str().unwrap(),
                 url.path().trim_start_matches('/'),
@@ -471,7 +778,8 @@ fn determine_mirror_s3_path(url: &str) -> String {
                 .next()
                 .map(ToOwned::to_owned)
                 .unwrap_or_else(|| String::from("png"));
-            format!("static/media/{}/{}.{}",
+            format!(
+                "static/media/{}/{}.{}",
                 REMOTE_MEDIA_DIRECTORY,
                 GUID::rand(),
                 ext,
@@ -1,6 +1,11 @@
 use crate::{
-    comments::Comment, notifications::*, posts::Post, schema::mentions, users::User, Connection,
-    Error, Result,
+    cache::{self, Entry},
+    comments::Comment,
+    notifications::*,
+    posts::Post,
+    schema::mentions,
+    users::User,
+    Connection, Error, Result, CONFIG,
 };
 use activitystreams::{
     base::BaseExt,
@@ -8,7 +13,23 @@ use activitystreams::{
     link::{self, LinkExt},
 };
 use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
-use plume_common::activity_pub::inbox::AsActor;
+use plume_common::activity_pub::{
+    inbox::{AsActor, FromId},
+    CustomPerson,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+use tracing::{warn, Span};
+
+/// How many mentioned accounts are looked up on other instances at the same time.
+const MAX_PARALLEL_LOOKUPS: usize = 4;
+
+/// How long an account that couldn't be found is not looked for again.
+const NOT_FOUND_TTL: Duration = Duration::from_secs(60 * 10);
 
 #[derive(Clone, Queryable, Identifiable)]
 pub struct Mention {
@@ -26,6 +47,43 @@ pub struct NewMention {
     pub comment_id: Option<i32>,
 }
 
+/// Why a mention couldn't be linked to an account.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MentionFailure {
+    /// There is no such account, or its instance doesn't support WebFinger
+    NotFound,
+    /// Its instance couldn't be reached
+    Unreachable,
+    /// Its instance answered with something that is not an account
+    InvalidActor,
+}
+
+impl MentionFailure {
+    pub fn code(self) -> &'static str {
+        match self {
+            MentionFailure::NotFound => "not_found",
+            MentionFailure::Unreachable => "unreachable",
+            MentionFailure::InvalidActor => "invalid_actor",
+        }
+    }
+
+    fn from_error(err: &Error) -> Self {
+        match err {
+            Error::Webfinger | Error::NotFound => MentionFailure::NotFound,
+            Error::Request => MentionFailure::Unreachable,
+            _ => MentionFailure::InvalidActor,
+        }
+    }
+}
+
+/// The mentions of a text, linked to the accounts they name.
+#[derive(Default)]
+pub struct ResolvedMentions {
+    pub mentions: Vec<link::Mention>,
+    /// The mentions that couldn't be linked, without their `@`, and why
+    pub failures: Vec<(String, MentionFailure)>,
+}
+
 impl Mention {
     insert!(mentions, NewMention);
     get!(mentions);
@@ -62,12 +120,89 @@ impl Mention {
 
     pub fn build_activity(conn: &Connection, ment: &str) -> Result<link::Mention> {
         let user = User::find_by_fqn(conn, ment)?;
+        Self::link(&user, ment)
+    }
+
+    fn link(user: &User, ment: &str) -> Result<link::Mention> {
         let mut mention = link::Mention::new();
         mention.set_href(user.ap_url.parse::<IriString>()?);
         mention.set_name(format!("@{}", ment));
         Ok(mention)
     }
 
+    /// Builds the links of all the mentions of a text, in order and without duplicates.
+    ///
+    /// The accounts this instance doesn't know yet are looked up on their instances a few at a
+    /// time, instead of one after the other. The ones that can't be found are remembered for
+    /// a few minutes, not to look for them again each time the text is saved or federated.
+    pub fn resolve_all(conn: &Connection, mentions: Vec<String>) -> ResolvedMentions {
+        let mut names: Vec<String> = vec![];
+        for name in mentions {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+
+        let mut found = HashMap::new();
+        let mut failed = HashMap::new();
+        let mut lookups = vec![];
+        for name in &names {
+            match User::find_known_by_fqn(conn, name) {
+                Ok(Some(user)) => {
+                    found.insert(name.clone(), user);
+                }
+                Ok(None) => match cache::get(Entry::MentionFailure(name)) {
+                    Some(failure) => {
+                        failed.insert(name.clone(), failure);
+                    }
+                    None => lookups.push(name.clone()),
+                },
+                Err(err) => {
+                    warn!(mention = %name, error = ?err, "Couldn't look for a mentioned account");
+                    failed.insert(name.clone(), MentionFailure::from_error(&err));
+                }
+            }
+        }
+
+        for (name, fetched) in fetch_all(lookups) {
+            // Saving the accounts needs the connection, so it is done here, one at a time
+            let user = fetched.and_then(|(url, person)| {
+                User::from_id(conn, &url, Some(person), CONFIG.proxy())
+                    .map_err(|_| MentionFailure::InvalidActor)
+            });
+            match user {
+                Ok(user) => {
+                    found.insert(name, user);
+                }
+                Err(failure) => {
+                    // Other failures may only last until the instance is back
+                    if failure == MentionFailure::NotFound {
+                        cache::set_for(Entry::MentionFailure(&name), &failure, NOT_FOUND_TTL);
+                    }
+                    failed.insert(name, failure);
+                }
+            }
+        }
+
+        let mut resolved = ResolvedMentions::default();
+        for name in names {
+            let link = found
+                .get(&name)
+                .ok_or_else(|| {
+                    failed
+                        .get(&name)
+                        .copied()
+                        .unwrap_or(MentionFailure::NotFound)
+                })
+                .and_then(|user| Self::link(user, &name).map_err(|_| MentionFailure::InvalidActor));
+            match link {
+                Ok(link) => resolved.mentions.push(link),
+                Err(failure) => resolved.failures.push((name, failure)),
+            }
+        }
+        resolved
+    }
+
     pub fn to_activity(&self, conn: &Connection) -> Result<link::Mention> {
         let user = self.get_mentioned(conn)?;
         let mut mention = link::Mention::new();
@@ -133,21 +268,63 @@ impl Mention {
     fn notify(&self, conn: &Connection) -> Result<()> {
         let m = self.get_mentioned(conn)?;
         if m.is_local() {
-            Notification::insert(
+            Notification::notify_from(
                 conn,
+                &self.get_user(conn)?,
+                "",
                 NewNotification {
                     kind: notification_kind::MENTION.to_string(),
                     object_id: self.id,
                     user_id: m.id,
+                    group_key: None,
+                    filtered: false,
                 },
+                None,
             )
-            .map(|_| ())
         } else {
             Ok(())
         }
     }
 }
 
+/// The address and the ActivityPub representation of a mentioned account.
+type Fetched = std::result::Result<(String, CustomPerson), MentionFailure>;
+
+/// Fetches the accounts named by WebFinger addresses, with a few threads.
+fn fetch_all(accts: Vec<String>) -> Vec<(String, Fetched)> {
+    let threads = accts.len().min(MAX_PARALLEL_LOOKUPS);
+    let queue = Arc::new(Mutex::new(accts.into_iter()));
+    let span = Span::current();
+    let workers = (0..threads)
+        .map(|_| {
+            let queue = queue.clone();
+            let span = span.clone();
+            thread::spawn(move || {
+                let _span = span.entered();
+                let mut fetched = vec![];
+                loop {
+                    let next = queue.lock().unwrap().next();
+                    let acct = match next {
+                        Some(acct) => acct,
+                        None => break,
+                    };
+                    let person = User::fetch_by_acct(&acct).map_err(|err| {
+                        warn!(mention = %acct, error = ?err, "Couldn't find a mentioned account");
+                        MentionFailure::from_error(&err)
+                    });
+                    fetched.push((acct, person));
+                }
+                fetched
+            })
+        })
+        .collect::<Vec<_>>();
+    // The accounts of a thread that panicked are not found
+    workers
+        .into_iter()
+        .flat_map(|worker| worker.join().unwrap_or_default())
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -177,6 +354,36 @@ mod tests {
         });
     }
 
+    #[test]
+    fn resolve_all() {
+        let conn = db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (_posts, users, _blogs) = fill_database(&conn);
+            let resolved = Mention::resolve_all(
+                &conn,
+                vec![
+                    users[1].fqn.clone(),
+                    users[0].fqn.clone(),
+                    users[1].fqn.clone(),
+                ],
+            );
+            assert!(resolved.failures.is_empty());
+            let names = resolved
+                .mentions
+                .into_iter()
+                .map(|m| to_value(m).map(|m| m["name"].clone()))
+                .collect::<serde_json::Result<Vec<_>>>()?;
+            assert_eq!(
+                names,
+                vec![
+                    json!(format!("@{}", users[1].fqn)),
+                    json!(format!("@{}", users[0].fqn))
+                ]
+            );
+            Ok(())
+        });
+    }
+
     #[test]
     fn to_activity() {
         let conn = db();
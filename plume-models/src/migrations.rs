@@ -1,8 +1,44 @@
-use crate::{Connection, Error, Result};
-use diesel::connection::{Connection as Conn, SimpleConnection};
+//! Migrations are run in two phases, so that a new version can be deployed while the
+//! previous one is still running.
+//!
+//! Expand migrations only add to the database: new tables, new columns with a default
+//! value, new indexes. They are run before the new version starts, and the previous one
+//! keeps working with the database they leave. Contract migrations remove what the previous
+//! version used, or change it in ways it can't handle. They are marked with a
+//! `-- phase: contract` line in their `up.sql`, and are run once all the servers run the
+//! new version.
+//!
+//! Migrations stay ordered: the contract migrations of a version must be run before the
+//! expand migrations of the next one.
+
+use crate::{db_conn::write_transaction, Connection, Error, Result};
+use diesel::connection::SimpleConnection;
 use migrations_internals::{setup_database, MigrationConnection};
-use std::path::Path;
-use tracing::info;
+use std::{path::Path, str::FromStr, time::Duration};
+use tracing::{info, warn};
+
+/// How long a migration can wait for a lock before giving up, instead of blocking all the
+/// queries that would wait after it.
+#[cfg(feature = "postgres")]
+const LOCK_TIMEOUT: &str = "10s";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+    Expand,
+    Contract,
+}
+
+impl FromStr for Phase {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, ()> {
+        match s {
+            "expand" => Ok(Phase::Expand),
+            "contract" => Ok(Phase::Contract),
+            _ => Err(()),
+        }
+    }
+}
 
 #[allow(dead_code)] //variants might not be constructed if not required by current migrations
 enum Action {
@@ -21,6 +57,7 @@ impl Action {
 
 struct ComplexMigration {
     name: &'static str,
+    phase: Phase,
     up: &'static [Action],
     down: &'static [Action],
 }
@@ -43,10 +80,69 @@ impl ComplexMigration {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MigrationStatus {
+    pub name: &'static str,
+    pub phase: Phase,
+    pub applied: bool,
+}
+
+/// A transaction that could keep a migration waiting for its locks.
+#[derive(Clone, Debug, PartialEq, QueryableByName)]
+pub struct LongTransaction {
+    #[sql_type = "diesel::sql_types::Integer"]
+    pub pid: i32,
+    #[sql_type = "diesel::sql_types::Double"]
+    pub seconds: f64,
+    #[sql_type = "diesel::sql_types::Text"]
+    pub query: String,
+}
+
+/// Lists the transactions that are open for longer than `min_duration`.
+///
+/// Migrations changing a table wait for the transactions using it to end, and all the
+/// queries on this table then wait for the migration.
+#[cfg(feature = "postgres")]
+pub fn long_running_transactions(
+    conn: &Connection,
+    min_duration: Duration,
+) -> Result<Vec<LongTransaction>> {
+    use diesel::{dsl::sql_query, sql_types::Double, RunQueryDsl};
+
+    sql_query(
+        "SELECT pid, EXTRACT(EPOCH FROM now() - xact_start)::float8 AS seconds, query \
+         FROM pg_stat_activity \
+         WHERE datname = current_database() \
+         AND pid <> pg_backend_pid() \
+         AND xact_start IS NOT NULL \
+         AND EXTRACT(EPOCH FROM now() - xact_start) > $1 \
+         ORDER BY xact_start",
+    )
+    .bind::<Double, _>(min_duration.as_secs_f64())
+    .load(conn)
+    .map_err(Error::from)
+}
+
+/// SQLite has a single writer: migrations wait for `DB_BUSY_TIMEOUT` at most.
+#[cfg(feature = "sqlite")]
+pub fn long_running_transactions(
+    _conn: &Connection,
+    _min_duration: Duration,
+) -> Result<Vec<LongTransaction>> {
+    Ok(vec![])
+}
+
 pub struct ImportedMigrations(&'static [ComplexMigration]);
 
 impl ImportedMigrations {
+    /// Runs all the pending migrations, of both phases.
     pub fn run_pending_migrations(&self, conn: &Connection, path: &Path) -> Result<()> {
+        self.run_phase(conn, path, Phase::Contract)
+    }
+
+    /// Runs the pending migrations, stopping before the first contract migration if `phase`
+    /// is `Phase::Expand`.
+    pub fn run_phase(&self, conn: &Connection, path: &Path, phase: Phase) -> Result<()> {
         use diesel::dsl::sql;
         use diesel::sql_types::Bool;
         use diesel::{select, RunQueryDsl};
@@ -83,8 +179,19 @@ impl ImportedMigrations {
         };
 
         let to_run = &self.0[latest_id..];
-        for migration in to_run {
-            conn.transaction(|| {
+        for (i, migration) in to_run.iter().enumerate() {
+            if migration.phase > phase {
+                if to_run[i..].iter().any(|m| m.phase == Phase::Expand) {
+                    warn!(
+                        "Migration {} must be run before the next ones, with the contract phase",
+                        migration.name
+                    );
+                }
+                break;
+            }
+            write_transaction(conn, || {
+                #[cfg(feature = "postgres")]
+                conn.batch_execute(&format!("SET LOCAL lock_timeout = '{}';", LOCK_TIMEOUT))?;
                 migration.run(conn, path)?;
                 conn.insert_new_migration(migration.name)
                     .map_err(Error::from)
@@ -93,13 +200,52 @@ impl ImportedMigrations {
         Ok(())
     }
 
+    /// Whether expand migrations need to be run before this version can start.
+    ///
+    /// Pending contract migrations are only logged: this version doesn't need them.
     pub fn is_pending(&self, conn: &Connection) -> Result<bool> {
-        let latest_migration = conn.latest_run_migration_version()?;
-        if let Some(migration) = latest_migration {
-            Ok(self.0.last().expect("no migrations found").name != migration)
-        } else {
-            Ok(true)
+        let pending = self
+            .status(conn)?
+            .into_iter()
+            .filter(|m| !m.applied)
+            .collect::<Vec<_>>();
+        if pending.iter().any(|m| m.phase == Phase::Expand) {
+            return Ok(true);
+        }
+        if !pending.is_empty() {
+            info!(
+                "{} contract migrations are pending, run them with `plm migration run --phase contract` once all your servers are up to date",
+                pending.len()
+            );
         }
+        Ok(false)
+    }
+
+    /// All the migrations, in the order they are run.
+    pub fn status(&self, conn: &Connection) -> Result<Vec<MigrationStatus>> {
+        let latest_id = match conn.latest_run_migration_version()? {
+            Some(migration) => self
+                .0
+                .binary_search_by_key(&migration.as_str(), |mig| mig.name)
+                .map(|id| id + 1)
+                .map_err(|_| Error::NotFound)?,
+            None => 0,
+        };
+        Ok(self
+            .0
+            .iter()
+            .enumerate()
+            .map(|(i, m)| MigrationStatus {
+                name: m.name,
+                phase: m.phase,
+                applied: i < latest_id,
+            })
+            .collect())
+    }
+
+    /// Whether this version of Plume has a migration named `name`.
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.binary_search_by_key(&name, |m| m.name).is_ok()
     }
 
     pub fn rerun_last_migration(&self, conn: &Connection, path: &Path) -> Result<()> {
@@ -108,7 +254,7 @@ impl ImportedMigrations {
             .and_then(|m| self.0.binary_search_by_key(&m.as_str(), |m| m.name).ok())
             .ok_or(Error::NotFound)?;
         let migration = &self.0[id];
-        conn.transaction(|| {
+        write_transaction(conn, || {
             migration.revert(conn, path)?;
             migration.run(conn, path)
         })
@@ -0,0 +1,471 @@
+//! Accounts, instances and words a user doesn't want to hear about, for a while or for good.
+//!
+//! Unlike blocks, mutes are never federated: the muted accounts can still follow and
+//! read their author, they just don't show up in their timelines and notifications
+//! anymore.
+
+use crate::{
+    posts::Post,
+    schema::{instances, mutes, post_authors, posts, users},
+    users::User,
+    Connection, Cursor, Error, Result,
+};
+use chrono::{NaiveDateTime, Utc};
+use diesel::{
+    self,
+    expression::BoxableExpression,
+    sql_types::{Integer, Text},
+    BoolExpressionMethods, ExpressionMethods, IntoSql, QueryDsl, RunQueryDsl,
+    TextExpressionMethods,
+};
+
+type Backend = <Connection as diesel::Connection>::Backend;
+
+/// Keywords are short, to be matched in titles and texts.
+pub const MAX_KEYWORD_LENGTH: usize = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MuteKind {
+    Account = 0,
+    Domain = 1,
+    Keyword = 2,
+}
+
+impl MuteKind {
+    pub fn from_i32(kind: i32) -> Option<Self> {
+        match kind {
+            0 => Some(MuteKind::Account),
+            1 => Some(MuteKind::Domain),
+            2 => Some(MuteKind::Keyword),
+            _ => None,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "account" => Some(MuteKind::Account),
+            "domain" => Some(MuteKind::Domain),
+            "keyword" => Some(MuteKind::Keyword),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MuteKind::Account => "account",
+            MuteKind::Domain => "domain",
+            MuteKind::Keyword => "keyword",
+        }
+    }
+}
+
+/// What is muted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MuteTarget {
+    Account(i32),
+    /// All the accounts of an instance
+    Domain(String),
+    /// Articles and comments containing this word, whatever its case
+    Keyword(String),
+}
+
+#[derive(Clone, Queryable, Identifiable)]
+pub struct Mute {
+    pub id: i32,
+    /// The user who muted something
+    pub user_id: i32,
+    /// A `MuteKind`
+    pub kind: i32,
+    /// The muted account
+    pub target_id: Option<i32>,
+    /// The muted domain or keyword
+    pub value: String,
+    /// Mutes without expiry last until they are removed
+    pub expires_at: Option<NaiveDateTime>,
+    pub creation_date: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "mutes"]
+pub struct NewMute {
+    pub user_id: i32,
+    pub kind: i32,
+    pub target_id: Option<i32>,
+    pub value: String,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+impl Mute {
+    insert!(mutes, NewMute);
+    get!(mutes);
+
+    /// Mutes `target` for `user`, until `expires_at` if given.
+    ///
+    /// Muting something again only changes when it expires.
+    pub fn add(
+        conn: &Connection,
+        user: &User,
+        target: MuteTarget,
+        expires_at: Option<NaiveDateTime>,
+    ) -> Result<Self> {
+        if expires_at.map_or(false, |date| date <= Utc::now().naive_utc()) {
+            return Err(Error::InvalidValue);
+        }
+        let (kind, target_id, value) = match target {
+            MuteTarget::Account(id) if id != user.id => {
+                (MuteKind::Account, Some(id), String::new())
+            }
+            MuteTarget::Domain(domain) => (MuteKind::Domain, None, normalize(&domain)?),
+            MuteTarget::Keyword(keyword) => (MuteKind::Keyword, None, normalize(&keyword)?),
+            MuteTarget::Account(_) => return Err(Error::InvalidValue),
+        };
+
+        let existing = mutes::table
+            .filter(mutes::user_id.eq(user.id))
+            .filter(mutes::kind.eq(kind as i32))
+            .filter(mutes::value.eq(&value))
+            .load::<Self>(conn)?
+            .into_iter()
+            .find(|mute| mute.target_id == target_id);
+        match existing {
+            Some(mute) => {
+                diesel::update(&mute)
+                    .set(mutes::expires_at.eq(expires_at))
+                    .execute(conn)?;
+                Self::get(conn, mute.id)
+            }
+            None => Self::insert(
+                conn,
+                NewMute {
+                    user_id: user.id,
+                    kind: kind as i32,
+                    target_id,
+                    value,
+                    expires_at,
+                },
+            ),
+        }
+    }
+
+    /// The mutes of a user that did not expire yet.
+    pub fn list_active(conn: &Connection, user_id: i32) -> Result<Vec<Self>> {
+        Self::active_query(user_id)
+            .order(mutes::creation_date.desc())
+            .load::<Self>(conn)
+            .map_err(Error::from)
+    }
+
+    /// A page of the mutes of a user that did not expire yet, as API clients ask for it.
+    pub fn list_active_page(conn: &Connection, user_id: i32, cursor: Cursor) -> Result<Vec<Self>> {
+        paginate!(Self::active_query(user_id), mutes, cursor)
+            .load::<Self>(conn)
+            .map(|page| cursor.finish(page))
+            .map_err(Error::from)
+    }
+
+    fn active_query(user_id: i32) -> mutes::BoxedQuery<'static, Backend> {
+        mutes::table
+            .filter(mutes::user_id.eq(user_id))
+            .filter(
+                mutes::expires_at
+                    .is_null()
+                    .or(mutes::expires_at.gt(Utc::now().naive_utc())),
+            )
+            .into_boxed()
+    }
+
+    /// Forgets the mutes that expired.
+    pub fn purge_expired(conn: &Connection) -> Result<usize> {
+        diesel::delete(mutes::table.filter(mutes::expires_at.lt(Utc::now().naive_utc())))
+            .execute(conn)
+            .map_err(Error::from)
+    }
+
+    pub fn mute_kind(&self) -> Option<MuteKind> {
+        MuteKind::from_i32(self.kind)
+    }
+
+    pub fn delete(&self, conn: &Connection) -> Result<()> {
+        diesel::delete(self)
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+}
+
+/// The active mutes of a user, loaded once to filter many things.
+#[derive(Clone, Default)]
+pub struct Mutes {
+    accounts: Vec<i32>,
+    domains: Vec<String>,
+    keywords: Vec<String>,
+}
+
+impl Mutes {
+    pub fn for_user(conn: &Connection, user_id: i32) -> Result<Self> {
+        let mut mutes = Self::default();
+        for mute in Mute::list_active(conn, user_id)? {
+            match mute.mute_kind() {
+                Some(MuteKind::Account) => mutes.accounts.extend(mute.target_id),
+                Some(MuteKind::Domain) => mutes.domains.push(mute.value),
+                Some(MuteKind::Keyword) => mutes.keywords.push(mute.value),
+                None => {}
+            }
+        }
+        Ok(mutes)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty() && self.domains.is_empty() && self.keywords.is_empty()
+    }
+
+    /// Whether the account, or its instance, is muted.
+    pub fn hides_account(&self, conn: &Connection, account: &User) -> Result<bool> {
+        if self.accounts.contains(&account.id) {
+            return Ok(true);
+        }
+        if self.domains.is_empty() {
+            return Ok(false);
+        }
+        let domain = account.get_instance(conn)?.public_domain.to_lowercase();
+        Ok(self.domains.contains(&domain))
+    }
+
+    /// Whether the text, that can be HTML, contains a muted keyword as whole words.
+    pub fn hides_text(&self, text: &str) -> bool {
+        if self.keywords.is_empty() {
+            return false;
+        }
+        let text = format!(" {} ", words(&plain_text(text)));
+        self.keywords
+            .iter()
+            .map(|keyword| words(keyword))
+            .any(|keyword| !keyword.is_empty() && text.contains(&format!(" {} ", keyword)))
+    }
+
+    /// Whether an article is written by a muted account, or talks about a muted keyword.
+    pub fn hides_post(&self, conn: &Connection, post: &Post) -> Result<bool> {
+        if self.hides_text(&post.title)
+            || self.hides_text(post.summary())
+            || self.hides_text(post.content.get())
+        {
+            return Ok(true);
+        }
+        if self.accounts.is_empty() && self.domains.is_empty() {
+            return Ok(false);
+        }
+        diesel::select(diesel::dsl::exists(
+            post_authors::table
+                .filter(post_authors::post_id.eq(post.id))
+                .filter(post_authors::author_id.eq_any(self.muted_accounts())),
+        ))
+        .get_result(conn)
+        .map_err(Error::from)
+    }
+
+    /// The IDs of the hidden articles, to leave them out of a query.
+    ///
+    /// Keywords are looked for in what authors wrote, as HTML can't be read in SQL: the
+    /// articles of remote authors that don't send their source are only hidden for their
+    /// title, subtitle and excerpt.
+    pub fn hidden_posts(&self) -> posts::BoxedQuery<'static, Backend, Integer> {
+        let mut hidden = posts::table
+            .select(posts::id)
+            .filter(
+                posts::id.eq_any(
+                    post_authors::table
+                        .filter(post_authors::author_id.eq_any(self.muted_accounts()))
+                        .select(post_authors::post_id),
+                ),
+            )
+            .into_boxed();
+        for keyword in &self.keywords {
+            let keyword = words(keyword);
+            if !keyword.is_empty() {
+                hidden = hidden.or_filter(post_words().like(format!("% {} %", keyword)));
+            }
+        }
+        hidden
+    }
+
+    /// The muted accounts, and the accounts of the muted instances.
+    fn muted_accounts(&self) -> users::BoxedQuery<'static, Backend, Integer> {
+        users::table
+            .select(users::id)
+            .filter(users::id.eq_any(self.accounts.clone()))
+            .or_filter(
+                users::instance_id.eq_any(
+                    instances::table
+                        .filter(lower(instances::public_domain).eq_any(self.domains.clone()))
+                        .select(instances::id),
+                ),
+            )
+            .into_boxed()
+    }
+
+    /// Whether `user_id` muted the author of a notification, or one of the keywords of its
+    /// `text`, so that they are not notified.
+    pub fn silence(conn: &Connection, user_id: i32, actor: &User, text: &str) -> Result<bool> {
+        let mutes = Self::for_user(conn, user_id)?;
+        Ok(mutes.hides_text(text) || mutes.hides_account(conn, actor)?)
+    }
+}
+
+/// Characters that separate words, besides whitespace.
+const SEPARATORS: &str = ".,;:!?()[]{}<>\"'`*_#/\\|~+-=&@%^$";
+
+sql_function!(fn lower(text: Text) -> Text);
+sql_function!(fn replace(text: Text, from: Text, to: Text) -> Text);
+
+/// The text of an article, in lowercase words separated by single spaces, the way `words`
+/// writes them, and with a space at each end.
+fn post_words() -> Box<dyn BoxableExpression<posts::table, Backend, SqlType = Text>> {
+    let mut text: Box<dyn BoxableExpression<posts::table, Backend, SqlType = Text>> =
+        Box::new(lower(
+            " ".into_sql::<Text>()
+                .concat(posts::title)
+                .concat(" ")
+                .concat(posts::subtitle)
+                .concat(" ")
+                .concat(posts::excerpt)
+                .concat(" ")
+                .concat(posts::source)
+                .concat(" "),
+        ));
+    for separator in SEPARATORS.chars().chain("\n\r\t".chars()) {
+        text = Box::new(replace(text, separator.to_string(), " "));
+    }
+    // Separators can follow each other: they are merged a few times, enough for most texts
+    for _ in 0..3 {
+        text = Box::new(replace(text, "  ", " "));
+    }
+    text
+}
+
+/// The words of `text`, in lowercase and separated by single spaces.
+fn words(text: &str) -> String {
+    text.split(|c: char| c.is_whitespace() || SEPARATORS.contains(c))
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Removes the tags and entities of HTML, leaving spaces instead.
+fn plain_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    let mut chars = html.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            '&' if !in_tag => {
+                let entity = chars
+                    .clone()
+                    .take(10)
+                    .take_while(|c| c.is_ascii_alphanumeric() || *c == '#')
+                    .count();
+                if chars.clone().nth(entity) == Some(';') {
+                    for _ in 0..=entity {
+                        chars.next();
+                    }
+                }
+                text.push(' ');
+            }
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text
+}
+
+fn normalize(value: &str) -> Result<String> {
+    Some(value.trim().to_lowercase())
+        .filter(|v| !v.is_empty() && v.chars().count() <= MAX_KEYWORD_LENGTH)
+        .ok_or(Error::InvalidValue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{inbox::tests::fill_database, tests::db};
+    use chrono::Duration;
+    use diesel::Connection;
+
+    #[test]
+    fn mute_and_expire() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, users, _) = fill_database(conn);
+            let (user, author) = (&users[1], &users[0]);
+            assert!(!Mutes::for_user(conn, user.id)?.hides_post(conn, &posts[0])?);
+            // Users can't mute themselves, nor in the past
+            assert!(Mute::add(conn, user, MuteTarget::Account(user.id), None).is_err());
+            let yesterday = Utc::now().naive_utc() - Duration::days(1);
+            assert!(
+                Mute::add(conn, user, MuteTarget::Keyword("a".into()), Some(yesterday)).is_err()
+            );
+
+            let mute = Mute::add(conn, user, MuteTarget::Account(author.id), None)?;
+            assert!(Mutes::for_user(conn, user.id)?.hides_post(conn, &posts[0])?);
+            // Only for the user who muted
+            assert!(!Mutes::for_user(conn, author.id)?.hides_post(conn, &posts[0])?);
+            mute.delete(conn)?;
+
+            let domain = author.get_instance(conn)?.public_domain;
+            let tomorrow = Utc::now().naive_utc() + Duration::days(1);
+            let mute = Mute::add(
+                conn,
+                user,
+                MuteTarget::Domain(domain.to_uppercase()),
+                Some(tomorrow),
+            )?;
+            assert_eq!(mute.value, domain.to_lowercase());
+            assert!(Mutes::for_user(conn, user.id)?.hides_account(conn, author)?);
+            mute.delete(conn)?;
+
+            let mute = Mute::add(conn, user, MuteTarget::Keyword(" TESTING ".into()), None)?;
+            let mutes = Mutes::for_user(conn, user.id)?;
+            assert!(mutes.hides_post(conn, &posts[0])?);
+            let shown = posts::table
+                .filter(posts::id.ne_all(mutes.hidden_posts()))
+                .count()
+                .get_result::<i64>(conn)?;
+            assert_eq!(shown as usize, posts.len() - 1);
+
+            // Muting again changes the expiry
+            let again = Mute::add(
+                conn,
+                user,
+                MuteTarget::Keyword("testing".into()),
+                Some(tomorrow),
+            )?;
+            assert_eq!(again.id, mute.id);
+            assert_eq!(again.expires_at, Some(tomorrow));
+
+            // Expired mutes are ignored
+            diesel::update(&again)
+                .set(mutes::expires_at.eq(Some(yesterday)))
+                .execute(conn)?;
+            assert!(Mutes::for_user(conn, user.id)?.is_empty());
+            assert_eq!(Mute::purge_expired(conn)?, 1);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn whole_words() {
+        let mutes = Mutes {
+            keywords: vec!["p".into(), "cat".into(), "new york".into()],
+            ..Mutes::default()
+        };
+        assert!(!mutes.hides_text(r#"<p class="x"><a href="/">Concatenate</a> &amp; more</p>"#));
+        assert!(mutes.hides_text("<p>My <em>cat</em>.</p>"));
+        assert!(mutes.hides_text("Flying to New\nYork!"));
+        assert!(!mutes.hides_text("New Yorkers"));
+    }
+}
@@ -0,0 +1,138 @@
+//! Friendly instances, whose public articles are gathered in the `neighborhood` timeline
+//! source.
+//!
+//! Following an instance means that the actor of this instance follows the actor of the
+//! other one. Plume sends public articles to all the instances it knows of, so the articles
+//! of a friendly instance are then received even if nobody here follows their authors.
+
+use crate::{
+    follows::{Follow, NewFollow},
+    instance::{Instance, LOCAL_INSTANCE_USERNAME},
+    schema::{follows, instances, users},
+    users::User,
+    Connection, Error, Result, CONFIG,
+};
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+use plume_common::activity_pub::broadcast;
+
+/// The actor of another Plume instance, fetching it if needed.
+fn remote_actor(conn: &Connection, domain: &str) -> Result<User> {
+    User::find_by_fqn(conn, &format!("{}@{}", LOCAL_INSTANCE_USERNAME, domain))
+}
+
+/// Starts following the instance at `domain`. It has to be a Plume instance.
+pub fn follow(conn: &Connection, domain: &str) -> Result<Instance> {
+    let actor = remote_actor(conn, domain)?;
+    let instance = actor.get_instance(conn)?;
+    if instance.local || instance.blocked {
+        return Err(Error::InvalidValue);
+    }
+    let sender = Instance::get_local_instance_user_uncached(conn)?;
+    if Follow::find(conn, sender.id, actor.id).is_ok() {
+        return Ok(instance);
+    }
+    let follow = Follow::insert(
+        conn,
+        NewFollow {
+            follower_id: sender.id,
+            following_id: actor.id,
+            ap_url: String::new(),
+        },
+    )?;
+    let act = follow.to_activity(conn)?;
+    broadcast(&sender, act, vec![actor], CONFIG.proxy().cloned());
+    Ok(instance)
+}
+
+/// Stops following an instance.
+pub fn unfollow(conn: &Connection, instance: &Instance) -> Result<()> {
+    let sender = Instance::get_local_instance_user_uncached(conn)?;
+    let actor = users::table
+        .filter(users::instance_id.eq(instance.id))
+        .filter(users::username.eq(LOCAL_INSTANCE_USERNAME))
+        .first::<User>(conn)?;
+    let follow = Follow::find(conn, sender.id, actor.id)?;
+    let undo = follow.build_undo(conn)?;
+    diesel::delete(&follow).execute(conn)?;
+    broadcast(&sender, undo, vec![actor], CONFIG.proxy().cloned());
+    Ok(())
+}
+
+/// The IDs of the instances this one follows.
+pub fn instance_ids(conn: &Connection) -> Result<Vec<i32>> {
+    let local_actor = Instance::get_local_instance_user_uncached(conn)?;
+    users::table
+        .filter(
+            users::id.eq_any(
+                follows::table
+                    .filter(follows::follower_id.eq(local_actor.id))
+                    .select(follows::following_id),
+            ),
+        )
+        .filter(users::username.eq(LOCAL_INSTANCE_USERNAME))
+        .select(users::instance_id)
+        .load(conn)
+        .map_err(Error::from)
+}
+
+/// The instances this one follows.
+pub fn list(conn: &Connection) -> Result<Vec<Instance>> {
+    instances::table
+        .filter(instances::id.eq_any(instance_ids(conn)?))
+        .order(instances::public_domain.asc())
+        .load(conn)
+        .map_err(Error::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        instance::tests as instance_tests, safe_string::SafeString, tests::db, users::NewUser,
+    };
+    use diesel::Connection;
+
+    #[test]
+    fn neighborhood() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let remote = instance_tests::fill_database(conn)
+                .into_iter()
+                .map(|(_, instance)| instance)
+                .find(|instance| !instance.local)
+                .unwrap();
+            let actor = User::insert(
+                conn,
+                NewUser {
+                    username: LOCAL_INSTANCE_USERNAME.to_owned(),
+                    display_name: remote.public_domain.clone(),
+                    outbox_url: format!("https://{}/@/__instance__/outbox", remote.public_domain),
+                    inbox_url: format!("https://{}/@/__instance__/inbox", remote.public_domain),
+                    instance_id: remote.id,
+                    ap_url: format!("https://{}/@/__instance__/", remote.public_domain),
+                    followers_endpoint: format!(
+                        "https://{}/@/__instance__/followers",
+                        remote.public_domain
+                    ),
+                    summary_html: SafeString::new(""),
+                    role: 2,
+                    fqn: format!("__instance__@{}", remote.public_domain),
+                    ..NewUser::default()
+                },
+            )?;
+            assert!(list(conn)?.is_empty());
+
+            assert_eq!(follow(conn, &remote.public_domain)?.id, remote.id);
+            assert_eq!(instance_ids(conn)?, vec![remote.id]);
+            // Following again changes nothing
+            follow(conn, &remote.public_domain)?;
+            assert_eq!(list(conn)?.len(), 1);
+
+            unfollow(conn, &remote)?;
+            assert!(list(conn)?.is_empty());
+            let local_actor = Instance::get_local_instance_user_uncached(conn)?;
+            assert!(Follow::find(conn, local_actor.id, actor.id).is_err());
+            Ok(())
+        });
+    }
+}
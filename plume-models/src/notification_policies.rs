@@ -0,0 +1,249 @@
+//! What users want to do with the notifications of accounts they may not know: show them
+//! as usual, file them apart to review them later, or not be notified at all.
+
+use crate::{mutes::Mutes, schema::notification_policies, users::User, Connection, Error, Result};
+use chrono::{Duration, Utc};
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+
+/// Accounts younger than this are new.
+pub const NEW_ACCOUNT_DAYS: i64 = 30;
+
+/// What to do with a notification, from the most to the least lenient.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PolicyAction {
+    Accept = 0,
+    /// Filed apart, with the filtered notifications
+    Filter = 1,
+    Drop = 2,
+}
+
+impl PolicyAction {
+    pub fn from_i32(action: i32) -> Option<Self> {
+        match action {
+            0 => Some(PolicyAction::Accept),
+            1 => Some(PolicyAction::Filter),
+            2 => Some(PolicyAction::Drop),
+            _ => None,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "accept" => Some(PolicyAction::Accept),
+            "filter" => Some(PolicyAction::Filter),
+            "drop" => Some(PolicyAction::Drop),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PolicyAction::Accept => "accept",
+            PolicyAction::Filter => "filter",
+            PolicyAction::Drop => "drop",
+        }
+    }
+}
+
+#[derive(Clone, Queryable, Identifiable)]
+#[table_name = "notification_policies"]
+pub struct NotificationPolicy {
+    pub id: i32,
+    pub user_id: i32,
+    /// For the accounts the user doesn't follow, a `PolicyAction`
+    pub not_following: i32,
+    /// For the accounts created less than `NEW_ACCOUNT_DAYS` ago
+    pub new_accounts: i32,
+    /// For the accounts without an avatar
+    pub no_avatar: i32,
+}
+
+#[derive(Insertable, AsChangeset)]
+#[table_name = "notification_policies"]
+pub struct NewNotificationPolicy {
+    pub user_id: i32,
+    pub not_following: i32,
+    pub new_accounts: i32,
+    pub no_avatar: i32,
+}
+
+impl NotificationPolicy {
+    insert!(notification_policies, NewNotificationPolicy);
+    get!(notification_policies);
+    find_by!(notification_policies, find_by_user, user_id as i32);
+
+    /// The policy of a user, accepting everything if they didn't choose one.
+    pub fn for_user(conn: &Connection, user_id: i32) -> Result<Self> {
+        match Self::find_by_user(conn, user_id) {
+            Err(Error::NotFound) => Ok(NotificationPolicy {
+                id: 0,
+                user_id,
+                not_following: PolicyAction::Accept as i32,
+                new_accounts: PolicyAction::Accept as i32,
+                no_avatar: PolicyAction::Accept as i32,
+            }),
+            policy => policy,
+        }
+    }
+
+    pub fn set(
+        conn: &Connection,
+        user_id: i32,
+        not_following: PolicyAction,
+        new_accounts: PolicyAction,
+        no_avatar: PolicyAction,
+    ) -> Result<Self> {
+        let new = NewNotificationPolicy {
+            user_id,
+            not_following: not_following as i32,
+            new_accounts: new_accounts as i32,
+            no_avatar: no_avatar as i32,
+        };
+        match Self::find_by_user(conn, user_id) {
+            Ok(policy) => {
+                diesel::update(&policy).set(&new).execute(conn)?;
+                Self::get(conn, policy.id)
+            }
+            Err(Error::NotFound) => Self::insert(conn, new),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn for_not_following(&self) -> PolicyAction {
+        PolicyAction::from_i32(self.not_following).unwrap_or(PolicyAction::Accept)
+    }
+
+    pub fn for_new_accounts(&self) -> PolicyAction {
+        PolicyAction::from_i32(self.new_accounts).unwrap_or(PolicyAction::Accept)
+    }
+
+    pub fn for_no_avatar(&self) -> PolicyAction {
+        PolicyAction::from_i32(self.no_avatar).unwrap_or(PolicyAction::Accept)
+    }
+
+    /// What to do with the notifications of `actor`, the strictest rule that applies winning.
+    pub fn action_for(&self, conn: &Connection, actor: &User) -> Result<PolicyAction> {
+        let mut action = PolicyAction::Accept;
+        if self.for_no_avatar() > action && actor.avatar_id.is_none() {
+            action = self.for_no_avatar();
+        }
+        let created = actor.published.unwrap_or(actor.creation_date);
+        if self.for_new_accounts() > action
+            && created > Utc::now().naive_utc() - Duration::days(NEW_ACCOUNT_DAYS)
+        {
+            action = self.for_new_accounts();
+        }
+        if self.for_not_following() > action && !actor.is_followed_by(conn, self.user_id)? {
+            action = self.for_not_following();
+        }
+        Ok(action)
+    }
+
+    /// What to do with a notification of `actor` for `user_id`, dropping it if they muted
+    /// `actor` or a keyword of `text`.
+    pub fn screen(
+        conn: &Connection,
+        user_id: i32,
+        actor: &User,
+        text: &str,
+    ) -> Result<PolicyAction> {
+        if Mutes::silence(conn, user_id, actor, text)? {
+            return Ok(PolicyAction::Drop);
+        }
+        Self::for_user(conn, user_id)?.action_for(conn, actor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        follows::{Follow, NewFollow},
+        notifications::Notification,
+        tests::db,
+        users::tests as user_tests,
+        Cursor,
+    };
+    use diesel::Connection;
+
+    #[test]
+    fn filter_and_drop() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let users = user_tests::fill_database(conn);
+            let (user, stranger, friend) = (&users[0], &users[1], &users[2]);
+            let follow = |follower: &User, following: &User| {
+                Follow::insert(
+                    conn,
+                    NewFollow {
+                        follower_id: follower.id,
+                        following_id: following.id,
+                        ap_url: format!("{}/follows/{}", follower.ap_url, following.id),
+                    },
+                )?
+                .notify(conn)
+            };
+            Follow::insert(
+                conn,
+                NewFollow {
+                    follower_id: user.id,
+                    following_id: friend.id,
+                    ap_url: "https://plu.me/follows/policies".to_owned(),
+                },
+            )?;
+
+            // Everything is accepted by default
+            assert_eq!(
+                NotificationPolicy::screen(conn, user.id, stranger, "")?,
+                PolicyAction::Accept
+            );
+            let policy = NotificationPolicy::set(
+                conn,
+                user.id,
+                PolicyAction::Filter,
+                PolicyAction::Accept,
+                PolicyAction::Accept,
+            )?;
+            assert_eq!(policy.for_not_following(), PolicyAction::Filter);
+            follow(stranger, user)?;
+            follow(friend, user)?;
+            let cursor = Cursor {
+                max_id: None,
+                min_id: None,
+                limit: 10,
+            };
+            assert_eq!(Notification::count_for_user(conn, user, &[])?, 1);
+            let filtered = Notification::list_filtered(conn, user, cursor)?;
+            assert_eq!(filtered.len(), 1);
+            assert_eq!(filtered[0].get_actor(conn)?.id, stranger.id);
+
+            // The strictest rule wins, and the policy can be changed
+            let policy = NotificationPolicy::set(
+                conn,
+                user.id,
+                PolicyAction::Filter,
+                PolicyAction::Drop,
+                PolicyAction::Accept,
+            )?;
+            assert_eq!(policy.action_for(conn, stranger)?, PolicyAction::Drop);
+            assert_eq!(
+                NotificationPolicy::for_user(conn, user.id)?.id,
+                NotificationPolicy::find_by_user(conn, user.id)?.id
+            );
+
+            // Filtered notifications are not read with the others
+            assert_eq!(
+                Notification::mark_read(conn, user, Some(&[filtered[0].id]))?,
+                0
+            );
+
+            // They can be accepted, and then join their group
+            assert_eq!(Notification::accept_filtered(conn, user, None)?, 1);
+            let notifications = Notification::page_for_user(conn, user, &[], (0, 10))?;
+            assert_eq!(notifications.len(), 1);
+            assert_eq!(notifications[0].count, 2);
+            assert!(Notification::list_filtered(conn, user, cursor)?.is_empty());
+            Ok(())
+        });
+    }
+}
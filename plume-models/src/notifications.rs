@@ -1,23 +1,106 @@
 use crate::{
+    comment_likes::CommentLike,
     comments::Comment,
+    db_conn::write_transaction,
     follows::Follow,
     likes::Like,
+    medias::Media,
     mentions::Mention,
+    notification_policies::{NotificationPolicy, PolicyAction},
     posts::Post,
     reshares::Reshare,
-    schema::{follows, notifications},
+    schema::{comment_likes, follows, likes, notifications, reshares},
+    severed_relationships::Severance,
     users::User,
-    Connection, Error, Result,
+    Connection, Cursor, Error, Result,
 };
 use chrono::NaiveDateTime;
-use diesel::{self, ExpressionMethods, JoinOnDsl, QueryDsl, RunQueryDsl};
+use diesel::{
+    self, dsl::max, ExpressionMethods, JoinOnDsl, OptionalExtension, QueryDsl, RunQueryDsl,
+};
 
 pub mod notification_kind {
     pub const COMMENT: &str = "COMMENT";
+    pub const COMMENT_LIKE: &str = "COMMENT_LIKE";
+    /// Recurring federation failures, for the admins
+    pub const FEDERATION_DIGEST: &str = "FEDERATION_DIGEST";
     pub const FOLLOW: &str = "FOLLOW";
     pub const LIKE: &str = "LIKE";
+    pub const MEDIA_QUARANTINED: &str = "MEDIA_QUARANTINED";
     pub const MENTION: &str = "MENTION";
     pub const RESHARE: &str = "RESHARE";
+    /// Follows lost because of a block between instances
+    pub const SEVERED_RELATIONSHIPS: &str = "SEVERED_RELATIONSHIPS";
+    pub const THREAD_COMMENT: &str = "THREAD_COMMENT";
+}
+
+/// What a notification is about, each type grouping one or more kinds of notifications.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NotificationType {
+    Mention,
+    Comment,
+    Follow,
+    Like,
+    Boost,
+    /// Something for the moderators to look at
+    Report,
+    /// Messages from the instance itself
+    System,
+}
+
+impl NotificationType {
+    pub const ALL: [NotificationType; 7] = [
+        NotificationType::Mention,
+        NotificationType::Comment,
+        NotificationType::Follow,
+        NotificationType::Like,
+        NotificationType::Boost,
+        NotificationType::Report,
+        NotificationType::System,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|t| t.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NotificationType::Mention => "mention",
+            NotificationType::Comment => "comment",
+            NotificationType::Follow => "follow",
+            NotificationType::Like => "like",
+            NotificationType::Boost => "boost",
+            NotificationType::Report => "report",
+            NotificationType::System => "system",
+        }
+    }
+
+    /// The kinds of notifications of this type.
+    pub fn kinds(self) -> &'static [&'static str] {
+        match self {
+            NotificationType::Mention => &[notification_kind::MENTION],
+            NotificationType::Comment => &[
+                notification_kind::COMMENT,
+                notification_kind::THREAD_COMMENT,
+            ],
+            NotificationType::Follow => &[notification_kind::FOLLOW],
+            NotificationType::Like => &[notification_kind::LIKE, notification_kind::COMMENT_LIKE],
+            NotificationType::Boost => &[notification_kind::RESHARE],
+            NotificationType::Report => &[notification_kind::MEDIA_QUARANTINED],
+            NotificationType::System => &[
+                notification_kind::FEDERATION_DIGEST,
+                notification_kind::SEVERED_RELATIONSHIPS,
+            ],
+        }
+    }
+
+    pub fn of_kind(kind: &str) -> Self {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|t| t.kinds().contains(&kind))
+            .unwrap_or(NotificationType::System)
+    }
 }
 
 #[derive(Clone, Queryable, Identifiable)]
@@ -27,6 +110,13 @@ pub struct Notification {
     pub creation_date: NaiveDateTime,
     pub kind: String,
     pub object_id: i32,
+    pub read: bool,
+    /// Notifications with the same key are grouped while they are unread
+    pub group_key: Option<String>,
+    /// How many times it happened in this group, `object_id` being the last one
+    pub count: i32,
+    /// Filed apart because of the notification policy of the user, until they accept it
+    pub filtered: bool,
 }
 
 #[derive(Insertable)]
@@ -35,12 +125,175 @@ pub struct NewNotification {
     pub user_id: i32,
     pub kind: String,
     pub object_id: i32,
+    /// Set by `insert_grouped` and `notify_from`
+    pub group_key: Option<String>,
+    pub filtered: bool,
 }
 
 impl Notification {
     insert!(notifications, NewNotification);
     get!(notifications);
 
+    /// Notifies of something that happened to `target` (the liked article for instance),
+    /// adding it to the unread notification of the same kind about `target` if there is one.
+    ///
+    /// A group keeps the date of its first notification, so that it stays at the same place
+    /// in the lists, sorted by date or by ID.
+    pub fn insert_grouped(
+        conn: &Connection,
+        new: NewNotification,
+        target: i32,
+    ) -> Result<Notification> {
+        let key = format!("{}:{}", new.kind, target);
+        let group = notifications::table
+            .filter(notifications::user_id.eq(new.user_id))
+            .filter(notifications::group_key.eq(&key))
+            .filter(notifications::read.eq(false))
+            .filter(notifications::filtered.eq(false))
+            .first::<Notification>(conn)
+            .optional()?;
+        if let Some(group) = group {
+            diesel::update(&group)
+                .set((
+                    notifications::object_id.eq(new.object_id),
+                    notifications::count.eq(notifications::count + 1),
+                ))
+                .execute(conn)?;
+            return Self::get(conn, group.id);
+        }
+        Self::insert(
+            conn,
+            NewNotification {
+                group_key: Some(key),
+                ..new
+            },
+        )
+    }
+
+    /// Notifies of something `actor` did, as the policy of the notified user says: filtered
+    /// notifications are filed apart, and only join their group once accepted.
+    ///
+    /// `target` is what to group the notification by, if it can be grouped, and `text` what
+    /// `actor` wrote, to drop the notification if it contains a muted keyword.
+    pub fn notify_from(
+        conn: &Connection,
+        actor: &User,
+        text: &str,
+        new: NewNotification,
+        target: Option<i32>,
+    ) -> Result<()> {
+        match (
+            NotificationPolicy::screen(conn, new.user_id, actor, text)?,
+            target,
+        ) {
+            (PolicyAction::Drop, _) => Ok(()),
+            (PolicyAction::Filter, target) => Self::insert(
+                conn,
+                NewNotification {
+                    group_key: target.map(|target| format!("{}:{}", new.kind, target)),
+                    filtered: true,
+                    ..new
+                },
+            )
+            .map(|_| ()),
+            (PolicyAction::Accept, Some(target)) => {
+                Self::insert_grouped(conn, new, target).map(|_| ())
+            }
+            (PolicyAction::Accept, None) => Self::insert(conn, new).map(|_| ()),
+        }
+    }
+
+    /// Removes a grouped notification because its object was undone (or is about to be),
+    /// or takes it out of its group if others happened to `target` since.
+    ///
+    /// Objects that are not the last one of their group are only taken out of its count.
+    pub fn retract(conn: &Connection, kind: &str, object_id: i32, target: i32) -> Result<()> {
+        let key = format!("{}:{}", kind, target);
+        let groups = notifications::table
+            .filter(notifications::group_key.eq(&key))
+            .filter(notifications::read.eq(false))
+            .filter(notifications::object_id.gt(object_id))
+            .filter(notifications::count.gt(1))
+            .load::<Notification>(conn)?;
+        for group in groups {
+            // Unless it was in a previous group, that was read
+            let last_read = notifications::table
+                .filter(notifications::user_id.eq(group.user_id))
+                .filter(notifications::group_key.eq(&key))
+                .filter(notifications::read.eq(true))
+                .select(max(notifications::object_id))
+                .first::<Option<i32>>(conn)?;
+            if last_read.map_or(true, |last_read| last_read < object_id) {
+                diesel::update(&group)
+                    .set(notifications::count.eq(notifications::count - 1))
+                    .execute(conn)?;
+            }
+        }
+
+        let notifications = notifications::table
+            .filter(notifications::kind.eq(kind))
+            .filter(notifications::object_id.eq(object_id))
+            .load::<Notification>(conn)?;
+        for notification in notifications {
+            let previous = if notification.count > 1 {
+                Self::previous_object(conn, kind, object_id, target)?
+            } else {
+                None
+            };
+            match previous {
+                Some(previous) => diesel::update(&notification)
+                    .set((
+                        notifications::object_id.eq(previous),
+                        notifications::count.eq(notifications::count - 1),
+                    ))
+                    .execute(conn)?,
+                None => diesel::delete(&notification).execute(conn)?,
+            };
+        }
+        Ok(())
+    }
+
+    /// The last object of a group, other than `object_id`.
+    fn previous_object(
+        conn: &Connection,
+        kind: &str,
+        object_id: i32,
+        target: i32,
+    ) -> Result<Option<i32>> {
+        let previous = match kind {
+            notification_kind::COMMENT_LIKE => comment_likes::table
+                .filter(comment_likes::comment_id.eq(target))
+                .filter(comment_likes::id.ne(object_id))
+                .order(comment_likes::id.desc())
+                .select(comment_likes::id)
+                .first(conn)
+                .optional(),
+            notification_kind::FOLLOW => follows::table
+                .filter(follows::following_id.eq(target))
+                .filter(follows::id.ne(object_id))
+                .order(follows::id.desc())
+                .select(follows::id)
+                .first(conn)
+                .optional(),
+            notification_kind::LIKE => likes::table
+                .filter(likes::post_id.eq(target))
+                .filter(likes::id.ne(object_id))
+                .order(likes::id.desc())
+                .select(likes::id)
+                .first(conn)
+                .optional(),
+            notification_kind::RESHARE => reshares::table
+                .filter(reshares::post_id.eq(target))
+                .filter(reshares::id.ne(object_id))
+                .order(reshares::id.desc())
+                .select(reshares::id)
+                .first(conn)
+                .optional(),
+            _ => Ok(None),
+        };
+        previous.map_err(Error::from)
+    }
+
     pub fn find_for_user(conn: &Connection, user: &User) -> Result<Vec<Notification>> {
         notifications::table
             .filter(notifications::user_id.eq(user.id))
@@ -59,7 +312,10 @@ impl Notification {
 
     pub fn find_for_comment(conn: &Connection, comment: &Comment) -> Result<Vec<Notification>> {
         notifications::table
-            .filter(notifications::kind.eq(notification_kind::COMMENT))
+            .filter(notifications::kind.eq_any(&[
+                notification_kind::COMMENT,
+                notification_kind::THREAD_COMMENT,
+            ]))
             .filter(notifications::object_id.eq(comment.id))
             .load::<Notification>(conn)
             .map_err(Error::from)
@@ -75,9 +331,42 @@ impl Notification {
             .map_err(Error::from)
     }
 
-    pub fn count_for_user(conn: &Connection, user: &User) -> Result<i64> {
-        notifications::table
+    /// The notifications of a user, only of some types if `types` is not empty.
+    fn query_for_user(
+        user: &User,
+        types: &[NotificationType],
+        unread_only: bool,
+    ) -> notifications::BoxedQuery<'static, <Connection as diesel::Connection>::Backend> {
+        let mut query = notifications::table
             .filter(notifications::user_id.eq(user.id))
+            .filter(notifications::filtered.eq(false))
+            .into_boxed();
+        if !types.is_empty() {
+            let kinds = types
+                .iter()
+                .flat_map(|t| t.kinds().iter().copied())
+                .collect::<Vec<_>>();
+            query = query.filter(notifications::kind.eq_any(kinds));
+        }
+        if unread_only {
+            query = query.filter(notifications::read.eq(false));
+        }
+        query
+    }
+
+    pub fn count_for_user(
+        conn: &Connection,
+        user: &User,
+        types: &[NotificationType],
+    ) -> Result<i64> {
+        Self::query_for_user(user, types, false)
+            .count()
+            .get_result(conn)
+            .map_err(Error::from)
+    }
+
+    pub fn count_unread(conn: &Connection, user: &User) -> Result<i64> {
+        Self::query_for_user(user, &[], true)
             .count()
             .get_result(conn)
             .map_err(Error::from)
@@ -86,10 +375,10 @@ impl Notification {
     pub fn page_for_user(
         conn: &Connection,
         user: &User,
+        types: &[NotificationType],
         (min, max): (i32, i32),
     ) -> Result<Vec<Notification>> {
-        notifications::table
-            .filter(notifications::user_id.eq(user.id))
+        Self::query_for_user(user, types, false)
             .order_by(notifications::creation_date.desc())
             .offset(min.into())
             .limit((max - min).into())
@@ -97,6 +386,131 @@ impl Notification {
             .map_err(Error::from)
     }
 
+    /// The notifications of a user, as API clients ask for them.
+    pub fn list_for_user(
+        conn: &Connection,
+        user: &User,
+        types: &[NotificationType],
+        unread_only: bool,
+        cursor: Cursor,
+    ) -> Result<Vec<Notification>> {
+        paginate!(
+            Self::query_for_user(user, types, unread_only),
+            notifications,
+            cursor
+        )
+        .load::<Notification>(conn)
+        .map(|page| cursor.finish(page))
+        .map_err(Error::from)
+    }
+
+    /// The notifications of a user that their policy filtered, as API clients ask for them.
+    pub fn list_filtered(
+        conn: &Connection,
+        user: &User,
+        cursor: Cursor,
+    ) -> Result<Vec<Notification>> {
+        paginate!(
+            notifications::table
+                .filter(notifications::user_id.eq(user.id))
+                .filter(notifications::filtered.eq(true))
+                .into_boxed(),
+            notifications,
+            cursor
+        )
+        .load::<Notification>(conn)
+        .map(|page| cursor.finish(page))
+        .map_err(Error::from)
+    }
+
+    /// Moves filtered notifications of a user with the others, all of them if `ids` is `None`.
+    ///
+    /// The ones that can be grouped join the unread group of the same key, if there is one.
+    ///
+    /// Returns how many were moved.
+    pub fn accept_filtered(conn: &Connection, user: &User, ids: Option<&[i32]>) -> Result<usize> {
+        write_transaction(conn, || {
+            let mut query = notifications::table
+                .filter(notifications::user_id.eq(user.id))
+                .filter(notifications::filtered.eq(true))
+                .order(notifications::id.asc())
+                .into_boxed();
+            if let Some(ids) = ids {
+                query = query.filter(notifications::id.eq_any(ids));
+            }
+            let accepted = query.load::<Notification>(conn)?;
+            for notification in &accepted {
+                let group = match notification.group_key {
+                    Some(ref key) => notifications::table
+                        .filter(notifications::user_id.eq(user.id))
+                        .filter(notifications::group_key.eq(key))
+                        .filter(notifications::read.eq(false))
+                        .filter(notifications::filtered.eq(false))
+                        .first::<Notification>(conn)
+                        .optional()?,
+                    None => None,
+                };
+                match group {
+                    Some(group) => {
+                        diesel::update(&group)
+                            .set((
+                                notifications::object_id
+                                    .eq(group.object_id.max(notification.object_id)),
+                                notifications::count.eq(notifications::count + notification.count),
+                            ))
+                            .execute(conn)?;
+                        diesel::delete(notification).execute(conn)?;
+                    }
+                    None => {
+                        diesel::update(notification)
+                            .set(notifications::filtered.eq(false))
+                            .execute(conn)?;
+                    }
+                }
+            }
+            Ok(accepted.len())
+        })
+    }
+
+    /// Deletes filtered notifications of a user, all of them if `ids` is `None`.
+    ///
+    /// Returns how many were deleted.
+    pub fn dismiss_filtered(conn: &Connection, user: &User, ids: Option<&[i32]>) -> Result<usize> {
+        let query = notifications::table
+            .filter(notifications::user_id.eq(user.id))
+            .filter(notifications::filtered.eq(true));
+        let deleted = match ids {
+            Some(ids) => diesel::delete(query.filter(notifications::id.eq_any(ids))).execute(conn),
+            None => diesel::delete(query).execute(conn),
+        };
+        deleted.map_err(Error::from)
+    }
+
+    /// Marks notifications of a user as read, all of them if `ids` is `None`.
+    ///
+    /// Filtered notifications are left for the user to review.
+    ///
+    /// Returns how many were unread.
+    pub fn mark_read(conn: &Connection, user: &User, ids: Option<&[i32]>) -> Result<usize> {
+        let query = notifications::table
+            .filter(notifications::user_id.eq(user.id))
+            .filter(notifications::read.eq(false))
+            .filter(notifications::filtered.eq(false));
+        let updated = match ids {
+            Some(ids) => diesel::update(query.filter(notifications::id.eq_any(ids)))
+                .set(notifications::read.eq(true))
+                .execute(conn),
+            None => diesel::update(query)
+                .set(notifications::read.eq(true))
+                .execute(conn),
+        };
+        updated.map_err(Error::from)
+    }
+
+    pub fn notification_type(&self) -> NotificationType {
+        NotificationType::of_kind(&self.kind)
+    }
+
     pub fn find<S: Into<String>>(conn: &Connection, kind: S, obj: i32) -> Result<Notification> {
         notifications::table
             .filter(notifications::kind.eq(kind.into()))
@@ -107,9 +521,22 @@ impl Notification {
 
     pub fn get_url(&self, conn: &Connection) -> Option<String> {
         match self.kind.as_ref() {
-            notification_kind::COMMENT => self
+            notification_kind::COMMENT | notification_kind::THREAD_COMMENT => self
                 .get_post(conn)
                 .and_then(|p| Some(format!("{}#comment-{}", p.url(conn).ok()?, self.object_id))),
+            notification_kind::COMMENT_LIKE => CommentLike::get(conn, self.object_id)
+                .and_then(|like| Comment::get(conn, like.comment_id))
+                .and_then(|comment| {
+                    Ok(format!(
+                        "{}#comment-{}",
+                        comment.get_post(conn)?.url(conn)?,
+                        comment.id
+                    ))
+                })
+                .ok(),
+            notification_kind::FEDERATION_DIGEST => {
+                Some(format!("/admin/federation/{}", self.object_id))
+            }
             notification_kind::FOLLOW => Some(format!("/@/{}/", self.get_actor(conn).ok()?.fqn)),
             notification_kind::MENTION => Mention::get(conn, self.object_id)
                 .and_then(|mention| {
@@ -132,7 +559,13 @@ impl Notification {
 
     pub fn get_post(&self, conn: &Connection) -> Option<Post> {
         match self.kind.as_ref() {
-            notification_kind::COMMENT => Comment::get(conn, self.object_id)
+            notification_kind::COMMENT | notification_kind::THREAD_COMMENT => {
+                Comment::get(conn, self.object_id)
+                    .and_then(|comment| comment.get_post(conn))
+                    .ok()
+            }
+            notification_kind::COMMENT_LIKE => CommentLike::get(conn, self.object_id)
+                .and_then(|like| Comment::get(conn, like.comment_id))
                 .and_then(|comment| comment.get_post(conn))
                 .ok(),
             notification_kind::LIKE => Like::get(conn, self.object_id)
@@ -145,15 +578,32 @@ impl Notification {
         }
     }
 
+    pub fn get_severance(&self, conn: &Connection) -> Option<Severance> {
+        match self.kind.as_ref() {
+            notification_kind::SEVERED_RELATIONSHIPS => Severance::get(conn, self.object_id).ok(),
+            _ => None,
+        }
+    }
+
     pub fn get_actor(&self, conn: &Connection) -> Result<User> {
         Ok(match self.kind.as_ref() {
-            notification_kind::COMMENT => Comment::get(conn, self.object_id)?.get_author(conn)?,
+            notification_kind::COMMENT | notification_kind::THREAD_COMMENT => {
+                Comment::get(conn, self.object_id)?.get_author(conn)?
+            }
+            notification_kind::COMMENT_LIKE => {
+                User::get(conn, CommentLike::get(conn, self.object_id)?.user_id)?
+            }
+            notification_kind::FEDERATION_DIGEST => return Err(Error::NotFound),
             notification_kind::FOLLOW => {
                 User::get(conn, Follow::get(conn, self.object_id)?.follower_id)?
             }
             notification_kind::LIKE => User::get(conn, Like::get(conn, self.object_id)?.user_id)?,
+            notification_kind::MEDIA_QUARANTINED => {
+                User::get(conn, Media::get(conn, self.object_id)?.owner_id)?
+            }
             notification_kind::MENTION => Mention::get(conn, self.object_id)?.get_user(conn)?,
             notification_kind::RESHARE => Reshare::get(conn, self.object_id)?.get_user(conn)?,
+            notification_kind::SEVERED_RELATIONSHIPS => return Err(Error::NotFound),
             _ => unreachable!("Notification::get_actor: Unknow type"),
         })
     }
@@ -161,10 +611,15 @@ impl Notification {
     pub fn icon_class(&self) -> &'static str {
         match self.kind.as_ref() {
             notification_kind::COMMENT => "icon-message-circle",
+            notification_kind::COMMENT_LIKE => "icon-heart",
+            notification_kind::FEDERATION_DIGEST => "icon-activity",
             notification_kind::FOLLOW => "icon-user-plus",
             notification_kind::LIKE => "icon-heart",
+            notification_kind::MEDIA_QUARANTINED => "icon-alert-octagon",
             notification_kind::MENTION => "icon-at-sign",
             notification_kind::RESHARE => "icon-repeat",
+            notification_kind::SEVERED_RELATIONSHIPS => "icon-user-x",
+            notification_kind::THREAD_COMMENT => "icon-message-circle",
             _ => unreachable!("Notification::get_actor: Unknow type"),
         }
     }
@@ -176,3 +631,129 @@ impl Notification {
             .map_err(Error::from)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{follows::NewFollow, tests::db, users::tests::fill_database};
+    use diesel::Connection;
+
+    #[test]
+    fn types() {
+        for t in NotificationType::ALL.iter() {
+            assert_eq!(NotificationType::from_name(t.name()), Some(*t));
+            for kind in t.kinds() {
+                assert_eq!(NotificationType::of_kind(kind), *t);
+            }
+        }
+        assert_eq!(NotificationType::from_name("unknown"), None);
+    }
+
+    #[test]
+    fn filter_and_read() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let users = fill_database(conn);
+            let notify = |kind: &str| {
+                Notification::insert(
+                    conn,
+                    NewNotification {
+                        user_id: users[0].id,
+                        kind: kind.to_owned(),
+                        object_id: 0,
+                        group_key: None,
+                        filtered: false,
+                    },
+                )
+            };
+            let mention = notify(notification_kind::MENTION)?;
+            let comment = notify(notification_kind::COMMENT)?;
+            notify(notification_kind::THREAD_COMMENT)?;
+            assert_eq!(comment.notification_type(), NotificationType::Comment);
+
+            assert_eq!(Notification::count_for_user(conn, &users[0], &[])?, 3);
+            assert_eq!(
+                Notification::count_for_user(conn, &users[0], &[NotificationType::Comment])?,
+                2
+            );
+            assert_eq!(Notification::count_for_user(conn, &users[1], &[])?, 0);
+
+            assert_eq!(
+                Notification::mark_read(conn, &users[0], Some(&[mention.id]))?,
+                1
+            );
+            // Other users can't mark them as read
+            assert_eq!(Notification::mark_read(conn, &users[1], None)?, 0);
+            assert_eq!(Notification::count_unread(conn, &users[0])?, 2);
+            let cursor = Cursor {
+                max_id: None,
+                min_id: None,
+                limit: 10,
+            };
+            let unread = Notification::list_for_user(conn, &users[0], &[], true, cursor)?;
+            assert_eq!(unread.len(), 2);
+            assert!(unread[0].id > unread[1].id);
+            assert!(unread.iter().all(|n| !n.read));
+
+            assert_eq!(Notification::mark_read(conn, &users[0], None)?, 2);
+            assert_eq!(Notification::count_unread(conn, &users[0])?, 0);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn grouping() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let users = fill_database(conn);
+            let follow = |follower: &User| {
+                let follow = Follow::insert(
+                    conn,
+                    NewFollow {
+                        follower_id: follower.id,
+                        following_id: users[0].id,
+                        ap_url: String::new(),
+                    },
+                )?;
+                follow.notify(conn)?;
+                Ok::<_, Error>(follow)
+            };
+            let first = follow(&users[1])?;
+            let second = follow(&users[2])?;
+            let notifications = Notification::find_for_user(conn, &users[0])?;
+            assert_eq!(notifications.len(), 1);
+            assert_eq!(notifications[0].count, 2);
+            assert_eq!(notifications[0].object_id, second.id);
+
+            // The previous follow is shown again when the last one is undone
+            Notification::retract(conn, notification_kind::FOLLOW, second.id, users[0].id)?;
+            diesel::delete(&second).execute(&**conn)?;
+            let notification = Notification::find_for_user(conn, &users[0])?.remove(0);
+            assert_eq!(notification.count, 1);
+            assert_eq!(notification.object_id, first.id);
+
+            // Read notifications are not grouped anymore
+            Notification::mark_read(conn, &users[0], None)?;
+            follow(&users[2])?;
+            assert_eq!(Notification::find_for_user(conn, &users[0])?.len(), 2);
+
+            Notification::retract(conn, notification_kind::FOLLOW, first.id, users[0].id)?;
+            assert_eq!(Notification::find_for_user(conn, &users[0])?.len(), 1);
+
+            // Undoing an object that is not the last one of its group only changes its count
+            let third = Notification::find_for_user(conn, &users[0])?.remove(0);
+            let fourth = follow(&users[1])?;
+            Notification::retract(
+                conn,
+                notification_kind::FOLLOW,
+                third.object_id,
+                users[0].id,
+            )?;
+            let notification = Notification::find_for_user(conn, &users[0])?.remove(0);
+            assert_eq!(notification.id, third.id);
+            assert_eq!(notification.count, 1);
+            assert_eq!(notification.object_id, fourth.id);
+            Ok(())
+        });
+    }
+}
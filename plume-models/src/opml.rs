@@ -0,0 +1,274 @@
+//! Lists of followed authors in the OPML format, used by feed readers.
+//!
+//! Each author is exported with the Atom feed of their articles on this instance, which
+//! also works for remote authors. Importing a list follows the authors it contains, from
+//! the feed URLs of Plume instances or from the address of their profile.
+
+use crate::{
+    follows::{Follow, NewFollow},
+    instance::Instance,
+    users::User,
+    Connection, Error, Result, CONFIG,
+};
+use chrono::Utc;
+use plume_common::activity_pub::{broadcast, inbox::FromId};
+use quick_xml::{
+    events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event},
+    Reader, Writer,
+};
+use rocket::http::RawStr;
+use tracing::{info, warn};
+use url::Url;
+
+/// The largest number of subscriptions read from a file.
+pub const MAX_SUBSCRIPTIONS: usize = 500;
+
+/// An `outline` of an OPML file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Subscription {
+    pub title: String,
+    /// The address of the feed
+    pub feed_url: Option<String>,
+    /// The address of the page of the author
+    pub page_url: Option<String>,
+}
+
+/// What happened to the subscriptions of an imported file.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub followed: usize,
+    pub already_followed: usize,
+    /// The titles of the subscriptions that don't match any author
+    pub failed: Vec<String>,
+}
+
+/// The authors followed by `user`, as an OPML file.
+pub fn export(conn: &Connection, user: &User) -> Result<String> {
+    let local = Instance::get_local()?;
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+    let mut write = |event: Event<'_>| writer.write_event(event).map_err(|_| Error::InvalidValue);
+
+    write(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+    write(Event::Start(
+        BytesStart::new("opml").with_attributes([("version", "2.0")]),
+    ))?;
+    write(Event::Start(BytesStart::new("head")))?;
+    write(Event::Start(BytesStart::new("title")))?;
+    write(Event::Text(BytesText::new(&format!(
+        "Authors followed by {} on {}",
+        user.name(),
+        local.name
+    ))))?;
+    write(Event::End(BytesEnd::new("title")))?;
+    write(Event::Start(BytesStart::new("dateCreated")))?;
+    write(Event::Text(BytesText::new(&Utc::now().to_rfc2822())))?;
+    write(Event::End(BytesEnd::new("dateCreated")))?;
+    write(Event::End(BytesEnd::new("head")))?;
+    write(Event::Start(BytesStart::new("body")))?;
+    for author in user.get_followed(conn)? {
+        let name = author.name();
+        let feed = local.compute_box("@", &author.fqn, "atom.xml");
+        write(Event::Empty(BytesStart::new("outline").with_attributes([
+            ("type", "rss"),
+            ("text", name.as_str()),
+            ("title", name.as_str()),
+            ("xmlUrl", feed.as_str()),
+            ("htmlUrl", author.ap_url.as_str()),
+        ])))?;
+    }
+    write(Event::End(BytesEnd::new("body")))?;
+    write(Event::End(BytesEnd::new("opml")))?;
+    String::from_utf8(writer.into_inner()).map_err(|_| Error::InvalidValue)
+}
+
+/// Reads the subscriptions of an OPML file, including the ones in folders.
+///
+/// Only the first `MAX_SUBSCRIPTIONS` are kept.
+pub fn parse(opml: &[u8]) -> Result<Vec<Subscription>> {
+    let mut reader = Reader::from_reader(opml);
+    let mut subscriptions = vec![];
+    loop {
+        match reader.read_event().map_err(|_| Error::InvalidValue)? {
+            Event::Eof => break,
+            Event::Start(ref e) | Event::Empty(ref e) if e.local_name().as_ref() == b"outline" => {
+                let mut subscription = Subscription::default();
+                for attr in e.attributes().with_checks(false).flatten() {
+                    let value = match attr.unescape_value() {
+                        Ok(value) => value.trim().to_owned(),
+                        Err(_) => continue,
+                    };
+                    match attr.key.local_name().as_ref() {
+                        b"text" if subscription.title.is_empty() => subscription.title = value,
+                        b"title" => subscription.title = value,
+                        b"xmlUrl" => subscription.feed_url = Some(value),
+                        b"htmlUrl" => subscription.page_url = Some(value),
+                        _ => {}
+                    }
+                }
+                if subscription.feed_url.is_some() || subscription.page_url.is_some() {
+                    subscriptions.push(subscription);
+                }
+                if subscriptions.len() >= MAX_SUBSCRIPTIONS {
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(subscriptions)
+}
+
+impl Subscription {
+    /// The author this subscription is about, fetching them if they are not known yet.
+    pub fn resolve(&self, conn: &Connection) -> Result<User> {
+        if let Some(fqn) = self.feed_url.as_deref().and_then(fqn_from_feed) {
+            if let Ok(user) = User::find_by_fqn(conn, &fqn) {
+                return Ok(user);
+            }
+        }
+        let page = self.page_url.as_deref().ok_or(Error::NotFound)?;
+        User::from_id(conn, page, None, CONFIG.proxy()).map_err(|(_, e)| e)
+    }
+}
+
+/// The author of a Plume feed, from its address (`https://instance/@/name/atom.xml`).
+fn fqn_from_feed(feed: &str) -> Option<String> {
+    let url = Url::parse(feed).ok()?;
+    let mut segments = url.path_segments()?;
+    if segments.next()? != "@" {
+        return None;
+    }
+    let name = RawStr::from_str(segments.next()?).percent_decode().ok()?;
+    if segments.next()? != "atom.xml" || segments.next().is_some() {
+        return None;
+    }
+    let host = match url.port() {
+        Some(port) => format!("{}:{}", url.host_str()?, port),
+        None => url.host_str()?.to_owned(),
+    };
+    if name.contains('@') || host == CONFIG.base_url {
+        Some(name.into_owned())
+    } else {
+        Some(format!("{}@{}", name, host))
+    }
+}
+
+/// Makes `user` follow the authors of `subscriptions`, that they don't follow yet.
+///
+/// It can take a while, as remote authors may have to be fetched.
+pub fn follow_all(conn: &Connection, user: &User, subscriptions: &[Subscription]) -> ImportReport {
+    let mut report = ImportReport::default();
+    for subscription in subscriptions {
+        let result = subscription.resolve(conn).and_then(|target| {
+            if target.id == user.id || user.is_following(conn, target.id)? {
+                return Ok(false);
+            }
+            let follow = Follow::insert(
+                conn,
+                NewFollow {
+                    follower_id: user.id,
+                    following_id: target.id,
+                    ap_url: String::new(),
+                },
+            )?;
+            follow.notify(conn)?;
+            let act = follow.to_activity(conn)?;
+            broadcast(user, act, vec![target], CONFIG.proxy().cloned());
+            Ok(true)
+        });
+        match result {
+            Ok(true) => report.followed += 1,
+            Ok(false) => report.already_followed += 1,
+            Err(e) => {
+                warn!("Couldn't follow {}: {:?}", subscription.title, e);
+                report.failed.push(subscription.title.clone());
+            }
+        }
+    }
+    info!(
+        "{} imported subscriptions: {} followed, {} already followed, {} failed",
+        user.fqn,
+        report.followed,
+        report.already_followed,
+        report.failed.len()
+    );
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tests::db, users::tests as user_tests};
+    use diesel::Connection;
+
+    #[test]
+    fn export_and_import() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let users = user_tests::fill_database(conn);
+            let (reader, author) = (&users[1], &users[2]);
+            let report = follow_all(
+                conn,
+                reader,
+                &[Subscription {
+                    title: author.name(),
+                    feed_url: Some(Instance::get_local()?.compute_box(
+                        "@",
+                        &author.fqn,
+                        "atom.xml",
+                    )),
+                    page_url: None,
+                }],
+            );
+            assert_eq!(report.followed, 1);
+            assert!(reader.is_following(conn, author.id)?);
+
+            let opml = export(conn, reader)?;
+            let subscriptions = parse(opml.as_bytes())?;
+            assert_eq!(subscriptions.len(), 1);
+            assert_eq!(subscriptions[0].title, author.name());
+            assert_eq!(subscriptions[0].resolve(conn)?.id, author.id);
+
+            let report = follow_all(conn, reader, &subscriptions);
+            assert_eq!(report.followed, 0);
+            assert_eq!(report.already_followed, 1);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn parse_folders() {
+        let opml = r#"<?xml version="1.0"?>
+<opml version="1.0">
+  <head><title>Feeds</title></head>
+  <body>
+    <outline text="Blogs">
+      <outline text="Alice &amp; co" type="rss" xmlUrl="https://plume.example/@/alice/atom.xml"/>
+      <outline text="Bob" htmlUrl="https://social.example/users/bob"></outline>
+    </outline>
+  </body>
+</opml>"#;
+        let subscriptions = parse(opml.as_bytes()).unwrap();
+        assert_eq!(subscriptions.len(), 2);
+        assert_eq!(subscriptions[0].title, "Alice & co");
+        assert_eq!(
+            subscriptions[1].page_url.as_deref(),
+            Some("https://social.example/users/bob")
+        );
+        assert!(parse(b"<opml><body></opml>").is_err());
+    }
+
+    #[test]
+    fn feed_authors() {
+        assert_eq!(
+            fqn_from_feed("https://plume.example/@/alice/atom.xml").as_deref(),
+            Some("alice@plume.example")
+        );
+        assert_eq!(
+            fqn_from_feed("https://plume.example/@/bob@social.example/atom.xml").as_deref(),
+            Some("bob@social.example")
+        );
+        assert_eq!(fqn_from_feed("https://plume.example/~/blog/atom.xml"), None);
+        assert_eq!(fqn_from_feed("https://plume.example/@/alice/"), None);
+    }
+}
@@ -0,0 +1,255 @@
+//! Requests to addresses chosen by users, or found in what they upload.
+//!
+//! Such an address could point inside the network of the instance: its database, the metadata
+//! service of a cloud provider, an admin panel… The host is resolved before connecting, and the
+//! request is refused unless all of its addresses are public. The client then connects to the
+//! address that was checked, instead of resolving the host again, and redirections are followed
+//! by hand to check each of them. Responses have to come in time, and are cut after a size.
+
+use crate::{Error, Result, CONFIG};
+use reqwest::{
+    blocking::{Client, ClientBuilder, Response},
+    header::{CONTENT_TYPE, LOCATION},
+    redirect::Policy,
+    Url,
+};
+use std::{
+    io::Read,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+use url::Host;
+
+const MAX_REDIRECTIONS: usize = 5;
+
+/// How long a request can take, and how large its response can be.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    pub connect_timeout: Duration,
+    /// From the connection to the end of the response
+    pub timeout: Duration,
+    /// In bytes
+    pub max_size: u64,
+}
+
+impl Limits {
+    pub fn new(timeout_secs: u64, max_size: u64) -> Self {
+        Limits {
+            connect_timeout: Duration::from_secs(5),
+            timeout: Duration::from_secs(timeout_secs),
+            max_size,
+        }
+    }
+}
+
+/// A downloaded file.
+pub struct Download {
+    /// Where it was found, after redirections
+    pub url: Url,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+/// Whether `ip` can be reached from the Internet, and isn't one of the special addresses.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public_v4(ip);
+            }
+            let segments = ip.segments();
+            // NAT64 addresses embed an IPv4 one
+            if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                let [a, b] = segments[6].to_be_bytes();
+                let [c, d] = segments[7].to_be_bytes();
+                return is_public_v4(Ipv4Addr::new(a, b, c, d));
+            }
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                // Unique local addresses
+                || segments[0] & 0xfe00 == 0xfc00
+                // Link-local addresses
+                || segments[0] & 0xffc0 == 0xfe80
+                // Documentation
+                || segments[..2] == [0x2001, 0xdb8])
+        }
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        // Shared address space, used by carrier-grade NATs
+        || (a == 100 && (64..128).contains(&b))
+        // IETF protocol assignments
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking
+        || (a == 198 && (b == 18 || b == 19))
+        // Reserved
+        || a >= 240)
+}
+
+/// Whether the host of `url` may be public, without resolving it.
+///
+/// Addresses that are saved to be requested later are checked with this, and again with
+/// `check_url` when they are requested, as what a domain points to can change.
+pub fn may_be_public(url: &Url) -> bool {
+    match url.host() {
+        Some(Host::Ipv4(ip)) => is_public(IpAddr::V4(ip)),
+        Some(Host::Ipv6(ip)) => is_public(IpAddr::V6(ip)),
+        Some(Host::Domain(domain)) => {
+            let domain = domain.trim_end_matches('.').to_lowercase();
+            domain != "localhost" && !domain.ends_with(".localhost")
+        }
+        None => false,
+    }
+}
+
+/// Parses `url`, and checks that it is an HTTP address of a public host.
+///
+/// Returns the address to connect to.
+pub fn check_url(url: &str) -> Result<(Url, SocketAddr)> {
+    let url = Url::parse(url)?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(Error::Url);
+    }
+    let addresses = url.socket_addrs(|| None).map_err(|_| Error::Request)?;
+    // A host can have a public and a private address: all of them are checked
+    match addresses.first() {
+        Some(address) if addresses.iter().all(|a| is_public(a.ip())) => Ok((url, *address)),
+        _ => Err(Error::Url),
+    }
+}
+
+/// A client that connects to the checked `address` of `url`, and doesn't follow redirections.
+pub fn client_for(url: &Url, address: SocketAddr, limits: &Limits) -> Result<Client> {
+    let mut client = ClientBuilder::new()
+        .connect_timeout(limits.connect_timeout)
+        .timeout(limits.timeout)
+        .redirect(Policy::none());
+    if let Some(domain) = url.domain() {
+        client = client.resolve(domain, address);
+    }
+    if let Some(proxy) = CONFIG.proxy() {
+        client = client.proxy(proxy.clone());
+    }
+    Ok(client.build()?)
+}
+
+/// Downloads `url`, following a few redirections.
+pub fn get(url: &str, limits: &Limits) -> Result<Download> {
+    fetch(url, limits, read_body)
+}
+
+/// Downloads the beginning of `url`, up to the maximum size, following a few redirections.
+pub fn get_beginning(url: &str, limits: &Limits) -> Result<Download> {
+    fetch(url, limits, |res, max_size| {
+        let mut body = Vec::new();
+        res.take(max_size).read_to_end(&mut body)?;
+        Ok(body)
+    })
+}
+
+fn fetch(
+    url: &str,
+    limits: &Limits,
+    read: impl Fn(Response, u64) -> Result<Vec<u8>>,
+) -> Result<Download> {
+    let mut url = url.to_owned();
+    for _ in 0..=MAX_REDIRECTIONS {
+        let (checked, address) = check_url(&url)?;
+        let res = client_for(&checked, address, limits)?
+            .get(checked.clone())
+            .send()?;
+        if res.status().is_redirection() {
+            let location = res
+                .headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .ok_or(Error::Request)?;
+            url = checked.join(location)?.to_string();
+            continue;
+        }
+        let res = res.error_for_status()?;
+        let content_type = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|ct| ct.to_str().ok())
+            .map(ToOwned::to_owned);
+        return Ok(Download {
+            url: checked,
+            content_type,
+            body: read(res, limits.max_size)?,
+        });
+    }
+    Err(Error::Request)
+}
+
+/// Reads the body of `res`, failing if it is larger than `max_size`.
+pub fn read_body(res: Response, max_size: u64) -> Result<Vec<u8>> {
+    if res.content_length().map_or(false, |len| len > max_size) {
+        return Err(Error::InvalidValue);
+    }
+    let mut body = Vec::new();
+    res.take(max_size + 1).read_to_end(&mut body)?;
+    if body.len() as u64 > max_size {
+        return Err(Error::InvalidValue);
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn public_addresses() {
+        for ip in &["1.1.1.1", "93.184.216.34", "2606:4700:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in &[
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn private_urls() {
+        assert!(check_url("http://127.0.0.1:8080/admin").is_err());
+        assert!(check_url("http://[::1]/").is_err());
+        assert!(check_url("http://localhost/").is_err());
+        assert!(check_url("file:///etc/passwd").is_err());
+
+        for url in &[
+            "http://127.0.0.1:9/",
+            "http://[fd00::1]/",
+            "http://LOCALHOST./",
+        ] {
+            assert!(!may_be_public(&Url::parse(url).unwrap()), "{}", url);
+        }
+        assert!(may_be_public(
+            &Url::parse("https://example.com/hook").unwrap()
+        ));
+    }
+}
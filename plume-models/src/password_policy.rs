@@ -0,0 +1,174 @@
+//! Checking the passwords users choose, when they sign up or change it.
+//!
+//! Passwords can be required to be long enough, hard enough to guess according to zxcvbn,
+//! and not to appear in known data breaches. Breaches are looked up with the k-anonymity
+//! range API of Have I Been Pwned (or a copy of it, to keep everything offline): only the
+//! first five characters of the SHA-1 hash of the password are sent.
+
+use crate::{config::PasswordPolicyConfig, Error, Result, CONFIG};
+use openssl::sha::sha1;
+use reqwest::blocking::ClientBuilder;
+use std::time::Duration;
+use tracing::warn;
+
+/// Why a password can't be used.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PasswordProblem {
+    /// It is shorter than this
+    TooShort(usize),
+    /// It is too easy to guess
+    TooWeak,
+    /// It appeared this many times in data breaches
+    Breached(u64),
+}
+
+impl PasswordProblem {
+    pub fn code(&self) -> &'static str {
+        match self {
+            PasswordProblem::TooShort(_) => "too_short",
+            PasswordProblem::TooWeak => "too_weak",
+            PasswordProblem::Breached(_) => "breached",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            PasswordProblem::TooShort(length) => {
+                format!("Password should be at least {} characters long", length)
+            }
+            PasswordProblem::TooWeak => {
+                "Password is too easy to guess, try a longer one or a few unrelated words"
+                    .to_owned()
+            }
+            PasswordProblem::Breached(_) => {
+                "Password appeared in a data breach, and is used by attackers: choose another one"
+                    .to_owned()
+            }
+        }
+    }
+}
+
+/// Why `password` can't be used, if it can't. `inputs` are the other things the user typed,
+/// like their username or email, that should not be part of it.
+pub fn problems(password: &str, inputs: &[&str]) -> Vec<PasswordProblem> {
+    problems_with(&CONFIG.password_policy, password, inputs)
+}
+
+/// Fails with `Error::WeakPassword` if `password` can't be used.
+pub fn enforce(password: &str, inputs: &[&str]) -> Result<()> {
+    let problems = problems(password, inputs);
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::WeakPassword(problems))
+    }
+}
+
+fn problems_with(
+    policy: &PasswordPolicyConfig,
+    password: &str,
+    inputs: &[&str],
+) -> Vec<PasswordProblem> {
+    let mut problems = vec![];
+    if password.chars().count() < policy.min_length {
+        problems.push(PasswordProblem::TooShort(policy.min_length));
+    }
+    if let Some(min_score) = policy.min_score {
+        let score = zxcvbn::zxcvbn(password, inputs).map_or(0, |entropy| entropy.score());
+        if score < min_score {
+            problems.push(PasswordProblem::TooWeak);
+        }
+    }
+    // The other problems are enough, no need to ask
+    if let (Some(api), true) = (&policy.breach_api, problems.is_empty()) {
+        match breach_count(api, password) {
+            Ok(0) => {}
+            Ok(count) => problems.push(PasswordProblem::Breached(count)),
+            // Signing up shouldn't depend on another service
+            Err(err) => warn!("Couldn't check if a password was breached: {}", err),
+        }
+    }
+    problems
+}
+
+/// How many times `password` appeared in the breaches known by the range API at `api`.
+fn breach_count(api: &str, password: &str) -> std::result::Result<u64, String> {
+    let hash = sha1(password.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<String>();
+    let (prefix, suffix) = hash.split_at(5);
+    let mut client = ClientBuilder::new()
+        .connect_timeout(Duration::from_secs(5))
+        .timeout(Duration::from_secs(10));
+    if let Some(proxy) = CONFIG.proxy() {
+        client = client.proxy(proxy.clone());
+    }
+    let body = client
+        .build()
+        .and_then(|client| {
+            client
+                .get(&format!("{}/{}", api, prefix))
+                // Fake entries hide how many hashes share the prefix
+                .header("Add-Padding", "true")
+                .send()
+        })
+        .and_then(|res| res.error_for_status())
+        .and_then(|res| res.text())
+        .map_err(|e| e.to_string())?;
+    Ok(count_in_range(&body, suffix))
+}
+
+/// Reads a response of the range API, made of `SUFFIX:COUNT` lines.
+fn count_in_range(body: &str, suffix: &str) -> u64 {
+    body.lines()
+        .filter_map(|line| line.trim().split_once(':'))
+        .find(|(candidate, _)| candidate.eq_ignore_ascii_case(suffix))
+        .and_then(|(_, count)| count.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_and_strength() {
+        let policy = PasswordPolicyConfig {
+            min_length: 10,
+            min_score: Some(3),
+            breach_api: None,
+        };
+        assert_eq!(
+            problems_with(&policy, "password", &[]),
+            vec![PasswordProblem::TooShort(10), PasswordProblem::TooWeak]
+        );
+        assert!(problems_with(&policy, "correct horse battery staple", &[]).is_empty());
+    }
+
+    #[test]
+    fn range_response() {
+        let body = "0018A45C4D1DEF81644B54AB7F969B88D65:1\r\n\
+            00D4F6E8FA6EECAD2A3AA415EEC418D38EC:2\r\n\
+            1E4C9B93F3F0682250B6CF8331B7EE68FD8:3730471\r\n\
+            0A1A1ECE5D2B4E6B2F0A3E9B1C9F1D3F0B2:0\r\n";
+        // The hash of "password" starts with 5BAA6
+        assert_eq!(
+            count_in_range(body, "1E4C9B93F3F0682250B6CF8331B7EE68FD8"),
+            3_730_471
+        );
+        assert_eq!(
+            count_in_range(body, "1e4c9b93f3f0682250b6cf8331b7ee68fd8"),
+            3_730_471
+        );
+        // Padding entries, and hashes that are not listed
+        assert_eq!(
+            count_in_range(body, "0A1A1ECE5D2B4E6B2F0A3E9B1C9F1D3F0B2"),
+            0
+        );
+        assert_eq!(
+            count_in_range(body, "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF"),
+            0
+        );
+    }
+}
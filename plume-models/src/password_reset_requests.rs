@@ -22,17 +22,20 @@ const TOKEN_VALIDITY_HOURS: i64 = 2;
 
 impl PasswordResetRequest {
     pub fn insert(conn: &Connection, email: &str) -> Result<String> {
+        Self::insert_valid_for(conn, email, Duration::hours(TOKEN_VALIDITY_HOURS))
+    }
+
+    /// Like `insert`, with a longer validity for the links sent by administrators.
+    pub fn insert_valid_for(conn: &Connection, email: &str, validity: Duration) -> Result<String> {
         // first, delete other password reset tokens associated with this email:
-        let existing_requests =
-            password_reset_requests::table.filter(password_reset_requests::email.eq(email));
-        diesel::delete(existing_requests).execute(conn)?;
+        Self::delete_for_email(conn, email)?;
 
         // now, generate a random token, set the expiry date,
         // and insert it into the DB:
         let token = plume_common::utils::random_hex();
         let expiration_date = Utc::now()
             .naive_utc()
-            .checked_add_signed(Duration::hours(TOKEN_VALIDITY_HOURS))
+            .checked_add_signed(validity)
             .expect("could not calculate expiration date");
         let new_request = NewPasswordResetRequest {
             email: email.to_owned(),
@@ -47,6 +50,13 @@ impl PasswordResetRequest {
         Ok(token)
     }
 
+    pub fn delete_for_email(conn: &Connection, email: &str) -> Result<()> {
+        let existing_requests =
+            password_reset_requests::table.filter(password_reset_requests::email.eq(email));
+        diesel::delete(existing_requests).execute(conn)?;
+        Ok(())
+    }
+
     pub fn find_by_token(conn: &Connection, token: &str) -> Result<Self> {
         let token = password_reset_requests::table
             .filter(password_reset_requests::token.eq(token))
@@ -0,0 +1,235 @@
+//! The personal data of users, so that they can get a copy of it or have it erased.
+//!
+//! The only IP addresses that are kept are the ones that created an account or a comment,
+//! for a limited time, and the last one each browser session was used from, until it
+//! expires. Views are counted with hashes that change every day.
+
+use crate::{
+    account_notes::AccountNote,
+    blogs::Blog,
+    comments::Comment,
+    email_signups::EmailSignup,
+    fundings::Funding,
+    ip_records::IpRecord,
+    legal_documents::DocumentKind,
+    medias::Media,
+    mutes::Mute,
+    password_reset_requests::PasswordResetRequest,
+    profile_fields::{ProfileField, ProfileOwner},
+    schema::{
+        api_tokens, apps, legal_acceptances, legal_documents, likes, post_authors, posts, reshares,
+    },
+    sessions::Session,
+    users::User,
+    Connection, Result, CONFIG,
+};
+use activitystreams::activity::Delete;
+use chrono::NaiveDateTime;
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use serde_json::{json, Value};
+
+fn date(date: NaiveDateTime) -> String {
+    date.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+/// A machine-readable copy of everything the instance knows about a user.
+pub fn export(conn: &Connection, user: &User) -> Result<Value> {
+    let posts = post_authors::table
+        .inner_join(posts::table)
+        .filter(post_authors::author_id.eq(user.id))
+        .select((
+            posts::title,
+            posts::subtitle,
+            posts::source,
+            posts::ap_url,
+            posts::published,
+            posts::creation_date,
+        ))
+        .load::<(String, String, String, String, bool, NaiveDateTime)>(conn)?
+        .into_iter()
+        .map(|(title, subtitle, source, url, published, creation_date)| {
+            json!({
+                "title": title,
+                "subtitle": subtitle,
+                "source": source,
+                "url": url,
+                "published": published,
+                "creation_date": date(creation_date),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let comments = Comment::list_by_author(conn, user.id)?
+        .into_iter()
+        .map(|comment| {
+            json!({
+                "content": comment.content.get(),
+                "url": comment.ap_url,
+                "spoiler_text": comment.spoiler_text,
+                "creation_date": date(comment.creation_date),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let likes = likes::table
+        .inner_join(posts::table)
+        .filter(likes::user_id.eq(user.id))
+        .select((posts::ap_url, likes::creation_date))
+        .load::<(String, NaiveDateTime)>(conn)?
+        .into_iter()
+        .map(|(post, creation_date)| json!({ "post": post, "creation_date": date(creation_date) }))
+        .collect::<Vec<_>>();
+
+    let reshares = reshares::table
+        .inner_join(posts::table)
+        .filter(reshares::user_id.eq(user.id))
+        .select((posts::ap_url, reshares::creation_date))
+        .load::<(String, NaiveDateTime)>(conn)?
+        .into_iter()
+        .map(|(post, creation_date)| json!({ "post": post, "creation_date": date(creation_date) }))
+        .collect::<Vec<_>>();
+
+    let sessions = api_tokens::table
+        .inner_join(apps::table)
+        .filter(api_tokens::user_id.eq(user.id))
+        .select((apps::name, api_tokens::scopes, api_tokens::creation_date))
+        .load::<(String, String, NaiveDateTime)>(conn)?
+        .into_iter()
+        .map(|(app, scopes, creation_date)| {
+            json!({ "app": app, "scopes": scopes, "creation_date": date(creation_date) })
+        })
+        .collect::<Vec<_>>();
+
+    let accepted_terms = legal_acceptances::table
+        .inner_join(legal_documents::table)
+        .filter(legal_acceptances::user_id.eq(user.id))
+        .select((
+            legal_documents::kind,
+            legal_documents::version,
+            legal_acceptances::creation_date,
+        ))
+        .load::<(i32, i32, NaiveDateTime)>(conn)?
+        .into_iter()
+        .map(|(kind, version, creation_date)| {
+            json!({
+                "document": DocumentKind::from_i32(kind).map(DocumentKind::name),
+                "version": version,
+                "creation_date": date(creation_date),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let urls = |users: Vec<User>| users.into_iter().map(|u| u.ap_url).collect::<Vec<_>>();
+
+    Ok(json!({
+        "account": {
+            "username": user.username,
+            "display_name": user.display_name,
+            "email": user.email,
+            "pending_email": user.pending_email,
+            "summary": user.summary,
+            "url": user.ap_url,
+            "creation_date": date(user.creation_date),
+            "last_active": user.last_active.map(date),
+            "preferred_theme": user.preferred_theme,
+            "profile_fields": ProfileField::list(conn, ProfileOwner::User(user.id))?
+                .into_iter()
+                .map(|f| json!({ "name": f.name, "value": f.value }))
+                .collect::<Vec<_>>(),
+            "funding": Funding::for_user(conn, user.id).ok().map(|f| json!({
+                "payment_pointer": f.payment_pointer,
+                "liberapay": f.liberapay_url,
+                "kofi": f.kofi_url,
+            })),
+        },
+        "blogs": Blog::find_for_author(conn, user)?
+            .into_iter()
+            .map(|b| b.ap_url)
+            .collect::<Vec<_>>(),
+        "posts": posts,
+        "comments": comments,
+        "likes": likes,
+        "reshares": reshares,
+        "following": urls(user.get_followed(conn)?),
+        "followers": urls(user.get_followers(conn)?),
+        "account_notes": AccountNote::list_for_owner(conn, user.id)?
+            .into_iter()
+            .map(|(note, account)| json!({
+                "account": account.ap_url,
+                "content": note.content,
+                "updated_at": date(note.updated_at),
+            }))
+            .collect::<Vec<_>>(),
+        "mutes": Mute::list_active(conn, user.id)?
+            .into_iter()
+            .map(|mute| Ok(json!({
+                "kind": mute.mute_kind().map(|kind| kind.name()),
+                "account": match mute.target_id {
+                    Some(id) => Some(User::get(conn, id)?.ap_url),
+                    None => None,
+                },
+                "value": mute.value,
+                "expires_at": mute.expires_at.map(date),
+            })))
+            .collect::<Result<Vec<_>>>()?,
+        "media": Media::for_user(conn, user.id)?
+            .into_iter()
+            .map(|m| json!({ "url": m.url().ok(), "alt_text": m.alt_text }))
+            .collect::<Vec<_>>(),
+        "sessions": sessions,
+        "browser_sessions": Session::list_for_user(conn, user.id)?
+            .into_iter()
+            .map(|s| json!({
+                "user_agent": s.user_agent,
+                "address": s.address,
+                "creation_date": date(s.creation_date),
+                "last_seen": date(s.last_seen),
+            }))
+            .collect::<Vec<_>>(),
+        "accepted_terms": accepted_terms,
+        "ip_addresses": IpRecord::for_user(conn, user.id)?
+            .into_iter()
+            .map(|r| json!({
+                "address": r.address,
+                "hashed": CONFIG.ip_hash.is_some(),
+                "creation_date": date(r.creation_date),
+            }))
+            .collect::<Vec<_>>(),
+    }))
+}
+
+/// Erases a user, with their content and the tokens sent to their email address.
+///
+/// The returned activity has to be sent to the other instances, for them to delete
+/// their copy of the account too.
+pub fn erase(conn: &Connection, user: &User) -> Result<Delete> {
+    if let Some(ref email) = user.email {
+        PasswordResetRequest::delete_for_email(conn, email)?;
+        EmailSignup::delete_existings_by_email(conn, email)?;
+    }
+    user.delete(conn)?;
+    user.delete_activity(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{inbox::tests::fill_database, tests::db, Error};
+    use diesel::Connection;
+
+    #[test]
+    fn export_and_erase() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, users, _) = fill_database(conn);
+            let data = export(conn, &users[0])?;
+            assert_eq!(data["account"]["username"], users[0].username.as_str());
+            assert_eq!(data["posts"][0]["url"], posts[0].ap_url.as_str());
+            assert_eq!(data["ip_addresses"].as_array().map(Vec::len), Some(0));
+
+            erase(conn, &users[0])?;
+            assert!(User::get(conn, users[0].id).is_err());
+            Ok(())
+        });
+    }
+}
@@ -2,12 +2,11 @@ pub use self::module::PlumeRocket;
 
 #[cfg(not(test))]
 mod module {
-    use crate::{search, users};
+    use crate::{search, users, worker::Worker};
     use rocket::{
         request::{self, FlashMessage, FromRequest, Request},
         Outcome, State,
     };
-    use scheduled_thread_pool::ScheduledThreadPool;
     use std::sync::Arc;
 
     /// Common context needed by most routes and operations on models
@@ -15,7 +14,7 @@ mod module {
         pub intl: rocket_i18n::I18n,
         pub user: Option<users::User>,
         pub searcher: Arc<search::Searcher>,
-        pub worker: Arc<ScheduledThreadPool>,
+        pub worker: Arc<Worker>,
         pub flash_msg: Option<(String, String)>,
     }
 
@@ -25,7 +24,7 @@ mod module {
         fn from_request(request: &'a Request<'r>) -> request::Outcome<PlumeRocket, ()> {
             let intl = request.guard::<rocket_i18n::I18n>()?;
             let user = request.guard::<users::User>().succeeded();
-            let worker = request.guard::<'_, State<'_, Arc<ScheduledThreadPool>>>()?;
+            let worker = request.guard::<'_, State<'_, Arc<Worker>>>()?;
             let searcher = request.guard::<'_, State<'_, Arc<search::Searcher>>>()?;
             let flash_msg = request.guard::<FlashMessage<'_, '_>>().succeeded();
             Outcome::Success(PlumeRocket {
@@ -41,19 +40,18 @@ mod module {
 
 #[cfg(test)]
 mod module {
-    use crate::{search, users};
+    use crate::{search, users, worker::Worker};
     use rocket::{
         request::{self, FromRequest, Request},
         Outcome, State,
     };
-    use scheduled_thread_pool::ScheduledThreadPool;
     use std::sync::Arc;
 
     /// Common context needed by most routes and operations on models
     pub struct PlumeRocket {
         pub user: Option<users::User>,
         pub searcher: Arc<search::Searcher>,
-        pub worker: Arc<ScheduledThreadPool>,
+        pub worker: Arc<Worker>,
     }
 
     impl<'a, 'r> FromRequest<'a, 'r> for PlumeRocket {
@@ -61,7 +59,7 @@ mod module {
 
         fn from_request(request: &'a Request<'r>) -> request::Outcome<PlumeRocket, ()> {
             let user = request.guard::<users::User>().succeeded();
-            let worker = request.guard::<'_, State<'_, Arc<ScheduledThreadPool>>>()?;
+            let worker = request.guard::<'_, State<'_, Arc<Worker>>>()?;
             let searcher = request.guard::<'_, State<'_, Arc<search::Searcher>>>()?;
             Outcome::Success(PlumeRocket {
                 user,
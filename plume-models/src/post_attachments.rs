@@ -0,0 +1,226 @@
+//! Audio files attached to articles.
+//!
+//! They are federated in the `attachment` property of articles, as `Audio` objects with
+//! their media type and duration, so that other instances can show a player. `Document`
+//! attachments with an audio media type, as sent by Mastodon, are accepted too.
+
+use crate::{
+    audio::{self, AudioMetadata},
+    medias::{Media, MediaCategory},
+    schema::{medias, post_attachments},
+    users::User,
+    Connection, Error, Result,
+};
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+use serde_json::Value;
+
+#[derive(Clone, Queryable, Identifiable)]
+pub struct PostAttachment {
+    pub id: i32,
+    pub post_id: i32,
+    pub media_id: i32,
+}
+
+#[derive(Insertable)]
+#[table_name = "post_attachments"]
+pub struct NewPostAttachment {
+    pub post_id: i32,
+    pub media_id: i32,
+}
+
+impl PostAttachment {
+    insert!(post_attachments, NewPostAttachment);
+
+    /// The media attached to an article, in the order they were attached.
+    pub fn media_for_post(conn: &Connection, post_id: i32) -> Result<Vec<Media>> {
+        post_attachments::table
+            .inner_join(medias::table)
+            .filter(post_attachments::post_id.eq(post_id))
+            .order(post_attachments::id.asc())
+            .select(medias::all_columns)
+            .load::<Media>(conn)
+            .map_err(Error::from)
+    }
+
+    /// Replaces the media attached to an article.
+    pub fn set_for_post(conn: &Connection, post_id: i32, media_ids: &[i32]) -> Result<()> {
+        diesel::delete(post_attachments::table.filter(post_attachments::post_id.eq(post_id)))
+            .execute(conn)?;
+        let mut attached = vec![];
+        for media_id in media_ids {
+            if attached.contains(media_id) {
+                continue;
+            }
+            Self::insert(
+                conn,
+                NewPostAttachment {
+                    post_id,
+                    media_id: *media_id,
+                },
+            )?;
+            attached.push(*media_id);
+        }
+        Ok(())
+    }
+
+    /// The object representing an attached media, in the `attachment` property of an article.
+    pub fn to_activity(media: &Media) -> Result<Value> {
+        let mut attachment = json!({
+            "type": "Audio",
+            "url": media.url()?,
+            "name": media.alt_text,
+        });
+        if let Some(media_type) = media.media_type() {
+            attachment["mediaType"] = json!(media_type);
+        }
+        if let Some(duration) = media.duration {
+            attachment["duration"] = json!(audio::iso_duration(duration as u32));
+        }
+        Ok(attachment)
+    }
+
+    /// Saves the audio files in the `attachment` property of a remote article, as media owned
+    /// by `owner`, and returns their IDs.
+    ///
+    /// Media that are already known are reused.
+    pub fn from_activity(conn: &Connection, attachments: &Value, owner: &User) -> Vec<i32> {
+        let attachments = match attachments {
+            Value::Array(attachments) => attachments.iter().collect(),
+            attachment @ Value::Object(_) => vec![attachment],
+            _ => vec![],
+        };
+        attachments
+            .into_iter()
+            .filter(|a| is_audio(a))
+            .filter_map(|a| save_remote(conn, a, owner).ok())
+            .map(|m| m.id)
+            .collect()
+    }
+}
+
+fn is_audio(attachment: &Value) -> bool {
+    let media_type = attachment["mediaType"].as_str().unwrap_or_default();
+    match attachment["type"].as_str() {
+        Some("Audio") => true,
+        Some("Document") => media_type.starts_with("audio/"),
+        _ => false,
+    }
+}
+
+fn save_remote(conn: &Connection, attachment: &Value, owner: &User) -> Result<Media> {
+    let url = match &attachment["url"] {
+        Value::String(url) => url.as_str(),
+        // A `Link`
+        link => link["href"].as_str().ok_or(Error::MissingApProperty)?,
+    };
+    let media = match medias::table
+        .filter(medias::is_remote.eq(true))
+        .filter(medias::remote_url.eq(url))
+        .first::<Media>(conn)
+    {
+        Ok(media) => media,
+        Err(_) => Media::save_remote(conn, url.to_owned(), owner)?,
+    };
+    let alt_text = attachment["name"].as_str().unwrap_or_default();
+    if media.alt_text != alt_text {
+        diesel::update(&media)
+            .set(medias::alt_text.eq(alt_text))
+            .execute(conn)?;
+    }
+    if let Some(duration) = attachment["duration"]
+        .as_str()
+        .and_then(audio::parse_iso_duration)
+    {
+        audio::set_metadata(
+            conn,
+            &media,
+            AudioMetadata {
+                duration,
+                bitrate: media.bitrate.unwrap_or_default() as u32,
+            },
+        )?;
+    }
+    Media::get(conn, media.id)
+}
+
+impl Media {
+    /// Whether this media can be attached to an article.
+    pub fn is_attachable(&self) -> bool {
+        self.category() == MediaCategory::Audio
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{inbox::tests::fill_database, medias::NewMedia, tests::db};
+    use diesel::Connection;
+
+    #[test]
+    fn attachments() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, users, _) = fill_database(conn);
+            let post_id = posts[0].id;
+            let audio = Media::insert(
+                conn,
+                NewMedia {
+                    file_path: "static/media/episode.mp3".to_owned(),
+                    alt_text: "Episode 0".to_owned(),
+                    is_remote: false,
+                    remote_url: None,
+                    sensitive: false,
+                    content_warning: None,
+                    owner_id: users[0].id,
+                },
+            )?;
+            assert!(audio.is_attachable());
+            audio::set_metadata(
+                conn,
+                &audio,
+                AudioMetadata {
+                    duration: 192_500,
+                    bitrate: 128_000,
+                },
+            )?;
+
+            PostAttachment::set_for_post(conn, post_id, &[audio.id, audio.id])?;
+            let attached = PostAttachment::media_for_post(conn, post_id)?;
+            assert_eq!(attached.len(), 1);
+            let activity = PostAttachment::to_activity(&attached[0])?;
+            assert_eq!(activity["type"], "Audio");
+            assert_eq!(activity["mediaType"], "audio/mpeg");
+            assert_eq!(activity["duration"], "PT192.5S");
+
+            let remote = json!([
+                {
+                    "type": "Document",
+                    "mediaType": "audio/ogg",
+                    "url": "https://example.org/episode.ogg",
+                    "name": "Episode 1",
+                    "duration": "PT3M",
+                },
+                {
+                    "type": "Image",
+                    "mediaType": "image/png",
+                    "url": "https://example.org/cover.png",
+                },
+            ]);
+            let ids = PostAttachment::from_activity(conn, &remote, &users[0]);
+            assert_eq!(ids.len(), 1);
+            let media = Media::get(conn, ids[0])?;
+            assert_eq!(
+                media.remote_url.as_deref(),
+                Some("https://example.org/episode.ogg")
+            );
+            assert_eq!(media.alt_text, "Episode 1");
+            assert_eq!(media.duration, Some(180_000));
+            // Known media are reused
+            assert_eq!(PostAttachment::from_activity(conn, &remote, &users[0]), ids);
+
+            PostAttachment::set_for_post(conn, post_id, &[])?;
+            assert!(PostAttachment::media_for_post(conn, post_id)?.is_empty());
+            Ok(())
+        });
+    }
+}
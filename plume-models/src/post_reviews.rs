@@ -0,0 +1,109 @@
+//! Articles waiting for the approval of an editor.
+//!
+//! Contributors of a blog can't publish by themselves: when they want a draft to be
+//! published, it is submitted for review, and the editors and owners of the blog
+//! approve it by publishing it.
+
+use crate::{
+    posts::Post,
+    schema::{post_reviews, posts},
+    users::User,
+    Connection, Error, Result,
+};
+use chrono::NaiveDateTime;
+use diesel::{self, ExpressionMethods, JoinOnDsl, QueryDsl, RunQueryDsl};
+
+#[derive(Clone, Queryable, Identifiable)]
+pub struct PostReview {
+    pub id: i32,
+    pub post_id: i32,
+    /// The contributor who asked for the article to be published
+    pub submitted_by: i32,
+    pub creation_date: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "post_reviews"]
+pub struct NewPostReview {
+    pub post_id: i32,
+    pub submitted_by: i32,
+}
+
+impl PostReview {
+    insert!(post_reviews, NewPostReview);
+    get!(post_reviews);
+    find_by!(post_reviews, find_by_post, post_id as i32);
+
+    /// Submits a draft for review. Submitting it again does nothing.
+    pub fn submit(conn: &Connection, post: &Post, user: &User) -> Result<Self> {
+        if post.published {
+            return Err(Error::InvalidValue);
+        }
+        Self::find_by_post(conn, post.id).or_else(|_| {
+            Self::insert(
+                conn,
+                NewPostReview {
+                    post_id: post.id,
+                    submitted_by: user.id,
+                },
+            )
+        })
+    }
+
+    pub fn is_pending(conn: &Connection, post_id: i32) -> Result<bool> {
+        diesel::dsl::select(diesel::dsl::exists(
+            post_reviews::table.filter(post_reviews::post_id.eq(post_id)),
+        ))
+        .get_result(conn)
+        .map_err(Error::from)
+    }
+
+    /// The drafts of a blog waiting for a review, oldest first.
+    pub fn list_for_blog(conn: &Connection, blog_id: i32) -> Result<Vec<(Post, Self)>> {
+        posts::table
+            .inner_join(post_reviews::table.on(post_reviews::post_id.eq(posts::id)))
+            .filter(posts::blog_id.eq(blog_id))
+            .filter(posts::published.eq(false))
+            .order(post_reviews::creation_date.asc())
+            .load::<(Post, Self)>(conn)
+            .map_err(Error::from)
+    }
+
+    /// Closes the review of an article, once it has been published or withdrawn.
+    pub fn close(conn: &Connection, post_id: i32) -> Result<()> {
+        diesel::delete(post_reviews::table.filter(post_reviews::post_id.eq(post_id)))
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{inbox::tests::fill_database, tests::db};
+    use diesel::Connection;
+
+    #[test]
+    fn submit_for_review() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, users, _) = fill_database(conn);
+            assert!(PostReview::submit(conn, &posts[0], &users[0]).is_err());
+            let mut draft = posts[0].clone();
+            draft.published = false;
+            let draft = draft.update(conn)?;
+
+            let review = PostReview::submit(conn, &draft, &users[0])?;
+            assert_eq!(PostReview::submit(conn, &draft, &users[0])?.id, review.id);
+            assert!(PostReview::is_pending(conn, draft.id)?);
+            let pending = PostReview::list_for_blog(conn, draft.blog_id)?;
+            assert_eq!(pending.len(), 1);
+            assert_eq!(pending[0].0.id, draft.id);
+
+            PostReview::close(conn, draft.id)?;
+            assert!(!PostReview::is_pending(conn, draft.id)?);
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,346 @@
+//! Translations of articles, submitted by their readers.
+//!
+//! A translation is an article of the same blog as its original, written by its translator.
+//! It is submitted as a draft, that the editors of the blog review and publish like any other
+//! article, and that the translator can edit until then. It is then federated as an article of
+//! its own, that points to its original with `translationOf`.
+
+use crate::{
+    cache,
+    import::{save_local_post, ImportedPost},
+    post_reviews::PostReview,
+    posts::Post,
+    schema::{post_translations, posts},
+    tags::Tag,
+    users::User,
+    Connection, Error, Result,
+};
+use chrono::NaiveDateTime;
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl, SaveChangesDsl};
+
+/// How many translations someone can have waiting for a review at the same time.
+const MAX_PENDING_TRANSLATIONS: i64 = 5;
+
+#[derive(Clone, Queryable, Identifiable)]
+pub struct PostTranslation {
+    pub id: i32,
+    /// The translated article
+    pub post_id: i32,
+    pub original_id: i32,
+    /// A language code, like `pt-BR`
+    pub language: String,
+    pub translator_id: i32,
+    pub creation_date: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "post_translations"]
+pub struct NewPostTranslation {
+    pub post_id: i32,
+    pub original_id: i32,
+    pub language: String,
+    pub translator_id: i32,
+}
+
+/// What a translator writes.
+pub struct SubmittedTranslation<'a> {
+    pub language: &'a str,
+    pub title: &'a str,
+    pub subtitle: &'a str,
+    /// As Markdown
+    pub source: &'a str,
+}
+
+impl PostTranslation {
+    insert!(post_translations, NewPostTranslation);
+    get!(post_translations);
+    find_by!(post_translations, find_by_post, post_id as i32);
+
+    /// The published translations of an article.
+    pub fn published_for(conn: &Connection, original_id: i32) -> Result<Vec<(Self, Post)>> {
+        post_translations::table
+            .inner_join(posts::table)
+            .filter(post_translations::original_id.eq(original_id))
+            .filter(posts::published.eq(true))
+            .order(post_translations::language.asc())
+            .load::<(Self, Post)>(conn)
+            .map_err(Error::from)
+    }
+
+    /// The original of an article and its published translations, with their language, to
+    /// switch between them. It is empty for articles that were not translated.
+    pub fn versions(conn: &Connection, post: &Post) -> Result<Vec<(Option<String>, Post)>> {
+        let original = match Self::find_by_post(conn, post.id) {
+            Ok(translation) => Post::get(conn, translation.original_id)?,
+            Err(_) => post.clone(),
+        };
+        let translations = Self::published_for(conn, original.id)?;
+        if translations.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut versions = vec![(None, original)];
+        versions.extend(
+            translations
+                .into_iter()
+                .map(|(translation, post)| (Some(translation.language), post)),
+        );
+        Ok(versions)
+    }
+
+    /// Submits a translation of `original`, for the editors of its blog to review.
+    ///
+    /// The translation is a draft, with the license and the tags of the original. Translators
+    /// can't submit more than a few translations that are not published yet.
+    pub fn submit(
+        conn: &Connection,
+        original: &Post,
+        translator: &User,
+        submitted: SubmittedTranslation<'_>,
+    ) -> Result<Post> {
+        let language = language_code(submitted.language).ok_or(Error::InvalidValue)?;
+        // Translations are made from the original, and only once in each language
+        if !original.published
+            || Self::find_by_post(conn, original.id).is_ok()
+            || Self::published_for(conn, original.id)?
+                .iter()
+                .any(|(t, _)| t.language == language)
+        {
+            return Err(Error::InvalidValue);
+        }
+        let pending = post_translations::table
+            .inner_join(posts::table)
+            .filter(post_translations::translator_id.eq(translator.id))
+            .filter(posts::published.eq(false))
+            .count()
+            .get_result::<i64>(conn)?;
+        if pending >= MAX_PENDING_TRANSLATIONS {
+            return Err(Error::Unauthorized);
+        }
+
+        let blog = original.get_blog(conn)?;
+        let tags = Tag::for_post(conn, original.id)?
+            .into_iter()
+            .filter(|t| !t.is_hashtag)
+            .map(|t| t.tag)
+            .collect();
+        let mut post = save_local_post(
+            conn,
+            &blog,
+            translator,
+            ImportedPost {
+                title: submitted.title.to_owned(),
+                subtitle: submitted.subtitle.to_owned(),
+                source: submitted.source.to_owned(),
+                creation_date: None,
+                published: false,
+                tags,
+            },
+        )?;
+        post.license = original.license.clone();
+        let post: Post = post.save_changes(conn)?;
+        Self::insert(
+            conn,
+            NewPostTranslation {
+                post_id: post.id,
+                original_id: original.id,
+                language,
+                translator_id: translator.id,
+            },
+        )?;
+        PostReview::submit(conn, &post, translator)?;
+        Ok(post)
+    }
+
+    /// Tells if `user` translated `post`, and can still edit it as it is not published.
+    pub fn is_draft_of(conn: &Connection, post: &Post, user: &User) -> bool {
+        !post.published
+            && Self::find_by_post(conn, post.id)
+                .map_or(false, |translation| translation.translator_id == user.id)
+    }
+
+    /// Links an article received from another instance to the original it translates, if
+    /// it is known here.
+    ///
+    /// Only translations published in the blog of the original, or by one of its authors,
+    /// are accepted: anyone else could claim to translate any article.
+    pub fn link_remote(
+        conn: &Connection,
+        post: &Post,
+        original: &str,
+        language: &str,
+    ) -> Result<()> {
+        if Self::find_by_post(conn, post.id).is_ok() {
+            return Ok(());
+        }
+        let language = language_code(language).ok_or(Error::InvalidValue)?;
+        let original = Post::find_by_ap_url(conn, original)?;
+        let authors = post.get_authors(conn)?;
+        let translator = authors.first().ok_or(Error::NotFound)?;
+        if post.blog_id != original.blog_id {
+            let original_authors = original.get_authors(conn)?;
+            if !authors
+                .iter()
+                .any(|a| original_authors.iter().any(|o| o.id == a.id))
+            {
+                return Err(Error::Unauthorized);
+            }
+        }
+        Self::insert(
+            conn,
+            NewPostTranslation {
+                post_id: post.id,
+                original_id: original.id,
+                language,
+                translator_id: translator.id,
+            },
+        )
+        .map(|_| ())
+    }
+
+    /// Forgets the cached pages of the other versions of a translated article, that link to it.
+    pub(crate) fn forget_other_versions(conn: &Connection, post_id: i32) -> Result<()> {
+        if let Ok(translation) = Self::find_by_post(conn, post_id) {
+            cache::forget_article(translation.original_id);
+            for other in post_translations::table
+                .filter(post_translations::original_id.eq(translation.original_id))
+                .select(post_translations::post_id)
+                .load::<i32>(conn)?
+            {
+                cache::forget_article(other);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn original(&self, conn: &Connection) -> Result<Post> {
+        Post::get(conn, self.original_id)
+    }
+
+    pub fn translator(&self, conn: &Connection) -> Result<User> {
+        User::get(conn, self.translator_id)
+    }
+}
+
+/// Normalizes a language code like `pt-br` to `pt-BR`, or returns `None` if it isn't one.
+pub fn language_code(code: &str) -> Option<String> {
+    let mut parts = code.trim().split(&['-', '_'][..]);
+    let language = parts.next()?;
+    if !(2..=3).contains(&language.len()) || !language.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let mut normalized = language.to_ascii_lowercase();
+    if let Some(region) = parts.next() {
+        if !(2..=4).contains(&region.len()) || !region.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+        normalized.push('-');
+        normalized.push_str(&region.to_ascii_uppercase());
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{inbox::tests::fill_database, tests::db};
+    use diesel::Connection;
+
+    #[test]
+    fn language_codes() {
+        assert_eq!(language_code("fr"), Some("fr".to_owned()));
+        assert_eq!(language_code(" pt_br "), Some("pt-BR".to_owned()));
+        assert_eq!(language_code("french"), None);
+        assert_eq!(language_code("en-US-x"), None);
+        assert_eq!(language_code(""), None);
+    }
+
+    #[test]
+    fn submit_and_publish() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, users, _) = fill_database(conn);
+            let original = &posts[0];
+            assert!(PostTranslation::versions(conn, original)?.is_empty());
+
+            let translation = SubmittedTranslation {
+                language: "fr",
+                title: "Test",
+                subtitle: "Au revoir",
+                source: "Bonjour",
+            };
+            let mut post = PostTranslation::submit(conn, original, &users[1], translation)?;
+            assert!(!post.published);
+            assert_eq!(post.license, original.license);
+            assert!(post.is_author(conn, users[1].id)?);
+            assert!(PostTranslation::is_draft_of(conn, &post, &users[1]));
+            assert!(!PostTranslation::is_draft_of(conn, &post, &users[0]));
+            assert!(PostReview::is_pending(conn, post.id)?);
+            // Drafts are not listed
+            assert!(PostTranslation::versions(conn, original)?.is_empty());
+
+            post.published = true;
+            let post: Post = post.save_changes(conn)?;
+            let versions = PostTranslation::versions(conn, &post)?;
+            assert_eq!(versions.len(), 2);
+            assert_eq!(versions[0].0, None);
+            assert_eq!(versions[0].1.id, original.id);
+            assert_eq!(versions[1].0, Some("fr".to_owned()));
+
+            // There is already a French translation, and translations are not translated
+            let again = SubmittedTranslation {
+                language: "fr",
+                title: "Test encore",
+                subtitle: "",
+                source: "Salut",
+            };
+            assert!(PostTranslation::submit(conn, original, &users[1], again).is_err());
+            let of_translation = SubmittedTranslation {
+                language: "de",
+                title: "Prüfung",
+                subtitle: "",
+                source: "Hallo",
+            };
+            assert!(PostTranslation::submit(conn, &post, &users[1], of_translation).is_err());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn remote_translations() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, users, blogs) = fill_database(conn);
+            let translate = |author: &User| {
+                save_local_post(
+                    conn,
+                    &blogs[2],
+                    author,
+                    ImportedPost {
+                        title: format!("Traduction {}", author.id),
+                        subtitle: String::new(),
+                        source: "Bonjour".to_owned(),
+                        creation_date: None,
+                        published: true,
+                        tags: vec![],
+                    },
+                )
+            };
+
+            // Someone who didn't write the original, in another blog
+            let other = translate(&users[2])?;
+            assert!(PostTranslation::link_remote(conn, &other, &posts[0].ap_url, "fr").is_err());
+            assert!(PostTranslation::find_by_post(conn, other.id).is_err());
+
+            let by_author = translate(&users[0])?;
+            PostTranslation::link_remote(conn, &by_author, &posts[0].ap_url, "fr")?;
+            assert_eq!(
+                PostTranslation::find_by_post(conn, by_author.id)?.original_id,
+                posts[0].id
+            );
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,299 @@
+use crate::{
+    posts::Post,
+    schema::{comments, likes, post_authors, post_view_visitors, post_views, reshares},
+    users::User,
+    Connection, Error, Result,
+};
+use chrono::{Duration, NaiveDate, NaiveDateTime, Utc};
+use diesel::{self, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+use openssl::sha::sha256;
+use plume_common::utils::random_hex;
+use rocket::{
+    request::{self, FromRequest, Request},
+    Outcome,
+};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+lazy_static! {
+    /// Salt used to hash visitors, renewed every day so that
+    /// hashes can't be linked from one day to another.
+    static ref VISITOR_SALT: Mutex<Option<(NaiveDate, String)>> = Mutex::new(None);
+}
+
+/// Anonymous information about the client reading a post.
+///
+/// It is never stored as is: only a salted hash of it is kept,
+/// for a day at most, to avoid counting the same visitor twice.
+pub struct Visitor {
+    pub ip: Option<String>,
+    pub user_agent: String,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Visitor {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        Outcome::Success(Visitor {
+            ip: request.client_ip().map(|ip| ip.to_string()),
+            user_agent: request
+                .headers()
+                .get_one("User-Agent")
+                .unwrap_or_default()
+                .to_owned(),
+        })
+    }
+}
+
+impl Visitor {
+    /// An identifier for this visitor, that changes every day.
+    pub fn anonymous_id(&self) -> String {
+        self.hash(0, Utc::now().naive_utc().date())
+    }
+
+    fn hash(&self, post_id: i32, day: NaiveDate) -> String {
+        let mut salt = VISITOR_SALT.lock().unwrap();
+        let salt = match *salt {
+            Some((salt_day, ref salt)) if salt_day == day => salt.clone(),
+            _ => {
+                let new_salt = random_hex();
+                *salt = Some((day, new_salt.clone()));
+                new_salt
+            }
+        };
+        sha256(
+            format!(
+                "{}|{}|{}|{}",
+                salt,
+                post_id,
+                self.ip.as_deref().unwrap_or_default(),
+                self.user_agent
+            )
+            .as_bytes(),
+        )
+        .iter()
+        .fold(String::new(), |res, byte| format!("{}{:02x}", res, byte))
+    }
+}
+
+#[derive(Clone, Queryable, Identifiable)]
+pub struct PostView {
+    pub id: i32,
+    pub post_id: i32,
+    pub day: NaiveDate,
+    pub views: i32,
+}
+
+#[derive(Insertable)]
+#[table_name = "post_views"]
+pub struct NewPostView {
+    pub post_id: i32,
+    pub day: NaiveDate,
+    pub views: i32,
+}
+
+#[derive(Insertable)]
+#[table_name = "post_view_visitors"]
+struct NewPostViewVisitor {
+    post_id: i32,
+    day: NaiveDate,
+    visitor: String,
+}
+
+/// Activity on the posts of an author, for a given day.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DailyStats {
+    pub day: NaiveDate,
+    pub views: i64,
+    pub likes: i64,
+    pub reshares: i64,
+    pub comments: i64,
+}
+
+impl PostView {
+    insert!(post_views, NewPostView);
+    get!(post_views);
+    list_by!(post_views, list_for_post, post_id as i32);
+
+    /// Counts a view of `post`, unless this visitor already read it today.
+    ///
+    /// Returns `true` if the view was counted.
+    pub fn record(conn: &Connection, post: &Post, visitor: &Visitor) -> Result<bool> {
+        let today = Utc::now().naive_utc().date();
+        let hash = visitor.hash(post.id, today);
+
+        let already_seen = post_view_visitors::table
+            .filter(post_view_visitors::post_id.eq(post.id))
+            .filter(post_view_visitors::visitor.eq(&hash))
+            .count()
+            .get_result::<i64>(conn)?
+            > 0;
+        if already_seen {
+            return Ok(false);
+        }
+        diesel::insert_into(post_view_visitors::table)
+            .values(NewPostViewVisitor {
+                post_id: post.id,
+                day: today,
+                visitor: hash,
+            })
+            .execute(conn)?;
+
+        let bucket = post_views::table
+            .filter(post_views::post_id.eq(post.id))
+            .filter(post_views::day.eq(today))
+            .first::<PostView>(conn)
+            .optional()?;
+        match bucket {
+            Some(bucket) => {
+                diesel::update(&bucket)
+                    .set(post_views::views.eq(post_views::views + 1))
+                    .execute(conn)?;
+            }
+            None => {
+                Self::insert(
+                    conn,
+                    NewPostView {
+                        post_id: post.id,
+                        day: today,
+                        views: 1,
+                    },
+                )?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Total number of views of a post.
+    pub fn count_for_post(conn: &Connection, post_id: i32) -> Result<i64> {
+        Ok(Self::list_for_post(conn, post_id)?
+            .into_iter()
+            .map(|v| i64::from(v.views))
+            .sum())
+    }
+
+    /// Forgets the visitors of the previous days: their hashes can't
+    /// be matched anymore since the salt changed.
+    pub fn prune_visitors(conn: &Connection) -> Result<usize> {
+        let today = Utc::now().naive_utc().date();
+        diesel::delete(post_view_visitors::table.filter(post_view_visitors::day.lt(today)))
+            .execute(conn)
+            .map_err(Error::from)
+    }
+
+    /// Views, likes, reshares and comments received by the posts of `author`
+    /// for each day since `since` (included), oldest first.
+    pub fn author_stats(
+        conn: &Connection,
+        author: &User,
+        since: NaiveDate,
+    ) -> Result<Vec<DailyStats>> {
+        let today = Utc::now().naive_utc().date();
+        let since_date = since.and_hms(0, 0, 0);
+        let mut days = BTreeMap::new();
+        let mut day = since;
+        while day <= today {
+            days.insert(
+                day,
+                DailyStats {
+                    day,
+                    views: 0,
+                    likes: 0,
+                    reshares: 0,
+                    comments: 0,
+                },
+            );
+            day += Duration::days(1);
+        }
+
+        let author_posts = post_authors::table
+            .filter(post_authors::author_id.eq(author.id))
+            .select(post_authors::post_id);
+
+        for (day, views) in post_views::table
+            .filter(post_views::post_id.eq_any(author_posts.clone()))
+            .filter(post_views::day.ge(since))
+            .select((post_views::day, post_views::views))
+            .load::<(NaiveDate, i32)>(conn)?
+        {
+            if let Some(stats) = days.get_mut(&day) {
+                stats.views += i64::from(views);
+            }
+        }
+        for date in likes::table
+            .filter(likes::post_id.eq_any(author_posts.clone()))
+            .filter(likes::creation_date.ge(since_date))
+            .select(likes::creation_date)
+            .load::<NaiveDateTime>(conn)?
+        {
+            if let Some(stats) = days.get_mut(&date.date()) {
+                stats.likes += 1;
+            }
+        }
+        for date in reshares::table
+            .filter(reshares::post_id.eq_any(author_posts.clone()))
+            .filter(reshares::creation_date.ge(since_date))
+            .select(reshares::creation_date)
+            .load::<NaiveDateTime>(conn)?
+        {
+            if let Some(stats) = days.get_mut(&date.date()) {
+                stats.reshares += 1;
+            }
+        }
+        for date in comments::table
+            .filter(comments::post_id.eq_any(author_posts))
+            .filter(comments::creation_date.ge(since_date))
+            .select(comments::creation_date)
+            .load::<NaiveDateTime>(conn)?
+        {
+            if let Some(stats) = days.get_mut(&date.date()) {
+                stats.comments += 1;
+            }
+        }
+
+        Ok(days.into_iter().map(|(_, stats)| stats).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{inbox::tests::fill_database, tests::db};
+    use diesel::Connection;
+
+    fn visitor(ip: &str) -> Visitor {
+        Visitor {
+            ip: Some(ip.to_owned()),
+            user_agent: "Plume tests".to_owned(),
+        }
+    }
+
+    #[test]
+    fn record_deduplicates_visitors() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, _, _) = fill_database(conn);
+            assert!(PostView::record(conn, &posts[0], &visitor("127.0.0.1"))?);
+            assert!(!PostView::record(conn, &posts[0], &visitor("127.0.0.1"))?);
+            assert!(PostView::record(conn, &posts[0], &visitor("127.0.0.2"))?);
+            assert_eq!(PostView::count_for_post(conn, posts[0].id)?, 2);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn author_stats() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, users, _) = fill_database(conn);
+            PostView::record(conn, &posts[0], &visitor("127.0.0.1"))?;
+
+            let today = Utc::now().naive_utc().date();
+            let stats = PostView::author_stats(conn, &users[0], today - Duration::days(6))?;
+            assert_eq!(stats.len(), 7);
+            assert_eq!(stats.last().unwrap().day, today);
+            assert_eq!(stats.iter().map(|s| s.views).sum::<i64>(), 1);
+            Ok(())
+        });
+    }
+}
@@ -1,10 +1,27 @@
 use crate::{
-    ap_url, blogs::Blog, instance::Instance, medias::Media, mentions::Mention, post_authors::*,
-    safe_string::SafeString, schema::posts, tags::*, timeline::*, users::User, Connection, Error,
-    PostEvent::*, Result, CONFIG, POST_CHAN,
+    ap_url,
+    blogs::Blog,
+    cache,
+    instance::Instance,
+    media_policies::{MediaAction, MediaPolicy},
+    medias::Media,
+    mentions::Mention,
+    post_attachments::PostAttachment,
+    post_authors::*,
+    post_translations::PostTranslation,
+    quotes::Quote,
+    safe_string::SafeString,
+    schema::posts,
+    tag_aliases::TagAlias,
+    tags::*,
+    timeline::*,
+    users::User,
+    Connection, Cursor, Error,
+    PostEvent::*,
+    Result, CONFIG, POST_CHAN,
 };
 use activitystreams::{
-    activity::{Create, Delete, Update},
+    activity::{Announce, Create, Delete, Update},
     base::{AnyBase, Base},
     iri_string::types::IriString,
     link::{self, kind::MentionType},
@@ -13,16 +30,21 @@ use activitystreams::{
     time::OffsetDateTime,
 };
 use chrono::{NaiveDateTime, Utc};
-use diesel::{self, BelongingToDsl, ExpressionMethods, QueryDsl, RunQueryDsl};
+use diesel::{
+    self, BelongingToDsl, BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl,
+};
 use once_cell::sync::Lazy;
 use plume_common::{
     activity_pub::{
+        canonical_url,
         inbox::{AsActor, AsObject, FromId},
+        set_canonical_url, set_translation_of,
         sign::Signer,
-        Hashtag, HashtagType, Id, IntoId, Licensed, LicensedArticle, ToAsString, ToAsUri,
-        PUBLIC_VISIBILITY,
+        translation_of, Hashtag, HashtagType, Id, IntoId, Licensed, LicensedArticle, ToAsString,
+        ToAsUri, PUBLIC_VISIBILITY,
     },
-    utils::{iri_percent_encode_seg, md_to_html},
+    shortcodes,
+    utils::{iri_percent_encode_seg, md_to_html, md_toc, TocEntry},
 };
 use riker::actors::{Publish, Tell};
 use std::collections::{HashMap, HashSet};
@@ -45,6 +67,10 @@ pub struct Post {
     pub subtitle: String,
     pub source: String,
     pub cover_id: Option<i32>,
+    /// Written by the authors to present the article in lists, feeds and previews
+    pub excerpt: String,
+    /// Where the article was first published, if it was cross-posted from elsewhere
+    pub canonical_url: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -61,6 +87,8 @@ pub struct NewPost {
     pub subtitle: String,
     pub source: String,
     pub cover_id: Option<i32>,
+    pub excerpt: String,
+    pub canonical_url: Option<String>,
 }
 
 impl Post {
@@ -88,6 +116,9 @@ impl Post {
 
     pub fn update(&self, conn: &Connection) -> Result<Self> {
         diesel::update(self).set(self).execute(conn)?;
+        cache::forget_article(self.id);
+        cache::forget_post_markdown(self.id);
+        PostTranslation::forget_other_versions(conn, self.id)?;
         let post = Self::get(conn, self.id)?;
         // TODO: Call publish_published() when newly published
         if post.published {
@@ -103,7 +134,11 @@ impl Post {
         for m in Mention::list_for_post(conn, self.id)? {
             m.delete(conn)?;
         }
+        PostTranslation::forget_other_versions(conn, self.id)?;
         diesel::delete(self).execute(conn)?;
+        cache::forget_article(self.id);
+        cache::forget_post_markdown(self.id);
+        cache::forget_timelines();
         self.publish_deleted();
         Ok(())
     }
@@ -170,6 +205,44 @@ impl Post {
         subtitle: Option<String>,
         content: Option<String>,
     ) -> Result<Vec<Post>> {
+        Self::filtered_query(title, subtitle, content)
+            .get_results::<Post>(conn)
+            .map_err(Error::from)
+    }
+
+    /// A page of the articles matching these filters, that `viewer` can see.
+    pub fn list_filtered_page(
+        conn: &Connection,
+        title: Option<String>,
+        subtitle: Option<String>,
+        content: Option<String>,
+        viewer: Option<i32>,
+        cursor: Cursor,
+    ) -> Result<Vec<Post>> {
+        use crate::schema::post_authors;
+
+        let query = Self::filtered_query(title, subtitle, content);
+        let query = match viewer {
+            Some(viewer) => query.filter(
+                posts::published.eq(true).or(posts::id.eq_any(
+                    post_authors::table
+                        .filter(post_authors::author_id.eq(viewer))
+                        .select(post_authors::post_id),
+                )),
+            ),
+            None => query.filter(posts::published.eq(true)),
+        };
+        paginate!(query, posts, cursor)
+            .load::<Post>(conn)
+            .map(|page| cursor.finish(page))
+            .map_err(Error::from)
+    }
+
+    fn filtered_query(
+        title: Option<String>,
+        subtitle: Option<String>,
+        content: Option<String>,
+    ) -> posts::BoxedQuery<'static, <Connection as diesel::Connection>::Backend> {
         let mut query = posts::table.into_boxed();
         if let Some(title) = title {
             query = query.filter(posts::title.eq(title));
@@ -180,8 +253,7 @@ impl Post {
         if let Some(content) = content {
             query = query.filter(posts::content.eq(content));
         }
-
-        query.get_results::<Post>(conn).map_err(Error::from)
+        query
     }
 
     pub fn get_recents_for_author(
@@ -275,6 +347,35 @@ impl Post {
             .map_err(Error::from)
     }
 
+    /// Whether it can be included in search: all of its authors have to allow it.
+    pub fn is_indexable(&self, conn: &Connection) -> Result<bool> {
+        Ok(self
+            .get_authors(conn)?
+            .iter()
+            .all(|author| author.indexable))
+    }
+
+    /// The published articles of this instance that one of their authors doesn't want to be
+    /// indexed.
+    pub fn list_local_not_indexable(conn: &Connection) -> Result<Vec<Post>> {
+        use crate::schema::{blogs, post_authors, users};
+        posts::table
+            .inner_join(blogs::table)
+            .filter(blogs::instance_id.eq(Instance::get_local()?.id))
+            .filter(posts::published.eq(true))
+            .filter(
+                posts::id.eq_any(
+                    post_authors::table
+                        .inner_join(users::table)
+                        .filter(users::indexable.eq(false))
+                        .select(post_authors::post_id),
+                ),
+            )
+            .select(posts::all_columns)
+            .load::<Post>(conn)
+            .map_err(Error::from)
+    }
+
     pub fn is_author(&self, conn: &Connection, author_id: i32) -> Result<bool> {
         use crate::schema::post_authors;
         Ok(PostAuthor::belonging_to(self)
@@ -292,6 +393,20 @@ impl Post {
             .map_err(Error::from)
     }
 
+    /// The outline of the article, linking to the anchors of its headings.
+    ///
+    /// Only local articles are known to have these anchors in their content.
+    pub fn table_of_contents(&self, conn: &Connection) -> Result<Vec<TocEntry>> {
+        if self.get_blog(conn)?.is_local() {
+            let revision = cache::revision(&self.source);
+            cache::get_or_insert(cache::Entry::TableOfContents(self.id, &revision), || {
+                Ok(md_toc(&self.source))
+            })
+        } else {
+            Ok(vec![])
+        }
+    }
+
     /// This method exists for use in templates to reduce database access.
     /// This should not be used for other purpose.
     ///
@@ -354,6 +469,11 @@ impl Post {
             .map(|t| json!(t.to_activity().ok()))
             .collect::<Vec<serde_json::Value>>();
         mentions_json.append(&mut tags_json);
+        mentions_json.extend(
+            Quote::list_for_post(conn, self.id)?
+                .into_iter()
+                .map(|q| q.to_activity()),
+        );
 
         let mut article = ApObject::new(Article::new());
         article.set_name(self.title.clone());
@@ -367,8 +487,13 @@ impl Post {
         authors.push(self.get_blog(conn)?.ap_url.parse::<IriString>()?); // add the blog URL here too
         article.set_many_attributed_tos(authors);
         article.set_content(self.content.get().clone());
+        let revision = cache::revision(&self.source);
+        let source_content = cache::get_or_insert(
+            cache::Entry::SourceWithFallbacks(self.id, &revision),
+            || Ok::<_, Error>(shortcodes::with_fallbacks(&self.source)),
+        )?;
         let source = AnyBase::from_arbitrary_json(serde_json::json!({
-            "content": self.source,
+            "content": source_content,
             "mediaType": "text/markdown",
         }))?;
         article.set_source(source);
@@ -376,7 +501,7 @@ impl Post {
             OffsetDateTime::from_unix_timestamp_nanos(self.creation_date.timestamp_nanos().into())
                 .expect("OffsetDateTime"),
         );
-        article.set_summary(&*self.subtitle);
+        article.set_summary(self.summary());
         article.set_many_tags(
             mentions_json
                 .iter()
@@ -397,6 +522,30 @@ impl Post {
             article.set_icon(cover.into_any_base()?);
         }
 
+        let attachments = PostAttachment::media_for_post(conn, self.id)?
+            .iter()
+            .map(PostAttachment::to_activity)
+            .collect::<Result<Vec<_>>>()?;
+        if !attachments.is_empty() {
+            article.set_many_attachments(
+                attachments
+                    .iter()
+                    .filter_map(|attachment| AnyBase::from_arbitrary_json(attachment).ok()),
+            );
+        }
+
+        if let Ok(translation) = PostTranslation::find_by_post(conn, self.id) {
+            set_translation_of(
+                &mut article,
+                &translation.original(conn)?.ap_url,
+                &translation.language,
+                self.content.get(),
+            )?;
+        }
+        if let Some(ref url) = self.canonical_url {
+            set_canonical_url(&mut article, url)?;
+        }
+
         article.set_url(self.ap_url.parse::<IriString>()?);
         article.set_many_tos(
             to.into_iter()
@@ -428,6 +577,24 @@ impl Post {
         Ok(act)
     }
 
+    /// The creation of this article, relayed by its blog.
+    ///
+    /// Groups following FEP-1b12, like Lemmy communities, are expected to announce
+    /// the activities of their members.
+    pub fn announce_activity(&self, conn: &Connection) -> Result<Announce> {
+        let create = self.create_activity(conn)?;
+        let to = create.to().ok_or(Error::MissingApProperty)?.clone();
+        let cc = create.cc().ok_or(Error::MissingApProperty)?.clone();
+        let mut act = Announce::new(
+            self.get_blog(conn)?.ap_url.parse::<IriString>()?,
+            create.into_any_base()?,
+        );
+        act.set_id(format!("{}/announce", self.ap_url).parse::<IriString>()?);
+        act.set_many_tos(to);
+        act.set_many_ccs(cc);
+        Ok(act)
+    }
+
     pub fn update_activity(&self, conn: &Connection) -> Result<Update> {
         let article = self.to_activity(conn)?;
         let to = article.to().ok_or(Error::MissingApProperty)?.clone();
@@ -485,7 +652,11 @@ impl Post {
     pub fn update_tags(&self, conn: &Connection, tags: Vec<Hashtag>) -> Result<()> {
         let tags_name = tags
             .iter()
-            .filter_map(|t| t.name.as_ref().map(|name| name.as_str().to_string()))
+            .filter_map(|t| {
+                t.name
+                    .as_ref()
+                    .map(|name| TagAlias::canonical(conn, name.as_str()))
+            })
             .collect::<HashSet<_>>();
 
         let old_tags = Tag::for_post(conn, self.id)?;
@@ -522,7 +693,11 @@ impl Post {
     pub fn update_hashtags(&self, conn: &Connection, tags: Vec<Hashtag>) -> Result<()> {
         let tags_name = tags
             .iter()
-            .filter_map(|t| t.name.as_ref().map(|name| name.as_str().to_string()))
+            .filter_map(|t| {
+                t.name
+                    .as_ref()
+                    .map(|name| TagAlias::canonical(conn, name.as_str()))
+            })
             .collect::<HashSet<_>>();
 
         let old_tags = Tag::for_post(conn, self.id)?;
@@ -561,12 +736,70 @@ impl Post {
         Ok(format!("/~/{}/{}", blog.fqn, self.slug))
     }
 
+    /// What presents the article in timelines, feeds, link previews and to other instances:
+    /// its excerpt, or its subtitle if it has none.
+    pub fn summary(&self) -> &str {
+        if self.excerpt.trim().is_empty() {
+            &self.subtitle
+        } else {
+            &self.excerpt
+        }
+    }
+
+    /// The other copies of this article: the ones that share its canonical URL, the article
+    /// this URL points to, and the copies of this one.
+    ///
+    /// Only the articles of one of its authors are copies: anyone else could use the URL of
+    /// an article to hide it.
+    pub fn duplicates(&self, conn: &Connection) -> Result<Vec<Post>> {
+        use crate::schema::post_authors;
+        let authors = post_authors::table
+            .filter(post_authors::post_id.eq(self.id))
+            .select(post_authors::author_id);
+        let same_authors = post_authors::table
+            .filter(post_authors::author_id.eq_any(authors))
+            .select(post_authors::post_id);
+        let query = posts::table
+            .filter(posts::id.ne(self.id))
+            .filter(posts::id.eq_any(same_authors))
+            .into_boxed();
+        let query = match self.canonical_url {
+            Some(ref url) => query.filter(
+                posts::canonical_url
+                    .eq(url)
+                    .or(posts::ap_url.eq(url))
+                    .or(posts::canonical_url.eq(&self.ap_url)),
+            ),
+            None => query.filter(posts::canonical_url.eq(&self.ap_url)),
+        };
+        query.load::<Post>(conn).map_err(Error::from)
+    }
+
     pub fn cover_url(&self, conn: &Connection) -> Option<String> {
         self.cover_id
             .and_then(|i| Media::get(conn, i).ok())
             .and_then(|c| c.url().ok())
     }
 
+    /// The content of the article, with the images that the media policies don't allow
+    /// removed, if it comes from another instance.
+    pub fn displayed_content(&self, conn: &Connection) -> String {
+        MediaPolicy::apply_to_html(conn, self.content.get(), Some(&self.ap_url))
+    }
+
+    /// The URL of the cover to show with the article, and whether it has to be blurred.
+    ///
+    /// Covers from other instances that are rejected or only shown on click are left out
+    /// (see `media_policies`).
+    pub fn displayed_cover(&self, conn: &Connection) -> Option<(String, bool)> {
+        let cover = Media::get(conn, self.cover_id?).ok()?;
+        match cover.policy(conn) {
+            None => Some((cover.url().ok()?, false)),
+            Some(MediaAction::Blur) => Some((cover.url().ok()?, true)),
+            Some(_) => None,
+        }
+    }
+
     pub fn build_delete(&self, conn: &Connection) -> Result<Delete> {
         let mut tombstone = Tombstone::new();
         tombstone.set_id(self.ap_url.parse()?);
@@ -594,6 +827,11 @@ impl Post {
         )
     }
 
+    /// Updates it in the search index, after its authors changed whether it can be included.
+    pub(crate) fn reindex(&self) {
+        self.publish_updated();
+    }
+
     fn publish_updated(&self) {
         POST_CHAN.tell(
             Publish {
@@ -624,8 +862,10 @@ impl FromId<Connection> for Post {
     }
 
     fn from_activity(conn: &Connection, article: LicensedArticle) -> Result<Self> {
-        let license = article.ext_one.license.unwrap_or_default();
-        let article = article.inner;
+        let license = article.ext_one.license;
+        let mut article = article.inner;
+        let translation = translation_of(&mut article);
+        let canonical = canonical_url(&mut article);
 
         let (blog, authors) = article
             .ap_object_ref()
@@ -652,6 +892,11 @@ impl FromId<Connection> for Post {
                 }
             });
 
+        // Articles without a license have the default one of their blog
+        let license = license
+            .or_else(|| blog.as_ref().and_then(|b| b.default_license.clone()))
+            .unwrap_or_default();
+
         let cover = article.icon().and_then(|icon| {
             icon.iter().next().and_then(|img| {
                 let image = img.to_owned().extend::<Image, ImageType>().ok()??;
@@ -689,7 +934,7 @@ impl FromId<Connection> for Post {
                 let mut updated = false;
 
                 let slug = Self::slug(&title);
-                let content = SafeString::new(
+                let content = SafeString::new_remote(
                     &article
                         .content()
                         .and_then(|content| content.to_as_string())
@@ -728,6 +973,10 @@ impl FromId<Connection> for Post {
                     post.cover_id = cover;
                     updated = true;
                 }
+                if post.canonical_url != canonical {
+                    post.canonical_url = canonical.clone();
+                    updated = true;
+                }
 
                 if updated {
                     post.update(conn)?;
@@ -742,7 +991,7 @@ impl FromId<Connection> for Post {
                         blog_id: blog.ok_or(Error::NotFound)?.id,
                         slug: Self::slug(&title).to_string(),
                         title,
-                        content: SafeString::new(
+                        content: SafeString::new_remote(
                             &article
                                 .content()
                                 .and_then(|content| content.to_as_string())
@@ -768,6 +1017,8 @@ impl FromId<Connection> for Post {
                             .ok_or(Error::MissingApProperty)?,
                         source,
                         cover_id: cover,
+                        excerpt: String::new(),
+                        canonical_url: canonical.clone(),
                     },
                 )
                 .and_then(|post| {
@@ -809,6 +1060,27 @@ impl FromId<Connection> for Post {
                     })
                     .ok();
             }
+            Quote::set_for_post(
+                conn,
+                post.id,
+                Quote::urls_from_tags(&serde_json::to_value(tags)?),
+            )?;
+        }
+
+        // Remote audio files are owned by the first author
+        if let (Some(attachments), Some(owner)) = (
+            article.attachment(),
+            post.get_authors(conn)?.into_iter().next(),
+        ) {
+            let media_ids =
+                PostAttachment::from_activity(conn, &serde_json::to_value(attachments)?, &owner);
+            PostAttachment::set_for_post(conn, post.id, &media_ids)?;
+        }
+
+        if let Some((original, language)) = translation {
+            if let Err(e) = PostTranslation::link_remote(conn, &post, &original, &language) {
+                tracing::debug!("Unknown original for translation {}: {:?}", post.ap_url, e);
+            }
         }
 
         Timeline::add_to_all_timelines(conn, &post, Kind::Original)?;
@@ -857,6 +1129,7 @@ pub struct PostUpdate {
     pub source: Option<String>,
     pub license: Option<String>,
     pub tags: Option<serde_json::Value>,
+    pub attachments: Option<serde_json::Value>,
 }
 
 impl FromId<Connection> for PostUpdate {
@@ -901,6 +1174,9 @@ impl FromId<Connection> for PostUpdate {
             tags: updated
                 .tag()
                 .and_then(|tags| serde_json::to_value(tags).ok()),
+            attachments: updated
+                .attachment()
+                .and_then(|attachments| serde_json::to_value(attachments).ok()),
         };
         post_update.cover = updated.ap_object_ref().icon().and_then(|img| {
             img.iter()
@@ -942,7 +1218,7 @@ impl AsObject<User, Update, &Connection> for PostUpdate {
         }
 
         if let Some(content) = self.content {
-            post.content = SafeString::new(&content);
+            post.content = SafeString::new_remote(&content);
         }
 
         if let Some(subtitle) = self.subtitle {
@@ -959,10 +1235,20 @@ impl AsObject<User, Update, &Connection> for PostUpdate {
             post.license = license;
         }
 
+        // Updates contain the whole article: attachments that are missing were removed
+        let media_ids = self
+            .attachments
+            .map(|attachments| PostAttachment::from_activity(conn, &attachments, &actor))
+            .unwrap_or_default();
+        PostAttachment::set_for_post(conn, post.id, &media_ids)?;
+
         let mut txt_hashtags = md_to_html(&post.source, None, false, None)
             .2
             .into_iter()
             .collect::<HashSet<_>>();
+        if let Some(ref tags) = self.tags {
+            Quote::set_for_post(conn, post.id, Quote::urls_from_tags(tags))?;
+        }
         if let Some(serde_json::Value::Array(mention_tags)) = self.tags {
             let mut mentions = vec![];
             let mut tags = vec![];
@@ -1073,6 +1359,8 @@ mod tests {
                     subtitle: "Testing".into(),
                     source: "Hello".into(),
                     cover_id: None,
+                    excerpt: String::new(),
+                    canonical_url: None,
                 },
             )
             .unwrap();
@@ -1185,6 +1473,30 @@ mod tests {
         });
     }
 
+    #[test]
+    fn announce_activity() {
+        let conn = db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (post, _mention, _posts, _users, _blogs) = prepare_activity(&conn);
+            let act = to_value(post.announce_activity(&conn)?)?;
+
+            assert_eq!(act["type"], "Announce");
+            assert_eq!(act["actor"], "https://plu.me/~/BlogName/");
+            assert_eq!(act["id"], "https://plu.me/~/BlogName/testing/announce");
+            assert_eq!(
+                act["to"],
+                json!(["https://www.w3.org/ns/activitystreams#Public"])
+            );
+            assert_eq!(act["object"]["type"], "Create");
+            assert_eq!(
+                act["object"]["object"]["id"],
+                "https://plu.me/~/BlogName/testing"
+            );
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn update_activity() {
         let conn = db();
@@ -1276,4 +1588,78 @@ mod tests {
             Ok(())
         });
     }
+
+    #[test]
+    fn excerpt_summary() {
+        let conn = db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (mut post, _mention, _posts, _users, _blogs) = prepare_activity(&conn);
+            assert_eq!(post.summary(), "Bye");
+
+            post.excerpt = "What this article is about".to_owned();
+            let post = post.update(&conn)?;
+            assert_eq!(post.summary(), "What this article is about");
+            let act = to_value(post.to_activity(&conn)?)?;
+            assert_eq!(act["summary"], "What this article is about");
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn paginated_list() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, users, blogs) = fill_database(conn);
+            let mut ids = vec![posts[0].id];
+            for (i, published) in [true, false, true].iter().enumerate() {
+                let post = Post::insert(
+                    conn,
+                    NewPost {
+                        blog_id: blogs[0].id,
+                        slug: format!("page-{}", i),
+                        title: format!("Page {}", i),
+                        content: SafeString::new("Hello"),
+                        published: *published,
+                        license: "WTFPL".to_owned(),
+                        creation_date: None,
+                        ap_url: format!("https://plu.me/~/{}/page-{}", blogs[0].actor_id, i),
+                        subtitle: String::new(),
+                        source: "Hello".to_owned(),
+                        cover_id: None,
+                        excerpt: String::new(),
+                        canonical_url: None,
+                    },
+                )?;
+                PostAuthor::insert(
+                    conn,
+                    NewPostAuthor {
+                        post_id: post.id,
+                        author_id: users[0].id,
+                    },
+                )?;
+                ids.push(post.id);
+            }
+
+            let page = |viewer, max_id, min_id| {
+                let cursor = Cursor {
+                    max_id,
+                    min_id,
+                    limit: 2,
+                };
+                Post::list_filtered_page(conn, None, None, None, viewer, cursor)
+                    .unwrap()
+                    .into_iter()
+                    .map(|p| p.id)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(page(None, None, None), vec![ids[3], ids[1]]);
+            assert_eq!(page(None, Some(ids[1]), None), vec![ids[0]]);
+            assert_eq!(page(Some(users[0].id), None, None), vec![ids[3], ids[2]]);
+            assert_eq!(
+                page(Some(users[0].id), None, Some(ids[0])),
+                vec![ids[2], ids[1]]
+            );
+            Ok(())
+        });
+    }
 }
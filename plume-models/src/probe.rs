@@ -0,0 +1,118 @@
+//! Checks of the federation with another instance, for `plm federation probe`.
+
+use crate::{
+    config::reloadable, failed_deliveries::FailedDelivery, instance::Instance, Connection, Error,
+    Result, CONFIG,
+};
+use plume_common::activity_pub::request::get;
+use reqwest::blocking::ClientBuilder;
+use serde_json::Value;
+use webfinger::resolve;
+
+/// The result of one of the checks.
+pub struct Check {
+    pub name: &'static str,
+    /// What was found, or what went wrong
+    pub result: std::result::Result<String, String>,
+}
+
+/// Tests if `domain` can be reached, if it accepts the signatures of this instance, and
+/// what this instance knows about it.
+///
+/// The signatures are tested by fetching `actor`, or the actor of the instance if it can be
+/// found with WebFinger.
+pub fn probe(conn: &Connection, domain: &str, actor: Option<&str>) -> Vec<Check> {
+    let mut checks = vec![
+        Check {
+            name: "Known instance",
+            result: known(conn, domain),
+        },
+        Check {
+            name: "NodeInfo",
+            result: nodeinfo(domain).map_err(|e| format!("{:?}", e)),
+        },
+    ];
+    let actor = match actor {
+        Some(actor) => Ok(actor.to_owned()),
+        None => instance_actor(domain),
+    };
+    checks.push(Check {
+        name: "WebFinger",
+        result: actor.clone(),
+    });
+    if let Ok(actor) = actor {
+        checks.push(Check {
+            name: "Signed fetch",
+            result: signed_fetch(conn, &actor),
+        });
+    }
+    checks
+}
+
+fn known(conn: &Connection, domain: &str) -> std::result::Result<String, String> {
+    let instance = Instance::find_by_domain(conn, domain)
+        .map_err(|_| "this instance never federated with it".to_owned())?;
+    if instance.blocked {
+        return Err("it is blocked".to_owned());
+    }
+    let failed = FailedDelivery::list(conn, Some(domain)).unwrap_or_default();
+    Ok(format!(
+        "{}, {} failed deliveries this week",
+        instance.name,
+        failed.len()
+    ))
+}
+
+/// The software of the instance, and its version.
+fn nodeinfo(domain: &str) -> Result<String> {
+    let federation = &reloadable().federation;
+    let mut client = ClientBuilder::new()
+        .connect_timeout(federation.connect_timeout)
+        .timeout(federation.timeout);
+    if let Some(proxy) = CONFIG.proxy() {
+        client = client.proxy(proxy.clone());
+    }
+    let client = client.build()?;
+    let links: Value = client
+        .get(&format!("https://{}/.well-known/nodeinfo", domain))
+        .send()?
+        .error_for_status()?
+        .json()?;
+    let href = links["links"]
+        .as_array()
+        .and_then(|links| links.iter().filter_map(|link| link["href"].as_str()).last())
+        .ok_or(Error::NotFound)?;
+    let nodeinfo: Value = client.get(href).send()?.error_for_status()?.json()?;
+    Ok(format!(
+        "{} {}",
+        nodeinfo["software"]["name"].as_str().unwrap_or("unknown"),
+        nodeinfo["software"]["version"].as_str().unwrap_or_default()
+    ))
+}
+
+/// The actor representing the instance, like the ones of Mastodon (`domain@domain`).
+fn instance_actor(domain: &str) -> std::result::Result<String, String> {
+    resolve(format!("{}@{}", domain, domain), true)
+        .map_err(|e| format!("{:?}", e))?
+        .links
+        .into_iter()
+        .find(|l| l.mime_type.as_deref() == Some("application/activity+json"))
+        .and_then(|l| l.href)
+        .ok_or_else(|| "no actor was found, give one with --actor".to_owned())
+}
+
+fn signed_fetch(conn: &Connection, actor: &str) -> std::result::Result<String, String> {
+    let sender =
+        Instance::get_local_instance_user_uncached(conn).map_err(|e| format!("{:?}", e))?;
+    let res = get(actor, &sender, CONFIG.proxy().cloned())
+        .map_err(|_| format!("{} could not be fetched", actor))?;
+    match res.status().as_u16() {
+        200..=299 => Ok(format!("{} accepted the signature", actor)),
+        401 | 403 => Err(format!(
+            "{} refused the signature (HTTP status {}), this instance may be blocked",
+            actor,
+            res.status()
+        )),
+        _ => Err(format!("HTTP status {} for {}", res.status(), actor)),
+    }
+}
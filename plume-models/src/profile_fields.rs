@@ -0,0 +1,470 @@
+//! Profile fields of users and blogs.
+//!
+//! Like on Mastodon, a profile can have a few label and value pairs, for a website,
+//! pronouns or anything else. They federate as `PropertyValue` attachments of the
+//! actor, the value being HTML.
+//!
+//! Fields that are a link are verified when the linked page links back to the profile
+//! with `rel="me"`. Like Mastodon, every instance checks the links by itself instead of
+//! trusting the other ones, for its local and remote profiles alike.
+
+use crate::{
+    blogs::Blog,
+    cache::{self, Entry},
+    outgoing::{self, Limits},
+    safe_string::SafeString,
+    schema::profile_fields,
+    users::User,
+    Connection, Error, Result,
+};
+use activitystreams::base::AnyBase;
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{self, BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl};
+use plume_common::utils::escape;
+use serde_json::json;
+use std::collections::HashMap;
+
+/// How many fields a profile can have.
+pub const MAX_PROFILE_FIELDS: usize = 4;
+
+const MAX_NAME_LENGTH: usize = 255;
+const MAX_VALUE_LENGTH: usize = 2047;
+
+/// How long before links are checked again.
+const VERIFICATION_INTERVAL_HOURS: i64 = 24;
+
+/// How many fields are checked at once.
+const VERIFICATIONS_PER_RUN: i64 = 20;
+
+/// Only the beginning of the linked pages is read.
+const MAX_PAGE_SIZE: u64 = 1024 * 1024;
+
+/// The profile the fields belong to.
+#[derive(Clone, Copy, Debug)]
+pub enum ProfileOwner {
+    Blog(i32),
+    User(i32),
+}
+
+#[derive(Clone, Queryable, Identifiable)]
+pub struct ProfileField {
+    pub id: i32,
+    pub blog_id: Option<i32>,
+    pub user_id: Option<i32>,
+    pub position: i32,
+    pub name: String,
+    /// The value, as text
+    pub value: String,
+    pub value_html: SafeString,
+    /// When the link of the field was found to link back to the profile
+    pub verified_at: Option<NaiveDateTime>,
+    pub last_checked_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "profile_fields"]
+pub struct NewProfileField {
+    pub blog_id: Option<i32>,
+    pub user_id: Option<i32>,
+    pub position: i32,
+    pub name: String,
+    pub value: String,
+    pub value_html: SafeString,
+    pub verified_at: Option<NaiveDateTime>,
+    pub last_checked_at: Option<NaiveDateTime>,
+}
+
+impl ProfileField {
+    insert!(profile_fields, NewProfileField);
+    get!(profile_fields);
+
+    /// The fields of a profile, in the order they should be displayed.
+    pub fn list(conn: &Connection, owner: ProfileOwner) -> Result<Vec<Self>> {
+        let query = profile_fields::table
+            .order(profile_fields::position.asc())
+            .into_boxed();
+        match owner {
+            ProfileOwner::Blog(id) => query.filter(profile_fields::blog_id.eq(id)),
+            ProfileOwner::User(id) => query.filter(profile_fields::user_id.eq(id)),
+        }
+        .load::<Self>(conn)
+        .map_err(Error::from)
+    }
+
+    /// Replaces the fields of a local profile.
+    ///
+    /// Pairs where both the label and the value are empty are ignored. Values that are
+    /// an HTTP(S) address are made into links.
+    pub fn replace(
+        conn: &Connection,
+        owner: ProfileOwner,
+        fields: &[(String, String)],
+    ) -> Result<Vec<Self>> {
+        let fields = fields
+            .iter()
+            .map(|(name, value)| (name.trim(), value.trim()))
+            .filter(|(name, value)| !name.is_empty() || !value.is_empty())
+            .collect::<Vec<_>>();
+        if fields.len() > MAX_PROFILE_FIELDS
+            || fields.iter().any(|(name, value)| {
+                name.is_empty()
+                    || name.chars().count() > MAX_NAME_LENGTH
+                    || value.chars().count() > MAX_VALUE_LENGTH
+            })
+        {
+            return Err(Error::InvalidValue);
+        }
+
+        let previous = Self::delete_all(conn, owner)?;
+        let fields = fields
+            .into_iter()
+            .enumerate()
+            .map(|(position, (name, value))| {
+                Self::insert(
+                    conn,
+                    Self::new_field(
+                        owner,
+                        position,
+                        name.to_owned(),
+                        value.to_owned(),
+                        value_html(value),
+                        &previous,
+                    ),
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        cache::forget(match owner {
+            ProfileOwner::Blog(id) => Entry::BlogActor(id),
+            ProfileOwner::User(id) => Entry::UserActor(id),
+        });
+        Ok(fields)
+    }
+
+    /// Replaces the fields of a remote profile with the `PropertyValue` attachments of
+    /// its actor. Other attachments are ignored.
+    pub fn replace_remote(
+        conn: &Connection,
+        owner: ProfileOwner,
+        attachments: &[AnyBase],
+    ) -> Result<Vec<Self>> {
+        let previous = Self::delete_all(conn, owner)?;
+        attachments
+            .iter()
+            .filter_map(|attachment| serde_json::to_value(attachment).ok())
+            .filter(|attachment| attachment["type"] == "PropertyValue")
+            .filter_map(|attachment| {
+                let name = attachment["name"].as_str()?.trim();
+                let value = attachment["value"].as_str()?.trim();
+                if name.is_empty() {
+                    None
+                } else {
+                    Some((
+                        name.chars().take(MAX_NAME_LENGTH).collect::<String>(),
+                        value.chars().take(MAX_VALUE_LENGTH).collect::<String>(),
+                    ))
+                }
+            })
+            .take(MAX_PROFILE_FIELDS)
+            .enumerate()
+            .map(|(position, (name, value))| {
+                let html = SafeString::new_remote(&value);
+                let text = ammonia::Builder::empty().clean(&value).to_string();
+                Self::insert(
+                    conn,
+                    Self::new_field(owner, position, name, text, html, &previous),
+                )
+            })
+            .collect()
+    }
+
+    /// The fields as `PropertyValue`, to be attached to an actor.
+    pub fn to_attachments(fields: &[Self]) -> Result<Vec<AnyBase>> {
+        fields
+            .iter()
+            .map(|field| {
+                AnyBase::from_arbitrary_json(json!({
+                    "type": "PropertyValue",
+                    "name": field.name,
+                    "value": field.value_html.get(),
+                }))
+                .map_err(Error::from)
+            })
+            .collect()
+    }
+
+    /// Checks the fields whose link has not been checked recently.
+    pub fn verify_due(conn: &Connection) -> Result<()> {
+        let before = Utc::now().naive_utc() - Duration::hours(VERIFICATION_INTERVAL_HOURS);
+        let due = profile_fields::table
+            .filter(
+                profile_fields::last_checked_at
+                    .is_null()
+                    .or(profile_fields::last_checked_at.lt(before)),
+            )
+            .order(profile_fields::id.asc())
+            .limit(VERIFICATIONS_PER_RUN)
+            .load::<Self>(conn)?;
+        for field in due {
+            if let Err(e) = field.verify(conn) {
+                tracing::warn!("Failed to verify profile field {}: {:?}", field.id, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks if the link of this field links back to the profile, and saves the result.
+    ///
+    /// When the page can't be fetched, the previous result is kept.
+    pub fn verify(&self, conn: &Connection) -> Result<bool> {
+        let now = Utc::now().naive_utc();
+        let verified = match self.link() {
+            Some(link) => match fetch_page(link) {
+                Ok(page) => {
+                    let profile = self.profile_url(conn)?;
+                    links_back(&page, &profile)
+                }
+                Err(e) => {
+                    tracing::info!("Couldn't fetch {}: {:?}", link, e);
+                    self.verified_at.is_some()
+                }
+            },
+            None => false,
+        };
+        let verified_at = if verified {
+            Some(self.verified_at.unwrap_or(now))
+        } else {
+            None
+        };
+        diesel::update(self)
+            .set((
+                profile_fields::verified_at.eq(verified_at),
+                profile_fields::last_checked_at.eq(now),
+            ))
+            .execute(conn)?;
+        Ok(verified)
+    }
+
+    /// The address the value of this field is, if any.
+    pub fn link(&self) -> Option<&str> {
+        Some(self.value.as_str()).filter(|value| is_link(value))
+    }
+
+    fn profile_url(&self, conn: &Connection) -> Result<String> {
+        match (self.blog_id, self.user_id) {
+            (Some(blog_id), _) => Blog::get(conn, blog_id).map(|blog| blog.ap_url),
+            (_, Some(user_id)) => User::get(conn, user_id).map(|user| user.ap_url),
+            _ => Err(Error::NotFound),
+        }
+    }
+
+    /// Fields whose value didn't change stay verified.
+    fn new_field(
+        owner: ProfileOwner,
+        position: usize,
+        name: String,
+        value: String,
+        value_html: SafeString,
+        previous: &[Self],
+    ) -> NewProfileField {
+        let (blog_id, user_id) = match owner {
+            ProfileOwner::Blog(id) => (Some(id), None),
+            ProfileOwner::User(id) => (None, Some(id)),
+        };
+        let previous = previous.iter().find(|field| field.value == value);
+        NewProfileField {
+            blog_id,
+            user_id,
+            position: position as i32,
+            name,
+            value,
+            value_html,
+            verified_at: previous.and_then(|field| field.verified_at),
+            last_checked_at: previous.and_then(|field| field.last_checked_at),
+        }
+    }
+
+    /// Deletes the fields of a profile, and returns them.
+    fn delete_all(conn: &Connection, owner: ProfileOwner) -> Result<Vec<Self>> {
+        let previous = Self::list(conn, owner)?;
+        match owner {
+            ProfileOwner::Blog(id) => {
+                diesel::delete(profile_fields::table.filter(profile_fields::blog_id.eq(id)))
+                    .execute(conn)
+            }
+            ProfileOwner::User(id) => {
+                diesel::delete(profile_fields::table.filter(profile_fields::user_id.eq(id)))
+                    .execute(conn)
+            }
+        }?;
+        Ok(previous)
+    }
+}
+
+fn is_link(value: &str) -> bool {
+    (value.starts_with("https://") || value.starts_with("http://"))
+        && !value.contains(char::is_whitespace)
+}
+
+fn value_html(value: &str) -> SafeString {
+    if is_link(value) {
+        SafeString::trusted(format!(
+            r#"<a href="{url}" rel="me nofollow noopener noreferrer" target="_blank">{url}</a>"#,
+            url = escape(value)
+        ))
+    } else {
+        SafeString::trusted(escape(value).to_string())
+    }
+}
+
+/// Fetches the beginning of a page, that has to be on a public host.
+fn fetch_page(url: &str) -> Result<String> {
+    let page = outgoing::get_beginning(url, &Limits::new(10, MAX_PAGE_SIZE))?;
+    Ok(String::from_utf8_lossy(&page.body).into_owned())
+}
+
+/// Tells if a HTML page has a `rel="me"` link to `profile`.
+fn links_back(page: &str, profile: &str) -> bool {
+    let profile = profile.trim_end_matches('/');
+    rel_me_links(page)
+        .iter()
+        .any(|href| href.trim_end_matches('/') == profile)
+}
+
+/// The addresses of the `<a>` and `<link>` tags of a HTML page with `rel="me"`.
+fn rel_me_links(page: &str) -> Vec<String> {
+    let mut links = Vec::new();
+    let mut rest = page;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let end = rest.find('>').unwrap_or_else(|| rest.len());
+        let tag = &rest[..end];
+        rest = &rest[end..];
+
+        let name_end = tag
+            .find(|c: char| c.is_whitespace() || c == '/')
+            .unwrap_or_else(|| tag.len());
+        let name = tag[..name_end].to_ascii_lowercase();
+        if name != "a" && name != "link" {
+            continue;
+        }
+        let attributes = attributes(&tag[name_end..]);
+        let is_me = attributes.get("rel").map_or(false, |rel| {
+            rel.split_whitespace()
+                .any(|rel| rel.eq_ignore_ascii_case("me"))
+        });
+        if let (true, Some(href)) = (is_me, attributes.get("href")) {
+            links.push(href.clone());
+        }
+    }
+    links
+}
+
+fn attributes(mut rest: &str) -> HashMap<String, String> {
+    let mut attributes = HashMap::new();
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        if rest.is_empty() {
+            break;
+        }
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '/')
+            .unwrap_or_else(|| rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+        let value = match rest.strip_prefix('=').map(str::trim_start) {
+            Some(value) => match value.chars().next() {
+                Some(quote @ '"') | Some(quote @ '\'') => {
+                    let end = value[1..].find(quote).map_or(value.len(), |end| end + 1);
+                    rest = value.get(end + 1..).unwrap_or("");
+                    &value[1..end]
+                }
+                _ => {
+                    let end = value
+                        .find(char::is_whitespace)
+                        .unwrap_or_else(|| value.len());
+                    rest = &value[end..];
+                    &value[..end]
+                }
+            },
+            None => "",
+        };
+        attributes.insert(name, value.replace("&amp;", "&"));
+    }
+    attributes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{blogs::tests::fill_database, tests::db};
+    use diesel::Connection;
+
+    #[test]
+    fn replace_fields() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (users, _) = fill_database(conn);
+            let owner = ProfileOwner::User(users[0].id);
+            ProfileField::replace(
+                conn,
+                owner,
+                &[
+                    ("Website".to_owned(), "https://plume.example/".to_owned()),
+                    (String::new(), String::new()),
+                    ("Likes".to_owned(), "Tea & <cake>".to_owned()),
+                ],
+            )?;
+            let fields = ProfileField::list(conn, owner)?;
+            assert_eq!(fields.len(), 2);
+            assert_eq!(fields[0].name, "Website");
+            assert!(fields[0].value_html.get().starts_with("<a href="));
+            assert_eq!(fields[1].value_html.get(), "Tea &amp; &lt;cake&gt;");
+            assert_eq!(ProfileField::to_attachments(&fields)?.len(), 2);
+            assert!(ProfileField::list(conn, ProfileOwner::User(users[1].id))?.is_empty());
+
+            let too_many = vec![("Name".to_owned(), "Value".to_owned()); MAX_PROFILE_FIELDS + 1];
+            assert!(ProfileField::replace(conn, owner, &too_many).is_err());
+            assert!(
+                ProfileField::replace(conn, owner, &[(String::new(), "Value".to_owned())])
+                    .is_err()
+            );
+            assert_eq!(ProfileField::list(conn, owner)?.len(), 2);
+
+            let remote = ProfileField::replace_remote(
+                conn,
+                owner,
+                &[
+                    AnyBase::from_arbitrary_json(json!({
+                        "type": "PropertyValue",
+                        "name": "Blog",
+                        "value": "<a href=\"https://blog.example/\">blog.example</a><script>alert(1)</script>",
+                    }))?,
+                    AnyBase::from_arbitrary_json(json!({
+                        "type": "IdentityProof",
+                        "name": "Keybase",
+                    }))?,
+                ],
+            )?;
+            assert_eq!(remote.len(), 1);
+            assert_eq!(remote[0].value, "blog.example");
+            assert!(!remote[0].value_html.get().contains("script"));
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn rel_me() {
+        let page = r#"<html><head><link rel="me" href="https://plu.me/@/alice/">
+            <LINK REL=stylesheet href="style.css"></head><body>
+            <a href='https://plu.me/~/blog' rel='nofollow me'>Blog</a>
+            <a href="https://plu.me/@/bob/">Bob</a></body></html>"#;
+        assert_eq!(
+            rel_me_links(page),
+            vec!["https://plu.me/@/alice/", "https://plu.me/~/blog"]
+        );
+        assert!(links_back(page, "https://plu.me/@/alice"));
+        assert!(links_back(page, "https://plu.me/~/blog/"));
+        assert!(!links_back(page, "https://plu.me/@/bob/"));
+        assert!(rel_me_links("<a rel=me").is_empty());
+    }
+}
@@ -0,0 +1,283 @@
+//! Quotes of other fediverse objects, expressed as object links (FEP-e232).
+//!
+//! Object links are `Link` tags with an ActivityStreams media type, pointing to
+//! the quoted object. This is how Misskey and Akkoma represent quote posts.
+
+use crate::{
+    comments::Comment,
+    posts::Post,
+    schema::{comments, posts, quotes},
+    users::User,
+    Connection, Error, Result,
+};
+use chrono::NaiveDateTime;
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+use serde_json::Value;
+
+/// The media type of object links, as recommended by FEP-e232.
+pub const OBJECT_LINK_MEDIA_TYPE: &str =
+    "application/ld+json; profile=\"https://www.w3.org/ns/activitystreams\"";
+
+/// Media types that are also accepted in incoming object links.
+const ACCEPTED_MEDIA_TYPES: &[&str] = &[OBJECT_LINK_MEDIA_TYPE, "application/activity+json"];
+
+#[derive(Clone, Queryable, Identifiable)]
+pub struct Quote {
+    pub id: i32,
+    pub post_id: Option<i32>,
+    pub comment_id: Option<i32>,
+    pub quoted_url: String,
+    pub creation_date: NaiveDateTime,
+}
+
+/// What a quote points to, as a given user can see it.
+pub enum Quoted {
+    Post(Post),
+    Comment {
+        comment: Comment,
+        url: String,
+    },
+    /// Unknown by this instance, or not visible by the user: only the link is shown
+    Link(String),
+}
+
+#[derive(Insertable)]
+#[table_name = "quotes"]
+pub struct NewQuote {
+    pub post_id: Option<i32>,
+    pub comment_id: Option<i32>,
+    pub quoted_url: String,
+}
+
+impl Quote {
+    insert!(quotes, NewQuote);
+    get!(quotes);
+    list_by!(quotes, list_for_post, post_id as i32);
+    list_by!(quotes, list_for_comment, comment_id as i32);
+
+    /// Replaces the objects quoted by an article.
+    pub fn set_for_post(conn: &Connection, post_id: i32, urls: Vec<String>) -> Result<()> {
+        diesel::delete(quotes::table.filter(quotes::post_id.eq(post_id))).execute(conn)?;
+        for quoted_url in urls {
+            Self::insert(
+                conn,
+                NewQuote {
+                    post_id: Some(post_id),
+                    comment_id: None,
+                    quoted_url,
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Saves the objects quoted by a comment.
+    pub fn set_for_comment(conn: &Connection, comment_id: i32, urls: Vec<String>) -> Result<()> {
+        diesel::delete(quotes::table.filter(quotes::comment_id.eq(comment_id))).execute(conn)?;
+        for quoted_url in urls {
+            Self::insert(
+                conn,
+                NewQuote {
+                    post_id: None,
+                    comment_id: Some(comment_id),
+                    quoted_url,
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The URLs of the objects linked from the `tag` property of an activity.
+    pub fn urls_from_tags(tags: &Value) -> Vec<String> {
+        let tags = match tags {
+            Value::Array(tags) => tags.iter().collect(),
+            tag @ Value::Object(_) => vec![tag],
+            _ => vec![],
+        };
+        let mut urls: Vec<String> = vec![];
+        for tag in tags {
+            let is_object_link = tag["type"].as_str() == Some("Link")
+                && tag["mediaType"]
+                    .as_str()
+                    .map(|t| ACCEPTED_MEDIA_TYPES.contains(&t))
+                    .unwrap_or(false);
+            if let Some(href) = tag["href"].as_str().filter(|_| is_object_link) {
+                if !urls.iter().any(|u| u == href) {
+                    urls.push(href.to_owned());
+                }
+            }
+        }
+        urls
+    }
+
+    /// The object link representing this quote, to be added to the tags of an activity.
+    pub fn to_activity(&self) -> Value {
+        json!({
+            "type": "Link",
+            "mediaType": OBJECT_LINK_MEDIA_TYPE,
+            "href": self.quoted_url,
+            "name": format!("RE: {}", self.quoted_url),
+        })
+    }
+
+    /// Finds what `quotes` point to, loading the known articles and comments at once.
+    ///
+    /// Drafts, and comments that `user` can't see, are replaced by a link.
+    pub fn resolve(conn: &Connection, quotes: Vec<Quote>, user: Option<&User>) -> Vec<Quoted> {
+        if quotes.is_empty() {
+            return vec![];
+        }
+        let urls = quotes
+            .iter()
+            .map(|q| q.quoted_url.clone())
+            .collect::<Vec<_>>();
+        let posts = posts::table
+            .filter(posts::ap_url.eq_any(&urls))
+            .filter(posts::published.eq(true))
+            .load::<Post>(conn)
+            .unwrap_or_default();
+        let mut comments = comments::table
+            .filter(comments::ap_url.eq_any(&urls))
+            .load::<Comment>(conn)
+            .unwrap_or_default();
+        comments.retain(|c| c.can_see(conn, user));
+
+        quotes
+            .into_iter()
+            .map(|quote| {
+                if let Some(post) = posts.iter().find(|p| p.ap_url == quote.quoted_url) {
+                    Quoted::Post(post.clone())
+                } else if let Some(comment) = comments
+                    .iter()
+                    .find(|c| c.ap_url.as_ref() == Some(&quote.quoted_url))
+                {
+                    Quoted::Comment {
+                        comment: comment.clone(),
+                        url: quote.quoted_url,
+                    }
+                } else {
+                    Quoted::Link(quote.quoted_url)
+                }
+            })
+            .collect()
+    }
+
+    pub fn delete(&self, conn: &Connection) -> Result<()> {
+        diesel::delete(self)
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        comment_seers::{CommentSeers, NewCommentSeers},
+        comments::NewComment,
+        inbox::tests::fill_database,
+        safe_string::SafeString,
+        tests::db,
+    };
+    use diesel::Connection;
+
+    #[test]
+    fn parse_object_links() {
+        let tags = json!([
+            {
+                "type": "Link",
+                "mediaType": "application/ld+json; profile=\"https://www.w3.org/ns/activitystreams\"",
+                "href": "https://misskey.example/notes/1",
+                "name": "RE: https://misskey.example/notes/1"
+            },
+            {
+                "type": "Link",
+                "mediaType": "application/activity+json",
+                "href": "https://akkoma.example/objects/2"
+            },
+            {
+                "type": "Link",
+                "mediaType": "text/html",
+                "href": "https://example.com"
+            },
+            {
+                "type": "Mention",
+                "href": "https://plu.me/@/admin/"
+            }
+        ]);
+        assert_eq!(
+            Quote::urls_from_tags(&tags),
+            vec![
+                "https://misskey.example/notes/1".to_owned(),
+                "https://akkoma.example/objects/2".to_owned()
+            ]
+        );
+        assert_eq!(
+            Quote::urls_from_tags(&tags[1]),
+            vec!["https://akkoma.example/objects/2".to_owned()]
+        );
+    }
+
+    #[test]
+    fn quote_local_post() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, _, _) = fill_database(conn);
+            Quote::set_for_post(
+                conn,
+                posts[0].id,
+                vec!["https://example.com/unknown".to_owned()],
+            )?;
+            Quote::set_for_post(conn, posts[0].id, vec![posts[0].ap_url.clone()])?;
+            let quotes = Quote::list_for_post(conn, posts[0].id)?;
+            assert_eq!(quotes.len(), 1);
+            assert_eq!(
+                Quote::urls_from_tags(&json!([quotes[0].to_activity()])),
+                vec![posts[0].ap_url.clone()]
+            );
+            match Quote::resolve(conn, quotes, None).as_slice() {
+                [Quoted::Post(post)] => assert_eq!(post.id, posts[0].id),
+                _ => panic!("The article should be found"),
+            }
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn quote_private_comment() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, users, _) = fill_database(conn);
+            let comment = Comment::insert(
+                conn,
+                NewComment {
+                    content: SafeString::new("Only for you"),
+                    post_id: posts[0].id,
+                    author_id: users[0].id,
+                    public_visibility: false,
+                    ..NewComment::default()
+                },
+            )?;
+            CommentSeers::insert(
+                conn,
+                NewCommentSeers {
+                    comment_id: comment.id,
+                    user_id: users[1].id,
+                },
+            )?;
+            let url = comment.ap_url.clone().unwrap();
+            Quote::set_for_post(conn, posts[1].id, vec![url.clone()])?;
+            let quotes = Quote::list_for_post(conn, posts[1].id)?;
+            match Quote::resolve(conn, quotes.clone(), Some(&users[1])).as_slice() {
+                [Quoted::Comment { comment: c, .. }] => assert_eq!(c.id, comment.id),
+                _ => panic!("The recipient should see the comment"),
+            }
+            match Quote::resolve(conn, quotes, None).as_slice() {
+                [Quoted::Link(link)] => assert_eq!(link, &url),
+                _ => panic!("The comment should be hidden"),
+            }
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,284 @@
+//! Limiting how often a client can make costly requests.
+//!
+//! Each kind of request has a token bucket for each IP address, and another one for each
+//! account (or, for comments received from other instances, for each remote actor). IPv6
+//! addresses are grouped by /64 network, as a single host usually has a whole one. Inboxes
+//! are only limited by address: the actor that signed an activity isn't known before its
+//! signature is checked, and anyone could use the `keyId` of someone else. A bucket holds up to `requests` tokens
+//! and is refilled continuously, so that it is full again after `period`. When one of them
+//! is empty, the request is refused with a `429 Too Many Requests` status and a
+//! `Retry-After` header telling when a token will be available.
+//!
+//! The buckets are kept in memory: they are not shared between processes, and are emptied
+//! when Plume restarts.
+
+pub use crate::config::{Rate, RateLimitConfig};
+use crate::{api_tokens::ApiToken, config::reloadable, ip_records::ClientIp, users::User};
+use once_cell::sync::Lazy;
+use rocket::{
+    http::Status,
+    request::{self, FromRequest, Request},
+    Outcome,
+};
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Past this number of buckets, the full ones are forgotten.
+const MAX_BUCKETS: usize = 10_000;
+
+static LIMITER: Lazy<RateLimiter> = Lazy::new(RateLimiter::default);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Bucket {
+    Login,
+    Registration,
+    MediaUpload,
+    ApiRead,
+    Inbox,
+    Comment,
+}
+
+impl Bucket {
+    pub fn rate(self, config: &RateLimitConfig) -> Option<Rate> {
+        match self {
+            Bucket::Login => config.login,
+            Bucket::Registration => config.registration,
+            Bucket::MediaUpload => config.media_upload,
+            Bucket::ApiRead => config.api_read,
+            Bucket::Inbox => config.inbox,
+            Bucket::Comment => config.comment,
+        }
+    }
+}
+
+/// Who is making a request.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Client {
+    /// Use `Client::address` to group IPv6 addresses by network
+    Address(IpAddr),
+    User(i32),
+    /// A remote actor, once their signature was checked
+    Actor(String),
+}
+
+impl Client {
+    pub fn address(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                Some(ip) => Client::Address(IpAddr::V4(ip)),
+                None => {
+                    let mut segments = ip.segments();
+                    segments[4..].fill(0);
+                    Client::Address(IpAddr::V6(segments.into()))
+                }
+            },
+            ip => Client::Address(ip),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct RateLimiter {
+    /// When each bucket will be full again. Taking a token pushes this date back by
+    /// `period / requests`, and a bucket is empty when it is `period` away.
+    full_at: Mutex<HashMap<(Bucket, Client), Instant>>,
+}
+
+impl RateLimiter {
+    /// Takes a token from the bucket of each client, or tells how long they have to wait
+    /// for one if a bucket is empty (in which case no token is taken).
+    pub fn take(
+        &self,
+        bucket: Bucket,
+        clients: &[Client],
+        rate: Rate,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let interval = rate.period / rate.requests;
+        let mut full_at = self.full_at.lock().unwrap();
+        if full_at.len() >= MAX_BUCKETS {
+            full_at.retain(|_, date| *date > now);
+        }
+        let mut updates = Vec::with_capacity(clients.len());
+        let mut wait = Duration::default();
+        for client in clients {
+            let key = (bucket, client.clone());
+            let next = full_at.get(&key).map_or(now, |date| *date.max(&now)) + interval;
+            let ahead = next - now;
+            if ahead > rate.period {
+                wait = wait.max(ahead - rate.period);
+            }
+            updates.push((key, next));
+        }
+        if wait > Duration::default() {
+            return Err(wait);
+        }
+        full_at.extend(updates);
+        Ok(())
+    }
+}
+
+/// A kind of request with its own limit.
+pub trait Limited {
+    const BUCKET: Bucket;
+}
+
+pub struct Login;
+impl Limited for Login {
+    const BUCKET: Bucket = Bucket::Login;
+}
+pub struct Registration;
+impl Limited for Registration {
+    const BUCKET: Bucket = Bucket::Registration;
+}
+pub struct MediaUpload;
+impl Limited for MediaUpload {
+    const BUCKET: Bucket = Bucket::MediaUpload;
+}
+pub struct ApiRead;
+impl Limited for ApiRead {
+    const BUCKET: Bucket = Bucket::ApiRead;
+}
+pub struct Inbox;
+impl Limited for Inbox {
+    const BUCKET: Bucket = Bucket::Inbox;
+}
+pub struct Commenting;
+impl Limited for Commenting {
+    const BUCKET: Bucket = Bucket::Comment;
+}
+
+/// Takes a token for something that isn't limited by a request guard, like a comment
+/// received from a remote actor, or tells how long the clients have to wait.
+pub fn take(bucket: Bucket, clients: &[Client]) -> Result<(), Duration> {
+    match bucket.rate(&reloadable().rate_limits) {
+        Some(rate) => LIMITER.take(bucket, clients, rate, Instant::now()),
+        None => Ok(()),
+    }
+}
+
+/// How many seconds a client that was refused has to wait, for the `Retry-After` header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryAfter(pub u64);
+
+/// The seconds to wait before retrying a request refused by a `RateLimit` guard.
+pub fn retry_after(request: &Request<'_>) -> u64 {
+    request.local_cache(|| RetryAfter(1)).0
+}
+
+/// A guard refusing requests with a `429` status when their client made too many of them.
+///
+/// It should come before the other guards, so that nothing else is done for requests that
+/// are refused.
+pub struct RateLimit<L>(PhantomData<L>);
+
+impl<'a, 'r, L: Limited> FromRequest<'a, 'r> for RateLimit<L> {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        if L::BUCKET.rate(&reloadable().rate_limits).is_none() {
+            return Outcome::Success(RateLimit(PhantomData));
+        }
+        let clients = clients(request, L::BUCKET);
+        match take(L::BUCKET, &clients) {
+            Ok(()) => Outcome::Success(RateLimit(PhantomData)),
+            Err(wait) => {
+                let seconds = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+                request.local_cache(|| RetryAfter(seconds.max(1)));
+                Outcome::Failure((Status::TooManyRequests, ()))
+            }
+        }
+    }
+}
+
+fn clients(request: &Request<'_>, bucket: Bucket) -> Vec<Client> {
+    let mut clients = vec![];
+    if let Outcome::Success(ClientIp(Some(ip))) = request.guard::<ClientIp>() {
+        clients.push(Client::address(ip));
+    }
+    let identity = match bucket {
+        Bucket::Login | Bucket::Registration | Bucket::Inbox => None,
+        Bucket::MediaUpload | Bucket::Comment => request
+            .guard::<User>()
+            .succeeded()
+            .map(|u| Client::User(u.id)),
+        Bucket::ApiRead => request
+            .guard::<ApiToken>()
+            .succeeded()
+            .map(|t| Client::User(t.user_id)),
+    };
+    clients.extend(identity);
+    clients
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_bucket() {
+        let limiter = RateLimiter::default();
+        let rate = Rate {
+            requests: 2,
+            period: Duration::from_secs(60),
+        };
+        let start = Instant::now();
+        let after = |secs| start + Duration::from_secs(secs);
+        let ip = Client::Address("192.0.2.1".parse().unwrap());
+        let other = Client::Address("192.0.2.2".parse().unwrap());
+        let user = Client::User(1);
+        let take = |bucket, clients: &[Client], now| limiter.take(bucket, clients, rate, now);
+
+        assert!(take(Bucket::Login, &[ip.clone()], start).is_ok());
+        assert!(take(Bucket::Login, &[ip.clone()], start).is_ok());
+        assert_eq!(
+            take(Bucket::Login, &[ip.clone()], start),
+            Err(Duration::from_secs(30))
+        );
+        // Each client and each kind of request has its own bucket
+        assert!(take(Bucket::Login, &[other.clone()], start).is_ok());
+        assert!(take(Bucket::Registration, &[ip.clone()], start).is_ok());
+        // A token is added every 30 seconds
+        assert_eq!(
+            take(Bucket::Login, &[ip.clone()], after(20)),
+            Err(Duration::from_secs(10))
+        );
+        assert!(take(Bucket::Login, &[ip.clone()], after(30)).is_ok());
+
+        // Refused when a client is out of tokens, without taking the ones of the others
+        assert_eq!(
+            take(Bucket::Login, &[user.clone(), ip], after(30)),
+            Err(Duration::from_secs(30))
+        );
+        assert!(take(Bucket::Login, &[user.clone(), other.clone()], after(30)).is_ok());
+        assert!(take(Bucket::Login, &[user.clone()], after(30)).is_ok());
+        assert!(take(Bucket::Login, &[user, other], after(30)).is_err());
+    }
+
+    #[test]
+    fn networks() {
+        let client = |ip: &str| Client::address(ip.parse().unwrap());
+        assert_eq!(client("2001:db8:1:2:3:4:5:6"), client("2001:db8:1:2::"));
+        assert_ne!(client("2001:db8:1:2::"), client("2001:db8:1:3::"));
+        assert_eq!(client("::ffff:192.0.2.1"), client("192.0.2.1"));
+        assert_ne!(client("192.0.2.1"), client("192.0.2.2"));
+    }
+
+    #[test]
+    fn parse_rates() {
+        assert_eq!(
+            "10/300".parse::<Rate>(),
+            Ok(Rate {
+                requests: 10,
+                period: Duration::from_secs(300),
+            })
+        );
+        assert!("10".parse::<Rate>().is_err());
+        assert!("0/60".parse::<Rate>().is_err());
+    }
+}
@@ -0,0 +1,266 @@
+//! Who to follow, for new users whose timelines are still empty.
+//!
+//! Local blogs are recommended by how much they published recently, and remote authors by
+//! how many local users follow them, as long as they still publish. Only the blogs and
+//! authors that chose to be discoverable are recommended.
+
+use crate::{
+    blogs::Blog,
+    instance::Instance,
+    schema::{blog_authors, blogs, follows, instances, post_authors, posts, users},
+    users::{Role, User},
+    Connection, Result,
+};
+use chrono::{Duration, Utc};
+use diesel::{
+    dsl::count, BoolExpressionMethods, ExpressionMethods, JoinOnDsl, QueryDsl, RunQueryDsl,
+};
+use std::collections::HashMap;
+
+/// Blogs and authors that did not publish during this period are not recommended.
+const ACTIVITY_DAYS: i64 = 30;
+
+#[derive(Clone, Debug)]
+pub struct Recommended<T> {
+    pub item: T,
+    /// The number of recent articles of a blog, or of local followers of an author
+    pub score: i64,
+}
+
+pub struct Recommendations {
+    pub blogs: Vec<Recommended<Blog>>,
+    pub authors: Vec<Recommended<User>>,
+}
+
+/// At most `limit` blogs and `limit` authors that `user` could follow.
+pub fn for_user(conn: &Connection, user: &User, limit: usize) -> Result<Recommendations> {
+    Ok(Recommendations {
+        blogs: blogs(conn, user, limit)?,
+        authors: authors(conn, user, limit)?,
+    })
+}
+
+/// The local blogs that published the most recently, except the ones of `user`.
+///
+/// The blogs of silenced or deactivated authors are not recommended.
+fn blogs(conn: &Connection, user: &User, limit: usize) -> Result<Vec<Recommended<Blog>>> {
+    let since = Utc::now().naive_utc() - Duration::days(ACTIVITY_DAYS);
+    let scores = posts::table
+        .inner_join(blogs::table)
+        .filter(blogs::instance_id.eq(Instance::get_local()?.id))
+        .filter(blogs::discoverable.eq(true))
+        .filter(posts::published.eq(true))
+        .filter(posts::creation_date.gt(since))
+        .filter(
+            posts::blog_id.ne_all(
+                blog_authors::table
+                    .inner_join(users::table)
+                    .filter(
+                        users::id
+                            .eq(user.id)
+                            .or(users::silenced.eq(true))
+                            .or(users::deactivated.eq(true)),
+                    )
+                    .select(blog_authors::blog_id),
+            ),
+        )
+        .group_by(posts::blog_id)
+        .select((posts::blog_id, count(posts::id)))
+        .order(count(posts::id).desc())
+        .limit(limit as i64)
+        .load::<(i32, i64)>(conn)?;
+    let blogs = blogs::table
+        .filter(blogs::id.eq_any(scores.iter().map(|(id, _)| *id).collect::<Vec<_>>()))
+        .load::<Blog>(conn)?;
+    Ok(ranked(scores, blogs, |blog| blog.id))
+}
+
+/// The remote authors with the most local followers, that `user` doesn't follow yet.
+///
+/// Silenced or deactivated authors, and the ones of blocked instances, are not recommended.
+fn authors(conn: &Connection, user: &User, limit: usize) -> Result<Vec<Recommended<User>>> {
+    let since = Utc::now().naive_utc() - Duration::days(ACTIVITY_DAYS);
+    let local_id = Instance::get_local()?.id;
+    let scores = follows::table
+        .inner_join(users::table.on(follows::follower_id.eq(users::id)))
+        .filter(users::instance_id.eq(local_id))
+        .filter(
+            follows::following_id.ne_all(
+                follows::table
+                    .filter(follows::follower_id.eq(user.id))
+                    .select(follows::following_id),
+            ),
+        )
+        .filter(
+            follows::following_id.eq_any(
+                users::table
+                    .inner_join(instances::table)
+                    .filter(instances::local.eq(false))
+                    .filter(instances::blocked.eq(false))
+                    .filter(users::discoverable.eq(true))
+                    .filter(users::silenced.eq(false))
+                    .filter(users::deactivated.eq(false))
+                    .filter(users::role.ne(Role::Instance as i32))
+                    .select(users::id),
+            ),
+        )
+        .filter(
+            follows::following_id.eq_any(
+                post_authors::table
+                    .inner_join(posts::table)
+                    .filter(posts::published.eq(true))
+                    .filter(posts::creation_date.gt(since))
+                    .select(post_authors::author_id),
+            ),
+        )
+        .group_by(follows::following_id)
+        .select((follows::following_id, count(follows::id)))
+        .order(count(follows::id).desc())
+        .limit(limit as i64)
+        .load::<(i32, i64)>(conn)?;
+    let authors = users::table
+        .filter(users::id.eq_any(scores.iter().map(|(id, _)| *id).collect::<Vec<_>>()))
+        .load::<User>(conn)?;
+    Ok(ranked(scores, authors, |author| author.id))
+}
+
+/// Puts `items` in the order of their `scores`.
+fn ranked<T>(
+    scores: Vec<(i32, i64)>,
+    items: Vec<T>,
+    id: impl Fn(&T) -> i32,
+) -> Vec<Recommended<T>> {
+    let mut items = items
+        .into_iter()
+        .map(|item| (id(&item), item))
+        .collect::<HashMap<_, _>>();
+    scores
+        .into_iter()
+        .filter_map(|(id, score)| items.remove(&id).map(|item| Recommended { item, score }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        blogs::tests::fill_database,
+        follows::{Follow, NewFollow},
+        post_authors::{NewPostAuthor, PostAuthor},
+        posts::{NewPost, Post},
+        safe_string::SafeString,
+        tests::db,
+        users::NewUser,
+        Error,
+    };
+    use diesel::{Connection, SaveChangesDsl};
+
+    #[test]
+    fn recommendations() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (users, mut blogs) = fill_database(conn);
+            let newcomer = User::insert(
+                conn,
+                NewUser {
+                    username: "newcomer".to_owned(),
+                    display_name: "Newcomer".to_owned(),
+                    outbox_url: "https://plu.me/@/newcomer/outbox".to_owned(),
+                    inbox_url: "https://plu.me/@/newcomer/inbox".to_owned(),
+                    instance_id: users[0].instance_id,
+                    ap_url: "https://plu.me/@/newcomer/".to_owned(),
+                    followers_endpoint: "https://plu.me/@/newcomer/followers".to_owned(),
+                    summary_html: SafeString::new(""),
+                    role: Role::Normal as i32,
+                    fqn: "newcomer".to_owned(),
+                    ..NewUser::default()
+                },
+            )?;
+            assert!(for_user(conn, &newcomer, 5)?.blogs.is_empty());
+
+            blogs[0].discoverable = true;
+            let _: Blog = blogs[0].save_changes(conn)?;
+            let post = Post::insert(
+                conn,
+                NewPost {
+                    blog_id: blogs[0].id,
+                    slug: "recent".to_owned(),
+                    title: "Recent".to_owned(),
+                    content: SafeString::new(""),
+                    published: true,
+                    license: String::new(),
+                    creation_date: None,
+                    ap_url: String::new(),
+                    subtitle: String::new(),
+                    source: String::new(),
+                    cover_id: None,
+                    excerpt: String::new(),
+                    canonical_url: None,
+                },
+            )?;
+            let recommended = for_user(conn, &newcomer, 5)?;
+            assert_eq!(recommended.blogs.len(), 1);
+            assert_eq!(recommended.blogs[0].item.id, blogs[0].id);
+            assert_eq!(recommended.blogs[0].score, 1);
+
+            // A remote author followed by a local user, and who published recently
+            let remote = User::insert(
+                conn,
+                NewUser {
+                    username: "writer".to_owned(),
+                    display_name: "Writer".to_owned(),
+                    outbox_url: "https://1plu.me/@/writer/outbox".to_owned(),
+                    inbox_url: "https://1plu.me/@/writer/inbox".to_owned(),
+                    instance_id: Instance::find_by_domain(conn, "1plu.me")?.id,
+                    ap_url: "https://1plu.me/@/writer/".to_owned(),
+                    followers_endpoint: "https://1plu.me/@/writer/followers".to_owned(),
+                    summary_html: SafeString::new(""),
+                    role: Role::Normal as i32,
+                    fqn: "writer@1plu.me".to_owned(),
+                    discoverable: true,
+                    ..NewUser::default()
+                },
+            )?;
+            PostAuthor::insert(
+                conn,
+                NewPostAuthor {
+                    post_id: post.id,
+                    author_id: remote.id,
+                },
+            )?;
+            Follow::insert(
+                conn,
+                NewFollow {
+                    follower_id: users[0].id,
+                    following_id: remote.id,
+                    ap_url: "https://plu.me/follows/1".to_owned(),
+                },
+            )?;
+            let recommended = for_user(conn, &newcomer, 5)?;
+            assert_eq!(recommended.authors.len(), 1);
+            assert_eq!(recommended.authors[0].item.id, remote.id);
+
+            // Silenced authors are not recommended
+            diesel::update(&remote)
+                .set(users::silenced.eq(true))
+                .execute(conn)?;
+            let recommended = for_user(conn, &newcomer, 5)?;
+            assert!(recommended.authors.is_empty());
+            diesel::update(&remote)
+                .set(users::silenced.eq(false))
+                .execute(conn)?;
+
+            // Already followed authors are not recommended
+            Follow::insert(
+                conn,
+                NewFollow {
+                    follower_id: newcomer.id,
+                    following_id: remote.id,
+                    ap_url: "https://plu.me/follows/2".to_owned(),
+                },
+            )?;
+            assert!(for_user(conn, &newcomer, 5)?.authors.is_empty());
+            Ok(())
+        });
+    }
+}
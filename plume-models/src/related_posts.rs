@@ -0,0 +1,255 @@
+use crate::{
+    db_conn::{DbConn, DbPool},
+    maintenance::Maintenance,
+    posts::{Post, PostEvent},
+    schema::{posts, related_posts, tags},
+    search::Searcher,
+    tags::Tag,
+    Connection, Error, Result, ACTOR_SYS, POST_CHAN,
+};
+use diesel::{self, ExpressionMethods, JoinOnDsl, QueryDsl, RunQueryDsl};
+use riker::actors::{Actor, ActorFactoryArgs, ActorRefFactory, Context, Sender, Subscribe, Tell};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::Duration;
+use tracing::error;
+
+/// How many related posts are kept for each post.
+pub const MAX_RELATED_POSTS: usize = 5;
+
+/// Weight of a tag shared between two posts. Text similarity
+/// is normalized between 0 and 1, so a shared tag weights as
+/// much as the best text match.
+const SHARED_TAG_WEIGHT: f32 = 1.0;
+
+/// How many documents are fetched from the search index before ranking.
+const TEXT_CANDIDATES: usize = 20;
+
+#[derive(Clone, Queryable, Identifiable)]
+pub struct RelatedPost {
+    pub id: i32,
+    pub post_id: i32,
+    pub related_post_id: i32,
+    pub score: f32,
+}
+
+#[derive(Insertable)]
+#[table_name = "related_posts"]
+pub struct NewRelatedPost {
+    pub post_id: i32,
+    pub related_post_id: i32,
+    pub score: f32,
+}
+
+impl RelatedPost {
+    insert!(related_posts, NewRelatedPost);
+    get!(related_posts);
+
+    /// Returns the cached related posts of `post`, best matches first.
+    pub fn list_for_post(conn: &Connection, post: &Post, limit: i64) -> Result<Vec<Post>> {
+        posts::table
+            .inner_join(related_posts::table.on(related_posts::related_post_id.eq(posts::id)))
+            .filter(related_posts::post_id.eq(post.id))
+            .filter(posts::published.eq(true))
+            .order(related_posts::score.desc())
+            .limit(limit)
+            .select(posts::all_columns)
+            .load::<Post>(conn)
+            .map_err(Error::from)
+    }
+
+    /// Ranks the posts related to `post`, combining the tags they share
+    /// and the similarity of their text, as given by the search index.
+    pub fn compute(conn: &Connection, searcher: &Searcher, post: &Post) -> Result<Vec<(i32, f32)>> {
+        let mut scores: HashMap<i32, f32> = HashMap::new();
+
+        let tags = Tag::for_post(conn, post.id)?
+            .into_iter()
+            .map(|t| t.tag)
+            .collect::<Vec<_>>();
+        if !tags.is_empty() {
+            for related in tags::table
+                .filter(tags::tag.eq_any(&tags))
+                .filter(tags::post_id.ne(post.id))
+                .select(tags::post_id)
+                .load::<i32>(conn)?
+            {
+                *scores.entry(related).or_insert(0.0) += SHARED_TAG_WEIGHT;
+            }
+        }
+
+        let text_matches = searcher.related_documents(post, TEXT_CANDIDATES);
+        let best = text_matches.iter().map(|(_, s)| *s).fold(0.0, f32::max);
+        if best > 0.0 {
+            for (related, score) in text_matches {
+                *scores.entry(related).or_insert(0.0) += score / best;
+            }
+        }
+        scores.remove(&post.id);
+
+        let published = posts::table
+            .filter(posts::id.eq_any(scores.keys().cloned().collect::<Vec<_>>()))
+            .filter(posts::published.eq(true))
+            .select(posts::id)
+            .load::<i32>(conn)?;
+        let mut ranked = scores
+            .into_iter()
+            .filter(|(id, _)| published.contains(id))
+            .collect::<Vec<_>>();
+        ranked.sort_by(|(a_id, a), (b_id, b)| {
+            b.partial_cmp(a)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a_id.cmp(b_id))
+        });
+        ranked.truncate(MAX_RELATED_POSTS);
+        Ok(ranked)
+    }
+
+    /// Computes the related posts of `post` again and replaces the cached ones.
+    pub fn refresh(
+        conn: &Connection,
+        searcher: &Searcher,
+        post: &Post,
+    ) -> Result<Vec<RelatedPost>> {
+        diesel::delete(related_posts::table.filter(related_posts::post_id.eq(post.id)))
+            .execute(conn)?;
+        if !post.published {
+            return Ok(vec![]);
+        }
+
+        Self::compute(conn, searcher, post)?
+            .into_iter()
+            .map(|(related_post_id, score)| {
+                Self::insert(
+                    conn,
+                    NewRelatedPost {
+                        post_id: post.id,
+                        related_post_id,
+                        score,
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+/// Refreshes related posts when a post is published or updated.
+pub struct RelatedPostsActor {
+    searcher: Arc<Searcher>,
+    conn: DbPool,
+}
+
+impl RelatedPostsActor {
+    pub fn init(searcher: Arc<Searcher>, conn: DbPool) {
+        let actor = ACTOR_SYS
+            .actor_of_args::<RelatedPostsActor, _>("related-posts", (searcher, conn))
+            .expect("Failed to initialize related posts actor");
+
+        POST_CHAN.tell(
+            Subscribe {
+                actor: Box::new(actor),
+                topic: "*".into(),
+            },
+            None,
+        )
+    }
+}
+
+impl Actor for RelatedPostsActor {
+    type Msg = PostEvent;
+
+    fn recv(&mut self, _ctx: &Context<Self::Msg>, msg: Self::Msg, _sender: Sender) {
+        use PostEvent::*;
+
+        match msg {
+            PostPublished(post) | PostUpdated(post) => {
+                // Wait for transaction commited
+                sleep(Duration::from_millis(500));
+                Maintenance::wait_for_end(&self.conn);
+
+                match self.conn.get() {
+                    Ok(conn) => {
+                        RelatedPost::refresh(&DbConn(conn), &self.searcher, &post)
+                            .map(|_| ())
+                            .unwrap_or_else(|e| error!("{:?}", e));
+                    }
+                    _ => {
+                        error!("Failed to get database connection");
+                    }
+                }
+            }
+            // Cached relations are removed by the database
+            PostDeleted(_) => {}
+        }
+    }
+}
+
+impl ActorFactoryArgs<(Arc<Searcher>, DbPool)> for RelatedPostsActor {
+    fn create_args((searcher, conn): (Arc<Searcher>, DbPool)) -> Self {
+        Self { searcher, conn }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        inbox::tests::fill_database, posts::NewPost, safe_string::SafeString,
+        search::tests::get_searcher, tags::NewTag, tests::db, CONFIG,
+    };
+    use diesel::Connection;
+
+    #[test]
+    fn refresh_with_shared_tags() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, _users, blogs) = fill_database(conn);
+            let searcher = get_searcher(&CONFIG.search_tokenizers);
+            let other = Post::insert(
+                conn,
+                NewPost {
+                    blog_id: blogs[0].id,
+                    slug: "related".to_owned(),
+                    title: "Related".to_owned(),
+                    content: SafeString::new("Hello again"),
+                    published: true,
+                    license: "WTFPL".to_owned(),
+                    creation_date: None,
+                    ap_url: "https://plu.me/~/Blog/related".to_owned(),
+                    subtitle: "".to_owned(),
+                    source: "Hello again".to_owned(),
+                    cover_id: None,
+                    excerpt: String::new(),
+                    canonical_url: None,
+                },
+            )?;
+            for post in &[&posts[0], &other] {
+                Tag::insert(
+                    conn,
+                    NewTag {
+                        tag: "Plume".to_owned(),
+                        is_hashtag: false,
+                        post_id: post.id,
+                    },
+                )?;
+            }
+
+            let related = RelatedPost::refresh(conn, &searcher, &posts[0])?;
+            assert_eq!(related.len(), 1);
+            assert_eq!(related[0].related_post_id, other.id);
+            assert_eq!(
+                RelatedPost::list_for_post(conn, &posts[0], 5)?
+                    .into_iter()
+                    .map(|p| p.id)
+                    .collect::<Vec<_>>(),
+                vec![other.id]
+            );
+
+            // Refreshing replaces the cached relations
+            RelatedPost::refresh(conn, &searcher, &posts[0])?;
+            assert_eq!(RelatedPost::list_for_post(conn, &posts[0], 5)?.len(), 1);
+            Ok(())
+        });
+    }
+}
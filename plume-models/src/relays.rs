@@ -0,0 +1,221 @@
+//! Subscriptions to ActivityPub relays.
+//!
+//! A relay forwards the public activities it receives to all the instances that
+//! subscribed to it. It gives followed hashtags a better coverage, as articles are
+//! received even if nobody here follows their author.
+
+use crate::{
+    ap_url, inbox::InboxResult, instance::Instance, posts::Post, schema::relays, users::User,
+    Connection, Error, Result, CONFIG,
+};
+use activitystreams::{
+    activity::{Follow, Undo},
+    base::AnyBase,
+    iri_string::types::IriString,
+    prelude::*,
+};
+use chrono::NaiveDateTime;
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+use openssl::{hash::MessageDigest, pkey::PKey, rsa::Rsa, sign::Verifier};
+use plume_common::activity_pub::{
+    broadcast,
+    inbox::{AsActor, FromId},
+    request, sign, PUBLIC_VISIBILITY,
+};
+use serde_json::Value;
+
+#[derive(Clone, Queryable, Identifiable)]
+pub struct Relay {
+    pub id: i32,
+    pub actor_id: String,
+    pub inbox_url: String,
+    pub public_key: String,
+    pub accepted: bool,
+    pub creation_date: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "relays"]
+pub struct NewRelay {
+    pub actor_id: String,
+    pub inbox_url: String,
+    pub public_key: String,
+}
+
+impl Relay {
+    insert!(relays, NewRelay);
+    get!(relays);
+    find_by!(relays, find_by_actor_id, actor_id as &str);
+
+    pub fn list(conn: &Connection) -> Result<Vec<Self>> {
+        relays::table
+            .order(relays::actor_id.asc())
+            .load::<Self>(conn)
+            .map_err(Error::from)
+    }
+
+    /// Saves a relay and asks it to forward its activities to this instance.
+    ///
+    /// The relay is only used once it accepted the subscription.
+    pub fn subscribe(conn: &Connection, url: &str) -> Result<Self> {
+        if Self::find_by_actor_id(conn, url).is_ok() {
+            return Err(Error::InvalidValue);
+        }
+        let sender = Instance::get_local_instance_user_uncached(conn)?;
+        let actor: Value = request::get(url, &sender, CONFIG.proxy().cloned())?.json()?;
+        let relay = Self::insert(
+            conn,
+            NewRelay {
+                actor_id: actor["id"].as_str().unwrap_or(url).to_owned(),
+                inbox_url: actor["endpoints"]["sharedInbox"]
+                    .as_str()
+                    .or_else(|| actor["inbox"].as_str())
+                    .ok_or(Error::MissingApProperty)?
+                    .to_owned(),
+                public_key: actor["publicKey"]["publicKeyPem"]
+                    .as_str()
+                    .ok_or(Error::MissingApProperty)?
+                    .to_owned(),
+            },
+        )?;
+        broadcast(
+            &sender,
+            relay.build_follow(&sender)?,
+            vec![relay.clone()],
+            CONFIG.proxy().cloned(),
+        );
+        Ok(relay)
+    }
+
+    /// Cancels the subscription and forgets the relay.
+    pub fn unsubscribe(&self, conn: &Connection) -> Result<()> {
+        let sender = Instance::get_local_instance_user_uncached(conn)?;
+        let mut undo = Undo::new(
+            sender.ap_url.parse::<IriString>()?,
+            AnyBase::from_extended(self.build_follow(&sender)?)?,
+        );
+        undo.set_id(format!("{}/undo", self.follow_id()).parse::<IriString>()?);
+        undo.set_many_tos(vec![self.actor_id.parse::<IriString>()?]);
+        broadcast(&sender, undo, vec![self.clone()], CONFIG.proxy().cloned());
+        diesel::delete(self)
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+
+    /// Relays follow the public collection, rather than a specific actor.
+    fn build_follow(&self, sender: &User) -> Result<Follow> {
+        let mut follow = Follow::new(
+            sender.ap_url.parse::<IriString>()?,
+            PUBLIC_VISIBILITY.parse::<IriString>()?,
+        );
+        follow.set_id(self.follow_id().parse::<IriString>()?);
+        follow.set_many_tos(vec![self.actor_id.parse::<IriString>()?]);
+        Ok(follow)
+    }
+
+    fn follow_id(&self) -> String {
+        ap_url(&format!("{}/relays/{}/follow", CONFIG.base_url, self.id))
+    }
+}
+
+/// Finds the relay that sent `act`, if it comes from one.
+pub fn sender(conn: &Connection, act: &Value) -> Option<Relay> {
+    act["actor"]
+        .as_str()
+        .or_else(|| act["actor"]["id"].as_str())
+        .and_then(|actor| Relay::find_by_actor_id(conn, actor).ok())
+}
+
+/// Handles an activity sent by a relay.
+///
+/// Relays accept subscriptions, and announce the public objects they receive. Only
+/// the announced articles are saved: they are fetched from their origin, as relays
+/// don't sign the activities they forward.
+pub fn handle(conn: &Connection, relay: Relay, act: &Value) -> Result<InboxResult> {
+    match act["type"].as_str() {
+        Some("Accept") => {
+            diesel::update(&relay)
+                .set(relays::accepted.eq(true))
+                .execute(conn)?;
+            Ok(InboxResult::Other)
+        }
+        Some("Announce") if relay.accepted => {
+            let id = act["object"]
+                .as_str()
+                .or_else(|| act["object"]["id"].as_str())
+                .ok_or(Error::MissingApProperty)?;
+            // Relays forward all kinds of objects, not only articles
+            Ok(Post::from_id(conn, id, None, CONFIG.proxy())
+                .map(InboxResult::Post)
+                .unwrap_or(InboxResult::Other))
+        }
+        _ => Ok(InboxResult::Other),
+    }
+}
+
+impl AsActor<&Connection> for Relay {
+    fn get_inbox_url(&self) -> String {
+        self.inbox_url.clone()
+    }
+
+    fn is_local(&self) -> bool {
+        false
+    }
+}
+
+impl sign::Signer for Relay {
+    fn get_key_id(&self) -> String {
+        format!("{}#main-key", self.actor_id)
+    }
+
+    /// Only the public key of relays is known.
+    fn sign(&self, _to_sign: &str) -> sign::Result<Vec<u8>> {
+        Err(sign::Error())
+    }
+
+    fn verify(&self, data: &str, signature: &[u8]) -> sign::Result<bool> {
+        let key = PKey::from_rsa(Rsa::public_key_from_pem(self.public_key.as_ref())?)?;
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &key)?;
+        verifier.update(data.as_bytes())?;
+        verifier.verify(signature).map_err(sign::Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{inbox::tests::fill_database, tests::db};
+    use diesel::Connection;
+
+    #[test]
+    fn accept_subscription() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (_, users, _) = fill_database(conn);
+            let relay = Relay::insert(
+                conn,
+                NewRelay {
+                    actor_id: "https://relay.example/actor".to_owned(),
+                    inbox_url: "https://relay.example/inbox".to_owned(),
+                    public_key: users[1].public_key.clone(),
+                },
+            )?;
+            assert!(sender(
+                conn,
+                &json!({ "type": "Announce", "actor": users[0].ap_url })
+            )
+            .is_none());
+
+            let accept = json!({
+                "type": "Accept",
+                "actor": "https://relay.example/actor",
+                "object": relay.follow_id(),
+            });
+            let relay = sender(conn, &accept).expect("Relay not found");
+            handle(conn, relay, &accept)?;
+            assert!(Relay::find_by_actor_id(conn, "https://relay.example/actor")?.accepted);
+            Ok(())
+        });
+    }
+}
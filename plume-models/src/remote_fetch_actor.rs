@@ -1,6 +1,7 @@
 use crate::{
     db_conn::{DbConn, DbPool},
     follows,
+    maintenance::Maintenance,
     posts::Post,
     users::{User, UserEvent},
     ACTOR_SYS, CONFIG, USER_CHAN,
@@ -41,6 +42,7 @@ impl Actor for RemoteFetchActor {
     fn recv(&mut self, _ctx: &Context<Self::Msg>, msg: Self::Msg, _sender: Sender) {
         use UserEvent::*;
 
+        Maintenance::wait_for_end(&self.conn);
         match msg {
             RemoteUserFound(user) => match self.conn.get() {
                 Ok(conn) => {
@@ -0,0 +1,103 @@
+//! An ID for each request, to find all the logs it produced.
+//!
+//! `RequestSpans` opens a `request` span with this ID for each request, and sends it back in
+//! the `X-Request-Id` header. The ID of the request is reused when it is given by a reverse
+//! proxy, or by another Plume instance delivering an activity.
+
+use plume_common::utils::short_id;
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::Header,
+    request::{self, FromRequest, Request},
+    Data, Outcome, Response,
+};
+use std::{cell::RefCell, time::Instant};
+use tracing::{debug, field, info_span, span::EnteredSpan};
+
+pub const HEADER: &str = "X-Request-Id";
+
+thread_local! {
+    /// Rocket handles each request in a single thread, from the first fairing to the last.
+    static CURRENT: RefCell<Option<EnteredSpan>> = RefCell::new(None);
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl<'a, 'r> FromRequest<'a, 'r> for RequestId {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        Outcome::Success(request.local_cache(|| RequestId(short_id())).clone())
+    }
+}
+
+/// Whether an ID given by a client can be used, without messing with the logs.
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+struct Start(Instant);
+
+pub struct RequestSpans;
+
+impl Fairing for RequestSpans {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request spans",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request<'_>, _: &Data) {
+        let id = request
+            .headers()
+            .get_one(HEADER)
+            .filter(|id| is_valid(id))
+            .map_or_else(short_id, str::to_owned);
+        let id = request.local_cache(|| RequestId(id));
+        request.local_cache(|| Start(Instant::now()));
+        let span = info_span!(
+            "request",
+            id = %id.0,
+            method = %request.method(),
+            path = %request.uri().path(),
+            status = field::Empty,
+            otel.kind = "server"
+        );
+        CURRENT.with(|current| *current.borrow_mut() = Some(span.entered()));
+    }
+
+    fn on_response(&self, request: &Request<'_>, response: &mut Response<'_>) {
+        let id = request.local_cache(|| RequestId(short_id()));
+        response.set_header(Header::new(HEADER, id.0.clone()));
+        let start = request.local_cache(|| Start(Instant::now()));
+        debug!(
+            status = response.status().code,
+            duration_ms = start.0.elapsed().as_millis() as u64,
+            "Request handled"
+        );
+        CURRENT.with(|current| {
+            if let Some(span) = current.borrow_mut().take() {
+                span.record("status", &response.status().code);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_ids() {
+        assert!(is_valid("3f2a9c0d-41b7"));
+        assert!(!is_valid(""));
+        assert!(!is_valid("id\nfake log line"));
+        assert!(!is_valid(&"a".repeat(65)));
+    }
+}
@@ -0,0 +1,225 @@
+//! Limits on the size of request bodies, and on the time taken to receive them.
+//!
+//! Rocket only has global limits, per content type. Routes that need their own use
+//! `LimitedForm` or `LimitedData` instead of `LenientForm` and `Data`: login and
+//! registration forms are small and should be sent quickly, media can be large and slow to
+//! upload, and activities are in between.
+//!
+//! Requests announcing a larger body are refused with a `413 Payload Too Large` status,
+//! before reading it. The body is then read until the limit, or until the deadline: it is
+//! checked between reads, so a client sending its body very slowly is cut off with a
+//! `408 Request Timeout`, while one sending nothing is left to the read timeout of the
+//! server.
+
+pub use crate::config::{BodyLimit, BodyLimits};
+use crate::CONFIG;
+use rocket::{
+    data::{self, DataStream, FromDataSimple},
+    http::Status,
+    request::{FormItems, FromForm, Request},
+    Data,
+    Outcome::*,
+};
+use std::{
+    error::Error as StdError,
+    fmt,
+    io::{self, Read},
+    marker::PhantomData,
+    ops::Deref,
+    time::Instant,
+};
+
+/// A kind of route with its own limits.
+pub trait RouteClass {
+    fn limit(limits: &BodyLimits) -> BodyLimit;
+}
+
+pub struct Auth;
+impl RouteClass for Auth {
+    fn limit(limits: &BodyLimits) -> BodyLimit {
+        limits.auth
+    }
+}
+pub struct Upload;
+impl RouteClass for Upload {
+    fn limit(limits: &BodyLimits) -> BodyLimit {
+        limits.media
+    }
+}
+pub struct Inbox;
+impl RouteClass for Inbox {
+    fn limit(limits: &BodyLimits) -> BodyLimit {
+        limits.inbox
+    }
+}
+
+#[derive(Debug)]
+struct TooLarge;
+
+impl fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the request body is too large")
+    }
+}
+
+impl StdError for TooLarge {}
+
+/// A reader failing once it read more than `size` bytes, or when its deadline is passed.
+pub struct LimitedReader<R> {
+    inner: R,
+    remaining: u64,
+    deadline: Instant,
+}
+
+impl<R: Read> LimitedReader<R> {
+    /// The deadline starts now.
+    pub fn new(inner: R, limit: BodyLimit) -> Self {
+        LimitedReader {
+            inner,
+            remaining: limit.size,
+            deadline: Instant::now() + limit.timeout,
+        }
+    }
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if Instant::now() >= self.deadline {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "the request body took too long to be received",
+            ));
+        }
+        if self.remaining == 0 {
+            // Only an error if there was more to read
+            let mut byte = [0];
+            return match self.inner.read(&mut byte)? {
+                0 => Ok(0),
+                _ => Err(io::Error::new(io::ErrorKind::Other, TooLarge)),
+            };
+        }
+        let max = self.remaining.min(buf.len() as u64) as usize;
+        let read = self.inner.read(&mut buf[..max])?;
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+/// The status to answer with when a body couldn't be read.
+pub fn error_status(err: &io::Error) -> Status {
+    if err.kind() == io::ErrorKind::TimedOut {
+        Status::RequestTimeout
+    } else if err.get_ref().map_or(false, |e| e.is::<TooLarge>()) {
+        Status::PayloadTooLarge
+    } else {
+        Status::BadRequest
+    }
+}
+
+/// Whether a request announces a body larger than `limit`.
+pub fn announces_more(request: &Request<'_>, limit: BodyLimit) -> bool {
+    request
+        .headers()
+        .get_one("Content-Length")
+        .and_then(|len| len.parse::<u64>().ok())
+        .map_or(false, |len| len > limit.size)
+}
+
+/// The body of a request, with the limits of its route.
+pub struct LimitedData<C> {
+    data: Data,
+    limit: BodyLimit,
+    class: PhantomData<C>,
+}
+
+impl<C> LimitedData<C> {
+    pub fn open(self) -> LimitedReader<DataStream> {
+        LimitedReader::new(self.data.open(), self.limit)
+    }
+}
+
+impl<C: RouteClass> FromDataSimple for LimitedData<C> {
+    type Error = ();
+
+    fn from_data(request: &Request<'_>, data: Data) -> data::Outcome<Self, ()> {
+        let limit = C::limit(&CONFIG.body_limits);
+        if announces_more(request, limit) {
+            return Failure((Status::PayloadTooLarge, ()));
+        }
+        Success(LimitedData {
+            data,
+            limit,
+            class: PhantomData,
+        })
+    }
+}
+
+/// Like `LenientForm`, with the limits of its route.
+pub struct LimitedForm<C, T>(T, PhantomData<C>);
+
+impl<C, T> LimitedForm<C, T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<C, T> Deref for LimitedForm<C, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<C: RouteClass, T: for<'f> FromForm<'f>> FromDataSimple for LimitedForm<C, T> {
+    type Error = ();
+
+    fn from_data(request: &Request<'_>, data: Data) -> data::Outcome<Self, ()> {
+        if !request.content_type().map_or(false, |ct| ct.is_form()) {
+            return Forward(data);
+        }
+        let data = match LimitedData::<C>::from_data(request, data) {
+            Success(data) => data,
+            Failure(failure) => return Failure(failure),
+            Forward(data) => return Forward(data),
+        };
+        let mut body = String::new();
+        if let Err(err) = data.open().read_to_string(&mut body) {
+            return Failure((error_status(&err), ()));
+        }
+        match T::from_form(&mut FormItems::from(body.as_str()), false) {
+            Ok(form) => Success(LimitedForm(form, PhantomData)),
+            Err(_) => Failure((Status::UnprocessableEntity, ())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn read(body: &[u8], limit: BodyLimit) -> io::Result<Vec<u8>> {
+        let mut res = vec![];
+        LimitedReader::new(body, limit).read_to_end(&mut res)?;
+        Ok(res)
+    }
+
+    #[test]
+    fn limited_reader() {
+        let limit = BodyLimit {
+            size: 5,
+            timeout: Duration::from_secs(10),
+        };
+        assert_eq!(read(b"short", limit).unwrap(), b"short");
+        let err = read(b"longer", limit).unwrap_err();
+        assert_eq!(error_status(&err), Status::PayloadTooLarge);
+
+        let expired = BodyLimit {
+            size: 5,
+            timeout: Duration::from_secs(0),
+        };
+        let err = read(b"short", expired).unwrap_err();
+        assert_eq!(error_status(&err), Status::RequestTimeout);
+    }
+}
@@ -82,15 +82,21 @@ impl Reshare {
 
     pub fn notify(&self, conn: &Connection) -> Result<()> {
         let post = self.get_post(conn)?;
+        let actor = self.get_user(conn)?;
         for author in post.get_authors(conn)? {
             if author.is_local() {
-                Notification::insert(
+                Notification::notify_from(
                     conn,
+                    &actor,
+                    "",
                     NewNotification {
                         kind: notification_kind::RESHARE.to_string(),
                         object_id: self.id,
                         user_id: author.id,
+                        group_key: None,
+                        filtered: false,
                     },
+                    Some(self.post_id),
                 )?;
             }
         }
@@ -191,10 +197,7 @@ impl AsObject<User, Undo, &Connection> for Reshare {
         if actor.id == self.user_id {
             diesel::delete(&self).execute(conn)?;
 
-            // delete associated notification if any
-            if let Ok(notif) = Notification::find(conn, notification_kind::RESHARE, self.id) {
-                diesel::delete(&notif).execute(conn)?;
-            }
+            Notification::retract(conn, notification_kind::RESHARE, self.id, self.post_id)?;
 
             Ok(())
         } else {
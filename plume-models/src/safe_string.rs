@@ -1,3 +1,4 @@
+use crate::CONFIG;
 use ammonia::{Builder, UrlRelative};
 use diesel::{
     self,
@@ -6,6 +7,7 @@ use diesel::{
     sql_types::Text,
     types::ToSql,
 };
+use plume_common::utils::is_embeddable;
 use serde::{self, de::Visitor, Deserialize, Deserializer, Serialize, Serializer};
 use std::{
     borrow::{Borrow, Cow},
@@ -14,71 +16,204 @@ use std::{
     ops::Deref,
 };
 
-lazy_static! {
-    static ref CLEAN: Builder<'static> = {
-        let mut b = Builder::new();
-        b.add_generic_attributes(&["id", "dir"])
-            .add_tags(&["iframe", "video", "audio", "label", "input"])
-            .id_prefix(Some("postcontent-"))
-            .url_relative(UrlRelative::Custom(Box::new(url_add_prefix)))
-            .add_tag_attributes(
-                "iframe",
-                ["width", "height", "src", "frameborder"].iter().cloned(),
-            )
-            .add_tag_attributes("video", ["src", "title", "controls"].iter())
-            .add_tag_attributes("audio", ["src", "title", "controls"].iter())
-            .add_tag_attributes("label", ["for"].iter())
-            .add_tag_attributes("input", ["type", "checked"].iter())
-            .add_allowed_classes("input", ["cw-checkbox"].iter())
-            .add_allowed_classes(
-                "span",
-                [
-                    "cw-container",
-                    "cw-text",
-                    //Scope classes for the syntax highlighting.
-                    "attribute-name",
-                    "comment",
-                    "constant",
-                    "control",
-                    "declaration",
-                    "entity",
-                    "function",
-                    "invalid",
-                    "keyword",
-                    "language",
-                    "modifier",
-                    "name",
-                    "numeric",
-                    "operator",
-                    "parameter",
-                    "punctuation",
-                    "source",
-                    "storage",
-                    "string",
-                    "support",
-                    "tag",
-                    "type",
-                    "variable",
-                ]
-                .iter(),
-            )
-            // Related to https://github.com/Plume-org/Plume/issues/637
-            .add_allowed_classes("sup", ["footnote-reference", "footnote-definition-label"].iter())
-            .add_allowed_classes("div", ["footnote-definition"].iter())
-            .attribute_filter(|elem, att, val| match (elem, att) {
-                ("input", "type") => Some("checkbox".into()),
-                ("input", "checked") => Some("checked".into()),
-                ("label", "for") => {
-                    if val.starts_with("postcontent-cw-") {
-                        Some(val.into())
-                    } else {
-                        None
-                    }
+/// MathML elements used to render formulas.
+const MATHML_TAGS: &[&str] = &[
+    "math",
+    "semantics",
+    "annotation",
+    "mrow",
+    "mi",
+    "mn",
+    "mo",
+    "ms",
+    "mtext",
+    "mspace",
+    "msub",
+    "msup",
+    "msubsup",
+    "munder",
+    "mover",
+    "munderover",
+    "mfrac",
+    "msqrt",
+    "mroot",
+    "mtable",
+    "mtr",
+    "mtd",
+    "mstyle",
+    "mpadded",
+    "mphantom",
+    "menclose",
+];
+
+const MATHML_ATTRIBUTES: &[&str] = &[
+    "display",
+    "mathvariant",
+    "stretchy",
+    "fence",
+    "separator",
+    "accent",
+    "accentunder",
+    "lspace",
+    "rspace",
+    "linethickness",
+    "columnalign",
+    "movablelimits",
+    "largeop",
+    "symmetric",
+    "notation",
+    "encoding",
+    "width",
+    "height",
+    "depth",
+];
+
+/// Where the HTML to clean comes from.
+#[derive(Clone, Copy, PartialEq)]
+enum Origin {
+    /// Written on this instance: the extra tags, attributes and styles of the admin apply
+    Local,
+    /// Received from another instance: only the default tags are allowed
+    Remote,
+    /// Received from another instance, with the strict policy: no styles or iframes either
+    StrictRemote,
+}
+
+/// Builds the HTML cleaner, following the policy of the instance.
+///
+/// What the admin allows in addition to the default tags is only trusted from local authors.
+fn cleaner(origin: Origin) -> Builder<'static> {
+    let policy = &CONFIG.sanitizer;
+    let strict = origin == Origin::StrictRemote;
+    let inline_styles = origin == Origin::Local && policy.inline_styles;
+    let mut b = Builder::new();
+    for tag in MATHML_TAGS {
+        b.add_tag_attributes(tag, MATHML_ATTRIBUTES.iter());
+    }
+    b.add_generic_attributes(&["id", "dir"])
+        .add_tags(&["iframe", "video", "audio", "label", "input"])
+        .add_tags(MATHML_TAGS)
+        .id_prefix(Some("postcontent-"))
+        .url_relative(UrlRelative::Custom(Box::new(url_add_prefix)))
+        .add_tag_attributes(
+            "iframe",
+            ["width", "height", "src", "frameborder"].iter().cloned(),
+        )
+        .add_tag_attributes("video", ["src", "title", "controls"].iter())
+        .add_tag_attributes("audio", ["src", "title", "controls"].iter())
+        .add_tag_attributes("div", ["data-embed"].iter())
+        .add_tag_attributes("label", ["for"].iter())
+        .add_tag_attributes("input", ["type", "checked"].iter())
+        .add_tag_attributes("th", ["style"].iter())
+        .add_tag_attributes("td", ["style"].iter())
+        .add_allowed_classes("input", ["cw-checkbox"].iter())
+        .add_allowed_classes(
+            "span",
+            [
+                "cw-container",
+                "cw-text",
+                //Scope classes for the syntax highlighting.
+                "attribute-name",
+                "comment",
+                "constant",
+                "control",
+                "declaration",
+                "entity",
+                "function",
+                "invalid",
+                "keyword",
+                "language",
+                "modifier",
+                "name",
+                "numeric",
+                "operator",
+                "parameter",
+                "punctuation",
+                "source",
+                "storage",
+                "string",
+                "support",
+                "tag",
+                "type",
+                "variable",
+            ]
+            .iter(),
+        )
+        // Related to https://github.com/Plume-org/Plume/issues/637
+        .add_allowed_classes(
+            "sup",
+            ["footnote-reference", "footnote-definition-label"].iter(),
+        )
+        .add_allowed_classes("div", ["footnote-definition", "gallery", "embed"].iter())
+        // Shortcodes
+        .add_allowed_classes("details", ["cw"].iter())
+        .add_allowed_classes("nav", ["toc"].iter())
+        .add_allowed_classes(
+            "li",
+            [
+                "toc-level-1",
+                "toc-level-2",
+                "toc-level-3",
+                "toc-level-4",
+                "toc-level-5",
+                "toc-level-6",
+            ]
+            .iter(),
+        )
+        .attribute_filter(move |elem, att, val| match (elem, att) {
+            ("input", "type") => Some("checkbox".into()),
+            ("input", "checked") => Some("checked".into()),
+            // Only the sites the instance allows can be embedded
+            ("iframe", "src") | ("div", "data-embed") => {
+                if is_embeddable(val) {
+                    Some(val.into())
+                } else {
+                    None
                 }
-                _ => Some(val.into()),
-            });
-        b
-    };
+            }
+            // Alignment of table columns
+            ("th", "style") | ("td", "style") if !inline_styles => match val {
+                "text-align: left" | "text-align: center" | "text-align: right" => Some(val.into()),
+                _ => None,
+            },
+            ("label", "for") => {
+                if val.starts_with("postcontent-cw-") {
+                    Some(val.into())
+                } else {
+                    None
+                }
+            }
+            _ => Some(val.into()),
+        });
+
+    if strict {
+        b.rm_tags(&["iframe"])
+            .rm_tag_attributes("th", &["style"])
+            .rm_tag_attributes("td", &["style"]);
+    } else if origin == Origin::Local {
+        // Scripts and style sheets are always removed with their content
+        b.add_tags(
+            policy
+                .extra_tags
+                .iter()
+                .filter(|t| *t != "script" && *t != "style"),
+        )
+        .add_generic_attributes(policy.extra_attributes.iter());
+        if inline_styles {
+            b.add_generic_attributes(&["style"]);
+        }
+    }
+    b.rm_tags(policy.removed_tags.iter());
+    if let Some(ref schemes) = policy.url_schemes {
+        b.url_schemes(schemes.iter().map(String::as_str).collect());
+    }
+    b
+}
+
+lazy_static! {
+    static ref CLEAN: Builder<'static> = cleaner(Origin::Local);
+    static ref REMOTE_CLEAN: Builder<'static> = cleaner(Origin::Remote);
+    static ref STRICT_CLEAN: Builder<'static> = cleaner(Origin::StrictRemote);
 }
 
 #[allow(clippy::unnecessary_wraps)]
@@ -106,6 +241,21 @@ impl SafeString {
         }
     }
 
+    /// Creates a new `SafeString` from content received from another instance.
+    ///
+    /// The tags and attributes allowed in addition to the default ones are removed, and it
+    /// uses the strict policy if the instance is configured to.
+    pub fn new_remote(value: &str) -> Self {
+        let cleaner = if CONFIG.sanitizer.strict_remote {
+            &*STRICT_CLEAN
+        } else {
+            &*REMOTE_CLEAN
+        };
+        SafeString {
+            value: cleaner.clean(value).to_string(),
+        }
+    }
+
     /// Creates a new `SafeString`, but without escaping the given value.
     ///
     /// Only use when you are sure you can trust the input (when the HTML
@@ -222,3 +372,64 @@ impl<'v> FromFormValue<'v> for SafeString {
         Ok(SafeString::new(&val))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plume_common::utils::md_to_html;
+
+    #[test]
+    fn extended_markdown() {
+        let md = "Text[^note] and ~~not~~ this.\n\n\
+                  | Left | Center |\n|:-----|:------:|\n| a | b |\n\n\
+                  - [x] done\n- [ ] todo\n\n\
+                  [^note]: The note.\n";
+        let html = SafeString::new(&md_to_html(md, None, false, None).0);
+        assert!(html.contains(
+            r##"<sup class="footnote-reference" id="postcontent-fnref-1"><a href="#postcontent-fn-1">1</a></sup>"##
+        ));
+        assert!(html.contains(r#"<div class="footnote-definition" id="postcontent-fn-1">"#));
+        assert!(html.contains(r##"<a href="#postcontent-fnref-1">↩</a></div>"##));
+        assert!(html.contains("<del>not</del>"));
+        assert!(html.contains(r#"style="text-align: center""#));
+        assert!(html.contains("☑ done</li>"));
+        assert!(html.contains("☐ todo</li>"));
+
+        let style = SafeString::new(r#"<td style="color: red">x</td>"#);
+        assert!(!style.contains("style"));
+    }
+
+    #[test]
+    fn math() {
+        let html = SafeString::new(&md_to_html("Area: $\\pi r^2$", None, false, None).0);
+        assert!(html.contains("<math"));
+        assert!(html.contains("<msup>"));
+    }
+
+    #[test]
+    fn embeds() {
+        let html = SafeString::new(
+            r#"<iframe src="https://www.youtube-nocookie.com/embed/abc"></iframe>
+<iframe src="https://tracker.example/embed/abc"></iframe>
+<div class="embed" data-embed="https://tracker.example/embed/abc"></div>"#,
+        );
+        assert!(html.contains(r#"src="https://www.youtube-nocookie.com/embed/abc""#));
+        assert!(!html.contains("tracker.example"));
+        assert!(html.contains(r#"<div class="embed">"#));
+    }
+
+    #[test]
+    fn strict_policy() {
+        let html = r#"<iframe src="https://www.youtube-nocookie.com/embed/abc"></iframe><table><tr><td style="text-align: center">x</td></tr></table><p>Text</p>"#;
+        let strict = STRICT_CLEAN.clean(html).to_string();
+        assert!(!strict.contains("iframe"));
+        assert!(!strict.contains("style"));
+        assert!(strict.contains("<p>Text</p>"));
+        let relaxed = CLEAN.clean(html).to_string();
+        assert!(relaxed.contains("iframe"));
+        assert!(relaxed.contains(r#"style="text-align: center""#));
+        let remote = REMOTE_CLEAN.clean(html).to_string();
+        assert!(remote.contains("iframe"));
+        assert!(remote.contains(r#"style="text-align: center""#));
+    }
+}
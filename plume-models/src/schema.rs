@@ -1,3 +1,53 @@
+table! {
+    account_notes (id) {
+        id -> Int4,
+        owner_id -> Int4,
+        target_id -> Int4,
+        content -> Text,
+        creation_date -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    about_sections (id) {
+        id -> Int4,
+        position -> Int4,
+        title -> Varchar,
+        content -> Text,
+        content_html -> Text,
+    }
+}
+
+table! {
+    announcement_dismissals (id) {
+        id -> Int4,
+        announcement_id -> Int4,
+        user_id -> Int4,
+    }
+}
+
+table! {
+    announcement_reactions (id) {
+        id -> Int4,
+        announcement_id -> Int4,
+        user_id -> Int4,
+        emoji -> Varchar,
+    }
+}
+
+table! {
+    announcements (id) {
+        id -> Int4,
+        author_id -> Nullable<Int4>,
+        content -> Text,
+        content_html -> Text,
+        starts_at -> Nullable<Timestamp>,
+        ends_at -> Nullable<Timestamp>,
+        creation_date -> Timestamp,
+    }
+}
+
 table! {
     api_tokens (id) {
         id -> Int4,
@@ -21,12 +71,39 @@ table! {
     }
 }
 
+table! {
+    blocklist_entries (id) {
+        id -> Int4,
+        source_id -> Int4,
+        domain -> Varchar,
+        reason -> Text,
+    }
+}
+
+table! {
+    blocklist_exceptions (id) {
+        id -> Int4,
+        domain -> Varchar,
+        creation_date -> Timestamp,
+    }
+}
+
+table! {
+    blocklist_sources (id) {
+        id -> Int4,
+        url -> Varchar,
+        last_sync -> Nullable<Timestamp>,
+        last_error -> Nullable<Text>,
+        creation_date -> Timestamp,
+    }
+}
+
 table! {
     blog_authors (id) {
         id -> Int4,
         blog_id -> Int4,
         author_id -> Int4,
-        is_owner -> Bool,
+        role -> Int4,
     }
 }
 
@@ -48,6 +125,25 @@ table! {
         icon_id -> Nullable<Int4>,
         banner_id -> Nullable<Int4>,
         theme -> Nullable<Varchar>,
+        comments_order -> Int4,
+        comments_max_depth -> Nullable<Int4>,
+        allow_guest_comments -> Bool,
+        discoverable -> Bool,
+        directory_category -> Nullable<Varchar>,
+        moved_to -> Nullable<Text>,
+        moved_from -> Nullable<Text>,
+        default_license -> Nullable<Text>,
+    }
+}
+
+table! {
+    categories (id) {
+        id -> Int4,
+        blog_id -> Int4,
+        parent_id -> Nullable<Int4>,
+        name -> Varchar,
+        slug -> Varchar,
+        creation_date -> Timestamp,
     }
 }
 
@@ -63,6 +159,18 @@ table! {
         sensitive -> Bool,
         spoiler_text -> Text,
         public_visibility -> Bool,
+        held -> Bool,
+        spam_score -> Nullable<Int4>,
+    }
+}
+
+table! {
+    comment_likes (id) {
+        id -> Int4,
+        user_id -> Int4,
+        comment_id -> Int4,
+        creation_date -> Timestamp,
+        ap_url -> Varchar,
     }
 }
 
@@ -74,6 +182,39 @@ table! {
     }
 }
 
+table! {
+    connectors (id) {
+        id -> Int4,
+        blog_id -> Int4,
+        kind -> Varchar,
+        url -> Varchar,
+        api_key -> Nullable<Varchar>,
+        enabled -> Bool,
+        creation_date -> Timestamp,
+    }
+}
+
+table! {
+    crosspost_opt_outs (id) {
+        id -> Int4,
+        post_id -> Int4,
+    }
+}
+
+table! {
+    crossposts (id) {
+        id -> Int4,
+        connector_id -> Int4,
+        post_id -> Int4,
+        state -> Int4,
+        attempts -> Int4,
+        next_attempt -> Timestamp,
+        remote_url -> Nullable<Varchar>,
+        last_error -> Nullable<Varchar>,
+        creation_date -> Timestamp,
+    }
+}
+
 table! {
     email_blocklist (id) {
         id -> Int4,
@@ -84,6 +225,17 @@ table! {
     }
 }
 
+table! {
+    email_changes (id) {
+        id -> Int4,
+        user_id -> Int4,
+        email -> Text,
+        token -> Varchar,
+        confirmed -> Bool,
+        expiration_date -> Timestamp,
+    }
+}
+
 table! {
     email_signups (id) {
         id -> Int4,
@@ -93,6 +245,49 @@ table! {
     }
 }
 
+table! {
+    failed_activities (id) {
+        id -> Int4,
+        activity -> Text,
+        error -> Text,
+        attempts -> Int4,
+        next_attempt -> Timestamp,
+        creation_date -> Timestamp,
+    }
+}
+
+table! {
+    failed_deliveries (id) {
+        id -> Int4,
+        activity_id -> Text,
+        activity -> Text,
+        key_id -> Text,
+        inbox -> Text,
+        host -> Varchar,
+        error -> Text,
+        attempts -> Int4,
+        creation_date -> Timestamp,
+        last_attempt -> Timestamp,
+    }
+}
+
+table! {
+    failed_logins (id) {
+        id -> Int4,
+        user_id -> Nullable<Int4>,
+        address -> Nullable<Text>,
+        creation_date -> Timestamp,
+    }
+}
+
+table! {
+    federation_digests (id) {
+        id -> Int4,
+        report -> Text,
+        creation_date -> Timestamp,
+    }
+}
+
 table! {
     follows (id) {
         id -> Int4,
@@ -102,6 +297,49 @@ table! {
     }
 }
 
+table! {
+    fundings (id) {
+        id -> Int4,
+        blog_id -> Nullable<Int4>,
+        user_id -> Nullable<Int4>,
+        payment_pointer -> Varchar,
+        liberapay_url -> Varchar,
+        kofi_url -> Varchar,
+    }
+}
+
+table! {
+    guest_comments (id) {
+        id -> Int4,
+        post_id -> Int4,
+        author_name -> Varchar,
+        author_email -> Nullable<Varchar>,
+        content -> Text,
+        creation_date -> Timestamp,
+        approved -> Bool,
+        visitor -> Varchar,
+    }
+}
+
+table! {
+    hashtag_follows (id) {
+        id -> Int4,
+        user_id -> Int4,
+        tag -> Varchar,
+        creation_date -> Timestamp,
+    }
+}
+
+table! {
+    instance_rules (id) {
+        id -> Int4,
+        position -> Int4,
+        short_text -> Varchar,
+        long_text -> Text,
+        creation_date -> Timestamp,
+    }
+}
+
 table! {
     instances (id) {
         id -> Int4,
@@ -116,6 +354,40 @@ table! {
         default_license -> Text,
         long_description_html -> Varchar,
         short_description_html -> Varchar,
+        blocked_by_subscription -> Bool,
+        contact_user_id -> Nullable<Int4>,
+    }
+}
+
+table! {
+    ip_records (id) {
+        id -> Int4,
+        kind -> Int4,
+        user_id -> Nullable<Int4>,
+        comment_id -> Nullable<Int4>,
+        address -> Text,
+        ip_range -> Nullable<Text>,
+        creation_date -> Timestamp,
+    }
+}
+
+table! {
+    legal_acceptances (id) {
+        id -> Int4,
+        user_id -> Int4,
+        document_id -> Int4,
+        creation_date -> Timestamp,
+    }
+}
+
+table! {
+    legal_documents (id) {
+        id -> Int4,
+        kind -> Int4,
+        version -> Int4,
+        content -> Text,
+        content_html -> Text,
+        creation_date -> Timestamp,
     }
 }
 
@@ -149,6 +421,23 @@ table! {
     }
 }
 
+table! {
+    maintenance (id) {
+        id -> Int4,
+        reason -> Text,
+        start_date -> Timestamp,
+    }
+}
+
+table! {
+    media_policies (id) {
+        id -> Int4,
+        domain -> Nullable<Varchar>,
+        action -> Int4,
+        creation_date -> Timestamp,
+    }
+}
+
 table! {
     medias (id) {
         id -> Int4,
@@ -159,6 +448,13 @@ table! {
         sensitive -> Bool,
         content_warning -> Nullable<Text>,
         owner_id -> Int4,
+        variants -> Nullable<Text>,
+        content_hash -> Nullable<Text>,
+        creation_date -> Timestamp,
+        scan_status -> Int4,
+        scan_result -> Nullable<Text>,
+        duration -> Nullable<Int4>,
+        bitrate -> Nullable<Int4>,
     }
 }
 
@@ -171,6 +467,28 @@ table! {
     }
 }
 
+table! {
+    mutes (id) {
+        id -> Int4,
+        user_id -> Int4,
+        kind -> Int4,
+        target_id -> Nullable<Int4>,
+        value -> Text,
+        expires_at -> Nullable<Timestamp>,
+        creation_date -> Timestamp,
+    }
+}
+
+table! {
+    notification_policies (id) {
+        id -> Int4,
+        user_id -> Int4,
+        not_following -> Int4,
+        new_accounts -> Int4,
+        no_avatar -> Int4,
+    }
+}
+
 table! {
     notifications (id) {
         id -> Int4,
@@ -178,6 +496,10 @@ table! {
         creation_date -> Timestamp,
         kind -> Varchar,
         object_id -> Int4,
+        read -> Bool,
+        group_key -> Nullable<Varchar>,
+        count -> Int4,
+        filtered -> Bool,
     }
 }
 
@@ -190,6 +512,14 @@ table! {
     }
 }
 
+table! {
+    post_attachments (id) {
+        id -> Int4,
+        post_id -> Int4,
+        media_id -> Int4,
+    }
+}
+
 table! {
     post_authors (id) {
         id -> Int4,
@@ -198,6 +528,52 @@ table! {
     }
 }
 
+table! {
+    post_categories (id) {
+        id -> Int4,
+        post_id -> Int4,
+        category_id -> Int4,
+    }
+}
+
+table! {
+    post_reviews (id) {
+        id -> Int4,
+        post_id -> Int4,
+        submitted_by -> Int4,
+        creation_date -> Timestamp,
+    }
+}
+
+table! {
+    post_translations (id) {
+        id -> Int4,
+        post_id -> Int4,
+        original_id -> Int4,
+        language -> Varchar,
+        translator_id -> Int4,
+        creation_date -> Timestamp,
+    }
+}
+
+table! {
+    post_view_visitors (id) {
+        id -> Int4,
+        post_id -> Int4,
+        day -> Date,
+        visitor -> Varchar,
+    }
+}
+
+table! {
+    post_views (id) {
+        id -> Int4,
+        post_id -> Int4,
+        day -> Date,
+        views -> Int4,
+    }
+}
+
 table! {
     posts (id) {
         id -> Int4,
@@ -212,6 +588,52 @@ table! {
         subtitle -> Text,
         source -> Text,
         cover_id -> Nullable<Int4>,
+        excerpt -> Text,
+        canonical_url -> Nullable<Text>,
+    }
+}
+
+table! {
+    profile_fields (id) {
+        id -> Int4,
+        blog_id -> Nullable<Int4>,
+        user_id -> Nullable<Int4>,
+        position -> Int4,
+        name -> Varchar,
+        value -> Text,
+        value_html -> Text,
+        verified_at -> Nullable<Timestamp>,
+        last_checked_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    quotes (id) {
+        id -> Int4,
+        post_id -> Nullable<Int4>,
+        comment_id -> Nullable<Int4>,
+        quoted_url -> Varchar,
+        creation_date -> Timestamp,
+    }
+}
+
+table! {
+    related_posts (id) {
+        id -> Int4,
+        post_id -> Int4,
+        related_post_id -> Int4,
+        score -> Float4,
+    }
+}
+
+table! {
+    relays (id) {
+        id -> Int4,
+        actor_id -> Varchar,
+        inbox_url -> Varchar,
+        public_key -> Text,
+        accepted -> Bool,
+        creation_date -> Timestamp,
     }
 }
 
@@ -225,6 +647,75 @@ table! {
     }
 }
 
+table! {
+    retired_keys (id) {
+        id -> Int4,
+        reference -> Text,
+        forget_after -> Timestamp,
+    }
+}
+
+table! {
+    series (id) {
+        id -> Int4,
+        blog_id -> Int4,
+        title -> Varchar,
+        slug -> Varchar,
+        summary -> Text,
+        creation_date -> Timestamp,
+    }
+}
+
+table! {
+    series_posts (id) {
+        id -> Int4,
+        series_id -> Int4,
+        post_id -> Int4,
+        position -> Int4,
+    }
+}
+
+table! {
+    sessions (id) {
+        id -> Int4,
+        user_id -> Int4,
+        token -> Text,
+        user_agent -> Nullable<Text>,
+        address -> Nullable<Text>,
+        creation_date -> Timestamp,
+        last_seen -> Timestamp,
+    }
+}
+
+table! {
+    severances (id) {
+        id -> Int4,
+        domain -> Varchar,
+        reason -> Varchar,
+        notified -> Bool,
+        creation_date -> Timestamp,
+    }
+}
+
+table! {
+    severed_relationships (id) {
+        id -> Int4,
+        severance_id -> Int4,
+        user_id -> Int4,
+        remote_actor -> Varchar,
+        following -> Bool,
+    }
+}
+
+table! {
+    tag_aliases (id) {
+        id -> Int4,
+        alias -> Varchar,
+        tag -> Varchar,
+        creation_date -> Timestamp,
+    }
+}
+
 table! {
     tags (id) {
         id -> Int4,
@@ -234,6 +725,16 @@ table! {
     }
 }
 
+table! {
+    thread_subscriptions (id) {
+        id -> Int4,
+        user_id -> Int4,
+        post_id -> Int4,
+        creation_date -> Timestamp,
+        last_emailed_comment_id -> Nullable<Int4>,
+    }
+}
+
 table! {
     timeline (id) {
         id -> Int4,
@@ -251,6 +752,26 @@ table! {
     }
 }
 
+table! {
+    trending_posts (id) {
+        id -> Int4,
+        post_id -> Int4,
+        score -> Float4,
+        accounts -> Int4,
+        computed_at -> Timestamp,
+    }
+}
+
+table! {
+    trending_tags (id) {
+        id -> Int4,
+        tag -> Varchar,
+        score -> Float4,
+        accounts -> Int4,
+        computed_at -> Timestamp,
+    }
+}
+
 table! {
     users (id) {
         id -> Int4,
@@ -275,18 +796,51 @@ table! {
         role -> Int4,
         preferred_theme -> Nullable<Varchar>,
         hide_custom_css -> Bool,
+        silenced -> Bool,
+        banner_id -> Nullable<Int4>,
+        deactivated -> Bool,
+        discoverable -> Bool,
+        indexable -> Bool,
+        published -> Nullable<Timestamp>,
+        last_active -> Nullable<Timestamp>,
+        inactivity_reminded_at -> Nullable<Timestamp>,
+        frozen_at -> Nullable<Timestamp>,
+        pending_email -> Nullable<Text>,
     }
 }
 
+joinable!(announcement_dismissals -> announcements (announcement_id));
+joinable!(announcement_dismissals -> users (user_id));
+joinable!(announcement_reactions -> announcements (announcement_id));
+joinable!(announcement_reactions -> users (user_id));
+joinable!(announcements -> users (author_id));
 joinable!(api_tokens -> apps (app_id));
 joinable!(api_tokens -> users (user_id));
+joinable!(blocklist_entries -> blocklist_sources (source_id));
 joinable!(blog_authors -> blogs (blog_id));
 joinable!(blog_authors -> users (author_id));
 joinable!(blogs -> instances (instance_id));
+joinable!(categories -> blogs (blog_id));
+joinable!(comment_likes -> comments (comment_id));
+joinable!(comment_likes -> users (user_id));
 joinable!(comment_seers -> comments (comment_id));
 joinable!(comment_seers -> users (user_id));
 joinable!(comments -> posts (post_id));
 joinable!(comments -> users (author_id));
+joinable!(connectors -> blogs (blog_id));
+joinable!(crosspost_opt_outs -> posts (post_id));
+joinable!(crossposts -> connectors (connector_id));
+joinable!(crossposts -> posts (post_id));
+joinable!(email_changes -> users (user_id));
+joinable!(failed_logins -> users (user_id));
+joinable!(fundings -> blogs (blog_id));
+joinable!(fundings -> users (user_id));
+joinable!(guest_comments -> posts (post_id));
+joinable!(hashtag_follows -> users (user_id));
+joinable!(ip_records -> comments (comment_id));
+joinable!(ip_records -> users (user_id));
+joinable!(legal_acceptances -> legal_documents (document_id));
+joinable!(legal_acceptances -> users (user_id));
 joinable!(likes -> posts (post_id));
 joinable!(likes -> users (user_id));
 joinable!(list_elems -> blogs (blog_id));
@@ -296,42 +850,116 @@ joinable!(lists -> users (user_id));
 joinable!(mentions -> comments (comment_id));
 joinable!(mentions -> posts (post_id));
 joinable!(mentions -> users (mentioned_id));
+joinable!(notification_policies -> users (user_id));
 joinable!(notifications -> users (user_id));
 joinable!(post_authors -> posts (post_id));
 joinable!(post_authors -> users (author_id));
+joinable!(post_attachments -> medias (media_id));
+joinable!(post_attachments -> posts (post_id));
+joinable!(post_categories -> categories (category_id));
+joinable!(post_categories -> posts (post_id));
+joinable!(post_reviews -> posts (post_id));
+joinable!(post_reviews -> users (submitted_by));
+joinable!(post_translations -> posts (post_id));
+joinable!(post_translations -> users (translator_id));
+joinable!(post_view_visitors -> posts (post_id));
+joinable!(post_views -> posts (post_id));
 joinable!(posts -> blogs (blog_id));
 joinable!(posts -> medias (cover_id));
+joinable!(profile_fields -> blogs (blog_id));
+joinable!(profile_fields -> users (user_id));
+joinable!(quotes -> comments (comment_id));
+joinable!(quotes -> posts (post_id));
+joinable!(related_posts -> posts (post_id));
 joinable!(reshares -> posts (post_id));
 joinable!(reshares -> users (user_id));
+joinable!(series -> blogs (blog_id));
+joinable!(series_posts -> posts (post_id));
+joinable!(series_posts -> series (series_id));
+joinable!(sessions -> users (user_id));
+joinable!(severed_relationships -> severances (severance_id));
+joinable!(severed_relationships -> users (user_id));
 joinable!(tags -> posts (post_id));
+joinable!(thread_subscriptions -> posts (post_id));
+joinable!(thread_subscriptions -> users (user_id));
 joinable!(timeline -> posts (post_id));
 joinable!(timeline -> timeline_definition (timeline_id));
 joinable!(timeline_definition -> users (user_id));
+joinable!(trending_posts -> posts (post_id));
 joinable!(users -> instances (instance_id));
 
 allow_tables_to_appear_in_same_query!(
+    about_sections,
+    account_notes,
+    announcement_dismissals,
+    announcement_reactions,
+    announcements,
     api_tokens,
     apps,
+    blocklist_entries,
+    blocklist_exceptions,
+    blocklist_sources,
     blog_authors,
     blogs,
+    categories,
     comments,
+    comment_likes,
     comment_seers,
+    connectors,
+    crosspost_opt_outs,
+    crossposts,
     email_blocklist,
+    email_changes,
     email_signups,
+    failed_activities,
+    failed_deliveries,
+    failed_logins,
+    federation_digests,
     follows,
+    fundings,
+    guest_comments,
+    hashtag_follows,
+    instance_rules,
     instances,
+    ip_records,
+    legal_acceptances,
+    legal_documents,
     likes,
     list_elems,
     lists,
+    maintenance,
+    media_policies,
     medias,
     mentions,
+    mutes,
+    notification_policies,
     notifications,
     password_reset_requests,
+    post_attachments,
     post_authors,
+    post_categories,
+    post_reviews,
+    post_translations,
+    post_view_visitors,
+    post_views,
     posts,
+    profile_fields,
+    quotes,
+    related_posts,
+    relays,
     reshares,
+    retired_keys,
+    series,
+    series_posts,
+    sessions,
+    severances,
+    severed_relationships,
+    tag_aliases,
     tags,
+    thread_subscriptions,
     timeline,
     timeline_definition,
+    trending_posts,
+    trending_tags,
     users,
 );
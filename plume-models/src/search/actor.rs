@@ -1,5 +1,8 @@
 use super::Searcher;
-use crate::{db_conn::DbPool, posts::PostEvent, ACTOR_SYS, POST_CHAN};
+use crate::{
+    comments::CommentEvent, db_conn::DbPool, maintenance::Maintenance, posts::PostEvent, ACTOR_SYS,
+    COMMENT_CHAN, POST_CHAN,
+};
 use riker::actors::{Actor, ActorFactoryArgs, ActorRefFactory, Context, Sender, Subscribe, Tell};
 use std::sync::Arc;
 use std::thread::sleep;
@@ -14,7 +17,7 @@ pub struct SearchActor {
 impl SearchActor {
     pub fn init(searcher: Arc<Searcher>, conn: DbPool) {
         let actor = ACTOR_SYS
-            .actor_of_args::<SearchActor, _>("search", (searcher, conn))
+            .actor_of_args::<SearchActor, _>("search", (searcher.clone(), conn.clone()))
             .expect("Failed to initialize searcher actor");
 
         POST_CHAN.tell(
@@ -23,6 +26,18 @@ impl SearchActor {
                 topic: "*".into(),
             },
             None,
+        );
+
+        let actor = ACTOR_SYS
+            .actor_of_args::<CommentSearchActor, _>("comment-search", (searcher, conn))
+            .expect("Failed to initialize comment searcher actor");
+
+        COMMENT_CHAN.tell(
+            Subscribe {
+                actor: Box::new(actor),
+                topic: "*".into(),
+            },
+            None,
         )
     }
 }
@@ -35,6 +50,7 @@ impl Actor for SearchActor {
 
         // Wait for transaction commited
         sleep(Duration::from_millis(500));
+        Maintenance::wait_for_end(&self.conn);
 
         match msg {
             PostPublished(post) => {
@@ -74,11 +90,47 @@ impl ActorFactoryArgs<(Arc<Searcher>, DbPool)> for SearchActor {
     }
 }
 
+/// Keeps the comments of the index up to date, whether they were written here or
+/// received in an inbox.
+pub struct CommentSearchActor {
+    searcher: Arc<Searcher>,
+    conn: DbPool,
+}
+
+impl Actor for CommentSearchActor {
+    type Msg = CommentEvent;
+
+    fn recv(&mut self, _ctx: &Context<Self::Msg>, msg: Self::Msg, _sender: Sender) {
+        use CommentEvent::*;
+
+        // Wait for transaction commited
+        sleep(Duration::from_millis(500));
+        Maintenance::wait_for_end(&self.conn);
+
+        match msg {
+            CommentPublished(comment) => match self.conn.get() {
+                Ok(conn) => self
+                    .searcher
+                    .update_comment(&conn, &comment)
+                    .unwrap_or_else(|e| error!("{:?}", e)),
+                _ => error!("Failed to get database connection"),
+            },
+            CommentDeleted(comment) => self.searcher.delete_comment(&comment),
+        }
+    }
+}
+
+impl ActorFactoryArgs<(Arc<Searcher>, DbPool)> for CommentSearchActor {
+    fn create_args((searcher, conn): (Arc<Searcher>, DbPool)) -> Self {
+        Self { searcher, conn }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::diesel::Connection;
     use crate::{
-        blog_authors::{BlogAuthor, NewBlogAuthor},
+        blog_authors::{BlogAuthor, BlogRole, NewBlogAuthor},
         blogs::{Blog, NewBlog},
         db_conn::{DbPool, PragmaForeignKey},
         instance::{Instance, NewInstance},
@@ -128,6 +180,8 @@ mod tests {
                 subtitle: "".to_owned(),
                 source: "".to_owned(),
                 cover_id: None,
+                excerpt: String::new(),
+                canonical_url: None,
             },
         )
         .unwrap();
@@ -187,6 +241,9 @@ mod tests {
                     summary_html: SafeString::new(""),
                     role: 0,
                     fqn: random_hex(),
+                    discoverable: false,
+                    indexable: true,
+                    published: None,
                 },
             )
             .unwrap();
@@ -204,7 +261,7 @@ mod tests {
                 NewBlogAuthor {
                     blog_id: blog.id,
                     author_id: user.id,
-                    is_owner: true,
+                    role: BlogRole::Owner as i32,
                 },
             )
             .unwrap();
@@ -2,15 +2,16 @@ pub mod actor;
 mod query;
 mod searcher;
 mod tokenizer;
-pub use self::query::PlumeQuery as Query;
+pub use self::query::{PlumeQuery as Query, ResultKind};
 pub use self::searcher::*;
 pub use self::tokenizer::TokenizerKind;
 
 #[cfg(test)]
 pub(crate) mod tests {
-    use super::{Query, Searcher};
+    use super::{Query, SearchResult, Searcher};
     use crate::{
         blogs::tests::fill_database,
+        comments::{Comment, NewComment},
         config::SearchTokenizerConfig,
         post_authors::*,
         posts::{NewPost, Post},
@@ -67,6 +68,12 @@ pub(crate) mod tests {
             ("-author:@user@domain", "-author:user@domain"),
             ("before:2017-11-05 before:2018-01-01", "before:2017-11-05"),
             ("after:2017-11-05 after:2018-01-01", "after:2018-01-01"),
+            ("#rust -#go", "tag:rust -tag:go"),
+            ("+@user@domain words", "words +author:user@domain"),
+            ("# @", "# @"),
+            ("type:comments -#go", "-tag:go type:comment"),
+            ("-type:comment", "type:article"),
+            ("type:something", ""),
         ];
         for (source, res) in vector {
             assert_eq!(&Query::from_str(source).unwrap().to_string(), res);
@@ -99,6 +106,27 @@ pub(crate) mod tests {
         }
     }
 
+    #[test]
+    fn parse_field() {
+        let vector = vec![
+            (
+                "tag",
+                "#rust \"free software\" -go",
+                "tag:rust tag:\"free software\" -tag:go",
+            ),
+            (
+                "author",
+                "@someone +other@somewhere",
+                "author:someone +author:other@somewhere",
+            ),
+            ("after", "2017-11-05 not-a-date", "after:2017-11-05"),
+            ("unknown", "something", ""),
+        ];
+        for (field, value, res) in vector {
+            assert_eq!(&Query::new().parse_field(field, value).to_string(), res);
+        }
+    }
+
     #[test]
     fn open() {
         let dir = temp_dir().join(format!("plume-test-{}", random_hex()));
@@ -143,6 +171,8 @@ pub(crate) mod tests {
                     subtitle: "".to_owned(),
                     source: "".to_owned(),
                     cover_id: None,
+                    excerpt: String::new(),
+                    canonical_url: None,
                 },
             )
             .unwrap();
@@ -173,6 +203,22 @@ pub(crate) mod tests {
             assert!(searcher
                 .search_document(conn, Query::from_str(&title).unwrap(), (0, 1))
                 .is_empty());
+            assert_eq!(
+                searcher.search_document(
+                    conn,
+                    Query::from_str(&format!("-{}", title)).unwrap(),
+                    (0, 1)
+                )[0]
+                .id,
+                post.id
+            );
+            assert!(searcher
+                .search_document(
+                    conn,
+                    Query::from_str(&format!("-{}", newtitle)).unwrap(),
+                    (0, 1)
+                )
+                .is_empty());
 
             searcher.delete_document(&post);
             searcher.commit();
@@ -183,6 +229,235 @@ pub(crate) mod tests {
         });
     }
 
+    #[test]
+    fn search_not_indexable() {
+        let conn = &db();
+        conn.test_transaction::<_, (), _>(|| {
+            let searcher = get_searcher(&CONFIG.search_tokenizers);
+            let blog = &fill_database(conn).1[0];
+            let author = &blog.list_authors(conn).unwrap()[0];
+            author.set_indexable(conn, false).unwrap();
+
+            let title = random_hex()[..8].to_owned();
+            let post = Post::insert(
+                conn,
+                NewPost {
+                    blog_id: blog.id,
+                    slug: title.clone(),
+                    title: title.clone(),
+                    content: SafeString::new(""),
+                    published: true,
+                    license: "CC-BY-SA".to_owned(),
+                    ap_url: "".to_owned(),
+                    creation_date: None,
+                    subtitle: "".to_owned(),
+                    source: "".to_owned(),
+                    cover_id: None,
+                    excerpt: String::new(),
+                    canonical_url: None,
+                },
+            )
+            .unwrap();
+            PostAuthor::insert(
+                conn,
+                NewPostAuthor {
+                    post_id: post.id,
+                    author_id: author.id,
+                },
+            )
+            .unwrap();
+            assert!(!post.is_indexable(conn).unwrap());
+            searcher.add_document(conn, &post).unwrap();
+            searcher.commit();
+            assert!(searcher
+                .search_document(conn, Query::from_str(&title).unwrap(), (0, 1))
+                .is_empty());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn search_comments() {
+        let conn = &db();
+        conn.test_transaction::<_, (), _>(|| {
+            let searcher = get_searcher(&CONFIG.search_tokenizers);
+            let (users, blogs) = fill_database(conn);
+            let author = &blogs[0].list_authors(conn).unwrap()[0];
+
+            let post = Post::insert(
+                conn,
+                NewPost {
+                    blog_id: blogs[0].id,
+                    slug: "commented".to_owned(),
+                    title: "Commented".to_owned(),
+                    content: SafeString::new(""),
+                    published: true,
+                    license: "CC-BY-SA".to_owned(),
+                    ap_url: "".to_owned(),
+                    creation_date: None,
+                    subtitle: "".to_owned(),
+                    source: "".to_owned(),
+                    cover_id: None,
+                    excerpt: String::new(),
+                    canonical_url: None,
+                },
+            )
+            .unwrap();
+            PostAuthor::insert(
+                conn,
+                NewPostAuthor {
+                    post_id: post.id,
+                    author_id: author.id,
+                },
+            )
+            .unwrap();
+            let public_word = random_hex()[..8].to_owned();
+            let private_word = random_hex()[..8].to_owned();
+            let public = Comment::insert(
+                conn,
+                NewComment {
+                    content: SafeString::new(&public_word),
+                    post_id: post.id,
+                    author_id: users[1].id,
+                    public_visibility: true,
+                    ..NewComment::default()
+                },
+            )
+            .unwrap();
+            let private = Comment::insert(
+                conn,
+                NewComment {
+                    content: SafeString::new(&private_word),
+                    post_id: post.id,
+                    author_id: users[1].id,
+                    public_visibility: false,
+                    ..NewComment::default()
+                },
+            )
+            .unwrap();
+            searcher.add_document(conn, &post).unwrap();
+            searcher.add_comment(conn, &public).unwrap();
+            searcher.add_comment(conn, &private).unwrap();
+            searcher.commit();
+
+            let found =
+                |query: &str| searcher.search(conn, Query::from_str(query).unwrap(), None, (0, 10));
+            match found(&public_word).as_slice() {
+                [SearchResult::Comment(comment)] => assert_eq!(comment.id, public.id),
+                _ => panic!("The public comment should be found"),
+            }
+            assert!(found(&private_word).is_empty());
+            assert!(searcher
+                .search_document(conn, Query::from_str(&public_word).unwrap(), (0, 10))
+                .is_empty());
+            assert!(found("type:comment")
+                .iter()
+                .all(|r| matches!(r, SearchResult::Comment(_))));
+            assert!(found("type:article")
+                .iter()
+                .any(|r| matches!(r, SearchResult::Post(p) if p.id == post.id)));
+
+            searcher.delete_comment(&public);
+            searcher.commit();
+            assert!(found(&public_word).is_empty());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn query_variants() {
+        let variants = super::tokenizer::query_variants("Gardens");
+        assert!(variants.contains(&vec!["gardens".to_owned()]));
+        assert!(variants.contains(&vec!["garden".to_owned()]));
+        let variants = super::tokenizer::query_variants("東京都");
+        assert!(variants.contains(&vec!["東京".to_owned(), "京都".to_owned()]));
+    }
+
+    #[test]
+    fn search_stemmed() {
+        let conn = &db();
+        conn.test_transaction::<_, (), _>(|| {
+            let searcher = get_searcher(&CONFIG.search_tokenizers);
+            let blog = &fill_database(conn).1[0];
+            let post = Post::insert(
+                conn,
+                NewPost {
+                    blog_id: blog.id,
+                    slug: "stemmed".to_owned(),
+                    title: "Stemmed".to_owned(),
+                    content: SafeString::new(
+                        "The cats were running quickly through the gardens of the old house, \
+                         chasing each other until the evening came and everyone went to sleep.",
+                    ),
+                    published: true,
+                    license: "CC-BY-SA".to_owned(),
+                    ap_url: "".to_owned(),
+                    creation_date: None,
+                    subtitle: "".to_owned(),
+                    source: "".to_owned(),
+                    cover_id: None,
+                    excerpt: String::new(),
+                    canonical_url: None,
+                },
+            )
+            .unwrap();
+            searcher.add_document(conn, &post).unwrap();
+            searcher.commit();
+            assert_eq!(
+                searcher.search_document(conn, Query::from_str("garden").unwrap(), (0, 1))[0].id,
+                post.id
+            );
+            assert_eq!(
+                searcher.search_document(conn, Query::from_str("+chase").unwrap(), (0, 1))[0].id,
+                post.id
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn rebuild() {
+        let conn = &db();
+        conn.test_transaction::<_, (), _>(|| {
+            let searcher = get_searcher(&CONFIG.search_tokenizers);
+            let blog = &fill_database(conn).1[0];
+            let post = Post::insert(
+                conn,
+                NewPost {
+                    blog_id: blog.id,
+                    slug: "indexed".to_owned(),
+                    title: "Indexed".to_owned(),
+                    content: SafeString::new(""),
+                    published: true,
+                    license: "CC-BY-SA".to_owned(),
+                    ap_url: "".to_owned(),
+                    creation_date: None,
+                    subtitle: "".to_owned(),
+                    source: "".to_owned(),
+                    cover_id: None,
+                    excerpt: String::new(),
+                    canonical_url: None,
+                },
+            )
+            .unwrap();
+            assert!(searcher.health(conn).unwrap().is_drifting());
+
+            searcher.rebuild(conn, None).unwrap();
+            searcher.commit();
+            let health = searcher.health(conn).unwrap();
+            assert!(health.indexed_articles > 0);
+            assert!(!health.is_drifting());
+
+            searcher.delete_document(&post);
+            searcher.commit();
+            assert!(searcher.health(conn).unwrap().is_drifting());
+            searcher.rebuild(conn, Some(post.creation_date)).unwrap();
+            searcher.commit();
+            assert_eq!(searcher.health(conn).unwrap(), health);
+            Ok(())
+        });
+    }
+
     #[cfg(feature = "search-lindera")]
     #[test]
     fn search_japanese() {
@@ -212,6 +487,8 @@ pub(crate) mod tests {
                     subtitle: "".to_owned(),
                     source: "".to_owned(),
                     cover_id: None,
+                    excerpt: String::new(),
+                    canonical_url: None,
                 },
             )
             .unwrap();
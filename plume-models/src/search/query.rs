@@ -1,6 +1,10 @@
-use crate::search::searcher::Searcher;
+use crate::{
+    search::{searcher::Searcher, tokenizer::query_variants},
+    tag_aliases::TagAlias,
+    Connection,
+};
 use chrono::{naive::NaiveDate, offset::Utc, Datelike};
-use std::{cmp, ops::Bound};
+use std::{cmp, ops::Bound, str::FromStr};
 use tantivy::{query::*, schema::*, Term};
 
 //Generate functions for advanced search
@@ -124,6 +128,42 @@ macro_rules! gen_to_query {
     }
 }
 
+/// The kinds of documents in the index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResultKind {
+    Article,
+    Comment,
+}
+
+impl ResultKind {
+    /// How this kind is written in the index, and in queries (`type:comment`)
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ResultKind::Article => "article",
+            ResultKind::Comment => "comment",
+        }
+    }
+
+    fn other(self) -> Self {
+        match self {
+            ResultKind::Article => ResultKind::Comment,
+            ResultKind::Comment => ResultKind::Article,
+        }
+    }
+}
+
+impl FromStr for ResultKind {
+    type Err = ();
+
+    fn from_str(kind: &str) -> Result<Self, ()> {
+        match kind.to_lowercase().as_str() {
+            "article" | "articles" | "post" | "posts" => Ok(ResultKind::Article),
+            "comment" | "comments" => Ok(ResultKind::Comment),
+            _ => Err(()),
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct PlumeQuery {
     text: Vec<(Occur, String)>,
@@ -138,6 +178,7 @@ pub struct PlumeQuery {
     license: Vec<(Occur, String)>,
     before: Option<i64>,
     after: Option<i64>,
+    kind: Option<ResultKind>,
 }
 
 impl PlumeQuery {
@@ -151,6 +192,72 @@ impl PlumeQuery {
         self.from_str_req(query.trim())
     }
 
+    /// Parse the value of a single field into this Query, as if each of its words had
+    /// been prefixed with `field:`
+    ///
+    /// This is how the fields of the advanced search form are read. Unknown fields are
+    /// ignored.
+    pub fn parse_field(&mut self, field: &str, mut value: &str) -> &mut Self {
+        while !value.trim().is_empty() {
+            let (token, rest) = Self::get_first_token(value);
+            value = rest;
+            match field {
+                "text" => {
+                    self.text(token, None);
+                }
+                "title" => {
+                    self.title(token, None);
+                }
+                "subtitle" => {
+                    self.subtitle(token, None);
+                }
+                "content" => {
+                    self.content(token, None);
+                }
+                "tag" => {
+                    self.tag(token.trim_start_matches('#'), None);
+                }
+                "instance" => {
+                    self.instance(token, None);
+                }
+                "author" => {
+                    self.author(token, None);
+                }
+                "blog" => {
+                    self.blog(token, None);
+                }
+                "lang" => {
+                    self.lang(token, None);
+                }
+                "license" => {
+                    self.license(token, None);
+                }
+                "type" => {
+                    self.kind(token, None);
+                }
+                "before" | "after" => {
+                    if let Ok(date) = NaiveDate::parse_from_str(token, "%Y-%m-%d") {
+                        if field == "before" {
+                            self.before(&date);
+                        } else {
+                            self.after(&date);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        self
+    }
+
+    /// Replace the tags of this Query that are aliases by the tag they stand for
+    pub fn resolve_tag_aliases(&mut self, conn: &Connection) -> &mut Self {
+        for (_, tag) in self.tag.iter_mut() {
+            *tag = TagAlias::canonical(conn, tag);
+        }
+        self
+    }
+
     /// Convert this Query to a Tantivy Query
     pub fn into_query(self) -> BooleanQuery {
         let mut result: Vec<(Occur, Box<dyn Query>)> = Vec::new();
@@ -162,11 +269,12 @@ impl PlumeQuery {
             match occur {
                 Occur::Must => {
                     // a Must mean this must be in one of title subtitle or content, not in all 3
-                    let subresult = vec![
+                    let mut subresult = vec![
                         (Occur::Should, Self::token_to_query(&token, "title")),
                         (Occur::Should, Self::token_to_query(&token, "subtitle")),
                         (Occur::Should, Self::token_to_query(&token, "content")),
                     ];
+                    subresult.extend(Self::stemmed_query(&token).map(|q| (Occur::Should, q)));
 
                     result.push((Occur::Must, Box::new(BooleanQuery::from(subresult))));
                 }
@@ -174,6 +282,10 @@ impl PlumeQuery {
                     result.push((occur, Self::token_to_query(&token, "title")));
                     result.push((occur, Self::token_to_query(&token, "subtitle")));
                     result.push((occur, Self::token_to_query(&token, "content")));
+                    // other forms of a word are found, but not excluded
+                    if occur == Occur::Should {
+                        result.extend(Self::stemmed_query(&token).map(|q| (occur, q)));
+                    }
                 }
             }
         }
@@ -196,12 +308,55 @@ impl PlumeQuery {
             result.push((Occur::Must, Box::new(range)));
         }
 
+        if let Some(kind) = self.kind {
+            let field = Searcher::schema().get_field("kind").unwrap();
+            result.push((
+                Occur::Must,
+                Box::new(TermQuery::new(
+                    Term::from_field_text(field, kind.as_str()),
+                    IndexRecordOption::Basic,
+                )),
+            ));
+        }
+
+        // a query that only excludes things would match nothing, make it exclude them
+        // from all the documents instead
+        if result.iter().all(|(occur, _)| *occur == Occur::MustNot) && !result.is_empty() {
+            result.push((Occur::Must, Box::new(AllQuery)));
+        }
+
         result.into()
     }
 
     //generate most setters functions
     gen_func!(text, title, subtitle, content, tag, instance, lang, license; strip: author, blog);
 
+    // only documents of this kind will be found, `-type:comment` meaning articles
+    pub fn kind(&mut self, mut val: &str, occur: Option<Occur>) -> &mut Self {
+        let occur = occur.unwrap_or_else(|| {
+            if let Some(v) = val.strip_prefix('-') {
+                val = v;
+                Occur::MustNot
+            } else {
+                val = val.trim_start_matches('+');
+                Occur::Must
+            }
+        });
+        if let Ok(kind) = val.trim_matches(&[' ', '"'][..]).parse::<ResultKind>() {
+            self.kind = Some(if occur == Occur::MustNot {
+                kind.other()
+            } else {
+                kind
+            });
+        }
+        self
+    }
+
+    /// The kind of documents this Query is restricted to, if any
+    pub fn get_kind(&self) -> Option<ResultKind> {
+        self.kind
+    }
+
     // documents newer than the provided date will be ignored
     pub fn before<D: Datelike>(&mut self, date: &D) -> &mut Self {
         let before = self
@@ -280,12 +435,57 @@ impl PlumeQuery {
         } else {
             Occur::Should
         };
-        gen_parser!(self, query, occur; normal: title, subtitle, content, tag,
-                        instance, author, blog, lang, license;
-                        date: after, before);
+        // `#tag` and `@author` are shorthands for `tag:tag` and `author:author`
+        let (token, rest) = Self::get_first_token(query);
+        if token.len() > 1 && token.starts_with('#') {
+            query = rest;
+            self.tag(&token[1..], Some(occur));
+        } else if token.len() > 1 && token.starts_with('@') {
+            query = rest;
+            self.author(token, Some(occur));
+        } else if let Some(kind) = token.strip_prefix("type:") {
+            query = rest;
+            self.kind(kind, Some(occur));
+        } else {
+            gen_parser!(self, query, occur; normal: title, subtitle, content, tag,
+                            instance, author, blog, lang, license;
+                            date: after, before);
+        }
         self.from_str_req(query)
     }
 
+    // match the words of a token in whatever language they are written in: each way to
+    // analyze them is tried, and all the words of one of them must be found
+    fn stemmed_query(token: &str) -> Option<Box<dyn Query>> {
+        let field = Searcher::schema().get_field("stemmed").unwrap();
+        let variants = query_variants(token)
+            .into_iter()
+            .map(|terms| {
+                let terms = terms
+                    .into_iter()
+                    .map(|term| {
+                        (
+                            Occur::Must,
+                            Box::new(TermQuery::new(
+                                Term::from_field_text(field, &term),
+                                IndexRecordOption::WithFreqs,
+                            )) as Box<dyn Query>,
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                (
+                    Occur::Should,
+                    Box::new(BooleanQuery::from(terms)) as Box<dyn Query>,
+                )
+            })
+            .collect::<Vec<_>>();
+        if variants.is_empty() {
+            None
+        } else {
+            Some(Box::new(BooleanQuery::from(variants)))
+        }
+    }
+
     // map a token and it's field to a query
     fn token_to_query(token: &str, field_name: &str) -> Box<dyn Query> {
         let token = token.to_lowercase();
@@ -387,6 +587,9 @@ impl ToString for PlumeQuery {
         gen_to_string!(self, result; normal: title, subtitle, content, tag,
                       instance, author, blog, lang, license;
                       date: before, after);
+        if let Some(kind) = self.kind {
+            result.push_str(&format!("type:{} ", kind.as_str()));
+        }
 
         result.pop(); // remove trailing ' '
         result
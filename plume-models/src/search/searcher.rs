@@ -1,15 +1,27 @@
+use super::tokenizer::{analyze, language_analyzer};
 use crate::{
-    config::SearchTokenizerConfig, instance::Instance, posts::Post, schema::posts,
-    search::query::PlumeQuery, tags::Tag, Connection, Error, Result,
+    comments::Comment,
+    config::SearchTokenizerConfig,
+    instance::Instance,
+    posts::Post,
+    schema::{comments, post_authors, posts, users},
+    search::query::{PlumeQuery, ResultKind},
+    tags::Tag,
+    users::User,
+    Connection, Error, Result,
 };
-use chrono::{Datelike, Utc};
+use chrono::{Datelike, NaiveDateTime, Utc};
 use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
 use itertools::Itertools;
 use std::fs;
-use std::{cmp, fs::create_dir_all, io, path::Path, sync::Mutex};
+use std::{cmp, collections::HashMap, fs::create_dir_all, io, path::Path, sync::Mutex};
 use tantivy::{
-    collector::TopDocs, directory::MmapDirectory, schema::*, Index, IndexReader, IndexWriter,
-    ReloadPolicy, TantivyError, Term,
+    collector::{Count, TopDocs},
+    directory::MmapDirectory,
+    query::{BooleanQuery, Occur, Query, TermQuery},
+    schema::*,
+    tokenizer::PreTokenizedString,
+    Index, IndexReader, IndexWriter, ReloadPolicy, TantivyError, Term,
 };
 use tracing::warn;
 use whatlang::{detect as detect_lang, Lang};
@@ -23,6 +35,36 @@ pub enum SearcherError {
     InvalidIndexDataError,
 }
 
+/// Something found in the index.
+pub enum SearchResult {
+    Post(Post),
+    Comment(Comment),
+}
+
+/// How many documents the index has, and how many it should have.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IndexHealth {
+    pub indexed_articles: i64,
+    /// The published articles whose authors allow them to be indexed
+    pub published_articles: i64,
+    pub indexed_comments: i64,
+    /// The comments on published articles, whose authors allow them to be indexed
+    pub comments: i64,
+}
+
+impl IndexHealth {
+    /// If some documents are missing from the index, or are still in it after being deleted.
+    pub fn is_drifting(&self) -> bool {
+        self.indexed_articles != self.published_articles || self.indexed_comments != self.comments
+    }
+
+    /// If nothing is indexed, while there is something to index.
+    pub fn is_empty(&self) -> bool {
+        self.indexed_articles + self.indexed_comments == 0
+            && self.published_articles + self.comments > 0
+    }
+}
+
 pub struct Searcher {
     index: Index,
     reader: IndexReader,
@@ -52,8 +94,12 @@ impl Searcher {
         let mut schema_builder = SchemaBuilder::default();
 
         schema_builder.add_i64_field("post_id", STORED | INDEXED);
+        schema_builder.add_i64_field("comment_id", STORED | INDEXED);
+        // the post a comment was written on, to forget the comment with it
+        schema_builder.add_i64_field("comment_post_id", INDEXED);
         schema_builder.add_i64_field("creation_date", INDEXED);
 
+        schema_builder.add_text_field("kind", tag_indexing.clone());
         schema_builder.add_text_field("instance", tag_indexing.clone());
         schema_builder.add_text_field("author", tag_indexing.clone());
         schema_builder.add_text_field("tag", tag_indexing);
@@ -61,7 +107,9 @@ impl Searcher {
         schema_builder.add_text_field("blog", content_indexing.clone());
         schema_builder.add_text_field("content", content_indexing.clone());
         schema_builder.add_text_field("subtitle", content_indexing.clone());
-        schema_builder.add_text_field("title", content_indexing);
+        schema_builder.add_text_field("title", content_indexing.clone());
+        // title, subtitle and content, analyzed according to their language
+        schema_builder.add_text_field("stemmed", content_indexing);
 
         schema_builder.add_text_field("lang", property_indexing.clone());
         schema_builder.add_text_field("license", property_indexing);
@@ -73,20 +121,18 @@ impl Searcher {
         let mut open_searcher = Self::open(path, tokenizers);
         if let Err(Error::Search(SearcherError::InvalidIndexDataError)) = open_searcher {
             if Self::create(path, tokenizers).is_err() {
+                // The outdated index is kept, but it has to be filled again
                 let backup_path = format!("{}.{}", path.as_ref().display(), Utc::now().timestamp());
                 let backup_path = Path::new(&backup_path);
                 fs::rename(path, backup_path)
                     .expect("main: error on backing up search index directory for recreating");
-                if Self::create(path, tokenizers).is_ok() {
-                    if fs::remove_dir_all(backup_path).is_err() {
-                        warn!(
-                            "error on removing backup directory: {}. it remains",
-                            backup_path.display()
-                        );
-                    }
-                } else {
+                if Self::create(path, tokenizers).is_err() {
                     panic!("main: error on recreating search index in new index format. remove search index and run `plm search init` manually");
                 }
+                warn!(
+                    "The search index was in an older format: it was moved to {}, and a new one will be filled from the database",
+                    backup_path.display()
+                );
             }
             open_searcher = Self::open(path, tokenizers);
         }
@@ -156,6 +202,10 @@ Then try to restart Plume
         let mut index =
             Index::open(MmapDirectory::open(path).map_err(|_| SearcherError::IndexOpeningError)?)
                 .map_err(|_| SearcherError::IndexOpeningError)?;
+        if index.schema().get_field("stemmed").is_none() {
+            // Search index was created before comments and languages were indexed.
+            return Err(SearcherError::InvalidIndexDataError.into());
+        }
 
         {
             let tokenizer_manager = index.tokenizers();
@@ -207,8 +257,9 @@ Then try to restart Plume
         })
     }
 
+    /// Indexes a published article, unless one of its authors doesn't allow it.
     pub fn add_document(&self, conn: &Connection, post: &Post) -> Result<()> {
-        if !post.published {
+        if !post.published || !post.is_indexable(conn)? {
             return Ok(());
         }
 
@@ -228,11 +279,15 @@ Then try to restart Plume
 
         let lang = schema.get_field("lang").unwrap();
         let license = schema.get_field("license").unwrap();
+        let kind = schema.get_field("kind").unwrap();
+        let stemmed = schema.get_field("stemmed").unwrap();
 
+        let language = detect_language(post.content.get());
         let mut writer = self.writer.lock().unwrap();
         let writer = writer.as_mut().unwrap();
         writer.add_document(doc!(
             post_id => i64::from(post.id),
+            kind => ResultKind::Article.as_str(),
             author => post.get_authors(conn)?.into_iter().map(|u| u.fqn).join(" "),
             creation_date => i64::from(post.creation_date.num_days_from_ce()),
             instance => Instance::get(conn, post.get_blog(conn)?.instance_id)?.public_domain,
@@ -241,35 +296,102 @@ Then try to restart Plume
             content => post.content.get().clone(),
             subtitle => post.subtitle.clone(),
             title => post.title.clone(),
-            lang => detect_lang(post.content.get()).and_then(|i| if i.is_reliable() { Some(i.lang()) } else {None} ).unwrap_or(Lang::Eng).name(),
+            stemmed => stemmed_text(format!("{}\n{}\n{}", post.title, post.subtitle, post.content.get()), language),
+            lang => language.unwrap_or(Lang::Eng).name(),
             license => post.license.clone(),
         ));
         Ok(())
     }
 
+    /// Forgets a post, and the comments written on it.
     pub fn delete_document(&self, post: &Post) {
+        self.delete_terms(&[("post_id", post.id), ("comment_post_id", post.id)]);
+    }
+
+    pub fn update_document(&self, conn: &Connection, post: &Post) -> Result<()> {
+        self.delete_terms(&[("post_id", post.id)]);
+        self.add_document(conn, post)
+    }
+
+    /// Indexes a local or remote comment.
+    ///
+    /// Comments are indexed even if they are not public: who can see them is checked
+    /// when searching.
+    pub fn add_comment(&self, conn: &Connection, comment: &Comment) -> Result<()> {
+        let post = comment.get_post(conn)?;
+        if !post.published || comment.held {
+            return Ok(());
+        }
+        let author = comment.get_author(conn)?;
+        if !author.indexable {
+            return Ok(());
+        }
+
         let schema = self.index.schema();
-        let post_id = schema.get_field("post_id").unwrap();
+        let comment_id = schema.get_field("comment_id").unwrap();
+        let comment_post_id = schema.get_field("comment_post_id").unwrap();
+        let creation_date = schema.get_field("creation_date").unwrap();
+        let kind = schema.get_field("kind").unwrap();
+        let instance = schema.get_field("instance").unwrap();
+        let author_field = schema.get_field("author").unwrap();
+        let blog_name = schema.get_field("blog").unwrap();
+        let content = schema.get_field("content").unwrap();
+        let lang = schema.get_field("lang").unwrap();
+        let stemmed = schema.get_field("stemmed").unwrap();
 
-        let doc_id = Term::from_field_i64(post_id, i64::from(post.id));
+        let language = detect_language(comment.content.get());
         let mut writer = self.writer.lock().unwrap();
         let writer = writer.as_mut().unwrap();
-        writer.delete_term(doc_id);
+        writer.add_document(doc!(
+            comment_id => i64::from(comment.id),
+            comment_post_id => i64::from(post.id),
+            creation_date => i64::from(comment.creation_date.num_days_from_ce()),
+            kind => ResultKind::Comment.as_str(),
+            instance => Instance::get(conn, author.instance_id)?.public_domain,
+            author_field => author.fqn,
+            blog_name => post.get_blog(conn)?.title,
+            content => comment.content.get().clone(),
+            stemmed => stemmed_text(comment.content.get().clone(), language),
+            lang => language.unwrap_or(Lang::Eng).name(),
+        ));
+        Ok(())
     }
 
-    pub fn update_document(&self, conn: &Connection, post: &Post) -> Result<()> {
-        self.delete_document(post);
-        self.add_document(conn, post)
+    /// Indexes a comment again, after it changed or was published again.
+    pub fn update_comment(&self, conn: &Connection, comment: &Comment) -> Result<()> {
+        self.delete_comment(comment);
+        self.add_comment(conn, comment)
     }
 
-    pub fn search_document(
+    pub fn delete_comment(&self, comment: &Comment) {
+        self.delete_terms(&[("comment_id", comment.id)]);
+    }
+
+    fn delete_terms(&self, terms: &[(&str, i32)]) {
+        let schema = self.index.schema();
+        let mut writer = self.writer.lock().unwrap();
+        let writer = writer.as_mut().unwrap();
+        for (field, id) in terms {
+            let field = schema.get_field(field).unwrap();
+            writer.delete_term(Term::from_field_i64(field, i64::from(*id)));
+        }
+    }
+
+    /// The articles and comments matching `query`, that `viewer` can see.
+    ///
+    /// Access is checked for each result, so a page can have less results than
+    /// asked for.
+    pub fn search(
         &self,
         conn: &Connection,
-        query: PlumeQuery,
+        mut query: PlumeQuery,
+        viewer: Option<&User>,
         (min, max): (i32, i32),
-    ) -> Vec<Post> {
+    ) -> Vec<SearchResult> {
+        query.resolve_tag_aliases(conn);
         let schema = self.index.schema();
         let post_id = schema.get_field("post_id").unwrap();
+        let comment_id = schema.get_field("comment_id").unwrap();
 
         let collector = TopDocs::with_limit(cmp::max(1, max) as usize);
 
@@ -281,23 +403,199 @@ Then try to restart Plume
             .iter()
             .filter_map(|(_, doc_add)| {
                 let doc = searcher.doc(*doc_add).ok()?;
+                if let Some(id) = doc.get_first(comment_id) {
+                    let comment = Comment::get(conn, id.i64_value() as i32).ok()?;
+                    let visible =
+                        comment.can_see(conn, viewer) && comment.get_post(conn).ok()?.published;
+                    Some(SearchResult::Comment(comment)).filter(|_| visible)
+                } else {
+                    let id = doc.get_first(post_id)?;
+                    Post::get(conn, id.i64_value() as i32)
+                        .ok()
+                        .filter(|post| post.published)
+                        .map(SearchResult::Post)
+                }
+            })
+            .collect()
+    }
+
+    /// The articles matching `query`.
+    pub fn search_document(
+        &self,
+        conn: &Connection,
+        mut query: PlumeQuery,
+        range: (i32, i32),
+    ) -> Vec<Post> {
+        query.kind(ResultKind::Article.as_str(), None);
+        self.search(conn, query, None, range)
+            .into_iter()
+            .filter_map(|result| match result {
+                SearchResult::Post(post) => Some(post),
+                SearchResult::Comment(_) => None,
+            })
+            .collect()
+    }
+
+    /// Find documents whose text is similar to the one of `post`.
+    ///
+    /// The most frequent terms of the post title, subtitle and source are
+    /// searched in the index, so that the scoring of the index (which takes
+    /// care of the inverse document frequency) gives the similarity.
+    /// Returns post ids with their score, best matches first.
+    pub fn related_documents(&self, post: &Post, limit: usize) -> Vec<(i32, f32)> {
+        const MAX_TERMS: usize = 25;
+
+        let schema = self.index.schema();
+        let post_id = schema.get_field("post_id").unwrap();
+        let content = schema.get_field("content").unwrap();
+        let title = schema.get_field("title").unwrap();
+        let kind = schema.get_field("kind").unwrap();
+
+        let analyzer = match self.index.tokenizers().get("content_tokenizer") {
+            Some(analyzer) => analyzer,
+            None => return vec![],
+        };
+        let mut frequencies = HashMap::new();
+        for text in &[&post.title, &post.subtitle, &post.source] {
+            analyzer.token_stream(text).process(&mut |token| {
+                *frequencies.entry(token.text.clone()).or_insert(0) += 1;
+            });
+        }
+        let terms = frequencies
+            .into_iter()
+            .sorted_by(|(_, a), (_, b)| b.cmp(a))
+            .take(MAX_TERMS)
+            .flat_map(|(term, _)| {
+                vec![
+                    Term::from_field_text(content, &term),
+                    Term::from_field_text(title, &term),
+                ]
+            })
+            .map(|term| {
+                (
+                    Occur::Should,
+                    Box::new(TermQuery::new(term, IndexRecordOption::WithFreqs)) as Box<dyn Query>,
+                )
+            })
+            .collect::<Vec<_>>();
+        if terms.is_empty() {
+            return vec![];
+        }
+
+        let mut query = terms;
+        query.push((
+            Occur::MustNot,
+            Box::new(TermQuery::new(
+                Term::from_field_text(kind, ResultKind::Comment.as_str()),
+                IndexRecordOption::Basic,
+            )),
+        ));
+        query.push((
+            Occur::MustNot,
+            Box::new(TermQuery::new(
+                Term::from_field_i64(post_id, i64::from(post.id)),
+                IndexRecordOption::Basic,
+            )),
+        ));
+
+        let searcher = self.reader.searcher();
+        let res = match searcher.search(
+            &BooleanQuery::from(query),
+            &TopDocs::with_limit(cmp::max(1, limit)),
+        ) {
+            Ok(res) => res,
+            Err(e) => {
+                warn!("Failed to look for related documents: {:?}", e);
+                return vec![];
+            }
+        };
+        res.into_iter()
+            .filter_map(|(score, doc_add)| {
+                let doc = searcher.doc(doc_add).ok()?;
                 let id = doc.get_first(post_id)?;
-                Post::get(conn, id.i64_value() as i32).ok()
-                //borrow checker don't want me to use filter_map or and_then here
+                Some((id.i64_value() as i32, score))
             })
             .collect()
     }
 
     pub fn fill(&self, conn: &Connection) -> Result<()> {
-        for post in posts::table
-            .filter(posts::published.eq(true))
-            .load::<Post>(conn)?
-        {
+        self.rebuild(conn, None)
+    }
+
+    /// Indexes again the articles and comments written since a date, or all of them.
+    ///
+    /// When everything is indexed again, the documents that are not in the database
+    /// anymore are removed too. The changes are only visible after a commit.
+    pub fn rebuild(&self, conn: &Connection, since: Option<NaiveDateTime>) -> Result<()> {
+        let mut posts = posts::table.filter(posts::published.eq(true)).into_boxed();
+        let mut comments = comments::table.into_boxed();
+        if let Some(since) = since {
+            posts = posts.filter(posts::creation_date.ge(since));
+            comments = comments.filter(comments::creation_date.ge(since));
+        } else {
+            let schema = self.index.schema();
+            let kind = schema.get_field("kind").unwrap();
+            let mut writer = self.writer.lock().unwrap();
+            let writer = writer.as_mut().unwrap();
+            for k in &[ResultKind::Article, ResultKind::Comment] {
+                writer.delete_term(Term::from_field_text(kind, k.as_str()));
+            }
+        }
+
+        for post in posts.load::<Post>(conn)? {
             self.update_document(conn, &post)?
         }
+        for comment in comments.load::<Comment>(conn)? {
+            self.update_comment(conn, &comment)?
+        }
         Ok(())
     }
 
+    /// Compares the committed content of the index with the database.
+    pub fn health(&self, conn: &Connection) -> Result<IndexHealth> {
+        Ok(IndexHealth {
+            indexed_articles: self.count(ResultKind::Article),
+            published_articles: posts::table
+                .filter(posts::published.eq(true))
+                .filter(
+                    posts::id.ne_all(
+                        post_authors::table
+                            .inner_join(users::table)
+                            .filter(users::indexable.eq(false))
+                            .select(post_authors::post_id),
+                    ),
+                )
+                .count()
+                .get_result(conn)?,
+            indexed_comments: self.count(ResultKind::Comment),
+            comments: comments::table
+                .inner_join(posts::table)
+                .filter(posts::published.eq(true))
+                .filter(
+                    comments::author_id.ne_all(
+                        users::table
+                            .filter(users::indexable.eq(false))
+                            .select(users::id),
+                    ),
+                )
+                .count()
+                .get_result(conn)?,
+        })
+    }
+
+    fn count(&self, kind: ResultKind) -> i64 {
+        let field = self.index.schema().get_field("kind").unwrap();
+        let query = TermQuery::new(
+            Term::from_field_text(field, kind.as_str()),
+            IndexRecordOption::Basic,
+        );
+        self.reader
+            .searcher()
+            .search(&query, &Count)
+            .map(|count| count as i64)
+            .unwrap_or(0)
+    }
+
     pub fn commit(&self) {
         let mut writer = self.writer.lock().unwrap();
         writer.as_mut().unwrap().commit().unwrap();
@@ -308,3 +606,15 @@ Then try to restart Plume
         self.writer.lock().unwrap().take();
     }
 }
+
+/// The language of `text`, if it can be reliably detected.
+fn detect_language(text: &str) -> Option<Lang> {
+    detect_lang(text)
+        .filter(|info| info.is_reliable())
+        .map(|info| info.lang())
+}
+
+fn stemmed_text(text: String, lang: Option<Lang>) -> PreTokenizedString {
+    let tokens = analyze(&language_analyzer(lang), &text);
+    PreTokenizedString { text, tokens }
+}
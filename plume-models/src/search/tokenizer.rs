@@ -2,6 +2,25 @@
 use lindera_tantivy::tokenizer::LinderaTokenizer;
 use std::str::CharIndices;
 use tantivy::tokenizer::*;
+use whatlang::Lang;
+
+/// The languages whose words are reduced to their stem in the `stemmed` field.
+const STEMMED_LANGUAGES: &[(Lang, Language)] = &[
+    (Lang::Dan, Language::Danish),
+    (Lang::Deu, Language::German),
+    (Lang::Eng, Language::English),
+    (Lang::Fin, Language::Finnish),
+    (Lang::Fra, Language::French),
+    (Lang::Hun, Language::Hungarian),
+    (Lang::Ita, Language::Italian),
+    (Lang::Nld, Language::Dutch),
+    (Lang::Por, Language::Portuguese),
+    (Lang::Ron, Language::Romanian),
+    (Lang::Rus, Language::Russian),
+    (Lang::Spa, Language::Spanish),
+    (Lang::Swe, Language::Swedish),
+    (Lang::Tur, Language::Turkish),
+];
 
 #[derive(Clone, Copy)]
 pub enum TokenizerKind {
@@ -92,3 +111,81 @@ impl<'a> TokenStream for WhitespaceTokenStream<'a> {
         &mut self.token
     }
 }
+
+/// The analyzer for the `stemmed` field of a document written in `lang`.
+///
+/// Words are reduced to their stem for the major European languages, and Chinese,
+/// Japanese and Korean texts, that don't separate words with spaces, are split in
+/// bigrams (or in words with Lindera for Japanese, when it is available).
+pub(crate) fn language_analyzer(lang: Option<Lang>) -> TextAnalyzer {
+    match lang {
+        Some(Lang::Jpn) => japanese_analyzer(),
+        Some(Lang::Cmn) | Some(Lang::Kor) => bigram_analyzer(),
+        Some(lang) => match STEMMED_LANGUAGES.iter().find(|(l, _)| *l == lang) {
+            Some((_, language)) => TextAnalyzer::from(SimpleTokenizer)
+                .filter(RemoveLongFilter::limit(40))
+                .filter(LowerCaser)
+                .filter(Stemmer::new(*language)),
+            None => TokenizerKind::Simple.into(),
+        },
+        None => TokenizerKind::Simple.into(),
+    }
+}
+
+fn bigram_analyzer() -> TextAnalyzer {
+    TextAnalyzer::from(NgramTokenizer::new(2, 2, false)).filter(LowerCaser)
+}
+
+#[cfg(feature = "search-lindera")]
+fn japanese_analyzer() -> TextAnalyzer {
+    TokenizerKind::Lindera.into()
+}
+
+#[cfg(not(feature = "search-lindera"))]
+fn japanese_analyzer() -> TextAnalyzer {
+    bigram_analyzer()
+}
+
+/// The tokens of `text`, as given by `analyzer`.
+pub(crate) fn analyze(analyzer: &TextAnalyzer, text: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    analyzer
+        .token_stream(text)
+        .process(&mut |token| tokens.push(token.clone()));
+    tokens
+}
+
+/// The different ways a word of a query may have been indexed in the `stemmed` field,
+/// since the language of the documents it should match is unknown.
+pub(crate) fn query_variants(text: &str) -> Vec<Vec<String>> {
+    let analyzers = if text.chars().any(is_cjk) {
+        vec![bigram_analyzer(), japanese_analyzer()]
+    } else {
+        let mut analyzers = vec![language_analyzer(None)];
+        analyzers.extend(
+            STEMMED_LANGUAGES
+                .iter()
+                .map(|(lang, _)| language_analyzer(Some(*lang))),
+        );
+        analyzers
+    };
+    let mut variants: Vec<Vec<String>> = vec![];
+    for analyzer in analyzers {
+        let terms = analyze(&analyzer, text)
+            .into_iter()
+            .map(|token| token.text)
+            .collect::<Vec<_>>();
+        if !terms.is_empty() && !variants.contains(&terms) {
+            variants.push(terms);
+        }
+    }
+    variants
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}' // kana
+        | '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}' // ideographs
+        | '\u{ac00}'..='\u{d7af}' // hangul
+    )
+}
@@ -0,0 +1,257 @@
+//! Series of articles of a blog, meant to be read in a given order.
+
+use crate::{
+    ap_url,
+    blogs::Blog,
+    posts::Post,
+    schema::{posts, series, series_posts},
+    Connection, Error, Result, CONFIG,
+};
+use activitystreams::{
+    base::AnyBase, collection::OrderedCollection, iri_string::types::IriString, prelude::*,
+};
+use chrono::NaiveDateTime;
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+use plume_common::utils::iri_percent_encode_seg;
+
+#[derive(Clone, Queryable, Identifiable)]
+#[table_name = "series"]
+pub struct Series {
+    pub id: i32,
+    pub blog_id: i32,
+    pub title: String,
+    pub slug: String,
+    pub summary: String,
+    pub creation_date: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "series"]
+pub struct NewSeries {
+    pub blog_id: i32,
+    pub title: String,
+    pub slug: String,
+    pub summary: String,
+}
+
+/// The place of an article in a series.
+#[derive(Clone, Queryable, Identifiable)]
+pub struct SeriesPost {
+    pub id: i32,
+    pub series_id: i32,
+    pub post_id: i32,
+    pub position: i32,
+}
+
+#[derive(Insertable)]
+#[table_name = "series_posts"]
+pub struct NewSeriesPost {
+    pub series_id: i32,
+    pub post_id: i32,
+    pub position: i32,
+}
+
+impl Series {
+    insert!(series, NewSeries);
+    get!(series);
+    find_by!(series, find_by_slug, blog_id as i32, slug as &str);
+    list_by!(series, list_for_blog, blog_id as i32);
+
+    /// Finds the series of `blog` with this title, creating it if needed.
+    pub fn find_or_create(conn: &Connection, blog: &Blog, title: &str) -> Result<Self> {
+        let title = title.trim();
+        if title.is_empty() {
+            return Err(Error::InvalidValue);
+        }
+        Self::find_by_slug(conn, blog.id, title).or_else(|_| {
+            Self::insert(
+                conn,
+                NewSeries {
+                    blog_id: blog.id,
+                    title: title.to_owned(),
+                    slug: Post::slug(title).to_owned(),
+                    summary: String::new(),
+                },
+            )
+        })
+    }
+
+    /// The series an article is part of, and its position in it.
+    pub fn for_post(conn: &Connection, post_id: i32) -> Result<(Self, SeriesPost)> {
+        let entry = series_posts::table
+            .filter(series_posts::post_id.eq(post_id))
+            .first::<SeriesPost>(conn)?;
+        Ok((Self::get(conn, entry.series_id)?, entry))
+    }
+
+    /// Adds an article at the end of a series, or removes it from its series.
+    pub fn set_for_post(conn: &Connection, post: &Post, series: Option<&Series>) -> Result<()> {
+        if let Ok((current, _)) = Self::for_post(conn, post.id) {
+            if series.map(|s| s.id) == Some(current.id) {
+                return Ok(());
+            }
+            diesel::delete(series_posts::table.filter(series_posts::post_id.eq(post.id)))
+                .execute(conn)?;
+        }
+        if let Some(series) = series {
+            let position = series_posts::table
+                .filter(series_posts::series_id.eq(series.id))
+                .select(diesel::dsl::max(series_posts::position))
+                .first::<Option<i32>>(conn)?
+                .map(|p| p + 1)
+                .unwrap_or(0);
+            diesel::insert_into(series_posts::table)
+                .values(NewSeriesPost {
+                    series_id: series.id,
+                    post_id: post.id,
+                    position,
+                })
+                .execute(conn)?;
+        }
+        Ok(())
+    }
+
+    /// The published articles of this series, in reading order.
+    pub fn posts(&self, conn: &Connection) -> Result<Vec<Post>> {
+        posts::table
+            .inner_join(series_posts::table)
+            .filter(series_posts::series_id.eq(self.id))
+            .filter(posts::published.eq(true))
+            .order(series_posts::position.asc())
+            .select(posts::all_columns)
+            .load::<Post>(conn)
+            .map_err(Error::from)
+    }
+
+    /// The articles before and after `post` in this series.
+    pub fn neighbours(
+        &self,
+        conn: &Connection,
+        post: &Post,
+    ) -> Result<(Option<Post>, Option<Post>)> {
+        let posts = self.posts(conn)?;
+        Ok(match posts.iter().position(|p| p.id == post.id) {
+            Some(i) => (
+                i.checked_sub(1).and_then(|i| posts.get(i)).cloned(),
+                posts.get(i + 1).cloned(),
+            ),
+            None => (None, None),
+        })
+    }
+
+    pub fn get_blog(&self, conn: &Connection) -> Result<Blog> {
+        Blog::get(conn, self.blog_id)
+    }
+
+    pub fn ap_url(&self, conn: &Connection) -> Result<String> {
+        Ok(ap_url(&format!(
+            "{}/~/{}/series/{}/",
+            CONFIG.base_url,
+            iri_percent_encode_seg(&self.get_blog(conn)?.fqn),
+            iri_percent_encode_seg(&self.slug)
+        )))
+    }
+
+    /// The articles of this series, as an ordered collection.
+    pub fn to_activity(&self, conn: &Connection) -> Result<OrderedCollection> {
+        let items = self
+            .posts(conn)?
+            .into_iter()
+            .filter_map(|p| p.ap_url.parse::<IriString>().ok())
+            .map(AnyBase::from_xsd_any_uri)
+            .collect::<Vec<_>>();
+        let mut coll = OrderedCollection::new();
+        coll.set_id(self.ap_url(conn)?.parse::<IriString>()?);
+        coll.set_name(self.title.clone());
+        if !self.summary.is_empty() {
+            coll.set_summary(self.summary.clone());
+        }
+        coll.set_attributed_to(self.get_blog(conn)?.ap_url.parse::<IriString>()?);
+        coll.set_total_items(items.len() as u64);
+        coll.set_many_ordered_items(items);
+        Ok(coll)
+    }
+
+    pub fn update_summary(&self, conn: &Connection, summary: &str) -> Result<()> {
+        diesel::update(self)
+            .set(series::summary.eq(summary))
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+
+    pub fn delete(&self, conn: &Connection) -> Result<()> {
+        diesel::delete(self)
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        inbox::tests::fill_database,
+        posts::{NewPost, Post},
+        safe_string::SafeString,
+        tests::db,
+    };
+    use diesel::Connection;
+
+    #[test]
+    fn series_order() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, _, blogs) = fill_database(conn);
+            let second = Post::insert(
+                conn,
+                NewPost {
+                    blog_id: blogs[0].id,
+                    slug: "second".to_owned(),
+                    title: "Second".to_owned(),
+                    content: SafeString::new(""),
+                    published: true,
+                    license: String::new(),
+                    creation_date: None,
+                    ap_url: "https://plu.me/~/BlogName/second".to_owned(),
+                    subtitle: String::new(),
+                    source: String::new(),
+                    cover_id: None,
+                    excerpt: String::new(),
+                    canonical_url: None,
+                },
+            )?;
+
+            assert!(Series::find_or_create(conn, &blogs[0], " ").is_err());
+            let series = Series::find_or_create(conn, &blogs[0], "Rust 101")?;
+            assert_eq!(
+                Series::find_or_create(conn, &blogs[0], "Rust 101 ")?.id,
+                series.id
+            );
+            Series::set_for_post(conn, &posts[0], Some(&series))?;
+            Series::set_for_post(conn, &second, Some(&series))?;
+            // Setting it again doesn't move it to the end
+            Series::set_for_post(conn, &posts[0], Some(&series))?;
+
+            assert_eq!(
+                series.posts(conn)?.iter().map(|p| p.id).collect::<Vec<_>>(),
+                vec![posts[0].id, second.id]
+            );
+            let (previous, next) = series.neighbours(conn, &second)?;
+            assert_eq!(previous.map(|p| p.id), Some(posts[0].id));
+            assert!(next.is_none());
+            assert_eq!(Series::for_post(conn, second.id)?.1.position, 1);
+
+            let coll = serde_json::to_value(series.to_activity(conn)?)?;
+            assert_eq!(coll["type"], "OrderedCollection");
+            assert_eq!(coll["totalItems"], 2);
+            assert_eq!(coll["orderedItems"][1], second.ap_url);
+
+            Series::set_for_post(conn, &posts[0], None)?;
+            assert!(Series::for_post(conn, posts[0].id).is_err());
+            assert_eq!(series.posts(conn)?.len(), 1);
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,246 @@
+//! The browsers in which users are logged in.
+//!
+//! The authentication cookie only contains a random token, pointing to one of these
+//! sessions: they can be listed and revoked from the account settings, and a revoked
+//! session stops working immediately. Sessions are forgotten when their cookie expires.
+
+use crate::{
+    db_conn::DbConn,
+    ip_records::stored_address,
+    maintenance::Maintenance,
+    schema::sessions,
+    users::{User, AUTH_COOKIE},
+    Connection, Cursor, Error, Result,
+};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+use plume_common::utils::random_hex;
+use rocket::{
+    http::{Cookie, SameSite},
+    outcome::IntoOutcome,
+    request::{self, FromRequest, Request},
+    Outcome,
+};
+use std::net::IpAddr;
+
+/// How long a session lasts, the same as Rocket's private cookies.
+pub const SESSION_DAYS: i64 = 7;
+/// `last_seen` is only updated once in a while, not to write to the database on each request.
+const LAST_SEEN_PRECISION: i64 = 5;
+
+/// The browser and the address a request comes from.
+#[derive(Clone, Debug, Default)]
+pub struct Device {
+    pub user_agent: Option<String>,
+    pub ip: Option<IpAddr>,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Device {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        Outcome::Success(Device {
+            user_agent: request
+                .headers()
+                .get_one("User-Agent")
+                .map(|ua| ua.chars().take(256).collect()),
+            ip: request.client_ip(),
+        })
+    }
+}
+
+#[derive(Clone, Queryable, Identifiable)]
+pub struct Session {
+    pub id: i32,
+    pub user_id: i32,
+    pub token: String,
+    pub user_agent: Option<String>,
+    /// The last address this session was used from, or its hash, as for `IpRecord`
+    pub address: Option<String>,
+    pub creation_date: NaiveDateTime,
+    pub last_seen: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "sessions"]
+pub struct NewSession {
+    pub user_id: i32,
+    pub token: String,
+    pub user_agent: Option<String>,
+    pub address: Option<String>,
+}
+
+impl Session {
+    insert!(sessions, NewSession);
+    get!(sessions);
+    find_by!(sessions, find_by_token, token as &str);
+
+    /// Starts a new session, when a user logs in.
+    pub fn open(conn: &Connection, user: &User, device: &Device) -> Result<Self> {
+        user.mark_active(conn)?;
+        Self::insert(
+            conn,
+            NewSession {
+                user_id: user.id,
+                token: random_hex(),
+                user_agent: device.user_agent.clone(),
+                address: device.ip.map(stored_address),
+            },
+        )
+    }
+
+    /// The cookie to give to the browser of this session.
+    pub fn cookie(&self) -> Cookie<'static> {
+        Cookie::build(AUTH_COOKIE, self.token.clone())
+            .same_site(SameSite::Lax)
+            .finish()
+    }
+
+    /// The address to show to the user, if it wasn't hashed.
+    pub fn shown_address(&self) -> Option<&str> {
+        self.address
+            .as_deref()
+            .filter(|address| address.parse::<IpAddr>().is_ok())
+    }
+
+    /// The sessions of a user, the most recently used first.
+    pub fn list_for_user(conn: &Connection, user_id: i32) -> Result<Vec<Self>> {
+        sessions::table
+            .filter(sessions::user_id.eq(user_id))
+            .filter(sessions::creation_date.gt(expiration_limit()))
+            .order(sessions::last_seen.desc())
+            .load::<Self>(conn)
+            .map_err(Error::from)
+    }
+
+    /// A page of the sessions of a user, as API clients ask for it.
+    pub fn list_for_user_page(
+        conn: &Connection,
+        user_id: i32,
+        cursor: Cursor,
+    ) -> Result<Vec<Self>> {
+        paginate!(
+            sessions::table
+                .filter(sessions::user_id.eq(user_id))
+                .filter(sessions::creation_date.gt(expiration_limit()))
+                .into_boxed(),
+            sessions,
+            cursor
+        )
+        .load::<Self>(conn)
+        .map(|page| cursor.finish(page))
+        .map_err(Error::from)
+    }
+
+    /// Logs a browser out.
+    pub fn revoke(&self, conn: &Connection) -> Result<()> {
+        diesel::delete(self)
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+
+    /// Logs out all the browsers of a user, except the one given.
+    pub fn revoke_all(conn: &Connection, user_id: i32, except: Option<i32>) -> Result<usize> {
+        diesel::delete(
+            sessions::table
+                .filter(sessions::user_id.eq(user_id))
+                .filter(sessions::id.ne(except.unwrap_or(-1))),
+        )
+        .execute(conn)
+        .map_err(Error::from)
+    }
+
+    /// Forgets the sessions whose cookie expired.
+    pub fn purge_expired(conn: &Connection) -> Result<usize> {
+        diesel::delete(sessions::table.filter(sessions::creation_date.lt(expiration_limit())))
+            .execute(conn)
+            .map_err(Error::from)
+    }
+
+    fn touch(self, conn: &Connection, device: &Device) -> Result<Self> {
+        let now = Utc::now().naive_utc();
+        // Nothing is written during maintenance
+        if now - self.last_seen < Duration::minutes(LAST_SEEN_PRECISION)
+            || Maintenance::is_active(conn)
+        {
+            return Ok(self);
+        }
+        let address = device
+            .ip
+            .map(stored_address)
+            .or_else(|| self.address.clone());
+        diesel::update(&self)
+            .set((sessions::last_seen.eq(now), sessions::address.eq(address)))
+            .execute(conn)?;
+        User::get(conn, self.user_id)?.mark_active(conn)?;
+        Self::get(conn, self.id)
+    }
+}
+
+fn expiration_limit() -> NaiveDateTime {
+    Utc::now().naive_utc() - Duration::days(SESSION_DAYS)
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Session {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        request
+            .local_cache(|| {
+                let conn = request.guard::<DbConn>().succeeded()?;
+                let device = request.guard::<Device>().succeeded()?;
+                let token = request.cookies().get_private(AUTH_COOKIE)?;
+                Session::find_by_token(&conn, token.value())
+                    .ok()
+                    .filter(|session| session.creation_date > expiration_limit())
+                    .and_then(|session| session.touch(&conn, &device).ok())
+            })
+            .clone()
+            .or_forward(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tests::db, users::tests::fill_database};
+    use diesel::Connection;
+
+    #[test]
+    fn open_and_revoke() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let users = fill_database(conn);
+            let device = Device {
+                user_agent: Some("Firefox".to_owned()),
+                ip: "198.51.100.7".parse().ok(),
+            };
+            let first = Session::open(conn, &users[1], &device)?;
+            let second = Session::open(conn, &users[1], &Device::default())?;
+            Session::open(conn, &users[2], &device)?;
+            assert_ne!(first.token, second.token);
+            assert_eq!(first.address, device.ip.map(stored_address));
+            assert_eq!(
+                first.shown_address().is_some(),
+                first.address.as_deref() == Some("198.51.100.7")
+            );
+            assert_eq!(
+                Session::find_by_token(conn, &second.token)?.user_id,
+                users[1].id
+            );
+            assert_eq!(Session::list_for_user(conn, users[1].id)?.len(), 2);
+
+            assert_eq!(Session::revoke_all(conn, users[1].id, Some(first.id))?, 1);
+            assert!(Session::find_by_token(conn, &second.token).is_err());
+            first.revoke(conn)?;
+            assert!(Session::list_for_user(conn, users[1].id)?.is_empty());
+            assert_eq!(Session::list_for_user(conn, users[2].id)?.len(), 1);
+            // Changing the password logs out everywhere
+            users[2].reset_password(conn, "new password")?;
+            assert!(Session::list_for_user(conn, users[2].id)?.is_empty());
+            assert_eq!(Session::purge_expired(conn)?, 0);
+            Ok(())
+        });
+    }
+}
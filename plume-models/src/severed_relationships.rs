@@ -0,0 +1,395 @@
+//! Follows that were lost because of a block between instances.
+//!
+//! When an administrator blocks an instance, the follows between local users and its
+//! accounts are removed, and the local users who had some are notified, with the list of
+//! the accounts they lost so that they can follow them again from elsewhere.
+//!
+//! Other instances don't tell when they block this one, but it can be guessed when all
+//! the accounts of an instance stop following local users at once: the unfollows of each
+//! instance are gathered for an hour before deciding if it was a block.
+
+use crate::{
+    follows::Follow,
+    instance::Instance,
+    notifications::{notification_kind, NewNotification, Notification},
+    schema::{follows, severances, severed_relationships, users},
+    users::User,
+    Connection, Error, Result,
+};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{self, ExpressionMethods, JoinOnDsl, OptionalExtension, QueryDsl, RunQueryDsl};
+
+pub mod severance_reason {
+    /// An administrator of this instance blocked the other one
+    pub const DOMAIN_BLOCK: &str = "DOMAIN_BLOCK";
+    /// All the followers from the other instance left at once
+    pub const REMOTE_BLOCK: &str = "REMOTE_BLOCK";
+}
+
+/// How many followers an instance has to remove at once for it to look like a block.
+const REMOTE_BLOCK_MIN_FOLLOWERS: usize = 5;
+
+/// How long the unfollows of an instance are gathered, in hours.
+const REMOTE_BLOCK_WINDOW_HOURS: i64 = 1;
+
+#[derive(Clone, Debug, Queryable, Identifiable)]
+pub struct Severance {
+    pub id: i32,
+    /// The other instance
+    pub domain: String,
+    /// One of `severance_reason`
+    pub reason: String,
+    /// Whether the users were notified (a guessed remote block is only once confirmed)
+    pub notified: bool,
+    pub creation_date: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "severances"]
+struct NewSeverance<'a> {
+    domain: &'a str,
+    reason: &'a str,
+    notified: bool,
+}
+
+#[derive(Clone, Debug, Queryable, Identifiable)]
+pub struct SeveredRelationship {
+    pub id: i32,
+    pub severance_id: i32,
+    /// The local user
+    pub user_id: i32,
+    /// The address of the remote account (`name@domain`)
+    pub remote_actor: String,
+    /// `true` if the local user followed the remote account, `false` if it was the opposite
+    pub following: bool,
+}
+
+#[derive(Insertable)]
+#[table_name = "severed_relationships"]
+struct NewSeveredRelationship {
+    severance_id: i32,
+    user_id: i32,
+    remote_actor: String,
+    following: bool,
+}
+
+impl Severance {
+    get!(severances);
+
+    fn insert(conn: &Connection, domain: &str, reason: &str, notified: bool) -> Result<Self> {
+        diesel::insert_into(severances::table)
+            .values(NewSeverance {
+                domain,
+                reason,
+                notified,
+            })
+            .execute(conn)?;
+        severances::table
+            .order(severances::id.desc())
+            .first(conn)
+            .map_err(Error::from)
+    }
+
+    /// Removes the follows between local users and the accounts of an instance that was just
+    /// blocked, and notifies the local users who had some.
+    pub fn sever_instance(conn: &Connection, instance: &Instance) -> Result<Self> {
+        let severance = Self::insert(
+            conn,
+            &instance.public_domain,
+            severance_reason::DOMAIN_BLOCK,
+            true,
+        )?;
+        let local_id = Instance::get_local()?.id;
+        // The follows of the actor of this instance (friendly instances) are removed too,
+        // but there is nobody to tell
+        let local_actor = Instance::get_local_instance_user_uncached(conn)?;
+
+        let following = follows::table
+            .inner_join(users::table.on(follows::following_id.eq(users::id)))
+            .filter(users::instance_id.eq(instance.id))
+            .filter(
+                follows::follower_id.eq_any(
+                    users::table
+                        .filter(users::instance_id.eq(local_id))
+                        .select(users::id),
+                ),
+            )
+            .select((follows::all_columns, users::fqn))
+            .load::<(Follow, String)>(conn)?
+            .into_iter()
+            .map(|(follow, fqn)| (follow.follower_id, follow, fqn, true));
+        let followers = follows::table
+            .inner_join(users::table.on(follows::follower_id.eq(users::id)))
+            .filter(users::instance_id.eq(instance.id))
+            .filter(
+                follows::following_id.eq_any(
+                    users::table
+                        .filter(users::instance_id.eq(local_id))
+                        .select(users::id),
+                ),
+            )
+            .select((follows::all_columns, users::fqn))
+            .load::<(Follow, String)>(conn)?
+            .into_iter()
+            .map(|(follow, fqn)| (follow.following_id, follow, fqn, false));
+
+        for (user_id, follow, remote_actor, following) in
+            following.chain(followers).collect::<Vec<_>>()
+        {
+            Notification::retract(
+                conn,
+                notification_kind::FOLLOW,
+                follow.id,
+                follow.following_id,
+            )?;
+            diesel::delete(&follow).execute(conn)?;
+            if user_id != local_actor.id {
+                severance.add(conn, user_id, remote_actor, following)?;
+            }
+        }
+        severance.notify(conn)?;
+        Ok(severance)
+    }
+
+    /// Remembers that a remote account stopped following a local user, in case their
+    /// instance is blocking this one.
+    pub fn record_unfollow(conn: &Connection, follower: &User, followed: &User) -> Result<()> {
+        let domain = follower.get_instance(conn)?.public_domain;
+        let pending = severances::table
+            .filter(severances::domain.eq(&domain))
+            .filter(severances::reason.eq(severance_reason::REMOTE_BLOCK))
+            .filter(severances::notified.eq(false))
+            .first::<Self>(conn)
+            .optional()?;
+        let severance = match pending {
+            Some(severance) => severance,
+            None => Self::insert(conn, &domain, severance_reason::REMOTE_BLOCK, false)?,
+        };
+        severance.add(conn, followed.id, follower.fqn.clone(), false)
+    }
+
+    /// Notifies the users who lost all their followers from an instance at once, which
+    /// probably blocked this one, and forgets the other unfollows.
+    ///
+    /// Returns the number of blocks that were found.
+    pub fn detect_remote_blocks(conn: &Connection) -> Result<usize> {
+        let limit = Utc::now().naive_utc() - Duration::hours(REMOTE_BLOCK_WINDOW_HOURS);
+        let pending = severances::table
+            .filter(severances::reason.eq(severance_reason::REMOTE_BLOCK))
+            .filter(severances::notified.eq(false))
+            .filter(severances::creation_date.lt(limit))
+            .load::<Self>(conn)?;
+        let mut found = 0;
+        for severance in pending {
+            let lost = severance.all_relationships(conn)?.len();
+            let remaining = match Instance::find_by_domain(conn, &severance.domain) {
+                Ok(instance) => follows::table
+                    .inner_join(users::table.on(follows::follower_id.eq(users::id)))
+                    .filter(users::instance_id.eq(instance.id))
+                    .count()
+                    .get_result::<i64>(conn)?,
+                Err(_) => 0,
+            };
+            if lost >= REMOTE_BLOCK_MIN_FOLLOWERS && remaining == 0 {
+                severance.notify(conn)?;
+                found += 1;
+            } else {
+                diesel::delete(&severance).execute(conn)?;
+            }
+        }
+        Ok(found)
+    }
+
+    fn add(
+        &self,
+        conn: &Connection,
+        user_id: i32,
+        remote_actor: String,
+        following: bool,
+    ) -> Result<()> {
+        diesel::insert_into(severed_relationships::table)
+            .values(NewSeveredRelationship {
+                severance_id: self.id,
+                user_id,
+                remote_actor,
+                following,
+            })
+            .execute(conn)?;
+        Ok(())
+    }
+
+    fn all_relationships(&self, conn: &Connection) -> Result<Vec<SeveredRelationship>> {
+        severed_relationships::table
+            .filter(severed_relationships::severance_id.eq(self.id))
+            .load(conn)
+            .map_err(Error::from)
+    }
+
+    /// Sends a notification to each user who lost some relationships.
+    fn notify(&self, conn: &Connection) -> Result<()> {
+        let mut user_ids = self
+            .all_relationships(conn)?
+            .into_iter()
+            .map(|r| r.user_id)
+            .collect::<Vec<_>>();
+        user_ids.sort_unstable();
+        user_ids.dedup();
+        for user_id in user_ids {
+            Notification::insert(
+                conn,
+                NewNotification {
+                    kind: notification_kind::SEVERED_RELATIONSHIPS.to_owned(),
+                    object_id: self.id,
+                    user_id,
+                    group_key: None,
+                    filtered: false,
+                },
+            )?;
+        }
+        diesel::update(self)
+            .set(severances::notified.eq(true))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    /// The relationships `user` lost.
+    pub fn relationships(
+        &self,
+        conn: &Connection,
+        user: &User,
+    ) -> Result<Vec<SeveredRelationship>> {
+        severed_relationships::table
+            .filter(severed_relationships::severance_id.eq(self.id))
+            .filter(severed_relationships::user_id.eq(user.id))
+            .order(severed_relationships::remote_actor.asc())
+            .load(conn)
+            .map_err(Error::from)
+    }
+
+    /// The accounts `user` followed (or that followed them), as a CSV file that other
+    /// platforms can import.
+    pub fn to_csv(&self, conn: &Connection, user: &User, following: bool) -> Result<String> {
+        Ok(self
+            .relationships(conn, user)?
+            .into_iter()
+            .filter(|r| r.following == following)
+            .fold("Account address\n".to_owned(), |csv, r| {
+                csv + &r.remote_actor + "\n"
+            }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        follows::NewFollow,
+        safe_string::SafeString,
+        schema::instances,
+        tests::db,
+        users::{tests as user_tests, NewUser},
+    };
+    use diesel::Connection;
+
+    fn remote_instance(conn: &Connection) -> Instance {
+        instances::table
+            .filter(instances::local.eq(false))
+            .first(&**conn)
+            .unwrap()
+    }
+
+    fn remote_user(conn: &Connection, instance: &Instance, name: &str) -> User {
+        let url = format!("https://{}/@/{}/", instance.public_domain, name);
+        User::insert(
+            conn,
+            NewUser {
+                username: name.to_owned(),
+                display_name: name.to_owned(),
+                outbox_url: format!("{}outbox", url),
+                inbox_url: format!("{}inbox", url),
+                instance_id: instance.id,
+                followers_endpoint: format!("{}followers", url),
+                ap_url: url,
+                summary_html: SafeString::new(""),
+                role: 2,
+                fqn: format!("{}@{}", name, instance.public_domain),
+                ..NewUser::default()
+            },
+        )
+        .unwrap()
+    }
+
+    fn follow(conn: &Connection, from: &User, to: &User) -> Follow {
+        Follow::insert(
+            conn,
+            NewFollow {
+                follower_id: from.id,
+                following_id: to.id,
+                ap_url: String::new(),
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn domain_block() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let users = user_tests::fill_database(conn);
+            let remote = remote_instance(conn);
+            let alice = remote_user(conn, &remote, "alice");
+            let bob = remote_user(conn, &remote, "bob");
+            follow(conn, &users[0], &alice);
+            follow(conn, &bob, &users[0]);
+            follow(conn, &bob, &users[1]);
+
+            let severance = Severance::sever_instance(conn, &remote)?;
+            assert!(!users[0].is_following(conn, alice.id)?);
+            assert!(!bob.is_following(conn, users[1].id)?);
+            assert_eq!(severance.relationships(conn, &users[0])?.len(), 2);
+            assert_eq!(
+                severance.to_csv(conn, &users[0], true)?,
+                format!("Account address\n{}\n", alice.fqn)
+            );
+            assert_eq!(
+                Notification::find_for_user(conn, &users[1])?
+                    .iter()
+                    .filter(|n| n.kind == notification_kind::SEVERED_RELATIONSHIPS)
+                    .count(),
+                1
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn remote_block() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let users = user_tests::fill_database(conn);
+            let remote = remote_instance(conn);
+            for i in 0..REMOTE_BLOCK_MIN_FOLLOWERS {
+                let follower = remote_user(conn, &remote, &format!("follower{}", i));
+                Severance::record_unfollow(conn, &follower, &users[0])?;
+            }
+            // Too recent to decide
+            assert_eq!(Severance::detect_remote_blocks(conn)?, 0);
+
+            diesel::update(severances::table)
+                .set(
+                    severances::creation_date.eq(Utc::now().naive_utc()
+                        - Duration::hours(REMOTE_BLOCK_WINDOW_HOURS + 1)),
+                )
+                .execute(&**conn)?;
+            assert_eq!(Severance::detect_remote_blocks(conn)?, 1);
+            assert_eq!(
+                Notification::find_for_user(conn, &users[0])?
+                    .iter()
+                    .filter(|n| n.kind == notification_kind::SEVERED_RELATIONSHIPS)
+                    .count(),
+                1
+            );
+            Ok(())
+        });
+    }
+}
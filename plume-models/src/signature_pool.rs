@@ -0,0 +1,170 @@
+//! The threads checking the signatures of incoming activities.
+//!
+//! Checking an RSA signature, and hashing a large activity to compare it with its digest,
+//! keeps a CPU busy. When many activities come at once, doing it on the Rocket threads would
+//! slow down the rendering of pages: it is done by a few threads of its own instead. The
+//! Rocket threads still wait for the result, so only a few of them can: when too many checks
+//! are in progress, the activities are refused right away, and the other instances deliver
+//! them again later. The other Rocket threads are left for pages.
+
+use plume_common::activity_pub::{
+    request::Digest,
+    sign::{verify_http_headers, Signable, Signer},
+};
+use rocket::http::HeaderMap;
+use serde_json::Value;
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{channel, sync_channel, SyncSender},
+        Arc, Mutex,
+    },
+    thread,
+};
+use tracing::{warn, Span};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Why a signature couldn't be checked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PoolError {
+    /// Too many requests are waiting for a signature check
+    Busy,
+    /// The check panicked
+    Failed,
+}
+
+pub struct SignaturePool {
+    jobs: Mutex<SyncSender<Job>>,
+    /// How many requests are waiting for a check, queued or running
+    waiting: AtomicUsize,
+    max_waiting: usize,
+}
+
+impl SignaturePool {
+    /// Starts `threads` threads, and lets up to `max_waiting` requests wait for them.
+    pub fn new(threads: usize, max_waiting: usize) -> Self {
+        let max_waiting = max_waiting.max(1);
+        let (jobs, receiver) = sync_channel::<Job>(max_waiting);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..threads.max(1) {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("signatures {}", i))
+                .spawn(move || loop {
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => {
+                            if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                                warn!("A signature check panicked");
+                            }
+                        }
+                        // The pool was dropped
+                        Err(_) => break,
+                    }
+                })
+                .expect("Couldn't start the signature threads");
+        }
+        SignaturePool {
+            jobs: Mutex::new(jobs),
+            waiting: AtomicUsize::new(0),
+            max_waiting,
+        }
+    }
+
+    /// Runs `check` on one of the threads of the pool and waits for its result, or fails
+    /// right away if too many requests are already waiting.
+    pub fn run<F, T>(&self, check: F) -> Result<T, PoolError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        if self.waiting.fetch_add(1, Ordering::SeqCst) >= self.max_waiting {
+            self.waiting.fetch_sub(1, Ordering::SeqCst);
+            return Err(PoolError::Busy);
+        }
+        let (result, receiver) = channel();
+        // The logs of the check are in the span of the request
+        let span = Span::current();
+        let res = self
+            .jobs
+            .lock()
+            .unwrap()
+            .try_send(Box::new(move || {
+                let _ = result.send(span.in_scope(check));
+            }))
+            .map_err(|_| PoolError::Busy)
+            .and_then(|_| receiver.recv().map_err(|_| PoolError::Failed));
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+        res
+    }
+
+    /// Whether `activity` was signed by `signer`, in the HTTP headers of the request that
+    /// delivered it (whose `body` is hashed to check its digest) or with a JSON-LD signature.
+    pub fn is_signed_by<S>(
+        &self,
+        signer: &S,
+        headers: &Arc<HeaderMap<'static>>,
+        body: &Arc<String>,
+        activity: &Value,
+    ) -> Result<bool, PoolError>
+    where
+        S: Signer + Clone + Send + 'static,
+    {
+        let (signer, headers, body, activity) = (
+            signer.clone(),
+            headers.clone(),
+            body.clone(),
+            activity.clone(),
+        );
+        self.run(move || {
+            let digest = Digest::from_body(&body);
+            verify_http_headers(&signer, &headers, &digest).is_secure() || activity.verify(&signer)
+        })
+    }
+}
+
+/// A copy of the headers of a request, that can be sent to the pool.
+pub fn owned_headers(headers: &HeaderMap<'_>) -> HeaderMap<'static> {
+    let mut owned = HeaderMap::new();
+    for header in headers.iter() {
+        owned.add_raw(header.name().to_owned(), header.value().to_owned());
+    }
+    owned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn bounded_queue() {
+        let pool = Arc::new(SignaturePool::new(1, 1));
+        assert_eq!(pool.run(|| 1 + 1), Ok(2));
+        assert_eq!(
+            pool.run(|| -> i32 { panic!("Invalid key") }),
+            Err(PoolError::Failed)
+        );
+
+        // The only request that can wait is waiting for a first check
+        let (release, blocked) = mpsc::channel::<()>();
+        let (started, running) = mpsc::channel();
+        let first = {
+            let pool = pool.clone();
+            thread::spawn(move || {
+                pool.run(move || {
+                    started.send(()).unwrap();
+                    blocked.recv().unwrap();
+                })
+            })
+        };
+        running.recv().unwrap();
+        assert_eq!(pool.run(|| 3), Err(PoolError::Busy));
+
+        release.send(()).unwrap();
+        assert_eq!(first.join().unwrap(), Ok(()));
+        assert_eq!(pool.run(|| 4), Ok(4));
+    }
+}
@@ -0,0 +1,157 @@
+//! Spam scoring of the comments received from other instances.
+//!
+//! Each signal adds to the score of a comment: the links it contains, how recently its
+//! author created their account (when their instance tells it), how the instance it comes
+//! from behaved until now, and the keywords configured with `SPAM_KEYWORDS`. Comments with a
+//! score of at least `SPAM_THRESHOLD` are held until an author of the blog approves them,
+//! instead of being published right away.
+
+use crate::{
+    instance::Instance,
+    schema::{comments, users},
+    users::User,
+    Connection, Result, CONFIG,
+};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+
+/// Past this number of held comments in the last 30 days, an instance is considered to
+/// send spam.
+const HELD_COMMENTS_FROM_SPAMMY_INSTANCE: i64 = 3;
+
+/// The spam score of a comment received from `author`, with `content` as HTML.
+pub fn score(conn: &Connection, author: &User, content: &str) -> Result<i32> {
+    let now = Utc::now().naive_utc();
+    let instance = author.get_instance(conn)?;
+    Ok(link_score(content)
+        + account_age_score(author.published, now)
+        + instance_score(conn, &instance, now)?
+        + keyword_score(content, &CONFIG.spam.keywords))
+}
+
+/// Whether a comment with this score has to be held for moderation.
+pub fn is_spam(score: i32) -> bool {
+    CONFIG
+        .spam
+        .threshold
+        .map_or(false, |threshold| score >= threshold)
+}
+
+/// One point for each link, up to three, and two more when there are less than ten words for
+/// each link.
+///
+/// Mentions and hashtags are not counted.
+fn link_score(content: &str) -> i32 {
+    let links = content
+        .split("<a ")
+        .skip(1)
+        .filter_map(|tag| tag.split('>').next())
+        .filter(|tag| tag.contains("href=") && !tag.contains("mention") && !tag.contains("hashtag"))
+        .count() as i32;
+    let words = text(content).split_whitespace().count() as i32;
+    let dense = links > 0 && words < links * 10;
+    links.min(3) + if dense { 2 } else { 0 }
+}
+
+/// Two points for accounts created less than a day ago, one for the ones of less than a week.
+fn account_age_score(published: Option<NaiveDateTime>, now: NaiveDateTime) -> i32 {
+    match published {
+        Some(date) if now - date < Duration::days(1) => 2,
+        Some(date) if now - date < Duration::days(7) => 1,
+        _ => 0,
+    }
+}
+
+/// One point for instances discovered less than a day ago, two for the ones whose comments
+/// were recently held.
+fn instance_score(conn: &Connection, instance: &Instance, now: NaiveDateTime) -> Result<i32> {
+    let mut score = 0;
+    if now - instance.creation_date < Duration::days(1) {
+        score += 1;
+    }
+    let held = comments::table
+        .inner_join(users::table)
+        .filter(users::instance_id.eq(instance.id))
+        .filter(comments::held.eq(true))
+        .filter(comments::creation_date.gt(now - Duration::days(30)))
+        .count()
+        .get_result::<i64>(conn)?;
+    if held >= HELD_COMMENTS_FROM_SPAMMY_INSTANCE {
+        score += 2;
+    }
+    Ok(score)
+}
+
+/// Two points for each keyword found in the text.
+fn keyword_score(content: &str, keywords: &[String]) -> i32 {
+    let text = text(content).to_lowercase();
+    keywords
+        .iter()
+        .filter(|k| text.contains(k.as_str()))
+        .count() as i32
+        * 2
+}
+
+/// The text of some HTML, without its tags.
+fn text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => {
+                in_tag = false;
+                text.push(' ');
+            }
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links() {
+        assert_eq!(link_score("<p>Nice article, thank you!</p>"), 0);
+        assert_eq!(
+            link_score(r#"<p><a href="https://buy.example">Cheap pills</a></p>"#),
+            3
+        );
+        assert_eq!(
+            link_score(
+                r#"<p>I wrote about the same subject last year, with more examples about the
+                borrow checker: <a href="https://blog.example/borrowck">my article</a></p>"#
+            ),
+            1
+        );
+        assert_eq!(
+            link_score(
+                r#"<p><a href="https://plu.me/@/user/" class="u-url mention">@user</a> I agree with what you wrote here</p>"#
+            ),
+            0
+        );
+    }
+
+    #[test]
+    fn account_age() {
+        let now = Utc::now().naive_utc();
+        assert_eq!(account_age_score(None, now), 0);
+        assert_eq!(account_age_score(Some(now - Duration::hours(2)), now), 2);
+        assert_eq!(account_age_score(Some(now - Duration::days(3)), now), 1);
+        assert_eq!(account_age_score(Some(now - Duration::days(300)), now), 0);
+    }
+
+    #[test]
+    fn keywords() {
+        let keywords = vec!["casino".to_owned(), "free money".to_owned()];
+        assert_eq!(keyword_score("<p>Great read</p>", &keywords), 0);
+        assert_eq!(
+            keyword_score("<p>Best <b>Casino</b> online, free money!</p>", &keywords),
+            4
+        );
+    }
+}
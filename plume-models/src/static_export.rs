@@ -0,0 +1,276 @@
+//! Static HTML copies of blogs.
+//!
+//! Every published article, every tag page and the index of the blog are written as
+//! self-contained HTML files, with the local media they use copied alongside. The
+//! copy can be kept as an archive, or hosted anywhere as a read-only mirror.
+
+use crate::{
+    blogs::Blog, medias::Media, posts::Post, tags::Tag, Connection, Error, Result, CONFIG,
+};
+use plume_common::utils::escape;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+const STYLE: &str = "body { max-width: 45em; margin: 2em auto; padding: 0 1em; \
+    font-family: sans-serif; line-height: 1.6; } img, video { max-width: 100%; } \
+    header { margin-bottom: 2em; } ul.articles { list-style: none; padding: 0; } \
+    .meta { color: #666; } pre { overflow-x: auto; }";
+
+/// What was written by an export.
+#[derive(Debug, Default)]
+pub struct ExportReport {
+    pub articles: usize,
+    pub tags: usize,
+    pub medias: usize,
+    /// Media that couldn't be copied, the articles still link to their original URL
+    pub errors: Vec<String>,
+}
+
+/// Writes a static copy of `blog` in the `out` directory.
+pub fn export(conn: &Connection, blog: &Blog, out: &Path) -> Result<ExportReport> {
+    let mut report = ExportReport::default();
+    for dir in &["articles", "tags", "media"] {
+        fs::create_dir_all(out.join(dir))?;
+    }
+
+    let mut posts = Post::get_for_blog(conn, blog)?;
+    posts.sort_by(|a, b| b.creation_date.cmp(&a.creation_date));
+
+    // The local media of the authors, by URL
+    let mut medias = HashMap::new();
+    for author in blog.list_authors(conn)? {
+        for media in Media::for_user(conn, author.id)? {
+            if !media.is_remote {
+                if let Ok(url) = media.url() {
+                    medias.insert(url, media);
+                }
+            }
+        }
+    }
+    let mut copied = HashMap::new();
+
+    let mut tags: BTreeMap<String, Vec<&Post>> = BTreeMap::new();
+    for post in &posts {
+        let post_tags = Tag::for_post(conn, post.id)?;
+        for tag in &post_tags {
+            tags.entry(tag.tag.clone()).or_default().push(post);
+        }
+
+        let mut content = post.content.get().clone();
+        for (url, media) in &medias {
+            if !content.contains(url.as_str()) {
+                continue;
+            }
+            let copy = match copied.get(&media.id) {
+                Some(copy) => Some(copy),
+                None => match copy_media(media, out) {
+                    Ok(file) => {
+                        report.medias += 1;
+                        copied.insert(media.id, file);
+                        copied.get(&media.id)
+                    }
+                    Err(e) => {
+                        report.errors.push(format!("{}: {:?}", url, e));
+                        None
+                    }
+                },
+            };
+            if let Some(file) = copy {
+                content = content.replace(url.as_str(), &format!("../media/{}", file));
+            }
+        }
+
+        let authors = post
+            .get_authors(conn)?
+            .iter()
+            .map(|a| escape(&a.name()).to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let tag_links = post_tags
+            .iter()
+            .map(|t| {
+                format!(
+                    r#"<a href="../tags/{}.html">#{}</a>"#,
+                    file_name(&t.tag),
+                    escape(&t.tag)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        let body = format!(
+            r#"<article><h1>{title}</h1><p class="meta">{subtitle}</p><p class="meta">{authors} · {date}</p>{content}<p class="meta">{tags}</p><p class="meta"><a href="{url}">{url}</a></p></article>"#,
+            title = escape(&post.title),
+            subtitle = escape(&post.subtitle),
+            authors = authors,
+            date = post.creation_date.format("%Y-%m-%d"),
+            content = content,
+            tags = tag_links,
+            url = escape(&post.ap_url),
+        );
+        fs::write(
+            out.join("articles")
+                .join(format!("{}.html", file_name(&post.slug))),
+            page(blog, &post.title, "../", &body),
+        )?;
+        report.articles += 1;
+    }
+
+    for (tag, tagged) in &tags {
+        let body = format!(
+            "<h1>#{}</h1>{}",
+            escape(tag),
+            article_list(tagged.iter().copied(), "../")
+        );
+        fs::write(
+            out.join("tags").join(format!("{}.html", file_name(tag))),
+            page(blog, &format!("#{}", tag), "../", &body),
+        )?;
+        report.tags += 1;
+    }
+
+    let body = format!(
+        "<h1>{}</h1>{}{}",
+        escape(&blog.title),
+        blog.summary_html.get(),
+        article_list(posts.iter(), "")
+    );
+    fs::write(out.join("index.html"), page(blog, &blog.title, "", &body))?;
+    Ok(report)
+}
+
+fn page(blog: &Blog, title: &str, root: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title} ⋅ {blog}</title>
+<link rel="canonical" href="{url}">
+<style>{style}</style>
+</head>
+<body>
+<header><a href="{root}index.html">{blog}</a></header>
+<main>
+{body}
+</main>
+</body>
+</html>
+"#,
+        title = escape(title),
+        blog = escape(&blog.title),
+        url = escape(&blog.ap_url),
+        style = STYLE,
+        root = root,
+        body = body,
+    )
+}
+
+fn article_list<'a>(posts: impl Iterator<Item = &'a Post>, root: &str) -> String {
+    let items = posts
+        .map(|post| {
+            format!(
+                r#"<li><a href="{root}articles/{file}.html">{title}</a> <span class="meta">{date}</span><br>{subtitle}</li>"#,
+                root = root,
+                file = file_name(&post.slug),
+                title = escape(&post.title),
+                date = post.creation_date.format("%Y-%m-%d"),
+                subtitle = escape(&post.subtitle),
+            )
+        })
+        .collect::<String>();
+    format!(r#"<ul class="articles">{}</ul>"#, items)
+}
+
+/// A name that can safely be used for a file, on any system.
+fn file_name(name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect::<String>();
+    if name.is_empty() {
+        "-".to_owned()
+    } else {
+        name
+    }
+}
+
+/// Copies a media file in the `media` directory, and returns its new name.
+fn copy_media(media: &Media, out: &Path) -> Result<String> {
+    let name = media
+        .relative_url()
+        .and_then(|url| url.rsplit('/').next().map(file_name_with_extension))
+        .ok_or(Error::NotFound)?;
+    let name = format!("{}-{}", media.id, name);
+    let dest = out.join("media").join(&name);
+
+    if CONFIG.s3.is_some() {
+        #[cfg(not(feature = "s3"))]
+        unreachable!();
+
+        #[cfg(feature = "s3")]
+        {
+            let data = CONFIG
+                .s3
+                .as_ref()
+                .unwrap()
+                .get_bucket()
+                .get_object_blocking(media.relative_url().ok_or(Error::NotFound)?)?;
+            fs::write(&dest, data.bytes())?;
+        }
+    } else {
+        fs::copy(media.local_path().ok_or(Error::NotFound)?, &dest)?;
+    }
+    Ok(name)
+}
+
+fn file_name_with_extension(name: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, ext)) => format!("{}.{}", file_name(stem), file_name(ext)),
+        None => file_name(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{inbox::tests::fill_database, tests::db};
+    use diesel::Connection;
+
+    #[test]
+    fn export_blog() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, _, blogs) = fill_database(conn);
+            let out = std::env::temp_dir().join(format!(
+                "plume-export-{}",
+                plume_common::utils::random_hex()
+            ));
+            let report = export(conn, &blogs[0], &out)?;
+            let index = fs::read_to_string(out.join("index.html"))?;
+            let exported = posts
+                .iter()
+                .filter(|p| p.blog_id == blogs[0].id && p.published)
+                .collect::<Vec<_>>();
+            assert_eq!(report.articles, exported.len());
+            for post in exported {
+                assert!(index.contains(&format!("articles/{}.html", file_name(&post.slug))));
+                assert!(out
+                    .join("articles")
+                    .join(format!("{}.html", file_name(&post.slug)))
+                    .exists());
+            }
+            assert_eq!(file_name("a/b c"), "a-b-c");
+            assert_eq!(file_name_with_extension("photo 1.png"), "photo-1.png");
+            fs::remove_dir_all(&out)?;
+            Ok(())
+        });
+    }
+}
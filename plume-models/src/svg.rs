@@ -0,0 +1,248 @@
+//! Sanitization of uploaded SVG images.
+//!
+//! An SVG file is a document that can run scripts, load other resources or embed HTML:
+//! only the elements and attributes that draw something are kept. References to other
+//! files are removed, except to parts of the same image and to embedded images.
+
+use crate::{Error, Result};
+use quick_xml::{
+    events::{attributes::Attribute, BytesStart, Event},
+    Reader, Writer,
+};
+
+/// The elements that can be kept, with their content.
+const ALLOWED_ELEMENTS: &[&str] = &[
+    "svg",
+    "g",
+    "defs",
+    "symbol",
+    "use",
+    "title",
+    "desc",
+    "metadata",
+    "style",
+    "path",
+    "rect",
+    "circle",
+    "ellipse",
+    "line",
+    "polyline",
+    "polygon",
+    "text",
+    "tspan",
+    "textPath",
+    "image",
+    "linearGradient",
+    "radialGradient",
+    "stop",
+    "clipPath",
+    "mask",
+    "pattern",
+    "marker",
+    "switch",
+    "view",
+    "filter",
+    "feBlend",
+    "feColorMatrix",
+    "feComponentTransfer",
+    "feComposite",
+    "feConvolveMatrix",
+    "feDiffuseLighting",
+    "feDisplacementMap",
+    "feDistantLight",
+    "feDropShadow",
+    "feFlood",
+    "feFuncA",
+    "feFuncB",
+    "feFuncG",
+    "feFuncR",
+    "feGaussianBlur",
+    "feMerge",
+    "feMergeNode",
+    "feMorphology",
+    "feOffset",
+    "fePointLight",
+    "feSpecularLighting",
+    "feSpotLight",
+    "feTile",
+    "feTurbulence",
+];
+
+/// Removes everything that could run code or load something from an SVG image.
+///
+/// Fails if the file is not a valid XML document.
+pub fn sanitize(svg: &[u8]) -> Result<Vec<u8>> {
+    let mut reader = Reader::from_reader(svg);
+    let mut writer = Writer::new(Vec::new());
+    // How deep we are in an element that is removed
+    let mut skipped = 0usize;
+    let mut in_style = false;
+    loop {
+        let event = reader.read_event().map_err(|_| Error::InvalidValue)?;
+        match event {
+            Event::Eof => break,
+            Event::Start(_) if skipped > 0 => skipped += 1,
+            Event::End(_) if skipped > 0 => skipped -= 1,
+            _ if skipped > 0 => {}
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                let is_start = matches!(event, Event::Start(_));
+                match clean_element(e) {
+                    Some(elem) => {
+                        in_style = is_start && e.local_name().as_ref() == b"style";
+                        writer
+                            .write_event(if is_start {
+                                Event::Start(elem)
+                            } else {
+                                Event::Empty(elem)
+                            })
+                            .map_err(|_| Error::InvalidValue)?;
+                    }
+                    None if is_start => skipped = 1,
+                    None => {}
+                }
+            }
+            Event::End(e) => {
+                in_style = false;
+                writer
+                    .write_event(Event::End(e))
+                    .map_err(|_| Error::InvalidValue)?;
+            }
+            Event::Text(ref text) if in_style => {
+                let css = text.unescape().map_err(|_| Error::InvalidValue)?;
+                if is_safe_css(&css) {
+                    writer
+                        .write_event(event.clone())
+                        .map_err(|_| Error::InvalidValue)?;
+                }
+            }
+            Event::CData(ref text) if in_style => {
+                if is_safe_css(&String::from_utf8_lossy(text)) {
+                    writer
+                        .write_event(event.clone())
+                        .map_err(|_| Error::InvalidValue)?;
+                }
+            }
+            Event::Text(_) | Event::CData(_) | Event::Decl(_) => {
+                writer.write_event(event).map_err(|_| Error::InvalidValue)?
+            }
+            // Doctypes can declare entities, and processing instructions can load stylesheets
+            Event::DocType(_) | Event::PI(_) | Event::Comment(_) => {}
+        }
+    }
+    Ok(writer.into_inner())
+}
+
+/// A copy of an element with only its safe attributes, or `None` if it is not allowed.
+fn clean_element(elem: &BytesStart<'_>) -> Option<BytesStart<'static>> {
+    let name = elem.local_name();
+    let name = std::str::from_utf8(name.as_ref()).ok()?;
+    if !ALLOWED_ELEMENTS.contains(&name) {
+        return None;
+    }
+
+    let mut clean = BytesStart::new(String::from_utf8(elem.name().as_ref().to_vec()).ok()?);
+    for attr in elem.attributes().with_checks(false).flatten() {
+        if !is_safe_attribute(&attr) {
+            continue;
+        }
+        // The value is escaped again, as it may have been quoted differently
+        if let (Ok(key), Ok(value)) = (
+            std::str::from_utf8(attr.key.as_ref()),
+            attr.unescape_value(),
+        ) {
+            clean.push_attribute((key, value.as_ref()));
+        }
+    }
+    Some(clean.into_owned())
+}
+
+fn is_safe_attribute(attr: &Attribute<'_>) -> bool {
+    let key = String::from_utf8_lossy(attr.key.as_ref()).to_lowercase();
+    let local_name = String::from_utf8_lossy(attr.key.local_name().as_ref()).to_lowercase();
+    let value = match attr.unescape_value() {
+        Ok(value) => value.trim().to_lowercase(),
+        Err(_) => return false,
+    };
+    if local_name.starts_with("on") || value.contains("javascript:") {
+        return false;
+    }
+    if local_name == "href" {
+        return value.starts_with('#')
+            || (value.starts_with("data:image/") && !value.starts_with("data:image/svg"));
+    }
+    if key == "style" {
+        return is_safe_css(&value);
+    }
+    // Presentation attributes like `fill` are CSS values too, and can point to other files
+    is_safe_css(&value)
+}
+
+/// Functions that load an image from a string, without `url(`.
+const LOADING_FUNCTIONS: &[&str] = &["image(", "image-set(", "src(", "cross-fade("];
+
+/// Checks that CSS doesn't load anything, except parts of the same image.
+fn is_safe_css(css: &str) -> bool {
+    let css = css.to_lowercase();
+    // Escapes can hide any name (`\75rl(` is `url(`), so they are never accepted
+    if css.contains('\\') || css.contains("@import") || css.contains("expression(") {
+        return false;
+    }
+    if LOADING_FUNCTIONS.iter().any(|f| css.contains(f)) {
+        return false;
+    }
+    css.match_indices("url(").all(|(i, _)| {
+        css[i + 4..]
+            .trim_start_matches(|c: char| c.is_whitespace() || c == '"' || c == '\'')
+            .starts_with('#')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clean(svg: &str) -> String {
+        String::from_utf8(sanitize(svg.as_bytes()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn keep_drawings() {
+        let svg = r##"<?xml version="1.0"?><svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 10 10"><defs><linearGradient id="g"><stop offset="0" stop-color="red"/></linearGradient></defs><rect width="10" height="10" fill="url(#g)"/><use href="#g"/></svg>"##;
+        assert_eq!(clean(svg), svg);
+    }
+
+    #[test]
+    fn remove_scripts() {
+        let cleaned = clean(
+            r#"<!DOCTYPE svg [<!ENTITY a "b">]><svg onload="alert(1)"><script>alert(2)</script><a href="javascript:alert(3)"><circle r="2"/></a><foreignObject><div><p>Hi</p></div></foreignObject><rect/></svg>"#,
+        );
+        assert_eq!(cleaned, "<svg><rect/></svg>");
+    }
+
+    #[test]
+    fn remove_external_references() {
+        let cleaned = clean(
+            r#"<svg><image href="https://example.com/track.png"/><image xlink:href="data:image/png;base64,AAAA"/><rect fill="url(https://example.com/a.svg#p)" style="fill: url(#ok)"/><style>@import url(https://example.com/a.css);</style><style>rect { fill: url(#g) }</style></svg>"#,
+        );
+        assert_eq!(
+            cleaned,
+            r#"<svg><image/><image xlink:href="data:image/png;base64,AAAA"/><rect style="fill: url(#ok)"/><style></style><style>rect { fill: url(#g) }</style></svg>"#
+        );
+    }
+
+    #[test]
+    fn remove_hidden_references() {
+        let cleaned = clean(
+            r#"<svg><rect style="fill: \75rl(https://example.com/a.svg)"/><rect style="fill: -webkit-image-set('https://example.com/a.png' 1x)"/><rect fill="\75 rl(https://example.com/a.svg)"/><style>rect { background: image-set("https://example.com/a.png" 1x) }</style><rect fill="red"/></svg>"#,
+        );
+        assert_eq!(
+            cleaned,
+            r#"<svg><rect/><rect/><rect/><style></style><rect fill="red"/></svg>"#
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(sanitize(b"<svg><rect></svg>").is_err());
+    }
+}
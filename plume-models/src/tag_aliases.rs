@@ -0,0 +1,167 @@
+//! Synonyms of tags.
+//!
+//! An alias is a tag name that stands for another one, like "rustlang" for "rust".
+//! Tags are always saved with the name they are an alias of, so tag pages, searches
+//! and federated hashtags only know one of them.
+
+use crate::{
+    posts::Post,
+    schema::{tag_aliases, tags},
+    tags::Tag,
+    Connection, Error, Result,
+};
+use chrono::NaiveDateTime;
+use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
+
+#[derive(Clone, Queryable, Identifiable)]
+#[table_name = "tag_aliases"]
+pub struct TagAlias {
+    pub id: i32,
+    pub alias: String,
+    pub tag: String,
+    pub creation_date: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "tag_aliases"]
+pub struct NewTagAlias {
+    pub alias: String,
+    pub tag: String,
+}
+
+impl TagAlias {
+    insert!(tag_aliases, NewTagAlias);
+    get!(tag_aliases);
+    find_by!(tag_aliases, find_by_alias, alias as &str);
+    list_by!(tag_aliases, list_for_tag, tag as &str);
+
+    pub fn list(conn: &Connection) -> Result<Vec<Self>> {
+        tag_aliases::table
+            .order((tag_aliases::tag.asc(), tag_aliases::alias.asc()))
+            .load::<Self>(conn)
+            .map_err(Error::from)
+    }
+
+    /// The name under which a tag is saved: the tag it is an alias of, or itself.
+    pub fn canonical(conn: &Connection, name: &str) -> String {
+        Self::find_by_alias(conn, name)
+            .map(|a| a.tag)
+            .unwrap_or_else(|_| name.to_owned())
+    }
+
+    /// Declares `alias` as a synonym of `tag`.
+    ///
+    /// If `tag` is itself an alias, the new one points to the same tag. Aliases of
+    /// `alias` are moved to `tag` too, so that merged tags don't form chains.
+    pub fn create(conn: &Connection, alias: &str, tag: &str) -> Result<Self> {
+        let alias = alias.trim().trim_start_matches('#');
+        let tag = Self::canonical(conn, tag.trim().trim_start_matches('#'));
+        if alias.is_empty() || tag.is_empty() || alias == tag {
+            return Err(Error::InvalidValue);
+        }
+        if Self::find_by_alias(conn, alias).is_ok() {
+            return Err(Error::InvalidValue);
+        }
+
+        diesel::update(tag_aliases::table.filter(tag_aliases::tag.eq(alias)))
+            .set(tag_aliases::tag.eq(&tag))
+            .execute(conn)?;
+        Self::insert(
+            conn,
+            NewTagAlias {
+                alias: alias.to_owned(),
+                tag,
+            },
+        )
+    }
+
+    /// Renames the existing tags with this alias to the tag they stand for.
+    ///
+    /// Returns the number of tags that were renamed or merged with an existing one.
+    pub fn rewrite_tags(&self, conn: &Connection) -> Result<usize> {
+        let aliased = tags::table
+            .filter(tags::tag.eq(&self.alias))
+            .load::<Tag>(conn)?;
+        for tag in &aliased {
+            let already_tagged = tags::table
+                .filter(tags::tag.eq(&self.tag))
+                .filter(tags::post_id.eq(tag.post_id))
+                .filter(tags::is_hashtag.eq(tag.is_hashtag))
+                .count()
+                .get_result::<i64>(conn)?
+                > 0;
+            if already_tagged {
+                tag.delete(conn)?;
+            } else {
+                diesel::update(tag)
+                    .set(tags::tag.eq(&self.tag))
+                    .execute(conn)?;
+            }
+            // Saving the article again updates its search index entry
+            Post::get(conn, tag.post_id)?.update(conn)?;
+        }
+        Ok(aliased.len())
+    }
+
+    /// Renames the existing tags for all the aliases.
+    pub fn rewrite_all_tags(conn: &Connection) -> Result<usize> {
+        Self::list(conn)?.iter().map(|a| a.rewrite_tags(conn)).sum()
+    }
+
+    pub fn delete(&self, conn: &Connection) -> Result<()> {
+        diesel::delete(self)
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{inbox::tests::fill_database, tags::NewTag, tests::db};
+    use diesel::Connection;
+
+    #[test]
+    fn merge_tags() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, _, _) = fill_database(conn);
+            for (tag, is_hashtag) in &[("rustlang", false), ("rust", false), ("rustlang", true)] {
+                Tag::insert(
+                    conn,
+                    NewTag {
+                        tag: (*tag).to_owned(),
+                        is_hashtag: *is_hashtag,
+                        post_id: posts[0].id,
+                    },
+                )?;
+            }
+
+            let alias = TagAlias::create(conn, "#rustlang", "rust")?;
+            assert!(TagAlias::create(conn, "rust", "rustlang").is_err());
+            // Aliases of an alias point to the final tag
+            assert_eq!(TagAlias::create(conn, "rs", "rustlang")?.tag, "rust");
+            assert_eq!(TagAlias::canonical(conn, "rs"), "rust");
+            assert_eq!(TagAlias::canonical(conn, "go"), "go");
+
+            assert_eq!(alias.rewrite_tags(conn)?, 2);
+            let tags = Tag::for_post(conn, posts[0].id)?;
+            assert_eq!(tags.len(), 2);
+            assert!(tags.iter().all(|t| t.tag == "rust"));
+
+            // New tags are saved with the canonical name
+            let tag = Tag::insert(
+                conn,
+                NewTag {
+                    tag: "rs".to_owned(),
+                    is_hashtag: true,
+                    post_id: posts[0].id,
+                },
+            )?;
+            assert_eq!(tag.tag, "rust");
+            assert_eq!(Tag::for_post(conn, posts[0].id)?.len(), 2);
+            Ok(())
+        });
+    }
+}
@@ -1,4 +1,6 @@
-use crate::{ap_url, instance::Instance, schema::tags, Connection, Error, Result};
+use crate::{
+    ap_url, instance::Instance, schema::tags, tag_aliases::TagAlias, Connection, Error, Result,
+};
 use activitystreams::iri_string::types::IriString;
 use diesel::{self, ExpressionMethods, QueryDsl, RunQueryDsl};
 use plume_common::activity_pub::{Hashtag, HashtagExt};
@@ -20,7 +22,30 @@ pub struct NewTag {
 }
 
 impl Tag {
-    insert!(tags, NewTag);
+    insert!(tags, NewTag, |inserted, conn| {
+        let canonical = TagAlias::canonical(conn, &inserted.tag);
+        if canonical == inserted.tag {
+            return Ok(inserted);
+        }
+        let existing = tags::table
+            .filter(tags::tag.eq(&canonical))
+            .filter(tags::post_id.eq(inserted.post_id))
+            .filter(tags::is_hashtag.eq(inserted.is_hashtag))
+            .first::<Tag>(conn);
+        match existing {
+            Ok(existing) => {
+                inserted.delete(conn)?;
+                Ok(existing)
+            }
+            Err(_) => {
+                diesel::update(&inserted)
+                    .set(tags::tag.eq(&canonical))
+                    .execute(conn)?;
+                inserted.tag = canonical;
+                Ok(inserted)
+            }
+        }
+    });
     get!(tags);
     find_by!(tags, find_by_name, tag as &str);
     list_by!(tags, for_post, post_id as i32);
@@ -0,0 +1,271 @@
+use crate::{
+    comments::Comment,
+    instance::Instance,
+    mutes::Mutes,
+    notification_policies::{NotificationPolicy, PolicyAction},
+    notifications::*,
+    posts::Post,
+    schema::{comments, thread_subscriptions, users},
+    users::User,
+    Connection, Error, Result,
+};
+use chrono::NaiveDateTime;
+use diesel::{
+    self, BoolExpressionMethods, ExpressionMethods, JoinOnDsl, QueryDsl, RunQueryDsl,
+    SaveChangesDsl,
+};
+
+/// A user following the comments of a post.
+#[derive(Clone, Queryable, Identifiable, AsChangeset)]
+pub struct ThreadSubscription {
+    pub id: i32,
+    pub user_id: i32,
+    pub post_id: i32,
+    pub creation_date: NaiveDateTime,
+    /// The last comment the subscriber was emailed about
+    pub last_emailed_comment_id: Option<i32>,
+}
+
+#[derive(Insertable)]
+#[table_name = "thread_subscriptions"]
+pub struct NewThreadSubscription {
+    pub user_id: i32,
+    pub post_id: i32,
+    pub last_emailed_comment_id: Option<i32>,
+}
+
+/// New comments in a thread, to be sent by email to one of its subscribers.
+pub struct ThreadDigest {
+    pub subscription: ThreadSubscription,
+    pub user: User,
+    pub post: Post,
+    pub comments: Vec<Comment>,
+}
+
+impl ThreadSubscription {
+    insert!(thread_subscriptions, NewThreadSubscription);
+    get!(thread_subscriptions);
+    find_by!(
+        thread_subscriptions,
+        find_for_user_on_post,
+        user_id as i32,
+        post_id as i32
+    );
+    list_by!(thread_subscriptions, list_for_post, post_id as i32);
+
+    /// Subscribes `user` to the comments of `post`, if not already subscribed.
+    ///
+    /// Comments that were already published are not emailed.
+    pub fn subscribe(conn: &Connection, user: &User, post: &Post) -> Result<Self> {
+        if let Ok(subscription) = Self::find_for_user_on_post(conn, user.id, post.id) {
+            return Ok(subscription);
+        }
+        let last_comment = comments::table
+            .filter(comments::post_id.eq(post.id))
+            .select(comments::id)
+            .order(comments::id.desc())
+            .first::<i32>(conn)
+            .ok();
+        Self::insert(
+            conn,
+            NewThreadSubscription {
+                user_id: user.id,
+                post_id: post.id,
+                last_emailed_comment_id: last_comment,
+            },
+        )
+    }
+
+    pub fn unsubscribe(conn: &Connection, user: &User, post: &Post) -> Result<()> {
+        diesel::delete(
+            thread_subscriptions::table
+                .filter(thread_subscriptions::user_id.eq(user.id))
+                .filter(thread_subscriptions::post_id.eq(post.id)),
+        )
+        .execute(conn)
+        .map(|_| ())
+        .map_err(Error::from)
+    }
+
+    pub fn is_subscribed(conn: &Connection, user: &User, post: &Post) -> bool {
+        Self::find_for_user_on_post(conn, user.id, post.id).is_ok()
+    }
+
+    /// Notifies local subscribers of the thread that `comment` was posted.
+    ///
+    /// Users in `already_notified` (the authors of the post or the mentioned
+    /// users for instance) are skipped, as well as the author of the comment.
+    pub fn notify(conn: &Connection, comment: &Comment, already_notified: &[i32]) -> Result<()> {
+        for subscription in Self::list_for_post(conn, comment.post_id)? {
+            if subscription.user_id == comment.author_id
+                || already_notified.contains(&subscription.user_id)
+            {
+                continue;
+            }
+            let user = User::get(conn, subscription.user_id)?;
+            if !user.is_local() || !comment.can_see(conn, Some(&user)) {
+                continue;
+            }
+            Notification::notify_from(
+                conn,
+                &comment.get_author(conn)?,
+                comment.content.get(),
+                NewNotification {
+                    kind: notification_kind::THREAD_COMMENT.to_string(),
+                    object_id: comment.id,
+                    user_id: user.id,
+                    group_key: None,
+                    filtered: false,
+                },
+                None,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Lists the comments that have not been emailed yet to each subscriber.
+    ///
+    /// Subscribers without email address are ignored.
+    pub fn pending_digests(conn: &Connection) -> Result<Vec<ThreadDigest>> {
+        // Only the subscriptions with new comments are loaded
+        let subscriptions = thread_subscriptions::table
+            .inner_join(users::table.on(thread_subscriptions::user_id.eq(users::id)))
+            .inner_join(comments::table.on(comments::post_id.eq(thread_subscriptions::post_id)))
+            .filter(users::email.is_not_null())
+            .filter(users::instance_id.eq(Instance::get_local()?.id))
+            .filter(comments::author_id.ne(thread_subscriptions::user_id))
+            .filter(
+                thread_subscriptions::last_emailed_comment_id
+                    .is_null()
+                    .or(comments::id
+                        .nullable()
+                        .gt(thread_subscriptions::last_emailed_comment_id)),
+            )
+            .select(thread_subscriptions::all_columns)
+            .distinct()
+            .load::<ThreadSubscription>(conn)?;
+
+        let mut digests = vec![];
+        for mut subscription in subscriptions {
+            let user = User::get(conn, subscription.user_id)?;
+            // Only what would be notified is emailed, filtered comments being left for
+            // the subscriber to review
+            let mutes = Mutes::for_user(conn, user.id)?;
+            let policy = NotificationPolicy::for_user(conn, user.id)?;
+            let new_comments = comments::table
+                .filter(comments::post_id.eq(subscription.post_id))
+                .filter(comments::id.gt(subscription.last_emailed_comment_id.unwrap_or(0)))
+                .filter(comments::author_id.ne(user.id))
+                .order(comments::id.asc())
+                .load::<Comment>(conn)?;
+            let last_comment_id = new_comments.iter().map(|c| c.id).max();
+            let mut comments = vec![];
+            for comment in new_comments {
+                if !comment.can_see(conn, Some(&user)) || mutes.hides_text(comment.content.get()) {
+                    continue;
+                }
+                let author = comment.get_author(conn)?;
+                if !mutes.hides_account(conn, &author)?
+                    && policy.action_for(conn, &author)? == PolicyAction::Accept
+                {
+                    comments.push(comment);
+                }
+            }
+            if comments.is_empty() {
+                // Nothing to send: these comments don't need to be looked at again
+                subscription.last_emailed_comment_id = last_comment_id;
+                let _: ThreadSubscription = subscription.save_changes(conn)?;
+                continue;
+            }
+            digests.push(ThreadDigest {
+                post: Post::get(conn, subscription.post_id)?,
+                subscription,
+                user,
+                comments,
+            });
+        }
+        Ok(digests)
+    }
+
+    /// Remembers that the comments of `digest` have been emailed.
+    pub fn mark_emailed(conn: &Connection, digest: ThreadDigest) -> Result<()> {
+        let mut subscription = digest.subscription;
+        subscription.last_emailed_comment_id = digest.comments.iter().map(|c| c.id).max();
+        let _: ThreadSubscription = subscription.save_changes(conn)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        comments::NewComment, inbox::tests::fill_database, safe_string::SafeString, tests::db,
+    };
+    use diesel::Connection;
+
+    #[test]
+    fn notify_and_digest() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, users, _) = fill_database(conn);
+            let subscription = ThreadSubscription::subscribe(conn, &users[1], &posts[0])?;
+            assert!(ThreadSubscription::is_subscribed(
+                conn, &users[1], &posts[0]
+            ));
+            assert_eq!(
+                ThreadSubscription::subscribe(conn, &users[1], &posts[0])?.id,
+                subscription.id
+            );
+
+            let comment = Comment::insert(
+                conn,
+                NewComment {
+                    content: SafeString::new("New comment"),
+                    post_id: posts[0].id,
+                    author_id: users[0].id,
+                    public_visibility: true,
+                    ..NewComment::default()
+                },
+            )?;
+            ThreadSubscription::notify(conn, &comment, &[])?;
+            assert!(Notification::find_for_user(conn, &users[1])?
+                .iter()
+                .any(|n| n.kind == notification_kind::THREAD_COMMENT && n.object_id == comment.id));
+
+            let digests = ThreadSubscription::pending_digests(conn)?
+                .into_iter()
+                .filter(|d| d.subscription.id == subscription.id)
+                .collect::<Vec<_>>();
+            assert_eq!(digests.len(), 1);
+            assert_eq!(digests[0].comments[0].id, comment.id);
+            for digest in digests {
+                ThreadSubscription::mark_emailed(conn, digest)?;
+            }
+            assert!(ThreadSubscription::pending_digests(conn)?
+                .iter()
+                .all(|d| d.subscription.id != subscription.id));
+
+            // Their own comments are not emailed to them
+            Comment::insert(
+                conn,
+                NewComment {
+                    content: SafeString::new("Reply"),
+                    post_id: posts[0].id,
+                    author_id: users[1].id,
+                    public_visibility: true,
+                    ..NewComment::default()
+                },
+            )?;
+            assert!(ThreadSubscription::pending_digests(conn)?
+                .iter()
+                .all(|d| d.subscription.id != subscription.id));
+
+            ThreadSubscription::unsubscribe(conn, &users[1], &posts[0])?;
+            assert!(!ThreadSubscription::is_subscribed(
+                conn, &users[1], &posts[0]
+            ));
+            Ok(())
+        });
+    }
+}
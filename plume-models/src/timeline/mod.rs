@@ -1,7 +1,10 @@
 use crate::{
+    cache::{self, Entry},
     lists::List,
+    mutes::Mutes,
     posts::Post,
     schema::{posts, timeline, timeline_definition},
+    users::User,
     Connection, Error, Result,
 };
 use diesel::{self, BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl};
@@ -183,15 +186,15 @@ impl Timeline {
 
     pub fn update(&self, conn: &Connection) -> Result<Self> {
         diesel::update(self).set(self).execute(conn)?;
+        cache::forget_timeline(self.id);
         let timeline = Self::get(conn, self.id)?;
         Ok(timeline)
     }
 
     pub fn delete(&self, conn: &Connection) -> Result<()> {
-        diesel::delete(self)
-            .execute(conn)
-            .map(|_| ())
-            .map_err(Error::from)
+        diesel::delete(self).execute(conn)?;
+        cache::forget_timeline(self.id);
+        Ok(())
     }
 
     pub fn get_latest(&self, conn: &Connection, count: i32) -> Result<Vec<Post>> {
@@ -199,9 +202,43 @@ impl Timeline {
     }
 
     pub fn get_page(&self, conn: &Connection, (min, max): (i32, i32)) -> Result<Vec<Post>> {
+        // Only the IDs are cached, so that edited posts are up to date
+        let ids = cache::get_or_insert(Entry::TimelinePage(self.id, (min, max)), || {
+            timeline::table
+                .filter(timeline::timeline_id.eq(self.id))
+                .inner_join(posts::table)
+                .order(posts::creation_date.desc())
+                .offset(min.into())
+                .limit((max - min).into())
+                .select(posts::id)
+                .load::<i32>(conn)
+                .map_err(Error::from)
+        })?;
+        let mut posts = posts::table
+            .filter(posts::id.eq_any(ids.clone()))
+            .load::<Post>(conn)?;
+        posts.sort_by_key(|post| ids.iter().position(|id| *id == post.id));
+        Ok(posts)
+    }
+
+    /// A page of this timeline as `viewer` sees it, without what they muted.
+    pub fn get_page_for(
+        &self,
+        conn: &Connection,
+        (min, max): (i32, i32),
+        viewer: Option<&User>,
+    ) -> Result<Vec<Post>> {
+        let mutes = match viewer {
+            Some(user) => Mutes::for_user(conn, user.id)?,
+            None => Mutes::default(),
+        };
+        if mutes.is_empty() {
+            return self.get_page(conn, (min, max));
+        }
         timeline::table
             .filter(timeline::timeline_id.eq(self.id))
             .inner_join(posts::table)
+            .filter(posts::id.ne_all(mutes.hidden_posts()))
             .order(posts::creation_date.desc())
             .offset(min.into())
             .limit((max - min).into())
@@ -211,9 +248,29 @@ impl Timeline {
     }
 
     pub fn count_posts(&self, conn: &Connection) -> Result<i64> {
+        cache::get_or_insert(Entry::TimelineCount(self.id), || {
+            timeline::table
+                .filter(timeline::timeline_id.eq(self.id))
+                .inner_join(posts::table)
+                .count()
+                .get_result(conn)
+                .map_err(Error::from)
+        })
+    }
+
+    /// The number of articles `viewer` sees in this timeline, without what they muted.
+    pub fn count_posts_for(&self, conn: &Connection, viewer: Option<&User>) -> Result<i64> {
+        let mutes = match viewer {
+            Some(user) => Mutes::for_user(conn, user.id)?,
+            None => Mutes::default(),
+        };
+        if mutes.is_empty() {
+            return self.count_posts(conn);
+        }
         timeline::table
             .filter(timeline::timeline_id.eq(self.id))
             .inner_join(posts::table)
+            .filter(posts::id.ne_all(mutes.hidden_posts()))
             .count()
             .get_result(conn)
             .map_err(Error::from)
@@ -223,15 +280,99 @@ impl Timeline {
         let timelines = timeline_definition::table
             .load::<Self>(conn.deref())
             .map_err(Error::from)?;
+        let silenced = post
+            .get_authors(conn)?
+            .into_iter()
+            .filter(|author| author.silenced)
+            .collect::<Vec<_>>();
+        // Cross-posted articles are only shown once: the first copy received, until the
+        // original replaces it
+        let duplicates = post.duplicates(conn)?;
+        let is_original = duplicates
+            .iter()
+            .any(|duplicate| duplicate.canonical_url.as_deref() == Some(&post.ap_url));
+        let duplicate_ids = duplicates.iter().map(|d| d.id).collect::<Vec<_>>();
+        let with_duplicates = timeline::table
+            .filter(timeline::post_id.eq_any(&duplicate_ids))
+            .select(timeline::timeline_id)
+            .distinct()
+            .load::<i32>(conn)?;
 
         for t in timelines {
+            // The posts of silenced users only reach the people following them
+            if !silenced.is_empty() {
+                let follows = match t.user_id {
+                    Some(user_id) => silenced
+                        .iter()
+                        .map(|author| author.is_followed_by(conn, user_id))
+                        .collect::<Result<Vec<_>>>()?
+                        .contains(&true),
+                    None => false,
+                };
+                if !follows {
+                    continue;
+                }
+            }
+            let has_duplicate = with_duplicates.contains(&t.id);
+            if has_duplicate && !is_original {
+                continue;
+            }
             if t.matches(conn, post, kind)? {
+                if has_duplicate {
+                    diesel::delete(
+                        timeline::table
+                            .filter(timeline::timeline_id.eq(t.id))
+                            .filter(timeline::post_id.eq_any(&duplicate_ids)),
+                    )
+                    .execute(conn)?;
+                }
                 t.add_post(conn, post)?;
             }
         }
         Ok(())
     }
 
+    /// Adds newly trending posts to the timelines that include them.
+    ///
+    /// Posts usually enter timelines when they are received, before they can trend.
+    pub fn add_to_trending_timelines(conn: &Connection, posts: &[Post]) -> Result<()> {
+        let timelines = timeline_definition::table
+            .load::<Self>(conn.deref())
+            .map_err(Error::from)?;
+
+        for t in timelines {
+            if !TimelineQuery::parse(&t.query)?.uses_trending() {
+                continue;
+            }
+            for post in posts {
+                if t.matches(conn, post, Kind::Original)? {
+                    t.add_post(conn, post)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the posts that stopped trending from the timelines that only included them
+    /// because they were trending.
+    pub fn remove_from_trending_timelines(conn: &Connection, posts: &[Post]) -> Result<()> {
+        let timelines = timeline_definition::table
+            .load::<Self>(conn.deref())
+            .map_err(Error::from)?;
+
+        for t in timelines {
+            if !TimelineQuery::parse(&t.query)?.uses_trending() {
+                continue;
+            }
+            for post in posts {
+                if !t.matches(conn, post, Kind::Original)? {
+                    t.remove_post(conn, post)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub fn add_post(&self, conn: &Connection, post: &Post) -> Result<()> {
         if self.includes_post(conn, post)? {
             return Ok(());
@@ -242,11 +383,12 @@ impl Timeline {
                 timeline_id: self.id,
             })
             .execute(conn)?;
+        cache::forget_timeline(self.id);
         Ok(())
     }
 
     pub fn remove_post(&self, conn: &Connection, post: &Post) -> Result<bool> {
-        if self.includes_post(conn, post)? {
+        if !self.includes_post(conn, post)? {
             return Ok(false);
         }
         diesel::delete(
@@ -255,12 +397,14 @@ impl Timeline {
                 .filter(timeline::post_id.eq(post.id)),
         )
         .execute(conn)?;
+        cache::forget_timeline(self.id);
         Ok(true)
     }
 
     pub fn remove_all_posts(&self, conn: &Connection) -> Result<u64> {
         let count = diesel::delete(timeline::table.filter(timeline::timeline_id.eq(self.id)))
             .execute(conn)?;
+        cache::forget_timeline(self.id);
         Ok(count as u64)
     }
 
@@ -445,6 +589,8 @@ mod tests {
                     subtitle: "".to_string(),
                     source: "you must say GNU/Linux, not Linux!!!".to_string(),
                     cover_id: None,
+                    excerpt: String::new(),
+                    canonical_url: None,
                 },
             )
             .unwrap();
@@ -464,6 +610,8 @@ mod tests {
                     subtitle: "".to_string(),
                     source: "so is Microsoft".to_string(),
                     cover_id: None,
+                    excerpt: String::new(),
+                    canonical_url: None,
                 },
             )
             .unwrap();
@@ -517,6 +665,8 @@ mod tests {
                     creation_date: None,
                     subtitle: "".to_string(),
                     cover_id: None,
+                    excerpt: String::new(),
+                    canonical_url: None,
                 },
             )
             .unwrap();
@@ -538,6 +688,8 @@ mod tests {
                     creation_date: None,
                     subtitle: "".to_string(),
                     cover_id: None,
+                    excerpt: String::new(),
+                    canonical_url: None,
                 },
             )
             .unwrap();
@@ -582,6 +734,8 @@ mod tests {
                     subtitle: "".to_string(),
                     source: "you must say GNU/Linux, not Linux!!!".to_string(),
                     cover_id: None,
+                    excerpt: String::new(),
+                    canonical_url: None,
                 },
             )
             .unwrap();
@@ -600,6 +754,8 @@ mod tests {
                     subtitle: "".to_string(),
                     source: "so is Microsoft".to_string(),
                     cover_id: None,
+                    excerpt: String::new(),
+                    canonical_url: None,
                 },
             )
             .unwrap();
@@ -618,6 +774,84 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_cross_posted_duplicates() {
+        let conn = &db();
+        conn.test_transaction::<_, (), _>(|| {
+            let (users, blogs) = blogTests::fill_database(conn);
+            let tl = Timeline::new_for_user(
+                conn,
+                users[0].id,
+                "Everything".to_owned(),
+                "all".to_owned(),
+            )
+            .unwrap();
+
+            let new_post = |slug: &str, canonical_url: Option<&str>, author: &User| {
+                let post = Post::insert(
+                    conn,
+                    NewPost {
+                        blog_id: blogs[0].id,
+                        slug: slug.to_string(),
+                        title: "Cross-posted".to_string(),
+                        content: SafeString::new("Also on my website"),
+                        published: true,
+                        license: "WTFPL".to_string(),
+                        ap_url: "".to_string(),
+                        creation_date: None,
+                        subtitle: "".to_string(),
+                        source: "Also on my website".to_string(),
+                        cover_id: None,
+                        excerpt: String::new(),
+                        canonical_url: canonical_url.map(str::to_owned),
+                    },
+                )
+                .unwrap();
+                PostAuthor::insert(
+                    conn,
+                    NewPostAuthor {
+                        post_id: post.id,
+                        author_id: author.id,
+                    },
+                )
+                .unwrap();
+                post
+            };
+
+            let original_url = format!("{}original", blogs[0].ap_url);
+            let copies = (0..2)
+                .map(|i| new_post(&format!("copy-{}", i), Some(&original_url), &users[0]))
+                .collect::<Vec<_>>();
+            // Someone else can't hide the original or its copies
+            let other = new_post("not-a-copy", Some(&original_url), &users[1]);
+            assert_eq!(copies[0].duplicates(conn).unwrap().len(), copies.len() - 1);
+
+            for post in copies.iter().chain(Some(&other)) {
+                Timeline::add_to_all_timelines(conn, post, Kind::Original).unwrap();
+            }
+            let ids = |tl: &Timeline| {
+                let mut ids = tl
+                    .get_latest(conn, 10)
+                    .unwrap()
+                    .into_iter()
+                    .map(|p| p.id)
+                    .collect::<Vec<_>>();
+                ids.sort_unstable();
+                ids
+            };
+            assert_eq!(ids(&tl), vec![copies[0].id, other.id]);
+
+            // The original replaces the copies
+            let mut original = new_post("original", None, &users[0]);
+            original.ap_url = original_url.clone();
+            original.update(conn).unwrap();
+            Timeline::add_to_all_timelines(conn, &original, Kind::Original).unwrap();
+            assert_eq!(ids(&tl), vec![other.id, original.id]);
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn test_matches_lists_direct() {
         let conn = &db();
@@ -638,6 +872,8 @@ mod tests {
                     subtitle: "".to_string(),
                     source: "you must say GNU/Linux, not Linux!!!".to_string(),
                     cover_id: None,
+                    excerpt: String::new(),
+                    canonical_url: None,
                 },
             )
             .unwrap();
@@ -778,6 +1014,8 @@ mod tests {
                     subtitle: "".to_string(),
                     source: "you must say GNU/Linux, not Linux!!!".to_string(),
                     cover_id: None,
+                    excerpt: String::new(),
+                    canonical_url: None,
                 },
             )
             .unwrap();
@@ -810,6 +1048,8 @@ mod tests {
                     subtitle: "Stallman is our god".to_string(),
                     source: "you must say GNU/Linux, not Linux!!!".to_string(),
                     cover_id: None,
+                    excerpt: String::new(),
+                    canonical_url: None,
                 },
             )
             .unwrap();
@@ -1,9 +1,14 @@
 use crate::{
     blogs::Blog,
+    categories::Category,
+    hashtag_follows::HashtagFollow,
     lists::{self, ListType},
+    neighborhood,
     posts::Post,
+    tag_aliases::TagAlias,
     tags::Tag,
     timeline::Timeline,
+    trends::TrendingPost,
     users::User,
     Connection, Result,
 };
@@ -181,12 +186,21 @@ impl<'a> TQ<'a> {
                     WithList::Author { .. } => ListType::User,
                     WithList::License => ListType::Word,
                     WithList::Tags => ListType::Word,
+                    WithList::Category => ListType::Word,
                     WithList::Lang => ListType::Prefix,
                 },
             )],
             TQ::Arg(_, _) => vec![],
         }
     }
+
+    fn uses_trending(&self) -> bool {
+        match self {
+            TQ::Or(inner) | TQ::And(inner) => inner.iter().any(TQ::uses_trending),
+            TQ::Arg(Arg::Boolean(Bool::Trending), _) => true,
+            TQ::Arg(_, _) => false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -218,6 +232,7 @@ enum WithList {
     Author { boosts: bool, likes: bool },
     License,
     Tags,
+    Category,
     Lang,
 }
 
@@ -258,10 +273,18 @@ impl WithList {
                     (WithList::License, ListType::Word) => list.contains_word(conn, &post.license),
                     (WithList::Tags, ListType::Word) => {
                         let tags = Tag::for_post(conn, post.id)?;
-                        Ok(list
-                            .list_words(conn)?
-                            .iter()
-                            .any(|s| tags.iter().any(|t| s == &t.tag)))
+                        Ok(list.list_words(conn)?.iter().any(|s| {
+                            let s = TagAlias::canonical(conn, s);
+                            tags.iter().any(|t| s == t.tag)
+                        }))
+                    }
+                    (WithList::Category, ListType::Word) => {
+                        let words = list.list_words(conn)?;
+                        Category::post_is_in(
+                            conn,
+                            post.id,
+                            &words.iter().map(String::as_str).collect::<Vec<_>>(),
+                        )
                     }
                     (WithList::Lang, ListType::Prefix) => {
                         let lang = whatlang::detect(post.content.get())
@@ -311,8 +334,12 @@ impl WithList {
                 WithList::License => Ok(list.iter().any(|s| s == &post.license)),
                 WithList::Tags => {
                     let tags = Tag::for_post(conn, post.id)?;
-                    Ok(list.iter().any(|s| tags.iter().any(|t| s == &t.tag)))
+                    Ok(list.iter().any(|s| {
+                        let s = TagAlias::canonical(conn, s);
+                        tags.iter().any(|t| s == t.tag)
+                    }))
                 }
+                WithList::Category => Category::post_is_in(conn, post.id, list),
                 WithList::Lang => {
                     let lang = whatlang::detect(post.content.get())
                         .and_then(|i| {
@@ -351,9 +378,15 @@ impl WithContains {
 
 #[derive(Debug, Clone, PartialEq)]
 enum Bool {
-    Followed { boosts: bool, likes: bool },
+    Followed {
+        boosts: bool,
+        likes: bool,
+    },
     HasCover,
     Local,
+    /// Published on an instance this one follows
+    Neighborhood,
+    Trending,
     All,
 }
 
@@ -372,10 +405,11 @@ impl Bool {
                 }
                 let user = timeline.user_id.unwrap();
                 match kind {
-                    Kind::Original => post
+                    Kind::Original => Ok(post
                         .get_authors(conn)?
                         .iter()
-                        .try_fold(false, |s, a| a.is_followed_by(conn, user).map(|r| s || r)),
+                        .try_fold(false, |s, a| a.is_followed_by(conn, user).map(|r| s || r))?
+                        || HashtagFollow::follows_tag_of(conn, user, post)?),
                     Kind::Reshare(u) => {
                         if *boosts {
                             u.is_followed_by(conn, user)
@@ -394,6 +428,11 @@ impl Bool {
             }
             Bool::HasCover => Ok(post.cover_id.is_some()),
             Bool::Local => Ok(post.get_blog(conn)?.is_local() && kind == Kind::Original),
+            Bool::Neighborhood => Ok(kind == Kind::Original
+                && neighborhood::instance_ids(conn)?.contains(&post.get_blog(conn)?.instance_id)),
+            Bool::Trending => {
+                Ok(kind == Kind::Original && TrendingPost::is_trending(conn, post.id)?)
+            }
             Bool::All => Ok(kind == Kind::Original),
         }
     }
@@ -482,7 +521,16 @@ fn parse_d<'a, 'b>(mut stream: &'b [Token<'a>]) -> QueryResult<(&'b [Token<'a>],
         .map(Token::get_text)
         .ok_or(QueryError::UnexpectedEndOfQuery)?
     {
-        s @ "blog" | s @ "author" | s @ "license" | s @ "tags" | s @ "lang" => {
+        "category" if matches!(stream.get(1), Some(Token::LParent(_))) => {
+            let (left, list) = parse_m(&stream[2..])?;
+            match left.get(0).ok_or(QueryError::UnexpectedEndOfQuery)? {
+                Token::RParent(_) => {
+                    Ok((&left[1..], Arg::In(WithList::Category, List::Array(list))))
+                }
+                t => t.get_error(Token::Word(0, 0, "one of ')' or ','")),
+            }
+        }
+        s @ "blog" | s @ "author" | s @ "license" | s @ "tags" | s @ "category" | s @ "lang" => {
             match stream.get(1).ok_or(QueryError::UnexpectedEndOfQuery)? {
                 Token::Word(_, _, r#in) if r#in == &"in" => {
                     let (mut left, list) = parse_l(&stream[2..])?;
@@ -523,6 +571,7 @@ fn parse_d<'a, 'b>(mut stream: &'b [Token<'a>]) -> QueryResult<(&'b [Token<'a>],
                         }
                         "license" => WithList::License,
                         "tags" => WithList::Tags,
+                        "category" => WithList::Category,
                         "lang" => WithList::Lang,
                         _ => unreachable!(),
                     };
@@ -552,50 +601,60 @@ fn parse_d<'a, 'b>(mut stream: &'b [Token<'a>]) -> QueryResult<(&'b [Token<'a>],
             }
             (t, _) => t.get_error(Token::Word(0, 0, "'contains'")),
         },
-        s @ "followed" | s @ "has_cover" | s @ "local" | s @ "all" => match s {
-            "followed" => {
-                let mut boosts = true;
-                let mut likes = false;
-                while let Some(Token::Word(s, e, clude)) = stream.get(1) {
-                    if *clude != "include" && *clude != "exclude" {
-                        break;
-                    }
-                    match (
-                        *clude,
-                        stream
-                            .get(2)
-                            .map(Token::get_text)
-                            .ok_or(QueryError::UnexpectedEndOfQuery)?,
-                    ) {
-                        ("include", "reshares") | ("include", "reshare") => boosts = true,
-                        ("exclude", "reshares") | ("exclude", "reshare") => boosts = false,
-                        ("include", "likes") | ("include", "like") => likes = true,
-                        ("exclude", "likes") | ("exclude", "like") => likes = false,
-                        (_, w) => {
-                            return Token::Word(*s, *e, w).get_error(Token::Word(
-                                0,
-                                0,
-                                "one of 'likes' or 'boosts'",
-                            ))
+        "trending" => match (stream.get(1), stream.get(2)) {
+            (Some(Token::LParent(_)), Some(Token::RParent(_))) => {
+                Ok((&stream[3..], Arg::Boolean(Bool::Trending)))
+            }
+            _ => Ok((&stream[1..], Arg::Boolean(Bool::Trending))),
+        },
+        s @ "followed" | s @ "has_cover" | s @ "local" | s @ "neighborhood" | s @ "all" => {
+            match s {
+                "followed" => {
+                    let mut boosts = true;
+                    let mut likes = false;
+                    while let Some(Token::Word(s, e, clude)) = stream.get(1) {
+                        if *clude != "include" && *clude != "exclude" {
+                            break;
                         }
+                        match (
+                            *clude,
+                            stream
+                                .get(2)
+                                .map(Token::get_text)
+                                .ok_or(QueryError::UnexpectedEndOfQuery)?,
+                        ) {
+                            ("include", "reshares") | ("include", "reshare") => boosts = true,
+                            ("exclude", "reshares") | ("exclude", "reshare") => boosts = false,
+                            ("include", "likes") | ("include", "like") => likes = true,
+                            ("exclude", "likes") | ("exclude", "like") => likes = false,
+                            (_, w) => {
+                                return Token::Word(*s, *e, w).get_error(Token::Word(
+                                    0,
+                                    0,
+                                    "one of 'likes' or 'boosts'",
+                                ))
+                            }
+                        }
+                        stream = &stream[2..];
                     }
-                    stream = &stream[2..];
+                    Ok((&stream[1..], Arg::Boolean(Bool::Followed { boosts, likes })))
                 }
-                Ok((&stream[1..], Arg::Boolean(Bool::Followed { boosts, likes })))
+                "has_cover" => Ok((&stream[1..], Arg::Boolean(Bool::HasCover))),
+                "local" => Ok((&stream[1..], Arg::Boolean(Bool::Local))),
+                "neighborhood" => Ok((&stream[1..], Arg::Boolean(Bool::Neighborhood))),
+                "all" => Ok((&stream[1..], Arg::Boolean(Bool::All))),
+                _ => unreachable!(),
             }
-            "has_cover" => Ok((&stream[1..], Arg::Boolean(Bool::HasCover))),
-            "local" => Ok((&stream[1..], Arg::Boolean(Bool::Local))),
-            "all" => Ok((&stream[1..], Arg::Boolean(Bool::All))),
-            _ => unreachable!(),
-        },
+        }
         _ => stream
             .get(0)
             .ok_or(QueryError::UnexpectedEndOfQuery)?
             .get_error(Token::Word(
                 0,
                 0,
-                "one of 'blog', 'author', 'license', 'tags', 'lang', \
-             'title', 'subtitle', 'content', 'followed', 'has_cover', 'local' or 'all'",
+                "one of 'blog', 'author', 'license', 'tags', 'category', 'lang', \
+             'title', 'subtitle', 'content', 'followed', 'has_cover', 'local', 'neighborhood', \
+             'trending' or 'all'",
             )),
     }
 }
@@ -664,6 +723,11 @@ impl<'a> TimelineQuery<'a> {
     pub fn list_used_lists(&self) -> Vec<(String, ListType)> {
         self.0.list_used_lists()
     }
+
+    /// Tells if the query depends on the trending posts, that change over time.
+    pub fn uses_trending(&self) -> bool {
+        self.0.uses_trending()
+    }
 }
 
 #[cfg(test)]
@@ -741,6 +805,33 @@ mod tests {
             ])
         );
 
+        let categories =
+            TimelineQuery::parse(r#"category(rust, "web dev") or category in f"#).unwrap();
+        assert_eq!(
+            categories.0,
+            TQ::Or(vec![
+                TQ::Arg(
+                    Arg::In(WithList::Category, List::Array(vec!["rust", "web dev"])),
+                    false
+                ),
+                TQ::Arg(Arg::In(WithList::Category, List::List("f"),), false),
+            ])
+        );
+
+        let trending = TimelineQuery::parse(r#"trending() and (trending or local)"#).unwrap();
+        assert!(trending.uses_trending());
+        assert_eq!(
+            trending.0,
+            TQ::And(vec![
+                TQ::Arg(Arg::Boolean(Bool::Trending), false),
+                TQ::Or(vec![
+                    TQ::Arg(Arg::Boolean(Bool::Trending), false),
+                    TQ::Arg(Arg::Boolean(Bool::Local), false),
+                ]),
+            ])
+        );
+        assert!(!categories.uses_trending());
+
         let contains = TimelineQuery::parse(
             r#"title contains a or subtitle contains b or content contains c"#,
         )
@@ -755,7 +846,7 @@ mod tests {
         );
 
         let booleans = TimelineQuery::parse(
-            r#"followed include like exclude reshares and has_cover and local and all"#,
+            r#"followed include like exclude reshares and has_cover and local and neighborhood and all"#,
         )
         .unwrap();
         assert_eq!(
@@ -770,6 +861,7 @@ mod tests {
                 ),
                 TQ::Arg(Arg::Boolean(Bool::HasCover), false),
                 TQ::Arg(Arg::Boolean(Bool::Local), false),
+                TQ::Arg(Arg::Boolean(Bool::Neighborhood), false),
                 TQ::Arg(Arg::Boolean(Bool::All), false),
             ])
         );
@@ -829,8 +921,8 @@ mod tests {
                 0,
                 11,
                 "Syntax Error: Expected one of 'blog', \
-'author', 'license', 'tags', 'lang', 'title', 'subtitle', 'content', 'followed', 'has_cover', \
-'local' or 'all', got 'not_a_field'"
+'author', 'license', 'tags', 'category', 'lang', 'title', 'subtitle', 'content', 'followed', 'has_cover', \
+'local', 'neighborhood', 'trending' or 'all', got 'not_a_field'"
                     .to_owned()
             )
         );
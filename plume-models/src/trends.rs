@@ -0,0 +1,381 @@
+//! Trending tags and articles.
+//!
+//! Trends are computed periodically from the recent activity, each interaction
+//! weighting less as it gets older. To keep a few accounts from making anything
+//! trend, each account only counts once per article or tag, authors don't count for
+//! their own articles, and accounts from blocked instances are ignored.
+
+use crate::{
+    db_conn::write_transaction,
+    posts::Post,
+    schema::{
+        comments, instances, likes, post_authors, posts, reshares, tags, trending_posts,
+        trending_tags, users,
+    },
+    timeline::{Kind, Timeline},
+    Connection, Error, Result,
+};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{self, ExpressionMethods, JoinOnDsl, QueryDsl, RunQueryDsl};
+use std::collections::{HashMap, HashSet};
+
+/// How far in the past activity is taken into account.
+const WINDOW_DAYS: i64 = 7;
+
+/// Time after which an interaction weights half as much.
+const HALF_LIFE_HOURS: f32 = 24.0;
+
+/// How many different accounts are needed for something to trend.
+const MIN_ACCOUNTS: usize = 3;
+
+/// How many tags and articles are kept.
+pub const MAX_TRENDS: usize = 20;
+
+/// How many trending articles can come from the same blog.
+const MAX_POSTS_PER_BLOG: usize = 2;
+
+const LIKE_WEIGHT: f32 = 1.0;
+const RESHARE_WEIGHT: f32 = 2.0;
+const COMMENT_WEIGHT: f32 = 1.5;
+
+#[derive(Clone, Queryable, Identifiable)]
+pub struct TrendingTag {
+    pub id: i32,
+    pub tag: String,
+    pub score: f32,
+    pub accounts: i32,
+    pub computed_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "trending_tags"]
+pub struct NewTrendingTag {
+    pub tag: String,
+    pub score: f32,
+    pub accounts: i32,
+}
+
+impl TrendingTag {
+    insert!(trending_tags, NewTrendingTag);
+
+    pub fn list(conn: &Connection, limit: i64) -> Result<Vec<Self>> {
+        trending_tags::table
+            .order(trending_tags::score.desc())
+            .limit(limit)
+            .load::<Self>(conn)
+            .map_err(Error::from)
+    }
+}
+
+#[derive(Clone, Queryable, Identifiable)]
+pub struct TrendingPost {
+    pub id: i32,
+    pub post_id: i32,
+    pub score: f32,
+    pub accounts: i32,
+    pub computed_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "trending_posts"]
+pub struct NewTrendingPost {
+    pub post_id: i32,
+    pub score: f32,
+    pub accounts: i32,
+}
+
+impl TrendingPost {
+    insert!(trending_posts, NewTrendingPost);
+
+    /// Returns the trending articles with their score, best first.
+    pub fn list(conn: &Connection, limit: i64) -> Result<Vec<(Post, Self)>> {
+        posts::table
+            .inner_join(trending_posts::table.on(trending_posts::post_id.eq(posts::id)))
+            .filter(posts::published.eq(true))
+            .order(trending_posts::score.desc())
+            .limit(limit)
+            .load::<(Post, Self)>(conn)
+            .map_err(Error::from)
+    }
+
+    pub fn is_trending(conn: &Connection, post_id: i32) -> Result<bool> {
+        diesel::dsl::select(diesel::dsl::exists(
+            trending_posts::table.filter(trending_posts::post_id.eq(post_id)),
+        ))
+        .get_result(conn)
+        .map_err(Error::from)
+    }
+}
+
+/// Computes the trends again, and adds the trending articles to the timelines
+/// using `trending`, or removes the ones that stopped trending.
+pub fn compute(conn: &Connection) -> Result<()> {
+    let now = Utc::now().naive_utc();
+    let since = now - Duration::days(WINDOW_DAYS);
+    let blocked = users::table
+        .inner_join(instances::table.on(users::instance_id.eq(instances::id)))
+        .filter(instances::blocked.eq(true))
+        .select(users::id)
+        .load::<i32>(conn)?
+        .into_iter()
+        .collect::<HashSet<_>>();
+
+    let posts = compute_posts(conn, now, since, &blocked)?;
+    let tags = compute_tags(conn, now, since, &blocked)?;
+
+    write_transaction(conn, || {
+        let previous = trending_posts::table
+            .select(trending_posts::post_id)
+            .load::<i32>(conn)?;
+        diesel::delete(trending_posts::table).execute(conn)?;
+        for (post, score, accounts) in &posts {
+            TrendingPost::insert(
+                conn,
+                NewTrendingPost {
+                    post_id: post.id,
+                    score: *score,
+                    accounts: *accounts as i32,
+                },
+            )?;
+        }
+
+        diesel::delete(trending_tags::table).execute(conn)?;
+        for (tag, score, accounts) in tags {
+            TrendingTag::insert(
+                conn,
+                NewTrendingTag {
+                    tag,
+                    score,
+                    accounts: accounts as i32,
+                },
+            )?;
+        }
+
+        let posts = posts.into_iter().map(|(p, _, _)| p).collect::<Vec<_>>();
+        Timeline::add_to_trending_timelines(conn, &posts)?;
+        let stopped = posts::table
+            .filter(posts::id.eq_any(previous))
+            .filter(posts::id.ne_all(posts.iter().map(|p| p.id).collect::<Vec<_>>()))
+            .load::<Post>(conn)?;
+        Timeline::remove_from_trending_timelines(conn, &stopped)
+    })
+}
+
+fn compute_posts(
+    conn: &Connection,
+    now: NaiveDateTime,
+    since: NaiveDateTime,
+    blocked: &HashSet<i32>,
+) -> Result<Vec<(Post, f32, usize)>> {
+    let mut interactions = Vec::new();
+    for (post, user, date) in likes::table
+        .filter(likes::creation_date.gt(since))
+        .select((likes::post_id, likes::user_id, likes::creation_date))
+        .load::<(i32, i32, NaiveDateTime)>(conn)?
+    {
+        interactions.push((post, user, date, LIKE_WEIGHT));
+    }
+    for (post, user, date) in reshares::table
+        .filter(reshares::creation_date.gt(since))
+        .select((
+            reshares::post_id,
+            reshares::user_id,
+            reshares::creation_date,
+        ))
+        .load::<(i32, i32, NaiveDateTime)>(conn)?
+    {
+        interactions.push((post, user, date, RESHARE_WEIGHT));
+    }
+    // Only the latest comment of each account counts
+    let mut commented: HashMap<(i32, i32), NaiveDateTime> = HashMap::new();
+    for (post, user, date) in comments::table
+        .filter(comments::creation_date.gt(since))
+        .filter(comments::public_visibility.eq(true))
+        .select((
+            comments::post_id,
+            comments::author_id,
+            comments::creation_date,
+        ))
+        .load::<(i32, i32, NaiveDateTime)>(conn)?
+    {
+        let latest = commented.entry((post, user)).or_insert(date);
+        if date > *latest {
+            *latest = date;
+        }
+    }
+    for ((post, user), date) in commented {
+        interactions.push((post, user, date, COMMENT_WEIGHT));
+    }
+
+    let post_ids = interactions
+        .iter()
+        .map(|(p, _, _, _)| *p)
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    let authors = post_authors::table
+        .filter(post_authors::post_id.eq_any(&post_ids))
+        .select((post_authors::post_id, post_authors::author_id))
+        .load::<(i32, i32)>(conn)?
+        .into_iter()
+        .collect::<HashSet<_>>();
+
+    let mut scores: HashMap<i32, (f32, HashSet<i32>)> = HashMap::new();
+    for (post, user, date, weight) in interactions {
+        if blocked.contains(&user) || authors.contains(&(post, user)) {
+            continue;
+        }
+        let entry = scores.entry(post).or_insert((0.0, HashSet::new()));
+        entry.0 += weight * decay(now, date);
+        entry.1.insert(user);
+    }
+
+    let mut ranked = scores
+        .into_iter()
+        .filter(|(_, (_, accounts))| accounts.len() >= MIN_ACCOUNTS)
+        .map(|(post, (score, accounts))| (post, score, accounts.len()))
+        .collect::<Vec<_>>();
+    ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+    let mut per_blog: HashMap<i32, usize> = HashMap::new();
+    let mut trending = Vec::new();
+    for (post, score, accounts) in ranked {
+        let post = match Post::get(conn, post) {
+            Ok(post) if post.published => post,
+            _ => continue,
+        };
+        let count = per_blog.entry(post.blog_id).or_insert(0);
+        if *count >= MAX_POSTS_PER_BLOG {
+            continue;
+        }
+        *count += 1;
+        trending.push((post, score, accounts));
+        if trending.len() >= MAX_TRENDS {
+            break;
+        }
+    }
+    Ok(trending)
+}
+
+/// Tags trend when they are used by many different authors: each author only
+/// counts once, for their latest article with this tag.
+fn compute_tags(
+    conn: &Connection,
+    now: NaiveDateTime,
+    since: NaiveDateTime,
+    blocked: &HashSet<i32>,
+) -> Result<Vec<(String, f32, usize)>> {
+    let mut uses: HashMap<String, HashMap<i32, NaiveDateTime>> = HashMap::new();
+    for (tag, author, date) in tags::table
+        .inner_join(posts::table.on(tags::post_id.eq(posts::id)))
+        .inner_join(post_authors::table.on(post_authors::post_id.eq(posts::id)))
+        .filter(posts::published.eq(true))
+        .filter(posts::creation_date.gt(since))
+        .select((tags::tag, post_authors::author_id, posts::creation_date))
+        .load::<(String, i32, NaiveDateTime)>(conn)?
+    {
+        if blocked.contains(&author) {
+            continue;
+        }
+        let latest = uses.entry(tag).or_default().entry(author).or_insert(date);
+        if date > *latest {
+            *latest = date;
+        }
+    }
+
+    let mut ranked = uses
+        .into_iter()
+        .filter(|(_, authors)| authors.len() >= MIN_ACCOUNTS)
+        .map(|(tag, authors)| {
+            let score = authors.values().map(|date| decay(now, *date)).sum();
+            (tag, score, authors.len())
+        })
+        .collect::<Vec<(String, f32, usize)>>();
+    ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    ranked.truncate(MAX_TRENDS);
+    Ok(ranked)
+}
+
+fn decay(now: NaiveDateTime, date: NaiveDateTime) -> f32 {
+    let hours = (now - date).num_minutes().max(0) as f32 / 60.0;
+    0.5f32.powf(hours / HALF_LIFE_HOURS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        inbox::tests::fill_database,
+        likes::{Like, NewLike},
+        tests::db,
+        users::{NewUser, Role},
+    };
+    use diesel::Connection;
+
+    #[test]
+    fn trending_posts() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let (posts, users, _) = fill_database(conn);
+            let timeline = Timeline::new_for_user(
+                conn,
+                users[1].id,
+                "Trending".to_owned(),
+                "trending()".to_owned(),
+            )?;
+
+            // Likes from the author don't count
+            for user in &users {
+                Like::insert(
+                    conn,
+                    NewLike {
+                        user_id: user.id,
+                        post_id: posts[0].id,
+                        ap_url: format!("{}/like/{}", posts[0].ap_url, user.id),
+                    },
+                )?;
+            }
+            compute(conn)?;
+            assert!(!TrendingPost::is_trending(conn, posts[0].id)?);
+            assert!(!timeline.matches(conn, &posts[0], Kind::Original)?);
+
+            // Three other accounts make it trend, until they don't like it anymore
+            let fourth = NewUser::new_local(
+                conn,
+                "fourth".to_owned(),
+                "Fourth user".to_owned(),
+                Role::Normal,
+                "",
+                "fourth@example.com".to_owned(),
+                None,
+            )?;
+            Like::insert(
+                conn,
+                NewLike {
+                    user_id: fourth.id,
+                    post_id: posts[0].id,
+                    ap_url: format!("{}/like/{}", posts[0].ap_url, fourth.id),
+                },
+            )?;
+            compute(conn)?;
+            assert!(TrendingPost::is_trending(conn, posts[0].id)?);
+            assert_eq!(timeline.get_latest(conn, 10)?[0].id, posts[0].id);
+
+            diesel::delete(likes::table.filter(likes::user_id.eq(fourth.id))).execute(conn)?;
+            compute(conn)?;
+            assert!(!TrendingPost::is_trending(conn, posts[0].id)?);
+            assert!(timeline.get_latest(conn, 10)?.is_empty());
+
+            assert!(decay(Utc::now().naive_utc(), Utc::now().naive_utc()) > 0.99);
+            assert!(
+                (decay(
+                    Utc::now().naive_utc(),
+                    Utc::now().naive_utc() - Duration::hours(24)
+                ) - 0.5)
+                    .abs()
+                    < 0.01
+            );
+            Ok(())
+        });
+    }
+}
@@ -1,8 +1,28 @@
 use crate::{
-    ap_url, blocklisted_emails::BlocklistedEmail, blogs::Blog, comments::Comment, db_conn::DbConn,
-    follows::Follow, instance::*, medias::Media, notifications::Notification,
-    post_authors::PostAuthor, posts::Post, safe_string::SafeString, schema::users,
-    timeline::Timeline, Connection, Error, Result, UserEvent::*, CONFIG, ITEMS_PER_PAGE, USER_CHAN,
+    admin::Permission,
+    ap_url,
+    blocklisted_emails::BlocklistedEmail,
+    blog_authors::{BlogAuthor, BlogRole},
+    blogs::Blog,
+    cache::{self, Entry},
+    comments::Comment,
+    db_conn::DbConn,
+    follows::Follow,
+    fundings::Funding,
+    instance::*,
+    keys::KEY_STORE,
+    medias::Media,
+    notifications::Notification,
+    post_authors::PostAuthor,
+    posts::Post,
+    profile_fields::{ProfileField, ProfileOwner},
+    safe_string::SafeString,
+    schema::users,
+    sessions::Session,
+    timeline::Timeline,
+    Connection, Error, Result,
+    UserEvent::*,
+    CONFIG, ITEMS_PER_PAGE, USER_CHAN,
 };
 use activitystreams::{
     activity::Delete,
@@ -11,10 +31,10 @@ use activitystreams::{
     collection::{OrderedCollection, OrderedCollectionPage},
     iri_string::types::IriString,
     markers::Activity,
-    object::{kind::ImageType, AsObject as _, Image, Tombstone},
+    object::{AsObject as _, Tombstone},
     prelude::*,
 };
-use chrono::{NaiveDateTime, Utc};
+use chrono::{Duration, NaiveDateTime, Utc};
 use diesel::{
     self, BelongingToDsl, BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl,
     RunQueryDsl, TextExpressionMethods,
@@ -28,10 +48,12 @@ use openssl::{
 };
 use plume_common::{
     activity_pub::{
+        discoverable,
         inbox::{AsActor, AsObject, FromId},
         request::get,
-        sign::{gen_keypair, Error as SignError, Result as SignResult, Signer},
-        ActivityStream, ApSignature, CustomPerson, Id, IntoId, PublicKey, ToAsString, ToAsUri,
+        set_discoverable,
+        sign::{Error as SignError, Result as SignResult, Signer},
+        ActivityStream, ApSignature, CustomPerson, Id, IntoId, PublicKey, ToAsString,
         PUBLIC_VISIBILITY,
     },
     utils,
@@ -44,6 +66,7 @@ use rocket::{
 use std::{
     cmp::PartialEq,
     hash::{Hash, Hasher},
+    str::FromStr,
     sync::Arc,
 };
 use webfinger::*;
@@ -55,6 +78,19 @@ pub enum Role {
     Instance = 3,
 }
 
+impl FromStr for Role {
+    type Err = Error;
+
+    fn from_str(role: &str) -> Result<Self> {
+        match role.to_lowercase().as_str() {
+            "admin" => Ok(Role::Admin),
+            "moderator" => Ok(Role::Moderator),
+            "normal" => Ok(Role::Normal),
+            _ => Err(Error::InvalidValue),
+        }
+    }
+}
+
 #[derive(Queryable, Identifiable, Clone, Debug, AsChangeset)]
 #[changeset_options(treat_none_as_null = "true")]
 pub struct User {
@@ -84,6 +120,27 @@ pub struct User {
     pub role: i32,
     pub preferred_theme: Option<String>,
     pub hide_custom_css: bool,
+    /// The posts of silenced users only appear in the timelines of the users following them
+    pub silenced: bool,
+    /// The image shown at the top of their profile
+    pub banner_id: Option<i32>,
+    /// Deactivated users can't log in, until an administrator reactivates them
+    pub deactivated: bool,
+    /// Whether they are listed in the directory, here and on other instances
+    pub discoverable: bool,
+    /// Whether search engines may index their profile and articles, and whether their
+    /// articles and comments can be found with the search of this instance and others
+    pub indexable: bool,
+    /// When the account was created on its instance, if it tells it
+    pub published: Option<NaiveDateTime>,
+    /// When its owner last used it, `None` if they never logged in (and for remote accounts)
+    pub last_active: Option<NaiveDateTime>,
+    /// When its owner was told it would be frozen or deleted if they didn't use it
+    pub inactivity_reminded_at: Option<NaiveDateTime>,
+    /// When it was deactivated because it wasn't used anymore
+    pub frozen_at: Option<NaiveDateTime>,
+    /// The address it will use once the change is confirmed from both addresses
+    pub pending_email: Option<String>,
 }
 
 #[derive(Default, Insertable)]
@@ -106,10 +163,15 @@ pub struct NewUser {
     pub summary_html: SafeString,
     pub role: i32,
     pub fqn: String,
+    pub discoverable: bool,
+    pub indexable: bool,
+    pub published: Option<NaiveDateTime>,
 }
 
 pub const AUTH_COOKIE: &str = "user_id";
 const USER_PREFIX: &str = "@";
+/// `last_active` is only updated once in a while, in hours, not to write on each request.
+const ACTIVITY_PRECISION: i64 = 1;
 
 impl User {
     insert!(users, NewUser);
@@ -126,6 +188,10 @@ impl User {
         self.role == Role::Admin as i32
     }
 
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        permission.granted_to(self.role)
+    }
+
     pub fn one_by_instance(conn: &Connection) -> Result<Vec<User>> {
         users::table
             .filter(users::instance_id.eq_any(users::table.select(users::instance_id).distinct()))
@@ -165,7 +231,8 @@ impl User {
         }
 
         for notif in Notification::find_followed_by(conn, self)? {
-            notif.delete(conn)?
+            // The followed user is the one who was notified
+            Notification::retract(conn, &notif.kind, notif.object_id, notif.user_id)?;
         }
 
         for comment in Comment::list_by_author(conn, self.id)? {
@@ -176,10 +243,12 @@ impl User {
             )?;
         }
 
-        diesel::delete(self)
-            .execute(conn)
-            .map(|_| ())
-            .map_err(Error::from)
+        diesel::delete(self).execute(conn)?;
+        if let Some(ref private_key) = self.private_key {
+            KEY_STORE.retire(conn, private_key)?;
+        }
+        self.forget_cached();
+        Ok(())
     }
 
     pub fn get_instance(&self, conn: &Connection) -> Result<Instance> {
@@ -194,6 +263,86 @@ impl User {
             .map_err(Error::from)
     }
 
+    pub fn set_silenced(&self, conn: &Connection, silenced: bool) -> Result<()> {
+        diesel::update(self)
+            .set(users::silenced.eq(silenced))
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+
+    /// Changes whether their articles and comments can be included in search, and updates the
+    /// search index.
+    pub fn set_indexable(&self, conn: &Connection, indexable: bool) -> Result<()> {
+        use crate::schema::{comments, post_authors, posts};
+        if self.indexable == indexable {
+            return Ok(());
+        }
+        diesel::update(self)
+            .set(users::indexable.eq(indexable))
+            .execute(conn)?;
+        for post in posts::table
+            .filter(posts::published.eq(true))
+            .filter(
+                posts::id.eq_any(
+                    post_authors::table
+                        .filter(post_authors::author_id.eq(self.id))
+                        .select(post_authors::post_id),
+                ),
+            )
+            .load::<Post>(conn)?
+        {
+            post.reindex();
+        }
+        for comment in comments::table
+            .filter(comments::author_id.eq(self.id))
+            .load::<Comment>(conn)?
+        {
+            comment.reindex(indexable);
+        }
+        Ok(())
+    }
+
+    /// Deactivating a user also logs them out everywhere.
+    ///
+    /// Reactivating an account frozen because it wasn't used gives its owner as much time as
+    /// if they just logged in.
+    pub fn set_deactivated(&self, conn: &Connection, deactivated: bool) -> Result<()> {
+        diesel::update(self)
+            .set(users::deactivated.eq(deactivated))
+            .execute(conn)?;
+        if deactivated {
+            Session::revoke_all(conn, self.id, None)?;
+        } else if self.frozen_at.is_some() {
+            diesel::update(self)
+                .set((
+                    users::frozen_at.eq(None::<NaiveDateTime>),
+                    users::inactivity_reminded_at.eq(None::<NaiveDateTime>),
+                    users::last_active.eq(Utc::now().naive_utc()),
+                ))
+                .execute(conn)?;
+        }
+        Ok(())
+    }
+
+    /// Remembers that the owner of this account is using it, which cancels the reminders
+    /// about its inactivity.
+    pub fn mark_active(&self, conn: &Connection) -> Result<()> {
+        let now = Utc::now().naive_utc();
+        if self.last_active.map_or(false, |date| {
+            now - date < Duration::hours(ACTIVITY_PRECISION)
+        }) {
+            return Ok(());
+        }
+        diesel::update(self)
+            .set((
+                users::last_active.eq(now),
+                users::inactivity_reminded_at.eq(None::<NaiveDateTime>),
+            ))
+            .execute(conn)?;
+        Ok(())
+    }
+
     pub fn count_local(conn: &Connection) -> Result<i64> {
         users::table
             .filter(users::instance_id.eq(Instance::get_local()?.id))
@@ -203,18 +352,32 @@ impl User {
             .map_err(Error::from)
     }
 
+    /// The local users who don't want their profile and articles to be indexed.
+    pub fn list_local_not_indexable(conn: &Connection) -> Result<Vec<User>> {
+        users::table
+            .filter(users::instance_id.eq(Instance::get_local()?.id))
+            .filter(users::indexable.eq(false))
+            .load::<User>(conn)
+            .map_err(Error::from)
+    }
+
     pub fn find_by_fqn(conn: &Connection, fqn: &str) -> Result<User> {
-        let from_db = users::table
-            .filter(users::fqn.eq(fqn))
-            .first(conn)
-            .optional()?;
-        if let Some(from_db) = from_db {
+        if let Some(from_db) = User::find_known_by_fqn(conn, fqn)? {
             Ok(from_db)
         } else {
             User::fetch_from_webfinger(conn, fqn)
         }
     }
 
+    /// Like `find_by_fqn`, without looking for the accounts this instance doesn't know.
+    pub fn find_known_by_fqn(conn: &Connection, fqn: &str) -> Result<Option<User>> {
+        users::table
+            .filter(users::fqn.eq(fqn))
+            .first(conn)
+            .optional()
+            .map_err(Error::from)
+    }
+
     pub fn search_local_by_name(
         conn: &Connection,
         name: &str,
@@ -267,7 +430,24 @@ impl User {
         .map_err(|(_, e)| e)
     }
 
+    /// Finds the address of an account from its WebFinger address (`user@instance`), and
+    /// fetches it without saving it: it doesn't need a database connection, and can be done
+    /// for many accounts at once.
+    pub fn fetch_by_acct(acct: &str) -> Result<(String, CustomPerson)> {
+        let url = resolve(acct.to_owned(), true)?
+            .links
+            .into_iter()
+            .find(|l| l.mime_type == Some(String::from("application/activity+json")))
+            .and_then(|l| l.href)
+            .ok_or(Error::Webfinger)?;
+        let person = User::fetch(&url)?;
+        Ok((url, person))
+    }
+
+    /// The template of the URL where the account `acct` (`user@instance`) can interact
+    /// with something from another instance, `{uri}` standing for its address.
     pub fn fetch_remote_interact_uri(acct: &str) -> Result<String> {
+        let acct = acct.trim().trim_start_matches('@');
         resolve(acct.to_owned(), true)?
             .links
             .into_iter()
@@ -288,14 +468,16 @@ impl User {
     }
 
     pub fn refetch(&self, conn: &Connection) -> Result<()> {
-        User::fetch(&self.ap_url.clone()).and_then(|json| {
+        User::fetch(&self.ap_url.clone()).and_then(|mut json| {
+            let discoverable = discoverable(&mut json.inner);
+            let indexable = indexable(&mut json.inner);
+            let published = published(&json);
             let avatar = json
                 .icon()
-                .and_then(|icon| icon.iter().next())
-                .and_then(|i| i.clone().extend::<Image, ImageType>().ok())
-                .and_then(|image| image)
-                .and_then(|image| image.id_unchecked().map(|url| url.to_string()))
-                .and_then(|url| Media::save_remote(conn, url, self).ok());
+                .and_then(|icon| Media::save_remote_image(conn, icon, self).ok());
+            let banner = json
+                .image()
+                .and_then(|image| Media::save_remote_image(conn, image, self).ok());
 
             let pub_key = &json.ext_one.public_key.public_key_pem;
             diesel::update(self)
@@ -316,7 +498,7 @@ impl User {
                         .ok_or(Error::MissingApProperty)?
                         .as_str()),
                     users::inbox_url.eq(json.ap_actor_ref().inbox()?.as_str()),
-                    users::summary.eq(SafeString::new(
+                    users::summary.eq(SafeString::new_remote(
                         &json
                             .ap_actor_ref()
                             .summary()
@@ -326,15 +508,25 @@ impl User {
                     users::followers_endpoint.eq(json
                         .ap_actor_ref()
                         .followers()?
-                        .ok_or(Error::MissingApProperty)?
-                        .as_str()),
+                        .map(|followers| followers.as_str())
+                        .unwrap_or(&self.followers_endpoint)),
                     users::avatar_id.eq(avatar.map(|a| a.id)),
+                    users::banner_id.eq(banner.map(|b| b.id)),
                     users::last_fetched_date.eq(Utc::now().naive_utc()),
                     users::public_key.eq(pub_key),
+                    users::discoverable.eq(discoverable),
+                    users::published.eq(published),
                 ))
-                .execute(conn)
+                .execute(conn)?;
+            self.set_indexable(conn, indexable)?;
+
+            let attachments = json
+                .object_ref()
+                .attachment()
+                .map(|attachments| attachments.iter().cloned().collect::<Vec<_>>())
+                .unwrap_or_default();
+            ProfileField::replace_remote(conn, ProfileOwner::User(self.id), &attachments)
                 .map(|_| ())
-                .map_err(Error::from)
         })
     }
 
@@ -409,6 +601,10 @@ impl User {
         }
     }
 
+    /// Checks the credentials of a local user.
+    ///
+    /// Accounts frozen because they were not used anymore are reactivated when their owner
+    /// logs in, unlike the ones deactivated by a moderator.
     pub fn login(conn: &Connection, ident: &str, password: &str) -> Result<User> {
         let local_id = Instance::get_local()?.id;
         let user = match User::find_by_email(conn, ident) {
@@ -416,7 +612,7 @@ impl User {
             _ => User::find_by_name(conn, ident, local_id),
         }
         .and_then(|u| {
-            if u.instance_id == local_id {
+            if u.instance_id == local_id && (!u.deactivated || u.frozen_at.is_some()) {
                 Ok(u)
             } else {
                 Err(Error::NotFound)
@@ -427,14 +623,14 @@ impl User {
             Ok(user) if user.hashed_password.is_some() => {
                 if bcrypt::verify(password, user.hashed_password.as_ref().unwrap()).unwrap_or(false)
                 {
-                    Ok(user)
+                    user.unfreeze(conn)
                 } else {
                     Err(Error::NotFound)
                 }
             }
             Ok(user) => {
                 if user.ldap_login(password) {
-                    Ok(user)
+                    user.unfreeze(conn)
                 } else {
                     Err(Error::NotFound)
                 }
@@ -454,10 +650,28 @@ impl User {
         }
     }
 
+    fn unfreeze(self, conn: &Connection) -> Result<User> {
+        if self.frozen_at.is_none() {
+            return Ok(self);
+        }
+        self.set_deactivated(conn, false)?;
+        User::get(conn, self.id)
+    }
+
+    /// Tells if `password` is the one of this user, to confirm sensitive actions.
+    pub fn check_password(&self, password: &str) -> bool {
+        match self.hashed_password {
+            Some(ref hash) => bcrypt::verify(password, hash).unwrap_or(false),
+            None => self.ldap_login(password),
+        }
+    }
+
+    /// Changing the password also logs the user out everywhere, in case someone else knew it.
     pub fn reset_password(&self, conn: &Connection, pass: &str) -> Result<()> {
         diesel::update(self)
             .set(users::hashed_password.eq(User::hash_pass(pass)?))
             .execute(conn)?;
+        Session::revoke_all(conn, self.id, None)?;
         Ok(())
     }
 
@@ -753,11 +967,40 @@ impl User {
             .map(|r| r > 0)
     }
 
+    /// The role of this user in a blog, if they are one of its members.
+    pub fn role_in(&self, conn: &Connection, blog: &Blog) -> Result<BlogRole> {
+        BlogAuthor::find(conn, blog.id, self.id).map(|member| member.role())
+    }
+
+    /// Tells if this user can publish articles on a blog without approval.
+    pub fn can_publish_in(&self, conn: &Connection, blog: &Blog) -> Result<bool> {
+        self.has_role_in(conn, blog, BlogRole::can_publish)
+    }
+
+    /// Tells if this user can edit a blog, its settings and its members.
+    pub fn can_manage(&self, conn: &Connection, blog: &Blog) -> Result<bool> {
+        self.has_role_in(conn, blog, BlogRole::can_manage)
+    }
+
+    fn has_role_in(
+        &self,
+        conn: &Connection,
+        blog: &Blog,
+        allows: fn(BlogRole) -> bool,
+    ) -> Result<bool> {
+        use crate::schema::blog_authors;
+        blog_authors::table
+            .filter(blog_authors::author_id.eq(self.id))
+            .filter(blog_authors::blog_id.eq(blog.id))
+            .select(blog_authors::role)
+            .get_result::<i32>(conn)
+            .optional()
+            .map(|role| role.and_then(BlogRole::from_i32).map_or(false, allows))
+            .map_err(Error::from)
+    }
+
     pub fn get_keypair(&self) -> Result<PKey<Private>> {
-        PKey::from_rsa(Rsa::private_key_from_pem(
-            self.private_key.clone().ok_or(Error::Signature)?.as_ref(),
-        )?)
-        .map_err(Error::from)
+        KEY_STORE.load(self.private_key.as_deref().ok_or(Error::Signature)?)
     }
 
     pub fn rotate_keypair(&self, conn: &Connection) -> Result<PKey<Private>> {
@@ -768,21 +1011,20 @@ impl User {
             //rotated recently
             self.get_keypair()
         } else {
-            let (public_key, private_key) = gen_keypair();
-            let public_key =
-                String::from_utf8(public_key).expect("NewUser::new_local: public key error");
-            let private_key =
-                String::from_utf8(private_key).expect("NewUser::new_local: private key error");
-            let res = PKey::from_rsa(Rsa::private_key_from_pem(private_key.as_ref())?)?;
+            let (public_key, private_key) = KEY_STORE.generate()?;
+            let res = KEY_STORE.load(&private_key)?;
             diesel::update(self)
                 .set((
                     users::public_key.eq(public_key),
                     users::private_key.eq(Some(private_key)),
                     users::last_fetched_date.eq(Utc::now().naive_utc()),
                 ))
-                .execute(conn)
-                .map_err(Error::from)
-                .map(|_| res)
+                .execute(conn)?;
+            if let Some(ref previous) = self.private_key {
+                KEY_STORE.retire(conn, previous)?;
+            }
+            self.forget_cached();
+            Ok(res)
         }
     }
 
@@ -816,10 +1058,24 @@ impl User {
         };
 
         if let Some(avatar_id) = self.avatar_id {
-            let mut avatar = Image::new();
-            avatar.set_url(Media::get(conn, avatar_id)?.url()?.parse::<IriString>()?);
+            let avatar = Media::get(conn, avatar_id)?.to_image(conn)?;
             actor.set_icon(avatar.into_any_base()?);
         }
+        if let Some(banner_id) = self.banner_id {
+            let banner = Media::get(conn, banner_id)?.to_image(conn)?;
+            actor.set_image(banner.into_any_base()?);
+        }
+
+        let mut attachments =
+            ProfileField::to_attachments(&ProfileField::list(conn, ProfileOwner::User(self.id))?)?;
+        if let Ok(funding) = Funding::for_user(conn, self.id) {
+            attachments.extend(funding.to_attachments()?);
+        }
+        if !attachments.is_empty() {
+            actor.set_many_attachments(attachments);
+        }
+        set_discoverable(&mut actor, self.discoverable)?;
+        set_indexable(&mut actor, self.indexable)?;
 
         Ok(CustomPerson::new(actor, ap_signature))
     }
@@ -845,7 +1101,8 @@ impl User {
 
     pub fn avatar_url(&self, conn: &Connection) -> String {
         self.avatar_id
-            .and_then(|id| Media::get(conn, id).and_then(|m| m.url()).ok())
+            .and_then(|id| Media::get(conn, id).ok())
+            .and_then(|m| m.inline_url(conn))
             .unwrap_or_else(|| "/static/images/default-avatar.png".to_string())
     }
 
@@ -881,7 +1138,16 @@ impl User {
                     mime_type: None,
                     href: None,
                     template: Some(format!(
-                        "https://{}/remote_interact?target={{uri}}",
+                        "https://{}/authorize_interaction?uri={{uri}}",
+                        self.get_instance(conn)?.public_domain
+                    )),
+                },
+                Link {
+                    rel: String::from("https://joinplu.me/ns#share"),
+                    mime_type: None,
+                    href: None,
+                    template: Some(format!(
+                        "https://{}/new?title={{title}}&body={{text}}&url={{url}}",
                         self.get_instance(conn)?.public_domain
                     )),
                 },
@@ -900,9 +1166,30 @@ impl User {
     pub fn set_avatar(&self, conn: &Connection, id: i32) -> Result<()> {
         diesel::update(self)
             .set(users::avatar_id.eq(id))
-            .execute(conn)
-            .map(|_| ())
-            .map_err(Error::from)
+            .execute(conn)?;
+        self.forget_cached();
+        Ok(())
+    }
+
+    pub fn banner_url(&self, conn: &Connection) -> Option<String> {
+        self.banner_id
+            .and_then(|id| Media::get(conn, id).ok())
+            .and_then(|m| m.inline_url(conn))
+    }
+
+    pub fn set_banner(&self, conn: &Connection, id: Option<i32>) -> Result<()> {
+        diesel::update(self)
+            .set(users::banner_id.eq(id))
+            .execute(conn)?;
+        self.forget_cached();
+        Ok(())
+    }
+
+    /// Forgets the cached actor document and WebFinger answer of this user, after they
+    /// changed.
+    pub fn forget_cached(&self) {
+        cache::forget(Entry::UserActor(self.id));
+        cache::forget(Entry::UserWebFinger(&self.fqn));
     }
 
     pub fn needs_update(&self) -> bool {
@@ -937,12 +1224,11 @@ impl<'a, 'r> FromRequest<'a, 'r> for User {
     type Error = ();
 
     fn from_request(request: &'a Request<'r>) -> request::Outcome<User, ()> {
+        let session = request.guard::<Session>()?;
         let conn = request.guard::<DbConn>()?;
-        request
-            .cookies()
-            .get_private(AUTH_COOKIE)
-            .and_then(|cookie| cookie.value().parse().ok())
-            .and_then(|id| User::get(&conn, id).ok())
+        User::get(&conn, session.user_id)
+            .ok()
+            .filter(|user| !user.deactivated)
             .or_forward(())
     }
 }
@@ -963,7 +1249,9 @@ impl FromId<Connection> for User {
         Self::find_by_ap_url(conn, id)
     }
 
-    fn from_activity(conn: &Connection, acct: CustomPerson) -> Result<Self> {
+    fn from_activity(conn: &Connection, mut acct: CustomPerson) -> Result<Self> {
+        let discoverable = discoverable(&mut acct.inner);
+        let indexable = indexable(&mut acct.inner);
         let actor = acct.ap_actor_ref();
         let username = actor
             .preferred_username()
@@ -993,7 +1281,7 @@ impl FromId<Connection> for User {
             outbox_url: actor.outbox()?.ok_or(Error::MissingApProperty)?.to_string(),
             inbox_url: actor.inbox()?.to_string(),
             role: 2,
-            summary_html: SafeString::new(&summary),
+            summary_html: SafeString::new_remote(&summary),
             summary,
             public_key: acct.ext_one.public_key.public_key_pem.to_string(),
             shared_inbox_url: actor
@@ -1001,12 +1289,21 @@ impl FromId<Connection> for User {
                 .and_then(|e| e.shared_inbox.map(|inbox| inbox.to_string())),
             followers_endpoint: actor
                 .followers()?
-                .ok_or(Error::MissingApProperty)?
-                .to_string(),
+                .map(|followers| followers.to_string())
+                .unwrap_or_default(),
+            discoverable,
+            indexable,
+            published: published(&acct),
             ..NewUser::default()
         };
 
-        let avatar_id = acct.object_ref().icon().and_then(|icon| icon.to_as_uri());
+        let avatar = acct.object_ref().icon().cloned();
+        let banner = acct.object_ref().image().cloned();
+        let attachments = acct
+            .object_ref()
+            .attachment()
+            .map(|attachments| attachments.iter().cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
 
         let (ap_url, inst) = {
             let any_base = acct.into_any_base()?;
@@ -1019,6 +1316,10 @@ impl FromId<Connection> for User {
                     .to_string(),
             )
         };
+        if new_user.followers_endpoint.is_empty() {
+            // Lemmy users don't have any followers collection, but this URL has to be unique
+            new_user.followers_endpoint = format!("{}#followers", ap_url);
+        }
         new_user.ap_url = ap_url;
 
         let instance = Instance::find_by_domain(conn, &inst).or_else(|_| {
@@ -1045,16 +1346,28 @@ impl FromId<Connection> for User {
             format!("{}@{}", username, instance.public_domain)
         };
 
-        let user = User::insert(conn, new_user)?;
-        if let Some(avatar_id) = avatar_id {
-            let avatar = Media::save_remote(conn, avatar_id, &user);
-
-            if let Ok(avatar) = avatar {
-                if let Err(e) = user.set_avatar(conn, avatar.id) {
-                    tracing::error!("{:?}", e);
+        let mut user = User::insert(conn, new_user)?;
+        if let Some(avatar) = avatar {
+            if let Ok(avatar) = Media::save_remote_image(conn, &avatar, &user) {
+                match user.set_avatar(conn, avatar.id) {
+                    Ok(_) => user.avatar_id = Some(avatar.id),
+                    Err(e) => tracing::error!("{:?}", e),
+                }
+            }
+        }
+        if let Some(banner) = banner {
+            if let Ok(banner) = Media::save_remote_image(conn, &banner, &user) {
+                match user.set_banner(conn, Some(banner.id)) {
+                    Ok(_) => user.banner_id = Some(banner.id),
+                    Err(e) => tracing::error!("{:?}", e),
                 }
             }
         }
+        if let Err(e) =
+            ProfileField::replace_remote(conn, ProfileOwner::User(user.id), &attachments)
+        {
+            tracing::error!("{:?}", e);
+        }
 
         Ok(user)
     }
@@ -1064,6 +1377,12 @@ impl FromId<Connection> for User {
     }
 }
 
+/// When a remote actor was created on its instance, if it tells it.
+fn published(acct: &CustomPerson) -> Option<NaiveDateTime> {
+    let published = acct.object_ref().published()?;
+    NaiveDateTime::from_timestamp_opt(published.unix_timestamp(), 0)
+}
+
 impl AsActor<&Connection> for User {
     fn get_inbox_url(&self) -> String {
         self.inbox_url.clone()
@@ -1136,12 +1455,12 @@ impl NewUser {
         email: String,
         password: Option<String>,
     ) -> Result<User> {
-        let (pub_key, priv_key) = gen_keypair();
         let instance = Instance::get_local()?;
         let blocklisted = BlocklistedEmail::matches_blocklist(conn, &email)?;
         if let Some(x) = blocklisted {
             return Err(Error::Blocklisted(x.notify_user, x.notification_text));
         }
+        let (public_key, private_key) = KEY_STORE.generate()?;
 
         let res = User::insert(
             conn,
@@ -1154,8 +1473,8 @@ impl NewUser {
                 email: Some(email),
                 hashed_password: password,
                 instance_id: instance.id,
-                public_key: String::from_utf8(pub_key).or(Err(Error::Signature))?,
-                private_key: Some(String::from_utf8(priv_key).or(Err(Error::Signature))?),
+                public_key,
+                private_key: Some(private_key),
                 outbox_url: instance.compute_box(USER_PREFIX, &username, "outbox"),
                 inbox_url: instance.compute_box(USER_PREFIX, &username, "inbox"),
                 ap_url: instance.compute_box(USER_PREFIX, &username, ""),
@@ -1163,6 +1482,9 @@ impl NewUser {
                 followers_endpoint: instance.compute_box(USER_PREFIX, &username, "followers"),
                 fqn: username,
                 avatar_id: None,
+                discoverable: false,
+                indexable: true,
+                published: None,
             },
         )?;
 
@@ -1283,6 +1605,8 @@ pub(crate) mod tests {
                     subtitle: "".into(),
                     source: content,
                     cover_id: None,
+                    excerpt: String::new(),
+                    canonical_url: None,
                 },
             )
             .unwrap();
@@ -1381,6 +1705,28 @@ pub(crate) mod tests {
         });
     }
 
+    #[test]
+    fn permissions() {
+        let conn = &db();
+        conn.test_transaction::<_, (), _>(|| {
+            let inserted = fill_database(conn);
+            assert!(inserted[0].has_permission(Permission::ManageSettings));
+            assert!(!inserted[1].has_permission(Permission::SilenceAccounts));
+
+            inserted[1].set_role(conn, Role::Moderator).unwrap();
+            let moderator = User::get(conn, inserted[1].id).unwrap();
+            assert!(moderator.has_permission(Permission::SilenceAccounts));
+            assert!(moderator.has_permission(Permission::BlockInstances));
+            assert!(!moderator.has_permission(Permission::DeleteUsers));
+            assert!(!moderator.has_permission(Permission::ManageRoles));
+            assert!(!moderator.has_permission(Permission::ManageSettings));
+
+            moderator.set_silenced(conn, true).unwrap();
+            assert!(User::get(conn, moderator.id).unwrap().silenced);
+            Ok(())
+        });
+    }
+
     #[test]
     fn auth() {
         let conn = &db();
@@ -1406,6 +1752,42 @@ pub(crate) mod tests {
         });
     }
 
+    #[test]
+    fn deactivate() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let users = fill_database(conn);
+            let user = &users[1];
+            user.reset_password(conn, "test_password")?;
+            Session::open(conn, user, &Default::default())?;
+
+            user.set_deactivated(conn, true)?;
+            assert!(User::get(conn, user.id)?.deactivated);
+            assert!(User::login(conn, &user.username, "test_password").is_err());
+            assert!(Session::list_for_user(conn, user.id)?.is_empty());
+
+            user.set_deactivated(conn, false)?;
+            assert!(User::login(conn, &user.username, "test_password").is_ok());
+
+            // Frozen accounts are unfrozen by logging in
+            diesel::update(user)
+                .set(users::frozen_at.eq(Utc::now().naive_utc()))
+                .execute(conn)?;
+            User::get(conn, user.id)?.set_deactivated(conn, true)?;
+            let unfrozen = User::login(conn, &user.username, "test_password")?;
+            assert!(!unfrozen.deactivated && unfrozen.frozen_at.is_none());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn parse_role() {
+        assert!(matches!("admin".parse(), Ok(Role::Admin)));
+        assert!(matches!("Moderator".parse(), Ok(Role::Moderator)));
+        assert!(matches!("normal".parse(), Ok(Role::Normal)));
+        assert!("instance".parse::<Role>().is_err());
+    }
+
     #[test]
     fn get_local_page() {
         let conn = &db();
@@ -1435,6 +1817,31 @@ pub(crate) mod tests {
         });
     }
 
+    #[test]
+    fn webfinger() {
+        let conn = &db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let users = fill_database(conn);
+            let webfinger = users[0].webfinger(conn)?;
+            let subscribe = webfinger
+                .links
+                .iter()
+                .find(|l| l.rel == "http://ostatus.org/schema/1.0/subscribe")
+                .and_then(|l| l.template.clone())
+                .unwrap();
+            assert!(subscribe.ends_with("/authorize_interaction?uri={uri}"));
+            assert!(webfinger
+                .links
+                .iter()
+                .any(|l| l.rel == "https://joinplu.me/ns#share"
+                    && l.template
+                        .as_deref()
+                        .unwrap_or_default()
+                        .contains("/new?title=")));
+            Ok(())
+        });
+    }
+
     #[test]
     fn self_federation() {
         let conn = db();
@@ -1461,6 +1868,29 @@ pub(crate) mod tests {
         });
     }
 
+    #[test]
+    fn federate_avatar_and_banner() {
+        let conn = db();
+        conn.test_transaction::<_, Error, _>(|| {
+            let users = fill_database(&conn);
+            let other = &users[2];
+            other.set_banner(&conn, other.avatar_id)?;
+            let other = User::get(&conn, other.id)?;
+            let avatar_url = other.avatar_url(&conn);
+            let banner_url = other.banner_url(&conn);
+            assert!(banner_url.is_some());
+
+            let ap_repr = other.to_activity(&conn)?;
+            other.delete(&conn)?;
+            let user = User::from_activity(&conn, ap_repr)?;
+            assert_eq!(user.avatar_url(&conn), avatar_url);
+            assert_eq!(user.banner_url(&conn), banner_url);
+            // The same remote file is used for both
+            assert_eq!(user.avatar_id, user.banner_id);
+            Ok(())
+        });
+    }
+
     #[test]
     fn to_activity() {
         let conn = db();
@@ -1470,12 +1900,14 @@ pub(crate) mod tests {
             let act = user.to_activity(&conn)?;
 
             let expected = json!({
+                "discoverable": false,
                 "endpoints": {
                     "sharedInbox": "https://plu.me/inbox"
                 },
                 "followers": "https://plu.me/@/admin/followers",
                 "id": "https://plu.me/@/admin/",
                 "inbox": "https://plu.me/@/admin/inbox",
+                "indexable": true,
                 "name": "The admin",
                 "outbox": "https://plu.me/@/admin/outbox",
                 "preferredUsername": "admin",
@@ -1491,19 +1923,30 @@ pub(crate) mod tests {
 
             assert_json_eq!(to_value(act)?, expected);
 
-            let other = &users[2];
+            users[2].set_banner(&conn, users[2].avatar_id)?;
+            let other = &User::get(&conn, users[2].id)?;
             let other_act = other.to_activity(&conn)?;
             let expected_other = json!({
+                "discoverable": false,
                 "endpoints": {
                     "sharedInbox": "https://plu.me/inbox"
                 },
                 "followers": "https://plu.me/@/other/followers",
                 "icon": {
+                    "attributedTo": "https://plu.me/@/other/",
+                    "mediaType": "image/png",
                     "url": "https://plu.me/static/media/example.png",
                     "type": "Image",
                 },
                 "id": "https://plu.me/@/other/",
+                "image": {
+                    "attributedTo": "https://plu.me/@/other/",
+                    "mediaType": "image/png",
+                    "url": "https://plu.me/static/media/example.png",
+                    "type": "Image",
+                },
                 "inbox": "https://plu.me/@/other/inbox",
+                "indexable": true,
                 "name": "Another user",
                 "outbox": "https://plu.me/@/other/outbox",
                 "preferredUsername": "other",
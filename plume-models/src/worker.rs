@@ -0,0 +1,127 @@
+//! The thread pool running background jobs, like deliveries and scheduled tasks.
+//!
+//! Each job runs in its own `job` span. The ones started while handling a request are
+//! children of its span, so that their logs carry its ID.
+
+use plume_common::utils::short_id;
+use scheduled_thread_pool::{JobHandle, ScheduledThreadPool};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tracing::info_span;
+
+pub struct Worker(ScheduledThreadPool);
+
+/// How many jobs of a kind can be waiting or running at once (see `Worker::try_execute`).
+pub struct JobQueue {
+    jobs: Arc<AtomicUsize>,
+    max: usize,
+}
+
+impl JobQueue {
+    pub fn new(max: usize) -> Self {
+        JobQueue {
+            jobs: Arc::new(AtomicUsize::new(0)),
+            max: max.max(1),
+        }
+    }
+}
+
+/// Leaves the queue of a job when it ends, even if it panics.
+struct QueuedJob(Arc<AtomicUsize>);
+
+impl Drop for QueuedJob {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Worker {
+    pub fn new(threads: usize) -> Self {
+        Worker(ScheduledThreadPool::with_name("worker {}", threads))
+    }
+
+    /// Runs a job as soon as a thread is available.
+    pub fn execute<F>(&self, job: F) -> JobHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let span = info_span!("job", id = %short_id());
+        self.0.execute(move || span.in_scope(job))
+    }
+
+    /// Runs a job once `delay` has passed.
+    pub fn execute_after<F>(&self, delay: Duration, job: F) -> JobHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let span = info_span!("job", id = %short_id());
+        self.0.execute_after(delay, move || span.in_scope(job))
+    }
+
+    /// Runs a job like `execute`, unless `queue` is full.
+    ///
+    /// Returns whether the job will run.
+    pub fn try_execute<F>(&self, queue: &JobQueue, job: F) -> bool
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if queue.jobs.fetch_add(1, Ordering::SeqCst) >= queue.max {
+            queue.jobs.fetch_sub(1, Ordering::SeqCst);
+            return false;
+        }
+        let queued = QueuedJob(queue.jobs.clone());
+        self.execute(move || {
+            let _queued = queued;
+            job()
+        });
+        true
+    }
+
+    /// Runs a job every `delay`, with a new span each time.
+    pub fn execute_with_fixed_delay<F>(
+        &self,
+        initial_delay: Duration,
+        delay: Duration,
+        mut job: F,
+    ) -> JobHandle
+    where
+        F: FnMut() + Send + 'static,
+    {
+        self.0
+            .execute_with_fixed_delay(initial_delay, delay, move || {
+                info_span!(parent: None, "job", id = %short_id()).in_scope(&mut job)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn bounded_queue() {
+        let worker = Worker::new(1);
+        let queue = JobQueue::new(1);
+        let (release, blocked) = mpsc::channel::<()>();
+        let (done, finished) = mpsc::channel();
+        assert!(worker.try_execute(&queue, move || {
+            blocked.recv().unwrap();
+            done.send(()).unwrap();
+        }));
+        assert!(!worker.try_execute(&queue, || {}));
+
+        release.send(()).unwrap();
+        finished.recv().unwrap();
+        // The first job may not have left the queue yet
+        while queue.jobs.load(Ordering::SeqCst) > 0 {
+            std::thread::yield_now();
+        }
+        assert!(worker.try_execute(&queue, || {}));
+    }
+}
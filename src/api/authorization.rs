@@ -32,6 +32,31 @@ impl Scope for plume_models::posts::Post {
         "posts"
     }
 }
+impl Scope for plume_models::users::User {
+    fn to_str() -> &'static str {
+        "users"
+    }
+}
+impl Scope for plume_models::blogs::Blog {
+    fn to_str() -> &'static str {
+        "blogs"
+    }
+}
+impl Scope for plume_models::instance::Instance {
+    fn to_str() -> &'static str {
+        "instance"
+    }
+}
+impl Scope for plume_models::medias::Media {
+    fn to_str() -> &'static str {
+        "medias"
+    }
+}
+impl Scope for plume_models::notifications::Notification {
+    fn to_str() -> &'static str {
+        "notifications"
+    }
+}
 
 pub struct Authorization<A, S>(pub ApiToken, PhantomData<(A, S)>);
 
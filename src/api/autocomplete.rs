@@ -0,0 +1,57 @@
+use rocket_contrib::json::Json;
+
+use crate::api::{authorization::*, Api};
+use plume_api::autocomplete::*;
+use plume_models::{
+    autocomplete,
+    db_conn::DbConn,
+    posts::Post,
+    rate_limits::{ApiRead, RateLimit},
+    users::User,
+};
+
+/// How many suggestions are returned by default, and at most.
+const DEFAULT_LIMIT: i64 = 5;
+const MAX_LIMIT: i64 = 20;
+
+/// Suggestions for the editor: actors when `q` starts with `@`,
+/// emoji when it starts with `:`, and both otherwise.
+#[get("/autocomplete?<q>&<limit>")]
+pub fn search(
+    _limit: RateLimit<ApiRead>,
+    q: String,
+    limit: Option<i64>,
+    auth: Authorization<Read, Post>,
+    conn: DbConn,
+) -> Api<AutocompleteData> {
+    let user = User::get(&conn, auth.0.user_id)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).max(1).min(MAX_LIMIT);
+
+    let actors = if q.starts_with(':') {
+        vec![]
+    } else {
+        autocomplete::actors(&conn, &user, &q, limit)?
+            .into_iter()
+            .map(|u| ActorSuggestionData {
+                id: u.id,
+                display_name: u.name(),
+                avatar: u.avatar_url(&conn),
+                url: u.ap_url.clone(),
+                fqn: u.fqn,
+            })
+            .collect()
+    };
+    let emojis = if q.starts_with('@') {
+        vec![]
+    } else {
+        autocomplete::emojis(&q, limit as usize)
+            .into_iter()
+            .map(|(shortcode, emoji)| EmojiSuggestionData {
+                shortcode: shortcode.to_owned(),
+                emoji: emoji.to_owned(),
+            })
+            .collect()
+    };
+
+    Ok(Json(AutocompleteData { actors, emojis }))
+}
@@ -0,0 +1,99 @@
+use rocket_contrib::json::Json;
+
+use crate::api::{authorization::*, Api};
+use plume_api::blogs::*;
+use plume_models::{
+    blog_authors::{BlogAuthor, BlogRole},
+    blogs::Blog,
+    db_conn::DbConn,
+    post_reviews::PostReview,
+    posts::Post,
+    rate_limits::{ApiRead, RateLimit},
+    users::User,
+    Error,
+};
+
+#[get("/blogs/<name>/members")]
+pub fn members(_limit: RateLimit<ApiRead>, name: String, conn: DbConn) -> Api<Vec<BlogMemberData>> {
+    let blog = Blog::find_by_fqn(&conn, &name)?;
+    members_data(&conn, &blog)
+}
+
+/// Adds a member to a blog, or changes their role. Only owners can do it.
+#[put("/blogs/<name>/members", data = "<payload>")]
+pub fn set_member(
+    name: String,
+    auth: Authorization<Write, Blog>,
+    payload: Json<BlogMemberData>,
+    conn: DbConn,
+) -> Api<Vec<BlogMemberData>> {
+    let blog = Blog::find_by_fqn(&conn, &name)?;
+    check_owner(&conn, &auth, &blog)?;
+    let user = User::find_by_fqn(&conn, &payload.user)?;
+    let role = BlogRole::from_name(&payload.role).ok_or(Error::InvalidValue)?;
+    BlogAuthor::set_role(&conn, blog.id, user.id, role)?;
+    members_data(&conn, &blog)
+}
+
+#[delete("/blogs/<name>/members/<user>")]
+pub fn remove_member(
+    name: String,
+    user: String,
+    auth: Authorization<Write, Blog>,
+    conn: DbConn,
+) -> Api<Vec<BlogMemberData>> {
+    let blog = Blog::find_by_fqn(&conn, &name)?;
+    check_owner(&conn, &auth, &blog)?;
+    let user = User::find_by_fqn(&conn, &user)?;
+    BlogAuthor::find(&conn, blog.id, user.id)?.remove(&conn)?;
+    members_data(&conn, &blog)
+}
+
+/// The drafts waiting for the approval of an editor of the blog.
+#[get("/blogs/<name>/reviews")]
+pub fn reviews(
+    _limit: RateLimit<ApiRead>,
+    name: String,
+    auth: Authorization<Read, Post>,
+    conn: DbConn,
+) -> Api<Vec<ReviewData>> {
+    let blog = Blog::find_by_fqn(&conn, &name)?;
+    if !User::get(&conn, auth.0.user_id)?.can_publish_in(&conn, &blog)? {
+        return Err(Error::Unauthorized.into());
+    }
+    Ok(Json(
+        PostReview::list_for_blog(&conn, blog.id)?
+            .into_iter()
+            .map(|(post, review)| {
+                Ok(ReviewData {
+                    post_id: post.id,
+                    title: post.title,
+                    submitted_by: User::get(&conn, review.submitted_by)?.fqn,
+                    creation_date: review.creation_date.format("%Y-%m-%d").to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?,
+    ))
+}
+
+fn check_owner(conn: &DbConn, auth: &Authorization<Write, Blog>, blog: &Blog) -> Result<(), Error> {
+    let user = User::get(conn, auth.0.user_id)?;
+    match user.role_in(conn, blog) {
+        Ok(role) if role.can_manage() => Ok(()),
+        _ => Err(Error::Unauthorized),
+    }
+}
+
+fn members_data(conn: &DbConn, blog: &Blog) -> Api<Vec<BlogMemberData>> {
+    Ok(Json(
+        BlogAuthor::list_for_blog(conn, blog.id)?
+            .into_iter()
+            .map(|member| {
+                Ok(BlogMemberData {
+                    user: User::get(conn, member.author_id)?.fqn,
+                    role: member.role().name().to_owned(),
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?,
+    ))
+}
@@ -0,0 +1,78 @@
+use rocket::request::LenientForm;
+
+use crate::api::{
+    pagination::{PageParams, Paginated},
+    ApiError,
+};
+use plume_api::directory::*;
+use plume_models::{
+    db_conn::DbConn,
+    directory::{self, Entry, Window, CATEGORIES},
+    rate_limits::{ApiRead, RateLimit},
+    Error,
+};
+
+/// The local blogs, or authors if `kind` is `authors`, that chose to be listed in the
+/// directory, the most recent first, with cursor-based pagination.
+///
+/// `category` is one of the categories blogs can be listed in. Clients can sort the
+/// entries by `last_activity`.
+#[get("/directory?<kind>&<category>&<page..>")]
+pub fn list(
+    _limit: RateLimit<ApiRead>,
+    kind: Option<String>,
+    category: Option<String>,
+    page: LenientForm<PageParams>,
+    conn: DbConn,
+) -> Result<Paginated<DirectoryEntryData>, ApiError> {
+    if category
+        .as_deref()
+        .map_or(false, |c| !CATEGORIES.contains(&c))
+    {
+        return Err(Error::InvalidValue.into());
+    }
+    let cursor = page.cursor();
+    let window = Window::Cursor(cursor);
+    let entries = match kind.as_deref() {
+        Some("authors") => directory::authors(&conn, category.as_deref(), window)?
+            .into_iter()
+            .map(
+                |Entry {
+                     item,
+                     last_activity,
+                 }| DirectoryEntryData {
+                    id: item.id,
+                    kind: "author".to_owned(),
+                    name: item.name(),
+                    summary: item.summary_html.to_string(),
+                    url: item.ap_url,
+                    category: None,
+                    creation_date: item.creation_date.format("%Y-%m-%d").to_string(),
+                    last_activity: last_activity.map(|d| d.format("%Y-%m-%d").to_string()),
+                    fqn: item.fqn,
+                },
+            )
+            .collect::<Vec<_>>(),
+        Some("blogs") | None => directory::blogs(&conn, category.as_deref(), window)?
+            .into_iter()
+            .map(
+                |Entry {
+                     item,
+                     last_activity,
+                 }| DirectoryEntryData {
+                    id: item.id,
+                    kind: "blog".to_owned(),
+                    name: item.title,
+                    summary: item.summary_html.to_string(),
+                    url: item.ap_url,
+                    category: item.directory_category,
+                    creation_date: item.creation_date.format("%Y-%m-%d").to_string(),
+                    last_activity: last_activity.map(|d| d.format("%Y-%m-%d").to_string()),
+                    fqn: item.fqn,
+                },
+            )
+            .collect(),
+        Some(_) => return Err(Error::InvalidValue.into()),
+    };
+    Ok(Paginated::new(entries, cursor, |entry| entry.id))
+}
@@ -0,0 +1,253 @@
+use chrono::NaiveDateTime;
+use rocket::{request::LenientForm, State};
+use rocket_contrib::json::Json;
+
+use crate::api::{
+    authorization::*,
+    pagination::{PageParams, Paginated},
+    Api, ApiError,
+};
+use crate::reload::Reloader;
+use plume_api::instance::*;
+use plume_models::{
+    about::{AboutSection, InstanceRule},
+    admin::Permission,
+    announcements::{Announcement, Reaction},
+    db_conn::DbConn,
+    instance::Instance,
+    maintenance::Maintenance,
+    posts::Post,
+    rate_limits::{ApiRead, RateLimit},
+    users::User,
+    Error,
+};
+
+fn maintenance_data(maintenance: Option<Maintenance>) -> MaintenanceData {
+    match maintenance {
+        Some(maintenance) => MaintenanceData {
+            active: true,
+            reason: maintenance.reason,
+            since: Some(
+                maintenance
+                    .start_date
+                    .format("%Y-%m-%dT%H:%M:%SZ")
+                    .to_string(),
+            ),
+        },
+        None => MaintenanceData::default(),
+    }
+}
+
+fn check_admin(conn: &DbConn, token: &Authorization<impl Action, Instance>) -> Result<(), Error> {
+    let user = User::get(conn, token.0.user_id)?;
+    if user.has_permission(Permission::ManageSettings) {
+        Ok(())
+    } else {
+        Err(Error::Unauthorized)
+    }
+}
+
+/// What this instance tells about itself: its description, rules and contact.
+#[get("/instance")]
+pub fn details(_limit: RateLimit<ApiRead>, conn: DbConn) -> Api<InstanceData> {
+    let instance = Instance::get_local()?;
+    let contact = instance
+        .contact(&conn)
+        .ok()
+        .map(|user| InstanceContactData {
+            name: user.name(),
+            fqn: user.fqn,
+            url: user.ap_url,
+        });
+    Ok(Json(InstanceData {
+        domain: instance.public_domain,
+        title: instance.name,
+        short_description: instance.short_description_html.to_string(),
+        description: instance.long_description_html.to_string(),
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        registrations: instance.open_registrations,
+        default_license: instance.default_license,
+        contact,
+        rules: InstanceRule::list(&conn)?
+            .into_iter()
+            .map(|rule| InstanceRuleData {
+                id: rule.id,
+                text: rule.short_text,
+                hint: rule.long_text,
+            })
+            .collect(),
+        sections: AboutSection::list(&conn)?
+            .into_iter()
+            .map(|section| AboutSectionData {
+                title: section.title,
+                content: section.content_html.to_string(),
+            })
+            .collect(),
+        stats: InstanceStatsData {
+            users: User::count_local(&conn)?,
+            articles: Post::count_local(&conn)?,
+            instances: Instance::count(&conn)? - 1,
+        },
+    }))
+}
+
+/// Whether the instance is in maintenance.
+#[get("/instance/maintenance")]
+pub fn maintenance(auth: Authorization<Read, Instance>, conn: DbConn) -> Api<MaintenanceData> {
+    check_admin(&conn, &auth)?;
+    Ok(Json(maintenance_data(Maintenance::current(&conn)?)))
+}
+
+/// Puts the instance in maintenance: it can only be read until the maintenance is stopped.
+#[put("/instance/maintenance", data = "<payload>")]
+pub fn start_maintenance(
+    auth: Authorization<Write, Instance>,
+    payload: Json<StartMaintenanceData>,
+    conn: DbConn,
+) -> Api<MaintenanceData> {
+    check_admin(&conn, &auth)?;
+    let reason = payload.reason.as_deref().unwrap_or_default();
+    Ok(Json(maintenance_data(Some(Maintenance::start(
+        &conn, reason,
+    )?))))
+}
+
+#[delete("/instance/maintenance")]
+pub fn stop_maintenance(
+    auth: Authorization<Write, Instance>,
+    conn: DbConn,
+) -> Api<MaintenanceData> {
+    check_admin(&conn, &auth)?;
+    Maintenance::stop(&conn)?;
+    Ok(Json(MaintenanceData::default()))
+}
+
+/// Reads the reloadable part of the configuration again, like `SIGHUP` does.
+#[post("/instance/config/reload")]
+pub fn reload_config(
+    auth: Authorization<Write, Instance>,
+    conn: DbConn,
+    reloader: State<'_, Reloader>,
+) -> Api<ConfigReloadData> {
+    check_admin(&conn, &auth)?;
+    let problems = reloader.reload();
+    Ok(Json(ConfigReloadData {
+        reloaded: problems.is_empty(),
+        problems: problems
+            .into_iter()
+            .map(|problem| ConfigProblemData {
+                variable: problem.variable,
+                message: problem.message,
+                hint: problem.hint,
+            })
+            .collect(),
+    }))
+}
+
+/// The running announcements, without the ones the user dismissed, the most recent first,
+/// with cursor-based pagination.
+#[get("/instance/announcements?<page..>")]
+pub fn announcements(
+    _limit: RateLimit<ApiRead>,
+    page: LenientForm<PageParams>,
+    auth: Option<Authorization<Read, User>>,
+    conn: DbConn,
+) -> Result<Paginated<AnnouncementData>, ApiError> {
+    let user = match auth {
+        Some(auth) => Some(User::get(&conn, auth.0.user_id)?),
+        None => None,
+    };
+    let cursor = page.cursor();
+    let announcements = Announcement::active_with_reactions_page(&conn, user.as_ref(), cursor)?
+        .into_iter()
+        .map(|(announcement, reactions)| announcement_data(announcement, reactions))
+        .collect::<Vec<_>>();
+    Ok(Paginated::new(announcements, cursor, |a| a.id))
+}
+
+#[post("/instance/announcements", data = "<payload>")]
+pub fn create_announcement(
+    auth: Authorization<Write, Instance>,
+    payload: Json<NewAnnouncementData>,
+    conn: DbConn,
+) -> Api<AnnouncementData> {
+    check_admin(&conn, &auth)?;
+    let author = User::get(&conn, auth.0.user_id)?;
+    let date = |date: &Option<String>| match date {
+        Some(date) => NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%SZ")
+            .map(Some)
+            .map_err(|_| Error::InvalidValue),
+        None => Ok(None),
+    };
+    let announcement = Announcement::create(
+        &conn,
+        &author,
+        &payload.content,
+        date(&payload.starts_at)?,
+        date(&payload.ends_at)?,
+    )?;
+    let reactions = announcement.reactions(&conn, None)?;
+    Ok(Json(announcement_data(announcement, reactions)))
+}
+
+#[delete("/instance/announcements/<id>")]
+pub fn delete_announcement(id: i32, auth: Authorization<Write, Instance>, conn: DbConn) -> Api<()> {
+    check_admin(&conn, &auth)?;
+    Announcement::get(&conn, id)?.delete(&conn)?;
+    Ok(Json(()))
+}
+
+/// Stops showing an announcement to the user.
+#[post("/instance/announcements/<id>/dismiss")]
+pub fn dismiss_announcement(id: i32, auth: Authorization<Write, User>, conn: DbConn) -> Api<()> {
+    let user = User::get(&conn, auth.0.user_id)?;
+    Announcement::get(&conn, id)?.dismiss(&conn, &user)?;
+    Ok(Json(()))
+}
+
+#[put("/instance/announcements/<id>/reactions/<emoji>")]
+pub fn react_to_announcement(
+    id: i32,
+    emoji: String,
+    auth: Authorization<Write, User>,
+    conn: DbConn,
+) -> Api<AnnouncementData> {
+    let user = User::get(&conn, auth.0.user_id)?;
+    let announcement = Announcement::get(&conn, id)?;
+    announcement.react(&conn, &user, &emoji)?;
+    let reactions = announcement.reactions(&conn, Some(&user))?;
+    Ok(Json(announcement_data(announcement, reactions)))
+}
+
+#[delete("/instance/announcements/<id>/reactions/<emoji>")]
+pub fn unreact_to_announcement(
+    id: i32,
+    emoji: String,
+    auth: Authorization<Write, User>,
+    conn: DbConn,
+) -> Api<AnnouncementData> {
+    let user = User::get(&conn, auth.0.user_id)?;
+    let announcement = Announcement::get(&conn, id)?;
+    announcement.unreact(&conn, &user, &emoji)?;
+    let reactions = announcement.reactions(&conn, Some(&user))?;
+    Ok(Json(announcement_data(announcement, reactions)))
+}
+
+fn announcement_data(announcement: Announcement, reactions: Vec<Reaction>) -> AnnouncementData {
+    let date = |date: NaiveDateTime| date.format("%Y-%m-%dT%H:%M:%SZ").to_string();
+    AnnouncementData {
+        reactions: reactions
+            .into_iter()
+            .map(|reaction| AnnouncementReactionData {
+                emoji: reaction.emoji,
+                count: reaction.count,
+                me: reaction.me,
+            })
+            .collect(),
+        id: announcement.id,
+        content: announcement.content_html.to_string(),
+        starts_at: announcement.starts_at.map(date),
+        ends_at: announcement.ends_at.map(date),
+        creation_date: date(announcement.creation_date),
+    }
+}
@@ -0,0 +1,53 @@
+use rocket_contrib::json::Json;
+
+use crate::api::{authorization::*, Api};
+use plume_api::medias::*;
+use plume_models::{
+    db_conn::DbConn,
+    media_variants,
+    medias::Media,
+    rate_limits::{ApiRead, RateLimit},
+    Error,
+};
+
+/// A media of the user, with the URLs of its smaller copies.
+#[get("/medias/<id>")]
+pub fn get(
+    _limit: RateLimit<ApiRead>,
+    id: i32,
+    auth: Authorization<Read, Media>,
+    conn: DbConn,
+) -> Api<MediaData> {
+    let media = Media::get(&conn, id)?;
+    if media.owner_id != auth.0.user_id {
+        return Err(Error::Unauthorized.into());
+    }
+
+    let variants = media_variants::list(&media);
+    let srcset = variants
+        .iter()
+        .filter_map(|v| {
+            media_variants::srcset(&media, &v.format).map(|s| (v.mime_type().to_owned(), s))
+        })
+        .collect();
+    Ok(Json(MediaData {
+        id: media.id,
+        url: media.url()?,
+        variants: variants
+            .iter()
+            .filter_map(|v| {
+                Some(MediaVariantData {
+                    size: v.size.clone(),
+                    mime_type: v.mime_type().to_owned(),
+                    width: v.width,
+                    height: v.height,
+                    url: v.url().ok()?,
+                })
+            })
+            .collect(),
+        srcset,
+        alt_text: media.alt_text,
+        sensitive: media.sensitive,
+        content_warning: media.content_warning,
+    }))
+}
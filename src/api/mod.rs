@@ -2,11 +2,22 @@
 use rocket::{
     request::{Form, Request},
     response::{self, Responder},
+    State,
 };
 use rocket_contrib::json::Json;
 
+use std::sync::{Arc, Mutex};
+
+use crate::mail::{send_lockout_warning, Mailer};
 use plume_common::utils::random_hex;
-use plume_models::{api_tokens::*, apps::App, db_conn::DbConn, users::User, Error};
+use plume_models::{
+    api_tokens::*,
+    apps::App,
+    db_conn::DbConn,
+    failed_logins::{LoginError, LoginGuard},
+    rate_limits::{Login, RateLimit},
+    Error,
+};
 
 type Api<T> = Result<Json<T>, ApiError>;
 
@@ -30,6 +41,21 @@ impl<'r> Responder<'r> for ApiError {
                 "error": "You are not authorized to access this resource"
             }))
             .respond_to(req),
+            Error::InvalidValue => Json(json!({
+                "error": "Invalid value"
+            }))
+            .respond_to(req),
+            Error::WeakPassword(problems) => Json(json!({
+                "error": "Invalid password",
+                "problems": problems
+                    .iter()
+                    .map(|problem| json!({
+                        "code": problem.code(),
+                        "message": problem.message(),
+                    }))
+                    .collect::<Vec<_>>(),
+            }))
+            .respond_to(req),
             _ => Json(json!({
                 "error": "Server error"
             }))
@@ -48,10 +74,17 @@ pub struct OAuthRequest {
 }
 
 #[get("/oauth2?<query..>")]
-pub fn oauth(query: Form<OAuthRequest>, conn: DbConn) -> Result<Json<serde_json::Value>, ApiError> {
+pub fn oauth(
+    _limit: RateLimit<Login>,
+    query: Form<OAuthRequest>,
+    guard: LoginGuard,
+    mail: State<'_, Arc<Mutex<Mailer>>>,
+    conn: DbConn,
+) -> Result<Json<serde_json::Value>, ApiError> {
     let app = App::find_by_client_id(&conn, &query.client_id)?;
     if app.client_secret == query.client_secret {
-        if let Ok(user) = User::login(&conn, &query.username, &query.password) {
+        let login = guard.login(&conn, &query.username, &query.password);
+        if let Ok(user) = login {
             let token = ApiToken::insert(
                 &conn,
                 NewApiToken {
@@ -64,7 +97,15 @@ pub fn oauth(query: Form<OAuthRequest>, conn: DbConn) -> Result<Json<serde_json:
             Ok(Json(json!({
                 "token": token.value
             })))
+        } else if let Err(LoginError::Locked(until)) = login {
+            Ok(Json(json!({
+                "error": "Too many failed attempts",
+                "retry_after": until.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+            })))
         } else {
+            if let Err(LoginError::Invalid(Some(user))) = login {
+                send_lockout_warning(&mail, &user);
+            }
             Ok(Json(json!({
                 "error": "Invalid credentials"
             })))
@@ -78,4 +119,17 @@ pub fn oauth(query: Form<OAuthRequest>, conn: DbConn) -> Result<Json<serde_json:
 
 pub mod apps;
 pub mod authorization;
+pub mod autocomplete;
+pub mod blogs;
+pub mod directory;
+pub mod instance;
+pub mod medias;
+pub mod notifications;
+pub mod pagination;
 pub mod posts;
+pub mod profiles;
+pub mod recommendations;
+pub mod search;
+pub mod stats;
+pub mod trends;
+pub mod users;
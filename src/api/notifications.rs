@@ -0,0 +1,169 @@
+use rocket::request::LenientForm;
+use rocket_contrib::json::Json;
+
+use crate::api::{
+    authorization::*,
+    pagination::{PageParams, Paginated},
+    Api, ApiError,
+};
+use plume_api::notifications::*;
+use plume_models::{
+    db_conn::DbConn,
+    notification_policies::{NotificationPolicy, PolicyAction},
+    notifications::{Notification, NotificationType},
+    rate_limits::{ApiRead, RateLimit},
+    users::User,
+    Connection, Error, CONFIG,
+};
+
+/// The notifications of the user, the most recent first, with cursor-based pagination.
+///
+/// `types` is a comma-separated list of notification types, like `mention,comment`, and
+/// `unread` only lists the notifications that were not read yet.
+#[get("/notifications?<types>&<unread>&<page..>")]
+pub fn list(
+    _limit: RateLimit<ApiRead>,
+    types: Option<String>,
+    unread: Option<bool>,
+    page: LenientForm<PageParams>,
+    auth: Authorization<Read, Notification>,
+    conn: DbConn,
+) -> Result<Paginated<NotificationData>, ApiError> {
+    let user = User::get(&conn, auth.0.user_id)?;
+    let types = types
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .filter(|name| !name.is_empty())
+        .map(|name| NotificationType::from_name(name.trim()).ok_or(Error::InvalidValue))
+        .collect::<Result<Vec<_>, _>>()?;
+    let cursor = page.cursor();
+    let notifications =
+        Notification::list_for_user(&conn, &user, &types, unread.unwrap_or(false), cursor)?;
+    Ok(Paginated::new(notifications, cursor, |n| n.id)
+        .filter_map(|n| Some(notification_data(&conn, n))))
+}
+
+/// Marks some notifications of the user as read, or all of them.
+#[post("/notifications/read", data = "<payload>")]
+pub fn mark_read(
+    auth: Authorization<Write, Notification>,
+    payload: Json<MarkReadData>,
+    conn: DbConn,
+) -> Api<MarkedReadData> {
+    let user = User::get(&conn, auth.0.user_id)?;
+    let marked = Notification::mark_read(&conn, &user, payload.ids.as_deref())?;
+    Ok(Json(MarkedReadData {
+        marked,
+        unread: Notification::count_unread(&conn, &user)?,
+    }))
+}
+
+/// The notifications that the policy of the user filtered, the most recent first.
+#[get("/notifications/filtered?<page..>")]
+pub fn filtered(
+    _limit: RateLimit<ApiRead>,
+    page: LenientForm<PageParams>,
+    auth: Authorization<Read, Notification>,
+    conn: DbConn,
+) -> Result<Paginated<NotificationData>, ApiError> {
+    let user = User::get(&conn, auth.0.user_id)?;
+    let cursor = page.cursor();
+    let notifications = Notification::list_filtered(&conn, &user, cursor)?;
+    Ok(Paginated::new(notifications, cursor, |n| n.id)
+        .filter_map(|n| Some(notification_data(&conn, n))))
+}
+
+/// Moves some filtered notifications with the others, or all of them.
+///
+/// Returns how many were accepted.
+#[post("/notifications/filtered/accept", data = "<payload>")]
+pub fn accept_filtered(
+    auth: Authorization<Write, Notification>,
+    payload: Json<ReviewFilteredData>,
+    conn: DbConn,
+) -> Api<usize> {
+    let user = User::get(&conn, auth.0.user_id)?;
+    Ok(Json(Notification::accept_filtered(
+        &conn,
+        &user,
+        payload.ids.as_deref(),
+    )?))
+}
+
+/// Deletes some filtered notifications, or all of them.
+///
+/// Returns how many were dismissed.
+#[post("/notifications/filtered/dismiss", data = "<payload>")]
+pub fn dismiss_filtered(
+    auth: Authorization<Write, Notification>,
+    payload: Json<ReviewFilteredData>,
+    conn: DbConn,
+) -> Api<usize> {
+    let user = User::get(&conn, auth.0.user_id)?;
+    Ok(Json(Notification::dismiss_filtered(
+        &conn,
+        &user,
+        payload.ids.as_deref(),
+    )?))
+}
+
+#[get("/notifications/policy")]
+pub fn policy(
+    _limit: RateLimit<ApiRead>,
+    auth: Authorization<Read, Notification>,
+    conn: DbConn,
+) -> Api<NotificationPolicyData> {
+    let policy = NotificationPolicy::for_user(&conn, auth.0.user_id)?;
+    Ok(Json(policy_data(&policy)))
+}
+
+/// Changes what to do with the notifications of the accounts the user may not know.
+#[put("/notifications/policy", data = "<payload>")]
+pub fn set_policy(
+    auth: Authorization<Write, Notification>,
+    payload: Json<NotificationPolicyData>,
+    conn: DbConn,
+) -> Api<NotificationPolicyData> {
+    let action = |name: &str| PolicyAction::from_name(name).ok_or(Error::InvalidValue);
+    let policy = NotificationPolicy::set(
+        &conn,
+        auth.0.user_id,
+        action(&payload.not_following)?,
+        action(&payload.new_accounts)?,
+        action(&payload.no_avatar)?,
+    )?;
+    Ok(Json(policy_data(&policy)))
+}
+
+fn policy_data(policy: &NotificationPolicy) -> NotificationPolicyData {
+    NotificationPolicyData {
+        not_following: policy.for_not_following().name().to_owned(),
+        new_accounts: policy.for_new_accounts().name().to_owned(),
+        no_avatar: policy.for_no_avatar().name().to_owned(),
+    }
+}
+
+fn notification_data(conn: &Connection, notification: Notification) -> NotificationData {
+    NotificationData {
+        id: notification.id,
+        notification_type: notification.notification_type().name().to_owned(),
+        read: notification.read,
+        creation_date: notification
+            .creation_date
+            .format("%Y-%m-%dT%H:%M:%SZ")
+            .to_string(),
+        account: notification.get_actor(conn).ok().map(|user| user.fqn),
+        post_id: notification.get_post(conn).map(|post| post.id),
+        url: notification.get_url(conn).map(|url| {
+            if url.starts_with('/') {
+                format!("https://{}{}", CONFIG.base_url, url)
+            } else {
+                url
+            }
+        }),
+        count: notification.count,
+        group_key: notification.group_key,
+        kind: notification.kind,
+    }
+}
@@ -0,0 +1,105 @@
+use rocket::{
+    request::Request,
+    response::{self, Responder},
+};
+use rocket_contrib::json::Json;
+use serde::Serialize;
+
+use plume_models::{Cursor, CONFIG};
+
+const DEFAULT_LIMIT: i64 = 20;
+const MAX_LIMIT: i64 = 40;
+
+/// The page of a list a client asked for: `limit` items older than `max_id`, or newer
+/// than `min_id`.
+#[derive(Clone, Copy, Default, FromForm)]
+pub struct PageParams {
+    max_id: Option<i32>,
+    min_id: Option<i32>,
+    limit: Option<i64>,
+}
+
+impl PageParams {
+    pub fn cursor(self) -> Cursor {
+        Cursor {
+            max_id: self.max_id,
+            min_id: self.min_id,
+            limit: self.limit.unwrap_or(DEFAULT_LIMIT).max(1).min(MAX_LIMIT),
+        }
+    }
+}
+
+/// A page of a list, the most recent items first, with `Link` headers pointing to the
+/// next (older) and previous (newer) pages.
+pub struct Paginated<T> {
+    items: Vec<T>,
+    /// The IDs of the first and last items, if there may be more items after them
+    prev_id: Option<i32>,
+    next_id: Option<i32>,
+}
+
+impl<T> Paginated<T> {
+    pub fn new(items: Vec<T>, cursor: Cursor, id: impl Fn(&T) -> i32) -> Self {
+        let next_id = items
+            .last()
+            .filter(|_| items.len() as i64 >= cursor.limit)
+            .map(&id);
+        let prev_id = items.first().map(&id);
+        Paginated {
+            items,
+            prev_id,
+            next_id,
+        }
+    }
+
+    /// Converts the items, keeping the links to the other pages.
+    pub fn filter_map<U>(self, f: impl FnMut(T) -> Option<U>) -> Paginated<U> {
+        Paginated {
+            items: self.items.into_iter().filter_map(f).collect(),
+            prev_id: self.prev_id,
+            next_id: self.next_id,
+        }
+    }
+}
+
+impl<'r, T: Serialize> Responder<'r> for Paginated<T> {
+    fn respond_to(self, req: &Request<'_>) -> response::Result<'r> {
+        let mut links = vec![];
+        if let Some(next_id) = self.next_id {
+            links.push(format!(
+                "<{}>; rel=\"next\"",
+                page_url(req, "max_id", next_id)
+            ));
+        }
+        if let Some(prev_id) = self.prev_id {
+            links.push(format!(
+                "<{}>; rel=\"prev\"",
+                page_url(req, "min_id", prev_id)
+            ));
+        }
+        let mut response = Json(self.items).respond_to(req)?;
+        if !links.is_empty() {
+            response.set_raw_header("Link", links.join(", "));
+        }
+        Ok(response)
+    }
+}
+
+/// The URL of the current request, with another cursor.
+fn page_url(req: &Request<'_>, param: &str, id: i32) -> String {
+    let mut query = req
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|p| !p.is_empty() && !p.starts_with("max_id=") && !p.starts_with("min_id="))
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+    query.push(format!("{}={}", param, id));
+    format!(
+        "https://{}{}?{}",
+        CONFIG.base_url,
+        req.uri().path(),
+        query.join("&")
+    )
+}
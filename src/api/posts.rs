@@ -1,23 +1,56 @@
 use chrono::NaiveDateTime;
+use rocket::request::LenientForm;
 use rocket_contrib::json::Json;
+use tracing::warn;
 
-use crate::api::{authorization::*, Api, ApiError};
+use crate::api::{
+    authorization::*,
+    pagination::{PageParams, Paginated},
+    Api, ApiError,
+};
 use plume_api::posts::*;
 use plume_common::{activity_pub::broadcast, utils::md_to_html};
 use plume_models::{
-    blogs::Blog, db_conn::DbConn, instance::Instance, medias::Media, mentions::*, post_authors::*,
-    posts::*, safe_string::SafeString, tags::*, timeline::*, users::User, Error, PlumeRocket,
-    CONFIG,
+    blogs::Blog,
+    crossposts::{Crosspost, CrosspostOptOut},
+    db_conn::DbConn,
+    groups,
+    instance::Instance,
+    medias::Media,
+    mentions::*,
+    post_attachments::PostAttachment,
+    post_authors::*,
+    post_reviews::PostReview,
+    posts::*,
+    rate_limits::{ApiRead, RateLimit},
+    related_posts::*,
+    safe_string::SafeString,
+    tags::*,
+    timeline::*,
+    users::User,
+    Error, PlumeRocket, CONFIG,
 };
 
 #[get("/posts/<id>")]
-pub fn get(id: i32, auth: Option<Authorization<Read, Post>>, conn: DbConn) -> Api<PostData> {
+pub fn get(
+    _limit: RateLimit<ApiRead>,
+    id: i32,
+    auth: Option<Authorization<Read, Post>>,
+    conn: DbConn,
+) -> Api<PostData> {
     let user = auth.and_then(|a| User::get(&conn, a.0.user_id).ok());
     let post = Post::get(&conn, id)?;
 
+    // Drafts are only visible to their authors, and to the editors who review them
     if !post.published
         && !user
-            .and_then(|u| post.is_author(&conn, u.id).ok())
+            .and_then(|u| {
+                Some(
+                    post.is_author(&conn, u.id).ok()?
+                        || (PostReview::is_pending(&conn, post.id).ok()?
+                            && u.can_publish_in(&conn, &post.get_blog(&conn).ok()?).ok()?),
+                )
+            })
             .unwrap_or(false)
     {
         return Err(Error::Unauthorized.into());
@@ -34,65 +67,66 @@ pub fn get(id: i32, auth: Option<Authorization<Read, Post>>, conn: DbConn) -> Ap
             .into_iter()
             .map(|t| t.tag)
             .collect(),
+        toc: toc_data(&conn, &post),
+        attachments: attachment_data(&conn, &post),
 
         id: post.id,
         title: post.title,
         subtitle: post.subtitle,
+        excerpt: post.excerpt,
         content: post.content.to_string(),
         source: Some(post.source),
         blog_id: post.blog_id,
         published: post.published,
         license: post.license,
         cover_id: post.cover_id,
+        canonical_url: post.canonical_url,
+        unresolved_mentions: vec![],
     }))
 }
 
-#[get("/posts?<title>&<subtitle>&<content>")]
+/// The articles matching the filters, the most recent first, with cursor-based pagination.
+#[get("/posts?<title>&<subtitle>&<content>&<page..>")]
 pub fn list(
+    _limit: RateLimit<ApiRead>,
     title: Option<String>,
     subtitle: Option<String>,
     content: Option<String>,
+    page: LenientForm<PageParams>,
+    auth: Option<Authorization<Read, Post>>,
+    conn: DbConn,
+) -> Result<Paginated<PostData>, ApiError> {
+    let user = auth.and_then(|a| User::get(&conn, a.0.user_id).ok());
+    let cursor = page.cursor();
+
+    let posts =
+        Post::list_filtered_page(&conn, title, subtitle, content, user.map(|u| u.id), cursor)?;
+
+    Ok(Paginated::new(posts, cursor, |p| p.id).filter_map(|p| post_data(&conn, p)))
+}
+
+#[get("/posts/<id>/related")]
+pub fn related(
+    _limit: RateLimit<ApiRead>,
+    id: i32,
     auth: Option<Authorization<Read, Post>>,
     conn: DbConn,
 ) -> Api<Vec<PostData>> {
     let user = auth.and_then(|a| User::get(&conn, a.0.user_id).ok());
-    let user_id = user.map(|u| u.id);
+    let post = Post::get(&conn, id)?;
+
+    if !post.published
+        && !user
+            .and_then(|u| post.is_author(&conn, u.id).ok())
+            .unwrap_or(false)
+    {
+        return Err(Error::Unauthorized.into());
+    }
 
     Ok(Json(
-        Post::list_filtered(&conn, title, subtitle, content)?
+        RelatedPost::list_for_post(&conn, &post, MAX_RELATED_POSTS as i64)?
             .into_iter()
-            .filter(|p| {
-                p.published
-                    || user_id
-                        .and_then(|u| p.is_author(&conn, u).ok())
-                        .unwrap_or(false)
-            })
-            .filter_map(|p| {
-                Some(PostData {
-                    authors: p
-                        .get_authors(&conn)
-                        .ok()?
-                        .into_iter()
-                        .map(|a| a.username)
-                        .collect(),
-                    creation_date: p.creation_date.format("%Y-%m-%d").to_string(),
-                    tags: Tag::for_post(&conn, p.id)
-                        .ok()?
-                        .into_iter()
-                        .map(|t| t.tag)
-                        .collect(),
-
-                    id: p.id,
-                    title: p.title,
-                    subtitle: p.subtitle,
-                    content: p.content.to_string(),
-                    source: Some(p.source),
-                    blog_id: p.blog_id,
-                    published: p.published,
-                    license: p.license,
-                    cover_id: p.cover_id,
-                })
-            })
+            .filter_map(|p| post_data(&conn, p))
             .collect(),
     ))
 }
@@ -132,11 +166,44 @@ pub fn create(
             }
         })
         .ok_or(ApiError(Error::NotFound))?;
+    let target = Blog::get(&conn, blog)?;
+    if target.moved_to.is_some() {
+        return Err(Error::Unauthorized.into());
+    }
+    let can_publish = author
+        .role_in(&conn, &target)
+        .map_err(|_| Error::Unauthorized)?
+        .can_publish();
+    // Articles without a license inherit the one of their blog
+    let license = match payload.license {
+        Some(ref license) => license.clone(),
+        None => target.license(&conn)?,
+    };
+    // Contributors can only submit their articles for review
+    let submitted = payload.published.unwrap_or(true) && !can_publish;
 
     if Post::find_by_slug(&conn, slug, blog).is_ok() {
         return Err(Error::InvalidValue.into());
     }
 
+    let canonical_url = match payload.canonical_url.as_deref().map(str::trim) {
+        Some(url) if url.is_empty() => None,
+        Some(url) if !url.starts_with("https://") && !url.starts_with("http://") => {
+            return Err(Error::InvalidValue.into())
+        }
+        url => url.map(str::to_owned),
+    };
+
+    // Only the audio files of the author can be attached
+    let mut attachments = vec![];
+    for id in payload.attachments.iter().flatten() {
+        let media = Media::get(&conn, *id)?;
+        if media.owner_id != author.id || !media.is_attachable() {
+            return Err(Error::Unauthorized.into());
+        }
+        attachments.push(media.id);
+    }
+
     let post = Post::insert(
         &conn,
         NewPost {
@@ -144,17 +211,15 @@ pub fn create(
             slug: slug.to_string(),
             title: payload.title.clone(),
             content: SafeString::new(content.as_ref()),
-            published: payload.published.unwrap_or(true),
-            license: payload.license.clone().unwrap_or_else(|| {
-                Instance::get_local()
-                    .map(|i| i.default_license)
-                    .unwrap_or_else(|_| String::from("CC-BY-SA"))
-            }),
+            published: payload.published.unwrap_or(true) && can_publish,
+            license,
             creation_date: date,
             ap_url: String::new(),
             subtitle: payload.subtitle.clone().unwrap_or_default(),
             source: payload.source.clone(),
             cover_id: payload.cover_id,
+            excerpt: payload.excerpt.clone().unwrap_or_default(),
+            canonical_url,
         },
     )?;
 
@@ -165,6 +230,11 @@ pub fn create(
             post_id: post.id,
         },
     )?;
+    CrosspostOptOut::set(&conn, post.id, payload.crosspost == Some(false))?;
+    PostAttachment::set_for_post(&conn, post.id, &attachments)?;
+    if submitted {
+        PostReview::submit(&conn, &post, &author)?;
+    }
 
     if let Some(ref tags) = payload.tags {
         for tag in tags {
@@ -189,20 +259,24 @@ pub fn create(
         )?;
     }
 
+    let mut mention_failures = vec![];
     if post.published {
-        for m in mentions.into_iter() {
-            Mention::from_activity(
-                &conn,
-                &Mention::build_activity(&conn, &m)?,
-                post.id,
-                true,
-                true,
-            )?;
+        let resolved = Mention::resolve_all(&conn, mentions);
+        for m in resolved.mentions {
+            Mention::from_activity(&conn, &m, post.id, true, true)?;
         }
+        mention_failures = resolved.failures;
 
         let act = post.create_activity(&conn)?;
-        let dest = User::one_by_instance(&conn)?;
-        worker.execute(move || broadcast(&author, act, dest, CONFIG.proxy().cloned()));
+        let announce = post.announce_activity(&conn)?;
+        let blog = post.get_blog(&conn)?;
+        let (dest, group_dest) = groups::split_destinations(&conn, User::one_by_instance(&conn)?)?;
+        worker.execute(move || {
+            broadcast(&author, act, dest, CONFIG.proxy().cloned());
+            broadcast(&blog, announce, group_dest, CONFIG.proxy().cloned());
+        });
+
+        Crosspost::schedule(&conn, &post)?;
     }
 
     Timeline::add_to_all_timelines(&conn, &post, Kind::Original)?;
@@ -218,19 +292,90 @@ pub fn create(
             .into_iter()
             .map(|t| t.tag)
             .collect(),
+        toc: toc_data(&conn, &post),
+        attachments: attachment_data(&conn, &post),
 
         id: post.id,
         title: post.title,
         subtitle: post.subtitle,
+        excerpt: post.excerpt,
         content: post.content.to_string(),
         source: Some(post.source),
         blog_id: post.blog_id,
         published: post.published,
         license: post.license,
         cover_id: post.cover_id,
+        canonical_url: post.canonical_url,
+        unresolved_mentions: mention_failures
+            .into_iter()
+            .map(|(mention, failure)| MentionFailureData {
+                mention,
+                reason: failure.code().to_owned(),
+            })
+            .collect(),
     }))
 }
 
+/// Publishes an article that a contributor submitted for review.
+#[post("/posts/<id>/approve")]
+pub fn approve(
+    id: i32,
+    auth: Authorization<Write, Post>,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Api<()> {
+    let editor = User::get(&conn, auth.0.user_id)?;
+    let mut post = Post::get(&conn, id)?;
+    let blog = post.get_blog(&conn)?;
+    if !editor.can_publish_in(&conn, &blog)? {
+        return Err(Error::Unauthorized.into());
+    }
+    if post.published || !PostReview::is_pending(&conn, post.id)? {
+        return Err(Error::InvalidValue.into());
+    }
+
+    post.published = true;
+    post.creation_date = chrono::Utc::now().naive_utc();
+    post.ap_url = Post::ap_url(blog.clone(), &post.slug);
+    let post = post.update(&conn)?;
+    PostReview::close(&conn, post.id)?;
+
+    let author = post
+        .get_authors(&conn)?
+        .into_iter()
+        .next()
+        .ok_or(Error::NotFound)?;
+    let (_, mentions, _) = md_to_html(
+        &post.source,
+        Some(&Instance::get_local()?.public_domain),
+        false,
+        Some(Media::get_media_processor(&conn, vec![&author])),
+    );
+    let resolved = Mention::resolve_all(&conn, mentions);
+    for m in resolved.mentions {
+        Mention::from_activity(&conn, &m, post.id, true, true)?;
+    }
+    for (mention, failure) in resolved.failures {
+        warn!(
+            "Couldn't link @{} in post {}: {}",
+            mention,
+            post.id,
+            failure.code()
+        );
+    }
+
+    let act = post.create_activity(&conn)?;
+    let announce = post.announce_activity(&conn)?;
+    let (dest, group_dest) = groups::split_destinations(&conn, User::one_by_instance(&conn)?)?;
+    rockets.worker.execute(move || {
+        broadcast(&author, act, dest, CONFIG.proxy().cloned());
+        broadcast(&blog, announce, group_dest, CONFIG.proxy().cloned());
+    });
+    Timeline::add_to_all_timelines(&conn, &post, Kind::Original)?;
+    Crosspost::schedule(&conn, &post)?;
+    Ok(Json(()))
+}
+
 #[delete("/posts/<id>")]
 pub fn delete(auth: Authorization<Write, Post>, conn: DbConn, id: i32) -> Api<()> {
     let author = User::get(&conn, auth.0.user_id)?;
@@ -241,3 +386,65 @@ pub fn delete(auth: Authorization<Write, Post>, conn: DbConn, id: i32) -> Api<()
     }
     Ok(Json(()))
 }
+
+/// The representation of a post in the API, if its authors and tags could be loaded.
+pub(crate) fn post_data(conn: &DbConn, post: Post) -> Option<PostData> {
+    Some(PostData {
+        authors: post
+            .get_authors(conn)
+            .ok()?
+            .into_iter()
+            .map(|a| a.username)
+            .collect(),
+        creation_date: post.creation_date.format("%Y-%m-%d").to_string(),
+        tags: Tag::for_post(conn, post.id)
+            .ok()?
+            .into_iter()
+            .map(|t| t.tag)
+            .collect(),
+        toc: toc_data(conn, &post),
+        attachments: attachment_data(conn, &post),
+
+        id: post.id,
+        title: post.title,
+        subtitle: post.subtitle,
+        excerpt: post.excerpt,
+        content: post.content.to_string(),
+        source: Some(post.source),
+        blog_id: post.blog_id,
+        published: post.published,
+        license: post.license,
+        cover_id: post.cover_id,
+        canonical_url: post.canonical_url,
+        unresolved_mentions: vec![],
+    })
+}
+
+/// The outline of a post, with the ids of the headings in its content.
+fn toc_data(conn: &DbConn, post: &Post) -> Vec<TocEntryData> {
+    post.table_of_contents(conn)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|e| TocEntryData {
+            level: e.level,
+            title: e.title,
+            anchor: e.anchor,
+        })
+        .collect()
+}
+
+/// The audio files attached to a post, for clients to show a player.
+fn attachment_data(conn: &DbConn, post: &Post) -> Vec<AttachmentData> {
+    PostAttachment::media_for_post(conn, post.id)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|media| AttachmentData {
+            id: media.id,
+            url: media.url().unwrap_or_default(),
+            mime_type: media.media_type().map(String::from),
+            alt_text: media.alt_text,
+            duration: media.duration,
+            bitrate: media.bitrate,
+        })
+        .collect()
+}
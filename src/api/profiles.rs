@@ -0,0 +1,88 @@
+use rocket_contrib::json::Json;
+
+use crate::api::{authorization::*, Api};
+use plume_api::profiles::*;
+use plume_models::{
+    blogs::Blog,
+    db_conn::DbConn,
+    profile_fields::{ProfileField, ProfileOwner},
+    rate_limits::{ApiRead, RateLimit},
+    users::User,
+    Error,
+};
+
+#[get("/users/<name>/fields")]
+pub fn user_fields(
+    _limit: RateLimit<ApiRead>,
+    name: String,
+    conn: DbConn,
+) -> Api<Vec<ProfileFieldData>> {
+    let user = User::find_by_fqn(&conn, &name)?;
+    fields_data(ProfileField::list(&conn, ProfileOwner::User(user.id))?)
+}
+
+#[put("/users/<name>/fields", data = "<payload>")]
+pub fn update_user_fields(
+    name: String,
+    auth: Authorization<Write, User>,
+    payload: Json<Vec<NewProfileFieldData>>,
+    conn: DbConn,
+) -> Api<Vec<ProfileFieldData>> {
+    let user = User::find_by_fqn(&conn, &name)?;
+    if user.id != auth.0.user_id {
+        return Err(Error::Unauthorized.into());
+    }
+    fields_data(ProfileField::replace(
+        &conn,
+        ProfileOwner::User(user.id),
+        &pairs(payload.into_inner()),
+    )?)
+}
+
+#[get("/blogs/<name>/fields")]
+pub fn blog_fields(
+    _limit: RateLimit<ApiRead>,
+    name: String,
+    conn: DbConn,
+) -> Api<Vec<ProfileFieldData>> {
+    let blog = Blog::find_by_fqn(&conn, &name)?;
+    fields_data(ProfileField::list(&conn, ProfileOwner::Blog(blog.id))?)
+}
+
+#[put("/blogs/<name>/fields", data = "<payload>")]
+pub fn update_blog_fields(
+    name: String,
+    auth: Authorization<Write, Blog>,
+    payload: Json<Vec<NewProfileFieldData>>,
+    conn: DbConn,
+) -> Api<Vec<ProfileFieldData>> {
+    let blog = Blog::find_by_fqn(&conn, &name)?;
+    if !User::get(&conn, auth.0.user_id)?.can_manage(&conn, &blog)? {
+        return Err(Error::Unauthorized.into());
+    }
+    fields_data(ProfileField::replace(
+        &conn,
+        ProfileOwner::Blog(blog.id),
+        &pairs(payload.into_inner()),
+    )?)
+}
+
+fn pairs(fields: Vec<NewProfileFieldData>) -> Vec<(String, String)> {
+    fields.into_iter().map(|f| (f.name, f.value)).collect()
+}
+
+fn fields_data(fields: Vec<ProfileField>) -> Api<Vec<ProfileFieldData>> {
+    Ok(Json(
+        fields
+            .into_iter()
+            .map(|f| ProfileFieldData {
+                name: f.name,
+                value: f.value,
+                value_html: f.value_html.to_string(),
+                verified_at: f
+                    .verified_at
+                    .map(|date| date.format("%Y-%m-%dT%H:%M:%SZ").to_string()),
+            })
+            .collect(),
+    ))
+}
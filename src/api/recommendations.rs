@@ -0,0 +1,45 @@
+use rocket_contrib::json::Json;
+
+use crate::api::{authorization::*, Api};
+use plume_api::recommendations::*;
+use plume_models::{db_conn::DbConn, recommendations, users::User};
+
+const MAX_LIMIT: i64 = 20;
+
+/// Local blogs and remote authors the user could follow, to fill their timelines.
+#[get("/recommendations?<limit>")]
+pub fn list(
+    auth: Authorization<Read, User>,
+    limit: Option<i64>,
+    conn: DbConn,
+) -> Api<RecommendationsData> {
+    let user = User::get(&conn, auth.0.user_id)?;
+    let limit = limit.unwrap_or(5).max(1).min(MAX_LIMIT) as usize;
+    let recommended = recommendations::for_user(&conn, &user, limit)?;
+    Ok(Json(RecommendationsData {
+        blogs: recommended
+            .blogs
+            .into_iter()
+            .map(|r| RecommendedBlogData {
+                id: r.item.id,
+                fqn: r.item.fqn,
+                title: r.item.title,
+                summary: r.item.summary_html.to_string(),
+                url: r.item.ap_url,
+                recent_articles: r.score,
+            })
+            .collect(),
+        authors: recommended
+            .authors
+            .into_iter()
+            .map(|r| RecommendedAuthorData {
+                id: r.item.id,
+                name: r.item.name(),
+                fqn: r.item.fqn,
+                summary: r.item.summary_html.to_string(),
+                url: r.item.ap_url,
+                local_followers: r.score,
+            })
+            .collect(),
+    }))
+}
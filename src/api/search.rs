@@ -0,0 +1,130 @@
+use rocket_contrib::json::Json;
+
+use crate::api::{posts::post_data, Api};
+use plume_api::search::*;
+use plume_models::{
+    api_tokens::ApiToken,
+    blogs::Blog,
+    comments::Comment,
+    db_conn::DbConn,
+    lookup::{self, Resolved},
+    rate_limits::{ApiRead, RateLimit},
+    search::{Query, SearchResult},
+    users::User,
+    PlumeRocket,
+};
+use std::str::FromStr;
+
+/// How many results of each type are returned by default, and at most.
+const DEFAULT_LIMIT: i64 = 10;
+const MAX_LIMIT: i64 = 40;
+
+/// Articles, accounts, blogs and hashtags matching `q`.
+///
+/// Articles and comments are found with the same syntax as the search page: `q` can
+/// contain field filters like `author:`, `tag:` or `type:comment`, quoted phrases, and
+/// words to exclude. Only the comments the client can see are returned.
+///
+/// When `q` is the URL of something on another instance, or the address of a remote
+/// account, authenticated clients can set `resolve` to fetch it if it isn't known yet.
+#[get("/search?<q>&<resolve>&<limit>")]
+pub fn search(
+    _limit: RateLimit<ApiRead>,
+    q: String,
+    resolve: Option<bool>,
+    limit: Option<i64>,
+    token: Option<ApiToken>,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Api<SearchData> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).max(1).min(MAX_LIMIT);
+    let q = q.trim();
+    let mut results = SearchData::default();
+    if q.is_empty() {
+        return Ok(Json(results));
+    }
+
+    let viewer = token
+        .as_ref()
+        .and_then(|token| User::get(&conn, token.user_id).ok());
+    if resolve.unwrap_or(false) && token.is_some() {
+        match lookup::resolve(&conn, q) {
+            Ok(Resolved::User(user)) => results.accounts.push(account_data(&conn, user)),
+            Ok(Resolved::Blog(blog)) => results.blogs.push(blog_data(&conn, blog)),
+            Ok(Resolved::Post(post)) if post.published => {
+                results.articles.extend(post_data(&conn, post))
+            }
+            Ok(Resolved::Comment(comment)) if comment.can_see(&conn, viewer.as_ref()) => {
+                results.comments.push(comment_data(&conn, comment))
+            }
+            _ => {}
+        }
+        if !results.articles.is_empty()
+            || !results.comments.is_empty()
+            || !results.accounts.is_empty()
+            || !results.blogs.is_empty()
+        {
+            return Ok(Json(results));
+        }
+    }
+
+    let query = Query::from_str(q).unwrap_or_default();
+    for result in rockets
+        .searcher
+        .search(&conn, query, viewer.as_ref(), (0, limit as i32))
+    {
+        match result {
+            SearchResult::Post(post) => results.articles.extend(post_data(&conn, post)),
+            SearchResult::Comment(comment) => results.comments.push(comment_data(&conn, comment)),
+        }
+    }
+    results.accounts = lookup::accounts(&conn, q, limit)?
+        .into_iter()
+        .map(|u| account_data(&conn, u))
+        .collect();
+    results.blogs = lookup::blogs(&conn, q, limit)?
+        .into_iter()
+        .map(|b| blog_data(&conn, b))
+        .collect();
+    results.hashtags = lookup::hashtags(&conn, q, limit)?;
+    Ok(Json(results))
+}
+
+fn account_data(conn: &DbConn, user: User) -> AccountData {
+    AccountData {
+        id: user.id,
+        display_name: user.name(),
+        avatar: user.avatar_url(conn),
+        url: user.ap_url.clone(),
+        fqn: user.fqn,
+    }
+}
+
+fn comment_data(conn: &DbConn, comment: Comment) -> CommentData {
+    CommentData {
+        author: comment
+            .get_author(conn)
+            .map(|author| author.fqn)
+            .unwrap_or_default(),
+        creation_date: comment.creation_date.format("%Y-%m-%d").to_string(),
+
+        id: comment.id,
+        post_id: comment.post_id,
+        in_response_to_id: comment.in_response_to_id,
+        content: comment.content.to_string(),
+        sensitive: comment.sensitive,
+        spoiler_text: comment.spoiler_text,
+        url: comment.ap_url,
+    }
+}
+
+fn blog_data(conn: &DbConn, blog: Blog) -> BlogData {
+    BlogData {
+        id: blog.id,
+        icon: blog.icon_url(conn),
+        title: blog.title.clone(),
+        summary: blog.summary.clone(),
+        url: blog.ap_url.clone(),
+        fqn: blog.fqn,
+    }
+}
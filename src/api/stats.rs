@@ -0,0 +1,46 @@
+use chrono::{Duration, Utc};
+use rocket_contrib::json::Json;
+
+use crate::api::{authorization::*, Api};
+use plume_api::stats::*;
+use plume_models::{
+    db_conn::DbConn,
+    post_views::PostView,
+    posts::Post,
+    rate_limits::{ApiRead, RateLimit},
+    users::User,
+};
+
+/// How many days of statistics are returned by default, and at most.
+const DEFAULT_DAYS: i64 = 30;
+const MAX_DAYS: i64 = 365;
+
+#[get("/stats?<days>")]
+pub fn author(
+    _limit: RateLimit<ApiRead>,
+    days: Option<i64>,
+    auth: Authorization<Read, Post>,
+    conn: DbConn,
+) -> Api<StatsData> {
+    let author = User::get(&conn, auth.0.user_id)?;
+    let days = days.unwrap_or(DEFAULT_DAYS).max(1).min(MAX_DAYS);
+    let since = Utc::now().naive_utc().date() - Duration::days(days - 1);
+
+    let days = PostView::author_stats(&conn, &author, since)?;
+    Ok(Json(StatsData {
+        views: days.iter().map(|d| d.views).sum(),
+        likes: days.iter().map(|d| d.likes).sum(),
+        reshares: days.iter().map(|d| d.reshares).sum(),
+        comments: days.iter().map(|d| d.comments).sum(),
+        days: days
+            .into_iter()
+            .map(|d| DailyStatsData {
+                date: d.day.format("%Y-%m-%d").to_string(),
+                views: d.views,
+                likes: d.likes,
+                reshares: d.reshares,
+                comments: d.comments,
+            })
+            .collect(),
+    }))
+}
@@ -0,0 +1,40 @@
+use rocket_contrib::json::Json;
+
+use crate::api::Api;
+use plume_api::trends::*;
+use plume_models::{
+    db_conn::DbConn,
+    rate_limits::{ApiRead, RateLimit},
+    trends::{TrendingPost, TrendingTag, MAX_TRENDS},
+};
+
+/// The tags and articles that got the most attention recently.
+#[get("/trends?<limit>")]
+pub fn list(_limit: RateLimit<ApiRead>, limit: Option<i64>, conn: DbConn) -> Api<TrendsData> {
+    let limit = limit
+        .unwrap_or(MAX_TRENDS as i64)
+        .max(1)
+        .min(MAX_TRENDS as i64);
+    Ok(Json(TrendsData {
+        tags: TrendingTag::list(&conn, limit)?
+            .into_iter()
+            .map(|t| TrendingTagData {
+                tag: t.tag,
+                score: t.score,
+                accounts: t.accounts,
+            })
+            .collect(),
+        posts: TrendingPost::list(&conn, limit)?
+            .into_iter()
+            .map(|(p, t)| TrendingPostData {
+                id: p.id,
+                title: p.title,
+                subtitle: p.subtitle,
+                url: p.ap_url,
+                blog_id: p.blog_id,
+                score: t.score,
+                accounts: t.accounts,
+            })
+            .collect(),
+    }))
+}
@@ -0,0 +1,282 @@
+use chrono::NaiveDateTime;
+use rocket::{http::Status, request::LenientForm, response::status};
+use rocket_contrib::json::Json;
+
+use crate::api::{
+    authorization::*,
+    pagination::{PageParams, Paginated},
+    Api, ApiError,
+};
+use plume_api::users::*;
+use plume_common::activity_pub::broadcast;
+use plume_models::{
+    account_notes::AccountNote,
+    db_conn::DbConn,
+    legal_documents::{DocumentKind, LegalDocument},
+    mutes::{Mute, MuteKind, MuteTarget},
+    password_policy, personal_data,
+    rate_limits::{ApiRead, RateLimit},
+    sessions::Session,
+    users::User,
+    Error, PlumeRocket, CONFIG,
+};
+
+/// A copy of all the personal data of the user.
+#[get("/me/data")]
+pub fn export(
+    _limit: RateLimit<ApiRead>,
+    auth: Authorization<Read, User>,
+    conn: DbConn,
+) -> Api<serde_json::Value> {
+    let user = User::get(&conn, auth.0.user_id)?;
+    Ok(Json(personal_data::export(&conn, &user)?))
+}
+
+/// Deletes the account of the user and all their content, here and on other instances.
+#[post("/me/erasure", data = "<payload>")]
+pub fn erase(
+    auth: Authorization<Write, User>,
+    payload: Json<ErasureData>,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Api<()> {
+    let user = User::get(&conn, auth.0.user_id)?;
+    if user.is_admin() || !user.check_password(&payload.password) {
+        return Err(Error::Unauthorized.into());
+    }
+    let target = User::one_by_instance(&conn)?;
+    let delete_act = personal_data::erase(&conn, &user)?;
+    rockets
+        .worker
+        .execute(move || broadcast(&user, delete_act, target, CONFIG.proxy().cloned()));
+    Ok(Json(()))
+}
+
+/// Changes the password of the user.
+#[put("/me/password", data = "<payload>")]
+pub fn change_password(
+    auth: Authorization<Write, User>,
+    payload: Json<PasswordChangeData>,
+    conn: DbConn,
+) -> Api<()> {
+    let user = User::get(&conn, auth.0.user_id)?;
+    if !user.check_password(&payload.password) {
+        return Err(Error::Unauthorized.into());
+    }
+    let email = user.email.clone().unwrap_or_default();
+    password_policy::enforce(&payload.new_password, &[&user.username, &email])?;
+    user.reset_password(&conn, &payload.new_password)?;
+    Ok(Json(()))
+}
+
+/// The current terms of the instance, and if the user accepted them.
+#[get("/me/terms")]
+pub fn terms(
+    _limit: RateLimit<ApiRead>,
+    auth: Authorization<Read, User>,
+    conn: DbConn,
+) -> Api<Vec<LegalDocumentData>> {
+    let user = User::get(&conn, auth.0.user_id)?;
+    terms_data(&conn, &user)
+}
+
+/// Accepts the current terms of the instance.
+#[post("/me/terms")]
+pub fn accept_terms(auth: Authorization<Write, User>, conn: DbConn) -> Api<Vec<LegalDocumentData>> {
+    let user = User::get(&conn, auth.0.user_id)?;
+    for document in LegalDocument::pending_for(&conn, &user)? {
+        document.accept(&conn, &user)?;
+    }
+    terms_data(&conn, &user)
+}
+
+/// Where `TermsAcceptance` sends the API requests of users who have new terms to accept.
+#[get("/me/terms/pending")]
+pub fn pending_terms() -> status::Custom<Json<serde_json::Value>> {
+    status::Custom(
+        Status::Forbidden,
+        Json(json!({
+            "error": "The new terms of the instance have to be accepted first",
+            "terms": "/api/v1/me/terms",
+        })),
+    )
+}
+
+fn terms_data(conn: &DbConn, user: &User) -> Api<Vec<LegalDocumentData>> {
+    let mut documents = vec![];
+    for kind in DocumentKind::ALL.iter() {
+        if let Some(document) = LegalDocument::latest(conn, *kind)? {
+            documents.push(LegalDocumentData {
+                kind: kind.name().to_owned(),
+                version: document.version,
+                published_at: document
+                    .creation_date
+                    .format("%Y-%m-%dT%H:%M:%SZ")
+                    .to_string(),
+                accepted: document.is_accepted_by(conn, user)?,
+            });
+        }
+    }
+    Ok(Json(documents))
+}
+
+/// The browsers in which the user is logged in, the most recent first, with cursor-based
+/// pagination.
+#[get("/me/sessions?<page..>")]
+pub fn sessions(
+    _limit: RateLimit<ApiRead>,
+    page: LenientForm<PageParams>,
+    auth: Authorization<Read, User>,
+    conn: DbConn,
+) -> Result<Paginated<SessionData>, ApiError> {
+    let cursor = page.cursor();
+    let sessions = Session::list_for_user_page(&conn, auth.0.user_id, cursor)?
+        .into_iter()
+        .map(|session| SessionData {
+            id: session.id,
+            address: session.shown_address().map(str::to_owned),
+            user_agent: session.user_agent,
+            creation_date: session
+                .creation_date
+                .format("%Y-%m-%dT%H:%M:%SZ")
+                .to_string(),
+            last_seen: session.last_seen.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        })
+        .collect::<Vec<_>>();
+    Ok(Paginated::new(sessions, cursor, |s| s.id))
+}
+
+/// Logs out one of the browsers of the user.
+#[delete("/me/sessions/<id>")]
+pub fn revoke_session(id: i32, auth: Authorization<Write, User>, conn: DbConn) -> Api<()> {
+    let session = Session::get(&conn, id)?;
+    if session.user_id != auth.0.user_id {
+        return Err(Error::Unauthorized.into());
+    }
+    session.revoke(&conn)?;
+    Ok(Json(()))
+}
+
+/// Logs out all the browsers of the user.
+#[delete("/me/sessions")]
+pub fn revoke_sessions(auth: Authorization<Write, User>, conn: DbConn) -> Api<()> {
+    Session::revoke_all(&conn, auth.0.user_id, None)?;
+    Ok(Json(()))
+}
+
+/// The private notes of the user about the accounts they follow, the most recent first,
+/// with cursor-based pagination.
+#[get("/me/notes?<page..>")]
+pub fn notes(
+    _limit: RateLimit<ApiRead>,
+    page: LenientForm<PageParams>,
+    auth: Authorization<Read, User>,
+    conn: DbConn,
+) -> Result<Paginated<AccountNoteData>, ApiError> {
+    let cursor = page.cursor();
+    let notes = AccountNote::list_for_owner_page(&conn, auth.0.user_id, cursor)?;
+    Ok(Paginated::new(notes, cursor, |(note, _)| note.id)
+        .filter_map(|(note, account)| Some(note_data(note, &account))))
+}
+
+#[get("/me/notes/<account>")]
+pub fn note(
+    _limit: RateLimit<ApiRead>,
+    account: String,
+    auth: Authorization<Read, User>,
+    conn: DbConn,
+) -> Api<AccountNoteData> {
+    let account = User::find_by_fqn(&conn, &account)?;
+    let note = AccountNote::find_for(&conn, auth.0.user_id, account.id)?;
+    Ok(Json(note_data(note, &account)))
+}
+
+/// Writes a note about a followed account, or deletes it if it is empty.
+#[put("/me/notes/<account>", data = "<payload>")]
+pub fn set_note(
+    account: String,
+    auth: Authorization<Write, User>,
+    payload: Json<NewAccountNoteData>,
+    conn: DbConn,
+) -> Api<Option<AccountNoteData>> {
+    let owner = User::get(&conn, auth.0.user_id)?;
+    let account = User::find_by_fqn(&conn, &account)?;
+    let note = AccountNote::set(&conn, &owner, &account, &payload.content)?;
+    Ok(Json(note.map(|note| note_data(note, &account))))
+}
+
+fn note_data(note: AccountNote, account: &User) -> AccountNoteData {
+    AccountNoteData {
+        account: account.fqn.clone(),
+        content: note.content,
+        updated_at: note.updated_at.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+    }
+}
+
+/// What the user muted, and did not expire yet, the most recent first, with cursor-based
+/// pagination.
+#[get("/me/mutes?<page..>")]
+pub fn mutes(
+    _limit: RateLimit<ApiRead>,
+    page: LenientForm<PageParams>,
+    auth: Authorization<Read, User>,
+    conn: DbConn,
+) -> Result<Paginated<MuteData>, ApiError> {
+    let cursor = page.cursor();
+    let mutes = Mute::list_active_page(&conn, auth.0.user_id, cursor)?
+        .into_iter()
+        .map(|mute| mute_data(&conn, mute))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Paginated::new(mutes, cursor, |m| m.id))
+}
+
+/// Mutes an account, an instance or a keyword, until `expires_at` if given.
+#[post("/me/mutes", data = "<payload>")]
+pub fn mute(
+    auth: Authorization<Write, User>,
+    payload: Json<NewMuteData>,
+    conn: DbConn,
+) -> Api<MuteData> {
+    let user = User::get(&conn, auth.0.user_id)?;
+    let target = match MuteKind::from_name(&payload.kind).ok_or(Error::InvalidValue)? {
+        MuteKind::Account => MuteTarget::Account(User::find_by_fqn(&conn, &payload.value)?.id),
+        MuteKind::Domain => MuteTarget::Domain(payload.value.clone()),
+        MuteKind::Keyword => MuteTarget::Keyword(payload.value.clone()),
+    };
+    let expires_at = match payload.expires_at {
+        Some(ref date) => Some(
+            NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%SZ")
+                .map_err(|_| Error::InvalidValue)?,
+        ),
+        None => None,
+    };
+    let mute = Mute::add(&conn, &user, target, expires_at)?;
+    Ok(Json(mute_data(&conn, mute)?))
+}
+
+#[delete("/me/mutes/<id>")]
+pub fn unmute(id: i32, auth: Authorization<Write, User>, conn: DbConn) -> Api<()> {
+    let mute = Mute::get(&conn, id)?;
+    if mute.user_id != auth.0.user_id {
+        return Err(Error::Unauthorized.into());
+    }
+    mute.delete(&conn)?;
+    Ok(Json(()))
+}
+
+fn mute_data(conn: &DbConn, mute: Mute) -> Result<MuteData, Error> {
+    let account = match mute.target_id {
+        Some(id) => Some(User::get(conn, id)?.fqn),
+        None => None,
+    };
+    Ok(MuteData {
+        id: mute.id,
+        kind: mute.mute_kind().map_or("", MuteKind::name).to_owned(),
+        account,
+        value: mute.value,
+        expires_at: mute
+            .expires_at
+            .map(|date| date.format("%Y-%m-%dT%H:%M:%SZ").to_string()),
+        creation_date: mute.creation_date.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+    })
+}
@@ -1,72 +1,206 @@
-use plume_common::activity_pub::{
-    inbox::FromId,
-    request::Digest,
-    sign::{verify_http_headers, Signable},
-};
+use plume_common::activity_pub::{inbox::FromId, sign::Signer};
 use plume_models::{
-    db_conn::DbConn, headers::Headers, inbox::inbox, instance::Instance, users::User, Error, CONFIG,
+    blog_transfer,
+    blogs::Blog,
+    db_conn::{DbConn, DbPool},
+    failed_activities::FailedActivity,
+    groups,
+    headers::Headers,
+    inbox::inbox,
+    instance::Instance,
+    maintenance::{self, Maintenance},
+    relays,
+    request_limits::{self, LimitedReader},
+    signature_pool::{owned_headers, PoolError, SignaturePool},
+    users::User,
+    worker::Worker,
+    CONFIG,
+};
+use rocket::{
+    data::*,
+    http::{HeaderMap, Status},
+    response::status,
+    Outcome::*,
+    Request, State,
 };
-use rocket::{data::*, http::Status, response::status, Outcome::*, Request};
 use rocket_contrib::json::*;
 use serde::Deserialize;
-use std::io::Read;
-use tracing::warn;
+use std::{
+    io::{self, Read},
+    sync::Arc,
+    time::Duration,
+};
+use tracing::{info_span, warn};
 
+/// How long to wait before handling an activity again, when the database is not available.
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Checks the signature of an activity, and leaves it to the worker pool, unless too many
+/// activities are already waiting for it.
+///
+/// Handling an activity can mean fetching many objects from other instances: it would keep
+/// a Rocket thread (and a database connection) busy for too long. The signature itself is
+/// checked by the signature pool, not to keep a CPU busy when many activities come at once.
 pub fn handle_incoming(
     conn: DbConn,
     data: SignedJson<serde_json::Value>,
     headers: Headers<'_>,
-) -> Result<String, status::BadRequest<&'static str>> {
+    pool: State<'_, DbPool>,
+    worker: State<'_, Arc<Worker>>,
+    signatures: State<'_, Arc<SignaturePool>>,
+) -> Result<String, status::Custom<&'static str>> {
     let act = data.1.into_inner();
-    let sig = data.0;
+    let body = data.0;
+    let headers = Arc::new(owned_headers(&headers.0));
 
     let activity = act.clone();
     let actor_id = activity["actor"]
         .as_str()
         .or_else(|| activity["actor"]["id"].as_str())
-        .ok_or(status::BadRequest(Some("Missing actor id for activity")))?;
+        .ok_or_else(|| bad_request("Missing actor id for activity"))?;
+    let _span = info_span!(
+        "inbox",
+        actor = %actor_id,
+        activity = %activity["id"].as_str().unwrap_or_default(),
+        kind = %activity["type"].as_str().unwrap_or_default()
+    )
+    .entered();
 
-    let actor = User::from_id(&conn, actor_id, None, CONFIG.proxy())
-        .expect("instance::shared_inbox: user error");
-    if !verify_http_headers(&actor, &headers.0, &sig).is_secure() && !act.clone().verify(&actor) {
-        // maybe we just know an old key?
-        actor
-            .refetch(&conn)
-            .and_then(|_| User::get(&conn, actor.id))
-            .and_then(|u| {
-                if verify_http_headers(&u, &headers.0, &sig).is_secure() || act.clone().verify(&u) {
-                    Ok(())
-                } else {
-                    Err(Error::Signature)
-                }
-            })
-            .map_err(|_| {
+    if let Some(relay) = relays::sender(&conn, &act) {
+        if !check_signature(&signatures, &relay, &headers, &body, &act)? {
+            warn!(
+                relay = %relay.actor_id,
+                ?headers,
+                "Rejected invalid activity supposedly from a relay"
+            );
+            return Err(bad_request("Invalid signature"));
+        }
+    } else if groups::is_group_announce(&act) {
+        // Groups like Lemmy communities relay the activities of their members
+        let group = groups::fetch_group(&conn, actor_id)
+            .map_err(|_| bad_request("Can't fetch the group"))?;
+        if !check_signature(&signatures, &group, &headers, &body, &act)? {
+            warn!(
+                group = %group.fqn,
+                ?headers,
+                "Rejected invalid activity supposedly from a group"
+            );
+            return Err(bad_request("Invalid signature"));
+        }
+    } else if blog_transfer::is_blog_move(&conn, &act) {
+        // Blogs don't publish anything, except when they move
+        let blog =
+            Blog::find_by_ap_url(&conn, actor_id).map_err(|_| bad_request("Unknown blog"))?;
+        if !check_signature(&signatures, &blog, &headers, &body, &act)? {
+            warn!(
+                blog = %blog.fqn,
+                ?headers,
+                "Rejected invalid activity supposedly from a blog"
+            );
+            return Err(bad_request("Invalid signature"));
+        }
+    } else {
+        let actor = User::from_id(&conn, actor_id, None, CONFIG.proxy())
+            .expect("instance::shared_inbox: user error");
+        if !check_signature(&signatures, &actor, &headers, &body, &act)? {
+            // maybe we just know an old key?
+            let refetched = actor
+                .refetch(&conn)
+                .and_then(|_| User::get(&conn, actor.id))
+                .ok();
+            let valid = match refetched {
+                Some(ref user) => check_signature(&signatures, user, &headers, &body, &act)?,
+                None => false,
+            };
+            if !valid {
                 warn!(
-                    "Rejected invalid activity supposedly from {}, with headers {:?}",
-                    actor.username, headers.0
+                    user = %actor.username,
+                    ?headers,
+                    "Rejected invalid activity with an invalid signature"
                 );
-                status::BadRequest(Some("Invalid signature"))
-            })?;
+                return Err(bad_request("Invalid signature"));
+            }
+        }
     }
 
     if Instance::is_blocked(&conn, actor_id)
-        .map_err(|_| status::BadRequest(Some("Can't tell if instance is blocked")))?
+        .map_err(|_| bad_request("Can't tell if instance is blocked"))?
     {
         return Ok(String::new());
     }
 
-    Ok(match inbox(&conn, act) {
-        Ok(_) => String::new(),
-        Err(e) => {
-            warn!("Shared inbox error: {:?}", e);
-            format!("Error: {:?}", e)
+    let pool = pool.clone();
+    let retry = worker.inner().clone();
+    let queued = worker.try_execute(&plume_models::inbox::QUEUE, move || {
+        handle(retry, pool, act)
+    });
+    if !queued {
+        // The other instance will deliver it again later
+        return Err(status::Custom(
+            Status::ServiceUnavailable,
+            "Too many activities to handle, try again later",
+        ));
+    }
+    Ok(String::new())
+}
+
+/// Handles an activity on the worker pool.
+///
+/// If it fails, it is saved to be handled again later (see `FailedActivity`): the other
+/// instance already got an answer, and won't deliver it again. The activities received just
+/// before a maintenance wait for its end.
+fn handle(worker: Arc<Worker>, pool: DbPool, act: serde_json::Value) {
+    let delay = match pool.get() {
+        Ok(ref conn) if Maintenance::is_active(conn) => {
+            Duration::from_secs(maintenance::RETRY_AFTER)
+        }
+        Ok(conn) => {
+            if let Err(e) = inbox(&conn, act.clone()) {
+                warn!(error = ?e, "Shared inbox error");
+                if let Err(e) = FailedActivity::record(&conn, &act, &format!("{:?}", e)) {
+                    warn!(error = ?e, "Failed to save the activity to handle it again");
+                }
+            }
+            return;
         }
-    })
+        Err(_) => {
+            warn!("Failed to get database connection, the activity will be handled later");
+            RETRY_DELAY
+        }
+    };
+    let retry = worker.clone();
+    worker.execute_after(delay, move || handle(retry, pool, act));
 }
 
-const JSON_LIMIT: u64 = 1 << 20;
+pub fn bad_request(message: &'static str) -> status::Custom<&'static str> {
+    status::Custom(Status::BadRequest, message)
+}
 
-pub struct SignedJson<T>(pub Digest, pub Json<T>);
+/// Whether `signer` signed the activity, on the signature pool.
+fn check_signature<S>(
+    signatures: &SignaturePool,
+    signer: &S,
+    headers: &Arc<HeaderMap<'static>>,
+    body: &Arc<String>,
+    act: &serde_json::Value,
+) -> Result<bool, status::Custom<&'static str>>
+where
+    S: Signer + Clone + Send + 'static,
+{
+    signatures
+        .is_signed_by(signer, headers, body, act)
+        .map_err(|err| match err {
+            // The other instance will deliver it again later
+            PoolError::Busy => status::Custom(
+                Status::ServiceUnavailable,
+                "Too many activities to check, try again later",
+            ),
+            PoolError::Failed => bad_request("Invalid signature"),
+        })
+}
+
+/// The body of an activity, kept to check its digest, and the activity.
+pub struct SignedJson<T>(pub Arc<String>, pub Json<T>);
 
 impl<'a, T: Deserialize<'a>> FromData<'a> for SignedJson<T> {
     type Error = JsonError<'a>;
@@ -77,11 +211,18 @@ impl<'a, T: Deserialize<'a>> FromData<'a> for SignedJson<T> {
         r: &Request<'_>,
         d: Data,
     ) -> Transform<rocket::data::Outcome<Self::Owned, Self::Error>> {
-        let size_limit = r.limits().get("json").unwrap_or(JSON_LIMIT);
+        let limit = CONFIG.body_limits.inbox;
+        if request_limits::announces_more(r, limit) {
+            let err = io::Error::new(io::ErrorKind::Other, "the activity is too large");
+            return Transform::Borrowed(Failure((Status::PayloadTooLarge, JsonError::Io(err))));
+        }
         let mut s = String::with_capacity(512);
-        match d.open().take(size_limit).read_to_string(&mut s) {
+        match LimitedReader::new(d.open(), limit).read_to_string(&mut s) {
             Ok(_) => Transform::Borrowed(Success(s)),
-            Err(e) => Transform::Borrowed(Failure((Status::BadRequest, JsonError::Io(e)))),
+            Err(e) => Transform::Borrowed(Failure((
+                request_limits::error_status(&e),
+                JsonError::Io(e),
+            ))),
         }
     }
 
@@ -91,7 +232,7 @@ impl<'a, T: Deserialize<'a>> FromData<'a> for SignedJson<T> {
     ) -> rocket::data::Outcome<Self, Self::Error> {
         let string = o.borrowed()?;
         match serde_json::from_str(string) {
-            Ok(v) => Success(SignedJson(Digest::from_body(string), Json(v))),
+            Ok(v) => Success(SignedJson(Arc::new(string.to_owned()), Json(v))),
             Err(e) => {
                 if e.is_data() {
                     Failure((Status::UnprocessableEntity, JsonError::Parse(string, e)))
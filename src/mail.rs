@@ -1,6 +1,15 @@
 #![warn(clippy::too_many_arguments)]
 use lettre_email::Email;
+use plume_models::{
+    inactivity::{self, Workflow},
+    lettre::Transport,
+    thread_subscriptions::ThreadSubscription,
+    users::User,
+    Connection, Result, CONFIG,
+};
 use std::env;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
 
 pub use self::mailer::*;
 
@@ -91,3 +100,152 @@ pub fn build_mail(dest: String, subject: String, body: String) -> Option<Email>
         .build()
         .ok()
 }
+
+/// Sends an email right away, returning whether it could be.
+pub fn send_now(mailer: &Arc<Mutex<Mailer>>, dest: String, subject: String, body: String) -> bool {
+    match (
+        build_mail(dest, subject, body),
+        &mut *mailer.lock().unwrap(),
+    ) {
+        (Some(message), Some(mail)) => mail
+            .send(message.into())
+            .map_err(|_| warn!("Couldn't send email"))
+            .is_ok(),
+        _ => false,
+    }
+}
+
+/// Warns the owner of an account that was just locked, after too many failed logins.
+pub fn send_lockout_warning(mailer: &Arc<Mutex<Mailer>>, user: &User) {
+    let dest = match user.email.clone() {
+        Some(dest) => dest,
+        None => return,
+    };
+    let body = format!(
+        "Someone failed to log in to your account ({}) several times in a row, so logging in has been disabled for a while.\n\n\
+        If it wasn't you, someone may be trying to guess your password: you can change it here: https://{}/password-reset\n",
+        user.username, CONFIG.base_url
+    );
+    if let Some(message) = build_mail(dest, "Suspicious login attempts".to_owned(), body) {
+        if let Some(ref mut mail) = *mailer.lock().unwrap() {
+            mail.send(message.into())
+                .map_err(|_| warn!("Couldn't send account lockout email"))
+                .ok();
+        }
+    }
+}
+
+/// Emails the subscribers of comment threads about the comments they didn't receive yet.
+pub fn send_thread_notifications(conn: &Connection, mailer: &Arc<Mutex<Mailer>>) -> Result<()> {
+    if mailer.lock().unwrap().is_none() {
+        // No mail server: the comments are skipped, instead of piling up until one is set
+        for digest in ThreadSubscription::pending_digests(conn)? {
+            ThreadSubscription::mark_emailed(conn, digest)?;
+        }
+        return Ok(());
+    }
+    for digest in ThreadSubscription::pending_digests(conn)? {
+        let dest = match digest.user.email.clone() {
+            Some(dest) => dest,
+            None => continue,
+        };
+        let post_url = format!("https://{}{}", CONFIG.base_url, digest.post.url(conn)?);
+        let mut body = format!(
+            "There are {} new comment(s) on \"{}\":\n\n",
+            digest.comments.len(),
+            digest.post.title
+        );
+        for comment in &digest.comments {
+            body += &format!(
+                "- {}: {}#comment-{}\n",
+                comment.get_author(conn)?.name(),
+                post_url,
+                comment.id
+            );
+        }
+        body += "\nYou can stop following this thread from the comments section of the article.\n";
+
+        if let Some(message) = build_mail(
+            dest,
+            format!("New comments on \"{}\"", digest.post.title),
+            body,
+        ) {
+            if let Some(ref mut mail) = *mailer.lock().unwrap() {
+                if mail.send(message.into()).is_err() {
+                    warn!("Couldn't send thread notification email");
+                    continue;
+                }
+            }
+        }
+        ThreadSubscription::mark_emailed(conn, digest)?;
+    }
+    Ok(())
+}
+
+/// Tells the owners of the accounts that are never or not used anymore that they will be
+/// frozen or deleted if they don't log in.
+pub fn send_inactivity_reminders(conn: &Connection, mailer: &Arc<Mutex<Mailer>>) -> Result<()> {
+    for reminder in inactivity::pending_reminders(conn, &CONFIG.inactivity)? {
+        let dest = match reminder.user.email.clone() {
+            Some(dest) => dest,
+            None => continue,
+        };
+        let deadline = reminder.deadline.format("%Y-%m-%d");
+        let consequence = if reminder.deletion {
+            format!(
+                "it will be deleted on {}, with everything you published",
+                deadline
+            )
+        } else {
+            format!(
+                "it will be deactivated on {}, and deleted later with everything you published",
+                deadline
+            )
+        };
+        let (subject, situation) = match reminder.workflow {
+            Workflow::Inactive if reminder.user.frozen_at.is_some() => (
+                "Your account will be deleted",
+                format!(
+                    "Your account ({}) on {} was deactivated, as it had not been used for a long time",
+                    reminder.user.username, CONFIG.base_url
+                ),
+            ),
+            Workflow::Unconfirmed => (
+                "You never logged in to your account",
+                format!(
+                    "You created an account ({}) on {}, but you never logged in",
+                    reminder.user.username, CONFIG.base_url
+                ),
+            ),
+            Workflow::Inactive => (
+                "Your account is not used anymore",
+                format!(
+                    "Your account ({}) on {} has not been used for a long time",
+                    reminder.user.username, CONFIG.base_url
+                ),
+            ),
+        };
+        let body = format!(
+            "{}: {}.\n\n\
+            To keep it, you just have to log in before, which reactivates it if needed: \
+            https://{}/login\n",
+            situation, consequence, CONFIG.base_url
+        );
+
+        // Only the owners who were actually reminded can lose their account
+        let message = match build_mail(dest, subject.to_owned(), body) {
+            Some(message) => message,
+            None => continue,
+        };
+        if let Some(ref mut mail) = *mailer.lock().unwrap() {
+            if mail.send(message.into()).is_err() {
+                warn!("Couldn't send inactivity reminder email");
+                continue;
+            }
+        } else {
+            return Ok(());
+        }
+        inactivity::mark_reminded(conn, &reminder.user)?;
+    }
+    Ok(())
+}
@@ -10,20 +10,44 @@ extern crate serde_json;
 
 use clap::App;
 use diesel::r2d2::ConnectionManager;
+use plume_common::activity_pub::broadcast;
 use plume_models::{
-    db_conn::{DbPool, PragmaForeignKey},
+    blocklists,
+    config::{self, LogFormat},
+    crossposts::Crosspost,
+    db_conn::{ConnectionSettings, DbPool},
+    failed_activities::FailedActivity,
+    failed_deliveries::FailedDelivery,
+    failed_logins,
+    federation_digests::FederationDigest,
+    inactivity,
     instance::Instance,
+    ip_records::IpRecord,
+    keys::KEY_STORE,
+    legal_documents::TermsAcceptance,
+    maintenance::{Maintenance, MaintenanceMode},
+    media_gc, media_scan, media_variants,
     migrations::IMPORTED_MIGRATIONS,
+    mutes::Mute,
+    post_views::PostView,
+    profile_fields::ProfileField,
+    related_posts::RelatedPostsActor,
     remote_fetch_actor::RemoteFetchActor,
+    request_ids::RequestSpans,
     search::{actor::SearchActor, Searcher as UnmanagedSearcher},
+    sessions::Session,
+    severed_relationships::Severance,
+    signature_pool::SignaturePool,
+    trends,
+    worker::Worker,
     Connection, CONFIG,
 };
 use rocket_csrf::CsrfFairingBuilder;
-use scheduled_thread_pool::ScheduledThreadPool;
 use std::process::exit;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tracing::warn;
+use tracing::{info, warn};
+use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
 init_i18n!(
     "plume", af, ar, bg, ca, cs, cy, da, de, el, en, eo, es, eu, fa, fi, fr, gl, he, hi, hr, hu,
@@ -33,10 +57,13 @@ init_i18n!(
 mod api;
 mod inbox;
 mod mail;
+mod reload;
 mod utils;
 #[macro_use]
 mod template_utils;
 mod routes;
+#[cfg(feature = "otlp")]
+mod telemetry;
 #[macro_use]
 extern crate shrinkwraprs;
 #[cfg(feature = "test")]
@@ -49,13 +76,19 @@ compile_i18n!();
 /// Initializes a database pool.
 fn init_pool() -> Option<DbPool> {
     let manager = ConnectionManager::<Connection>::new(CONFIG.database_url.as_str());
-    let mut builder = DbPool::builder()
-        .connection_customizer(Box::new(PragmaForeignKey))
-        .min_idle(CONFIG.db_min_idle);
-    if let Some(max_size) = CONFIG.db_max_size {
-        builder = builder.max_size(max_size);
-    };
-    let pool = builder.build(manager).ok()?;
+    // By default, every Rocket thread and every background job can have a connection at
+    // the same time, like the actors indexing posts and fetching remote objects
+    let max_size = CONFIG.db_max_size.unwrap_or_else(|| {
+        let rocket_workers = CONFIG.rocket.as_ref().map_or(0, |c| u32::from(c.workers));
+        rocket_workers + num_cpus::get() as u32 + 4
+    });
+    let pool = DbPool::builder()
+        .connection_customizer(Box::new(ConnectionSettings::from_config()))
+        .min_idle(CONFIG.db_min_idle)
+        .max_size(max_size)
+        .connection_timeout(CONFIG.db_acquire_timeout)
+        .build(manager)
+        .ok()?;
     let conn = pool.get().unwrap();
     Instance::cache_local(&conn);
     let _ = Instance::create_local_instance_user(&conn);
@@ -63,13 +96,72 @@ fn init_pool() -> Option<DbPool> {
     Some(pool)
 }
 
+/// Runs a job using the database every `delay`. It is paused during maintenance.
+fn schedule<F>(
+    workpool: &Worker,
+    dbpool: &DbPool,
+    initial_delay: Duration,
+    delay: Duration,
+    mut job: F,
+) where
+    F: FnMut(&Connection) + Send + 'static,
+{
+    let pool = dbpool.clone();
+    workpool.execute_with_fixed_delay(initial_delay, delay, move || match pool.get() {
+        Ok(ref conn) if Maintenance::is_active(conn) => {}
+        Ok(conn) => job(&conn),
+        Err(_) => warn!("Failed to get database connection"),
+    });
+}
+
+/// Exits with all the problems of the configuration, if there are any.
+fn check_config() {
+    let problems = config::check();
+    if problems.is_empty() {
+        return;
+    }
+    eprintln!("The configuration is not valid:\n");
+    for problem in problems {
+        eprintln!("{}", problem);
+    }
+    eprintln!("\nSee https://docs.joinplu.me/installation/config for more info.");
+    exit(1);
+}
+
+fn init_logger() -> reload::Reloader {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(reload::DEFAULT_LOG_FILTER));
+    let (filter, handle) = tracing_subscriber::reload::Layer::new(filter);
+    let text = (CONFIG.log_format == LogFormat::Text).then(fmt::layer);
+    let json = (CONFIG.log_format == LogFormat::Json)
+        .then(|| fmt::layer().json().with_current_span(false));
+    let subscriber = tracing_subscriber::registry()
+        .with(filter)
+        .with(text)
+        .with(json);
+    #[cfg(feature = "otlp")]
+    let subscriber = subscriber.with(telemetry::layer());
+    subscriber.init();
+
+    #[cfg(not(feature = "otlp"))]
+    if CONFIG.otlp.is_some() {
+        warn!("OTLP_ENDPOINT is ignored: Plume was built without the otlp feature");
+    }
+    reload::Reloader::new(handle)
+}
+
 pub(crate) fn init_rocket() -> rocket::Rocket {
+    config::save_environment();
     match dotenv::dotenv() {
         Ok(path) => eprintln!("Configuration read from {}", path.display()),
         Err(ref e) if e.not_found() => eprintln!("no .env was found"),
         e => e.map(|_| ()).unwrap(),
     }
-    tracing_subscriber::fmt::init();
+    // Tests don't need a complete configuration
+    if !cfg!(test) {
+        check_config();
+    }
+    let reloader = init_logger();
 
     App::new("Plume")
         .bin_name("plume")
@@ -84,7 +176,24 @@ and https://docs.joinplu.me/installation/init for more info.
         "#,
         )
         .get_matches();
+    plume_common::utils::set_math_rendering(CONFIG.math_rendering);
+    plume_common::utils::set_embed_allowlist(CONFIG.embed_allowlist.clone());
+    let federation = &config::reloadable().federation;
+    plume_common::activity_pub::set_timeouts(federation.connect_timeout, federation.timeout);
     let dbpool = init_pool().expect("main: database pool initialization error");
+    let failures_pool = dbpool.clone();
+    plume_common::activity_pub::on_delivery_failure(move |failure| match failures_pool.get() {
+        Ok(conn) => {
+            if let Err(e) = FailedDelivery::record(&conn, failure) {
+                warn!("Failed to save a failed delivery: {:?}", e);
+            }
+        }
+        Err(_) => warn!("Failed to get database connection"),
+    });
+    let deliveries_pool = dbpool.clone();
+    plume_common::activity_pub::before_delivery(move || {
+        Maintenance::wait_for_end(&deliveries_pool)
+    });
     if IMPORTED_MIGRATIONS
         .is_pending(&dbpool.get().unwrap())
         .unwrap_or(true)
@@ -97,11 +206,29 @@ this command:
 
     plm migration run
 
+Or, to keep the previous version running while you upgrade, only run the
+migrations it supports:
+
+    plm migration run --phase expand
+
 Then try to restart Plume.
 "#
         )
     }
-    let workpool = ScheduledThreadPool::with_name("worker {}", num_cpus::get());
+    let workpool = Worker::new(num_cpus::get());
+    // The signature checks keep some CPUs, and some Rocket threads, for pages
+    let rocket_workers = CONFIG.rocket.as_ref().map_or(1, |c| usize::from(c.workers));
+    let signatures = SignaturePool::new(
+        CONFIG
+            .signature_pool
+            .threads
+            .unwrap_or_else(|| num_cpus::get() / 2),
+        CONFIG
+            .signature_pool
+            .queue
+            .unwrap_or(rocket_workers / 2)
+            .min(rocket_workers.saturating_sub(1)),
+    );
     // we want a fast exit here, so
     let searcher = Arc::new(UnmanagedSearcher::open_or_recreate(
         &CONFIG.search_index,
@@ -109,26 +236,265 @@ Then try to restart Plume.
     ));
     RemoteFetchActor::init(dbpool.clone());
     SearchActor::init(searcher.clone(), dbpool.clone());
+    RelatedPostsActor::init(searcher.clone(), dbpool.clone());
+    // An index that was just created, or recreated in a newer format, is filled again
+    let fill_searcher = searcher.clone();
+    let fill_pool = dbpool.clone();
+    workpool.execute(move || match fill_pool.get() {
+        Ok(conn) => match fill_searcher.health(&conn) {
+            Ok(health) if health.is_empty() => {
+                info!("Filling the search index");
+                match fill_searcher.fill(&conn) {
+                    Ok(()) => fill_searcher.commit(),
+                    Err(e) => warn!("Failed to fill the search index: {:?}", e),
+                }
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Failed to check the search index: {:?}", e),
+        },
+        Err(_) => warn!("Failed to get database connection"),
+    });
     let commiter = searcher.clone();
     workpool.execute_with_fixed_delay(
         Duration::from_secs(5),
         Duration::from_secs(60 * 30),
         move || commiter.commit(),
     );
+    let health_searcher = searcher.clone();
+    schedule(
+        &workpool,
+        &dbpool,
+        Duration::from_secs(60 * 10),
+        Duration::from_secs(60 * 60 * 6),
+        move |conn| {
+            // the pending changes are committed first, not to count them as missing
+            health_searcher.commit();
+            match health_searcher.health(conn) {
+                Ok(health) if health.is_drifting() => warn!(
+                    "The search index is out of date ({} of {} articles and {} of {} comments are indexed), run `plm search rebuild --all` to fix it",
+                    health.indexed_articles,
+                    health.published_articles,
+                    health.indexed_comments,
+                    health.comments
+                ),
+                Ok(_) => {}
+                Err(e) => warn!("Failed to check the search index: {:?}", e),
+            }
+        },
+    );
+    schedule(
+        &workpool,
+        &dbpool,
+        Duration::from_secs(60),
+        Duration::from_secs(60 * 60),
+        move |conn| {
+            if let Err(e) = PostView::prune_visitors(conn) {
+                warn!("Failed to prune post visitors: {:?}", e);
+            }
+        },
+    );
+
+    schedule(
+        &workpool,
+        &dbpool,
+        Duration::from_secs(30),
+        Duration::from_secs(60),
+        move |conn| {
+            if let Err(e) = Crosspost::deliver_due(conn) {
+                warn!("Failed to send crossposts: {:?}", e);
+            }
+        },
+    );
+
+    schedule(
+        &workpool,
+        &dbpool,
+        Duration::from_secs(60),
+        Duration::from_secs(60),
+        move |conn| {
+            if let Err(e) = FailedActivity::retry_due(conn) {
+                warn!("Failed to handle activities again: {:?}", e);
+            }
+        },
+    );
+
+    schedule(
+        &workpool,
+        &dbpool,
+        Duration::from_secs(60 * 2),
+        Duration::from_secs(60 * 15),
+        move |conn| {
+            if let Err(e) = trends::compute(conn) {
+                warn!("Failed to compute trends: {:?}", e);
+            }
+        },
+    );
+
+    schedule(
+        &workpool,
+        &dbpool,
+        Duration::from_secs(60 * 3),
+        Duration::from_secs(60 * 5),
+        move |conn| {
+            if let Err(e) = ProfileField::verify_due(conn) {
+                warn!("Failed to verify profile fields: {:?}", e);
+            }
+        },
+    );
+
+    schedule(
+        &workpool,
+        &dbpool,
+        Duration::from_secs(60 * 5),
+        Duration::from_secs(60 * 60 * 6),
+        move |conn| {
+            if let Err(e) = blocklists::sync_all(conn) {
+                warn!("Failed to sync the blocklists: {:?}", e);
+            }
+        },
+    );
+
+    schedule(
+        &workpool,
+        &dbpool,
+        Duration::from_secs(60 * 10),
+        Duration::from_secs(60 * 60),
+        move |conn| {
+            if let Err(e) = IpRecord::purge_expired(conn) {
+                warn!("Failed to forget old IP addresses: {:?}", e);
+            }
+            if let Err(e) = failed_logins::purge_expired(conn) {
+                warn!("Failed to forget old failed logins: {:?}", e);
+            }
+            if let Err(e) = Session::purge_expired(conn) {
+                warn!("Failed to forget expired sessions: {:?}", e);
+            }
+            if let Err(e) = Mute::purge_expired(conn) {
+                warn!("Failed to forget expired mutes: {:?}", e);
+            }
+            // Before the failed deliveries it summarizes are forgotten
+            if let Err(e) = FederationDigest::compile(conn) {
+                warn!("Failed to compile the federation digest: {:?}", e);
+            }
+            if let Err(e) = FailedDelivery::purge_expired(conn) {
+                warn!("Failed to forget old failed deliveries: {:?}", e);
+            }
+            if let Err(e) = FailedActivity::purge_expired(conn) {
+                warn!("Failed to forget old failed activities: {:?}", e);
+            }
+            if let Err(e) = Severance::detect_remote_blocks(conn) {
+                warn!("Failed to look for instances blocking this one: {:?}", e);
+            }
+            if let Err(e) = KEY_STORE.forget_retired(conn) {
+                warn!("Failed to delete retired private keys: {:?}", e);
+            }
+        },
+    );
+
+    if CONFIG.media_gc.enabled {
+        schedule(
+            &workpool,
+            &dbpool,
+            Duration::from_secs(60 * 20),
+            Duration::from_secs(60 * 60 * 24),
+            move |conn| {
+                let grace = chrono::Duration::days(CONFIG.media_gc.grace_days);
+                match media_gc::collect(conn, grace, false) {
+                    Ok(report) => info!(
+                        "{} unused media deleted, {} KiB reclaimed",
+                        report.removed,
+                        report.reclaimed / 1024
+                    ),
+                    Err(e) => warn!("Failed to delete unused media: {:?}", e),
+                }
+            },
+        );
+    }
+
+    if media_scan::enabled() {
+        schedule(
+            &workpool,
+            &dbpool,
+            Duration::from_secs(60 * 5),
+            Duration::from_secs(60 * 10),
+            move |conn| match media_scan::scan_pending(conn) {
+                Ok(released) => {
+                    for media in released {
+                        if let Err(e) = media_variants::generate(conn, &media) {
+                            warn!(
+                                "Failed to generate the variants of media {}: {:?}",
+                                media.id, e
+                            );
+                        }
+                    }
+                }
+                Err(e) => warn!("Failed to scan pending media: {:?}", e),
+            },
+        );
+    }
+
+    let mail = Arc::new(Mutex::new(mail::init()));
+    if mail.lock().unwrap().is_none() && CONFIG.rocket.as_ref().unwrap().environment.is_prod() {
+        warn!("Warning: the email server is not configured (or not completely).");
+        warn!("Please refer to the documentation to see how to configure it.");
+    }
+    let thread_mail = mail.clone();
+    schedule(
+        &workpool,
+        &dbpool,
+        Duration::from_secs(60),
+        Duration::from_secs(60 * 15),
+        move |conn| {
+            if let Err(e) = mail::send_thread_notifications(conn, &thread_mail) {
+                warn!("Failed to send thread notifications: {:?}", e);
+            }
+        },
+    );
+
+    if CONFIG.inactivity.enabled {
+        let inactivity_mail = mail.clone();
+        schedule(
+            &workpool,
+            &dbpool,
+            Duration::from_secs(60 * 25),
+            Duration::from_secs(60 * 60 * 24),
+            move |conn| {
+                if let Err(e) = mail::send_inactivity_reminders(conn, &inactivity_mail) {
+                    warn!("Failed to send inactivity reminders: {:?}", e);
+                }
+                match inactivity::clean_up(conn, &CONFIG.inactivity, false) {
+                    Ok(report) => {
+                        info!(
+                            "{} inactive accounts frozen, {} deleted",
+                            report.frozen.len(),
+                            report.deleted.len()
+                        );
+                        for deletion in report.deletions {
+                            broadcast(
+                                &deletion.user,
+                                deletion.activity,
+                                deletion.targets,
+                                CONFIG.proxy().cloned(),
+                            );
+                        }
+                    }
+                    Err(e) => warn!("Failed to clean up inactive accounts: {:?}", e),
+                }
+            },
+        );
+    }
 
     let search_unlocker = searcher.clone();
     ctrlc::set_handler(move || {
         search_unlocker.commit();
         search_unlocker.drop_writer();
+        #[cfg(feature = "otlp")]
+        telemetry::shutdown();
         exit(0);
     })
     .expect("Error setting Ctrl-c handler");
-
-    let mail = mail::init();
-    if mail.is_none() && CONFIG.rocket.as_ref().unwrap().environment.is_prod() {
-        warn!("Warning: the email server is not configured (or not completely).");
-        warn!("Please refer to the documentation to see how to configure it.");
-    }
+    #[cfg(unix)]
+    reloader.clone().on_sighup();
 
     rocket::custom(CONFIG.rocket.clone().unwrap())
         .mount(
@@ -138,20 +504,48 @@ Then try to restart Plume.
                 routes::blogs::activity_details,
                 routes::blogs::outbox,
                 routes::blogs::outbox_page,
+                routes::blogs::inbox,
                 routes::blogs::new,
                 routes::blogs::new_auth,
                 routes::blogs::create,
                 routes::blogs::delete,
+                routes::blogs::export,
+                routes::blogs::move_blog,
                 routes::blogs::edit,
                 routes::blogs::update,
                 routes::blogs::atom_feed,
+                routes::categories::list,
+                routes::categories::create,
+                routes::categories::promote,
+                routes::categories::move_category,
+                routes::categories::delete,
+                routes::categories::details,
+                routes::categories::atom_feed,
                 routes::comments::create,
                 routes::comments::delete,
+                routes::comments::like,
+                routes::comments::like_auth,
+                routes::comments::subscribe,
+                routes::comments::subscribe_auth,
                 routes::comments::activity_pub,
+                routes::connectors::list,
+                routes::connectors::create,
+                routes::connectors::toggle,
+                routes::connectors::delete,
+                routes::connectors::retry,
                 routes::email_signups::create,
                 routes::email_signups::created,
                 routes::email_signups::show,
                 routes::email_signups::signup,
+                routes::guest_comments::create,
+                routes::guest_comments::moderation,
+                routes::guest_comments::approve,
+                routes::guest_comments::delete,
+                routes::held_comments::moderation,
+                routes::held_comments::approve,
+                routes::held_comments::delete,
+                routes::imports::new,
+                routes::imports::upload,
                 routes::instance::index,
                 routes::instance::admin,
                 routes::instance::admin_mod,
@@ -161,41 +555,96 @@ Then try to restart Plume.
                 routes::instance::admin_email_blocklist,
                 routes::instance::add_email_blocklist,
                 routes::instance::delete_email_blocklist,
+                routes::instance::admin_tag_aliases,
+                routes::instance::add_tag_alias,
+                routes::instance::delete_tag_alias,
+                routes::instance::admin_media_policies,
+                routes::instance::set_media_policy,
+                routes::instance::delete_media_policy,
+                routes::instance::admin_blocklists,
+                routes::instance::add_blocklist,
+                routes::instance::blocklist_details,
+                routes::instance::sync_blocklist,
+                routes::instance::delete_blocklist,
+                routes::instance::add_blocklist_exception,
+                routes::instance::delete_blocklist_exception,
+                routes::instance::admin_about,
+                routes::instance::set_contact,
+                routes::instance::add_rule,
+                routes::instance::update_rule,
+                routes::instance::move_rule_up,
+                routes::instance::delete_rule,
+                routes::instance::add_about_section,
+                routes::instance::update_about_section,
+                routes::instance::move_about_section_up,
+                routes::instance::delete_about_section,
+                routes::instance::admin_legal,
+                routes::instance::publish_legal_document,
+                routes::instance::admin_announcements,
+                routes::instance::add_announcement,
+                routes::instance::update_announcement,
+                routes::instance::delete_announcement,
+                routes::instance::dismiss_announcement,
+                routes::instance::react_to_announcement,
                 routes::instance::edit_users,
                 routes::instance::toggle_block,
+                routes::instance::toggle_follow,
+                routes::instance::federation_digest,
                 routes::instance::update_settings,
                 routes::instance::shared_inbox,
                 routes::instance::interact,
+                routes::instance::authorize_interaction,
                 routes::instance::nodeinfo,
                 routes::instance::about,
+                routes::directory::index,
                 routes::instance::privacy,
+                routes::instance::terms,
+                routes::instance::accept_terms_form,
+                routes::instance::pending_terms,
+                routes::instance::accept_terms,
                 routes::instance::web_manifest,
+                routes::instance::opensearch,
+                routes::instance::robots_txt,
                 routes::likes::create,
                 routes::likes::create_auth,
                 routes::medias::list,
                 routes::medias::new,
                 routes::medias::upload,
                 routes::medias::details,
+                routes::medias::proxy,
                 routes::medias::delete,
                 routes::medias::set_avatar,
+                routes::medias::set_banner,
                 routes::notifications::notifications,
                 routes::notifications::notifications_auth,
+                routes::notifications::mark_all_read,
+                routes::notifications::severed_relationships,
                 routes::posts::details,
                 routes::posts::activity_details,
                 routes::posts::edit,
                 routes::posts::update,
                 routes::posts::new,
+                routes::posts::share,
+                routes::posts::share_auth,
                 routes::posts::new_auth,
                 routes::posts::create,
                 routes::posts::delete,
                 routes::posts::remote_interact,
                 routes::posts::remote_interact_post,
+                routes::translations::new,
+                routes::translations::create,
                 routes::reshares::create,
                 routes::reshares::create_auth,
                 routes::search::search,
+                routes::series::details,
+                routes::series::activity_details,
+                routes::series::update,
+                routes::series::delete,
                 routes::session::new,
                 routes::session::create,
                 routes::session::delete,
+                routes::session::revoke,
+                routes::session::revoke_others,
                 routes::session::password_reset_request_form,
                 routes::session::password_reset_request,
                 routes::session::password_reset_form,
@@ -205,6 +654,7 @@ Then try to restart Plume.
                 routes::static_files,
                 routes::plume_media_files,
                 routes::tags::tag,
+                routes::tags::follow,
                 routes::timelines::details,
                 routes::timelines::new,
                 routes::timelines::create,
@@ -220,7 +670,12 @@ Then try to restart Plume.
                 routes::user::edit,
                 routes::user::edit_auth,
                 routes::user::update,
+                routes::user::confirm_email_change,
+                routes::user::cancel_email_change,
                 routes::user::delete,
+                routes::user::export_data,
+                routes::user::export_subscriptions,
+                routes::user::import_subscriptions,
                 routes::user::follow,
                 routes::user::follow_not_connected,
                 routes::user::follow_auth,
@@ -235,7 +690,8 @@ Then try to restart Plume.
                 routes::well_known::host_meta,
                 routes::well_known::nodeinfo,
                 routes::well_known::webfinger,
-                routes::errors::csrf_violation
+                routes::errors::csrf_violation,
+                routes::errors::maintenance
             ],
         )
         .mount(
@@ -243,23 +699,79 @@ Then try to restart Plume.
             routes![
                 api::oauth,
                 api::apps::create,
+                api::autocomplete::search,
+                api::blogs::members,
+                api::blogs::set_member,
+                api::blogs::remove_member,
+                api::blogs::reviews,
+                api::instance::details,
+                api::instance::maintenance,
+                api::instance::start_maintenance,
+                api::instance::stop_maintenance,
+                api::instance::reload_config,
+                api::instance::announcements,
+                api::instance::create_announcement,
+                api::instance::delete_announcement,
+                api::instance::dismiss_announcement,
+                api::instance::react_to_announcement,
+                api::instance::unreact_to_announcement,
+                api::medias::get,
+                api::notifications::list,
+                api::notifications::mark_read,
+                api::notifications::filtered,
+                api::notifications::accept_filtered,
+                api::notifications::dismiss_filtered,
+                api::notifications::policy,
+                api::notifications::set_policy,
                 api::posts::get,
                 api::posts::list,
+                api::posts::related,
                 api::posts::create,
+                api::posts::approve,
                 api::posts::delete,
+                api::profiles::user_fields,
+                api::profiles::update_user_fields,
+                api::profiles::blog_fields,
+                api::profiles::update_blog_fields,
+                api::recommendations::list,
+                api::search::search,
+                api::stats::author,
+                api::trends::list,
+                api::directory::list,
+                api::users::export,
+                api::users::erase,
+                api::users::change_password,
+                api::users::terms,
+                api::users::accept_terms,
+                api::users::pending_terms,
+                api::users::sessions,
+                api::users::revoke_session,
+                api::users::revoke_sessions,
+                api::users::notes,
+                api::users::note,
+                api::users::set_note,
+                api::users::mutes,
+                api::users::mute,
+                api::users::unmute,
             ],
         )
         .register(catchers![
             routes::errors::not_found,
             routes::errors::unprocessable_entity,
+            routes::errors::too_many_requests,
             routes::errors::server_error
         ])
-        .manage(Arc::new(Mutex::new(mail)))
+        .manage(mail)
         .manage::<Arc<Mutex<Vec<routes::session::ResetRequest>>>>(Arc::new(Mutex::new(vec![])))
         .manage(dbpool)
+        .manage(reloader)
         .manage(Arc::new(workpool))
+        .manage(Arc::new(signatures))
         .manage(searcher)
         .manage(include_i18n!())
+        .attach(RequestSpans)
+        .attach(MaintenanceMode)
+        .attach(TermsAcceptance)
         .attach(
             CsrfFairingBuilder::new()
                 .set_default_target(
@@ -0,0 +1,93 @@
+//! Reloading a part of the configuration while the server is running.
+//!
+//! The log level, the rate limits, the federation timeouts and the IP blocklists are read
+//! again from `.env` and from the environment when the server receives `SIGHUP`, or when an
+//! administrator asks for it with the API. The other settings still need a restart.
+//!
+//! The environment of the process is never changed: `.env` is read into a map, the new
+//! configuration is checked, and only then it replaces the previous one.
+
+use plume_models::config::{self, Problem};
+use std::collections::HashMap;
+use tracing::{info, warn};
+use tracing_subscriber::{reload::Handle, EnvFilter, Registry};
+
+/// The filter used when `RUST_LOG` is not set.
+pub const DEFAULT_LOG_FILTER: &str = "info";
+
+#[derive(Clone)]
+pub struct Reloader {
+    log_filter: Handle<EnvFilter, Registry>,
+}
+
+impl Reloader {
+    pub fn new(log_filter: Handle<EnvFilter, Registry>) -> Self {
+        Reloader { log_filter }
+    }
+
+    /// Reads the configuration again, and returns the problems that prevented it.
+    ///
+    /// Nothing changes if there is a problem. Variables that were removed from `.env` get
+    /// their default value again.
+    pub fn reload(&self) -> Vec<Problem> {
+        let env_file = match read_env_file() {
+            Ok(env_file) => env_file,
+            Err(problem) => return vec![problem],
+        };
+        let filter = match config::env_value(&env_file, "RUST_LOG") {
+            Some(directives) => match EnvFilter::try_new(&directives) {
+                Ok(filter) => filter,
+                Err(e) => {
+                    return vec![Problem {
+                        variable: "RUST_LOG".to_owned(),
+                        message: format!("{} is not a valid filter ({})", directives, e),
+                        hint: "Use a level, like info, or directives like plume=debug,info"
+                            .to_owned(),
+                    }]
+                }
+            },
+            None => EnvFilter::new(DEFAULT_LOG_FILTER),
+        };
+        if let Err(problems) = config::reload(env_file) {
+            for problem in &problems {
+                warn!("The configuration was not reloaded: {}", problem);
+            }
+            return problems;
+        }
+        if let Err(e) = self.log_filter.reload(filter) {
+            warn!("The log filter was not reloaded: {}", e);
+        }
+        info!("Configuration reloaded");
+        vec![]
+    }
+
+    /// Reloads the configuration each time the process receives `SIGHUP`.
+    #[cfg(unix)]
+    pub fn on_sighup(self) {
+        use signal_hook::{consts::SIGHUP, iterator::Signals};
+
+        let mut signals = Signals::new(&[SIGHUP]).expect("Error setting SIGHUP handler");
+        std::thread::spawn(move || {
+            for _ in signals.forever() {
+                self.reload();
+            }
+        });
+    }
+}
+
+/// The variables of `.env`, that is read again.
+fn read_env_file() -> Result<HashMap<String, String>, Problem> {
+    match dotenv::dotenv_iter() {
+        Ok(vars) => vars.collect::<Result<_, _>>().map_err(env_file_problem),
+        Err(ref e) if e.not_found() => Ok(HashMap::new()),
+        Err(e) => Err(env_file_problem(e)),
+    }
+}
+
+fn env_file_problem(e: dotenv::Error) -> Problem {
+    Problem {
+        variable: ".env".to_owned(),
+        message: format!("it can't be read ({})", e),
+        hint: "Check the syntax of .env, the previous configuration is kept meanwhile".to_owned(),
+    }
+}
@@ -3,20 +3,39 @@ use diesel::SaveChangesDsl;
 use rocket::{
     http::ContentType,
     request::LenientForm,
-    response::{content::Content, Flash, Redirect},
+    response::{content::Content, status, Flash, Redirect},
+    State,
 };
 use rocket_i18n::I18n;
-use std::{borrow::Cow, collections::HashMap};
+use std::{borrow::Cow, collections::HashMap, sync::Arc};
 use validator::{Validate, ValidationError, ValidationErrors};
 
-use crate::routes::{errors::ErrorPage, Page, RespondOrRedirect};
+use crate::inbox;
+use crate::routes::{errors::ErrorPage, profile_field_inputs, Page, RespondOrRedirect};
 use crate::template_utils::{IntoContext, Ructe};
 use crate::utils::requires_login;
-use plume_common::activity_pub::{ActivityStream, ApRequest, CustomGroup};
+use plume_common::activity_pub::{broadcast, ActivityStream, ApRequest, CustomGroup};
 use plume_common::utils;
 use plume_models::{
-    blog_authors::*, blogs::*, db_conn::DbConn, instance::Instance, medias::*, posts::Post,
-    safe_string::SafeString, users::User, Connection, PlumeRocket,
+    blog_authors::*,
+    blog_transfer,
+    blogs::*,
+    cache::{self, Entry},
+    comments::CommentOrder,
+    db_conn::{DbConn, DbPool},
+    directory,
+    fundings::{Funding, NewFunding},
+    headers::Headers,
+    instance::Instance,
+    medias::*,
+    posts::Post,
+    profile_fields::{ProfileField, ProfileOwner},
+    rate_limits::{Inbox, RateLimit},
+    safe_string::SafeString,
+    signature_pool::SignaturePool,
+    users::User,
+    worker::Worker,
+    Connection, Error, PlumeRocket, CONFIG,
 };
 
 #[get("/~/<name>?<page>", rank = 2)]
@@ -49,7 +68,8 @@ pub fn activity_details(
     _ap: ApRequest,
 ) -> Option<ActivityStream<CustomGroup>> {
     let blog = Blog::find_by_fqn(&conn, &name).ok()?;
-    Some(ActivityStream::new(blog.to_activity(&conn).ok()?))
+    let actor = cache::get_or_insert(Entry::BlogActor(blog.id), || blog.to_activity(&conn));
+    Some(ActivityStream::new(actor.ok()?))
 }
 
 #[get("/blogs/new")]
@@ -138,7 +158,7 @@ pub fn create(
         NewBlogAuthor {
             blog_id: blog.id,
             author_id: user.id,
-            is_owner: true,
+            role: BlogRole::Owner as i32,
         },
     )
     .expect("blog::create: author error");
@@ -157,7 +177,7 @@ pub fn delete(name: String, conn: DbConn, rockets: PlumeRocket) -> RespondOrRedi
     if rockets
         .user
         .clone()
-        .and_then(|u| u.is_author_in(&conn, &blog).ok())
+        .and_then(|u| u.can_manage(&conn, &blog).ok())
         .unwrap_or(false)
     {
         blog.delete(&conn).expect("blog::expect: deletion error");
@@ -179,6 +199,74 @@ pub fn delete(name: String, conn: DbConn, rockets: PlumeRocket) -> RespondOrRedi
     }
 }
 
+/// An archive of the blog, to import it on another instance.
+#[get("/~/<name>/export")]
+pub fn export(name: String, user: User, conn: DbConn) -> Result<Content<Vec<u8>>, ErrorPage> {
+    let blog = Blog::find_by_fqn(&conn, &name)?;
+    if !user
+        .role_in(&conn, &blog)
+        .map_or(false, BlogRole::can_manage)
+    {
+        return Err(Error::Unauthorized.into());
+    }
+    Ok(Content(
+        ContentType::new("application", "zip"),
+        blog_transfer::export(&conn, &blog)?,
+    ))
+}
+
+#[derive(FromForm)]
+pub struct MoveForm {
+    /// The URL or the full name of the new blog
+    pub target: String,
+}
+
+/// Moves the blog to the one its archive was imported in, and tells the followers of its
+/// authors.
+#[post("/~/<name>/move", data = "<form>")]
+pub fn move_blog(
+    name: String,
+    user: User,
+    form: LenientForm<MoveForm>,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let blog = Blog::find_by_fqn(&conn, &name)?;
+    if !user
+        .role_in(&conn, &blog)
+        .map_or(false, BlogRole::can_manage)
+    {
+        return Err(Error::Unauthorized.into());
+    }
+    let back = uri!(edit: name = &name);
+    let target = form.target.trim().trim_start_matches('~');
+    let target = if target.starts_with("https://") {
+        Ok(target.to_owned())
+    } else {
+        Blog::find_by_fqn(&conn, target).map(|b| b.ap_url)
+    };
+    let act = match target.and_then(|target| blog_transfer::move_to(&conn, &blog, &target)) {
+        Ok(act) => act,
+        Err(_) => {
+            return Ok(Flash::error(
+                Redirect::to(back),
+                i18n!(
+                    rockets.intl.catalog,
+                    "This blog can't move there: its archive has to be imported there first."
+                ),
+            ))
+        }
+    };
+    let followers = blog_transfer::followers(&conn, &blog)?;
+    rockets
+        .worker
+        .execute(move || broadcast(&blog, act, followers, CONFIG.proxy().cloned()));
+    Ok(Flash::success(
+        Redirect::to(back),
+        i18n!(rockets.intl.catalog, "Your blog moved."),
+    ))
+}
+
 #[derive(FromForm, Validate)]
 pub struct EditForm {
     #[validate(custom(function = "valid_slug", message = "Invalid name"))]
@@ -187,6 +275,35 @@ pub struct EditForm {
     pub icon: Option<i32>,
     pub banner: Option<i32>,
     pub theme: Option<String>,
+    pub comments_order: i32,
+    pub comments_max_depth: Option<i32>,
+    pub allow_guest_comments: bool,
+    pub discoverable: bool,
+    pub directory_category: Option<String>,
+    /// Empty to use the license of the instance
+    pub default_license: String,
+    pub payment_pointer: String,
+    pub liberapay_url: String,
+    pub kofi_url: String,
+    pub field_name_0: String,
+    pub field_value_0: String,
+    pub field_name_1: String,
+    pub field_value_1: String,
+    pub field_name_2: String,
+    pub field_value_2: String,
+    pub field_name_3: String,
+    pub field_value_3: String,
+}
+
+impl EditForm {
+    pub fn profile_fields(&self) -> Vec<(String, String)> {
+        vec![
+            (self.field_name_0.clone(), self.field_value_0.clone()),
+            (self.field_name_1.clone(), self.field_value_1.clone()),
+            (self.field_name_2.clone(), self.field_value_2.clone()),
+            (self.field_name_3.clone(), self.field_value_3.clone()),
+        ]
+    }
 }
 
 #[get("/~/<name>/edit")]
@@ -195,7 +312,7 @@ pub fn edit(name: String, conn: DbConn, rockets: PlumeRocket) -> Result<Ructe, E
     if rockets
         .user
         .clone()
-        .and_then(|u| u.is_author_in(&conn, &blog).ok())
+        .and_then(|u| u.can_manage(&conn, &blog).ok())
         .unwrap_or(false)
     {
         let user = rockets
@@ -203,6 +320,10 @@ pub fn edit(name: String, conn: DbConn, rockets: PlumeRocket) -> Result<Ructe, E
             .clone()
             .expect("blogs::edit: User was None while it shouldn't");
         let medias = Media::for_user(&conn, user.id).expect("Couldn't list media");
+        let funding = Funding::for_blog(&conn, blog.id).ok();
+        let fields = profile_field_inputs(
+            ProfileField::list(&conn, ProfileOwner::Blog(blog.id)).unwrap_or_default(),
+        );
         Ok(render!(blogs::edit(
             &(&conn, &rockets).to_context(),
             &blog,
@@ -213,6 +334,29 @@ pub fn edit(name: String, conn: DbConn, rockets: PlumeRocket) -> Result<Ructe, E
                 icon: blog.icon_id,
                 banner: blog.banner_id,
                 theme: blog.theme.clone(),
+                comments_order: blog.comments_order,
+                comments_max_depth: blog.comments_max_depth,
+                allow_guest_comments: blog.allow_guest_comments,
+                discoverable: blog.discoverable,
+                directory_category: blog.directory_category.clone(),
+                default_license: blog.default_license.clone().unwrap_or_default(),
+                payment_pointer: funding
+                    .as_ref()
+                    .map(|f| f.payment_pointer.clone())
+                    .unwrap_or_default(),
+                liberapay_url: funding
+                    .as_ref()
+                    .map(|f| f.liberapay_url.clone())
+                    .unwrap_or_default(),
+                kofi_url: funding.map(|f| f.kofi_url).unwrap_or_default(),
+                field_name_0: fields[0].0.clone(),
+                field_value_0: fields[0].1.clone(),
+                field_name_1: fields[1].0.clone(),
+                field_value_1: fields[1].1.clone(),
+                field_name_2: fields[2].0.clone(),
+                field_value_2: fields[2].1.clone(),
+                field_name_3: fields[3].0.clone(),
+                field_value_3: fields[3].1.clone(),
             },
             ValidationErrors::default()
         )))
@@ -249,7 +393,7 @@ pub fn update(
     if !rockets
         .user
         .clone()
-        .and_then(|u| u.is_author_in(&conn, &blog).ok())
+        .and_then(|u| u.can_manage(&conn, &blog).ok())
         .unwrap_or(false)
     {
         // TODO actually return 403 error code
@@ -305,6 +449,49 @@ pub fn update(
                 }
             }
 
+            Funding::save(
+                &conn,
+                NewFunding {
+                    blog_id: Some(blog.id),
+                    payment_pointer: form.payment_pointer.clone(),
+                    liberapay_url: form.liberapay_url.clone(),
+                    kofi_url: form.kofi_url.clone(),
+                    ..NewFunding::default()
+                },
+            )
+            .map_err(|_| {
+                let mut errors = ValidationErrors::new();
+                errors.add(
+                    "",
+                    ValidationError {
+                        code: Cow::from("funding"),
+                        message: Some(Cow::from(i18n!(
+                            intl,
+                            "Invalid payment pointer or funding account."
+                        ))),
+                        params: HashMap::new(),
+                    },
+                );
+                errors
+            })?;
+
+            ProfileField::replace(&conn, ProfileOwner::Blog(blog.id), &form.profile_fields())
+                .map_err(|_| {
+                    let mut errors = ValidationErrors::new();
+                    errors.add(
+                        "",
+                        ValidationError {
+                            code: Cow::from("profile_fields"),
+                            message: Some(Cow::from(i18n!(
+                                intl,
+                                "Profile fields need a label, and can't be too long."
+                            ))),
+                            params: HashMap::new(),
+                        },
+                    );
+                    errors
+                })?;
+
             blog.title = form.title.clone();
             blog.summary = form.summary.clone();
             blog.summary_html = SafeString::new(
@@ -325,8 +512,20 @@ pub fn update(
             blog.icon_id = form.icon;
             blog.banner_id = form.banner;
             blog.theme = form.theme.clone();
+            blog.comments_order = CommentOrder::from(form.comments_order) as i32;
+            blog.comments_max_depth = form.comments_max_depth.filter(|depth| *depth > 0);
+            blog.allow_guest_comments = form.allow_guest_comments;
+            blog.discoverable = form.discoverable;
+            blog.directory_category = form
+                .directory_category
+                .clone()
+                .filter(|category| directory::CATEGORIES.contains(&category.as_str()));
+            blog.default_license = Some(form.default_license.trim())
+                .filter(|license| !license.is_empty())
+                .map(str::to_owned);
             blog.save_changes::<Blog>(&*conn)
                 .expect("Couldn't save blog changes");
+            blog.forget_cached();
             Ok(Flash::success(
                 Redirect::to(uri!(details: name = name, page = _)),
                 i18n!(intl, "Your blog information have been updated."),
@@ -361,6 +560,20 @@ pub fn outbox_page(
     let blog = Blog::find_by_fqn(&conn, &name).ok()?;
     blog.outbox_page(&conn, page.limits()).ok()
 }
+#[post("/~/<name>/inbox", data = "<data>")]
+pub fn inbox(
+    _limit: RateLimit<Inbox>,
+    name: String,
+    data: inbox::SignedJson<serde_json::Value>,
+    headers: Headers<'_>,
+    conn: DbConn,
+    pool: State<'_, DbPool>,
+    worker: State<'_, Arc<Worker>>,
+    signatures: State<'_, Arc<SignaturePool>>,
+) -> Result<String, status::Custom<&'static str>> {
+    Blog::find_by_fqn(&conn, &name).map_err(|_| inbox::bad_request("Blog not found"))?;
+    inbox::handle_incoming(conn, data, headers, pool, worker, signatures)
+}
 #[get("/~/<name>/atom.xml")]
 pub fn atom_feed(name: String, conn: DbConn) -> Option<Content<String>> {
     let blog = Blog::find_by_fqn(&conn, &name).ok()?;
@@ -384,18 +597,19 @@ mod tests {
     use diesel::Connection;
     use plume_common::utils::random_hex;
     use plume_models::{
-        blog_authors::{BlogAuthor, NewBlogAuthor},
+        blog_authors::{BlogAuthor, BlogRole, NewBlogAuthor},
         blogs::{Blog, NewBlog},
         db_conn::{DbConn, DbPool},
         instance::{Instance, NewInstance},
         post_authors::{NewPostAuthor, PostAuthor},
         posts::{NewPost, Post},
         safe_string::SafeString,
-        users::{NewUser, User, AUTH_COOKIE},
+        sessions::{Device, Session},
+        users::{NewUser, User},
         Connection as Conn, CONFIG,
     };
     use rocket::{
-        http::{Cookie, Cookies, SameSite},
+        http::Cookies,
         local::{Client, LocalRequest},
     };
 
@@ -436,7 +650,7 @@ mod tests {
         assert!(!body.contains(&edit_link));
 
         let request = client.get(&blog_path);
-        login(&request, &user);
+        login(&request, conn, &user);
         let mut response = request.dispatch();
         let body = response.body_string().unwrap();
         assert!(body.contains(&edit_link));
@@ -487,7 +701,7 @@ mod tests {
                 NewBlogAuthor {
                     blog_id: blog.id,
                     author_id: user.id,
-                    is_owner: true,
+                    role: BlogRole::Owner as i32,
                 },
             )
             .unwrap();
@@ -505,6 +719,8 @@ mod tests {
                     subtitle: "".to_owned(),
                     source: "".to_owned(),
                     cover_id: None,
+                    excerpt: String::new(),
+                    canonical_url: None,
                 },
             )
             .unwrap();
@@ -522,12 +738,13 @@ mod tests {
         .unwrap()
     }
 
-    fn login(request: &LocalRequest, user: &User) {
-        request.inner().guard::<Cookies>().unwrap().add_private(
-            Cookie::build(AUTH_COOKIE, user.id.to_string())
-                .same_site(SameSite::Lax)
-                .finish(),
-        );
+    fn login(request: &LocalRequest, conn: &DbConn, user: &User) {
+        let session = Session::open(conn, user, &Device::default()).unwrap();
+        request
+            .inner()
+            .guard::<Cookies>()
+            .unwrap()
+            .add_private(session.cookie());
     }
 
     #[test]
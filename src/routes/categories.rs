@@ -0,0 +1,204 @@
+use rocket::{
+    http::ContentType,
+    request::LenientForm,
+    response::{content::Content, Flash, Redirect},
+};
+use rocket_i18n::I18n;
+
+use crate::routes::{errors::ErrorPage, Page};
+use crate::template_utils::{IntoContext, Ructe};
+use plume_common::utils::iri_percent_encode_seg;
+use plume_models::{
+    ap_url, blogs::Blog, categories::Category, db_conn::DbConn, instance::Instance, users::User,
+    Error, PlumeRocket,
+};
+
+/// How many articles are listed in the feed of a category.
+const FEED_LENGTH: i32 = 15;
+
+#[derive(Default, FromForm)]
+pub struct NewCategoryForm {
+    pub name: String,
+    pub parent: Option<i32>,
+}
+
+#[derive(Default, FromForm)]
+pub struct PromoteTagForm {
+    pub tag: String,
+    pub parent: Option<i32>,
+}
+
+#[derive(Default, FromForm)]
+pub struct MoveCategoryForm {
+    pub parent: Option<i32>,
+}
+
+#[get("/~/<blog>/categories")]
+pub fn list(blog: String, conn: DbConn, rockets: PlumeRocket) -> Result<Ructe, ErrorPage> {
+    let blog = Blog::find_by_fqn(&conn, &blog)?;
+    let categories = Category::tree_for_blog(&conn, blog.id)?;
+    Ok(render!(categories::list(
+        &(&conn, &rockets).to_context(),
+        &blog,
+        categories
+    )))
+}
+
+#[post("/~/<blog>/categories", data = "<form>")]
+pub fn create(
+    blog: String,
+    form: LenientForm<NewCategoryForm>,
+    user: User,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let b = author_blog(&conn, &blog, &user)?;
+    let parent = find_parent(&conn, &b, form.parent)?;
+    Ok(
+        match Category::find_or_create(&conn, &b, &form.name, parent.as_ref()) {
+            Ok(_) => Flash::success(
+                Redirect::to(uri!(list: blog = blog)),
+                i18n!(intl.catalog, "The category has been created."),
+            ),
+            Err(_) => Flash::error(
+                Redirect::to(uri!(list: blog = blog)),
+                i18n!(intl.catalog, "This category couldn't be created."),
+            ),
+        },
+    )
+}
+
+#[post("/~/<blog>/categories/promote", data = "<form>")]
+pub fn promote(
+    blog: String,
+    form: LenientForm<PromoteTagForm>,
+    user: User,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let b = author_blog(&conn, &blog, &user)?;
+    let parent = find_parent(&conn, &b, form.parent)?;
+    Ok(
+        match Category::promote_tag(&conn, &b, &form.tag, parent.as_ref()) {
+            Ok((_, added)) => Flash::success(
+                Redirect::to(uri!(list: blog = blog)),
+                i18n!(intl.catalog, "One article has been added to the category.", "{0} articles have been added to the category."; added),
+            ),
+            Err(_) => Flash::error(
+                Redirect::to(uri!(list: blog = blog)),
+                i18n!(intl.catalog, "This tag couldn't be turned into a category."),
+            ),
+        },
+    )
+}
+
+#[post("/~/<blog>/categories/<slug>", data = "<form>", rank = 5)]
+pub fn move_category(
+    blog: String,
+    slug: String,
+    form: LenientForm<MoveCategoryForm>,
+    user: User,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let b = author_blog(&conn, &blog, &user)?;
+    let mut category = Category::find_by_slug(&conn, b.id, &slug)?;
+    let parent = find_parent(&conn, &b, form.parent)?;
+    Ok(match category.set_parent(&conn, parent.as_ref()) {
+        Ok(_) => Flash::success(
+            Redirect::to(uri!(list: blog = blog)),
+            i18n!(intl.catalog, "The category has been moved."),
+        ),
+        Err(_) => Flash::error(
+            Redirect::to(uri!(list: blog = blog)),
+            i18n!(
+                intl.catalog,
+                "A category can't be moved inside of one of its sub-categories."
+            ),
+        ),
+    })
+}
+
+#[post("/~/<blog>/categories/<slug>/delete")]
+pub fn delete(
+    blog: String,
+    slug: String,
+    user: User,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let b = author_blog(&conn, &blog, &user)?;
+    Category::find_by_slug(&conn, b.id, &slug)?.delete(&conn)?;
+    Ok(Flash::success(
+        Redirect::to(uri!(list: blog = blog)),
+        i18n!(
+            intl.catalog,
+            "The category has been deleted. Its articles are still on the blog."
+        ),
+    ))
+}
+
+#[get("/~/<blog>/category/<slug>?<page>", rank = 6)]
+pub fn details(
+    blog: String,
+    slug: String,
+    page: Option<Page>,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Result<Ructe, ErrorPage> {
+    let page = page.unwrap_or_default();
+    let blog = Blog::find_by_fqn(&conn, &blog)?;
+    let category = Category::find_by_slug(&conn, blog.id, &slug)?;
+    let posts = category.posts_page(&conn, page.limits())?;
+    let count = category.count_posts(&conn)?;
+    Ok(render!(categories::details(
+        &(&conn, &rockets).to_context(),
+        &blog,
+        &category,
+        posts,
+        page.0,
+        Page::total(count as i32)
+    )))
+}
+
+#[get("/~/<blog>/category/<slug>/atom.xml", rank = 6)]
+pub fn atom_feed(blog: String, slug: String, conn: DbConn) -> Option<Content<String>> {
+    let b = Blog::find_by_fqn(&conn, &blog).ok()?;
+    let category = Category::find_by_slug(&conn, b.id, &slug).ok()?;
+    let entries = category.posts_page(&conn, (0, FEED_LENGTH)).ok()?;
+    let uri = ap_url(&format!(
+        "{}/~/{}/category/{}/atom.xml",
+        Instance::get_local().ok()?.public_domain,
+        iri_percent_encode_seg(&blog),
+        iri_percent_encode_seg(&slug)
+    ));
+    let title = format!("{} - {}", b.title, category.name);
+    let feed = super::build_atom_feed(entries, &uri, &title, &category.creation_date, &conn);
+    Some(Content(
+        ContentType::new("application", "atom+xml"),
+        feed.to_string(),
+    ))
+}
+
+/// Finds a blog, checking that `user` can manage its categories.
+fn author_blog(conn: &DbConn, blog: &str, user: &User) -> Result<Blog, Error> {
+    let blog = Blog::find_by_fqn(conn, blog)?;
+    if !user.can_publish_in(conn, &blog)? {
+        return Err(Error::Unauthorized);
+    }
+    Ok(blog)
+}
+
+/// Finds the parent category selected in a form, that must belong to the same blog.
+fn find_parent(conn: &DbConn, blog: &Blog, parent: Option<i32>) -> Result<Option<Category>, Error> {
+    match parent {
+        Some(id) => {
+            let parent = Category::get(conn, id)?;
+            if parent.blog_id != blog.id {
+                return Err(Error::Unauthorized);
+            }
+            Ok(Some(parent))
+        }
+        None => Ok(None),
+    }
+}
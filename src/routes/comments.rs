@@ -4,20 +4,35 @@ use rocket::{
     request::LenientForm,
     response::{Flash, Redirect},
 };
+use rocket_i18n::I18n;
 use validator::Validate;
 
 use std::time::Duration;
 
 use crate::routes::errors::ErrorPage;
 use crate::template_utils::IntoContext;
+use crate::utils::requires_login;
 use plume_common::{
     activity_pub::{broadcast, ActivityStream, ApRequest},
     utils,
 };
 use plume_models::{
-    blogs::Blog, comments::*, db_conn::DbConn, inbox::inbox, instance::Instance, medias::Media,
-    mentions::Mention, posts::Post, safe_string::SafeString, tags::Tag, users::User, Error,
-    PlumeRocket, CONFIG,
+    blogs::Blog,
+    comment_likes::*,
+    comments::*,
+    db_conn::DbConn,
+    inbox::inbox,
+    instance::Instance,
+    ip_records::{ClientIp, IpRecord, IpRecordKind},
+    medias::Media,
+    mentions::Mention,
+    posts::Post,
+    rate_limits::{Commenting, RateLimit},
+    safe_string::SafeString,
+    tags::Tag,
+    thread_subscriptions::ThreadSubscription,
+    users::User,
+    Error, PlumeRocket, CONFIG,
 };
 
 #[derive(Default, FromForm, Debug, Validate)]
@@ -30,12 +45,14 @@ pub struct NewCommentForm {
 
 #[post("/~/<blog_name>/<slug>/comment", data = "<form>")]
 pub fn create(
+    _limit: RateLimit<Commenting>,
     blog_name: String,
     slug: String,
     form: LenientForm<NewCommentForm>,
     user: User,
     conn: DbConn,
     rockets: PlumeRocket,
+    ip: ClientIp,
 ) -> Result<Flash<Redirect>, Ructe> {
     let blog = Blog::find_by_fqn(&conn, &blog_name).expect("comments::create: blog error");
     let post = Post::find_by_slug(&conn, &slug, blog.id).expect("comments::create: post error");
@@ -51,10 +68,32 @@ pub fn create(
                 true,
                 Some(Media::get_media_processor(&conn, vec![&user])),
             );
+            let content = SafeString::new(html.as_ref());
+            let refused = match Comment::is_recent_duplicate(&conn, user.id, post.id, &content) {
+                Ok(false) => None,
+                Ok(true) => Some(i18n!(
+                    &rockets.intl.catalog,
+                    "You already posted this comment on this article."
+                )),
+                Err(_) => Some(i18n!(
+                    &rockets.intl.catalog,
+                    "Your comment couldn't be posted, please try again."
+                )),
+            };
+            if let Some(message) = refused {
+                return Flash::error(
+                    Redirect::to(uri!(
+                        super::posts::details: blog = blog_name,
+                        slug = slug,
+                        responding_to = _
+                    )),
+                    message,
+                );
+            }
             let comm = Comment::insert(
                 &conn,
                 NewComment {
-                    content: SafeString::new(html.as_ref()),
+                    content,
                     in_response_to_id: form.responding_to,
                     post_id: post.id,
                     author_id: user.id,
@@ -62,27 +101,33 @@ pub fn create(
                     sensitive: !form.warning.is_empty(),
                     spoiler_text: form.warning.clone(),
                     public_visibility: true,
+                    held: false,
+                    spam_score: None,
                 },
             )
             .expect("comments::create: insert error");
+            IpRecord::record(
+                &conn,
+                IpRecordKind::Comment,
+                ip.0,
+                Some(user.id),
+                Some(comm.id),
+            )
+            .expect("comments::create: IP record error");
             let new_comment = comm
                 .create_activity(&conn)
                 .expect("comments::create: activity error");
 
             // save mentions
-            for ment in mentions {
-                Mention::from_activity(
-                    &conn,
-                    &Mention::build_activity(&conn, &ment)
-                        .expect("comments::create: build mention error"),
-                    comm.id,
-                    false,
-                    true,
-                )
-                .expect("comments::create: mention save error");
+            let resolved = Mention::resolve_all(&conn, mentions);
+            for ment in resolved.mentions {
+                Mention::from_activity(&conn, &ment, comm.id, false, true)
+                    .expect("comments::create: mention save error");
             }
 
             comm.notify(&conn).expect("comments::create: notify error");
+            ThreadSubscription::subscribe(&conn, &user, &post)
+                .expect("comments::create: subscription error");
 
             // federate
             let dest = User::one_by_instance(&conn).expect("comments::create: dest error");
@@ -91,13 +136,15 @@ pub fn create(
                 broadcast(&user_clone, new_comment, dest, CONFIG.proxy().cloned())
             });
 
-            Flash::success(
+            super::flash_with_mentions(
+                &rockets.intl.catalog,
                 Redirect::to(uri!(
                     super::posts::details: blog = blog_name,
                     slug = slug,
                     responding_to = _
                 )),
                 i18n!(&rockets.intl.catalog, "Your comment has been posted."),
+                &resolved.failures,
             )
         })
         .map_err(|errors| {
@@ -133,7 +180,8 @@ pub fn create(
                 .expect("comments::create: following error"),
                 post.get_authors(&conn)
                     .expect("comments::create: authors error")[0]
-                    .clone()
+                    .clone(),
+                post.displayed_cover(&conn)
             ))
         })
 }
@@ -178,6 +226,112 @@ pub fn delete(
     ))
 }
 
+#[post("/~/<blog>/<slug>/comment/<id>/like")]
+pub fn like(
+    blog: String,
+    slug: String,
+    id: i32,
+    user: User,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Result<Redirect, ErrorPage> {
+    let blog_id = Blog::find_by_fqn(&conn, &blog)?.id;
+    let post = Post::find_by_slug(&conn, &slug, blog_id)?;
+    let comment = Comment::get(&conn, id)?;
+    if comment.post_id != post.id || !comment.can_see(&conn, Some(&user)) {
+        return Err(Error::NotFound.into());
+    }
+
+    if let Ok(like) = CommentLike::find_by_user_on_comment(&conn, user.id, comment.id) {
+        let delete_act = like.build_undo(&conn)?;
+        inbox(
+            &conn,
+            serde_json::to_value(&delete_act).map_err(Error::from)?,
+        )?;
+
+        let dest = User::one_by_instance(&conn)?;
+        rockets
+            .worker
+            .execute(move || broadcast(&user, delete_act, dest, CONFIG.proxy().cloned()));
+    } else {
+        let like = CommentLike::insert(&conn, NewCommentLike::new(&comment, &user))?;
+        like.notify(&conn)?;
+
+        let dest = User::one_by_instance(&conn)?;
+        let act = like.to_activity(&conn)?;
+        rockets
+            .worker
+            .execute(move || broadcast(&user, act, dest, CONFIG.proxy().cloned()));
+    }
+
+    Ok(Redirect::to(format!(
+        "{}#comment-{}",
+        uri!(
+            super::posts::details: blog = blog,
+            slug = slug,
+            responding_to = _
+        ),
+        id
+    )))
+}
+
+#[post("/~/<blog>/<slug>/comment/<id>/like", rank = 2)]
+pub fn like_auth(blog: String, slug: String, id: i32, i18n: I18n) -> Flash<Redirect> {
+    requires_login(
+        &i18n!(i18n.catalog, "To like a comment, you need to be logged in"),
+        uri!(like: blog = blog, slug = slug, id = id),
+    )
+}
+
+#[post("/~/<blog>/<slug>/subscribe")]
+pub fn subscribe(
+    blog: String,
+    slug: String,
+    user: User,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let b = Blog::find_by_fqn(&conn, &blog)?;
+    let post = Post::find_by_slug(&conn, &slug, b.id)?;
+
+    let message = if ThreadSubscription::is_subscribed(&conn, &user, &post) {
+        ThreadSubscription::unsubscribe(&conn, &user, &post)?;
+        i18n!(
+            &rockets.intl.catalog,
+            "You will not be notified of new comments anymore."
+        )
+    } else {
+        ThreadSubscription::subscribe(&conn, &user, &post)?;
+        i18n!(
+            &rockets.intl.catalog,
+            "You will be notified of new comments."
+        )
+    };
+
+    Ok(Flash::success(
+        Redirect::to(format!(
+            "{}#comments",
+            uri!(
+                super::posts::details: blog = blog,
+                slug = slug,
+                responding_to = _
+            )
+        )),
+        message,
+    ))
+}
+
+#[post("/~/<blog>/<slug>/subscribe", rank = 2)]
+pub fn subscribe_auth(blog: String, slug: String, i18n: I18n) -> Flash<Redirect> {
+    requires_login(
+        &i18n!(
+            i18n.catalog,
+            "To follow the comments of an article, you need to be logged in"
+        ),
+        uri!(subscribe: blog = blog, slug = slug),
+    )
+}
+
 #[get("/~/<_blog>/<_slug>/comment/<id>")]
 pub fn activity_pub(
     _blog: String,
@@ -0,0 +1,155 @@
+use rocket::{
+    request::LenientForm,
+    response::{Flash, Redirect},
+};
+use rocket_i18n::I18n;
+
+use crate::routes::errors::ErrorPage;
+use crate::template_utils::{IntoContext, Ructe};
+use plume_models::{
+    blogs::Blog,
+    connectors::{Connector, ConnectorKind},
+    crossposts::Crosspost,
+    db_conn::DbConn,
+    users::User,
+    Error, PlumeRocket,
+};
+
+/// How many crossposts are listed for each connector.
+const RECENT_CROSSPOSTS: i64 = 10;
+
+#[derive(Default, FromForm)]
+pub struct NewConnectorForm {
+    pub kind: String,
+    pub url: String,
+    pub api_key: String,
+}
+
+#[get("/~/<name>/connectors")]
+pub fn list(
+    name: String,
+    user: User,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Result<Ructe, ErrorPage> {
+    let blog = Blog::find_by_fqn(&conn, &name)?;
+    if !user.can_manage(&conn, &blog)? {
+        return Err(Error::Unauthorized.into());
+    }
+    let connectors = Connector::list_for_blog(&conn, blog.id)?
+        .into_iter()
+        .map(|c| {
+            let crossposts = Crosspost::list_for_connector(&conn, c.id, RECENT_CROSSPOSTS)?
+                .into_iter()
+                .filter_map(|cp| Some((cp.get_post(&conn).ok()?, cp)))
+                .collect();
+            Ok((c, crossposts))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(render!(blogs::connectors(
+        &(&conn, &rockets).to_context(),
+        &blog,
+        connectors
+    )))
+}
+
+#[post("/~/<name>/connectors", data = "<form>")]
+pub fn create(
+    name: String,
+    form: LenientForm<NewConnectorForm>,
+    user: User,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let blog = Blog::find_by_fqn(&conn, &name)?;
+    if !user.can_manage(&conn, &blog)? {
+        return Err(Error::Unauthorized.into());
+    }
+    let created = form
+        .kind
+        .parse::<ConnectorKind>()
+        .and_then(|kind| Connector::create(&conn, &blog, kind, &form.url, Some(&form.api_key)));
+    Ok(match created {
+        Ok(_) => Flash::success(
+            Redirect::to(uri!(list: name = name)),
+            i18n!(
+                intl.catalog,
+                "The connector has been added. Articles published from now on will be sent to it."
+            ),
+        ),
+        Err(_) => Flash::error(
+            Redirect::to(uri!(list: name = name)),
+            i18n!(
+                intl.catalog,
+                "This connector couldn't be added. Please check its URL and API key."
+            ),
+        ),
+    })
+}
+
+#[post("/~/<name>/connectors/<id>/toggle")]
+pub fn toggle(
+    name: String,
+    id: i32,
+    user: User,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let connector = blog_connector(&conn, &name, id, &user)?;
+    connector.set_enabled(&conn, !connector.enabled)?;
+    Ok(Flash::success(
+        Redirect::to(uri!(list: name = name)),
+        if connector.enabled {
+            i18n!(intl.catalog, "The connector has been disabled.")
+        } else {
+            i18n!(intl.catalog, "The connector has been enabled.")
+        },
+    ))
+}
+
+#[post("/~/<name>/connectors/<id>/delete")]
+pub fn delete(
+    name: String,
+    id: i32,
+    user: User,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let connector = blog_connector(&conn, &name, id, &user)?;
+    connector.delete(&conn)?;
+    Ok(Flash::success(
+        Redirect::to(uri!(list: name = name)),
+        i18n!(intl.catalog, "The connector has been deleted."),
+    ))
+}
+
+#[post("/~/<name>/connectors/<id>/crossposts/<crosspost>/retry")]
+pub fn retry(
+    name: String,
+    id: i32,
+    crosspost: i32,
+    user: User,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let connector = blog_connector(&conn, &name, id, &user)?;
+    let crosspost = Crosspost::get(&conn, crosspost)?;
+    if crosspost.connector_id != connector.id {
+        return Err(Error::Unauthorized.into());
+    }
+    crosspost.retry(&conn)?;
+    Ok(Flash::success(
+        Redirect::to(uri!(list: name = name)),
+        i18n!(intl.catalog, "The article will be sent again shortly."),
+    ))
+}
+
+/// Finds a connector of `blog`, checking that `user` can manage it.
+fn blog_connector(conn: &DbConn, blog: &str, id: i32, user: &User) -> Result<Connector, Error> {
+    let blog = Blog::find_by_fqn(conn, blog)?;
+    let connector = Connector::get(conn, id)?;
+    if connector.blog_id != blog.id || !user.can_manage(conn, &blog)? {
+        return Err(Error::Unauthorized);
+    }
+    Ok(connector)
+}
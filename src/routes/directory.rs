@@ -0,0 +1,50 @@
+use crate::routes::{errors::ErrorPage, Page};
+use crate::template_utils::{IntoContext, Ructe};
+use plume_models::{
+    db_conn::DbConn,
+    directory::{self, Order, Window, CATEGORIES},
+    PlumeRocket,
+};
+
+/// The local blogs, or authors if `kind` is `authors`, that chose to be listed.
+#[get("/directory?<kind>&<category>&<order>&<page>")]
+pub fn index(
+    kind: Option<String>,
+    category: Option<String>,
+    order: Option<String>,
+    page: Option<Page>,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Result<Ructe, ErrorPage> {
+    let page = page.unwrap_or_default();
+    let authors = kind.as_deref() == Some("authors");
+    let category = category.filter(|c| CATEGORIES.contains(&c.as_str()));
+    let order = order
+        .as_deref()
+        .and_then(Order::from_name)
+        .unwrap_or_default();
+    let window = Window::Range(order, page.limits());
+
+    let (blogs, authors, total) = if authors {
+        (
+            None,
+            Some(directory::authors(&conn, category.as_deref(), window)?),
+            directory::count_authors(&conn, category.as_deref())?,
+        )
+    } else {
+        (
+            Some(directory::blogs(&conn, category.as_deref(), window)?),
+            None,
+            directory::count_blogs(&conn, category.as_deref())?,
+        )
+    };
+    Ok(render!(directory::index(
+        &(&conn, &rockets).to_context(),
+        blogs,
+        authors,
+        category,
+        order,
+        page.0,
+        Page::total(total as i32)
+    )))
+}
@@ -1,16 +1,21 @@
 use crate::{
     mail::{build_mail, Mailer},
-    routes::{errors::ErrorPage, RespondOrRedirect},
+    routes::{check_password, errors::ErrorPage, RespondOrRedirect},
     template_utils::{IntoContext, Ructe},
 };
 
 use plume_models::{
-    db_conn::DbConn, email_signups::EmailSignup, instance::Instance, lettre::Transport, signups,
-    Error, PlumeRocket, CONFIG,
+    db_conn::DbConn,
+    email_signups::EmailSignup,
+    instance::Instance,
+    ip_records::{ClientIp, IpRecord, IpRecordKind},
+    lettre::Transport,
+    rate_limits::{RateLimit, Registration},
+    request_limits::{Auth, LimitedForm},
+    signups, Error, PlumeRocket, CONFIG,
 };
 use rocket::{
     http::Status,
-    request::LenientForm,
     response::{Flash, Redirect},
     State,
 };
@@ -52,9 +57,8 @@ fn emails_match(form: &EmailSignupForm) -> Result<(), ValidationError> {
 pub struct NewUserForm {
     #[validate(length(min = 1, message = "Username should be at least 1 characters long"))]
     pub username: String,
-    #[validate(length(min = 8, message = "Password should be at least 8 characters long"))]
+    /// Checked with the password policy
     pub password: String,
-    #[validate(length(min = 8, message = "Password should be at least 8 characters long"))]
     pub password_confirmation: String,
     pub email: String,
     pub token: String,
@@ -70,10 +74,12 @@ pub fn passwords_match(form: &NewUserForm) -> Result<(), ValidationError> {
 
 #[post("/email_signups/new", data = "<form>")]
 pub fn create(
+    _limit: RateLimit<Registration>,
     mail: State<'_, Arc<Mutex<Mailer>>>,
-    form: LenientForm<EmailSignupForm>,
+    form: LimitedForm<Auth, EmailSignupForm>,
     conn: DbConn,
     rockets: PlumeRocket,
+    ip: ClientIp,
     _enabled: signups::Email,
 ) -> Result<RespondOrRedirect, ErrorPage> {
     let registration_open = Instance::get_local()
@@ -101,7 +107,8 @@ pub fn create(
         ))
         .into());
     }
-    let res = EmailSignup::start(&conn, &form.email);
+    let res = IpRecord::check_registration(&conn, ip.0)
+        .and_then(|_| EmailSignup::start(&conn, &form.email));
     if let Some(err) = res.as_ref().err() {
         return Ok(match err {
             Error::UserAlreadyExists => {
@@ -219,9 +226,11 @@ pub fn show(
 
 #[post("/email_signups/signup", data = "<form>")]
 pub fn signup(
-    form: LenientForm<NewUserForm>,
+    _limit: RateLimit<Registration>,
+    form: LimitedForm<Auth, NewUserForm>,
     conn: DbConn,
     rockets: PlumeRocket,
+    ip: ClientIp,
     _enabled: signups::Email,
 ) -> Result<RespondOrRedirect, Status> {
     use RespondOrRedirect::{FlashRedirect, Response};
@@ -230,7 +239,11 @@ pub fn signup(
         warn!("{:?}", e);
         Status::InternalServerError
     })?;
-    if let Some(err) = form.validate().err() {
+    if let Some(err) = form
+        .validate()
+        .and_then(|_| check_password(&form.password, &[&form.username, &form.email]))
+        .err()
+    {
         return Ok(Response(render!(email_signups::edit(
             &(&conn, &rockets).to_context(),
             instance.open_registrations,
@@ -254,7 +267,8 @@ pub fn signup(
             err
         ))));
     }
-    let user = signup.complete(&conn, form.username.clone(), form.password.clone());
+    let user = IpRecord::check_registration(&conn, ip.0)
+        .and_then(|_| signup.complete(&conn, form.username.clone(), form.password.clone()));
     match user {
         Err(Error::Blocklisted(show, msg)) => {
             let instance = Instance::get_local().map_err(|_| Status::UnprocessableEntity)?;
@@ -284,7 +298,13 @@ pub fn signup(
             warn!("{:?}", e);
             return Err(Status::UnprocessableEntity);
         }
-        _ => {}
+        Ok(user) => {
+            if let Err(e) =
+                IpRecord::record(&conn, IpRecordKind::Registration, ip.0, Some(user.id), None)
+            {
+                warn!("{:?}", e);
+            }
+        }
     }
     Ok(FlashRedirect(Flash::success(
         Redirect::to(uri!(super::session::new: m = _)),
@@ -1,10 +1,15 @@
 use crate::template_utils::{IntoContext, Ructe};
-use plume_models::{db_conn::DbConn, Error, PlumeRocket};
+use plume_models::{
+    db_conn::DbConn,
+    maintenance::{self, Maintenance, RefusedPath},
+    rate_limits, Error, PlumeRocket,
+};
 use rocket::{
     http::Status,
-    response::{self, Responder},
+    response::{self, Responder, Response},
     Request,
 };
+use rocket_contrib::json::Json;
 use tracing::warn;
 
 #[derive(Debug)]
@@ -45,6 +50,79 @@ pub fn unprocessable_entity(req: &Request<'_>) -> Ructe {
     ))
 }
 
+/// Requests refused by a `RateLimit` guard.
+#[catch(429)]
+pub fn too_many_requests(req: &Request<'_>) -> response::Result<'static> {
+    let retry_after = rate_limits::retry_after(req);
+    let body = if wants_json(req, req.uri().path()) {
+        Json(json!({
+            "error": "Too many requests",
+            "retry_after": retry_after,
+        }))
+        .respond_to(req)?
+    } else {
+        let conn = req.guard::<DbConn>().unwrap();
+        let rockets = req.guard::<PlumeRocket>().unwrap();
+        render!(errors::too_many_requests(
+            &(&conn, &rockets).to_context(),
+            retry_after
+        ))
+        .respond_to(req)?
+    };
+    Response::build()
+        .merge(body)
+        .raw_header("Retry-After", retry_after.to_string())
+        .ok()
+}
+
+/// Requests sent to `path` expecting JSON, like API calls and activities.
+fn wants_json(req: &Request<'_>, path: &str) -> bool {
+    path.starts_with("/api/")
+        || req
+            .content_type()
+            .map_or(false, |ct| ct.sub().as_str().ends_with("json"))
+}
+
+/// Where `MaintenanceMode` sends the requests that could change something.
+#[get("/maintenance")]
+pub fn maintenance(conn: DbConn) -> Option<Unavailable> {
+    if Maintenance::is_active(&conn) {
+        Some(Unavailable)
+    } else {
+        None
+    }
+}
+
+pub struct Unavailable;
+
+impl<'r> Responder<'r> for Unavailable {
+    fn respond_to(self, req: &Request<'_>) -> response::Result<'r> {
+        let path = req.local_cache(|| RefusedPath(req.uri().path().to_owned()));
+        let conn = req.guard::<DbConn>().unwrap();
+        let reason = Maintenance::current(&conn)
+            .ok()
+            .flatten()
+            .map(|m| m.reason)
+            .unwrap_or_default();
+        let body = if wants_json(req, &path.0) {
+            Json(json!({
+                "error": "Maintenance in progress",
+                "reason": reason,
+                "retry_after": maintenance::RETRY_AFTER,
+            }))
+            .respond_to(req)?
+        } else {
+            let rockets = req.guard::<PlumeRocket>().unwrap();
+            render!(errors::maintenance(&(&conn, &rockets).to_context(), reason)).respond_to(req)?
+        };
+        Response::build()
+            .merge(body)
+            .status(Status::ServiceUnavailable)
+            .raw_header("Retry-After", maintenance::RETRY_AFTER.to_string())
+            .ok()
+    }
+}
+
 #[catch(500)]
 pub fn server_error(req: &Request<'_>) -> Ructe {
     let conn = req.guard::<DbConn>().unwrap();
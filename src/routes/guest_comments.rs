@@ -0,0 +1,164 @@
+use rocket::{
+    request::LenientForm,
+    response::{Flash, Redirect},
+};
+use rocket_i18n::I18n;
+use validator::Validate;
+
+use crate::routes::errors::ErrorPage;
+use crate::template_utils::{IntoContext, Ructe};
+use plume_models::{
+    blogs::Blog,
+    db_conn::DbConn,
+    guest_comments::GuestComment,
+    post_views::Visitor,
+    posts::Post,
+    rate_limits::{Commenting, RateLimit},
+    users::User,
+    Error, PlumeRocket,
+};
+
+#[derive(Default, FromForm, Debug, Validate)]
+pub struct GuestCommentForm {
+    #[validate(length(min = 1, max = 64, message = "Your name can't be empty"))]
+    pub name: String,
+    #[validate(email(message = "Invalid email"))]
+    pub email: Option<String>,
+    #[validate(length(min = 1, message = "Your comment can't be empty"))]
+    pub content: String,
+}
+
+#[post("/~/<blog_name>/<slug>/guest-comment", data = "<form>")]
+pub fn create(
+    _limit: RateLimit<Commenting>,
+    blog_name: String,
+    slug: String,
+    form: LenientForm<GuestCommentForm>,
+    visitor: Visitor,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let blog = Blog::find_by_fqn(&conn, &blog_name)?;
+    let post = Post::find_by_slug(&conn, &slug, blog.id)?;
+    let back = format!(
+        "{}#comments",
+        uri!(
+            super::posts::details: blog = &blog_name,
+            slug = &slug,
+            responding_to = _
+        )
+    );
+
+    if !blog.allow_guest_comments {
+        return Err(Error::Unauthorized.into());
+    }
+    let email = form.email.clone().filter(|e| !e.trim().is_empty());
+    let form = GuestCommentForm {
+        email,
+        name: form.name.clone(),
+        content: form.content.clone(),
+    };
+    if form.validate().is_err() {
+        return Ok(Flash::error(
+            Redirect::to(back),
+            i18n!(
+                intl.catalog,
+                "Please give your name and a comment, and check your email address."
+            ),
+        ));
+    }
+    if GuestComment::is_rate_limited(&conn, &visitor)? {
+        return Ok(Flash::error(
+            Redirect::to(back),
+            i18n!(
+                intl.catalog,
+                "You posted too many comments recently, please try again later."
+            ),
+        ));
+    }
+
+    GuestComment::create(
+        &conn,
+        &post,
+        &form.name,
+        form.email.as_deref(),
+        &form.content,
+        &visitor,
+    )?;
+    Ok(Flash::success(
+        Redirect::to(back),
+        i18n!(
+            intl.catalog,
+            "Your comment has been sent, and will be published once approved by the authors of this blog."
+        ),
+    ))
+}
+
+#[get("/~/<name>/guest-comments")]
+pub fn moderation(
+    name: String,
+    user: User,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Result<Ructe, ErrorPage> {
+    let blog = Blog::find_by_fqn(&conn, &name)?;
+    if !user.can_publish_in(&conn, &blog)? {
+        return Err(Error::Unauthorized.into());
+    }
+    let comments = GuestComment::list_pending_for_blog(&conn, &blog)?
+        .into_iter()
+        .filter_map(|c| Some((c.get_post(&conn).ok()?, c)))
+        .collect();
+    Ok(render!(blogs::guest_comments(
+        &(&conn, &rockets).to_context(),
+        &blog,
+        comments
+    )))
+}
+
+#[post("/~/<name>/guest-comments/<id>/approve")]
+pub fn approve(
+    name: String,
+    id: i32,
+    user: User,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let comment = moderated_comment(&conn, &name, id, &user)?;
+    comment.approve(&conn)?;
+    Ok(Flash::success(
+        Redirect::to(uri!(moderation: name = name)),
+        i18n!(intl.catalog, "The comment has been published."),
+    ))
+}
+
+#[post("/~/<name>/guest-comments/<id>/delete")]
+pub fn delete(
+    name: String,
+    id: i32,
+    user: User,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let comment = moderated_comment(&conn, &name, id, &user)?;
+    comment.delete(&conn)?;
+    Ok(Flash::success(
+        Redirect::to(uri!(moderation: name = name)),
+        i18n!(intl.catalog, "The comment has been deleted."),
+    ))
+}
+
+/// Finds a guest comment on `blog`, checking that `user` can moderate it.
+fn moderated_comment(
+    conn: &DbConn,
+    blog: &str,
+    id: i32,
+    user: &User,
+) -> Result<GuestComment, Error> {
+    let blog = Blog::find_by_fqn(conn, blog)?;
+    let comment = GuestComment::get(conn, id)?;
+    if comment.get_post(conn)?.blog_id != blog.id || !user.can_publish_in(conn, &blog)? {
+        return Err(Error::Unauthorized);
+    }
+    Ok(comment)
+}
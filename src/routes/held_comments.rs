@@ -0,0 +1,76 @@
+use rocket::response::{Flash, Redirect};
+use rocket_i18n::I18n;
+
+use crate::routes::errors::ErrorPage;
+use crate::template_utils::{IntoContext, Ructe};
+use plume_models::{
+    blogs::Blog, comments::Comment, db_conn::DbConn, users::User, Error, PlumeRocket,
+};
+
+/// The comments from other instances that were held because they look like spam.
+#[get("/~/<name>/held-comments")]
+pub fn moderation(
+    name: String,
+    user: User,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Result<Ructe, ErrorPage> {
+    let blog = Blog::find_by_fqn(&conn, &name)?;
+    if !user.can_publish_in(&conn, &blog)? {
+        return Err(Error::Unauthorized.into());
+    }
+    let comments = Comment::list_held_for_blog(&conn, &blog)?
+        .into_iter()
+        .filter_map(|c| Some((c.get_post(&conn).ok()?, c.get_author(&conn).ok()?, c)))
+        .collect();
+    Ok(render!(blogs::held_comments(
+        &(&conn, &rockets).to_context(),
+        &blog,
+        comments
+    )))
+}
+
+#[post("/~/<name>/held-comments/<id>/approve")]
+pub fn approve(
+    name: String,
+    id: i32,
+    user: User,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let comment = held_comment(&conn, &name, id, &user)?;
+    comment.approve(&conn)?;
+    Ok(Flash::success(
+        Redirect::to(uri!(moderation: name = name)),
+        i18n!(intl.catalog, "The comment has been published."),
+    ))
+}
+
+#[post("/~/<name>/held-comments/<id>/delete")]
+pub fn delete(
+    name: String,
+    id: i32,
+    user: User,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let comment = held_comment(&conn, &name, id, &user)?;
+    comment.delete(&conn)?;
+    Ok(Flash::success(
+        Redirect::to(uri!(moderation: name = name)),
+        i18n!(intl.catalog, "The comment has been deleted."),
+    ))
+}
+
+/// Finds a held comment on `blog`, checking that `user` can moderate it.
+fn held_comment(conn: &DbConn, blog: &str, id: i32, user: &User) -> Result<Comment, Error> {
+    let blog = Blog::find_by_fqn(conn, blog)?;
+    let comment = Comment::get(conn, id)?;
+    if !comment.held
+        || comment.get_post(conn)?.blog_id != blog.id
+        || !user.can_publish_in(conn, &blog)?
+    {
+        return Err(Error::Unauthorized);
+    }
+    Ok(comment)
+}
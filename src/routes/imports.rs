@@ -0,0 +1,152 @@
+use crate::routes::errors::ErrorPage;
+use crate::template_utils::{IntoContext, Ructe};
+use multipart::server::{
+    save::{PartialReason, SaveResult, SavedData},
+    Multipart,
+};
+use plume_models::{
+    blog_transfer,
+    blogs::Blog,
+    db_conn::{DbConn, DbPool},
+    import::{self, ghost, medium},
+    posts::Post,
+    request_limits::{error_status, LimitedData, Upload},
+    users::User,
+    Error, PlumeRocket,
+};
+use rocket::{
+    http::{ContentType, Status},
+    response::{status, Redirect},
+    State,
+};
+use std::fs;
+use std::io::Cursor;
+use tracing::warn;
+
+#[get("/~/<name>/import")]
+pub fn new(
+    name: String,
+    user: User,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Result<Ructe, ErrorPage> {
+    let blog = Blog::find_by_fqn(&conn, &name)?;
+    if !user.can_publish_in(&conn, &blog)? || blog.moved_to.is_some() {
+        return Err(Error::Unauthorized.into());
+    }
+    Ok(render!(blogs::import(
+        &(&conn, &rockets).to_context(),
+        &blog
+    )))
+}
+
+#[post("/~/<name>/import", data = "<data>")]
+pub fn upload(
+    name: String,
+    user: User,
+    data: LimitedData<Upload>,
+    ct: &ContentType,
+    conn: DbConn,
+    pool: State<'_, DbPool>,
+    rockets: PlumeRocket,
+) -> Result<Result<Ructe, Redirect>, status::Custom<&'static str>> {
+    let blog = Blog::find_by_fqn(&conn, &name)
+        .map_err(|_| status::Custom(Status::BadRequest, "Unknown blog"))?;
+    if !user.can_publish_in(&conn, &blog).unwrap_or(false) {
+        return Err(status::Custom(
+            Status::BadRequest,
+            "You are not an author of this blog",
+        ));
+    }
+    if blog.moved_to.is_some() {
+        return Err(status::Custom(
+            Status::BadRequest,
+            "This blog moved to another place",
+        ));
+    }
+    if !ct.is_form_data() {
+        return Ok(Err(Redirect::to(uri!(new: name = name))));
+    }
+
+    let (_, boundary) = ct
+        .params()
+        .find(|&(k, _)| k == "boundary")
+        .ok_or(status::Custom(Status::BadRequest, "No boundary"))?;
+
+    let saved = Multipart::with_body(data.open(), boundary).save().temp();
+    if let SaveResult::Full(entries) = saved {
+        let file = entries
+            .fields
+            .get("file")
+            .and_then(|v| v.iter().next())
+            .ok_or(status::Custom(Status::BadRequest, "No file uploaded"))?;
+        let bytes = match file.data {
+            SavedData::Bytes(ref bytes) => bytes.clone(),
+            SavedData::File(ref path, _) => fs::read(path)
+                .map_err(|_| status::Custom(Status::BadRequest, "Couldn't read the file"))?,
+            _ => return Ok(Err(Redirect::to(uri!(new: name = name)))),
+        };
+
+        let format = match entries.fields.get("format").and_then(|v| v.iter().next()) {
+            Some(field) => match field.data {
+                SavedData::Text(ref format) => format.clone(),
+                _ => return Err(status::Custom(Status::BadRequest, "Invalid format")),
+            },
+            None => "medium".to_owned(),
+        };
+        let mut moved_from = None;
+        let report = match format.as_ref() {
+            "medium" => medium::import_archive(&conn, Cursor::new(bytes), &blog, &user)
+                .map_err(|_| status::Custom(Status::BadRequest, "Invalid archive"))?,
+            "plume" => {
+                let report = blog_transfer::import(&conn, Cursor::new(bytes), &blog, &user)
+                    .map_err(|_| status::Custom(Status::BadRequest, "Invalid Plume archive"))?;
+                moved_from = Some(report.moved_from);
+                report.articles
+            }
+            "ghost" => {
+                let json = String::from_utf8(bytes)
+                    .map_err(|_| status::Custom(Status::BadRequest, "Invalid Ghost export"))?;
+                ghost::import_export(&conn, &json, &blog, &user)
+                    .map_err(|_| status::Custom(Status::BadRequest, "Invalid Ghost export"))?
+            }
+            _ => return Err(status::Custom(Status::BadRequest, "Unknown format")),
+        };
+        if moved_from.is_none() {
+            localize_images(&pool, &rockets, &report.posts, &user);
+        }
+        Ok(Ok(render!(blogs::import_report(
+            &(&conn, &rockets).to_context(),
+            &blog,
+            report,
+            moved_from
+        ))))
+    } else {
+        match saved {
+            // The body was cut off, because it was too large or too slow to be received
+            SaveResult::Partial(_, PartialReason::IoError(ref err))
+            | SaveResult::Error(ref err) => Err(status::Custom(
+                error_status(err),
+                "The file couldn't be received",
+            )),
+            _ => Ok(Err(Redirect::to(uri!(new: name = name)))),
+        }
+    }
+}
+
+/// Downloads the images of imported articles in the background, not to make the import wait
+/// for other websites.
+fn localize_images(pool: &DbPool, rockets: &PlumeRocket, posts: &[Post], author: &User) {
+    let ids = posts.iter().map(|post| post.id).collect::<Vec<_>>();
+    let (pool, author) = (pool.clone(), author.clone());
+    rockets.worker.execute(move || match pool.get() {
+        Ok(conn) => {
+            for id in ids {
+                if let Err(err) = import::localize_post_images(&conn, id, &author) {
+                    warn!("Couldn't download the images of post {}: {:?}", id, err);
+                }
+            }
+        }
+        Err(_) => warn!("Couldn't download imported images: no database connection"),
+    });
+}
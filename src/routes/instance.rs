@@ -1,28 +1,45 @@
+use chrono::NaiveDateTime;
 use rocket::{
+    http::{ext::IntoOwned, uri::Uri, ContentType},
     request::{Form, FormItems, FromForm, LenientForm},
-    response::{status, Flash, Redirect},
+    response::{status, Content, Flash, Redirect},
+    State,
 };
 use rocket_contrib::json::Json;
 use rocket_i18n::I18n;
-use scheduled_thread_pool::ScheduledThreadPool;
-use std::str::FromStr;
+use std::{str::FromStr, sync::Arc};
+use tracing::warn;
 use validator::{Validate, ValidationErrors};
 
 use crate::inbox;
 use crate::routes::{errors::ErrorPage, rocket_uri_macro_static_files, Page, RespondOrRedirect};
 use crate::template_utils::{IntoContext, Ructe};
-use plume_common::activity_pub::{broadcast, inbox::FromId};
+use crate::utils::requires_login;
+use plume_common::{activity_pub::broadcast, utils::escape};
 use plume_models::{
+    about::{AboutSection, InstanceRule},
     admin::*,
+    announcements::Announcement,
     blocklisted_emails::*,
+    blocklists::{BlocklistException, BlocklistSource},
     comments::Comment,
-    db_conn::DbConn,
+    db_conn::{DbConn, DbPool, POOL_METRICS},
+    federation_digests::FederationDigest,
     headers::Headers,
     instance::*,
+    legal_documents::{DocumentKind, LegalDocument, PendingFrom},
+    lookup,
+    media_policies::{MediaAction, MediaPolicy},
+    neighborhood,
     posts::Post,
+    rate_limits::{Inbox, RateLimit},
     safe_string::SafeString,
+    severed_relationships::Severance,
+    signature_pool::SignaturePool,
+    tag_aliases::TagAlias,
     timeline::Timeline,
     users::{Role, User},
+    worker::Worker,
     Connection, Error, PlumeRocket, CONFIG,
 };
 
@@ -35,8 +52,8 @@ pub fn index(conn: DbConn, rockets: PlumeRocket) -> Result<Ructe, ErrorPage> {
         let inst = Instance::get_local()?;
         let page = Page::default();
         let tl = &all_tl[0];
-        let posts = tl.get_page(&conn, page.limits())?;
-        let total_posts = tl.count_posts(&conn)?;
+        let posts = tl.get_page_for(&conn, page.limits(), rockets.user.as_ref())?;
+        let total_posts = tl.count_posts_for(&conn, rockets.user.as_ref())?;
         Ok(render!(instance::index(
             &(&conn, &rockets).to_context(),
             inst,
@@ -51,7 +68,12 @@ pub fn index(conn: DbConn, rockets: PlumeRocket) -> Result<Ructe, ErrorPage> {
 }
 
 #[get("/admin")]
-pub fn admin(_admin: InclusiveAdmin, conn: DbConn, rockets: PlumeRocket) -> Result<Ructe, ErrorPage> {
+pub fn admin(
+    _admin: InclusiveAdmin,
+    conn: DbConn,
+    rockets: PlumeRocket,
+    pool: State<'_, DbPool>,
+) -> Result<Ructe, ErrorPage> {
     let local_inst = Instance::get_local()?;
     Ok(render!(instance::admin(
         &(&conn, &rockets).to_context(),
@@ -63,7 +85,8 @@ pub fn admin(_admin: InclusiveAdmin, conn: DbConn, rockets: PlumeRocket) -> Resu
             long_description: local_inst.long_description,
             default_license: local_inst.default_license,
         },
-        ValidationErrors::default()
+        ValidationErrors::default(),
+        POOL_METRICS.stats(&pool)
     )))
 }
 
@@ -85,10 +108,11 @@ pub struct InstanceSettingsForm {
 
 #[post("/admin", data = "<form>")]
 pub fn update_settings(
-    _admin: Admin,
+    _admin: Can<permissions::ManageSettings>,
     form: LenientForm<InstanceSettingsForm>,
     conn: DbConn,
     rockets: PlumeRocket,
+    pool: State<'_, DbPool>,
 ) -> RespondOrRedirect {
     if let Err(e) = form.validate() {
         let local_inst =
@@ -97,7 +121,8 @@ pub fn update_settings(
             &(&conn, &rockets).to_context(),
             local_inst,
             form.clone(),
-            e
+            e,
+            POOL_METRICS.stats(&pool)
         ))
         .into()
     } else {
@@ -134,6 +159,7 @@ pub fn admin_instances(
         &(&conn, &rockets).to_context(),
         Instance::get_local()?,
         instances,
+        neighborhood::instance_ids(&conn)?,
         page.0,
         Page::total(Instance::count(&conn)? as i32)
     )))
@@ -141,7 +167,7 @@ pub fn admin_instances(
 
 #[post("/admin/instances/<id>/block")]
 pub fn toggle_block(
-    _mod: Moderator,
+    _mod: Can<permissions::BlockInstances>,
     conn: DbConn,
     id: i32,
     intl: I18n,
@@ -154,12 +180,65 @@ pub fn toggle_block(
     };
 
     inst.toggle_block(&conn)?;
+    if !inst.blocked {
+        Severance::sever_instance(&conn, &inst)?;
+    } else if inst.blocked_by_subscription {
+        // Otherwise the next sync would block it again
+        BlocklistException::add(&conn, &inst.public_domain)?;
+    }
     Ok(Flash::success(
         Redirect::to(uri!(admin_instances: page = _)),
         message,
     ))
 }
 
+/// Follows an instance, or stops following it, to see its articles in the `neighborhood`
+/// timeline source.
+#[post("/admin/instances/<id>/follow")]
+pub fn toggle_follow(
+    _admin: Can<permissions::ManageSettings>,
+    conn: DbConn,
+    id: i32,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let inst = Instance::get(&conn, id)?;
+    let message = if neighborhood::instance_ids(&conn)?.contains(&inst.id) {
+        neighborhood::unfollow(&conn, &inst)?;
+        i18n!(intl.catalog, "You are not following {} anymore."; &inst.name)
+    } else {
+        neighborhood::follow(&conn, &inst.public_domain)?;
+        i18n!(intl.catalog, "You are now following {}."; &inst.name)
+    };
+    Ok(Flash::success(
+        Redirect::to(uri!(admin_instances: page = _)),
+        message,
+    ))
+}
+
+/// The instances that kept failing to receive activities, and what can be done about them.
+#[get("/admin/federation/<id>")]
+pub fn federation_digest(
+    _admin: Admin,
+    id: i32,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Result<Ructe, ErrorPage> {
+    let digest = FederationDigest::get(&conn, id)?;
+    let hosts = digest
+        .hosts()?
+        .into_iter()
+        .map(|report| {
+            let instance = Instance::find_by_domain(&conn, &report.host).ok();
+            (report, instance)
+        })
+        .collect();
+    Ok(render!(instance::federation_digest(
+        &(&conn, &rockets).to_context(),
+        digest,
+        hosts
+    )))
+}
+
 #[get("/admin/users?<page>", rank = 2)]
 pub fn admin_users(
     _mod: Moderator,
@@ -217,7 +296,7 @@ impl<'f> FromForm<'f> for BlocklistEmailDeletion {
 }
 #[post("/admin/emails/delete", data = "<form>")]
 pub fn delete_email_blocklist(
-    _mod: Moderator,
+    _mod: Can<permissions::ManageEmailBlocklist>,
     form: Form<BlocklistEmailDeletion>,
     conn: DbConn,
     rockets: PlumeRocket,
@@ -231,7 +310,7 @@ pub fn delete_email_blocklist(
 
 #[post("/admin/emails/new", data = "<form>")]
 pub fn add_email_blocklist(
-    _mod: Moderator,
+    _mod: Can<permissions::ManageEmailBlocklist>,
     form: LenientForm<NewBlocklistedEmail>,
     conn: DbConn,
     rockets: PlumeRocket,
@@ -266,6 +345,668 @@ pub fn admin_email_blocklist(
     )))
 }
 
+#[derive(Default, FromForm)]
+pub struct TagAliasForm {
+    pub alias: String,
+    pub tag: String,
+}
+
+#[get("/admin/tags")]
+pub fn admin_tag_aliases(
+    _mod: Moderator,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Result<Ructe, ErrorPage> {
+    Ok(render!(instance::tag_aliases(
+        &(&conn, &rockets).to_context(),
+        TagAlias::list(&conn)?
+    )))
+}
+
+#[post("/admin/tags", data = "<form>")]
+pub fn add_tag_alias(
+    _mod: Can<permissions::ManageTags>,
+    form: LenientForm<TagAliasForm>,
+    conn: DbConn,
+    pool: State<'_, DbPool>,
+    rockets: PlumeRocket,
+) -> Flash<Redirect> {
+    match TagAlias::create(&conn, &form.alias, &form.tag) {
+        // Each renamed article is saved and indexed again, which can take a while
+        Ok(alias) => {
+            let pool = pool.clone();
+            rockets.worker.execute(move || match pool.get() {
+                Ok(conn) => {
+                    if let Err(e) = alias.rewrite_tags(&conn) {
+                        warn!("Failed to rename the tags {}: {:?}", alias.alias, e);
+                    }
+                }
+                Err(_) => warn!(
+                    "Couldn't rename the tags {}: no database connection",
+                    alias.alias
+                ),
+            });
+            Flash::success(
+                Redirect::to(uri!(admin_tag_aliases)),
+                i18n!(
+                    rockets.intl.catalog,
+                    "The alias has been added. The tags will be merged in a moment."
+                ),
+            )
+        }
+        Err(_) => Flash::error(
+            Redirect::to(uri!(admin_tag_aliases)),
+            i18n!(
+                rockets.intl.catalog,
+                "This alias couldn't be added. It may already be used, or be the tag itself."
+            ),
+        ),
+    }
+}
+
+#[post("/admin/tags/<id>/delete")]
+pub fn delete_tag_alias(
+    _mod: Can<permissions::ManageTags>,
+    id: i32,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    TagAlias::get(&conn, id)?.delete(&conn)?;
+    Ok(Flash::success(
+        Redirect::to(uri!(admin_tag_aliases)),
+        i18n!(
+            intl.catalog,
+            "The alias has been deleted. Tags that were already merged keep their new name."
+        ),
+    ))
+}
+
+#[derive(Default, FromForm)]
+pub struct MediaPolicyForm {
+    /// Empty for the policy of sensitive media
+    pub domain: String,
+    pub action: String,
+}
+
+#[get("/admin/media")]
+pub fn admin_media_policies(
+    _admin: Can<permissions::ManageSettings>,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Result<Ructe, ErrorPage> {
+    Ok(render!(instance::media_policies(
+        &(&conn, &rockets).to_context(),
+        MediaPolicy::list(&conn)?
+    )))
+}
+
+#[post("/admin/media", data = "<form>")]
+pub fn set_media_policy(
+    _admin: Can<permissions::ManageSettings>,
+    form: LenientForm<MediaPolicyForm>,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let domain = Some(form.domain.trim()).filter(|d| !d.is_empty());
+    let saved = MediaAction::from_name(&form.action)
+        .ok_or(Error::InvalidValue)
+        .and_then(|action| MediaPolicy::set(&conn, domain, action));
+    Ok(match saved {
+        Ok(_) => Flash::success(
+            Redirect::to(uri!(admin_media_policies)),
+            i18n!(intl.catalog, "The media policy has been saved."),
+        ),
+        Err(_) => Flash::error(
+            Redirect::to(uri!(admin_media_policies)),
+            i18n!(
+                intl.catalog,
+                "This media policy couldn't be saved. Please check the domain."
+            ),
+        ),
+    })
+}
+
+#[post("/admin/media/<id>/delete")]
+pub fn delete_media_policy(
+    _admin: Can<permissions::ManageSettings>,
+    id: i32,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    MediaPolicy::get(&conn, id)?.delete(&conn)?;
+    Ok(Flash::success(
+        Redirect::to(uri!(admin_media_policies)),
+        i18n!(intl.catalog, "The media policy has been deleted."),
+    ))
+}
+
+#[derive(Default, FromForm)]
+pub struct BlocklistForm {
+    pub url: String,
+}
+
+#[derive(Default, FromForm)]
+pub struct BlocklistExceptionForm {
+    pub domain: String,
+}
+
+#[get("/admin/blocklists")]
+pub fn admin_blocklists(
+    _admin: Can<permissions::ManageSettings>,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Result<Ructe, ErrorPage> {
+    let sources = BlocklistSource::list(&conn)?
+        .into_iter()
+        .map(|source| {
+            let count = source.count_entries(&conn)?;
+            Ok((source, count))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(render!(instance::blocklists(
+        &(&conn, &rockets).to_context(),
+        sources,
+        BlocklistException::list(&conn)?
+    )))
+}
+
+#[post("/admin/blocklists", data = "<form>")]
+pub fn add_blocklist(
+    _admin: Can<permissions::ManageSettings>,
+    form: LenientForm<BlocklistForm>,
+    conn: DbConn,
+    pool: State<'_, DbPool>,
+    rockets: PlumeRocket,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let source = match BlocklistSource::add(&conn, &form.url) {
+        Ok(source) => source,
+        Err(_) => {
+            return Ok(Flash::error(
+                Redirect::to(uri!(admin_blocklists)),
+                i18n!(
+                    rockets.intl.catalog,
+                    "This blocklist couldn't be added. Please check its address."
+                ),
+            ))
+        }
+    };
+    Ok(sync_later(&pool, &rockets, &source))
+}
+
+#[get("/admin/blocklists/<id>")]
+pub fn blocklist_details(
+    _admin: Can<permissions::ManageSettings>,
+    id: i32,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Result<Ructe, ErrorPage> {
+    let source = BlocklistSource::get(&conn, id)?;
+    let entries = source.entries(&conn)?;
+    Ok(render!(instance::blocklist_details(
+        &(&conn, &rockets).to_context(),
+        source,
+        entries,
+        BlocklistException::list(&conn)?
+    )))
+}
+
+#[post("/admin/blocklists/<id>/sync")]
+pub fn sync_blocklist(
+    _admin: Can<permissions::ManageSettings>,
+    id: i32,
+    conn: DbConn,
+    pool: State<'_, DbPool>,
+    rockets: PlumeRocket,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let source = BlocklistSource::get(&conn, id)?;
+    Ok(sync_later(&pool, &rockets, &source))
+}
+
+#[post("/admin/blocklists/<id>/delete")]
+pub fn delete_blocklist(
+    _admin: Can<permissions::ManageSettings>,
+    id: i32,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    BlocklistSource::get(&conn, id)?.delete(&conn)?;
+    Ok(Flash::success(
+        Redirect::to(uri!(admin_blocklists)),
+        i18n!(
+            intl.catalog,
+            "You unsubscribed from this blocklist, and the instances it blocked have been unblocked."
+        ),
+    ))
+}
+
+#[post("/admin/blocklists/exceptions", data = "<form>")]
+pub fn add_blocklist_exception(
+    _admin: Can<permissions::ManageSettings>,
+    form: LenientForm<BlocklistExceptionForm>,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    Ok(match BlocklistException::add(&conn, &form.domain) {
+        Ok(_) => Flash::success(
+            Redirect::to(uri!(admin_blocklists)),
+            i18n!(
+                intl.catalog,
+                "{} will not be blocked by blocklists anymore."; form.domain.trim()
+            ),
+        ),
+        Err(_) => Flash::error(
+            Redirect::to(uri!(admin_blocklists)),
+            i18n!(
+                intl.catalog,
+                "This exception couldn't be saved. Please check the domain."
+            ),
+        ),
+    })
+}
+
+#[post("/admin/blocklists/exceptions/<id>/delete")]
+pub fn delete_blocklist_exception(
+    _admin: Can<permissions::ManageSettings>,
+    id: i32,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    BlocklistException::get(&conn, id)?.delete(&conn)?;
+    Ok(Flash::success(
+        Redirect::to(uri!(admin_blocklists)),
+        i18n!(intl.catalog, "The exception has been deleted."),
+    ))
+}
+
+/// Syncs `source` and applies it, telling how it went.
+/// Syncs a blocklist in the background: downloading it and severing the relationships with
+/// the instances it blocks can take a while.
+fn sync_later(pool: &DbPool, rockets: &PlumeRocket, source: &BlocklistSource) -> Flash<Redirect> {
+    let (pool, source_id, url) = (pool.clone(), source.id, source.url.clone());
+    rockets.worker.execute(move || match pool.get() {
+        Ok(conn) => {
+            if let Err(e) = BlocklistSource::get(&conn, source_id).and_then(|s| s.sync(&conn)) {
+                warn!("Failed to sync the blocklist at {}: {:?}", url, e);
+            }
+        }
+        Err(_) => warn!(
+            "Couldn't sync the blocklist at {}: no database connection",
+            url
+        ),
+    });
+    Flash::success(
+        Redirect::to(uri!(blocklist_details: id = source.id)),
+        i18n!(
+            rockets.intl.catalog,
+            "The blocklist will be synced in a moment. Reload this page to see the result."
+        ),
+    )
+}
+
+#[derive(Default, FromForm)]
+pub struct ContactForm {
+    /// Empty for the main administrator
+    pub username: String,
+}
+
+#[derive(Default, FromForm)]
+pub struct InstanceRuleForm {
+    pub short_text: String,
+    pub long_text: String,
+}
+
+#[derive(Default, FromForm)]
+pub struct AboutSectionForm {
+    pub title: String,
+    pub content: String,
+}
+
+#[get("/admin/about")]
+pub fn admin_about(
+    _admin: Can<permissions::ManageSettings>,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Result<Ructe, ErrorPage> {
+    let instance = Instance::get_local()?;
+    let contact = match instance.contact_user_id {
+        Some(id) => Some(User::get(&conn, id)?),
+        None => None,
+    };
+    Ok(render!(instance::about_settings(
+        &(&conn, &rockets).to_context(),
+        contact,
+        InstanceRule::list(&conn)?,
+        AboutSection::list(&conn)?
+    )))
+}
+
+#[post("/admin/about/contact", data = "<form>")]
+pub fn set_contact(
+    _admin: Can<permissions::ManageSettings>,
+    form: LenientForm<ContactForm>,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let username = form.username.trim().trim_start_matches('@');
+    let saved = if username.is_empty() {
+        Instance::get_local()?.set_contact(&conn, None)
+    } else {
+        User::find_by_fqn(&conn, username)
+            .and_then(|user| Instance::get_local()?.set_contact(&conn, Some(&user)))
+    };
+    Ok(match saved {
+        Ok(_) => Flash::success(
+            Redirect::to(uri!(admin_about)),
+            i18n!(intl.catalog, "The contact of the instance has been saved."),
+        ),
+        Err(_) => Flash::error(
+            Redirect::to(uri!(admin_about)),
+            i18n!(
+                intl.catalog,
+                "The contact has to be a user of this instance."
+            ),
+        ),
+    })
+}
+
+#[post("/admin/about/rules", data = "<form>")]
+pub fn add_rule(
+    _admin: Can<permissions::ManageSettings>,
+    form: LenientForm<InstanceRuleForm>,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    Ok(rule_saved(
+        InstanceRule::add(&conn, &form.short_text, &form.long_text).map(|_| ()),
+        &intl,
+    ))
+}
+
+#[post("/admin/about/rules/<id>", data = "<form>")]
+pub fn update_rule(
+    _admin: Can<permissions::ManageSettings>,
+    id: i32,
+    form: LenientForm<InstanceRuleForm>,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let mut rule = InstanceRule::get(&conn, id)?;
+    Ok(rule_saved(
+        rule.update(&conn, &form.short_text, &form.long_text),
+        &intl,
+    ))
+}
+
+fn rule_saved(saved: Result<(), Error>, intl: &I18n) -> Flash<Redirect> {
+    match saved {
+        Ok(_) => Flash::success(
+            Redirect::to(uri!(admin_about)),
+            i18n!(intl.catalog, "The rule has been saved."),
+        ),
+        Err(_) => Flash::error(
+            Redirect::to(uri!(admin_about)),
+            i18n!(
+                intl.catalog,
+                "Rules can't be empty, or longer than 300 characters."
+            ),
+        ),
+    }
+}
+
+#[post("/admin/about/rules/<id>/up")]
+pub fn move_rule_up(
+    _admin: Can<permissions::ManageSettings>,
+    id: i32,
+    conn: DbConn,
+) -> Result<Redirect, ErrorPage> {
+    InstanceRule::get(&conn, id)?.move_up(&conn)?;
+    Ok(Redirect::to(uri!(admin_about)))
+}
+
+#[post("/admin/about/rules/<id>/delete")]
+pub fn delete_rule(
+    _admin: Can<permissions::ManageSettings>,
+    id: i32,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    InstanceRule::get(&conn, id)?.delete(&conn)?;
+    Ok(Flash::success(
+        Redirect::to(uri!(admin_about)),
+        i18n!(intl.catalog, "The rule has been deleted."),
+    ))
+}
+
+#[post("/admin/about/sections", data = "<form>")]
+pub fn add_about_section(
+    _admin: Can<permissions::ManageSettings>,
+    form: LenientForm<AboutSectionForm>,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    Ok(section_saved(
+        AboutSection::add(&conn, &form.title, &form.content).map(|_| ()),
+        &intl,
+    ))
+}
+
+#[post("/admin/about/sections/<id>", data = "<form>")]
+pub fn update_about_section(
+    _admin: Can<permissions::ManageSettings>,
+    id: i32,
+    form: LenientForm<AboutSectionForm>,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let mut section = AboutSection::get(&conn, id)?;
+    Ok(section_saved(
+        section.update(&conn, &form.title, &form.content),
+        &intl,
+    ))
+}
+
+fn section_saved(saved: Result<(), Error>, intl: &I18n) -> Flash<Redirect> {
+    match saved {
+        Ok(_) => Flash::success(
+            Redirect::to(uri!(admin_about)),
+            i18n!(intl.catalog, "The section has been saved."),
+        ),
+        Err(_) => Flash::error(
+            Redirect::to(uri!(admin_about)),
+            i18n!(intl.catalog, "Sections need a title."),
+        ),
+    }
+}
+
+#[post("/admin/about/sections/<id>/up")]
+pub fn move_about_section_up(
+    _admin: Can<permissions::ManageSettings>,
+    id: i32,
+    conn: DbConn,
+) -> Result<Redirect, ErrorPage> {
+    AboutSection::get(&conn, id)?.move_up(&conn)?;
+    Ok(Redirect::to(uri!(admin_about)))
+}
+
+#[post("/admin/about/sections/<id>/delete")]
+pub fn delete_about_section(
+    _admin: Can<permissions::ManageSettings>,
+    id: i32,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    AboutSection::get(&conn, id)?.delete(&conn)?;
+    Ok(Flash::success(
+        Redirect::to(uri!(admin_about)),
+        i18n!(intl.catalog, "The section has been deleted."),
+    ))
+}
+
+#[get("/admin/legal")]
+pub fn admin_legal(
+    _admin: Can<permissions::ManageSettings>,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Result<Ructe, ErrorPage> {
+    Ok(render!(instance::legal(
+        &(&conn, &rockets).to_context(),
+        LegalDocument::latest(&conn, DocumentKind::Terms)?,
+        LegalDocument::latest(&conn, DocumentKind::Privacy)?
+    )))
+}
+
+#[derive(FromForm)]
+pub struct LegalDocumentForm {
+    pub kind: String,
+    pub content: String,
+}
+
+#[post("/admin/legal", data = "<form>")]
+pub fn publish_legal_document(
+    _admin: Can<permissions::ManageSettings>,
+    form: LenientForm<LegalDocumentForm>,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let kind = DocumentKind::from_name(&form.kind).ok_or(Error::InvalidValue)?;
+    let document = LegalDocument::publish(&conn, kind, form.content.trim())?;
+    Ok(Flash::success(
+        Redirect::to(uri!(admin_legal)),
+        i18n!(intl.catalog, "Version {0} has been published."; document.version),
+    ))
+}
+
+#[get("/admin/announcements")]
+pub fn admin_announcements(
+    _admin: Can<permissions::ManageSettings>,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Result<Ructe, ErrorPage> {
+    Ok(render!(instance::announcements(
+        &(&conn, &rockets).to_context(),
+        Announcement::list(&conn)?
+    )))
+}
+
+#[derive(Default, FromForm)]
+pub struct AnnouncementForm {
+    pub content: String,
+    /// Like `2022-04-28T14:35`, in UTC
+    pub starts_at: String,
+    pub ends_at: String,
+}
+
+impl AnnouncementForm {
+    fn dates(&self) -> Result<(Option<NaiveDateTime>, Option<NaiveDateTime>), Error> {
+        let parse = |date: &str| match date.trim() {
+            "" => Ok(None),
+            date => NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M")
+                .map(Some)
+                .map_err(|_| Error::InvalidValue),
+        };
+        Ok((parse(&self.starts_at)?, parse(&self.ends_at)?))
+    }
+}
+
+#[post("/admin/announcements", data = "<form>")]
+pub fn add_announcement(
+    admin: Can<permissions::ManageSettings>,
+    form: LenientForm<AnnouncementForm>,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    Ok(announcement_saved(
+        form.dates().and_then(|(starts_at, ends_at)| {
+            Announcement::create(&conn, &admin.0, &form.content, starts_at, ends_at).map(|_| ())
+        }),
+        &intl,
+    ))
+}
+
+#[post("/admin/announcements/<id>", data = "<form>")]
+pub fn update_announcement(
+    _admin: Can<permissions::ManageSettings>,
+    id: i32,
+    form: LenientForm<AnnouncementForm>,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let mut announcement = Announcement::get(&conn, id)?;
+    Ok(announcement_saved(
+        form.dates().and_then(|(starts_at, ends_at)| {
+            announcement.update(&conn, &form.content, starts_at, ends_at)
+        }),
+        &intl,
+    ))
+}
+
+fn announcement_saved(saved: Result<(), Error>, intl: &I18n) -> Flash<Redirect> {
+    match saved {
+        Ok(_) => Flash::success(
+            Redirect::to(uri!(admin_announcements)),
+            i18n!(intl.catalog, "The announcement has been saved."),
+        ),
+        Err(_) => Flash::error(
+            Redirect::to(uri!(admin_announcements)),
+            i18n!(
+                intl.catalog,
+                "Announcements can't be empty, and have to end after they start."
+            ),
+        ),
+    }
+}
+
+#[post("/admin/announcements/<id>/delete")]
+pub fn delete_announcement(
+    _admin: Can<permissions::ManageSettings>,
+    id: i32,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    Announcement::get(&conn, id)?.delete(&conn)?;
+    Ok(Flash::success(
+        Redirect::to(uri!(admin_announcements)),
+        i18n!(intl.catalog, "The announcement has been deleted."),
+    ))
+}
+
+#[post("/announcements/<id>/dismiss")]
+pub fn dismiss_announcement(id: i32, user: User, conn: DbConn) -> Result<Redirect, ErrorPage> {
+    Announcement::get(&conn, id)?.dismiss(&conn, &user)?;
+    Ok(Redirect::to(uri!(index)))
+}
+
+#[derive(Default, FromForm)]
+pub struct ReactionForm {
+    pub emoji: String,
+}
+
+/// Reacts to an announcement with an emoji, or takes the reaction back.
+#[post("/announcements/<id>/react", data = "<form>")]
+pub fn react_to_announcement(
+    id: i32,
+    form: LenientForm<ReactionForm>,
+    user: User,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<RespondOrRedirect, ErrorPage> {
+    let announcement = Announcement::get(&conn, id)?;
+    Ok(
+        match announcement.toggle_reaction(&conn, &user, &form.emoji) {
+            Ok(_) => Redirect::to(uri!(index)).into(),
+            Err(Error::NotFound) => return Err(Error::NotFound.into()),
+            Err(_) => Flash::error(
+                Redirect::to(uri!(index)),
+                i18n!(
+                    intl.catalog,
+                    "Reactions have to be a single emoji, and only a few different ones can be used."
+                ),
+            )
+            .into(),
+        },
+    )
+}
+
 /// A structure to handle forms that are a list of items on which actions are applied.
 ///
 /// This is for instance the case of the user list in the administration.
@@ -310,6 +1051,8 @@ pub enum UserActions {
     RevokeAdmin,
     Moderator,
     RevokeModerator,
+    Silence,
+    Unsilence,
     Ban,
 }
 
@@ -322,6 +1065,8 @@ impl FromStr for UserActions {
             "un-admin" => Ok(UserActions::RevokeAdmin),
             "moderator" => Ok(UserActions::Moderator),
             "un-moderator" => Ok(UserActions::RevokeModerator),
+            "silence" => Ok(UserActions::Silence),
+            "unsilence" => Ok(UserActions::Unsilence),
             "ban" => Ok(UserActions::Ban),
             _ => Err(()),
         }
@@ -343,20 +1088,22 @@ pub fn edit_users(
         ));
     }
 
-    // moderators can't grant or revoke admin rights
-    if !moderator.0.is_admin() {
-        match form.action {
-            UserActions::Admin | UserActions::RevokeAdmin => {
-                return Ok(Flash::error(
-                    Redirect::to(uri!(admin_users: page = _)),
-                    i18n!(
-                        rockets.intl.catalog,
-                        "You are not allowed to take this action."
-                    ),
-                ))
-            }
-            _ => {}
-        }
+    let permission = match form.action {
+        UserActions::Admin
+        | UserActions::RevokeAdmin
+        | UserActions::Moderator
+        | UserActions::RevokeModerator => Permission::ManageRoles,
+        UserActions::Silence | UserActions::Unsilence => Permission::SilenceAccounts,
+        UserActions::Ban => Permission::DeleteUsers,
+    };
+    if !moderator.0.has_permission(permission) {
+        return Ok(Flash::error(
+            Redirect::to(uri!(admin_users: page = _)),
+            i18n!(
+                rockets.intl.catalog,
+                "You are not allowed to take this action."
+            ),
+        ));
     }
 
     let worker = &*rockets.worker;
@@ -376,6 +1123,12 @@ pub fn edit_users(
                 User::get(&conn, u)?.set_role(&conn, Role::Normal)?;
             }
         }
+        UserActions::Silence | UserActions::Unsilence => {
+            let silenced = matches!(form.action, UserActions::Silence);
+            for u in form.ids.clone() {
+                User::get(&conn, u)?.set_silenced(&conn, silenced)?;
+            }
+        }
         UserActions::Ban => {
             for u in form.ids.clone() {
                 ban(u, &conn, worker)?;
@@ -389,7 +1142,7 @@ pub fn edit_users(
     ))
 }
 
-fn ban(id: i32, conn: &Connection, worker: &ScheduledThreadPool) -> Result<(), ErrorPage> {
+fn ban(id: i32, conn: &Connection, worker: &Worker) -> Result<(), ErrorPage> {
     let u = User::get(conn, id)?;
     u.delete(conn)?;
     if Instance::get_local()
@@ -416,39 +1169,57 @@ fn ban(id: i32, conn: &Connection, worker: &ScheduledThreadPool) -> Result<(), E
 
 #[post("/inbox", data = "<data>")]
 pub fn shared_inbox(
+    _limit: RateLimit<Inbox>,
     conn: DbConn,
     data: inbox::SignedJson<serde_json::Value>,
     headers: Headers<'_>,
-) -> Result<String, status::BadRequest<&'static str>> {
-    inbox::handle_incoming(conn, data, headers)
+    pool: State<'_, DbPool>,
+    worker: State<'_, Arc<Worker>>,
+    signatures: State<'_, Arc<SignaturePool>>,
+) -> Result<String, status::Custom<&'static str>> {
+    inbox::handle_incoming(conn, data, headers, pool, worker, signatures)
 }
 
 #[get("/remote_interact?<target>")]
 pub fn interact(conn: DbConn, user: Option<User>, target: String) -> Option<Redirect> {
-    if User::find_by_fqn(&conn, &target).is_ok() {
-        return Some(Redirect::to(uri!(super::user::details: name = target)));
-    }
+    open_remote(&conn, user.as_ref(), &target)
+}
 
-    if let Ok(post) = Post::from_id(&conn, &target, None, CONFIG.proxy()) {
-        return Some(Redirect::to(uri!(
-            super::posts::details: blog = post.get_blog(&conn).expect("Can't retrieve blog").fqn,
-            slug = &post.slug,
-            responding_to = _
-        )));
+/// Where other instances send the users of this one to interact with something they
+/// found there: it is shown here, so that they can follow, like or boost it.
+///
+/// This is the same endpoint as Mastodon, so that they can find it without WebFinger.
+#[get("/authorize_interaction?<uri>")]
+pub fn authorize_interaction(
+    conn: DbConn,
+    user: Option<User>,
+    uri: String,
+    i18n: I18n,
+) -> Option<RespondOrRedirect> {
+    match user {
+        Some(user) => open_remote(&conn, Some(&user), &uri).map(Into::into),
+        None => Some(
+            requires_login(
+                &i18n!(
+                    i18n.catalog,
+                    "To interact with this, you need to be logged in"
+                ),
+                uri!(authorize_interaction: uri = uri.as_str()),
+            )
+            .into(),
+        ),
     }
+}
 
-    if let Ok(comment) = Comment::from_id(&conn, &target, None, CONFIG.proxy()) {
-        if comment.can_see(&conn, user.as_ref()) {
-            let post = comment.get_post(&conn).expect("Can't retrieve post");
-            return Some(Redirect::to(uri!(
-                super::posts::details: blog =
-                    post.get_blog(&conn).expect("Can't retrieve blog").fqn,
-                slug = &post.slug,
-                responding_to = comment.id
-            )));
-        }
+fn open_remote(conn: &DbConn, user: Option<&User>, target: &str) -> Option<Redirect> {
+    let target = target.trim_start_matches("acct:");
+    if User::find_by_fqn(conn, target).is_ok() {
+        return Some(Redirect::to(uri!(super::user::details: name = target)));
     }
-    None
+
+    lookup::resolve(conn, target)
+        .ok()
+        .and_then(|resolved| super::search::redirect_to(conn, user, resolved))
 }
 
 #[get("/nodeinfo/<version>")]
@@ -479,7 +1250,15 @@ pub fn nodeinfo(conn: DbConn, version: String) -> Result<Json<serde_json::Value>
         },
         "metadata": {
             "nodeName": local_inst.name,
-            "nodeDescription": local_inst.short_description
+            "nodeDescription": local_inst.short_description,
+            "rules": InstanceRule::list(&conn)?
+                .into_iter()
+                .map(|rule| json!({ "id": rule.id, "text": rule.short_text, "hint": rule.long_text }))
+                .collect::<Vec<_>>(),
+            "contact": local_inst.contact(&conn).ok().map(|user| json!({
+                "account": user.fqn,
+                "url": user.ap_url
+            }))
         }
     });
 
@@ -492,19 +1271,85 @@ pub fn nodeinfo(conn: DbConn, version: String) -> Result<Json<serde_json::Value>
 
 #[get("/about")]
 pub fn about(conn: DbConn, rockets: PlumeRocket) -> Result<Ructe, ErrorPage> {
+    let instance = Instance::get_local()?;
+    let contact = instance.contact(&conn)?;
     Ok(render!(instance::about(
         &(&conn, &rockets).to_context(),
-        Instance::get_local()?,
-        Instance::get_local()?.main_admin(&conn)?,
+        instance,
+        contact,
         User::count_local(&conn)?,
         Post::count_local(&conn)?,
-        Instance::count(&conn)? - 1
+        Instance::count(&conn)? - 1,
+        InstanceRule::list(&conn)?,
+        AboutSection::list(&conn)?
     )))
 }
 
 #[get("/privacy")]
-pub fn privacy(conn: DbConn, rockets: PlumeRocket) -> Ructe {
-    render!(instance::privacy(&(&conn, &rockets).to_context()))
+pub fn privacy(conn: DbConn, rockets: PlumeRocket) -> Result<Ructe, ErrorPage> {
+    Ok(render!(instance::privacy(
+        &(&conn, &rockets).to_context(),
+        LegalDocument::latest(&conn, DocumentKind::Privacy)?
+    )))
+}
+
+#[get("/terms")]
+pub fn terms(conn: DbConn, rockets: PlumeRocket) -> Result<Ructe, ErrorPage> {
+    Ok(render!(instance::terms(
+        &(&conn, &rockets).to_context(),
+        LegalDocument::latest(&conn, DocumentKind::Terms)?.ok_or(Error::NotFound)?
+    )))
+}
+
+/// Asks users to accept the new versions of the terms, before going to `next`.
+#[get("/terms/accept?<next>")]
+pub fn accept_terms_form(
+    user: User,
+    next: Option<String>,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Result<RespondOrRedirect, ErrorPage> {
+    let next = local_uri(next);
+    let pending = LegalDocument::pending_for(&conn, &user)?;
+    if pending.is_empty() {
+        return Ok(Redirect::to(next).into());
+    }
+    Ok(render!(instance::accept_terms(
+        &(&conn, &rockets).to_context(),
+        pending,
+        &next.to_string()
+    ))
+    .into())
+}
+
+/// Where `TermsAcceptance` sends the requests of users who have new terms to accept.
+#[get("/terms/pending")]
+pub fn pending_terms(from: PendingFrom) -> Redirect {
+    Redirect::to(uri!(accept_terms_form: next = Some(from.0)))
+}
+
+#[derive(FromForm)]
+pub struct AcceptTermsForm {
+    pub next: String,
+}
+
+#[post("/terms/accept", data = "<form>")]
+pub fn accept_terms(
+    user: User,
+    form: LenientForm<AcceptTermsForm>,
+    conn: DbConn,
+) -> Result<Redirect, ErrorPage> {
+    for document in LegalDocument::pending_for(&conn, &user)? {
+        document.accept(&conn, &user)?;
+    }
+    Ok(Redirect::to(local_uri(Some(form.next.clone()))))
+}
+
+/// Only the pages of this instance are valid destinations after accepting the terms.
+fn local_uri(next: Option<String>) -> Uri<'static> {
+    next.filter(|next| next.starts_with('/') && !next.starts_with("//"))
+        .and_then(|next| Uri::parse(&next).map(IntoOwned::into_owned).ok())
+        .unwrap_or_else(|| Uri::parse("/").map(IntoOwned::into_owned).unwrap())
 }
 
 #[get("/manifest.json")]
@@ -524,3 +1369,45 @@ pub fn web_manifest() -> Result<Json<serde_json::Value>, ErrorPage> {
             .collect::<Vec<_>>()
     })))
 }
+
+/// Asks search engines not to crawl the profiles and articles of the users who don't want
+/// them to be indexed.
+#[get("/robots.txt")]
+pub fn robots_txt(conn: DbConn) -> Result<Content<String>, ErrorPage> {
+    let mut rules = String::from("User-agent: *\n");
+    for user in User::list_local_not_indexable(&conn)? {
+        rules.push_str(&format!("Disallow: /@/{}/\n", user.fqn));
+    }
+    for post in Post::list_local_not_indexable(&conn)? {
+        rules.push_str(&format!("Disallow: {}\n", post.url(&conn)?));
+    }
+    Ok(Content(ContentType::Plain, rules))
+}
+
+/// Lets browsers and other tools add the search of this instance to their search engines.
+#[get("/opensearch.xml")]
+pub fn opensearch() -> Result<Content<String>, ErrorPage> {
+    let instance = Instance::get_local()?;
+    // The short name can't be longer than 16 characters
+    let short_name = instance.name.chars().take(16).collect::<String>();
+    Ok(Content(
+        ContentType::new("application", "opensearchdescription+xml"),
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<OpenSearchDescription xmlns="http://a9.com/-/spec/opensearch/1.1/">
+    <ShortName>{short_name}</ShortName>
+    <Description>{name}</Description>
+    <InputEncoding>UTF-8</InputEncoding>
+    <Image width="16" height="16" type="image/png">https://{domain}{favicon}</Image>
+    <Url type="text/html" method="get" template="https://{domain}/search?q={{searchTerms}}"/>
+    <Url type="application/json" method="get" template="https://{domain}/api/v1/search?q={{searchTerms}}"/>
+    <Url type="application/opensearchdescription+xml" rel="self" template="https://{domain}/opensearch.xml"/>
+</OpenSearchDescription>
+"#,
+            short_name = escape(&short_name),
+            name = escape(&instance.name),
+            domain = CONFIG.base_url,
+            favicon = escape(&uri!(static_files: file = CONFIG.logo.favicon.as_str()).to_string()),
+        ),
+    ))
+}
@@ -1,18 +1,36 @@
-use crate::routes::{errors::ErrorPage, Page};
+use crate::routes::{errors::ErrorPage, CachedFile, FileKind, Page};
 use crate::template_utils::{IntoContext, Ructe};
 use guid_create::GUID;
 use multipart::server::{
-    save::{SaveResult, SavedField, SavedData},
+    save::{PartialReason, SaveResult, SavedField, SavedData},
     Multipart,
 };
-use plume_models::{db_conn::DbConn, medias::*, users::User, Error, PlumeRocket, CONFIG};
+use plume_models::{
+    audio,
+    db_conn::DbConn,
+    image_cleanup, media_dedup,
+    media_policies::MediaAction,
+    media_proxy,
+    media_scan::{self, ScanStatus},
+    media_variants,
+    medias::*,
+    rate_limits::{MediaUpload, RateLimit},
+    request_limits::{error_status, LimitedData, Upload},
+    users::User,
+    worker::Worker,
+    Error, PlumeRocket, CONFIG,
+};
 use rocket::{
-    http::ContentType,
-    response::{status, Flash, Redirect},
-    Data,
+    http::{
+        hyper::header::{CacheControl, CacheDirective},
+        ContentType, Status,
+    },
+    response::{status, Flash, NamedFile, Redirect},
+    State,
 };
 use rocket_i18n::I18n;
-use std::fs;
+use std::{borrow::Cow, fs, sync::Arc};
+use tracing::warn;
 
 #[get("/medias?<page>")]
 pub fn list(
@@ -38,11 +56,13 @@ pub fn new(_user: User, conn: DbConn, rockets: PlumeRocket) -> Ructe {
 
 #[post("/medias/new", data = "<data>")]
 pub fn upload(
+    _limit: RateLimit<MediaUpload>,
     user: User,
-    data: Data,
+    data: LimitedData<Upload>,
     ct: &ContentType,
     conn: DbConn,
-) -> Result<Redirect, status::BadRequest<&'static str>> {
+    rockets: PlumeRocket,
+) -> Result<Redirect, status::Custom<&'static str>> {
     if !ct.is_form_data() {
         return Ok(Redirect::to(uri!(new)));
     }
@@ -50,49 +70,112 @@ pub fn upload(
     let (_, boundary) = ct
         .params()
         .find(|&(k, _)| k == "boundary")
-        .ok_or(status::BadRequest(Some("No boundary")))?;
+        .ok_or(status::Custom(Status::BadRequest, "No boundary"))?;
 
-    if let SaveResult::Full(entries) = Multipart::with_body(data.open(), boundary).save().temp() {
+    let saved = Multipart::with_body(data.open(), boundary).save().temp();
+    if let SaveResult::Full(entries) = saved {
         let fields = entries.fields;
 
         let file = fields
             .get("file")
             .and_then(|v| v.iter().next())
-            .ok_or(status::BadRequest(Some("No file uploaded")))?;
+            .ok_or(status::Custom(Status::BadRequest, "No file uploaded"))?;
 
-        let file_path = match save_uploaded_file(file) {
-            Ok(Some(file_path)) => file_path,
+        let (bytes, ext) = match read_uploaded_file(file) {
+            Ok(Some(upload)) => upload,
             Ok(None) => return Ok(Redirect::to(uri!(new))),
-            Err(_) => return Err(status::BadRequest(Some("Couldn't save uploaded media: {}"))),
+            Err(_) => {
+                return Err(status::Custom(
+                    Status::BadRequest,
+                    "Couldn't save uploaded media: {}",
+                ))
+            }
         };
+        let alt_text = read(&fields["alt"][0].data)?;
+        let content_warning = Some(read(&fields["cw"][0].data)?).filter(|cw| !cw.is_empty());
+        let hash = media_dedup::content_hash(&bytes);
+        let duplicate = media_dedup::find_duplicate(&conn, &hash, user.id)
+            .map_err(|_| status::Custom(Status::BadRequest, "Error while saving media"))?;
+        // The same file was already uploaded by this user, with the same description: a new
+        // description gets a new media, using the same file
+        if let Some(ref duplicate) = duplicate {
+            if duplicate.owner_id == user.id
+                && duplicate.alt_text == alt_text
+                && duplicate.content_warning == content_warning
+            {
+                return Ok(Redirect::to(uri!(details: id = duplicate.id)));
+            }
+        }
+
+        // New files are only published once the antivirus checked them
+        let scanned = duplicate.is_none() && media_scan::enabled();
+        let file_path = match duplicate {
+            Some(ref duplicate) => Ok(duplicate.file_path.clone()),
+            None if scanned => media_scan::store_pending(&bytes, &ext),
+            None => save_uploaded_file(file, &bytes, &ext),
+        }
+        .map_err(|_| status::Custom(Status::BadRequest, "Couldn't save uploaded media: {}"))?;
 
-        let has_cw = !read(&fields["cw"][0].data)
-            .map(|cw| cw.is_empty())
-            .unwrap_or(false);
         let media = Media::insert(
             &conn,
             NewMedia {
                 file_path,
-                alt_text: read(&fields["alt"][0].data)?,
+                alt_text,
                 is_remote: false,
                 remote_url: None,
-                sensitive: has_cw,
-                content_warning: if has_cw {
-                    Some(read(&fields["cw"][0].data)?)
-                } else {
-                    None
-                },
+                sensitive: content_warning.is_some(),
+                content_warning,
                 owner_id: user.id,
             },
         )
-        .map_err(|_| status::BadRequest(Some("Error while saving media")))?;
-        Ok(Redirect::to(uri!(details: id = media.id)))
+        .and_then(|media| match duplicate {
+            // The file is reused, with its variants
+            Some(ref duplicate) => media_dedup::share(&conn, duplicate, &media),
+            None => media_dedup::set_content_hash(&conn, &media, &hash),
+        })
+        .and_then(|media| audio::save_metadata(&conn, &media, &bytes))
+        .and_then(|media| {
+            if scanned {
+                media_scan::set_pending(&conn, &media)
+            } else {
+                Ok(media)
+            }
+        })
+        .map_err(|_| status::Custom(Status::BadRequest, "Error while saving media"))?;
+        let id = media.id;
+        if duplicate.is_none() {
+            rockets.worker.execute(move || {
+                let media = match media_scan::check(&conn, &media) {
+                    Ok(media) if media.scan_status() == ScanStatus::Clean => media,
+                    Ok(_) => return,
+                    Err(err) => {
+                        warn!("Couldn't scan media {}: {:?}", media.id, err);
+                        return;
+                    }
+                };
+                if let Err(err) = media_variants::generate(&conn, &media) {
+                    warn!("Couldn't generate the variants of media {}: {:?}", media.id, err);
+                }
+            });
+        }
+        Ok(Redirect::to(uri!(details: id = id)))
     } else {
-        Ok(Redirect::to(uri!(new)))
+        match saved {
+            // The body was cut off, because it was too large or too slow to be received
+            SaveResult::Partial(_, PartialReason::IoError(ref err))
+            | SaveResult::Error(ref err) => Err(status::Custom(
+                error_status(err),
+                "The file couldn't be received",
+            )),
+            _ => Ok(Redirect::to(uri!(new))),
+        }
     }
 }
 
-fn save_uploaded_file(file: &SavedField) -> Result<Option<String>, plume_models::Error> {
+/// The content of an uploaded file, without its metadata, and its extension.
+fn read_uploaded_file(
+    file: &SavedField,
+) -> Result<Option<(Vec<u8>, String)>, plume_models::Error> {
     // Remove extension if it contains something else than just letters and numbers
     let ext = file
         .headers
@@ -111,60 +194,58 @@ fn save_uploaded_file(file: &SavedField) -> Result<Option<String>, plume_models:
         })
         .unwrap_or_default();
 
+    let bytes = match file.data {
+        SavedData::Bytes(ref bytes) => Cow::from(bytes),
+        SavedData::File(ref path, _) => Cow::from(fs::read(path)?),
+        _ => {
+            return Ok(None);
+        }
+    };
+    // Removes the metadata of images, and makes the largest ones smaller
+    let bytes = image_cleanup::clean(&bytes, &ext)?.into_owned();
+    Ok(Some((bytes, ext)))
+}
+
+fn save_uploaded_file(
+    #[cfg_attr(not(feature = "s3"), allow(unused_variables))] file: &SavedField,
+    bytes: &[u8],
+    ext: &str,
+) -> Result<String, plume_models::Error> {
     if CONFIG.s3.is_some() {
         #[cfg(not(feature="s3"))]
         unreachable!();
 
         #[cfg(feature="s3")]
         {
-            use std::borrow::Cow;
-
             let dest = format!("static/media/{}.{}", GUID::rand(), ext);
 
-            let bytes = match file.data {
-                SavedData::Bytes(ref bytes) => Cow::from(bytes),
-                SavedData::File(ref path, _) => Cow::from(fs::read(path)?),
-                _ => {
-                    return Ok(None);
-                }
-            };
-
             let bucket = CONFIG.s3.as_ref().unwrap().get_bucket();
             let content_type = match &file.headers.content_type {
                 Some(ct) => ct.to_string(),
-                None => ContentType::from_extension(&ext)
+                None => ContentType::from_extension(ext)
                     .unwrap_or(ContentType::Binary)
                     .to_string(),
             };
 
-            bucket.put_object_with_content_type_blocking(&dest, &bytes, &content_type)?;
+            bucket.put_object_with_content_type_blocking(&dest, bytes, &content_type)?;
 
-            Ok(Some(dest))
+            Ok(dest)
         }
     } else {
         let dest = format!("{}/{}.{}", CONFIG.media_directory, GUID::rand(), ext);
-
-        match file.data {
-            SavedData::Bytes(ref bytes) => {
-                fs::write(&dest, bytes)?;
-            }
-            SavedData::File(ref path, _) => {
-                fs::copy(path, &dest)?;
-            }
-            _ => {
-                return Ok(None);
-            }
-        }
-
-        Ok(Some(dest))
+        fs::write(&dest, bytes)?;
+        Ok(dest)
     }
 }
 
-fn read(data: &SavedData) -> Result<String, status::BadRequest<&'static str>> {
+fn read(data: &SavedData) -> Result<String, status::Custom<&'static str>> {
     if let SavedData::Text(s) = data {
         Ok(s.clone())
     } else {
-        Err(status::BadRequest(Some("Error while reading data")))
+        Err(status::Custom(
+            Status::BadRequest,
+            "Error while reading data",
+        ))
     }
 }
 
@@ -186,6 +267,32 @@ pub fn details(
     }
 }
 
+/// What the media proxy answers.
+#[derive(Responder)]
+pub enum Proxied {
+    File(CachedFile),
+    /// To the original file, until the proxy has a copy of it
+    Original(Redirect),
+}
+
+/// A remote avatar, icon, banner or cover, served by the media proxy.
+#[get("/medias/<id>/proxy")]
+pub fn proxy(id: i32, conn: DbConn, worker: State<'_, Arc<Worker>>) -> Option<Proxied> {
+    let media = Media::get(&conn, id).ok()?;
+    if media.policy(&conn) == Some(MediaAction::Reject) {
+        return None;
+    }
+    match media_proxy::get(&conn, &media, &worker).ok()? {
+        media_proxy::Proxied::File(path) => NamedFile::open(path).ok().map(|f| {
+            Proxied::File(CachedFile {
+                inner: FileKind::Local(f),
+                cache_control: CacheControl(vec![CacheDirective::MaxAge(60 * 60 * 24)]),
+            })
+        }),
+        media_proxy::Proxied::Fetching(url) => Some(Proxied::Original(Redirect::to(url))),
+    }
+}
+
 #[post("/medias/<id>/delete")]
 pub fn delete(id: i32, user: User, conn: DbConn, intl: I18n) -> Result<Flash<Redirect>, ErrorPage> {
     let media = Media::get(&conn, id)?;
@@ -224,3 +331,25 @@ pub fn set_avatar(
         ))
     }
 }
+
+#[post("/medias/<id>/banner")]
+pub fn set_banner(
+    id: i32,
+    user: User,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let media = Media::get(&conn, id)?;
+    if media.owner_id == user.id && media.category() == MediaCategory::Image {
+        user.set_banner(&conn, Some(media.id))?;
+        Ok(Flash::success(
+            Redirect::to(uri!(details: id = id)),
+            i18n!(intl.catalog, "Your banner has been updated."),
+        ))
+    } else {
+        Ok(Flash::error(
+            Redirect::to(uri!(details: id = id)),
+            i18n!(intl.catalog, "You are not allowed to use this media."),
+        ))
+    }
+}
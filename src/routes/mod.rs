@@ -1,10 +1,20 @@
 #![warn(clippy::too_many_arguments)]
 use crate::template_utils::Ructe;
 use atom_syndication::{
-    ContentBuilder, Entry, EntryBuilder, Feed, FeedBuilder, LinkBuilder, Person, PersonBuilder,
+    Category, CategoryBuilder, ContentBuilder, Entry, EntryBuilder, Feed, FeedBuilder, LinkBuilder,
+    Person, PersonBuilder, Text,
 };
 use chrono::{naive::NaiveDateTime, DateTime, Utc};
-use plume_models::{posts::Post, Connection, CONFIG, ITEMS_PER_PAGE};
+use gettext::Catalog;
+use plume_models::{
+    media_scan,
+    mentions::MentionFailure,
+    password_policy,
+    posts::Post,
+    profile_fields::{ProfileField, MAX_PROFILE_FIELDS},
+    series::Series,
+    Connection, CONFIG, ITEMS_PER_PAGE,
+};
 use rocket::{
     http::{
         hyper::header::{CacheControl, CacheDirective, ETag, EntityTag},
@@ -16,10 +26,12 @@ use rocket::{
     Outcome,
 };
 use std::{
-    collections::hash_map::DefaultHasher,
+    borrow::Cow,
+    collections::{hash_map::DefaultHasher, HashMap},
     hash::Hasher,
     path::{Path, PathBuf},
 };
+use validator::{ValidationError, ValidationErrors};
 
 #[cfg(feature = "s3")]
 use rocket::http::ContentType;
@@ -127,6 +139,70 @@ pub struct RemoteForm {
     pub remote: String,
 }
 
+/// The label and value of each profile field input of the settings forms, that have
+/// `MAX_PROFILE_FIELDS` pairs of inputs.
+pub fn profile_field_inputs(fields: Vec<ProfileField>) -> Vec<(String, String)> {
+    let mut inputs = fields
+        .into_iter()
+        .map(|field| (field.name, field.value))
+        .collect::<Vec<_>>();
+    inputs.resize(MAX_PROFILE_FIELDS, (String::new(), String::new()));
+    inputs
+}
+
+/// Checks a new password against the password policy, as errors of the `password` input.
+/// `inputs` are the other things the user typed, like their username.
+pub fn check_password(password: &str, inputs: &[&str]) -> Result<(), ValidationErrors> {
+    let problems = password_policy::problems(password, inputs);
+    if problems.is_empty() {
+        return Ok(());
+    }
+    let mut errors = ValidationErrors::new();
+    for problem in problems {
+        errors.add(
+            "password",
+            ValidationError {
+                code: Cow::from(problem.code()),
+                message: Some(Cow::from(problem.message())),
+                params: HashMap::new(),
+            },
+        );
+    }
+    Err(errors)
+}
+
+/// Tells the author that their text was saved with `message`, and which of its mentions
+/// couldn't be linked to an account, if some couldn't.
+pub fn flash_with_mentions(
+    cat: &Catalog,
+    redirect: Redirect,
+    message: String,
+    failures: &[(String, MentionFailure)],
+) -> Flash<Redirect> {
+    if failures.is_empty() {
+        return Flash::success(redirect, message);
+    }
+    let failures = failures
+        .iter()
+        .map(|(name, failure)| match failure {
+            MentionFailure::NotFound => i18n!(cat, "@{0} (no such account)"; name),
+            MentionFailure::Unreachable => {
+                i18n!(cat, "@{0} (its instance couldn't be reached)"; name)
+            }
+            MentionFailure::InvalidActor => i18n!(cat, "@{0} (not an account)"; name),
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    Flash::warning(
+        redirect,
+        format!(
+            "{} {}",
+            message,
+            i18n!(cat, "These mentions couldn't be linked to an account: {0}."; failures)
+        ),
+    )
+}
+
 pub fn build_atom_feed(
     entries: Vec<Post>,
     uri: &str,
@@ -179,21 +255,58 @@ fn post_to_atom(post: Post, conn: &Connection) -> Entry {
                 })
                 .collect::<Vec<Person>>(),
         )
+        // The series of the article, if any
+        .categories(
+            Series::for_post(conn, post.id)
+                .map(|(series, _)| {
+                    CategoryBuilder::default()
+                        .term(series.slug.clone())
+                        .scheme(series.ap_url(conn).ok())
+                        .label(Some(series.title))
+                        .build()
+                })
+                .into_iter()
+                .collect::<Vec<Category>>(),
+        )
         // Using RFC 4287 format, see https://tools.ietf.org/html/rfc4287#section-3.3 for dates
         // eg: 2003-12-13T18:30:02Z (Z is here because there is no timezone support with the NaiveDateTime crate)
         .published(Some(
             DateTime::<Utc>::from_utc(post.creation_date, Utc).into(),
         ))
         .updated(DateTime::<Utc>::from_utc(post.creation_date, Utc))
+        .summary(
+            Some(post.summary())
+                .filter(|summary| !summary.is_empty())
+                .map(Text::plain),
+        )
         .id(post.ap_url.clone())
-        .links(vec![LinkBuilder::default().href(post.ap_url).build()])
+        .links(
+            // The cover, if it doesn't have to be blurred
+            post.displayed_cover(conn)
+                .filter(|(_, blurred)| !blurred)
+                .map(|(cover, _)| {
+                    LinkBuilder::default()
+                        .href(cover)
+                        .rel("enclosure".to_string())
+                        .build()
+                })
+                .into_iter()
+                .chain(vec![LinkBuilder::default().href(post.ap_url).build()])
+                .collect::<Vec<_>>(),
+        )
         .build()
 }
 
 pub mod blogs;
+pub mod categories;
 pub mod comments;
+pub mod connectors;
+pub mod directory;
 pub mod email_signups;
 pub mod errors;
+pub mod guest_comments;
+pub mod held_comments;
+pub mod imports;
 pub mod instance;
 pub mod likes;
 pub mod medias;
@@ -201,9 +314,11 @@ pub mod notifications;
 pub mod posts;
 pub mod reshares;
 pub mod search;
+pub mod series;
 pub mod session;
 pub mod tags;
 pub mod timelines;
+pub mod translations;
 pub mod user;
 pub mod well_known;
 
@@ -256,13 +371,32 @@ pub fn theme_files(file: PathBuf, _build_id: &RawStr) -> Option<ThemeFile> {
         .map(ThemeFile)
 }
 
+/// Uploaded files can't run scripts or load anything when they are opened, even if an
+/// SVG image or an HTML file got through.
+const MEDIA_CSP: &str = "default-src 'none'; img-src data:; style-src 'unsafe-inline'; sandbox";
+
+pub struct MediaFile(CachedFile);
+
+impl<'r> Responder<'r> for MediaFile {
+    fn respond_to(self, r: &Request<'_>) -> response::Result<'r> {
+        Response::build_from(self.0.respond_to(r)?)
+            .raw_header("Content-Security-Policy", MEDIA_CSP)
+            .raw_header("X-Content-Type-Options", "nosniff")
+            .ok()
+    }
+}
+
 #[allow(unused_variables)]
 #[get("/static/cached/<build_id>/<file..>", rank = 2)]
 pub fn plume_static_files(file: PathBuf, build_id: &RawStr) -> Option<CachedFile> {
     static_files(file)
 }
 #[get("/static/media/<file..>")]
-pub fn plume_media_files(file: PathBuf) -> Option<CachedFile> {
+pub fn plume_media_files(file: PathBuf) -> Option<MediaFile> {
+    // Files that were not scanned yet, or that are infected
+    if file.starts_with(media_scan::PENDING_DIRECTORY) {
+        return None;
+    }
     if CONFIG.s3.is_some() {
         #[cfg(not(feature="s3"))]
         unreachable!();
@@ -278,17 +412,19 @@ pub fn plume_media_files(file: PathBuf) -> Option<CachedFile> {
                     .and_then(|ext| ContentType::from_extension(&ext.to_string_lossy())))
                 .unwrap_or(ContentType::Binary);
 
-            Some(CachedFile {
+            Some(MediaFile(CachedFile {
                 inner: FileKind::S3(data.to_vec(), ct),
                 cache_control: CacheControl(vec![CacheDirective::MaxAge(60 * 60 * 24 * 30)]),
-            })
+            }))
         }
     } else {
         NamedFile::open(Path::new(&CONFIG.media_directory).join(file))
             .ok()
-            .map(|f| CachedFile {
-                inner: FileKind::Local(f),
-                cache_control: CacheControl(vec![CacheDirective::MaxAge(60 * 60 * 24 * 30)]),
+            .map(|f| {
+                MediaFile(CachedFile {
+                    inner: FileKind::Local(f),
+                    cache_control: CacheControl(vec![CacheDirective::MaxAge(60 * 60 * 24 * 30)]),
+                })
             })
     }
 }
@@ -1,34 +1,76 @@
-use rocket::response::{Flash, Redirect};
+use rocket::http::ContentType;
+use rocket::response::{Content, Flash, Redirect};
 use rocket_i18n::I18n;
 
 use crate::routes::{errors::ErrorPage, Page};
 use crate::template_utils::{IntoContext, Ructe};
 use crate::utils::requires_login;
-use plume_models::{db_conn::DbConn, notifications::Notification, users::User, PlumeRocket};
+use plume_models::{
+    db_conn::DbConn,
+    notifications::{Notification, NotificationType},
+    severed_relationships::Severance,
+    users::User,
+    Error, PlumeRocket,
+};
 
-#[get("/notifications?<page>")]
+/// The notifications of the user, only of one type if `filter` is the name of a type.
+#[get("/notifications?<page>&<filter>")]
 pub fn notifications(
     user: User,
     page: Option<Page>,
+    filter: Option<String>,
     conn: DbConn,
     rockets: PlumeRocket,
 ) -> Result<Ructe, ErrorPage> {
     let page = page.unwrap_or_default();
+    let filter = filter.as_deref().and_then(NotificationType::from_name);
+    let types = filter.into_iter().collect::<Vec<_>>();
     Ok(render!(notifications::index(
         &(&conn, &rockets).to_context(),
-        Notification::page_for_user(&conn, &user, page.limits())?,
+        Notification::page_for_user(&conn, &user, &types, page.limits())?,
+        filter,
+        Notification::count_unread(&conn, &user)?,
         page.0,
-        Page::total(Notification::count_for_user(&conn, &user)? as i32)
+        Page::total(Notification::count_for_user(&conn, &user, &types)? as i32)
     )))
 }
 
-#[get("/notifications?<page>", rank = 2)]
-pub fn notifications_auth(i18n: I18n, page: Option<Page>) -> Flash<Redirect> {
+#[get("/notifications?<page>&<filter>", rank = 2)]
+pub fn notifications_auth(
+    i18n: I18n,
+    page: Option<Page>,
+    filter: Option<String>,
+) -> Flash<Redirect> {
     requires_login(
         &i18n!(
             i18n.catalog,
             "To see your notifications, you need to be logged in"
         ),
-        uri!(notifications: page = page),
+        uri!(notifications: page = page, filter = filter),
     )
 }
+
+#[post("/notifications/read")]
+pub fn mark_all_read(user: User, conn: DbConn) -> Result<Redirect, ErrorPage> {
+    Notification::mark_read(&conn, &user, None)?;
+    Ok(Redirect::to(uri!(notifications: page = _, filter = _)))
+}
+
+/// The accounts a user lost because of a block between instances, as a CSV file.
+///
+/// `list` is `following.csv` or `followers.csv`.
+#[get("/severed-relationships/<id>/<list>")]
+pub fn severed_relationships(
+    user: User,
+    id: i32,
+    list: String,
+    conn: DbConn,
+) -> Result<Content<String>, ErrorPage> {
+    let following = match list.as_str() {
+        "following.csv" => true,
+        "followers.csv" => false,
+        _ => return Err(Error::NotFound.into()),
+    };
+    let csv = Severance::get(&conn, id)?.to_csv(&conn, &user, following)?;
+    Ok(Content(ContentType::CSV, csv))
+}
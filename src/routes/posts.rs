@@ -8,6 +8,7 @@ use std::{
     collections::{HashMap, HashSet},
     time::Duration,
 };
+use tracing::warn;
 use validator::{Validate, ValidationError, ValidationErrors};
 
 use crate::routes::{
@@ -19,19 +20,30 @@ use plume_common::activity_pub::{broadcast, ActivityStream, ApRequest, LicensedA
 use plume_common::utils::md_to_html;
 use plume_models::{
     blogs::*,
+    cache::{self, Entry},
+    categories::Category,
     comments::{Comment, CommentTree},
+    crossposts::{Crosspost, CrosspostOptOut},
     db_conn::DbConn,
+    groups,
     inbox::inbox,
     instance::Instance,
+    maintenance::Maintenance,
     medias::Media,
     mentions::Mention,
+    post_attachments::PostAttachment,
     post_authors::*,
+    post_reviews::PostReview,
+    post_translations::PostTranslation,
+    post_views::{PostView, Visitor},
     posts::*,
+    quotes::Quote,
     safe_string::SafeString,
+    series::Series,
     tags::*,
     timeline::*,
     users::User,
-    Error, PlumeRocket, CONFIG,
+    Connection, Error, PlumeRocket, CONFIG,
 };
 
 #[get("/~/<blog>/<slug>?<responding_to>", rank = 4)]
@@ -39,29 +51,45 @@ pub fn details(
     blog: String,
     slug: String,
     responding_to: Option<i32>,
+    visitor: Visitor,
     conn: DbConn,
     rockets: PlumeRocket,
 ) -> Result<Ructe, ErrorPage> {
     let user = rockets.user.clone();
     let blog = Blog::find_by_fqn(&conn, &blog)?;
     let post = Post::find_by_slug(&conn, &slug, blog.id)?;
-    if !(post.published
-        || post
-            .get_authors(&conn)?
-            .into_iter()
-            .any(|a| a.id == user.clone().map(|u| u.id).unwrap_or(0)))
-    {
+    let is_author = post
+        .get_authors(&conn)?
+        .into_iter()
+        .any(|a| a.id == user.clone().map(|u| u.id).unwrap_or(0));
+    // Editors can read the drafts they have to review
+    let is_reviewer = !post.published
+        && PostReview::is_pending(&conn, post.id)?
+        && user
+            .as_ref()
+            .map_or(Ok(false), |u| u.can_publish_in(&conn, &blog))?;
+    if !(post.published || is_author || is_reviewer) {
         return Ok(render!(errors::not_authorized(
             &(&conn, &rockets).to_context(),
             i18n!(rockets.intl.catalog, "This post isn't published yet.")
         )));
     }
 
-    let comments = CommentTree::from_post(&conn, &post, user.as_ref())?;
+    // Nothing is written during maintenance
+    if !is_author && !Maintenance::is_active(&conn) {
+        if let Err(e) = PostView::record(&conn, &post, &visitor) {
+            warn!("Failed to count post view: {:?}", e);
+        }
+    }
+
+    // Anonymous readers all see the same page
+    let anonymous = user.is_none() && responding_to.is_none() && rockets.flash_msg.is_none();
+    let render_page = || -> Result<Ructe, ErrorPage> {
+        let comments = CommentTree::from_post(&conn, &post, user.as_ref())?;
 
-    let previous = responding_to.and_then(|r| Comment::get(&conn, r).ok());
+        let previous = responding_to.and_then(|r| Comment::get(&conn, r).ok());
 
-    Ok(render!(posts::details(
+        Ok(render!(posts::details(
             &(&conn, &rockets).to_context(),
             post.clone(),
             blog,
@@ -96,8 +124,17 @@ pub fn details(
             user.clone().and_then(|u| u.has_liked(&conn, &post).ok()).unwrap_or(false),
             user.clone().and_then(|u| u.has_reshared(&conn, &post).ok()).unwrap_or(false),
             user.and_then(|u| u.is_following(&conn, post.get_authors(&conn).ok()?[0].id).ok()).unwrap_or(false),
-            post.get_authors(&conn)?[0].clone()
+            post.get_authors(&conn)?[0].clone(),
+            post.displayed_cover(&conn)
         )))
+    };
+    if anonymous {
+        let page = cache::get_or_insert(Entry::Article(post.id, rockets.intl.lang), || {
+            render_page().map(|page| String::from_utf8_lossy(&page.0).into_owned())
+        })?;
+        return Ok(Ructe(page.into_bytes()));
+    }
+    render_page()
 }
 
 #[get("/~/<blog>/<slug>", rank = 3)]
@@ -147,6 +184,15 @@ pub fn new(
             i18n!(rockets.intl.catalog, "You are not an author of this blog.")
         )));
     }
+    if b.moved_to.is_some() {
+        return Ok(render!(errors::not_authorized(
+            &(&conn, &rockets).to_context(),
+            i18n!(
+                rockets.intl.catalog,
+                "This blog moved to another place, new articles can't be published here."
+            )
+        )));
+    }
 
     let medias = Media::for_user(&conn, user.id)?;
     Ok(render!(posts::new(
@@ -155,7 +201,7 @@ pub fn new(
         b,
         false,
         &NewPostForm {
-            license: Instance::get_local()?.default_license,
+            license: b.license(&conn)?,
             ..NewPostForm::default()
         },
         true,
@@ -166,6 +212,99 @@ pub fn new(
     )))
 }
 
+/// Something to publish, sent by another website or a bookmarklet.
+#[derive(Clone, Default, FromForm, UriDisplayQuery)]
+pub struct ShareIntent {
+    /// The FQN of the blog to write in, if the user has more than one
+    pub blog: Option<String>,
+    pub title: Option<String>,
+    pub body: Option<String>,
+    pub url: Option<String>,
+}
+
+impl ShareIntent {
+    /// The shared text, followed by the shared link.
+    pub fn content(&self) -> String {
+        [self.body.as_deref(), self.url.as_deref()]
+            .iter()
+            .flatten()
+            .map(|part| part.trim())
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
+#[get("/new?<intent..>", rank = 2)]
+pub fn share_auth(intent: LenientForm<ShareIntent>, i18n: I18n) -> Flash<Redirect> {
+    requires_login(
+        &i18n!(i18n.catalog, "To share this, you need to be logged in"),
+        uri!(share: intent = intent.into_inner()),
+    )
+}
+
+/// Opens the editor with the shared title and text, in the chosen blog.
+///
+/// When the user has more than one blog, or when the shared link is an article they can
+/// comment, they are asked what they want to do first.
+#[get("/new?<intent..>", rank = 1)]
+pub fn share(
+    intent: LenientForm<ShareIntent>,
+    user: User,
+    cl: ContentLen,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Result<Ructe, ErrorPage> {
+    let intent = intent.into_inner();
+    let mut blogs = Blog::find_for_author(&conn, &user)?;
+    let blog = match intent.blog {
+        Some(ref fqn) => blogs
+            .iter()
+            .position(|b| &b.fqn == fqn)
+            .map(|i| blogs.remove(i)),
+        None if blogs.len() == 1 => blogs.pop(),
+        None => None,
+    };
+    let article = intent
+        .url
+        .as_deref()
+        .and_then(|url| Post::find_by_ap_url(&conn, url.trim()).ok())
+        .filter(|post| post.published)
+        .and_then(|post| post.get_blog(&conn).ok().map(|blog| (post, blog)));
+
+    match blog {
+        Some(blog) if intent.blog.is_some() || article.is_none() => {
+            let medias = Media::for_user(&conn, user.id)?;
+            Ok(render!(posts::new(
+                &(&conn, &rockets).to_context(),
+                i18n!(rockets.intl.catalog, "New post"),
+                blog,
+                false,
+                &NewPostForm {
+                    title: intent.title.clone().unwrap_or_default(),
+                    content: intent.content(),
+                    license: blog.license(&conn)?,
+                    ..NewPostForm::default()
+                },
+                true,
+                None,
+                ValidationErrors::default(),
+                medias,
+                cl.0
+            )))
+        }
+        blog => {
+            blogs.extend(blog);
+            Ok(render!(posts::share(
+                &(&conn, &rockets).to_context(),
+                intent,
+                blogs,
+                article
+            )))
+        }
+    }
+}
+
 #[get("/~/<blog>/<slug>/edit")]
 pub fn edit(
     blog: String,
@@ -179,12 +318,18 @@ pub fn edit(
     let post = Post::find_by_slug(&conn, &slug, b.id)?;
     let user = rockets.user.clone().unwrap();
 
-    if !user.is_author_in(&conn, &b)? {
+    if !user.is_author_in(&conn, &b)? && !PostTranslation::is_draft_of(&conn, &post, &user) {
         return Ok(render!(errors::not_authorized(
             &(&conn, &rockets).to_context(),
             i18n!(intl, "You are not an author of this blog.")
         )));
     }
+    if !user.can_publish_in(&conn, &b)? && (post.published || !post.is_author(&conn, user.id)?) {
+        return Ok(render!(errors::not_authorized(
+            &(&conn, &rockets).to_context(),
+            i18n!(intl, "Contributors can only edit their own drafts.")
+        )));
+    }
 
     let source = if !post.source.is_empty() {
         post.source.clone()
@@ -202,6 +347,7 @@ pub fn edit(
         &NewPostForm {
             title: post.title.clone(),
             subtitle: post.subtitle.clone(),
+            excerpt: post.excerpt.clone(),
             content: source,
             tags: Tag::for_post(&conn, post.id)?
                 .into_iter()
@@ -211,6 +357,26 @@ pub fn edit(
             license: post.license.clone(),
             draft: true,
             cover: post.cover_id,
+            no_crosspost: CrosspostOptOut::exists(&conn, post.id)?,
+            quote: Quote::list_for_post(&conn, post.id)?
+                .into_iter()
+                .map(|q| q.quoted_url)
+                .next()
+                .unwrap_or_default(),
+            canonical_url: post.canonical_url.clone().unwrap_or_default(),
+            series: Series::for_post(&conn, post.id)
+                .map(|(series, _)| series.title)
+                .unwrap_or_default(),
+            categories: Category::for_post(&conn, post.id)?
+                .into_iter()
+                .map(|c| c.name)
+                .collect::<Vec<String>>()
+                .join(", "),
+            attachments: PostAttachment::media_for_post(&conn, post.id)?
+                .into_iter()
+                .map(|m| m.id.to_string())
+                .collect::<Vec<String>>()
+                .join(", "),
         },
         !post.published,
         Some(post),
@@ -258,9 +424,13 @@ pub fn update(
     }
 
     if errors.is_empty() {
+        let can_publish = user
+            .can_publish_in(&conn, &b)
+            .expect("posts::update: role error");
         if !user
             .is_author_in(&conn, &b)
             .expect("posts::update: is author in error")
+            && !PostTranslation::is_draft_of(&conn, &post, &user)
         {
             // actually it's not "Ok"…
             Flash::error(
@@ -268,6 +438,17 @@ pub fn update(
                 i18n!(&intl, "You are not allowed to publish on this blog."),
             )
             .into()
+        } else if !can_publish
+            && (post.published
+                || !post
+                    .is_author(&conn, user.id)
+                    .expect("posts::update: is author error"))
+        {
+            Flash::error(
+                Redirect::to(uri!(super::blogs::details: name = blog, page = _)),
+                i18n!(&intl, "Contributors can only edit their own drafts."),
+            )
+            .into()
         } else {
             let (content, mentions, hashtags) = md_to_html(
                 form.content.to_string().as_ref(),
@@ -286,8 +467,10 @@ pub fn update(
                 )),
             );
 
+            // Contributors' articles are only published once an editor approves them
+            let submitted = !post.published && !form.draft && !can_publish;
             // update publication date if when this article is no longer a draft
-            let newly_published = if !post.published && !form.draft {
+            let newly_published = if !post.published && !form.draft && can_publish {
                 post.published = true;
                 post.creation_date = Utc::now().naive_utc();
                 post.ap_url = Post::ap_url(post.get_blog(&conn).unwrap(), &new_slug);
@@ -299,21 +482,44 @@ pub fn update(
             post.slug = new_slug.clone();
             post.title = form.title.clone();
             post.subtitle = form.subtitle.clone();
+            post.excerpt = form.excerpt.trim().to_owned();
             post.content = SafeString::new(&content);
             post.source = form.content.clone();
             post.license = form.license.clone();
             post.cover_id = form.cover;
+            post.canonical_url = form.canonical_url();
             post.update(&conn).expect("post::update: update error");
+            if submitted {
+                PostReview::submit(&conn, &post, &user).expect("post::update: review error");
+            } else if newly_published {
+                PostReview::close(&conn, post.id).expect("post::update: review error");
+            }
+            CrosspostOptOut::set(&conn, post.id, form.no_crosspost)
+                .expect("post::update: cross-posting error");
+            Quote::set_for_post(&conn, post.id, form.quoted_urls())
+                .expect("post::update: quote error");
+            let series = form
+                .find_series(&conn, &b)
+                .expect("post::update: series error");
+            Series::set_for_post(&conn, &post, series.as_ref())
+                .expect("post::update: series error");
+            let categories = form
+                .find_categories(&conn, &b)
+                .expect("post::update: categories error");
+            Category::set_for_post(&conn, post.id, &categories)
+                .expect("post::update: categories error");
+            let attachments = form
+                .attachment_ids(&conn, &user)
+                .expect("post::update: attachments error");
+            PostAttachment::set_for_post(&conn, post.id, &attachments)
+                .expect("post::update: attachments error");
 
+            let mut mention_failures = vec![];
             if post.published {
-                post.update_mentions(
-                    &conn,
-                    mentions
-                        .into_iter()
-                        .filter_map(|m| Mention::build_activity(&conn, &m).ok())
-                        .collect(),
-                )
-                .expect("post::update: mentions error");
+                let resolved = Mention::resolve_all(&conn, mentions);
+                post.update_mentions(&conn, resolved.mentions)
+                    .expect("post::update: mentions error");
+                mention_failures = resolved.failures;
             }
 
             let tags = form
@@ -342,12 +548,19 @@ pub fn update(
                     let act = post
                         .create_activity(&conn)
                         .expect("post::update: act error");
-                    let dest = User::one_by_instance(&conn).expect("post::update: dest error");
-                    rockets
-                        .worker
-                        .execute(move || broadcast(&user, act, dest, CONFIG.proxy().cloned()));
+                    let announce = post
+                        .announce_activity(&conn)
+                        .expect("post::update: announce error");
+                    let (dest, group_dest) = User::one_by_instance(&conn)
+                        .and_then(|dest| groups::split_destinations(&conn, dest))
+                        .expect("post::update: dest error");
+                    rockets.worker.execute(move || {
+                        broadcast(&user, act, dest, CONFIG.proxy().cloned());
+                        broadcast(&b, announce, group_dest, CONFIG.proxy().cloned());
+                    });
 
                     Timeline::add_to_all_timelines(&conn, &post, Kind::Original).ok();
+                    Crosspost::schedule(&conn, &post).expect("post::update: cross-posting error");
                 } else {
                     let act = post
                         .update_activity(&conn)
@@ -359,13 +572,19 @@ pub fn update(
                 }
             }
 
-            Flash::success(
+            super::flash_with_mentions(
+                intl,
                 Redirect::to(uri!(
                     details: blog = blog,
                     slug = new_slug,
                     responding_to = _
                 )),
-                i18n!(intl, "Your article has been updated."),
+                if submitted {
+                    i18n!(intl, "Your article has been submitted for review.")
+                } else {
+                    i18n!(intl, "Your article has been updated.")
+                },
+                &mention_failures,
             )
             .into()
         }
@@ -392,11 +611,95 @@ pub struct NewPostForm {
     #[validate(custom(function = "valid_slug", message = "Invalid title"))]
     pub title: String,
     pub subtitle: String,
+    #[validate(length(max = 500, message = "The excerpt is too long"))]
+    pub excerpt: String,
     pub content: String,
     pub tags: String,
     pub license: String,
     pub draft: bool,
     pub cover: Option<i32>,
+    pub no_crosspost: bool,
+    /// URL of a fediverse post quoted by this article
+    #[validate(custom(function = "valid_url", message = "Invalid URL"))]
+    pub quote: String,
+    /// Where the article was first published, if it is cross-posted here
+    #[validate(custom(function = "valid_url", message = "Invalid URL"))]
+    pub canonical_url: String,
+    /// Title of the series this article is part of
+    pub series: String,
+    /// Comma-separated names of the categories of this article
+    pub categories: String,
+    /// Comma-separated IDs of the audio files attached to this article
+    pub attachments: String,
+}
+
+impl NewPostForm {
+    fn quoted_urls(&self) -> Vec<String> {
+        Some(self.quote.trim())
+            .filter(|q| !q.is_empty())
+            .map(|q| vec![q.to_owned()])
+            .unwrap_or_default()
+    }
+
+    fn canonical_url(&self) -> Option<String> {
+        Some(self.canonical_url.trim())
+            .filter(|url| !url.is_empty())
+            .map(str::to_owned)
+    }
+
+    /// Finds or creates the series of the article, if there is one.
+    fn find_series(&self, conn: &Connection, blog: &Blog) -> Result<Option<Series>, Error> {
+        Some(self.series.trim())
+            .filter(|s| !s.is_empty())
+            .map(|s| Series::find_or_create(conn, blog, s))
+            .transpose()
+    }
+
+    /// Finds the categories of the article. Unknown ones are created at the top level.
+    fn find_categories(&self, conn: &Connection, blog: &Blog) -> Result<Vec<Category>, Error> {
+        let mut categories: Vec<Category> = vec![];
+        for name in self
+            .categories
+            .split(',')
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+        {
+            let category = Category::find_or_create(conn, blog, name, None)?;
+            if !categories.iter().any(|c| c.id == category.id) {
+                categories.push(category);
+            }
+        }
+        Ok(categories)
+    }
+
+    /// The audio files to attach to the article. Media of other users are ignored.
+    fn attachment_ids(&self, conn: &Connection, user: &User) -> Result<Vec<i32>, Error> {
+        let mut ids = vec![];
+        for id in self
+            .attachments
+            .split(',')
+            .filter_map(|id| id.trim().parse::<i32>().ok())
+        {
+            let media = match Media::get(conn, id) {
+                Ok(media) => media,
+                Err(Error::NotFound) => continue,
+                Err(err) => return Err(err),
+            };
+            if media.owner_id == user.id && media.is_attachable() {
+                ids.push(media.id);
+            }
+        }
+        Ok(ids)
+    }
+}
+
+pub fn valid_url(quote: &str) -> Result<(), ValidationError> {
+    let quote = quote.trim();
+    if quote.is_empty() || quote.starts_with("https://") || quote.starts_with("http://") {
+        Ok(())
+    } else {
+        Err(ValidationError::new("invalid_url"))
+    }
 }
 
 pub fn valid_slug(title: &str) -> Result<(), ValidationError> {
@@ -441,6 +744,7 @@ pub fn create(
         if !user
             .is_author_in(&conn, &blog)
             .expect("post::create: is author in error")
+            || blog.moved_to.is_some()
         {
             // actually it's not "Ok"…
             return Ok(Flash::error(
@@ -452,6 +756,9 @@ pub fn create(
             )
             .into());
         }
+        let can_publish = user
+            .can_publish_in(&conn, &blog)
+            .expect("post::create: role error");
 
         let (content, mentions, hashtags) = md_to_html(
             form.content.to_string().as_ref(),
@@ -477,13 +784,15 @@ pub fn create(
                 slug: slug.to_string(),
                 title: form.title.to_string(),
                 content: SafeString::new(&content),
-                published: !form.draft,
+                published: !form.draft && can_publish,
                 license: form.license.clone(),
                 ap_url: "".to_string(),
                 creation_date: None,
                 subtitle: form.subtitle.clone(),
                 source: form.content.clone(),
                 cover_id: form.cover,
+                excerpt: form.excerpt.trim().to_owned(),
+                canonical_url: form.canonical_url(),
             },
         )
         .expect("post::create: post save error");
@@ -496,6 +805,27 @@ pub fn create(
             },
         )
         .expect("post::create: author save error");
+        let submitted = !form.draft && !can_publish;
+        if submitted {
+            PostReview::submit(&conn, &post, &user).expect("post::create: review error");
+        }
+        CrosspostOptOut::set(&conn, post.id, form.no_crosspost)
+            .expect("post::create: cross-posting error");
+        Quote::set_for_post(&conn, post.id, form.quoted_urls()).expect("post::create: quote error");
+        let series = form
+            .find_series(&conn, &blog)
+            .expect("post::create: series error");
+        Series::set_for_post(&conn, &post, series.as_ref()).expect("post::create: series error");
+        let categories = form
+            .find_categories(&conn, &blog)
+            .expect("post::create: categories error");
+        Category::set_for_post(&conn, post.id, &categories)
+            .expect("post::create: categories error");
+        let attachments = form
+            .attachment_ids(&conn, &user)
+            .expect("post::create: attachments error");
+        PostAttachment::set_for_post(&conn, post.id, &attachments)
+            .expect("post::create: attachments error");
 
         let tags = form
             .tags
@@ -526,35 +856,50 @@ pub fn create(
             .expect("post::create: hashtags save error");
         }
 
+        let mut mention_failures = vec![];
         if post.published {
-            for m in mentions {
-                Mention::from_activity(
-                    &conn,
-                    &Mention::build_activity(&conn, &m).expect("post::create: mention build error"),
-                    post.id,
-                    true,
-                    true,
-                )
-                .expect("post::create: mention save error");
+            let resolved = Mention::resolve_all(&conn, mentions);
+            for m in resolved.mentions {
+                Mention::from_activity(&conn, &m, post.id, true, true)
+                    .expect("post::create: mention save error");
             }
+            mention_failures = resolved.failures;
 
             let act = post
                 .create_activity(&conn)
                 .expect("posts::create: activity error");
-            let dest = User::one_by_instance(&conn).expect("posts::create: dest error");
+            let announce = post
+                .announce_activity(&conn)
+                .expect("posts::create: announce error");
+            let (dest, group_dest) = User::one_by_instance(&conn)
+                .and_then(|dest| groups::split_destinations(&conn, dest))
+                .expect("posts::create: dest error");
             let worker = &rockets.worker;
-            worker.execute(move || broadcast(&user, act, dest, CONFIG.proxy().cloned()));
+            worker.execute(move || {
+                broadcast(&user, act, dest, CONFIG.proxy().cloned());
+                broadcast(&blog, announce, group_dest, CONFIG.proxy().cloned());
+            });
 
             Timeline::add_to_all_timelines(&conn, &post, Kind::Original)?;
+            Crosspost::schedule(&conn, &post)?;
         }
 
-        Ok(Flash::success(
+        Ok(super::flash_with_mentions(
+            &rockets.intl.catalog,
             Redirect::to(uri!(
                 details: blog = blog_name,
                 slug = slug,
                 responding_to = _
             )),
-            i18n!(&rockets.intl.catalog, "Your article has been saved."),
+            if submitted {
+                i18n!(
+                    &rockets.intl.catalog,
+                    "Your article has been submitted for review."
+                )
+            } else {
+                i18n!(&rockets.intl.catalog, "Your article has been saved.")
+            },
+            &mention_failures,
         )
         .into())
     } else {
@@ -1,9 +1,15 @@
 use chrono::offset::Utc;
-use rocket::request::Form;
+use rocket::{request::Form, response::Redirect};
 
-use crate::routes::Page;
+use crate::routes::{Page, RespondOrRedirect};
 use crate::template_utils::{IntoContext, Ructe};
-use plume_models::{db_conn::DbConn, search::Query, PlumeRocket};
+use plume_models::{
+    db_conn::DbConn,
+    lookup::{self, Resolved},
+    search::Query,
+    users::User,
+    PlumeRocket,
+};
 use std::str::FromStr;
 
 #[derive(Default, FromForm)]
@@ -20,45 +26,55 @@ pub struct SearchQuery {
     license: Option<String>,
     after: Option<String>,
     before: Option<String>,
+    #[form(field = "type")]
+    kind: Option<String>,
     page: Option<Page>,
 }
 
-macro_rules! param_to_query {
-    ( $query:ident, $parsed_query:ident; normal: $($field:ident),*; date: $($date:ident),*) => {
-        $(
-            let mut rest = $query.$field.as_ref().map(String::as_str).unwrap_or_default();
-            while !rest.is_empty() {
-                let (token, r) = Query::get_first_token(rest);
-                rest = r;
-                $parsed_query.$field(token, None);
-            }
-        )*
-        $(
-            if let Some(ref field) = $query.$date {
-                let mut rest = field.as_str();
-                while !rest.is_empty() {
-                    use chrono::naive::NaiveDate;
-                    let (token, r) = Query::get_first_token(rest);
-                    rest = r;
-                    if let Ok(token) = NaiveDate::parse_from_str(token, "%Y-%m-%d") {
-                        $parsed_query.$date(&token);
-                    }
-                }
+#[get("/search?<query..>")]
+pub fn search(
+    query: Option<Form<SearchQuery>>,
+    user: Option<User>,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> RespondOrRedirect {
+    let query = query.map(Form::into_inner).unwrap_or_default();
+
+    // A pasted URL or address brings the remote content here. Only logged in users
+    // can make the instance fetch something.
+    if let (Some(user), Some(q)) = (user.as_ref(), query.q.as_deref()) {
+        if lookup::is_address(q) {
+            if let Some(redirect) = lookup::resolve(&conn, q)
+                .ok()
+                .and_then(|resolved| redirect_to(&conn, Some(user), resolved))
+            {
+                return redirect.into();
             }
-        )*
+        }
     }
-}
 
-#[get("/search?<query..>")]
-pub fn search(query: Option<Form<SearchQuery>>, conn: DbConn, rockets: PlumeRocket) -> Ructe {
-    let query = query.map(Form::into_inner).unwrap_or_default();
     let page = query.page.unwrap_or_default();
     let mut parsed_query =
         Query::from_str(query.q.as_deref().unwrap_or_default()).unwrap_or_default();
 
-    param_to_query!(query, parsed_query; normal: title, subtitle, content, tag,
-              instance, author, blog, lang, license;
-              date: before, after);
+    for (field, value) in &[
+        ("title", &query.title),
+        ("subtitle", &query.subtitle),
+        ("content", &query.content),
+        ("tag", &query.tag),
+        ("instance", &query.instance),
+        ("author", &query.author),
+        ("blog", &query.blog),
+        ("lang", &query.lang),
+        ("license", &query.license),
+        ("before", &query.before),
+        ("after", &query.after),
+        ("type", &query.kind),
+    ] {
+        if let Some(value) = value {
+            parsed_query.parse_field(field, value);
+        }
+    }
 
     let str_query = parsed_query.to_string();
 
@@ -67,10 +83,11 @@ pub fn search(query: Option<Form<SearchQuery>>, conn: DbConn, rockets: PlumeRock
             &(&conn, &rockets).to_context(),
             &format!("{}", Utc::now().date_naive().format("%Y-%m-d"))
         ))
+        .into()
     } else {
         let res = rockets
             .searcher
-            .search_document(&conn, parsed_query, page.limits());
+            .search(&conn, parsed_query, user.as_ref(), page.limits());
         let next_page = if res.is_empty() { 0 } else { page.0 + 1 };
         render!(search::result(
             &(&conn, &rockets).to_context(),
@@ -79,5 +96,32 @@ pub fn search(query: Option<Form<SearchQuery>>, conn: DbConn, rockets: PlumeRock
             page.0,
             next_page
         ))
+        .into()
+    }
+}
+
+/// The local page of something found with `lookup::resolve`, if `user` can see it.
+pub fn redirect_to(conn: &DbConn, user: Option<&User>, resolved: Resolved) -> Option<Redirect> {
+    match resolved {
+        Resolved::User(user) => Some(Redirect::to(uri!(super::user::details: name = user.fqn))),
+        Resolved::Blog(blog) => Some(Redirect::to(uri!(
+            super::blogs::details: name = blog.fqn,
+            page = _
+        ))),
+        Resolved::Post(post) if post.published => Some(Redirect::to(uri!(
+            super::posts::details: blog = post.get_blog(conn).ok()?.fqn,
+            slug = &post.slug,
+            responding_to = _
+        ))),
+        Resolved::Post(_) => None,
+        Resolved::Comment(comment) if comment.can_see(conn, user) => {
+            let post = comment.get_post(conn).ok()?;
+            Some(Redirect::to(uri!(
+                super::posts::details: blog = post.get_blog(conn).ok()?.fqn,
+                slug = &post.slug,
+                responding_to = comment.id
+            )))
+        }
+        Resolved::Comment(_) => None,
     }
 }
@@ -0,0 +1,91 @@
+use activitystreams::collection::OrderedCollection;
+use rocket::{
+    request::LenientForm,
+    response::{Flash, Redirect},
+};
+use rocket_i18n::I18n;
+
+use crate::routes::errors::ErrorPage;
+use crate::template_utils::{IntoContext, Ructe};
+use plume_common::activity_pub::{ActivityStream, ApRequest};
+use plume_models::{blogs::Blog, db_conn::DbConn, series::Series, users::User, Error, PlumeRocket};
+
+#[derive(Default, FromForm)]
+pub struct SeriesForm {
+    pub summary: String,
+}
+
+#[get("/~/<blog>/series/<slug>", rank = 6)]
+pub fn details(
+    blog: String,
+    slug: String,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Result<Ructe, ErrorPage> {
+    let blog = Blog::find_by_fqn(&conn, &blog)?;
+    let series = Series::find_by_slug(&conn, blog.id, &slug)?;
+    let posts = series.posts(&conn)?;
+    Ok(render!(series::details(
+        &(&conn, &rockets).to_context(),
+        blog,
+        series,
+        posts
+    )))
+}
+
+#[get("/~/<blog>/series/<slug>", rank = 5)]
+pub fn activity_details(
+    blog: String,
+    slug: String,
+    _ap: ApRequest,
+    conn: DbConn,
+) -> Option<ActivityStream<OrderedCollection>> {
+    let blog = Blog::find_by_fqn(&conn, &blog).ok()?;
+    let series = Series::find_by_slug(&conn, blog.id, &slug).ok()?;
+    Some(ActivityStream::new(series.to_activity(&conn).ok()?))
+}
+
+#[post("/~/<blog>/series/<slug>", data = "<form>", rank = 5)]
+pub fn update(
+    blog: String,
+    slug: String,
+    form: LenientForm<SeriesForm>,
+    user: User,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let series = blog_series(&conn, &blog, &slug, &user)?;
+    series.update_summary(&conn, form.summary.trim())?;
+    Ok(Flash::success(
+        Redirect::to(uri!(details: blog = blog, slug = slug)),
+        i18n!(intl.catalog, "The series has been updated."),
+    ))
+}
+
+#[post("/~/<blog>/series/<slug>/delete")]
+pub fn delete(
+    blog: String,
+    slug: String,
+    user: User,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let series = blog_series(&conn, &blog, &slug, &user)?;
+    series.delete(&conn)?;
+    Ok(Flash::success(
+        Redirect::to(uri!(super::blogs::details: name = blog, page = _)),
+        i18n!(
+            intl.catalog,
+            "The series has been deleted. Its articles are still on the blog."
+        ),
+    ))
+}
+
+/// Finds a series of `blog`, checking that `user` can manage it.
+fn blog_series(conn: &DbConn, blog: &str, slug: &str, user: &User) -> Result<Series, Error> {
+    let blog = Blog::find_by_fqn(conn, blog)?;
+    if !user.can_publish_in(conn, &blog)? {
+        return Err(Error::Unauthorized);
+    }
+    Series::find_by_slug(conn, blog.id, slug)
+}
@@ -1,9 +1,9 @@
-use crate::routes::RespondOrRedirect;
+use crate::routes::{check_password, errors::ErrorPage, RespondOrRedirect};
+use chrono::Utc;
 use plume_models::lettre::Transport;
 use rocket::http::ext::IntoOwned;
 use rocket::{
-    http::{uri::Uri, Cookie, Cookies, SameSite},
-    request::LenientForm,
+    http::{uri::Uri, Cookies},
     response::{Flash, Redirect},
     State,
 };
@@ -16,11 +16,16 @@ use std::{
 use tracing::warn;
 use validator::{Validate, ValidationError, ValidationErrors};
 
-use crate::mail::{build_mail, Mailer};
+use crate::mail::{build_mail, send_lockout_warning, Mailer};
 use crate::template_utils::{IntoContext, Ructe};
 use plume_models::{
     db_conn::DbConn,
+    failed_logins::{LoginError, LoginGuard},
+    legal_documents::LegalDocument,
     password_reset_requests::*,
+    rate_limits::{Login, RateLimit},
+    request_limits::{Auth, LimitedForm},
+    sessions::{Device, Session},
     users::{User, AUTH_COOKIE},
     Error, PlumeRocket, CONFIG,
 };
@@ -45,7 +50,11 @@ pub struct LoginForm {
 
 #[post("/login", data = "<form>")]
 pub fn create(
-    form: LenientForm<LoginForm>,
+    _limit: RateLimit<Login>,
+    form: LimitedForm<Auth, LoginForm>,
+    guard: LoginGuard,
+    device: Device,
+    mail: State<'_, Arc<Mutex<Mailer>>>,
     mut cookies: Cookies<'_>,
     conn: DbConn,
     rockets: PlumeRocket,
@@ -54,27 +63,60 @@ pub fn create(
         Ok(_) => ValidationErrors::new(),
         Err(e) => e,
     };
-    let user = User::login(&conn, &form.email_or_name, &form.password);
-    let user_id = if let Ok(user) = user {
-        user.id.to_string()
-    } else {
-        let mut err = ValidationError::new("invalid_login");
-        err.message = Some(Cow::from("Invalid username, or password"));
-        errors.add("email_or_name", err);
-        return render!(session::login(
-            &(&conn, &rockets).to_context(),
-            None,
-            &*form,
-            errors
-        ))
-        .into();
+    let user = match guard.login(&conn, &form.email_or_name, &form.password) {
+        Ok(user) => user,
+        Err(err) => {
+            let message = match err {
+                LoginError::Locked(until) => {
+                    let minutes = (until - Utc::now().naive_utc()).num_minutes() + 1;
+                    i18n!(
+                        rockets.intl.catalog,
+                        "Too many failed attempts, please try again in {0} minutes.";
+                        minutes
+                    )
+                }
+                LoginError::Invalid(locked) => {
+                    if let Some(user) = locked {
+                        send_lockout_warning(&mail, &user);
+                    }
+                    i18n!(rockets.intl.catalog, "Invalid username, or password")
+                }
+                LoginError::Other(_) => {
+                    i18n!(rockets.intl.catalog, "Invalid username, or password")
+                }
+            };
+            let mut err = ValidationError::new("invalid_login");
+            err.message = Some(Cow::from(message));
+            errors.add("email_or_name", err);
+            return render!(session::login(
+                &(&conn, &rockets).to_context(),
+                None,
+                &*form,
+                errors
+            ))
+            .into();
+        }
     };
 
-    cookies.add_private(
-        Cookie::build(AUTH_COOKIE, user_id)
-            .same_site(SameSite::Lax)
-            .finish(),
-    );
+    let session = match Session::open(&conn, &user, &device) {
+        Ok(session) => session,
+        Err(_) => {
+            let mut err = ValidationError::new("invalid_login");
+            err.message = Some(Cow::from(i18n!(
+                rockets.intl.catalog,
+                "Couldn't log you in, please try again later."
+            )));
+            errors.add("email_or_name", err);
+            return render!(session::login(
+                &(&conn, &rockets).to_context(),
+                None,
+                &*form,
+                errors
+            ))
+            .into();
+        }
+    };
+    cookies.add_private(session.cookie());
     let destination = rockets
         .flash_msg
         .clone()
@@ -88,6 +130,15 @@ pub fn create(
             },
         )
         .unwrap_or_else(|| "/".to_owned());
+    // New versions of the terms have to be accepted first
+    let destination = if LegalDocument::pending_for(&conn, &user)
+        .map(|pending| !pending.is_empty())
+        .unwrap_or(false)
+    {
+        uri!(super::instance::accept_terms_form: next = Some(destination)).to_string()
+    } else {
+        destination
+    };
 
     if let Ok(uri) = Uri::parse(&destination).map(IntoOwned::into_owned) {
         Flash::success(
@@ -107,7 +158,18 @@ pub fn create(
 }
 
 #[get("/logout")]
-pub fn delete(mut cookies: Cookies<'_>, intl: I18n) -> Flash<Redirect> {
+pub fn delete(
+    session: Option<Session>,
+    mut cookies: Cookies<'_>,
+    conn: DbConn,
+    intl: I18n,
+) -> Flash<Redirect> {
+    if let Some(session) = session {
+        session
+            .revoke(&conn)
+            .map_err(|_| warn!("Couldn't revoke session"))
+            .ok();
+    }
     if let Some(cookie) = cookies.get_private(AUTH_COOKIE) {
         cookies.remove_private(cookie);
     }
@@ -117,6 +179,35 @@ pub fn delete(mut cookies: Cookies<'_>, intl: I18n) -> Flash<Redirect> {
     )
 }
 
+/// Logs out another browser of the current user.
+#[post("/me/sessions/<id>/revoke")]
+pub fn revoke(id: i32, user: User, conn: DbConn, intl: I18n) -> Result<Flash<Redirect>, ErrorPage> {
+    let session = Session::get(&conn, id)?;
+    if session.user_id != user.id {
+        return Err(Error::Unauthorized.into());
+    }
+    session.revoke(&conn)?;
+    Ok(Flash::success(
+        Redirect::to(uri!(super::user::edit: name = user.username)),
+        i18n!(intl.catalog, "This session has been closed."),
+    ))
+}
+
+/// Logs out all the browsers of the current user, except this one.
+#[post("/me/sessions/revoke-others")]
+pub fn revoke_others(
+    session: Session,
+    user: User,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    Session::revoke_all(&conn, user.id, Some(session.id))?;
+    Ok(Flash::success(
+        Redirect::to(uri!(super::user::edit: name = user.username)),
+        i18n!(intl.catalog, "All your other sessions have been closed."),
+    ))
+}
+
 #[derive(Clone)]
 pub struct ResetRequest {
     pub mail: String,
@@ -148,7 +239,7 @@ pub struct ResetForm {
 #[post("/password-reset", data = "<form>")]
 pub fn password_reset_request(
     mail: State<'_, Arc<Mutex<Mailer>>>,
-    form: LenientForm<ResetForm>,
+    form: LimitedForm<Auth, ResetForm>,
     conn: DbConn,
     rockets: PlumeRocket,
 ) -> Ructe {
@@ -212,17 +303,19 @@ fn passwords_match(form: &NewPasswordForm) -> Result<(), ValidationError> {
 #[post("/password-reset/<token>", data = "<form>")]
 pub fn password_reset(
     token: String,
-    form: LenientForm<NewPasswordForm>,
+    form: LimitedForm<Auth, NewPasswordForm>,
     conn: DbConn,
     rockets: PlumeRocket,
 ) -> Result<Flash<Redirect>, Ructe> {
-    form.validate().map_err(|err| {
-        render!(session::password_reset(
-            &(&conn, &rockets).to_context(),
-            &form,
-            err
-        ))
-    })?;
+    form.validate()
+        .and_then(|_| check_password(&form.password, &[]))
+        .map_err(|err| {
+            render!(session::password_reset(
+                &(&conn, &rockets).to_context(),
+                &form,
+                err
+            ))
+        })?;
 
     PasswordResetRequest::find_and_delete_by_token(&conn, &token)
         .and_then(|request| User::find_by_email(&conn, &request.email))
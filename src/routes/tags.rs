@@ -1,6 +1,12 @@
+use rocket::response::{Flash, Redirect};
+use rocket_i18n::I18n;
+
 use crate::routes::{errors::ErrorPage, Page};
 use crate::template_utils::{IntoContext, Ructe};
-use plume_models::{db_conn::DbConn, posts::Post, PlumeRocket};
+use plume_models::{
+    db_conn::DbConn, hashtag_follows::HashtagFollow, posts::Post, tag_aliases::TagAlias,
+    users::User, PlumeRocket,
+};
 
 #[get("/tag/<name>?<page>")]
 pub fn tag(
@@ -10,6 +16,7 @@ pub fn tag(
     rockets: PlumeRocket,
 ) -> Result<Ructe, ErrorPage> {
     let page = page.unwrap_or_default();
+    let name = TagAlias::canonical(&conn, &name);
     let posts = Post::list_by_tag(&conn, name.clone(), page.limits())?;
     Ok(render!(tags::index(
         &(&conn, &rockets).to_context(),
@@ -19,3 +26,23 @@ pub fn tag(
         Page::total(Post::count_for_tag(&conn, name)? as i32)
     )))
 }
+
+#[post("/tag/<name>/follow")]
+pub fn follow(
+    name: String,
+    user: User,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let message = if let Ok(follow) = HashtagFollow::find(&conn, user.id, &name) {
+        follow.delete(&conn)?;
+        i18n!(intl.catalog, "You are no longer following #{}."; &follow.tag)
+    } else {
+        let follow = HashtagFollow::follow(&conn, &user, &name)?;
+        i18n!(intl.catalog, "You are now following #{}."; &follow.tag)
+    };
+    Ok(Flash::success(
+        Redirect::to(uri!(tag: name = name, page = _)),
+        message,
+    ))
+}
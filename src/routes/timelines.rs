@@ -3,7 +3,7 @@
 use crate::routes::Page;
 use crate::template_utils::IntoContext;
 use crate::{routes::errors::ErrorPage, template_utils::Ructe};
-use plume_models::{db_conn::DbConn, timeline::*, PlumeRocket};
+use plume_models::{db_conn::DbConn, recommendations, timeline::*, PlumeRocket};
 use rocket::response::Redirect;
 
 #[get("/timeline/<id>?<page>")]
@@ -16,13 +16,21 @@ pub fn details(
     let page = page.unwrap_or_default();
     let all_tl = Timeline::list_all_for_user(&conn, rockets.user.clone().map(|u| u.id))?;
     let tl = Timeline::get(&conn, id)?;
-    let posts = tl.get_page(&conn, page.limits())?;
-    let total_posts = tl.count_posts(&conn)?;
+    let posts = tl.get_page_for(&conn, page.limits(), rockets.user.as_ref())?;
+    let total_posts = tl.count_posts_for(&conn, rockets.user.as_ref())?;
+    // Empty timelines suggest who to follow to their owners
+    let recommended = match rockets.user {
+        Some(ref user) if total_posts == 0 && tl.user_id == Some(user.id) => {
+            Some(recommendations::for_user(&conn, user, 5)?)
+        }
+        _ => None,
+    };
     Ok(render!(timelines::details(
         &(&conn, &rockets).to_context(),
         tl,
         posts,
         all_tl,
+        recommended,
         page.0,
         Page::total(total_posts as i32)
     )))
@@ -0,0 +1,125 @@
+use rocket::request::LenientForm;
+use rocket::response::{Flash, Redirect};
+use std::{borrow::Cow, collections::HashMap};
+use validator::{Validate, ValidationError, ValidationErrors};
+
+use crate::routes::{errors::ErrorPage, RespondOrRedirect};
+use crate::template_utils::{IntoContext, Ructe};
+use plume_models::{
+    blogs::Blog,
+    db_conn::DbConn,
+    instance::Instance,
+    post_translations::{language_code, PostTranslation, SubmittedTranslation},
+    posts::Post,
+    users::User,
+    Error, PlumeRocket,
+};
+
+#[derive(Default, FromForm, Validate)]
+pub struct TranslationForm {
+    #[validate(custom(function = "valid_language", message = "Unknown language code"))]
+    pub language: String,
+    #[validate(length(min = 1, message = "The title can't be empty"))]
+    pub title: String,
+    pub subtitle: String,
+    pub content: String,
+}
+
+fn valid_language(language: &str) -> Result<(), ValidationError> {
+    language_code(language)
+        .map(|_| ())
+        .ok_or_else(|| ValidationError::new("language"))
+}
+
+/// Only the published articles of this instance that are not translations themselves can be
+/// translated.
+fn translatable(conn: &DbConn, blog: &str, slug: &str) -> Result<(Blog, Post), ErrorPage> {
+    let blog = Blog::find_by_fqn(conn, blog)?;
+    let post = Post::find_by_slug(conn, slug, blog.id)?;
+    if blog.instance_id != Instance::get_local()?.id
+        || !post.published
+        || PostTranslation::find_by_post(conn, post.id).is_ok()
+    {
+        return Err(Error::NotFound.into());
+    }
+    Ok((blog, post))
+}
+
+#[get("/~/<blog>/<slug>/translate")]
+pub fn new(
+    blog: String,
+    slug: String,
+    _user: User,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Result<Ructe, ErrorPage> {
+    let (blog, post) = translatable(&conn, &blog, &slug)?;
+    let form = TranslationForm {
+        content: post.source.clone(),
+        ..TranslationForm::default()
+    };
+    Ok(render!(posts::translate(
+        &(&conn, &rockets).to_context(),
+        &blog,
+        &post,
+        &form,
+        ValidationErrors::default()
+    )))
+}
+
+/// Saves a translation as a draft, for the editors of the blog to review.
+#[post("/~/<blog>/<slug>/translate", data = "<form>")]
+pub fn create(
+    blog: String,
+    slug: String,
+    form: LenientForm<TranslationForm>,
+    user: User,
+    conn: DbConn,
+    rockets: PlumeRocket,
+) -> Result<RespondOrRedirect, ErrorPage> {
+    let (blog, post) = translatable(&conn, &blog, &slug)?;
+    let mut errors = match form.validate() {
+        Ok(_) => ValidationErrors::new(),
+        Err(e) => e,
+    };
+    if errors.is_empty() {
+        let submitted = SubmittedTranslation {
+            language: &form.language,
+            title: &form.title,
+            subtitle: &form.subtitle,
+            source: &form.content,
+        };
+        match PostTranslation::submit(&conn, &post, &user, submitted) {
+            Ok(_) => {
+                return Ok(Flash::success(
+                    Redirect::to(
+                        uri!(super::posts::details: blog = &blog.fqn, slug = &post.slug, responding_to = _),
+                    ),
+                    i18n!(
+                        rockets.intl.catalog,
+                        "Thank you! Your translation will be published once the editors of this blog approve it."
+                    ),
+                )
+                .into())
+            }
+            Err(_) => errors.add(
+                "language",
+                ValidationError {
+                    code: Cow::from("existing_translation"),
+                    message: Some(Cow::from(
+                        "This article was already translated in this language.",
+                    )),
+                    params: HashMap::new(),
+                },
+            ),
+        }
+    }
+    Ok(render!(posts::translate(
+        &(&conn, &rockets).to_context(),
+        &blog,
+        &post,
+        &*form,
+        errors
+    ))
+    .into())
+}
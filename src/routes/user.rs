@@ -4,18 +4,32 @@ use activitystreams::{
     prelude::*,
 };
 use diesel::SaveChangesDsl;
+use multipart::server::{
+    save::{SaveResult, SavedData},
+    Multipart,
+};
 use rocket::{
     http::{uri::Uri, ContentType, Cookies},
     request::LenientForm,
     response::{status, Content, Flash, Redirect},
+    State,
 };
+use rocket_contrib::json::Json;
 use rocket_i18n::I18n;
-use std::{borrow::Cow, collections::HashMap};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fs,
+    sync::{Arc, Mutex},
+};
+use tracing::warn;
 use validator::{Validate, ValidationError, ValidationErrors};
 
 use crate::inbox;
+use crate::mail::{send_now, Mailer};
 use crate::routes::{
-    email_signups::EmailSignupForm, errors::ErrorPage, Page, RemoteForm, RespondOrRedirect,
+    check_password, email_signups::EmailSignupForm, errors::ErrorPage, profile_field_inputs, Page,
+    RemoteForm, RespondOrRedirect,
 };
 use crate::template_utils::{IntoContext, Ructe};
 use crate::utils::requires_login;
@@ -23,17 +37,28 @@ use plume_common::activity_pub::{broadcast, ActivityStream, ApRequest, CustomPer
 use plume_common::utils::md_to_html;
 use plume_models::{
     blogs::Blog,
-    db_conn::DbConn,
+    cache::{self, Entry},
+    db_conn::{write_transaction, DbConn, DbPool},
+    email_changes::{EmailChange, EmailChangeStatus},
     follows,
+    fundings::{Funding, NewFunding},
     headers::Headers,
     inbox::inbox as local_inbox,
     instance::Instance,
+    ip_records::{ClientIp, IpRecord, IpRecordKind},
     medias::Media,
+    opml, personal_data,
     posts::Post,
+    profile_fields::{ProfileField, ProfileOwner},
+    rate_limits::{Inbox, RateLimit, Registration},
+    request_limits::{Auth, LimitedData, LimitedForm, Upload},
     reshares::Reshare,
     safe_string::SafeString,
+    sessions::Session,
+    signature_pool::SignaturePool,
     signups::{self, Strategy as SignupStrategy},
     users::*,
+    worker::Worker,
     Error, PlumeRocket, CONFIG,
 };
 
@@ -272,7 +297,8 @@ pub fn activity_details(
     _ap: ApRequest,
 ) -> Option<ActivityStream<CustomPerson>> {
     let user = User::find_by_fqn(&conn, &name).ok()?;
-    Some(ActivityStream::new(user.to_activity(&conn).ok()?))
+    let actor = cache::get_or_insert(Entry::UserActor(user.id), || user.to_activity(&conn));
+    Some(ActivityStream::new(actor.ok()?))
 }
 
 #[get("/users/new")]
@@ -300,10 +326,15 @@ pub fn new(conn: DbConn, rockets: PlumeRocket) -> Result<Ructe, ErrorPage> {
 pub fn edit(
     name: String,
     user: User,
+    session: Session,
     conn: DbConn,
     rockets: PlumeRocket,
 ) -> Result<Ructe, ErrorPage> {
     if user.username == name && !name.contains('@') {
+        let funding = Funding::for_user(&conn, user.id).ok();
+        let fields = profile_field_inputs(
+            ProfileField::list(&conn, ProfileOwner::User(user.id)).unwrap_or_default(),
+        );
         Ok(render!(users::edit(
             &(&conn, &rockets).to_context(),
             UpdateUserForm {
@@ -312,8 +343,29 @@ pub fn edit(
                 summary: user.summary.clone(),
                 theme: user.preferred_theme,
                 hide_custom_css: user.hide_custom_css,
+                discoverable: user.discoverable,
+                indexable: user.indexable,
+                payment_pointer: funding
+                    .as_ref()
+                    .map(|f| f.payment_pointer.clone())
+                    .unwrap_or_default(),
+                liberapay_url: funding
+                    .as_ref()
+                    .map(|f| f.liberapay_url.clone())
+                    .unwrap_or_default(),
+                kofi_url: funding.map(|f| f.kofi_url).unwrap_or_default(),
+                field_name_0: fields[0].0.clone(),
+                field_value_0: fields[0].1.clone(),
+                field_name_1: fields[1].0.clone(),
+                field_value_1: fields[1].1.clone(),
+                field_name_2: fields[2].0.clone(),
+                field_value_2: fields[2].1.clone(),
+                field_name_3: fields[3].0.clone(),
+                field_value_3: fields[3].1.clone(),
             },
-            ValidationErrors::default()
+            ValidationErrors::default(),
+            Session::list_for_user(&conn, user.id).unwrap_or_default(),
+            session.id
         )))
     } else {
         Err(Error::Unauthorized.into())
@@ -338,6 +390,30 @@ pub struct UpdateUserForm {
     pub summary: String,
     pub theme: Option<String>,
     pub hide_custom_css: bool,
+    pub discoverable: bool,
+    pub indexable: bool,
+    pub payment_pointer: String,
+    pub liberapay_url: String,
+    pub kofi_url: String,
+    pub field_name_0: String,
+    pub field_value_0: String,
+    pub field_name_1: String,
+    pub field_value_1: String,
+    pub field_name_2: String,
+    pub field_value_2: String,
+    pub field_name_3: String,
+    pub field_value_3: String,
+}
+
+impl UpdateUserForm {
+    pub fn profile_fields(&self) -> Vec<(String, String)> {
+        vec![
+            (self.field_name_0.clone(), self.field_value_0.clone()),
+            (self.field_name_1.clone(), self.field_value_1.clone()),
+            (self.field_name_2.clone(), self.field_value_2.clone()),
+            (self.field_name_3.clone(), self.field_value_3.clone()),
+        ]
+    }
 }
 
 #[allow(unused_variables)]
@@ -347,10 +423,10 @@ pub fn update(
     conn: DbConn,
     mut user: User,
     form: LenientForm<UpdateUserForm>,
+    mail: State<'_, Arc<Mutex<Mailer>>>,
     intl: I18n,
 ) -> Result<Flash<Redirect>, ErrorPage> {
     user.display_name = form.display_name.clone();
-    user.email = Some(form.email.clone());
     user.summary = form.summary.clone();
     user.summary_html = SafeString::new(
         &md_to_html(
@@ -366,7 +442,62 @@ pub fn update(
         .clone()
         .and_then(|t| if t.is_empty() { None } else { Some(t) });
     user.hide_custom_css = form.hide_custom_css;
-    let _: User = user.save_changes(&*conn).map_err(Error::from)?;
+    user.discoverable = form.discoverable;
+    // Nothing is saved if a part of the form is invalid
+    let mut invalid = None;
+    let saved = write_transaction(&conn, || {
+        let _: User = user.save_changes(&*conn)?;
+        if Funding::save(
+            &conn,
+            NewFunding {
+                user_id: Some(user.id),
+                payment_pointer: form.payment_pointer.clone(),
+                liberapay_url: form.liberapay_url.clone(),
+                kofi_url: form.kofi_url.clone(),
+                ..NewFunding::default()
+            },
+        )
+        .is_err()
+        {
+            invalid = Some(i18n!(
+                intl.catalog,
+                "Invalid payment pointer or funding account."
+            ));
+            return Err(Error::InvalidValue);
+        }
+        if ProfileField::replace(&conn, ProfileOwner::User(user.id), &form.profile_fields())
+            .is_err()
+        {
+            invalid = Some(i18n!(
+                intl.catalog,
+                "Profile fields need a label, and can't be too long."
+            ));
+            return Err(Error::InvalidValue);
+        }
+        Ok(())
+    });
+    if let Some(message) = invalid {
+        return Ok(Flash::error(Redirect::to(uri!(edit: name = name)), message));
+    }
+    saved?;
+    user.forget_cached();
+    // The index is only updated once the rest is saved
+    user.set_indexable(&conn, form.indexable)?;
+    user.indexable = form.indexable;
+
+    let email = form.email.trim();
+    if !email.is_empty() && user.email.as_deref() != Some(email) {
+        if let Err(message) = change_email(&conn, &user, email, &mail, &intl) {
+            return Ok(Flash::error(Redirect::to(uri!(edit: name = name)), message));
+        }
+        return Ok(Flash::success(
+            Redirect::to(uri!(me)),
+            i18n!(
+                intl.catalog,
+                "Your profile has been updated. To change your email address, follow the links sent to both addresses."
+            ),
+        ));
+    }
 
     Ok(Flash::success(
         Redirect::to(uri!(me)),
@@ -374,41 +505,246 @@ pub fn update(
     ))
 }
 
-#[post("/@/<name>/delete")]
+/// Starts changing the email address of `user`, sending the links to confirm it to both
+/// addresses.
+fn change_email(
+    conn: &DbConn,
+    user: &User,
+    email: &str,
+    mail: &Arc<Mutex<Mailer>>,
+    intl: &I18n,
+) -> Result<(), String> {
+    let tokens = EmailChange::request(conn, user, email).map_err(|err| match err {
+        Error::Blocklisted(true, message) => message,
+        Error::UserAlreadyExists => i18n!(intl.catalog, "This email address is already used."),
+        _ => i18n!(intl.catalog, "This email address is not valid."),
+    })?;
+    let link = |token: &str| {
+        format!(
+            "https://{}{}",
+            CONFIG.base_url,
+            uri!(confirm_email_change: token = token)
+        )
+    };
+    let subject = i18n!(intl.catalog, "Confirm your new email address");
+    let mut sent = send_now(
+        mail,
+        email.to_owned(),
+        subject.clone(),
+        i18n!(intl.catalog, "Follow this link to use this address for your account ({0}): {1}"; &user.username, link(&tokens.new)),
+    );
+    if let (Some(current), Some(token)) = (user.email.clone(), tokens.current) {
+        sent = sent
+            && send_now(
+                mail,
+                current,
+                subject,
+                i18n!(intl.catalog, "Someone asked to use {0} for your account ({1}) instead of this address. If it was you, follow this link to confirm it: {2}\n\nOtherwise, ignore this email and change your password."; email, &user.username, link(&token)),
+            );
+    }
+    if !sent {
+        EmailChange::cancel(conn, user).ok();
+        return Err(i18n!(
+            intl.catalog,
+            "The emails to confirm the change couldn't be sent, your address has not been changed."
+        ));
+    }
+    Ok(())
+}
+
+#[get("/email-change/<token>")]
+pub fn confirm_email_change(
+    token: String,
+    conn: DbConn,
+    mail: State<'_, Arc<Mutex<Mailer>>>,
+    intl: I18n,
+) -> Flash<Redirect> {
+    let home = || Redirect::to(uri!(super::instance::index));
+    match EmailChange::confirm(&conn, &token) {
+        Ok(EmailChangeStatus::Waiting) => Flash::success(
+            home(),
+            i18n!(
+                intl.catalog,
+                "Thanks! Your address will change once the link sent to the other one is followed too."
+            ),
+        ),
+        Ok(EmailChangeStatus::Done { user, previous }) => {
+            if let (Some(previous), Some(email)) = (previous, user.email) {
+                send_now(
+                    &mail,
+                    previous,
+                    i18n!(intl.catalog, "Your email address has been changed"),
+                    i18n!(intl.catalog, "Your account ({0}) now uses {1}, and not this address anymore."; &user.username, &email),
+                );
+            }
+            Flash::success(
+                home(),
+                i18n!(intl.catalog, "Your email address has been changed."),
+            )
+        }
+        Err(Error::Expired) => Flash::error(
+            home(),
+            i18n!(
+                intl.catalog,
+                "This link has expired, change your address again to get a new one."
+            ),
+        ),
+        Err(Error::UserAlreadyExists) => Flash::error(
+            home(),
+            i18n!(intl.catalog, "This email address is already used."),
+        ),
+        Err(_) => Flash::error(
+            home(),
+            i18n!(intl.catalog, "This link is not valid anymore."),
+        ),
+    }
+}
+
+#[post("/email-change/cancel")]
+pub fn cancel_email_change(
+    user: User,
+    conn: DbConn,
+    intl: I18n,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    EmailChange::cancel(&conn, &user)?;
+    Ok(Flash::success(
+        Redirect::to(uri!(edit: name = user.username)),
+        i18n!(intl.catalog, "Your email address will not change."),
+    ))
+}
+
+#[derive(Default, FromForm)]
+pub struct DeleteAccountForm {
+    pub password: String,
+}
+
+#[post("/@/<name>/delete", data = "<form>")]
 pub fn delete(
     name: String,
     user: User,
+    form: LenientForm<DeleteAccountForm>,
     mut cookies: Cookies<'_>,
     conn: DbConn,
     rockets: PlumeRocket,
 ) -> Result<Flash<Redirect>, ErrorPage> {
     let account = User::find_by_fqn(&conn, &name)?;
-    if user.id == account.id {
-        account.delete(&conn)?;
-
-        let target = User::one_by_instance(&conn)?;
-        let delete_act = account.delete_activity(&conn)?;
-        rockets
-            .worker
-            .execute(move || broadcast(&account, delete_act, target, CONFIG.proxy().cloned()));
-
-        if let Some(cookie) = cookies.get_private(AUTH_COOKIE) {
-            cookies.remove_private(cookie);
-        }
-
-        Ok(Flash::success(
-            Redirect::to(uri!(super::instance::index)),
-            i18n!(rockets.intl.catalog, "Your account has been deleted."),
-        ))
-    } else {
-        Ok(Flash::error(
+    if user.id != account.id {
+        return Ok(Flash::error(
             Redirect::to(uri!(edit: name = name)),
             i18n!(
                 rockets.intl.catalog,
                 "You can't delete someone else's account."
             ),
-        ))
+        ));
+    }
+    // The form is hidden for them, but they could still send it
+    if account.is_admin() {
+        return Ok(Flash::error(
+            Redirect::to(uri!(edit: name = name)),
+            i18n!(
+                rockets.intl.catalog,
+                "Sorry, but as an admin, you can't leave your own instance."
+            ),
+        ));
+    }
+    if !account.check_password(&form.password) {
+        return Ok(Flash::error(
+            Redirect::to(uri!(edit: name = name)),
+            i18n!(
+                rockets.intl.catalog,
+                "Wrong password, your account has not been deleted."
+            ),
+        ));
+    }
+
+    let target = User::one_by_instance(&conn)?;
+    let delete_act = personal_data::erase(&conn, &account)?;
+    rockets
+        .worker
+        .execute(move || broadcast(&account, delete_act, target, CONFIG.proxy().cloned()));
+
+    if let Some(cookie) = cookies.get_private(AUTH_COOKIE) {
+        cookies.remove_private(cookie);
     }
+
+    Ok(Flash::success(
+        Redirect::to(uri!(super::instance::index)),
+        i18n!(rockets.intl.catalog, "Your account has been deleted."),
+    ))
+}
+
+/// A copy of all the personal data of the current user.
+#[get("/me/data")]
+pub fn export_data(user: User, conn: DbConn) -> Result<Json<serde_json::Value>, ErrorPage> {
+    Ok(Json(personal_data::export(&conn, &user)?))
+}
+
+/// The authors followed by the current user, for feed readers.
+#[get("/me/subscriptions.opml")]
+pub fn export_subscriptions(user: User, conn: DbConn) -> Result<Content<String>, ErrorPage> {
+    Ok(Content(
+        ContentType::new("text", "x-opml"),
+        opml::export(&conn, &user)?,
+    ))
+}
+
+/// Follows the authors of an OPML file, in the background.
+#[post("/me/subscriptions.opml", data = "<data>")]
+pub fn import_subscriptions(
+    user: User,
+    data: LimitedData<Upload>,
+    ct: &ContentType,
+    pool: State<'_, DbPool>,
+    rockets: PlumeRocket,
+) -> Result<Flash<Redirect>, ErrorPage> {
+    let back = uri!(edit: name = &user.username);
+    let boundary = ct
+        .params()
+        .find(|&(k, _)| k == "boundary")
+        .map(|(_, boundary)| boundary)
+        .filter(|_| ct.is_form_data())
+        .ok_or(Error::InvalidValue)?;
+    let entries = match Multipart::with_body(data.open(), boundary).save().temp() {
+        SaveResult::Full(entries) => entries,
+        _ => return Err(Error::InvalidValue.into()),
+    };
+    let file = entries
+        .fields
+        .get("file")
+        .and_then(|v| v.iter().next())
+        .ok_or(Error::InvalidValue)?;
+    let bytes = match file.data {
+        SavedData::Bytes(ref bytes) => bytes.clone(),
+        SavedData::File(ref path, _) => fs::read(path).map_err(|_| Error::InvalidValue)?,
+        _ => return Err(Error::InvalidValue.into()),
+    };
+    let subscriptions = match opml::parse(&bytes) {
+        Ok(subscriptions) if !subscriptions.is_empty() => subscriptions,
+        _ => {
+            return Ok(Flash::error(
+                Redirect::to(back),
+                i18n!(
+                    rockets.intl.catalog,
+                    "This file doesn't contain any subscription."
+                ),
+            ))
+        }
+    };
+
+    let message = i18n!(
+        rockets.intl.catalog,
+        "One author is being followed, it may take a few minutes.",
+        "{0} authors are being followed, it may take a few minutes.";
+        subscriptions.len()
+    );
+    let pool = pool.inner().clone();
+    rockets.worker.execute(move || match pool.get() {
+        Ok(conn) => {
+            opml::follow_all(&conn, &user, &subscriptions);
+        }
+        Err(_) => warn!("Couldn't import subscriptions: no database connection"),
+    });
+    Ok(Flash::success(Redirect::to(back), message))
 }
 
 #[derive(Default, FromForm, Validate)]
@@ -428,9 +764,8 @@ pub struct NewUserForm {
     pub username: String,
     #[validate(email(message = "Invalid email"))]
     pub email: String,
-    #[validate(length(min = 8, message = "Password should be at least 8 characters long"))]
+    /// Checked with the password policy
     pub password: String,
-    #[validate(length(min = 8, message = "Password should be at least 8 characters long"))]
     pub password_confirmation: String,
 }
 
@@ -477,9 +812,11 @@ fn to_validation(x: Error) -> ValidationErrors {
 
 #[post("/users/new", data = "<form>")]
 pub fn create(
-    form: LenientForm<NewUserForm>,
+    _limit: RateLimit<Registration>,
+    form: LimitedForm<Auth, NewUserForm>,
     conn: DbConn,
     rockets: PlumeRocket,
+    ip: ClientIp,
     _enabled: signups::Password,
 ) -> Result<Flash<Redirect>, Ructe> {
     if !Instance::get_local()
@@ -499,8 +836,10 @@ pub fn create(
     form.username = form.username.trim().to_owned();
     form.email = form.email.trim().to_owned();
     form.validate()
+        .and_then(|_| check_password(&form.password, &[&form.username, &form.email]))
         .and_then(|_| {
-            NewUser::new_local(
+            IpRecord::check_registration(&conn, ip.0).map_err(to_validation)?;
+            let user = NewUser::new_local(
                 &conn,
                 form.username.to_string(),
                 form.username.to_string(),
@@ -509,6 +848,8 @@ pub fn create(
                 form.email.to_string(),
                 Some(User::hash_pass(&form.password).map_err(to_validation)?),
             ).map_err(to_validation)?;
+            IpRecord::record(&conn, IpRecordKind::Registration, ip.0, Some(user.id), None)
+                .map_err(to_validation)?;
             Ok(Flash::success(
                 Redirect::to(uri!(super::session::new: m = _)),
                 i18n!(
@@ -545,13 +886,17 @@ pub fn outbox_page(
 }
 #[post("/@/<name>/inbox", data = "<data>")]
 pub fn inbox(
+    _limit: RateLimit<Inbox>,
     name: String,
     data: inbox::SignedJson<serde_json::Value>,
     headers: Headers<'_>,
     conn: DbConn,
-) -> Result<String, status::BadRequest<&'static str>> {
-    User::find_by_fqn(&conn, &name).map_err(|_| status::BadRequest(Some("User not found")))?;
-    inbox::handle_incoming(conn, data, headers)
+    pool: State<'_, DbPool>,
+    worker: State<'_, Arc<Worker>>,
+    signatures: State<'_, Arc<SignaturePool>>,
+) -> Result<String, status::Custom<&'static str>> {
+    User::find_by_fqn(&conn, &name).map_err(|_| inbox::bad_request("User not found"))?;
+    inbox::handle_incoming(conn, data, headers, pool, worker, signatures)
 }
 
 #[get("/@/<name>/followers", rank = 1)]
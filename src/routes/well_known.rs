@@ -2,7 +2,14 @@ use rocket::http::ContentType;
 use rocket::response::Content;
 use webfinger::*;
 
-use plume_models::{ap_url, blogs::Blog, db_conn::DbConn, users::User, CONFIG};
+use plume_models::{
+    ap_url,
+    blogs::Blog,
+    cache::{self, Entry},
+    db_conn::DbConn,
+    users::User,
+    CONFIG,
+};
 
 #[get("/.well-known/nodeinfo")]
 pub fn nodeinfo() -> Content<String> {
@@ -49,12 +56,14 @@ impl Resolver<DbConn> for WebfingerResolver {
 
     fn find(prefix: Prefix, acct: String, conn: DbConn) -> Result<Webfinger, ResolverError> {
         match prefix {
-            Prefix::Acct => User::find_by_fqn(&conn, &acct)
-                .and_then(|usr| usr.webfinger(&conn))
-                .or(Err(ResolverError::NotFound)),
-            Prefix::Group => Blog::find_by_fqn(&conn, &acct)
-                .and_then(|blog| blog.webfinger(&conn))
-                .or(Err(ResolverError::NotFound)),
+            Prefix::Acct => cache::get_or_insert(Entry::UserWebFinger(&acct), || {
+                User::find_by_fqn(&conn, &acct).and_then(|usr| usr.webfinger(&conn))
+            })
+            .or(Err(ResolverError::NotFound)),
+            Prefix::Group => cache::get_or_insert(Entry::BlogWebFinger(&acct), || {
+                Blog::find_by_fqn(&conn, &acct).and_then(|blog| blog.webfinger(&conn))
+            })
+            .or(Err(ResolverError::NotFound)),
             Prefix::Custom(_) => Err(ResolverError::NotFound),
         }
     }
@@ -0,0 +1,47 @@
+//! Exporting traces to an OpenTelemetry collector, when `OTLP_ENDPOINT` is set.
+//!
+//! The spans of requests, database queries, signature checks and deliveries are sent over
+//! HTTP, as they end, from a separate thread.
+
+use opentelemetry::{
+    global,
+    sdk::{trace, Resource},
+    KeyValue,
+};
+use plume_models::CONFIG;
+use tracing::Subscriber;
+use tracing_subscriber::{registry::LookupSpan, Layer};
+
+pub fn layer<S>() -> Option<impl Layer<S>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let config = CONFIG.otlp.as_ref()?;
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(&config.endpoint),
+        )
+        .with_trace_config(
+            trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                config.service_name.clone(),
+            )])),
+        )
+        .install_simple();
+    match tracer {
+        Ok(tracer) => Some(tracing_opentelemetry::layer().with_tracer(tracer)),
+        Err(e) => {
+            // The logger is not ready yet
+            eprintln!("Traces won't be exported: {}", e);
+            None
+        }
+    }
+}
+
+/// Sends the spans that were not exported yet.
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
@@ -1,4 +1,7 @@
-use plume_models::{db_conn::DbConn, notifications::*, users::User, Connection, PlumeRocket};
+use plume_models::{
+    db_conn::DbConn, federation_digests::Problem, media_policies::MediaAction, notifications::*,
+    users::User, Connection, PlumeRocket,
+};
 
 use crate::templates::Html;
 use gettext::Catalog;
@@ -105,14 +108,85 @@ pub fn translate_notification(ctx: BaseContext<'_>, notif: Notification) -> Stri
         .map_or_else(|_| i18n!(ctx.1, "Someone"), |user| user.name());
     match notif.kind.as_ref() {
         notification_kind::COMMENT => i18n!(ctx.1, "{0} commented on your article."; &name),
+        notification_kind::COMMENT_LIKE if notif.count > 1 => {
+            i18n!(ctx.1, "{1} and one other person liked your comment.", "{1} and {0} other people liked your comment."; notif.count - 1, &name)
+        }
+        notification_kind::COMMENT_LIKE => i18n!(ctx.1, "{0} liked your comment."; &name),
+        notification_kind::FEDERATION_DIGEST => i18n!(
+            ctx.1,
+            "Some instances keep failing to receive activities from this one."
+        ),
+        notification_kind::FOLLOW if notif.count > 1 => {
+            i18n!(ctx.1, "{1} and one other person subscribed to you.", "{1} and {0} other people subscribed to you."; notif.count - 1, &name)
+        }
         notification_kind::FOLLOW => i18n!(ctx.1, "{0} is subscribed to you."; &name),
+        notification_kind::LIKE if notif.count > 1 => {
+            i18n!(ctx.1, "{1} and one other person liked your article.", "{1} and {0} other people liked your article."; notif.count - 1, &name)
+        }
         notification_kind::LIKE => i18n!(ctx.1, "{0} liked your article."; &name),
+        notification_kind::MEDIA_QUARANTINED => {
+            i18n!(ctx.1, "The antivirus found something in a file uploaded by {0}."; &name)
+        }
         notification_kind::MENTION => i18n!(ctx.1, "{0} mentioned you."; &name),
+        notification_kind::RESHARE if notif.count > 1 => {
+            i18n!(ctx.1, "{1} and one other person boosted your article.", "{1} and {0} other people boosted your article."; notif.count - 1, &name)
+        }
         notification_kind::RESHARE => i18n!(ctx.1, "{0} boosted your article."; &name),
+        notification_kind::SEVERED_RELATIONSHIPS => {
+            let domain = notif
+                .get_severance(ctx.0)
+                .map(|severance| severance.domain)
+                .unwrap_or_default();
+            i18n!(ctx.1, "You lost some of your subscriptions and subscribers on {0}, because of a block between instances."; &domain)
+        }
+        notification_kind::THREAD_COMMENT => {
+            i18n!(ctx.1, "{0} replied in a thread you follow."; &name)
+        }
         _ => unreachable!("translate_notification: Unknow type"),
     }
 }
 
+/// What an admin can do about the failures of an instance.
+pub fn problem_advice(cat: &Catalog, problem: Problem) -> String {
+    match problem {
+        Problem::Signature => i18n!(cat, "It refuses the signatures of this instance. Check that the clocks of both servers are right: if they are, it may have blocked this instance."),
+        Problem::Gone => i18n!(cat, "Its inboxes don't exist anymore. If the instance has shut down, you can block it to stop sending it activities."),
+        Problem::ServerError => i18n!(cat, "It has server errors. It may be overloaded or down for a while: contact its administrators if it lasts."),
+        Problem::Tls => i18n!(cat, "Secure connections to it can't be established, usually because its certificate has expired: contact its administrators."),
+        Problem::Unreachable => i18n!(cat, "It can't be reached. Check the DNS and the network of this server: if it is the only one, the instance may have shut down."),
+        Problem::Other => i18n!(cat, "It refuses the activities for another reason: the last error may tell why."),
+    }
+}
+
+/// The translated name of a category of the directory.
+pub fn directory_category_name(cat: &Catalog, category: &str) -> String {
+    match category {
+        "art" => i18n!(cat, "Art"),
+        "culture" => i18n!(cat, "Culture"),
+        "education" => i18n!(cat, "Education"),
+        "food" => i18n!(cat, "Food"),
+        "games" => i18n!(cat, "Games"),
+        "music" => i18n!(cat, "Music"),
+        "news" => i18n!(cat, "News"),
+        "personal" => i18n!(cat, "Personal"),
+        "politics" => i18n!(cat, "Politics"),
+        "science" => i18n!(cat, "Science"),
+        "sports" => i18n!(cat, "Sports"),
+        "technology" => i18n!(cat, "Technology"),
+        "travel" => i18n!(cat, "Travel"),
+        c => c.to_string(),
+    }
+}
+
+/// The translated description of what is done with some media from other instances.
+pub fn media_action_name(cat: &Catalog, action: MediaAction) -> String {
+    match action {
+        MediaAction::Blur => i18n!(cat, "Blur"),
+        MediaAction::ClickThrough => i18n!(cat, "Show on click"),
+        MediaAction::Reject => i18n!(cat, "Reject"),
+    }
+}
+
 pub fn i18n_timeline_name(cat: &Catalog, tl: &str) -> String {
     match tl {
         "Your feed" => i18n!(cat, "Your feed"),